@@ -0,0 +1,114 @@
+use arcache::{
+    Cache, ClockCache, DynCache, FIFOCache, LFUCache, LRUCache, RandomReplacementCache, TTLCache,
+};
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use std::sync::{Arc, Barrier};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// cache factory type, caches are shared between threads so they are created behind an Arc
+type SharedCacheFactory = (&'static str, Box<dyn Fn() -> DynCache<u64, u64>>);
+
+const CAPACITY: u64 = 1_000;
+const KEY_SPACE: u64 = 2_000;
+const OPS_PER_THREAD: u64 = 10_000;
+const THREADS: [usize; 4] = [1, 2, 4, 8];
+// percentage of operations which are gets, the rest are sets
+const READ_PERCENTAGES: [u64; 3] = [50, 90, 99];
+
+/// A tiny xorshift generator so each thread has its own cheap, deterministic stream of keys.
+struct XorShift(u64);
+
+impl XorShift {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+}
+
+/// Run `threads` threads doing a mix of gets and sets against the same cache, returning the time taken.
+fn run_mixed(cache: &DynCache<u64, u64>, threads: usize, read_percentage: u64) -> Duration {
+    let barrier = Arc::new(Barrier::new(threads + 1));
+    let handles: Vec<_> = (0..threads)
+        .map(|t| {
+            let cache = cache.clone();
+            let barrier = barrier.clone();
+            thread::spawn(move || {
+                let mut rng = XorShift(0x9e37_79b9_7f4a_7c15 ^ (t as u64 + 1));
+                barrier.wait();
+                for _ in 0..OPS_PER_THREAD {
+                    let n = rng.next();
+                    let key = n % KEY_SPACE;
+                    if (n >> 32) % 100 < read_percentage {
+                        black_box(cache.get(&key));
+                    } else {
+                        cache.set(key, black_box(n));
+                    }
+                }
+            })
+        })
+        .collect();
+
+    barrier.wait();
+    let start = Instant::now();
+    for handle in handles {
+        handle.join().unwrap();
+    }
+    start.elapsed()
+}
+
+fn bench_contention(c: &mut Criterion) {
+    let cache_factories: Vec<SharedCacheFactory> = vec![
+        ("LRU", Box::new(|| Arc::new(LRUCache::new(CAPACITY)))),
+        (
+            "LRU_BATCHED",
+            Box::new(|| Arc::new(LRUCache::new(CAPACITY).with_batched_recency(64))),
+        ),
+        ("CLOCK", Box::new(|| Arc::new(ClockCache::new(CAPACITY)))),
+        (
+            "LRU_FAIR",
+            Box::new(|| Arc::new(LRUCache::new(CAPACITY).with_fair_inserts(8))),
+        ),
+        ("FIFO", Box::new(|| Arc::new(FIFOCache::new(CAPACITY)))),
+        ("LFU", Box::new(|| Arc::new(LFUCache::new(CAPACITY)))),
+        (
+            "RANDOM",
+            Box::new(|| Arc::new(RandomReplacementCache::new(CAPACITY))),
+        ),
+        (
+            "TTL",
+            Box::new(|| Arc::new(TTLCache::new(Duration::from_secs(60), CAPACITY))),
+        ),
+    ];
+
+    for (label, factory) in cache_factories {
+        for read_percentage in READ_PERCENTAGES {
+            let mut group =
+                c.benchmark_group(format!("{}_contention_{}pct_reads", label, read_percentage));
+            for threads in THREADS {
+                group.throughput(Throughput::Elements(threads as u64 * OPS_PER_THREAD));
+                group.bench_with_input(
+                    BenchmarkId::from_parameter(threads),
+                    &threads,
+                    |b, &threads| {
+                        b.iter_custom(|iters| {
+                            let cache = factory();
+                            for key in 0..CAPACITY {
+                                cache.set(key, key);
+                            }
+                            (0..iters)
+                                .map(|_| run_mixed(&cache, threads, read_percentage))
+                                .sum()
+                        })
+                    },
+                );
+            }
+            group.finish();
+        }
+    }
+}
+
+criterion_group!(benches, bench_contention);
+criterion_main!(benches);
@@ -1,4 +1,6 @@
-use arcache::{Cache, FIFOCache, LFUCache, LIFOCache, LRUCache, MRUCache, RandomReplacementCache};
+use arcache::{
+    Cache, ClockCache, FIFOCache, LFUCache, LIFOCache, LRUCache, MRUCache, RandomReplacementCache,
+};
 use criterion::{black_box, criterion_group, criterion_main, Criterion};
 
 // cache factory type
@@ -12,6 +14,7 @@ fn bench_all(c: &mut Criterion) {
         ("FIFO", Box::new(|| Box::new(FIFOCache::new(100)))),
         ("LIFO", Box::new(|| Box::new(LIFOCache::new(100)))),
         ("LFU", Box::new(|| Box::new(LFUCache::new(100)))),
+        ("CLOCK", Box::new(|| Box::new(ClockCache::new(100)))),
         (
             "RANDOM",
             Box::new(|| Box::new(RandomReplacementCache::new(100))),
@@ -0,0 +1,149 @@
+//! Measures the hit ratio of each policy under skewed and scanning workloads and prints a comparison table.
+//!
+//! Run with `cargo bench --bench hit_ratio_bench`.
+use arcache::{
+    Cache, ClockCache, ClockProCache, FIFOCache, GDSFCache, LFUCache, LIFOCache, LRUCache,
+    MRUCache, RandomReplacementCache, TTLCache, TwoQCache, WindowedLFUCache,
+};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::time::Duration;
+
+// cache factory type, each factory is given the capacity to build the cache with
+type CacheFactory = (&'static str, Box<dyn Fn(u64) -> Box<dyn Cache<u64, u64>>>);
+// workload type, each workload is given the capacity so scans can be sized relative to it
+type Workload = (&'static str, Box<dyn Fn(u64) -> Vec<u64>>);
+
+const KEY_SPACE: u64 = 10_000;
+const ACCESSES: usize = 200_000;
+const CAPACITIES: [u64; 3] = [100, 500, 1_000];
+const SEED: u64 = 42;
+
+/// A Zipfian distribution over `0..n`, key `k` is drawn with probability proportional to `1 / (k + 1)^exponent`.
+struct Zipf {
+    cdf: Vec<f64>,
+}
+
+impl Zipf {
+    fn new(n: u64, exponent: f64) -> Self {
+        let mut cdf = Vec::with_capacity(n as usize);
+        let mut total = 0.0;
+        for k in 0..n {
+            total += 1.0 / ((k + 1) as f64).powf(exponent);
+            cdf.push(total);
+        }
+        for p in cdf.iter_mut() {
+            *p /= total;
+        }
+        Zipf { cdf }
+    }
+
+    fn sample(&self, rng: &mut StdRng) -> u64 {
+        let p: f64 = rng.random();
+        self.cdf.partition_point(|&c| c < p) as u64
+    }
+}
+
+/// Generate a stream of Zipfian distributed keys.
+fn zipf(exponent: f64) -> Vec<u64> {
+    let mut rng = StdRng::seed_from_u64(SEED);
+    let zipf = Zipf::new(KEY_SPACE, exponent);
+    (0..ACCESSES).map(|_| zipf.sample(&mut rng)).collect()
+}
+
+/// Generate a stream which loops over the same `length` keys in order.
+fn looping_scan(length: u64) -> Vec<u64> {
+    (0..ACCESSES as u64).map(|i| i % length).collect()
+}
+
+/// Generate a Zipfian stream which is interrupted by a scan of one-off keys every `every` accesses.
+fn zipf_with_scans(exponent: f64, every: usize, scan_length: u64) -> Vec<u64> {
+    let mut rng = StdRng::seed_from_u64(SEED);
+    let zipf = Zipf::new(KEY_SPACE, exponent);
+    let mut next_scan_key = KEY_SPACE;
+    let mut keys = Vec::with_capacity(ACCESSES);
+    while keys.len() < ACCESSES {
+        if keys.len() % every == 0 {
+            keys.extend(next_scan_key..next_scan_key + scan_length);
+            next_scan_key += scan_length;
+        } else {
+            keys.push(zipf.sample(&mut rng));
+        }
+    }
+    keys.truncate(ACCESSES);
+    keys
+}
+
+/// Replay the keys against the cache, setting every key which misses, and return the hit ratio.
+fn hit_ratio(cache: &dyn Cache<u64, u64>, keys: &[u64]) -> f64 {
+    let mut hits = 0;
+    for &key in keys {
+        if cache.get(&key).is_some() {
+            hits += 1;
+        } else {
+            cache.set(key, key);
+        }
+    }
+    hits as f64 / keys.len() as f64
+}
+
+fn main() {
+    let cache_factories: Vec<CacheFactory> = vec![
+        ("LRU", Box::new(|c| Box::new(LRUCache::new(c)))),
+        (
+            "LRU (scan resistant)",
+            Box::new(|c| Box::new(LRUCache::new(c).with_scan_resistance(c / 5))),
+        ),
+        ("MRU", Box::new(|c| Box::new(MRUCache::new(c)))),
+        ("FIFO", Box::new(|c| Box::new(FIFOCache::new(c)))),
+        ("LIFO", Box::new(|c| Box::new(LIFOCache::new(c)))),
+        ("LFU", Box::new(|c| Box::new(LFUCache::new(c)))),
+        ("W-LFU", Box::new(|c| Box::new(WindowedLFUCache::new(c)))),
+        ("2Q", Box::new(|c| Box::new(TwoQCache::new(c)))),
+        ("CLOCK", Box::new(|c| Box::new(ClockCache::new(c)))),
+        ("CLOCK-Pro", Box::new(|c| Box::new(ClockProCache::new(c)))),
+        ("GDSF", Box::new(|c| Box::new(GDSFCache::new(c)))),
+        (
+            "RANDOM",
+            Box::new(|c| Box::new(RandomReplacementCache::new(c))),
+        ),
+        (
+            "TTL",
+            Box::new(|c| Box::new(TTLCache::new(Duration::from_secs(3600), c))),
+        ),
+    ];
+
+    let workloads: Vec<Workload> = vec![
+        ("zipf 0.8", Box::new(|_| zipf(0.8))),
+        ("zipf 0.99", Box::new(|_| zipf(0.99))),
+        // a loop slightly larger than the cache, the worst case for LRU
+        ("looping scan", Box::new(|c| looping_scan(c + c / 10))),
+        (
+            "zipf 0.99 + scans",
+            Box::new(|c| zipf_with_scans(0.99, 10_000, c * 2)),
+        ),
+    ];
+
+    print!("| workload | capacity |");
+    for (label, _) in &cache_factories {
+        print!(" {} |", label);
+    }
+    println!();
+    print!("| --- | --- |");
+    for _ in &cache_factories {
+        print!(" --- |");
+    }
+    println!();
+
+    for (workload, generate) in &workloads {
+        for capacity in CAPACITIES {
+            let keys = generate(capacity);
+            print!("| {} | {} |", workload, capacity);
+            for (_, factory) in &cache_factories {
+                let cache = factory(capacity);
+                print!(" {:.2}% |", hit_ratio(cache.as_ref(), &keys) * 100.0);
+            }
+            println!();
+        }
+    }
+}
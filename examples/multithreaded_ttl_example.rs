@@ -1,7 +1,6 @@
 use arcache::{Cache, TTLCache};
 use rand::rng;
 use rand::{seq::SliceRandom, Rng};
-use std::sync::Arc;
 use std::thread;
 use std::time::{Duration, Instant};
 
@@ -60,16 +59,14 @@ fn main() {
     println!("Single-threaded execution time: {:?}", single_duration);
 
     // --- Multithreaded execution ---
-    let arc_cache = Arc::new(TTLCache::<String, UserData>::new(
-        ttl_duration,
-        cache_capacity,
-    ));
+    let shared_cache = TTLCache::<String, UserData>::new(ttl_duration, cache_capacity);
     let start = Instant::now();
     let mut handles = Vec::new();
 
     let chunk_size = user_ids.len().div_ceil(num_threads);
     for chunk in user_ids.chunks(chunk_size) {
-        let cache_clone = Arc::clone(&arc_cache);
+        // clones are handles to the same cache
+        let cache_clone = shared_cache.clone();
         let chunk: Vec<String> = chunk.to_vec();
         let handle = thread::spawn(move || {
             let mut local_rng = rng();
@@ -94,5 +91,5 @@ fn main() {
     println!("Multithreading speedup: {:.2}x", speedup);
 
     // Optionally, if your TTLCache has stats:
-    println!("Cache stats: {:?}", arc_cache.stats());
+    println!("Cache stats: {:?}", shared_cache.stats());
 }
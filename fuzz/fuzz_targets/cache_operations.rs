@@ -0,0 +1,8 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+// drive arbitrary operation sequences against every policy, panicking when an invariant breaks
+fuzz_target!(|data: &[u8]| {
+    arcache::test_support::fuzz_every_policy(data);
+});
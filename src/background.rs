@@ -0,0 +1,130 @@
+//! Handles for the background threads spawned by caches.
+#[cfg(not(loom))]
+use crate::sync::{Condvar, Mutex};
+// background threads are real threads sleeping on real time, which loom cannot model
+use std::sync::Arc;
+#[cfg(loom)]
+use std::sync::{Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// BackgroundTask is a handle to a thread doing periodic work for a cache, such as removing expired items.
+///
+/// The thread is stopped and joined when the handle is dropped or `shutdown` is called, so no detached threads outlive it. The thread also stops by itself once the cache it works on has been dropped.
+///
+/// Example:
+/// ```
+/// use arcache::{Cache, TTLCache};
+/// use std::time::Duration;
+///
+/// let cache = TTLCache::<&str, String>::new(Duration::from_secs(1), 10);
+/// let reaper = cache.start_reaper(Duration::from_millis(100));
+///
+/// cache.set("key", "value".to_string());
+///
+/// // stop the reaper and wait for its thread to exit
+/// reaper.shutdown();
+/// ```
+pub struct BackgroundTask {
+    stop: Arc<(Mutex<bool>, Condvar)>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl BackgroundTask {
+    /// Spawn a named thread which calls `tick` every `interval` until it is stopped or `tick` returns false.
+    pub(crate) fn spawn_periodic<F>(name: &str, interval: Duration, mut tick: F) -> Self
+    where
+        F: FnMut() -> bool + Send + 'static,
+    {
+        let stop = Arc::new((Mutex::new(false), Condvar::new()));
+        let thread_stop = stop.clone();
+        let handle = thread::Builder::new()
+            .name(name.to_string())
+            .spawn(move || {
+                let (lock, condvar) = &*thread_stop;
+                loop {
+                    let stopped = lock.lock().unwrap();
+                    let (stopped, _) = condvar
+                        .wait_timeout_while(stopped, interval, |stopped| !*stopped)
+                        .unwrap();
+                    if *stopped {
+                        break;
+                    }
+                    drop(stopped);
+                    if !tick() {
+                        break;
+                    }
+                }
+            })
+            .expect("failed to spawn background thread");
+        BackgroundTask {
+            stop,
+            handle: Some(handle),
+        }
+    }
+
+    /// Check if the background thread has exited.
+    pub fn is_finished(&self) -> bool {
+        self.handle
+            .as_ref()
+            .is_none_or(|handle| handle.is_finished())
+    }
+
+    /// Stop the background thread and wait for it to exit.
+    pub fn shutdown(mut self) {
+        self.stop();
+    }
+
+    fn stop(&mut self) {
+        let (lock, condvar) = &*self.stop;
+        *lock.lock().unwrap() = true;
+        condvar.notify_all();
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+impl Drop for BackgroundTask {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    #[test]
+    fn test_background_task_shutdown() {
+        let ticks = Arc::new(AtomicU64::new(0));
+        let counter = ticks.clone();
+        let task = BackgroundTask::spawn_periodic("test", Duration::from_millis(1), move || {
+            counter.fetch_add(1, Ordering::Relaxed);
+            true
+        });
+        while ticks.load(Ordering::Relaxed) == 0 {
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert!(!task.is_finished());
+        task.shutdown();
+        let after_shutdown = ticks.load(Ordering::Relaxed);
+        thread::sleep(Duration::from_millis(10));
+        assert_eq!(ticks.load(Ordering::Relaxed), after_shutdown);
+    }
+
+    #[test]
+    fn test_background_task_stops_itself() {
+        let task = BackgroundTask::spawn_periodic("test", Duration::from_millis(1), || false);
+        while !task.is_finished() {
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn test_background_task_drop_does_not_wait_for_interval() {
+        let task = BackgroundTask::spawn_periodic("test", Duration::from_secs(3600), || true);
+        drop(task);
+    }
+}
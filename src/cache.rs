@@ -1,18 +1,272 @@
+use std::any::Any;
+use std::collections::HashMap;
+use std::fmt::Display;
 use std::hash::Hash;
+use std::io::{self, BufRead, Write};
+use std::str::FromStr;
 use std::sync::Arc;
+use std::time::Duration;
+
+use crate::cache::identity::CacheIdentity;
+use crate::cache::listener::EntrySource;
+use crate::error::CacheError;
+
+/// The version of the ranking format written by `export_ranking`.
+pub const RANKING_VERSION: u16 = 1;
+
+/// The start of the header line of a ranking, followed by its format version.
+const RANKING_HEADER: &str = "#arcache-ranking";
 
 /// CacheStats contains cache statistics
-#[derive(Debug, Clone)]
+///
+/// Every policy counts the same way, so the statistics of different caches can be compared. Only reads count as hits and misses, a `set` is never a hit or a miss, even for policies such as LFU where overwriting a key raises its frequency. Each `set` that stores its value is counted once, as an insert if the key is new or an update if it overwrote the key. Items the policy removes to make room, including when the capacity shrinks, are evictions, while items removed because their TTL passed are expirations. Explicit removes and clears are not counted.
+///
+/// New counters may be added, so read the fields rather than constructing or destructuring the struct. Statistics only some policies keep are in `detailed_stats`.
+#[derive(Debug, Clone, Default)]
+#[non_exhaustive]
 pub struct CacheStats {
+    /// Gets which found a live item.
     pub hits: u64,
+    /// Gets which found no item, or only an expired one.
     pub misses: u64,
+    /// Sets which stored a new key.
+    pub inserts: u64,
+    /// Sets which overwrote a key already in the cache.
+    pub updates: u64,
+    /// Items removed by the policy to make room.
+    pub evictions: u64,
+    /// Evicted items which were never hit by a get since they were set, if most evictions are wasted the cache is mostly churn.
+    pub wasted_inserts: u64,
+    /// Items removed because their TTL passed.
+    pub expirations: u64,
+    /// Panics in eviction listeners and other callbacks which the cache caught, always 0 unless it was built to isolate them with `CallbackPanics`.
+    pub callback_panics: u64,
+    /// The number of items in the cache.
     pub size: u64,
+    /// The maximum number of items the cache holds.
     pub capacity: u64,
+    /// The ID and name of the cache, the default identity with ID 0 for statistics added up over several caches.
+    pub cache: CacheIdentity,
+}
+
+impl CacheStats {
+    /// Add the counts, size and capacity of another cache to these.
+    pub(crate) fn accumulate(&mut self, other: &CacheStats) {
+        self.hits += other.hits;
+        self.misses += other.misses;
+        self.inserts += other.inserts;
+        self.updates += other.updates;
+        self.evictions += other.evictions;
+        self.wasted_inserts += other.wasted_inserts;
+        self.expirations += other.expirations;
+        self.callback_panics += other.callback_panics;
+        self.size += other.size;
+        self.capacity += other.capacity;
+    }
+}
+
+/// DetailedStats contains a cache's statistics together with any extra statistics kept by its policy.
+///
+/// Policy specific statistics are None for caches which do not keep them, and more may be added, so read the fields rather than constructing or destructuring the struct.
+///
+/// Example:
+/// ```
+/// use arcache::{Cache, LRUCache};
+///
+/// let cache = LRUCache::<&str, u64>::new(10);
+/// cache.set("key", 1);
+///
+/// let detailed = cache.detailed_stats();
+/// assert_eq!(detailed.stats.size, 1);
+/// ```
+#[derive(Debug, Clone, Default)]
+#[non_exhaustive]
+pub struct DetailedStats {
+    /// The statistics every cache keeps.
+    pub stats: CacheStats,
+    /// The number of items in each power of two range of frequency, index `i` counts the items with a frequency from `2^i` to `2^(i+1) - 1`, kept by `LFUCache`.
+    pub frequency_histogram: Option<Vec<u64>>,
+    /// How soon the items expire, kept by every policy which supports TTLs.
+    pub expiry_distribution: Option<ExpiryDistribution>,
+    /// How long evicted items had been in the cache and gone untouched, kept by every policy.
+    pub eviction_ages: Option<EvictionAges>,
+}
+
+/// ExpiryDistribution counts the items of a cache by how soon they expire, to plan capacity and schedule refreshes.
+///
+/// Each item is counted in exactly one field. Items which have already expired but not yet been removed are not counted.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct ExpiryDistribution {
+    /// Items expiring within the next second.
+    pub within_1s: u64,
+    /// Items expiring in 1 to 10 seconds.
+    pub within_10s: u64,
+    /// Items expiring in 10 to 60 seconds.
+    pub within_60s: u64,
+    /// Items expiring in 60 seconds or more.
+    pub later: u64,
+    /// Items without a TTL.
+    pub never: u64,
+}
+
+impl ExpiryDistribution {
+    /// Count an item with `remaining` time to live.
+    pub(crate) fn record(&mut self, remaining: Duration) {
+        match remaining.as_secs() {
+            0 => self.within_1s += 1,
+            1..=9 => self.within_10s += 1,
+            10..=59 => self.within_60s += 1,
+            _ => self.later += 1,
+        }
+    }
+}
+
+/// AgeDistribution counts items by an age, such as how long they were in the cache.
+///
+/// Each item is counted in exactly one field.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct AgeDistribution {
+    /// Items under a second old.
+    pub under_1s: u64,
+    /// Items 1 to 10 seconds old.
+    pub under_10s: u64,
+    /// Items 10 to 60 seconds old.
+    pub under_60s: u64,
+    /// Items 1 to 10 minutes old.
+    pub under_10m: u64,
+    /// Items 10 to 60 minutes old.
+    pub under_1h: u64,
+    /// Items an hour old or more.
+    pub older: u64,
+}
+
+impl AgeDistribution {
+    /// Count an item of the given age.
+    pub(crate) fn record(&mut self, age: Duration) {
+        match age.as_secs() {
+            0 => self.under_1s += 1,
+            1..=9 => self.under_10s += 1,
+            10..=59 => self.under_60s += 1,
+            60..=599 => self.under_10m += 1,
+            600..=3599 => self.under_1h += 1,
+            _ => self.older += 1,
+        }
+    }
+}
+
+/// EvictionAges counts the items a cache evicted by how long they had been in it and how long since they were last set or hit.
+///
+/// Items evicted long before their TTL would have run out mean capacity is the binding constraint, while items evicted after a long idle time were going unused anyway. Only items removed by the policy to make room are counted, not expired or removed ones.
+///
+/// Example:
+/// ```
+/// use arcache::{Cache, LRUCache};
+///
+/// let cache = LRUCache::<&str, u64>::new(1);
+/// cache.set("a", 1);
+/// cache.set("b", 2);
+///
+/// let ages = cache.detailed_stats().eviction_ages.unwrap();
+/// assert_eq!(ages.lived.under_1s, 1);
+/// assert_eq!(ages.idle.under_1s, 1);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct EvictionAges {
+    /// How long evicted items had been in the cache, overwrites do not restart the count.
+    pub lived: AgeDistribution,
+    /// How long evicted items had gone without being set or hit by a get.
+    pub idle: AgeDistribution,
+}
+
+impl DetailedStats {
+    /// Create a new DetailedStats with no policy specific statistics.
+    pub(crate) fn new(stats: CacheStats) -> Self {
+        DetailedStats {
+            stats,
+            frequency_histogram: None,
+            expiry_distribution: None,
+            eviction_ages: None,
+        }
+    }
+}
+
+/// IterationOrder chooses the order `Cache::items` lists the items of a cache in.
+///
+/// Each order lists items from the oldest or coldest to the newest or hottest. Policies only support the orders they track, every policy supports `Arbitrary`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum IterationOrder {
+    /// The order keys were added to the cache, overwriting a key does not move it.
+    Insertion,
+    /// From the least to the most recently used.
+    Recency,
+    /// From the least to the most frequently used.
+    Frequency,
+    /// Any order, which may differ between calls.
+    Arbitrary,
+}
+
+/// FullBehavior decides what happens when a new key is set in a cache which is already full.
+///
+/// Overwriting a key which is already in the cache is always allowed, and expired items are removed before the cache is considered full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FullBehavior {
+    /// Evict an item chosen by the cache's policy to make space, the default.
+    #[default]
+    EvictPerPolicy,
+    /// Drop the new item, `set` returns None.
+    RejectNew,
+    /// Drop the new item, `try_set` returns `CacheError::Full` and `set` returns None.
+    ReturnError,
+}
+
+impl FullBehavior {
+    /// Check if a full cache should evict to make space for a new item.
+    pub(crate) fn evicts(self) -> bool {
+        self == FullBehavior::EvictPerPolicy
+    }
+
+    /// Get the result of setting a new item in a full cache which does not evict.
+    pub(crate) fn rejection<T>(self) -> Result<Option<T>, CacheError> {
+        match self {
+            FullBehavior::ReturnError => Err(CacheError::Full),
+            _ => Ok(None),
+        }
+    }
+}
+
+/// GetOutcome tells a hit from the different kinds of miss, it is returned by `Cache::get_with_outcome`.
+///
+/// Caches remember why the most recent keys which expired or were turned away are missing, so a caller can for example fetch a fresh value for an expired key but treat a key which was never cached as unknown. A key is forgotten once it is set again, or once enough newer keys have gone, after which its miss is reported as `MissAbsent`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum GetOutcome {
+    /// The key was found.
+    Hit,
+    /// The key is not in the cache, it was never set, was evicted or removed, or was forgotten.
+    MissAbsent,
+    /// The key's TTL or time-to-idle passed, including an item a `TTLCache` keeps within its stale grace period.
+    MissExpired,
+    /// The key's last set was turned away, by a full cache built with `FullBehavior::RejectNew` or `FullBehavior::ReturnError`, or it was not loaded by a `LoadingCache` because it recently failed to load or too many loads were in flight.
+    MissRejected,
+}
+
+impl GetOutcome {
+    /// Check if the outcome is a hit.
+    pub fn is_hit(self) -> bool {
+        self == GetOutcome::Hit
+    }
 }
 
 /// Cache trait defines the methods that a cache should implement and provides a shared interface for different cache implementations
 ///
 /// The cache trait is useful for defining generic functions that can work with any cache implementation
+///
+/// Every policy treats small capacities the same way. A cache with a capacity of zero holds nothing, `set` stores nothing and returns None, and `try_set` returns `CacheError::CapacityZero`. A cache with a capacity of one holds a single item, which is replaced by each new key unless the cache was built to reject new keys when full.
+///
 /// Example:
 /// ```
 /// use arcache::{Cache, LFUCache, LRUCache};
@@ -44,12 +298,1115 @@ pub trait Cache<K: Eq + Hash + Clone + Send + Sync, V: Send + Sync>: Send + Sync
     fn clear(&self);
     fn stats(&self) -> CacheStats;
     fn change_capacity(&self, capacity: u64);
+
+    /// Copy the items into a HashMap.
+    ///
+    /// Every cache in this crate copies its unexpired items in one locked pass, this default returns an empty map for implementations which cannot list their items.
+    fn to_map(&self) -> HashMap<K, Arc<V>> {
+        HashMap::new()
+    }
+
+    /// Get the name of the eviction policy, "custom" for implementations which do not name theirs.
+    fn policy_name(&self) -> &'static str {
+        "custom"
+    }
+
+    /// Get up to `limit` items, ordered with the items the policy would evict last first.
+    ///
+    /// Every cache in this crate orders by its policy, this default takes items from `to_map` in no particular order.
+    fn hottest(&self, limit: usize) -> Vec<(K, Arc<V>)> {
+        self.to_map().into_iter().take(limit).collect()
+    }
+
+    /// Clear the cache, returning every item which was removed.
+    ///
+    /// Every cache in this crate does this in one locked step, this default copies the items with `to_map` and then clears, so items set in between are cleared without being returned.
+    fn clear_returning(&self) -> Vec<(K, Arc<V>)> {
+        let items = self.to_map().into_iter().collect();
+        self.clear();
+        items
+    }
+
+    /// Change the capacity of the cache, returning the items which were removed to fit the new capacity.
+    ///
+    /// Every cache in this crate returns the removed items, this default changes the capacity and returns none.
+    fn change_capacity_collect(&self, capacity: u64) -> Vec<(K, Arc<V>)> {
+        self.change_capacity(capacity);
+        Vec::new()
+    }
+
+    /// Check if a key is in the cache.
+    ///
+    /// Every cache in this crate checks without counting a hit or miss or updating the item's position, this default goes through `get`, so it does both.
+    fn contains_key(&self, key: &K) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// Get the time left before an item expires, or None if it never expires or is not in the cache.
+    ///
+    /// Every cache in this crate reports the item's deadline, this default reports that nothing expires. Wrappers which keep copies of values, such as ThreadLocalCache, use it to stop serving a copy once the item has expired.
+    fn ttl_remaining(&self, key: &K) -> Option<Duration> {
+        let _ = key;
+        None
+    }
+
+    /// Get the cache as `Any` so it can be downcast from a `dyn Cache`, None if the implementation does not support downcasting.
+    ///
+    /// Every cache in this crate supports it, implementations elsewhere return `Some(self)` to opt in.
+    fn as_any(&self) -> Option<&dyn Any>
+    where
+        Self: 'static,
+    {
+        None
+    }
+
+    /// Get an owned clone of a value from the cache.
+    ///
+    /// Useful for small `Clone` values (e.g. integers) where holding on to an `Arc` is more cumbersome than copying the value out. The cache still stores the value in an `Arc`, this clones the value out of the `Arc` that `get` returns.
+    fn get_cloned(&self, key: &K) -> Option<V>
+    where
+        V: Clone,
+    {
+        self.get(key).map(|value| V::clone(&value))
+    }
+
+    /// Get a value from the cache together with whether it was a hit, or why it was a miss.
+    ///
+    /// Caches which do not remember why keys are missing report every miss as `GetOutcome::MissAbsent`.
+    fn get_with_outcome(&self, key: &K) -> (Option<Arc<V>>, GetOutcome) {
+        let result = self.get(key);
+        let outcome = match result {
+            Some(_) => GetOutcome::Hit,
+            None => GetOutcome::MissAbsent,
+        };
+        (result, outcome)
+    }
+
+    /// Get the cache statistics together with any extra statistics its policy keeps.
+    fn detailed_stats(&self) -> DetailedStats {
+        DetailedStats::new(self.stats())
+    }
+
+    /// Get the unexpired items in the given order, or None if the policy does not track that order.
+    ///
+    /// Unlike `hottest`, priorities are not taken into account, so downstream code can rely on the order itself.
+    fn items(&self, order: IterationOrder) -> Option<Vec<(K, Arc<V>)>> {
+        match order {
+            IterationOrder::Arbitrary => Some(self.to_map().into_iter().collect()),
+            _ => None,
+        }
+    }
+
+    /// Set a value, recording how it came to be in the cache, for example that a loader filled it in, so `entry_info` and eviction listeners can report it.
+    ///
+    /// Caches which do not track sources set the value as `set` does.
+    fn set_with_source(&self, key: K, value: V, source: EntrySource) -> Option<Arc<V>> {
+        let _ = source;
+        self.set(key, value)
+    }
+
+    /// Get a value from the cache and pass a reference to it into `f`, returning the result.
+    ///
+    /// This avoids handing out an `Arc` when the caller only needs to read from the value. It is not a borrow of the cache's storage: values are still stored in an `Arc`, the `Arc` is cloned out by `get` and `f` runs after the cache lock is released, so `f` may use the cache and the value can be replaced while `f` reads it.
+    fn get_with<R, F>(&self, key: &K, f: F) -> Option<R>
+    where
+        F: FnOnce(&V) -> R,
+        Self: Sized,
+    {
+        self.get(key).map(|value| f(&value))
+    }
+
+    /// Copy up to `limit` of the hottest items from `other` into this cache, returning how many were copied.
+    ///
+    /// Items are ranked by the source's own policy with `hottest` and set from coldest to hottest, so under recency based policies the hottest items end up the most recently used. Values are cloned into this cache, which applies its own TTL and policy to them.
+    fn warm_from<C>(&self, other: &C, limit: usize) -> usize
+    where
+        C: Cache<K, V> + ?Sized,
+        V: Clone,
+        Self: Sized,
+    {
+        let items = other.hottest(limit);
+        let copied = items.len();
+        for (key, value) in items.into_iter().rev() {
+            self.set(key, V::clone(&value));
+        }
+        copied
+    }
+
+    /// Load the keys listed in `reader`, one per line from most to least important, until the cache is full, returning how many were set.
+    ///
+    /// The list is typically written by `export_ranking` before a restart, so a cold cache starts with the keys which mattered most. A list without the `export_ranking` header line is read too, so it can also be written by hand or by other tools. Blank lines, keys already in the cache and keys for which `loader` returns None are skipped. Loaded items are set from least to most important, so under recency based policies the most important keys end up the most recently used. Lines which do not parse as a key are reported as `InvalidData`, and nothing is set.
+    fn warm_from_ranking<R, F>(&self, reader: R, mut loader: F) -> io::Result<usize>
+    where
+        R: BufRead,
+        F: FnMut(&K) -> Option<V>,
+        K: FromStr,
+        Self: Sized,
+    {
+        let stats = self.stats();
+        let free = stats.capacity.saturating_sub(stats.size) as usize;
+        let mut loaded = Vec::new();
+        for (number, line) in reader.lines().enumerate() {
+            let line = line?;
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            if number == 0 {
+                if let Some(version) = line.strip_prefix(RANKING_HEADER) {
+                    if version.trim() != RANKING_VERSION.to_string() {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!(
+                                "ranking format version {} is not supported, expected {}",
+                                version.trim(),
+                                RANKING_VERSION
+                            ),
+                        ));
+                    }
+                    continue;
+                }
+            }
+            if loaded.len() >= free {
+                break;
+            }
+            let key = K::from_str(line).map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("line {} is not a valid key", number + 1),
+                )
+            })?;
+            if self.contains_key(&key) {
+                continue;
+            }
+            if let Some(value) = loader(&key) {
+                loaded.push((key, value));
+            }
+        }
+        let set = loaded.len();
+        for (key, value) in loaded.into_iter().rev() {
+            self.set(key, value);
+        }
+        Ok(set)
+    }
+
+    /// Write the keys of the cache to `writer`, one per line ranked from most to least important by the cache's policy, returning how many were written.
+    ///
+    /// Only keys are written, so the hot set can be shipped to another host and loaded there with `warm_from_ranking` without copying the values. The list starts with a header line carrying `RANKING_VERSION`. Keys are written with `Display` and must read back with `FromStr`, so they should not contain line breaks.
+    fn export_ranking<W>(&self, mut writer: W) -> io::Result<usize>
+    where
+        W: Write,
+        K: Display,
+        Self: Sized,
+    {
+        let items = self.hottest(usize::MAX);
+        writeln!(writer, "{} {}", RANKING_HEADER, RANKING_VERSION)?;
+        for (key, _) in &items {
+            writeln!(writer, "{}", key)?;
+        }
+        writer.flush()?;
+        Ok(items.len())
+    }
+
+    /// Evict `fraction` of the items per the cache's policy, returning how many were evicted.
+    ///
+    /// The fraction is clamped between 0 and 1 and rounded up to whole items. The capacity is unchanged afterwards, so this frees memory without shrinking the cache for good, for example in response to memory pressure.
+    ///
+    /// The caches in this crate evict under a single lock. By default the capacity is shrunk and then restored, so a set in between can see the smaller capacity.
+    fn shed(&self, fraction: f64) -> usize {
+        let stats = self.stats();
+        let shed = shed_count(stats.size as usize, fraction) as u64;
+        if shed == 0 {
+            return 0;
+        }
+        let evicted = self.change_capacity_collect(stats.size - shed).len();
+        self.change_capacity(stats.capacity);
+        evicted
+    }
+
+    /// Load values for `keys` in parallel on the rayon thread pool and set them as they arrive, returning how many were set.
+    ///
+    /// Loading stops once the cache is full, so at most the free capacity is loaded. Keys for which `loader` returns None are skipped.
+    #[cfg(feature = "rayon")]
+    fn warm_parallel<I, F>(&self, keys: I, loader: F) -> usize
+    where
+        I: rayon::iter::IntoParallelIterator<Item = K>,
+        F: Fn(&K) -> Option<V> + Send + Sync,
+        Self: Sized,
+    {
+        use rayon::iter::ParallelIterator;
+        use std::sync::atomic::{AtomicU64, Ordering};
+
+        let stats = self.stats();
+        let free = stats.capacity.saturating_sub(stats.size);
+        // slots are claimed before loading so no more than the free capacity is loaded
+        let claimed = AtomicU64::new(0);
+        keys.into_par_iter().for_each(|key| {
+            if claimed.fetch_add(1, Ordering::Relaxed) >= free {
+                claimed.fetch_sub(1, Ordering::Relaxed);
+                return;
+            }
+            match loader(&key) {
+                Some(value) => {
+                    self.set(key, value);
+                }
+                None => {
+                    claimed.fetch_sub(1, Ordering::Relaxed);
+                }
+            }
+        });
+        claimed.into_inner() as usize
+    }
+}
+
+/// TryCache is implemented by caches whose operations can fail in ways that `Cache` does not report, such as a poisoned lock or a full cache which rejects new items.
+///
+/// Example:
+/// ```
+/// use arcache::{CacheError, FullBehavior, LRUCache, TryCache};
+///
+/// let cache = LRUCache::<u64, String>::new(1).with_full_behavior(FullBehavior::ReturnError);
+///
+/// assert!(cache.try_set(1, "one".to_string()).is_ok());
+/// assert_eq!(cache.try_set(2, "two".to_string()), Err(CacheError::Full));
+/// assert_eq!(cache.try_get(&2), Ok(None));
+/// ```
+pub trait TryCache<K: Eq + Hash + Clone + Send + Sync, V: Send + Sync>: Cache<K, V> {
+    /// Get a value from the cache, or an error if the cache cannot be read.
+    fn try_get(&self, key: &K) -> Result<Option<Arc<V>>, CacheError>;
+
+    /// Set a value in the cache, or an error if the value was not stored.
+    fn try_set(&self, key: K, value: V) -> Result<Option<Arc<V>>, CacheError>;
+}
+
+/// VersionedCache gives every item a version which changes each time it is set, so a value can be read, modified and written back without a lock held across the three steps.
+///
+/// Versions only ever increase within a cache and are never 0, so a set expecting version 0 only succeeds if the key is not in the cache.
+///
+/// Example:
+/// ```
+/// use arcache::{CacheError, LRUCache, VersionedCache};
+///
+/// let cache = LRUCache::<&str, u64>::new(10);
+/// cache.set_versioned("counter", 1, 0).unwrap();
+///
+/// let (value, version) = cache.get_versioned(&"counter").unwrap();
+/// cache.set_versioned("counter", *value + 1, version).unwrap();
+///
+/// // a second write based on the same read is rejected
+/// let result = cache.set_versioned("counter", *value + 1, version);
+/// assert!(matches!(result, Err(CacheError::VersionMismatch(_))));
+/// ```
+pub trait VersionedCache<K: Eq + Hash + Clone + Send + Sync, V: Send + Sync>: Cache<K, V> {
+    /// Get the current version of an item, without counting a hit or miss or updating its position.
+    fn version(&self, key: &K) -> Option<u64>;
+
+    /// Set a value only if the item is at `expected_version`, or is not in the cache if it is 0, otherwise return `CacheError::VersionMismatch` with the version found.
+    fn set_versioned(
+        &self,
+        key: K,
+        value: V,
+        expected_version: u64,
+    ) -> Result<Option<Arc<V>>, CacheError>;
+
+    /// Get a value together with the version it was read at.
+    fn get_versioned(&self, key: &K) -> Option<(Arc<V>, u64)> {
+        loop {
+            let before = self.version(key);
+            let value = self.get(key);
+            let after = self.version(key);
+            // a set in between would have changed the version, so read again
+            if before == after {
+                return value.zip(after);
+            }
+        }
+    }
+}
+
+/// DynCache is a shared cache whose policy is chosen at runtime, so caches of different types can be stored together.
+///
+/// `Arc` and `Box` of any cache implement `Cache` themselves, so a DynCache can be passed wherever a cache is expected.
+///
+/// Example:
+/// ```
+/// use arcache::{Cache, DynCache, LFUCache, LRUCache};
+/// use std::sync::Arc;
+///
+/// let caches: Vec<DynCache<u64, String>> = vec![
+///     Arc::new(LRUCache::new(10)),
+///     Arc::new(LFUCache::new(10)),
+/// ];
+/// for cache in &caches {
+///     cache.set(1, "one".to_string());
+/// }
+///
+/// assert!(caches[0].is::<LRUCache<u64, String>>());
+/// let lfu = caches[1].downcast_ref::<LFUCache<u64, String>>().unwrap();
+/// assert_eq!(lfu.frequency(&1), Some(1));
+/// ```
+pub type DynCache<K, V> = Arc<dyn Cache<K, V>>;
+
+impl<K, V> dyn Cache<K, V>
+where
+    K: Eq + Hash + Clone + Send + Sync + 'static,
+    V: Send + Sync + 'static,
+{
+    /// Check if the cache is a `C`.
+    pub fn is<C: Cache<K, V> + 'static>(&self) -> bool {
+        self.downcast_ref::<C>().is_some()
+    }
+
+    /// Get a reference to the cache as a `C`, or None if it is another type.
+    pub fn downcast_ref<C: Cache<K, V> + 'static>(&self) -> Option<&C> {
+        self.as_any()?.downcast_ref()
+    }
 }
 
+macro_rules! impl_cache_for_pointer {
+    ($pointer:ident) => {
+        impl<K, V, C> Cache<K, V> for $pointer<C>
+        where
+            K: Eq + Hash + Clone + Send + Sync,
+            V: Send + Sync,
+            C: Cache<K, V> + ?Sized,
+        {
+            fn get(&self, key: &K) -> Option<Arc<V>> {
+                (**self).get(key)
+            }
+
+            fn set(&self, key: K, value: V) -> Option<Arc<V>> {
+                (**self).set(key, value)
+            }
+
+            fn remove(&self, key: &K) -> Option<Arc<V>> {
+                (**self).remove(key)
+            }
+
+            fn contains_key(&self, key: &K) -> bool {
+                (**self).contains_key(key)
+            }
+
+            fn ttl_remaining(&self, key: &K) -> Option<Duration> {
+                (**self).ttl_remaining(key)
+            }
+
+            fn clear(&self) {
+                (**self).clear()
+            }
+
+            fn clear_returning(&self) -> Vec<(K, Arc<V>)> {
+                (**self).clear_returning()
+            }
+
+            fn to_map(&self) -> HashMap<K, Arc<V>> {
+                (**self).to_map()
+            }
+
+            fn hottest(&self, limit: usize) -> Vec<(K, Arc<V>)> {
+                (**self).hottest(limit)
+            }
+
+            fn policy_name(&self) -> &'static str {
+                (**self).policy_name()
+            }
+
+            fn stats(&self) -> CacheStats {
+                (**self).stats()
+            }
+
+            fn detailed_stats(&self) -> DetailedStats {
+                (**self).detailed_stats()
+            }
+
+            fn get_with_outcome(&self, key: &K) -> (Option<Arc<V>>, GetOutcome) {
+                (**self).get_with_outcome(key)
+            }
+
+            fn items(&self, order: IterationOrder) -> Option<Vec<(K, Arc<V>)>> {
+                (**self).items(order)
+            }
+
+            fn set_with_source(&self, key: K, value: V, source: EntrySource) -> Option<Arc<V>> {
+                (**self).set_with_source(key, value, source)
+            }
+
+            fn change_capacity(&self, capacity: u64) {
+                (**self).change_capacity(capacity)
+            }
+
+            fn change_capacity_collect(&self, capacity: u64) -> Vec<(K, Arc<V>)> {
+                (**self).change_capacity_collect(capacity)
+            }
+
+            fn as_any(&self) -> Option<&dyn Any>
+            where
+                Self: 'static,
+            {
+                (**self).as_any()
+            }
+
+            fn get_cloned(&self, key: &K) -> Option<V>
+            where
+                V: Clone,
+            {
+                (**self).get_cloned(key)
+            }
+
+            fn shed(&self, fraction: f64) -> usize {
+                (**self).shed(fraction)
+            }
+        }
+
+        impl<K, V, C> TryCache<K, V> for $pointer<C>
+        where
+            K: Eq + Hash + Clone + Send + Sync,
+            V: Send + Sync,
+            C: TryCache<K, V> + ?Sized,
+        {
+            fn try_get(&self, key: &K) -> Result<Option<Arc<V>>, CacheError> {
+                (**self).try_get(key)
+            }
+
+            fn try_set(&self, key: K, value: V) -> Result<Option<Arc<V>>, CacheError> {
+                (**self).try_set(key, value)
+            }
+        }
+
+        impl<K, V, C> VersionedCache<K, V> for $pointer<C>
+        where
+            K: Eq + Hash + Clone + Send + Sync,
+            V: Send + Sync,
+            C: VersionedCache<K, V> + ?Sized,
+        {
+            fn version(&self, key: &K) -> Option<u64> {
+                (**self).version(key)
+            }
+
+            fn set_versioned(
+                &self,
+                key: K,
+                value: V,
+                expected_version: u64,
+            ) -> Result<Option<Arc<V>>, CacheError> {
+                (**self).set_versioned(key, value, expected_version)
+            }
+
+            fn get_versioned(&self, key: &K) -> Option<(Arc<V>, u64)> {
+                (**self).get_versioned(key)
+            }
+        }
+    };
+}
+
+// a shared or boxed cache is a cache, so trait objects and owned caches can be used interchangeably
+impl_cache_for_pointer!(Arc);
+impl_cache_for_pointer!(Box);
+
+/// Get how many of `len` items to evict to shed `fraction` of them, the fraction is clamped between 0 and 1 and rounded up to whole items.
+pub(crate) fn shed_count(len: usize, fraction: f64) -> usize {
+    (len as f64 * fraction.clamp(0.0, 1.0)).ceil() as usize
+}
+
+/// Get the capacity for a cache split off from one with `capacity` and `len` items, in proportion to the `moved` items but never too small to hold them.
+pub(crate) fn split_capacity(capacity: u64, moved: usize, len: usize) -> u64 {
+    let proportional = (capacity as u128 * moved as u128).div_ceil(len.max(1) as u128);
+    (proportional.min(capacity as u128) as u64)
+        .max(moved as u64)
+        .max(1)
+}
+
+pub mod admission;
+pub mod autotune;
+pub(crate) mod bloom;
+pub mod bloom_filter;
+pub mod clock_cache;
+pub mod clock_pro;
+pub(crate) mod expiry;
+pub(crate) mod fairness;
 pub mod fifo;
+pub mod gdsf;
+pub mod guard;
+pub mod identity;
+pub mod interned;
+pub mod invalidation;
+pub mod latency;
+pub mod leak;
 pub mod lfu;
 pub mod lifo;
+pub mod listener;
+pub mod loading;
 pub mod lru;
+pub mod metadata;
+#[cfg(feature = "mmap")]
+pub mod mmap_store;
 pub mod mru;
+pub mod negative;
+pub mod nested;
+pub mod partitioned;
+pub(crate) mod policy_core;
+pub mod priority;
+pub mod quota;
 pub mod random_replacement;
+pub(crate) mod recency;
+pub mod serialized;
+pub(crate) mod stats;
+pub mod thread_local;
+pub mod trace;
 pub mod ttl;
+pub mod two_q;
+pub mod windowed_lfu;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        BloomFilterCache, ClockCache, ClockProCache, FIFOCache, GDSFCache, LFUCache, LIFOCache,
+        LRUCache, MRUCache, RandomReplacementCache, TTLCache, TwoQCache, WindowedLFUCache,
+    };
+    use std::time::Duration;
+
+    #[test]
+    fn test_get_cloned() {
+        let cache = LRUCache::new(2);
+        cache.set(1, 10u64);
+        assert_eq!(cache.get_cloned(&1), Some(10));
+        assert_eq!(cache.get_cloned(&2), None);
+        assert_eq!(cache.stats().hits, 1);
+        assert_eq!(cache.stats().misses, 1);
+    }
+
+    #[test]
+    fn test_get_with() {
+        let cache = LRUCache::new(2);
+        cache.set("key", "value".to_string());
+        assert_eq!(cache.get_with(&"key", |v| v.len()), Some(5));
+        assert_eq!(cache.get_with(&"missing", |v| v.len()), None);
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn test_warm_parallel() {
+        let cache = LRUCache::new(100);
+        cache.set(0, 0);
+        let loaded = cache.warm_parallel(0..1000u64, |key| (key % 2 == 1).then_some(*key));
+        assert_eq!(loaded, 99);
+        assert_eq!(cache.stats().size, 100);
+        assert!(cache.contains_key(&0));
+    }
+
+    /// A cache implementing only the methods of `Cache` without a default, as a cache outside the crate might.
+    struct MinimalCache(std::sync::Mutex<HashMap<u32, Arc<u32>>>);
+
+    impl Cache<u32, u32> for MinimalCache {
+        fn get(&self, key: &u32) -> Option<Arc<u32>> {
+            self.0.lock().unwrap().get(key).cloned()
+        }
+
+        fn set(&self, key: u32, value: u32) -> Option<Arc<u32>> {
+            self.0.lock().unwrap().insert(key, Arc::new(value))
+        }
+
+        fn remove(&self, key: &u32) -> Option<Arc<u32>> {
+            self.0.lock().unwrap().remove(key)
+        }
+
+        fn clear(&self) {
+            self.0.lock().unwrap().clear();
+        }
+
+        fn stats(&self) -> CacheStats {
+            CacheStats::default()
+        }
+
+        fn change_capacity(&self, _capacity: u64) {}
+    }
+
+    #[test]
+    fn test_cache_defaults() {
+        let cache = MinimalCache(std::sync::Mutex::new(HashMap::new()));
+        cache.set(1, 1);
+        assert!(cache.contains_key(&1));
+        assert!(!cache.contains_key(&2));
+        assert!(cache.change_capacity_collect(0).is_empty());
+        assert!(cache.to_map().is_empty());
+        assert!(cache.hottest(10).is_empty());
+        assert_eq!(cache.policy_name(), "custom");
+        // without a listing, items are cleared but cannot be returned
+        assert!(cache.clear_returning().is_empty());
+        assert!(!cache.contains_key(&1));
+    }
+
+    #[test]
+    fn test_dyn_cache() {
+        fn fill<C: Cache<u64, u64>>(cache: C) -> u64 {
+            cache.set(1, 1);
+            cache.set(2, 2);
+            cache.stats().size
+        }
+        let shared: DynCache<u64, u64> = Arc::new(LRUCache::new(4));
+        assert_eq!(fill(shared.clone()), 2);
+        assert_eq!(fill(Box::new(LFUCache::new(4))), 2);
+        assert_eq!(shared.get(&1).map(|v| *v), Some(1));
+        assert!(shared.is::<LRUCache<u64, u64>>());
+        assert!(!shared.is::<LFUCache<u64, u64>>());
+        assert_eq!(
+            shared
+                .downcast_ref::<LRUCache<u64, u64>>()
+                .unwrap()
+                .age_rank(&2),
+            Some(0)
+        );
+    }
+
+    /// Every policy implements both, so the tests over every policy can use either.
+    trait PolicyCache<K: Eq + Hash + Clone + Send + Sync, V: Send + Sync>:
+        TryCache<K, V> + VersionedCache<K, V>
+    {
+    }
+
+    impl<K, V, C> PolicyCache<K, V> for C
+    where
+        K: Eq + Hash + Clone + Send + Sync,
+        V: Send + Sync,
+        C: TryCache<K, V> + VersionedCache<K, V>,
+    {
+    }
+
+    // one cache of every policy with the given capacity
+    fn every_policy(capacity: u64) -> Vec<Arc<dyn PolicyCache<u32, u32>>> {
+        vec![
+            Arc::new(LRUCache::new(capacity)),
+            Arc::new(LRUCache::new(capacity).with_scan_resistance(1)),
+            Arc::new(MRUCache::new(capacity)),
+            Arc::new(LFUCache::new(capacity)),
+            Arc::new(FIFOCache::new(capacity)),
+            Arc::new(LIFOCache::new(capacity)),
+            Arc::new(RandomReplacementCache::new(capacity)),
+            Arc::new(TTLCache::new(Duration::from_secs(60), capacity)),
+            Arc::new(WindowedLFUCache::new(capacity)),
+            Arc::new(TwoQCache::new(capacity)),
+            Arc::new(ClockCache::new(capacity)),
+            Arc::new(ClockProCache::new(capacity)),
+            Arc::new(GDSFCache::new(capacity)),
+        ]
+    }
+
+    #[test]
+    fn test_items_in_order() {
+        let keys = |items: Option<Vec<(u32, Arc<u32>)>>| {
+            items.map(|items| items.into_iter().map(|(key, _)| key).collect::<Vec<_>>())
+        };
+        let fill = |cache: &dyn Cache<u32, u32>| {
+            for key in 1..=3 {
+                cache.set(key, key);
+            }
+            for key in [1, 1, 2] {
+                cache.get(&key);
+            }
+        };
+        type Case = (Box<dyn Cache<u32, u32>>, IterationOrder, Vec<u32>);
+        let cases: Vec<Case> = vec![
+            (
+                Box::new(FIFOCache::new(10)),
+                IterationOrder::Insertion,
+                vec![1, 2, 3],
+            ),
+            (
+                Box::new(LIFOCache::new(10)),
+                IterationOrder::Insertion,
+                vec![1, 2, 3],
+            ),
+            (
+                Box::new(LFUCache::new(10)),
+                IterationOrder::Insertion,
+                vec![1, 2, 3],
+            ),
+            (
+                Box::new(LRUCache::new(10)),
+                IterationOrder::Recency,
+                vec![3, 1, 2],
+            ),
+            (
+                Box::new(MRUCache::new(10)),
+                IterationOrder::Recency,
+                vec![3, 1, 2],
+            ),
+            (
+                Box::new(TTLCache::new(Duration::from_secs(60), 10)),
+                IterationOrder::Recency,
+                vec![3, 1, 2],
+            ),
+            (
+                Box::new(LFUCache::new(10)),
+                IterationOrder::Frequency,
+                vec![3, 2, 1],
+            ),
+            (
+                Box::new(WindowedLFUCache::new(10)),
+                IterationOrder::Frequency,
+                vec![3, 2, 1],
+            ),
+        ];
+        for (cache, order, expected) in cases {
+            fill(cache.as_ref());
+            assert_eq!(
+                keys(cache.items(order)),
+                Some(expected),
+                "{}",
+                cache.policy_name()
+            );
+        }
+        for cache in every_policy(10) {
+            fill(cache.as_ref());
+            let mut arbitrary = keys(cache.items(IterationOrder::Arbitrary)).unwrap();
+            arbitrary.sort();
+            assert_eq!(arbitrary, vec![1, 2, 3], "{}", cache.policy_name());
+        }
+        assert!(LRUCache::<u32, u32>::new(10)
+            .items(IterationOrder::Frequency)
+            .is_none());
+        assert!(RandomReplacementCache::<u32, u32>::new(10)
+            .items(IterationOrder::Recency)
+            .is_none());
+    }
+
+    #[test]
+    fn test_capacity_zero() {
+        for cache in every_policy(0) {
+            let policy = cache.policy_name();
+            assert_eq!(cache.set(1, 1), None, "{}", policy);
+            assert_eq!(cache.get(&1), None, "{}", policy);
+            assert_eq!(
+                cache.try_set(1, 1),
+                Err(CacheError::CapacityZero),
+                "{}",
+                policy
+            );
+            assert_eq!(cache.stats().size, 0, "{}", policy);
+            assert!(cache.hottest(10).is_empty(), "{}", policy);
+            assert_eq!(cache.shed(0.5), 0, "{}", policy);
+        }
+        for cache in every_policy(4) {
+            let policy = cache.policy_name();
+            cache.set(1, 1);
+            cache.set(2, 2);
+            assert_eq!(cache.change_capacity_collect(0).len(), 2, "{}", policy);
+            assert_eq!(cache.stats().size, 0, "{}", policy);
+            assert_eq!(cache.set(3, 3), None, "{}", policy);
+            assert!(!cache.contains_key(&3), "{}", policy);
+        }
+    }
+
+    #[test]
+    fn test_capacity_one() {
+        for cache in every_policy(1) {
+            let policy = cache.policy_name();
+            cache.set(1, 1);
+            assert_eq!(cache.get(&1).map(|v| *v), Some(1), "{}", policy);
+            assert_eq!(cache.set(1, 10).map(|v| *v), Some(1), "{}", policy);
+            // the newest item always replaces the only one
+            cache.set(2, 2);
+            assert_eq!(cache.get(&2).map(|v| *v), Some(2), "{}", policy);
+            assert!(!cache.contains_key(&1), "{}", policy);
+            assert_eq!(cache.stats().size, 1, "{}", policy);
+            assert_eq!(cache.shed(0.5), 1, "{}", policy);
+            assert_eq!(cache.stats().size, 0, "{}", policy);
+        }
+    }
+
+    #[test]
+    fn test_overwrite_does_not_evict() {
+        for cache in every_policy(3) {
+            let policy = cache.policy_name();
+            for key in 0..3 {
+                cache.set(key, key);
+            }
+            for round in 1..5 {
+                for key in 0..3 {
+                    assert_eq!(
+                        cache.set(key, key + round * 10).map(|v| *v),
+                        Some(key + (round - 1) * 10),
+                        "{}",
+                        policy
+                    );
+                }
+            }
+            assert_eq!(cache.stats().size, 3, "{}", policy);
+            for key in 0..3 {
+                assert_eq!(cache.get(&key).map(|v| *v), Some(key + 40), "{}", policy);
+            }
+        }
+    }
+
+    #[test]
+    fn test_stats_consistent_across_policies() {
+        for cache in every_policy(2) {
+            let policy = cache.policy_name();
+            cache.set(1, 1);
+            cache.set(2, 2);
+            cache.set(1, 10);
+            cache.set(3, 3);
+            cache.get(&3);
+            cache.get(&99);
+            let stats = cache.stats();
+            assert_eq!(stats.inserts, 3, "{}", policy);
+            assert_eq!(stats.updates, 1, "{}", policy);
+            assert_eq!(stats.evictions, 1, "{}", policy);
+            assert_eq!(stats.expirations, 0, "{}", policy);
+            assert_eq!((stats.hits, stats.misses), (1, 1), "{}", policy);
+        }
+        let cache = LFUCache::new(2).with_ttl(Duration::ZERO);
+        cache.set(1, 1);
+        cache.get(&1);
+        let stats = cache.stats();
+        assert_eq!((stats.expirations, stats.misses), (1, 1));
+    }
+
+    #[test]
+    fn test_every_policy_has_unique_id() {
+        let caches = every_policy(2);
+        let ids: std::collections::HashSet<u64> = caches
+            .iter()
+            .map(|cache| cache.stats().cache.id())
+            .collect();
+        assert_eq!(ids.len(), caches.len());
+        assert!(!ids.contains(&0));
+
+        let events = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = events.clone();
+        let cache = TwoQCache::new(1)
+            .with_name("sessions")
+            .with_eviction_listener(move |event: &crate::RemovalEvent<u32, u32>| {
+                sink.lock().unwrap().push(event.cache.clone());
+            });
+        cache.set(1, 1);
+        cache.set(2, 2);
+        assert_eq!(*events.lock().unwrap(), vec![cache.stats().cache]);
+        assert_eq!(events.lock().unwrap()[0].name(), Some("sessions"));
+    }
+
+    #[test]
+    fn test_wasted_inserts() {
+        for cache in every_policy(1) {
+            let policy = cache.policy_name();
+            cache.set(1, 1);
+            cache.get(&1);
+            cache.set(2, 2);
+            cache.set(3, 3);
+            let stats = cache.stats();
+            assert_eq!(stats.evictions, 2, "{}", policy);
+            assert_eq!(stats.wasted_inserts, 1, "{}", policy);
+        }
+    }
+
+    #[test]
+    fn test_eviction_ages() {
+        for cache in every_policy(1) {
+            let policy = cache.policy_name();
+            cache.set(1, 1);
+            cache.set(2, 2);
+            cache.set(3, 3);
+            let ages = cache.detailed_stats().eviction_ages.unwrap();
+            assert_eq!(ages.lived.under_1s, 2, "{}", policy);
+            assert_eq!(ages.idle.under_1s, 2, "{}", policy);
+            assert_eq!(ages.lived.older, 0, "{}", policy);
+        }
+
+        let mut ages = AgeDistribution::default();
+        for secs in [0, 5, 30, 120, 1200, 7200] {
+            ages.record(Duration::from_secs(secs));
+        }
+        assert_eq!((ages.under_1s, ages.under_10s, ages.under_60s), (1, 1, 1));
+        assert_eq!((ages.under_10m, ages.under_1h, ages.older), (1, 1, 1));
+    }
+
+    #[test]
+    fn test_detailed_stats() {
+        for cache in every_policy(2) {
+            let policy = cache.policy_name();
+            cache.set(1, 1);
+            cache.get(&1);
+            cache.get(&2);
+            let stats = cache.stats();
+            let detailed = cache.detailed_stats().stats;
+            assert_eq!(
+                (
+                    detailed.hits,
+                    detailed.misses,
+                    detailed.inserts,
+                    detailed.size
+                ),
+                (stats.hits, stats.misses, stats.inserts, stats.size),
+                "{}",
+                policy
+            );
+        }
+        for cache in every_policy(10) {
+            let policy = cache.policy_name();
+            for key in 0..3 {
+                cache.set(key, key);
+            }
+            let distribution = cache.detailed_stats().expiry_distribution.unwrap();
+            let counted = distribution.within_1s
+                + distribution.within_10s
+                + distribution.within_60s
+                + distribution.later
+                + distribution.never;
+            assert_eq!(counted, 3, "{}", policy);
+        }
+        let wrapped = BloomFilterCache::new(LRUCache::<u32, u32>::new(2), 100);
+        wrapped.get(&1);
+        assert_eq!(wrapped.detailed_stats().stats.misses, 1);
+    }
+
+    #[test]
+    fn test_versions_across_policies() {
+        for cache in every_policy(3) {
+            let policy = cache.policy_name();
+            assert_eq!(cache.set_versioned(1, 1, 0), Ok(None), "{}", policy);
+            let (value, version) = cache.get_versioned(&1).unwrap();
+            assert_eq!(
+                (*value, cache.version(&1)),
+                (1, Some(version)),
+                "{}",
+                policy
+            );
+            assert!(cache.set_versioned(1, 2, version).is_ok(), "{}", policy);
+            let newer = cache.version(&1).unwrap();
+            assert!(newer > version, "{}", policy);
+            assert_eq!(
+                cache.set_versioned(1, 3, version),
+                Err(CacheError::VersionMismatch(newer)),
+                "{}",
+                policy
+            );
+            assert_eq!(
+                cache.set_versioned(1, 3, 0),
+                Err(CacheError::VersionMismatch(newer)),
+                "{}",
+                policy
+            );
+            // items which leave the cache in any way lose their version
+            for key in 2..10 {
+                cache.set(key, key);
+            }
+            cache.remove(&9);
+            for key in 1..10 {
+                assert_eq!(
+                    cache.version(&key).is_some(),
+                    cache.contains_key(&key),
+                    "{}",
+                    policy
+                );
+            }
+            cache.clear();
+            assert_eq!(cache.version(&2), None, "{}", policy);
+            assert_eq!(cache.set_versioned(1, 1, 0), Ok(None), "{}", policy);
+        }
+    }
+
+    #[test]
+    fn test_shed() {
+        let cache = LRUCache::new(10);
+        for key in 0..10 {
+            cache.set(key, key);
+        }
+        cache.get(&0);
+        assert_eq!(cache.shed(0.25), 3);
+        assert!(cache.contains_key(&0));
+        assert!(!cache.contains_key(&3));
+        assert!(cache.contains_key(&4));
+        assert_eq!(cache.stats().capacity, 10);
+        assert_eq!(cache.shed(0.0), 0);
+    }
+
+    #[test]
+    fn test_shed_every_policy() {
+        for cache in every_policy(10) {
+            for key in 0..10 {
+                cache.set(key, key);
+            }
+            assert_eq!(cache.shed(0.3), 3, "{}", cache.policy_name());
+            let stats = cache.stats();
+            assert_eq!(
+                (stats.size, stats.capacity),
+                (7, 10),
+                "{}",
+                cache.policy_name()
+            );
+            assert_eq!(stats.evictions, 3, "{}", cache.policy_name());
+            // the freed space is there to use again without evicting
+            for key in 10..13 {
+                cache.set(key, key);
+            }
+            assert_eq!(cache.stats().evictions, 3, "{}", cache.policy_name());
+            assert_eq!(cache.shed(1.0), 10, "{}", cache.policy_name());
+        }
+    }
+
+    #[test]
+    fn test_warm_from() {
+        let old = LFUCache::new(4);
+        for (key, uses) in [(1, 1), (2, 4), (3, 2), (4, 3)] {
+            old.set(key, key);
+            for _ in 0..uses {
+                old.get(&key);
+            }
+        }
+        let new = LRUCache::new(4);
+        assert_eq!(new.warm_from(&old, 2), 2);
+        assert!(new.contains_key(&2));
+        assert!(new.contains_key(&4));
+        assert!(!new.contains_key(&1));
+        assert_eq!(new.hottest(2), vec![(2, Arc::new(2)), (4, Arc::new(4))]);
+    }
+
+    #[test]
+    fn test_warm_from_ranking() {
+        let cache = LRUCache::new(3);
+        cache.set(5, 50);
+        let ranking = "1\n\n5\n2\n3\n4\n".as_bytes();
+        let loaded = cache.warm_from_ranking(ranking, |key| (*key != 2).then_some(key * 10));
+        // 5 was already cached and 2 failed to load, so 1 and 3 fill the cache
+        assert_eq!(loaded.unwrap(), 2);
+        assert_eq!(
+            cache.hottest(3),
+            vec![(1, Arc::new(10)), (3, Arc::new(30)), (5, Arc::new(50))]
+        );
+        assert!(!cache.contains_key(&4));
+
+        let cache = LRUCache::<u32, u32>::new(3);
+        let error = cache
+            .warm_from_ranking("1\nnot a key\n".as_bytes(), |key| Some(*key))
+            .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(cache.stats().size, 0);
+    }
+
+    #[test]
+    fn test_export_ranking() {
+        let old = LFUCache::new(4);
+        for (key, uses) in [(1, 1), (2, 4), (3, 2), (4, 3)] {
+            old.set(key, key);
+            for _ in 0..uses {
+                old.get(&key);
+            }
+        }
+        let mut ranking = Vec::new();
+        assert_eq!(old.export_ranking(&mut ranking).unwrap(), 4);
+        assert_eq!(
+            String::from_utf8(ranking.clone()).unwrap(),
+            format!("#arcache-ranking {}\n2\n4\n3\n1\n", RANKING_VERSION)
+        );
+
+        let new = LRUCache::new(2);
+        let loaded = new.warm_from_ranking(ranking.as_slice(), |key| Some(key * 10));
+        assert_eq!(loaded.unwrap(), 2);
+        assert_eq!(new.hottest(2), vec![(2, Arc::new(20)), (4, Arc::new(40))]);
+
+        let error = new
+            .warm_from_ranking("#arcache-ranking 99\n1\n".as_bytes(), |key| Some(*key))
+            .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+}
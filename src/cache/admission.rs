@@ -0,0 +1,351 @@
+use crate::sync::atomic::{AtomicU64, Ordering};
+use crate::sync::Mutex;
+use std::any::Any;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::cache::bloom::BloomFilter;
+use crate::cache::listener::EntrySource;
+use crate::cache::{Cache, CacheStats, DetailedStats, GetOutcome, IterationOrder, TryCache};
+use crate::error::CacheError;
+use crate::sketch::FrequencySketch;
+
+/// AdmissionPolicy decides whether a new key is allowed into a cache.
+///
+/// The policy is only consulted for keys which are not already in the cache, overwrites are always admitted.
+pub trait AdmissionPolicy<K>: Send + Sync {
+    /// Return true if the key should be inserted into the cache.
+    fn admit(&self, key: &K) -> bool;
+
+    /// Forget any state the policy has accumulated, called when the cache is cleared.
+    fn clear(&self) {}
+}
+
+/// Doorkeeper is an admission policy that only admits keys which have been seen at least twice.
+///
+/// The first time a key is seen it is recorded in a bloom filter and rejected, the second time it is admitted. The filter is reset after a configurable number of insertions so that stale history ages out.
+pub struct Doorkeeper {
+    filter: BloomFilter,
+    insertions: AtomicU64,
+    reset_after: u64,
+}
+
+impl Doorkeeper {
+    /// Create a new Doorkeeper sized for the given number of distinct keys, it resets after that many keys have been recorded.
+    pub fn new(expected_keys: usize) -> Self {
+        Doorkeeper {
+            filter: BloomFilter::new(expected_keys),
+            insertions: AtomicU64::new(0),
+            reset_after: expected_keys.max(1) as u64,
+        }
+    }
+}
+
+impl<K: Hash> AdmissionPolicy<K> for Doorkeeper {
+    /// Admit the key if it has been seen before, otherwise record it and reject it.
+    fn admit(&self, key: &K) -> bool {
+        if self.filter.insert(key) {
+            return true;
+        }
+        if self.insertions.fetch_add(1, Ordering::Relaxed) + 1 >= self.reset_after {
+            self.insertions.store(0, Ordering::Relaxed);
+            self.filter.clear();
+        }
+        false
+    }
+
+    /// Reset the bloom filter.
+    fn clear(&self) {
+        self.insertions.store(0, Ordering::Relaxed);
+        self.filter.clear();
+    }
+}
+
+/// FrequencyAdmission is an admission policy that admits keys once a FrequencySketch estimates they have been seen `threshold` times.
+///
+/// Unlike the Doorkeeper this ages gradually, halving all counts periodically rather than forgetting everything at once.
+pub struct FrequencyAdmission {
+    sketch: Mutex<FrequencySketch>,
+    threshold: u8,
+}
+
+impl FrequencyAdmission {
+    /// Create a new FrequencyAdmission sized for the given number of distinct keys, admitting keys seen at least `threshold` times.
+    pub fn new(expected_keys: usize, threshold: u8) -> Self {
+        FrequencyAdmission {
+            sketch: Mutex::new(FrequencySketch::new(expected_keys)),
+            threshold,
+        }
+    }
+}
+
+impl<K: Hash> AdmissionPolicy<K> for FrequencyAdmission {
+    /// Record the key and admit it if its estimated frequency has reached the threshold.
+    fn admit(&self, key: &K) -> bool {
+        let mut sketch = self.sketch.lock().unwrap();
+        sketch.increment(key);
+        sketch.frequency(key) >= self.threshold
+    }
+
+    /// Zero the frequency sketch.
+    fn clear(&self) {
+        let mut sketch = self.sketch.lock().unwrap();
+        sketch.clear();
+    }
+}
+
+/// AdmissionCache wraps any cache and consults an AdmissionPolicy before inserting new keys.
+///
+/// Rejected sets are dropped and return `None`, they are counted and can be read with `rejected`.
+///
+/// Example:
+/// ```
+/// use arcache::{AdmissionCache, Cache, Doorkeeper, LRUCache};
+///
+/// let cache = AdmissionCache::new(LRUCache::<&str, String>::new(10), Doorkeeper::new(1000));
+///
+/// // the first sighting of a key is rejected
+/// cache.set("key", "value".to_string());
+/// assert!(cache.get(&"key").is_none());
+///
+/// // the second is admitted
+/// cache.set("key", "value".to_string());
+/// assert_eq!(*cache.get(&"key").unwrap(), "value".to_string());
+/// assert_eq!(cache.rejected(), 1);
+/// ```
+pub struct AdmissionCache<C, P> {
+    cache: C,
+    policy: P,
+    rejected: AtomicU64,
+}
+
+impl<C, P> AdmissionCache<C, P> {
+    /// Create a new AdmissionCache wrapping the given cache with the given admission policy.
+    pub fn new(cache: C, policy: P) -> Self {
+        AdmissionCache {
+            cache,
+            policy,
+            rejected: AtomicU64::new(0),
+        }
+    }
+
+    /// Get the number of sets rejected by the admission policy.
+    pub fn rejected(&self) -> u64 {
+        self.rejected.load(Ordering::Relaxed)
+    }
+
+    /// Get a reference to the wrapped cache.
+    pub fn inner(&self) -> &C {
+        &self.cache
+    }
+
+    /// Check if a set of `key` should go ahead, counting it as rejected if not.
+    fn admits<K>(&self, key: &K) -> bool
+    where
+        K: Eq + Hash + Clone + Send + Sync,
+        P: AdmissionPolicy<K>,
+    {
+        if !self.policy.admit(key) {
+            self.rejected.fetch_add(1, Ordering::Relaxed);
+            return false;
+        }
+        true
+    }
+}
+
+impl<K, V, C, P> Cache<K, V> for AdmissionCache<C, P>
+where
+    K: Eq + Hash + Clone + Send + Sync,
+    V: Send + Sync,
+    C: Cache<K, V>,
+    P: AdmissionPolicy<K>,
+{
+    /// Get a value from the cache.
+    fn get(&self, key: &K) -> Option<Arc<V>> {
+        self.cache.get(key)
+    }
+
+    /// Get a value from the wrapped cache, with whether it was a hit or why it was a miss.
+    fn get_with_outcome(&self, key: &K) -> (Option<Arc<V>>, GetOutcome) {
+        self.cache.get_with_outcome(key)
+    }
+
+    /// Set a value in the cache if the key is already present or the admission policy admits it.
+    fn set(&self, key: K, value: V) -> Option<Arc<V>> {
+        self.set_with_source(key, value, EntrySource::Inserted)
+    }
+
+    /// Set a value like `set`, passing on how it came to be in the cache to the wrapped cache.
+    fn set_with_source(&self, key: K, value: V, source: EntrySource) -> Option<Arc<V>> {
+        if !self.cache.contains_key(&key) && !self.admits(&key) {
+            return None;
+        }
+        self.cache.set_with_source(key, value, source)
+    }
+
+    /// Remove a value from the cache.
+    fn remove(&self, key: &K) -> Option<Arc<V>> {
+        self.cache.remove(key)
+    }
+
+    /// Check if a key is in the cache.
+    fn contains_key(&self, key: &K) -> bool {
+        self.cache.contains_key(key)
+    }
+
+    /// Get the time left before an item expires, or None if it never expires or is not in the cache.
+    fn ttl_remaining(&self, key: &K) -> Option<Duration> {
+        self.cache.ttl_remaining(key)
+    }
+
+    /// Clear the cache and the admission policy's history.
+    fn clear(&self) {
+        self.cache.clear();
+        self.policy.clear();
+    }
+
+    /// Clear the cache and the admission policy's history, returning every item which was removed.
+    fn clear_returning(&self) -> Vec<(K, Arc<V>)> {
+        let cleared = self.cache.clear_returning();
+        self.policy.clear();
+        cleared
+    }
+
+    /// Copy the items of the wrapped cache into a HashMap.
+    fn to_map(&self) -> HashMap<K, Arc<V>> {
+        self.cache.to_map()
+    }
+
+    /// Get the hottest items of the wrapped cache.
+    fn hottest(&self, limit: usize) -> Vec<(K, Arc<V>)> {
+        self.cache.hottest(limit)
+    }
+
+    /// Get the items of the wrapped cache in the given order.
+    fn items(&self, order: IterationOrder) -> Option<Vec<(K, Arc<V>)>> {
+        self.cache.items(order)
+    }
+
+    /// Get the name of the wrapped cache's eviction policy.
+    fn policy_name(&self) -> &'static str {
+        self.cache.policy_name()
+    }
+
+    /// Get the cache as `Any`, so it can be downcast from a `dyn Cache`.
+    fn as_any(&self) -> Option<&dyn Any>
+    where
+        Self: 'static,
+    {
+        Some(self)
+    }
+
+    /// Get the statistics of the wrapped cache.
+    fn stats(&self) -> CacheStats {
+        self.cache.stats()
+    }
+
+    /// Get the detailed statistics of the wrapped cache.
+    fn detailed_stats(&self) -> DetailedStats {
+        self.cache.detailed_stats()
+    }
+
+    /// Change the capacity of the wrapped cache.
+    fn change_capacity(&self, capacity: u64) {
+        self.cache.change_capacity(capacity);
+    }
+
+    /// Change the capacity of the wrapped cache, returning the items which were removed.
+    fn change_capacity_collect(&self, capacity: u64) -> Vec<(K, Arc<V>)> {
+        self.cache.change_capacity_collect(capacity)
+    }
+
+    /// Shed `fraction` of the items of the wrapped cache.
+    fn shed(&self, fraction: f64) -> usize {
+        self.cache.shed(fraction)
+    }
+}
+
+impl<K, V, C, P> TryCache<K, V> for AdmissionCache<C, P>
+where
+    K: Eq + Hash + Clone + Send + Sync,
+    V: Send + Sync,
+    C: TryCache<K, V>,
+    P: AdmissionPolicy<K>,
+{
+    /// Get a value from the wrapped cache.
+    fn try_get(&self, key: &K) -> Result<Option<Arc<V>>, CacheError> {
+        self.cache.try_get(key)
+    }
+
+    /// Set a value in the wrapped cache, returning `CacheError::RejectedByAdmission` if the admission policy rejects it.
+    fn try_set(&self, key: K, value: V) -> Result<Option<Arc<V>>, CacheError> {
+        if !self.cache.contains_key(&key) && !self.admits(&key) {
+            return Err(CacheError::RejectedByAdmission);
+        }
+        self.cache.try_set(key, value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::LRUCache;
+
+    #[test]
+    fn test_doorkeeper() {
+        let doorkeeper = Doorkeeper::new(100);
+        assert!(!doorkeeper.admit(&1));
+        assert!(doorkeeper.admit(&1));
+        assert!(!doorkeeper.admit(&2));
+        AdmissionPolicy::<i32>::clear(&doorkeeper);
+        assert!(!doorkeeper.admit(&1));
+    }
+
+    #[test]
+    fn test_frequency_admission() {
+        let policy = FrequencyAdmission::new(100, 3);
+        assert!(!policy.admit(&1));
+        assert!(!policy.admit(&1));
+        assert!(policy.admit(&1));
+        assert!(!policy.admit(&2));
+    }
+
+    #[test]
+    fn test_admission_cache_protects_working_set() {
+        let cache = AdmissionCache::new(LRUCache::new(2), Doorkeeper::new(1000));
+        for _ in 0..2 {
+            cache.set(1, 1);
+            cache.set(2, 2);
+        }
+        // a scan of one-hit wonders does not evict the working set
+        for i in 100..200 {
+            cache.set(i, i);
+        }
+        assert_eq!(cache.get(&1).map(|v| *v), Some(1));
+        assert_eq!(cache.get(&2).map(|v| *v), Some(2));
+        assert_eq!(cache.rejected(), 102);
+    }
+
+    #[test]
+    fn test_admission_cache_always_admits_overwrites() {
+        let cache = AdmissionCache::new(LRUCache::new(2), Doorkeeper::new(1000));
+        cache.set(1, 1);
+        cache.set(1, 1);
+        cache.clear();
+        cache.set(1, 1);
+        cache.set(1, 1);
+        assert_eq!(cache.set(1, 2).map(|v| *v), Some(1));
+        assert_eq!(cache.get(&1).map(|v| *v), Some(2));
+    }
+
+    #[test]
+    fn test_admission_cache_try_set() {
+        let cache = AdmissionCache::new(LRUCache::new(2), Doorkeeper::new(1000));
+        assert_eq!(cache.try_set(1, 1), Err(CacheError::RejectedByAdmission));
+        assert_eq!(cache.try_set(1, 1), Ok(None));
+        assert_eq!(cache.try_get(&1).unwrap().map(|v| *v), Some(1));
+        assert_eq!(cache.rejected(), 1);
+    }
+}
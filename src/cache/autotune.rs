@@ -0,0 +1,217 @@
+use std::hash::Hash;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::background::BackgroundTask;
+use crate::cache::{Cache, CacheStats};
+
+// ticks with fewer lookups than this leave the capacity alone, the hit rate is too noisy to act on
+const DEFAULT_MIN_LOOKUPS: u64 = 100;
+
+/// CapacityTuner grows or shrinks a cache's capacity towards a target hit rate, within bounds and an optional memory budget.
+///
+/// Each `tick` looks at the hits, misses and evictions since the previous tick. If the hit rate fell short of the target and the cache evicted items, the capacity grows by `step`, since a larger cache would have kept them. Evictions stand in for the hits a larger capacity would gain, no ghost entries or miss ratio curve are kept, so a cache which evicts while its misses are for keys it never held keeps growing up to its bounds. If the hit rate is above the target, the capacity shrinks by `step` to hand memory back. Within `tolerance` of the target nothing changes. The capacity never leaves the bounds, and a memory budget lowers the upper bound to the number of items of the given size which fit in it.
+///
+/// Example:
+/// ```
+/// use arcache::{Cache, CapacityTuner, LRUCache};
+///
+/// let cache = LRUCache::<u64, u64>::new(10);
+/// let mut tuner = CapacityTuner::new(0.9).with_bounds(10, 1000).with_min_lookups(1);
+///
+/// // cycling over more keys than fit misses every time
+/// for _ in 0..2 {
+///     for key in 0..50 {
+///         if cache.get(&key).is_none() {
+///             cache.set(key, key);
+///         }
+///     }
+/// }
+/// assert_eq!(tuner.tick(&cache), Some(11));
+/// assert_eq!(cache.stats().capacity, 11);
+/// ```
+#[derive(Debug, Clone)]
+pub struct CapacityTuner {
+    target_hit_rate: f64,
+    tolerance: f64,
+    step: f64,
+    min_capacity: u64,
+    max_capacity: u64,
+    min_lookups: u64,
+    last: CacheStats,
+}
+
+impl CapacityTuner {
+    /// Create a new CapacityTuner aiming for `target_hit_rate`, which is clamped between 0 and 1.
+    pub fn new(target_hit_rate: f64) -> Self {
+        CapacityTuner {
+            target_hit_rate: target_hit_rate.clamp(0.0, 1.0),
+            tolerance: 0.02,
+            step: 0.1,
+            min_capacity: 1,
+            max_capacity: u64::MAX,
+            min_lookups: DEFAULT_MIN_LOOKUPS,
+            last: CacheStats::default(),
+        }
+    }
+
+    /// Set the smallest and largest capacity the tuner may choose.
+    pub fn with_bounds(mut self, min_capacity: u64, max_capacity: u64) -> Self {
+        self.min_capacity = min_capacity;
+        self.max_capacity = max_capacity.max(min_capacity);
+        self
+    }
+
+    /// Cap the capacity at the number of items of `item_bytes` which fit in `budget_bytes`.
+    pub fn with_memory_budget(mut self, budget_bytes: u64, item_bytes: u64) -> Self {
+        let fits = budget_bytes / item_bytes.max(1);
+        self.max_capacity = self.max_capacity.min(fits).max(self.min_capacity);
+        self
+    }
+
+    /// Set how far from the target the hit rate may be before the capacity changes, 0.02 by default.
+    pub fn with_tolerance(mut self, tolerance: f64) -> Self {
+        self.tolerance = tolerance.max(0.0);
+        self
+    }
+
+    /// Set the fraction of the capacity added or removed by each change, 0.1 by default. Every change is at least one item.
+    pub fn with_step(mut self, step: f64) -> Self {
+        self.step = step.max(0.0);
+        self
+    }
+
+    /// Set how many lookups a tick needs to see before it changes the capacity, 100 by default.
+    pub fn with_min_lookups(mut self, min_lookups: u64) -> Self {
+        self.min_lookups = min_lookups;
+        self
+    }
+
+    /// Compare the cache's statistics with the previous tick and change its capacity if needed, returning the new capacity if it changed.
+    ///
+    /// Ticks which see fewer lookups than the minimum are skipped and their lookups counted towards the next tick.
+    pub fn tick<K, V, C>(&mut self, cache: &C) -> Option<u64>
+    where
+        K: Eq + Hash + Clone + Send + Sync,
+        V: Send + Sync,
+        C: Cache<K, V> + ?Sized,
+    {
+        let stats = cache.stats();
+        let last = &self.last;
+        let hits = stats.hits.saturating_sub(last.hits);
+        let misses = stats.misses.saturating_sub(last.misses);
+        let evictions = stats.evictions.saturating_sub(last.evictions);
+        if hits + misses < self.min_lookups.max(1) {
+            return None;
+        }
+        let capacity = stats.capacity;
+        self.last = stats;
+        let hit_rate = hits as f64 / (hits + misses) as f64;
+        let step = ((capacity as f64 * self.step).ceil() as u64).max(1);
+        let tuned = if hit_rate < self.target_hit_rate - self.tolerance && evictions > 0 {
+            capacity.saturating_add(step)
+        } else if hit_rate > self.target_hit_rate + self.tolerance {
+            capacity.saturating_sub(step)
+        } else {
+            capacity
+        }
+        .clamp(self.min_capacity, self.max_capacity);
+        if tuned == capacity {
+            return None;
+        }
+        cache.change_capacity(tuned);
+        // items evicted by shrinking are not a sign the cache is too small
+        self.last.evictions = cache.stats().evictions;
+        Some(tuned)
+    }
+
+    /// Start a background thread which calls `tick` on the cache every `interval`.
+    ///
+    /// The thread runs until the returned handle is dropped or shut down, or the cache is dropped.
+    pub fn start<K, V, C>(mut self, cache: &Arc<C>, interval: Duration) -> BackgroundTask
+    where
+        K: Eq + Hash + Clone + Send + Sync,
+        V: Send + Sync,
+        C: Cache<K, V> + ?Sized + 'static,
+    {
+        let cache = Arc::downgrade(cache);
+        BackgroundTask::spawn_periodic("arcache-capacity-tuner", interval, move || {
+            match cache.upgrade() {
+                Some(cache) => {
+                    self.tick(cache.as_ref());
+                    true
+                }
+                None => false,
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::LRUCache;
+
+    fn cycle(cache: &LRUCache<u64, u64>, keys: u64) {
+        for key in 0..keys {
+            if cache.get(&key).is_none() {
+                cache.set(key, key);
+            }
+        }
+    }
+
+    #[test]
+    fn test_capacity_tuner() {
+        let cache = LRUCache::new(10);
+        let mut tuner = CapacityTuner::new(0.9)
+            .with_bounds(5, 12)
+            .with_min_lookups(20);
+        cycle(&cache, 10);
+        assert_eq!(tuner.tick(&cache), None);
+        cycle(&cache, 50);
+        assert_eq!(tuner.tick(&cache), Some(11));
+        cycle(&cache, 50);
+        assert_eq!(tuner.tick(&cache), Some(12));
+        cycle(&cache, 50);
+        assert_eq!(tuner.tick(&cache), None);
+        for _ in 0..100 {
+            cache.get(&49);
+        }
+        assert_eq!(tuner.tick(&cache), Some(10));
+        assert_eq!(cache.stats().capacity, 10);
+        // a full cache which never evicts is not grown, however low its hit rate
+        for key in 100..200 {
+            cache.get(&key);
+        }
+        assert_eq!(tuner.tick(&cache), None);
+    }
+
+    #[test]
+    fn test_capacity_tuner_memory_budget() {
+        let cache = LRUCache::new(10);
+        let mut tuner = CapacityTuner::new(1.0)
+            .with_memory_budget(1100, 100)
+            .with_min_lookups(1);
+        cycle(&cache, 50);
+        assert_eq!(tuner.tick(&cache), Some(11));
+        cycle(&cache, 50);
+        assert_eq!(tuner.tick(&cache), None);
+    }
+
+    #[test]
+    fn test_capacity_tuner_start() {
+        let cache = Arc::new(LRUCache::<u64, u64>::new(10));
+        let task = CapacityTuner::new(0.0)
+            .with_min_lookups(1)
+            .start(&cache, Duration::from_millis(1));
+        cache.set(1, 1);
+        while cache.stats().capacity == 10 {
+            cache.get(&1);
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        drop(cache);
+        while !task.is_finished() {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+}
@@ -0,0 +1,87 @@
+use crate::sync::atomic::{AtomicU64, Ordering};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+/// A lock-free bloom filter over atomic words, used to remember which keys have been seen.
+pub(crate) struct BloomFilter {
+    bits: Vec<AtomicU64>,
+    num_bits: u64,
+    num_hashes: u32,
+}
+
+impl BloomFilter {
+    /// Create a new BloomFilter sized for roughly `expected_items` with a false positive rate of about 1%.
+    pub(crate) fn new(expected_items: usize) -> Self {
+        // ~10 bits per item and 7 hash functions gives a false positive rate of about 1%.
+        let num_bits = (expected_items.max(1) as u64 * 10).next_multiple_of(64);
+        BloomFilter {
+            bits: (0..num_bits / 64).map(|_| AtomicU64::new(0)).collect(),
+            num_bits,
+            num_hashes: 7,
+        }
+    }
+
+    /// Derive the bit positions for a key using double hashing.
+    fn positions<K: Hash + ?Sized>(&self, key: &K) -> impl Iterator<Item = u64> + '_ {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        let hash = hasher.finish();
+        let h1 = hash & 0xffff_ffff;
+        let h2 = (hash >> 32) | 1;
+        (0..self.num_hashes as u64)
+            .map(move |i| h1.wrapping_add(i.wrapping_mul(h2)) % self.num_bits)
+    }
+
+    /// Insert a key, returning true if it may already have been present.
+    pub(crate) fn insert<K: Hash + ?Sized>(&self, key: &K) -> bool {
+        let mut present = true;
+        for bit in self.positions(key) {
+            let mask = 1 << (bit % 64);
+            let previous = self.bits[(bit / 64) as usize].fetch_or(mask, Ordering::Relaxed);
+            present &= previous & mask != 0;
+        }
+        present
+    }
+
+    /// Check if a key may have been inserted, without inserting it. False means it was never inserted.
+    pub(crate) fn contains<K: Hash + ?Sized>(&self, key: &K) -> bool {
+        self.positions(key).all(|bit| {
+            let mask = 1 << (bit % 64);
+            self.bits[(bit / 64) as usize].load(Ordering::Relaxed) & mask != 0
+        })
+    }
+
+    /// Reset the filter, forgetting every key.
+    pub(crate) fn clear(&self) {
+        for word in &self.bits {
+            word.store(0, Ordering::Relaxed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bloom_filter() {
+        let filter = BloomFilter::new(100);
+        assert!(!filter.contains(&1));
+        assert!(!filter.insert(&1));
+        assert!(filter.contains(&1));
+        assert!(filter.insert(&1));
+        filter.clear();
+        assert!(!filter.insert(&1));
+    }
+
+    #[test]
+    fn test_bloom_filter_no_false_negatives() {
+        let filter = BloomFilter::new(1000);
+        for i in 0..1000 {
+            filter.insert(&i);
+        }
+        assert!((0..1000).all(|i| filter.insert(&i)));
+        let false_positives = (1000..1100).filter(|i| filter.insert(i)).count();
+        assert!(false_positives < 10);
+    }
+}
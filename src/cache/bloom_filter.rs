@@ -0,0 +1,243 @@
+use crate::sync::atomic::{AtomicU64, Ordering};
+use std::any::Any;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::cache::bloom::BloomFilter;
+use crate::cache::listener::EntrySource;
+use crate::cache::{Cache, CacheStats, DetailedStats, GetOutcome, IterationOrder, TryCache};
+use crate::error::CacheError;
+
+/// BloomFilterCache wraps any cache with a bloom filter of every key ever set, so gets of keys which were never set miss without touching the wrapped cache or its lock.
+///
+/// The filter is lock free and never forgets a key, even when the item leaves the cache, so size it for the number of distinct keys you expect to set over the cache's lifetime. Past that it fills up and lets more unknown keys through to the wrapped cache, which is still correct but slower. Misses answered by the filter are added to the wrapped cache's misses in `stats`, they are not seen by the wrapped cache's access listeners.
+///
+/// Example:
+/// ```
+/// use arcache::{BloomFilterCache, Cache, LRUCache};
+///
+/// let cache = BloomFilterCache::new(LRUCache::<&str, String>::new(10), 1000);
+///
+/// cache.set("key", "value".to_string());
+/// assert_eq!(*cache.get(&"key").unwrap(), "value".to_string());
+///
+/// // a key which was never set misses without taking the cache lock
+/// assert!(cache.get(&"other").is_none());
+/// assert_eq!(cache.filtered(), 1);
+/// ```
+pub struct BloomFilterCache<C> {
+    cache: C,
+    filter: BloomFilter,
+    filtered: AtomicU64,
+}
+
+impl<C> BloomFilterCache<C> {
+    /// Create a new BloomFilterCache wrapping the given cache, with a filter sized for `expected_keys` distinct keys.
+    pub fn new(cache: C, expected_keys: usize) -> Self {
+        BloomFilterCache {
+            cache,
+            filter: BloomFilter::new(expected_keys),
+            filtered: AtomicU64::new(0),
+        }
+    }
+
+    /// Get the number of gets answered by the filter alone.
+    pub fn filtered(&self) -> u64 {
+        self.filtered.load(Ordering::Relaxed)
+    }
+
+    /// Get a reference to the wrapped cache.
+    pub fn inner(&self) -> &C {
+        &self.cache
+    }
+
+    /// Check if a key may have been set, counting it as filtered if it was not.
+    fn may_contain<K: Hash>(&self, key: &K) -> bool {
+        if !self.filter.contains(key) {
+            self.filtered.fetch_add(1, Ordering::Relaxed);
+            return false;
+        }
+        true
+    }
+}
+
+impl<K, V, C> Cache<K, V> for BloomFilterCache<C>
+where
+    K: Eq + Hash + Clone + Send + Sync,
+    V: Send + Sync,
+    C: Cache<K, V>,
+{
+    /// Get a value from the cache, missing straight away if the key was never set.
+    fn get(&self, key: &K) -> Option<Arc<V>> {
+        if !self.may_contain(key) {
+            return None;
+        }
+        self.cache.get(key)
+    }
+
+    /// Get a value with whether it was a hit or why it was a miss, a key the filter has never seen is absent.
+    fn get_with_outcome(&self, key: &K) -> (Option<Arc<V>>, GetOutcome) {
+        if !self.may_contain(key) {
+            return (None, GetOutcome::MissAbsent);
+        }
+        self.cache.get_with_outcome(key)
+    }
+
+    /// Set a value in the cache, recording the key in the filter.
+    fn set(&self, key: K, value: V) -> Option<Arc<V>> {
+        self.set_with_source(key, value, EntrySource::Inserted)
+    }
+
+    /// Set a value like `set`, passing on how it came to be in the cache to the wrapped cache.
+    fn set_with_source(&self, key: K, value: V, source: EntrySource) -> Option<Arc<V>> {
+        self.filter.insert(&key);
+        self.cache.set_with_source(key, value, source)
+    }
+
+    /// Remove a value from the cache.
+    fn remove(&self, key: &K) -> Option<Arc<V>> {
+        self.cache.remove(key)
+    }
+
+    /// Check if a key is in the cache, answering false straight away if the key was never set, without counting a miss.
+    fn contains_key(&self, key: &K) -> bool {
+        self.filter.contains(key) && self.cache.contains_key(key)
+    }
+
+    /// Get the time left before an item expires, or None if it never expires or is not in the cache.
+    fn ttl_remaining(&self, key: &K) -> Option<Duration> {
+        self.cache.ttl_remaining(key)
+    }
+
+    /// Clear the wrapped cache, the filter keeps every key.
+    fn clear(&self) {
+        self.cache.clear();
+    }
+
+    /// Clear the wrapped cache, returning every item which was removed.
+    fn clear_returning(&self) -> Vec<(K, Arc<V>)> {
+        self.cache.clear_returning()
+    }
+
+    /// Copy the items of the wrapped cache into a HashMap.
+    fn to_map(&self) -> HashMap<K, Arc<V>> {
+        self.cache.to_map()
+    }
+
+    /// Get the hottest items of the wrapped cache.
+    fn hottest(&self, limit: usize) -> Vec<(K, Arc<V>)> {
+        self.cache.hottest(limit)
+    }
+
+    /// Get the items of the wrapped cache in the given order.
+    fn items(&self, order: IterationOrder) -> Option<Vec<(K, Arc<V>)>> {
+        self.cache.items(order)
+    }
+
+    /// Get the name of the wrapped cache's eviction policy.
+    fn policy_name(&self) -> &'static str {
+        self.cache.policy_name()
+    }
+
+    /// Get the cache as `Any`, so it can be downcast from a `dyn Cache`.
+    fn as_any(&self) -> Option<&dyn Any>
+    where
+        Self: 'static,
+    {
+        Some(self)
+    }
+
+    /// Get the statistics of the wrapped cache, with the gets answered by the filter added to the misses.
+    fn stats(&self) -> CacheStats {
+        let mut stats = self.cache.stats();
+        stats.misses += self.filtered();
+        stats
+    }
+
+    /// Get the detailed statistics of the wrapped cache, with the gets answered by the filter added to the misses.
+    fn detailed_stats(&self) -> DetailedStats {
+        let mut detailed = self.cache.detailed_stats();
+        detailed.stats.misses += self.filtered();
+        detailed
+    }
+
+    /// Change the capacity of the wrapped cache.
+    fn change_capacity(&self, capacity: u64) {
+        self.cache.change_capacity(capacity);
+    }
+
+    /// Change the capacity of the wrapped cache, returning the items which were removed.
+    fn change_capacity_collect(&self, capacity: u64) -> Vec<(K, Arc<V>)> {
+        self.cache.change_capacity_collect(capacity)
+    }
+
+    /// Shed `fraction` of the items of the wrapped cache.
+    fn shed(&self, fraction: f64) -> usize {
+        self.cache.shed(fraction)
+    }
+}
+
+impl<K, V, C> TryCache<K, V> for BloomFilterCache<C>
+where
+    K: Eq + Hash + Clone + Send + Sync,
+    V: Send + Sync,
+    C: TryCache<K, V>,
+{
+    /// Get a value from the wrapped cache, missing straight away if the key was never set.
+    fn try_get(&self, key: &K) -> Result<Option<Arc<V>>, CacheError> {
+        if !self.may_contain(key) {
+            return Ok(None);
+        }
+        self.cache.try_get(key)
+    }
+
+    /// Set a value in the wrapped cache, recording the key in the filter.
+    fn try_set(&self, key: K, value: V) -> Result<Option<Arc<V>>, CacheError> {
+        self.filter.insert(&key);
+        self.cache.try_set(key, value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::LRUCache;
+
+    #[test]
+    fn test_bloom_filter_cache() {
+        let cache = BloomFilterCache::new(LRUCache::new(2), 1000);
+        cache.set(1, 1);
+        cache.set(2, 2);
+        cache.set(3, 3);
+        assert_eq!(cache.get(&3).map(|v| *v), Some(3));
+        // evicted keys pass the filter and miss in the wrapped cache
+        assert_eq!(cache.get(&1), None);
+        assert_eq!(cache.filtered(), 0);
+        for key in 100..200 {
+            assert_eq!(cache.get(&key), None);
+        }
+        assert!(cache.filtered() >= 95);
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses), (1, 101));
+        // existence checks are not gets, so they are not counted
+        let filtered = cache.filtered();
+        for key in 200..300 {
+            assert!(!cache.contains_key(&key));
+        }
+        assert_eq!(cache.filtered(), filtered);
+        assert_eq!(cache.stats().misses, 101);
+    }
+
+    #[test]
+    fn test_bloom_filter_cache_keeps_keys_after_clear() {
+        let cache = BloomFilterCache::new(LRUCache::new(2), 1000);
+        cache.set(1, 1);
+        cache.clear();
+        assert!(!cache.contains_key(&1));
+        cache.set(1, 1);
+        assert_eq!(cache.try_get(&1).unwrap().map(|v| *v), Some(1));
+        assert_eq!(cache.try_get(&2), Ok(None));
+    }
+}
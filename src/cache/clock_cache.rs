@@ -0,0 +1,808 @@
+use crate::sync::atomic::{AtomicBool, Ordering};
+use crate::sync::RwLock;
+use std::any::Any;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::cache::latency::{LatencyTracker, OperationLatencies};
+use crate::cache::listener::{
+    AccessListener, AccessListeners, CallbackPanics, EntrySource, EvictionListener, RemovalCause,
+};
+use crate::cache::metadata::{EntryInfo, EntryMetadata};
+use crate::cache::policy_core::{policy_methods, PolicyCore};
+use crate::cache::{
+    shed_count, Cache, CacheStats, DetailedStats, FullBehavior, GetOutcome, TryCache,
+    VersionedCache,
+};
+use crate::clock::Clock;
+use crate::error::CacheError;
+
+/// An item in the circular buffer, with its reference bit.
+///
+/// The bits are atomic so gets can set them while holding only a shared lock.
+struct Slot<K, V> {
+    key: K,
+    value: Arc<V>,
+    referenced: AtomicBool,
+    // whether the item has been read since it was last set, kept here rather than in the metadata so gets need not touch it
+    read: AtomicBool,
+}
+
+impl<K, V> Slot<K, V> {
+    /// Set the reference and read bits of an item which was just read, only writing them if they are clear.
+    fn mark_read(&self) {
+        if !self.referenced.load(Ordering::Relaxed) {
+            self.referenced.store(true, Ordering::Relaxed);
+        }
+        if !self.read.load(Ordering::Relaxed) {
+            self.read.store(true, Ordering::Relaxed);
+        }
+    }
+}
+
+/// ClockCacheInner contains the inner data structure for the ClockCache.
+struct ClockCacheInner<K: Eq + Hash + Clone + Send, V: Send + Sync> {
+    capacity: u64,
+    // the circular buffer the hand sweeps, empty slots are reused before the buffer grows
+    slots: Vec<Option<Slot<K, V>>>,
+    index: HashMap<K, usize>,
+    free: Vec<usize>,
+    hand: usize,
+    idle_tracking: bool,
+    core: PolicyCore<K, V>,
+}
+
+impl<K: Eq + Hash + Clone + Send, V: Send + Sync> ClockCacheInner<K, V> {
+    /// Create a new ClockCacheInner with the given capacity.
+    fn new(capacity: u64) -> Self {
+        ClockCacheInner {
+            capacity,
+            slots: Vec::with_capacity(capacity as usize),
+            index: HashMap::with_capacity(capacity as usize),
+            free: Vec::new(),
+            hand: 0,
+            idle_tracking: false,
+            core: PolicyCore::new(),
+        }
+    }
+
+    /// Check if a key is held.
+    fn holds(&self, key: &K) -> bool {
+        self.index.contains_key(key)
+    }
+
+    /// Get the number of items held.
+    fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    /// Put a new item in a free slot, or at the end of the buffer if there is none.
+    fn place(&mut self, key: K, value: Arc<V>) {
+        let slot = Slot {
+            key: key.clone(),
+            value,
+            referenced: AtomicBool::new(false),
+            read: AtomicBool::new(false),
+        };
+        let position = match self.free.pop() {
+            Some(position) => {
+                self.slots[position] = Some(slot);
+                position
+            }
+            None => {
+                self.slots.push(Some(slot));
+                self.slots.len() - 1
+            }
+        };
+        self.index.insert(key, position);
+    }
+
+    /// Remove an item from every structure, freeing its slot.
+    fn remove_entry(&mut self, key: &K) -> Option<Arc<V>> {
+        let position = self.index.remove(key)?;
+        let slot = self.slots[position].take()?;
+        self.free.push(position);
+        self.core.forget(key);
+        Some(slot.value)
+    }
+
+    /// Find an item and set its reference and read bits.
+    fn lookup(&self, key: &K) -> Option<Arc<V>> {
+        let slot = self.slots[*self.index.get(key)?].as_ref()?;
+        slot.mark_read();
+        Some(slot.value.clone())
+    }
+
+    /// Sweep the hand to the next item whose reference bit is clear, clearing the bits it passes, and return its slot.
+    ///
+    /// If priorities have been assigned only items with the lowest priority are considered, the hand passes the others without clearing their bits.
+    fn victim(&mut self) -> Option<usize> {
+        if self.index.is_empty() {
+            return None;
+        }
+        let lowest = self.core.priorities.lowest(self.len());
+        // every item considered has its bit cleared within one turn, so this ends within two
+        loop {
+            let position = self.hand;
+            self.hand = (self.hand + 1) % self.slots.len();
+            let Some(slot) = self.slots[position].as_ref() else {
+                continue;
+            };
+            if lowest.is_some_and(|lowest| self.core.priorities.get(&slot.key) != lowest) {
+                continue;
+            }
+            if slot.referenced.load(Ordering::Relaxed) {
+                slot.referenced.store(false, Ordering::Relaxed);
+                continue;
+            }
+            return Some(position);
+        }
+    }
+
+    /// Evict the first item the hand finds which has not been referenced since it last passed.
+    fn evict(&mut self) -> Option<(K, Arc<V>)> {
+        let position = self.victim()?;
+        let slot = self.slots[position].as_ref()?;
+        let key = slot.key.clone();
+        let mut lifetime = self.core.metadata.lifetime(&key, self.core.clock.now());
+        if let Some(lifetime) = lifetime.as_mut() {
+            lifetime.never_read &= !slot.read.load(Ordering::Relaxed);
+        }
+        let value = self.remove_entry(&key)?;
+        self.core
+            .listeners
+            .notify(&key, &value, RemovalCause::Evicted);
+        self.core.stats.record_eviction(lifetime);
+        Some((key, value))
+    }
+
+    /// Evict items per the policy until at most `len` are left.
+    fn evict_to(&mut self, len: usize) -> Vec<(K, Arc<V>)> {
+        let mut evicted = Vec::new();
+        while self.len() > len {
+            match self.evict() {
+                Some(item) => evicted.push(item),
+                None => break,
+            }
+        }
+        evicted
+    }
+
+    /// Remove every item which has passed its deadline.
+    fn purge_expired(&mut self) {
+        let now = self.core.clock.now();
+        while let Some(key) = self.core.expiries.pop_expired(now) {
+            if let Some(value) = self.remove_entry(&key) {
+                self.core
+                    .listeners
+                    .notify(&key, &value, RemovalCause::Expired);
+                self.core.stats.record_expiration();
+            }
+        }
+    }
+
+    /// Remove every item, notifying listeners and returning the removed items.
+    fn clear_entries(&mut self) -> Vec<(K, Arc<V>)> {
+        self.index.clear();
+        self.free.clear();
+        self.hand = 0;
+        self.core.clear();
+        let cleared: Vec<(K, Arc<V>)> = self
+            .slots
+            .drain(..)
+            .flatten()
+            .map(|slot| (slot.key, slot.value))
+            .collect();
+        for (key, value) in &cleared {
+            self.core
+                .listeners
+                .notify(key, value, RemovalCause::Cleared);
+        }
+        cleared
+    }
+
+    /// Get the items in the order the hand reaches them.
+    fn sweep_order(&self) -> impl Iterator<Item = &Slot<K, V>> {
+        self.slots[self.hand..]
+            .iter()
+            .chain(&self.slots[..self.hand])
+            .flatten()
+    }
+
+    /// Pack the items into the front of the buffer in the order the hand reaches them, dropping empty slots.
+    fn compact(&mut self) {
+        let hand = self.hand;
+        let mut slots: Vec<Option<Slot<K, V>>> = self.slots.drain(hand..).collect();
+        slots.append(&mut self.slots);
+        self.slots = slots.into_iter().filter(Option::is_some).collect();
+        self.free.clear();
+        self.hand = 0;
+        for (position, slot) in self.slots.iter().flatten().enumerate() {
+            self.index.insert(slot.key.clone(), position);
+        }
+    }
+
+    /// Get the unexpired items, with the items the policy would evict last first.
+    ///
+    /// Referenced items survive the next turn of the hand, so they come first, each group in the reverse of the order the hand reaches them.
+    fn hottest_items(&self) -> Vec<(K, Arc<V>)> {
+        let now = self.core.clock.now();
+        let (mut referenced, mut unreferenced): (Vec<_>, Vec<_>) = self
+            .sweep_order()
+            .filter(|slot| !self.core.expiries.is_expired(&slot.key, now))
+            .partition(|slot| slot.referenced.load(Ordering::Relaxed));
+        referenced.reverse();
+        unreferenced.reverse();
+        referenced
+            .into_iter()
+            .chain(unreferenced)
+            .map(|slot| (slot.key.clone(), slot.value.clone()))
+            .collect()
+    }
+}
+
+/// ClockCache is a cache which approximates least recently used eviction with a circular buffer and a reference bit per item, rather than a linked list.
+///
+/// A get only sets the reference bit of the item it finds, where an `LRUCache` has to move the item to the front of its list, so hits are cheaper, and as the bit is atomic gets only take a shared lock and never wait on each other. When the cache is full a hand sweeps around the buffer, giving every item whose bit is set a second chance by clearing it, and evicts the first item whose bit is already clear, an item which has not been read since the hand last passed it. New items take the slot of the item they replace, just behind the hand, so they get a full turn of the hand to be read before they can be evicted.
+///
+/// All mutability is handled internally with a RwLock, so the cache can be shared between threads. Values are returned as Arcs to allow for shared ownership.
+///
+/// Example:
+/// ```
+/// use arcache::{Cache, ClockCache};
+///
+/// let cache = ClockCache::<&str, u64>::new(2);
+///
+/// cache.set("a", 1);
+/// cache.set("b", 2);
+/// cache.get(&"a");
+///
+/// // "a" was read, so the hand passes it over and evicts "b"
+/// cache.set("c", 3);
+/// assert!(cache.contains_key(&"a"));
+/// assert!(!cache.contains_key(&"b"));
+/// ```
+pub struct ClockCache<K: Eq + Hash + Clone + Send, V: Send + Sync> {
+    inner: Arc<RwLock<ClockCacheInner<K, V>>>,
+    access_listeners: Arc<AccessListeners<K>>,
+    latency: Arc<LatencyTracker>,
+}
+
+impl<K: Eq + Hash + Clone + Sync + Send, V: Send + Sync> ClockCache<K, V> {
+    /// Create a new ClockCache with the given capacity.
+    pub fn new(capacity: u64) -> Self {
+        ClockCache {
+            inner: Arc::new(RwLock::new(ClockCacheInner::new(capacity))),
+            access_listeners: Arc::new(AccessListeners::new()),
+            latency: Arc::new(LatencyTracker::new()),
+        }
+    }
+
+    policy_methods!(read, write);
+
+    /// Record when every get hits an item, so `idle_time`, `purge_idle` and the idle ages of evicted items count reads as well as sets.
+    ///
+    /// By default a get only sets the item's reference bit, and an item counts as idle from when it was last set. With idle tracking every get takes the exclusive lock to record the time.
+    pub fn with_idle_tracking(self) -> Self {
+        self.inner.write().unwrap().idle_tracking = true;
+        self
+    }
+
+    /// Get the number of items whose reference bit is set, which the hand would pass over on its next turn.
+    pub fn referenced_len(&self) -> usize {
+        let inner = self.inner.read().unwrap();
+        inner
+            .slots
+            .iter()
+            .flatten()
+            .filter(|slot| slot.referenced.load(Ordering::Relaxed))
+            .count()
+    }
+
+    /// Set a value in the cache with a priority, when the cache is full lower priority items are evicted before higher priority ones.
+    ///
+    /// Within a priority the item the policy would evict first goes first. New items set with `set` have the lowest priority, `DEFAULT_PRIORITY`, and setting a key again without a priority keeps the one it has.
+    pub fn set_with_priority(&self, key: K, value: V, priority: u32) -> Option<Arc<V>> {
+        self.insert(
+            key,
+            Arc::new(value),
+            Some(priority),
+            None,
+            None,
+            None,
+            EntrySource::Inserted,
+        )
+        .unwrap_or(None)
+    }
+
+    /// Set a value in the cache which expires after the given time-to-live, overriding the cache's default TTL.
+    ///
+    /// Expired items are never returned and are removed before any live item is evicted.
+    pub fn set_with_ttl(&self, key: K, value: V, ttl: Duration) -> Option<Arc<V>> {
+        self.insert(
+            key,
+            Arc::new(value),
+            None,
+            Some(ttl),
+            None,
+            None,
+            EntrySource::Inserted,
+        )
+        .unwrap_or(None)
+    }
+
+    /// Set a value in the cache with a metadata value attached, which can be read back with `entry_info`.
+    ///
+    /// Metadata is dropped when the item leaves the cache or is set again without metadata.
+    pub fn set_with_metadata(
+        &self,
+        key: K,
+        value: V,
+        metadata: impl Any + Send + Sync,
+    ) -> Option<Arc<V>> {
+        self.insert(
+            key,
+            Arc::new(value),
+            None,
+            None,
+            Some(Arc::new(metadata)),
+            None,
+            EntrySource::Inserted,
+        )
+        .unwrap_or(None)
+    }
+
+    /// Insert a value with a priority and an optional TTL, evicting an item if the cache is full.
+    #[allow(clippy::too_many_arguments)]
+    fn insert(
+        &self,
+        key: K,
+        value: Arc<V>,
+        priority: Option<u32>,
+        ttl: Option<Duration>,
+        metadata: Option<EntryMetadata>,
+        expected_version: Option<u64>,
+        source: EntrySource,
+    ) -> Result<Option<Arc<V>>, CacheError> {
+        let mut inner = self.inner.write().map_err(|_| CacheError::Poisoned)?;
+        if inner.capacity == 0 {
+            return Err(CacheError::CapacityZero);
+        }
+        inner.purge_expired();
+        inner.core.metadata.check_version(&key, expected_version)?;
+        // overwriting a key never evicts another item
+        let is_new = !inner.index.contains_key(&key);
+        if is_new && !inner.core.full_behavior.evicts() && inner.len() as u64 >= inner.capacity {
+            inner.core.listeners.reject(&key);
+            return inner.core.full_behavior.rejection();
+        }
+        if is_new && inner.len() as u64 >= inner.capacity {
+            inner.evict();
+        }
+        inner.core.stats.record_set(is_new);
+        inner.core.track(&key, priority, ttl, metadata, source);
+        if let Some(&position) = inner.index.get(&key) {
+            // an overwritten item counts as referenced and keeps its slot
+            let slot = inner.slots[position]
+                .as_mut()
+                .expect("indexed slots are occupied");
+            slot.referenced.store(true, Ordering::Relaxed);
+            slot.read.store(false, Ordering::Relaxed);
+            return Ok(Some(std::mem::replace(&mut slot.value, value)));
+        }
+        inner.place(key, value);
+        Ok(None)
+    }
+}
+
+impl<K: Eq + Hash + Clone + Send, V: Send + Sync> Clone for ClockCache<K, V> {
+    /// Get another handle to the same cache, sharing its items, settings and statistics.
+    fn clone(&self) -> Self {
+        ClockCache {
+            inner: self.inner.clone(),
+            access_listeners: self.access_listeners.clone(),
+            latency: self.latency.clone(),
+        }
+    }
+}
+
+impl<K: Eq + Hash + Clone + Sync + Send, V: Send + Sync> Cache<K, V> for ClockCache<K, V> {
+    /// Get a value from the cache, setting its reference bit.
+    ///
+    /// Gets hold only the shared lock, unless the key has expired and must be removed or the cache was built `with_idle_tracking`.
+    fn get(&self, key: &K) -> Option<Arc<V>> {
+        let started = self.latency.start();
+        let shared = {
+            let inner = self.inner.read().unwrap();
+            if inner.idle_tracking || inner.core.is_expired(key) {
+                None
+            } else {
+                let result = inner.lookup(key);
+                match result {
+                    Some(_) => inner.core.stats.record_shared_hit(),
+                    None => inner.core.stats.record_shared_miss(),
+                }
+                Some(result)
+            }
+        };
+        let result = shared.unwrap_or_else(|| {
+            let mut inner = self.inner.write().unwrap();
+            inner.purge_expired();
+            let result = inner.lookup(key);
+            if result.is_some() {
+                inner.core.stats.record_hit();
+                if inner.idle_tracking {
+                    let now = inner.core.clock.now();
+                    inner.core.metadata.touch(key, now);
+                }
+            } else {
+                inner.core.stats.record_miss();
+            }
+            result
+        });
+        self.latency.record_get(started);
+        self.access_listeners.notify(key, &result);
+        result
+    }
+
+    /// Get a value from the cache, with whether it was a hit, or a miss because the key expired, was rejected or is absent.
+    fn get_with_outcome(&self, key: &K) -> (Option<Arc<V>>, GetOutcome) {
+        let result = self.get(key);
+        let outcome = match result {
+            Some(_) => GetOutcome::Hit,
+            None => self.inner.read().unwrap().core.listeners.miss_outcome(key),
+        };
+        (result, outcome)
+    }
+
+    /// Set a value in the cache.
+    fn set(&self, key: K, value: V) -> Option<Arc<V>> {
+        let started = self.latency.start();
+        let result = self
+            .insert(
+                key,
+                Arc::new(value),
+                None,
+                None,
+                None,
+                None,
+                EntrySource::Inserted,
+            )
+            .unwrap_or(None);
+        self.latency.record_set(started);
+        result
+    }
+
+    /// Set a value in the cache, recording how it came to be in the cache for `entry_info` and eviction listeners.
+    fn set_with_source(&self, key: K, value: V, source: EntrySource) -> Option<Arc<V>> {
+        let started = self.latency.start();
+        let result = self
+            .insert(key, Arc::new(value), None, None, None, None, source)
+            .unwrap_or(None);
+        self.latency.record_set(started);
+        result
+    }
+
+    /// Remove a value from the cache.
+    fn remove(&self, key: &K) -> Option<Arc<V>> {
+        let mut inner = self.inner.write().unwrap();
+        let result = inner.remove_entry(key);
+        if let Some(value) = &result {
+            inner
+                .core
+                .listeners
+                .notify(key, value, RemovalCause::Removed);
+        }
+        result
+    }
+
+    /// Check if a key is in the cache and has not expired, without counting a hit or miss or updating its position.
+    fn contains_key(&self, key: &K) -> bool {
+        let inner = self.inner.read().unwrap();
+        inner.index.contains_key(key)
+            && !inner.core.expiries.is_expired(key, inner.core.clock.now())
+    }
+
+    /// Get the time left before an item expires, or None if it never expires or is not in the cache.
+    fn ttl_remaining(&self, key: &K) -> Option<Duration> {
+        self.entry_info(key)?.ttl_remaining
+    }
+
+    /// Clear the cache.
+    fn clear(&self) {
+        let mut inner = self.inner.write().unwrap();
+        inner.clear_entries();
+    }
+
+    /// Clear the cache, returning every item which was removed.
+    fn clear_returning(&self) -> Vec<(K, Arc<V>)> {
+        let mut inner = self.inner.write().unwrap();
+        inner.clear_entries()
+    }
+
+    /// Copy the unexpired items into a HashMap in one locked pass.
+    fn to_map(&self) -> HashMap<K, Arc<V>> {
+        let inner = self.inner.read().unwrap();
+        let now = inner.core.clock.now();
+        inner
+            .slots
+            .iter()
+            .flatten()
+            .filter(|slot| !inner.core.expiries.is_expired(&slot.key, now))
+            .map(|slot| (slot.key.clone(), slot.value.clone()))
+            .collect()
+    }
+
+    /// Get up to `limit` unexpired items, ordered with the items the policy would evict last first. Higher priority items come before lower ones, and otherwise the items whose reference bit is set, then the rest, each in the reverse of the order the hand reaches them.
+    fn hottest(&self, limit: usize) -> Vec<(K, Arc<V>)> {
+        let inner = self.inner.read().unwrap();
+        let mut items = inner.hottest_items();
+        inner.core.priorities.sort_descending(&mut items);
+        items.truncate(limit);
+        items
+    }
+
+    /// Get the name of the eviction policy.
+    fn policy_name(&self) -> &'static str {
+        "clock"
+    }
+
+    /// Get the cache as `Any`, so it can be downcast from a `dyn Cache`.
+    fn as_any(&self) -> Option<&dyn Any>
+    where
+        Self: 'static,
+    {
+        Some(self)
+    }
+
+    /// Get cache statistics.
+    fn stats(&self) -> CacheStats {
+        let mut inner = self.inner.write().unwrap();
+        inner.core.stats.fold_shared();
+        inner
+            .core
+            .stats
+            .snapshot(inner.len() as u64, inner.capacity)
+    }
+
+    /// Get the cache statistics together with how soon the items expire.
+    fn detailed_stats(&self) -> DetailedStats {
+        let mut inner = self.inner.write().unwrap();
+        inner.core.stats.fold_shared();
+        let size = inner.len() as u64;
+        let mut detailed = DetailedStats::new(inner.core.stats.snapshot(size, inner.capacity));
+        detailed.eviction_ages = Some(inner.core.stats.eviction_ages());
+        detailed.expiry_distribution = Some(
+            inner
+                .core
+                .expiries
+                .distribution(inner.core.clock.now(), size),
+        );
+        detailed
+    }
+
+    /// Change the capacity of the cache, if the new capacity is smaller than the current size, items are evicted per the policy.
+    fn change_capacity(&self, capacity: u64) {
+        self.change_capacity_collect(capacity);
+    }
+
+    /// Change the capacity of the cache, returning the items which were removed to fit the new capacity.
+    fn change_capacity_collect(&self, capacity: u64) -> Vec<(K, Arc<V>)> {
+        let mut inner = self.inner.write().unwrap();
+        let old_capacity = inner.capacity;
+        inner.capacity = capacity;
+        let evicted = inner.evict_to(capacity as usize);
+        if inner.capacity < old_capacity {
+            // give back the slots of the evicted items
+            inner.compact();
+        } else {
+            let additional = (inner.capacity - old_capacity) as usize;
+            inner.slots.reserve(additional);
+            inner.index.reserve(additional);
+        }
+        evicted
+    }
+
+    /// Evict `fraction` of the items per the policy in one locked pass, leaving the capacity unchanged.
+    fn shed(&self, fraction: f64) -> usize {
+        let mut inner = self.inner.write().unwrap();
+        let len = inner.len();
+        inner.evict_to(len - shed_count(len, fraction)).len()
+    }
+}
+
+impl<K: Eq + Hash + Clone + Sync + Send, V: Send + Sync> TryCache<K, V> for ClockCache<K, V> {
+    /// Get a value from the cache, returning `CacheError::Poisoned` instead of panicking if the cache lock is poisoned.
+    fn try_get(&self, key: &K) -> Result<Option<Arc<V>>, CacheError> {
+        if self.inner.is_poisoned() {
+            return Err(CacheError::Poisoned);
+        }
+        Ok(self.get(key))
+    }
+
+    /// Set a value in the cache, returning an error instead of inserting if the cache is full and was built with `FullBehavior::ReturnError`, has a capacity of zero, or its lock is poisoned.
+    fn try_set(&self, key: K, value: V) -> Result<Option<Arc<V>>, CacheError> {
+        let started = self.latency.start();
+        let result = self.insert(
+            key,
+            Arc::new(value),
+            None,
+            None,
+            None,
+            None,
+            EntrySource::Inserted,
+        );
+        self.latency.record_set(started);
+        result
+    }
+}
+
+impl<K: Eq + Hash + Clone + Sync + Send, V: Send + Sync> VersionedCache<K, V> for ClockCache<K, V> {
+    /// Get the current version of an item, without counting a hit or miss or updating its position.
+    fn version(&self, key: &K) -> Option<u64> {
+        let inner = self.inner.read().unwrap();
+        if inner.core.expiries.is_expired(key, inner.core.clock.now()) {
+            return None;
+        }
+        inner.core.metadata.version(key)
+    }
+
+    /// Set a value only if the item is at `expected_version`, or is not in the cache if it is 0.
+    fn set_versioned(
+        &self,
+        key: K,
+        value: V,
+        expected_version: u64,
+    ) -> Result<Option<Arc<V>>, CacheError> {
+        let started = self.latency.start();
+        let result = self.insert(
+            key,
+            Arc::new(value),
+            None,
+            None,
+            None,
+            Some(expected_version),
+            EntrySource::Inserted,
+        );
+        self.latency.record_set(started);
+        result
+    }
+}
+
+impl<K: Eq + Hash + Clone + Sync + Send, V: Send + Sync> IntoIterator for ClockCache<K, V> {
+    type Item = (K, Arc<V>);
+    type IntoIter = std::vec::IntoIter<(K, Arc<V>)>;
+
+    /// Consume the handle, yielding the cache's unexpired items from the hottest to the coldest. Other handles still hold the items.
+    fn into_iter(self) -> Self::IntoIter {
+        let items = self.inner.read().unwrap().hottest_items();
+        items.into_iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+    use std::thread;
+    use std::time::Instant;
+
+    struct ManualClock(Mutex<Instant>);
+
+    impl Clock for Arc<ManualClock> {
+        fn now(&self) -> Instant {
+            *self.0.lock().unwrap()
+        }
+    }
+
+    #[test]
+    fn test_clock_cache_second_chance() {
+        let cache = ClockCache::new(3);
+        for key in 1..=3 {
+            cache.set(key, key);
+        }
+        cache.get(&1);
+        cache.get(&3);
+        // the hand clears the bit of 1, evicts 2, and the new item takes its slot
+        cache.set(4, 4);
+        assert!(!cache.contains_key(&2));
+        // the hand clears the bit of 3, then evicts 1 whose bit it cleared on the last turn
+        cache.set(5, 5);
+        assert!(!cache.contains_key(&1));
+        assert!(cache.contains_key(&3));
+        assert!(cache.contains_key(&4));
+        assert_eq!(cache.stats().evictions, 2);
+    }
+
+    #[test]
+    fn test_clock_cache_reuses_free_slots() {
+        let cache = ClockCache::new(4);
+        for key in 1..=4 {
+            cache.set(key, key);
+        }
+        cache.remove(&2);
+        cache.set(5, 5);
+        assert_eq!(cache.stats().evictions, 0);
+        assert_eq!(cache.inner.read().unwrap().slots.len(), 4);
+        cache.change_capacity(2);
+        assert_eq!(cache.stats().size, 2);
+        assert_eq!(cache.inner.read().unwrap().slots.len(), 2);
+        cache.set(6, 6);
+        assert_eq!(cache.stats().size, 2);
+        assert_eq!(cache.get(&6).map(|v| *v), Some(6));
+    }
+
+    #[test]
+    fn test_clock_cache_priorities() {
+        let cache = ClockCache::new(2);
+        cache.set_with_priority(1, 1, 5);
+        cache.set(2, 2);
+        cache.get(&2);
+        // 2 is the only item with the lowest priority, so it is evicted even though it was read
+        cache.set(3, 3);
+        assert!(cache.contains_key(&1));
+        assert!(!cache.contains_key(&2));
+        let hottest: Vec<u64> = cache.hottest(2).into_iter().map(|(key, _)| key).collect();
+        assert_eq!(hottest, vec![1, 3]);
+    }
+
+    #[test]
+    fn test_clock_cache_gets_share_the_lock() {
+        let cache = ClockCache::new(2);
+        cache.set(1, 1);
+        cache.set(2, 2);
+        // a get only needs the shared lock, so it is served while another reader holds it
+        let reader = cache.inner.read().unwrap();
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let handle = cache.clone();
+        thread::spawn(move || sender.send(handle.get(&1).map(|v| *v)).unwrap());
+        assert_eq!(
+            receiver.recv_timeout(Duration::from_secs(5)).unwrap(),
+            Some(1)
+        );
+        drop(reader);
+
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let cache = cache.clone();
+                thread::spawn(move || {
+                    for _ in 0..1000 {
+                        cache.get(&1);
+                        cache.get(&3);
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses), (4001, 4000));
+        // the bit set under the shared lock still gives 1 its second chance
+        cache.set(3, 3);
+        assert!(cache.contains_key(&1));
+        assert!(!cache.contains_key(&2));
+    }
+
+    #[test]
+    fn test_clock_cache_idle_tracking() {
+        let clock = Arc::new(ManualClock(Mutex::new(Instant::now())));
+        let advance = |by: Duration| *clock.0.lock().unwrap() += by;
+        let untracked = ClockCache::new(2).with_clock(clock.clone());
+        let tracked = ClockCache::new(2)
+            .with_clock(clock.clone())
+            .with_idle_tracking();
+        untracked.set(1, 1);
+        tracked.set(1, 1);
+        advance(Duration::from_secs(10));
+        untracked.get(&1);
+        tracked.get(&1);
+        // by default only sets reset the idle time
+        assert_eq!(untracked.idle_time(&1), Some(Duration::from_secs(10)));
+        assert_eq!(tracked.idle_time(&1), Some(Duration::ZERO));
+        assert_eq!(untracked.purge_idle(Duration::from_secs(5)), 1);
+        assert_eq!(tracked.purge_idle(Duration::from_secs(5)), 0);
+    }
+}
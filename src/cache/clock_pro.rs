@@ -0,0 +1,913 @@
+use crate::sync::Mutex;
+use std::any::Any;
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::cache::latency::{LatencyTracker, OperationLatencies};
+use crate::cache::listener::{
+    AccessListener, AccessListeners, CallbackPanics, EntrySource, EvictionListener, RemovalCause,
+};
+use crate::cache::metadata::{EntryInfo, EntryMetadata};
+use crate::cache::policy_core::{policy_methods, PolicyCore};
+use crate::cache::{
+    shed_count, Cache, CacheStats, DetailedStats, FullBehavior, GetOutcome, TryCache,
+    VersionedCache,
+};
+use crate::clock::Clock;
+use crate::error::CacheError;
+
+/// ClockProStats describes how a ClockProCache has divided its items between hot and cold.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ClockProStats {
+    /// The number of hot items, which have been read again within their test period.
+    pub hot: u64,
+    /// The number of cold items, which are evicted first.
+    pub cold: u64,
+    /// The number of keys of evicted cold items still in their test period, kept without their values.
+    pub test: u64,
+    /// The number of cold items the cache is currently aiming for, which adapts to the workload.
+    pub cold_target: u64,
+    /// The number of cold items which became hot, because they were read again before the cold hand reached them or set again during their test period.
+    pub promotions: u64,
+    /// The number of hot items which became cold, because they were not read between two turns of the hot hand.
+    pub demotions: u64,
+}
+
+impl ClockProStats {
+    /// Get the share of the items which are hot, or 0 if the cache is empty.
+    pub fn hot_ratio(&self) -> f64 {
+        let resident = self.hot + self.cold;
+        if resident == 0 {
+            0.0
+        } else {
+            self.hot as f64 / resident as f64
+        }
+    }
+}
+
+/// What an entry on the clock is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PageKind {
+    Hot,
+    Cold,
+    // an evicted cold item whose key is kept for its test period, without its value
+    Test,
+}
+
+/// An entry on the clock, linked to its neighbours.
+struct Page<K, V> {
+    key: K,
+    value: Option<Arc<V>>,
+    kind: PageKind,
+    referenced: bool,
+    prev: usize,
+    next: usize,
+}
+
+/// ClockProCacheInner contains the inner data structure for the ClockProCache.
+struct ClockProCacheInner<K: Eq + Hash + Clone + Send, V: Send + Sync> {
+    capacity: u64,
+    // a circular doubly linked list of pages, empty slots are reused before the list grows
+    pages: Vec<Option<Page<K, V>>>,
+    free: Vec<usize>,
+    index: HashMap<K, usize>,
+    hand_hot: Option<usize>,
+    hand_cold: Option<usize>,
+    hand_test: Option<usize>,
+    hot: usize,
+    cold: usize,
+    test: usize,
+    cold_target: usize,
+    promotions: u64,
+    demotions: u64,
+    core: PolicyCore<K, V>,
+}
+
+impl<K: Eq + Hash + Clone + Send, V: Send + Sync> ClockProCacheInner<K, V> {
+    /// Create a new ClockProCacheInner with the given capacity.
+    fn new(capacity: u64) -> Self {
+        ClockProCacheInner {
+            capacity,
+            pages: Vec::new(),
+            free: Vec::new(),
+            index: HashMap::new(),
+            hand_hot: None,
+            hand_cold: None,
+            hand_test: None,
+            hot: 0,
+            cold: 0,
+            test: 0,
+            cold_target: 1,
+            promotions: 0,
+            demotions: 0,
+            core: PolicyCore::new(),
+        }
+    }
+
+    /// Get the number of items held, test pages hold no items.
+    fn len(&self) -> usize {
+        self.hot + self.cold
+    }
+
+    fn page(&self, position: usize) -> &Page<K, V> {
+        self.pages[position]
+            .as_ref()
+            .expect("linked pages are occupied")
+    }
+
+    fn page_mut(&mut self, position: usize) -> &mut Page<K, V> {
+        self.pages[position]
+            .as_mut()
+            .expect("linked pages are occupied")
+    }
+
+    /// Check if a key is held with its value, rather than only as a test page.
+    fn holds(&self, key: &K) -> bool {
+        self.index
+            .get(key)
+            .is_some_and(|&position| self.page(position).value.is_some())
+    }
+
+    /// Get the number of hot items the cache may hold before the hot hand turns some cold.
+    fn hot_limit(&self) -> usize {
+        (self.capacity as usize).saturating_sub(self.cold_target)
+    }
+
+    fn count(&mut self, kind: PageKind) -> &mut usize {
+        match kind {
+            PageKind::Hot => &mut self.hot,
+            PageKind::Cold => &mut self.cold,
+            PageKind::Test => &mut self.test,
+        }
+    }
+
+    /// Link a new page in just behind the hot hand, the head of the clock.
+    fn link(&mut self, key: K, value: Option<Arc<V>>, kind: PageKind) {
+        let mut page = Page {
+            key: key.clone(),
+            value,
+            kind,
+            referenced: false,
+            prev: 0,
+            next: 0,
+        };
+        let position = self.free.pop().unwrap_or(self.pages.len());
+        match self.hand_hot {
+            Some(head) => {
+                let prev = self.page(head).prev;
+                page.prev = prev;
+                page.next = head;
+                self.page_mut(prev).next = position;
+                self.page_mut(head).prev = position;
+            }
+            None => {
+                page.prev = position;
+                page.next = position;
+                self.hand_hot = Some(position);
+                self.hand_cold = Some(position);
+                self.hand_test = Some(position);
+            }
+        }
+        if position == self.pages.len() {
+            self.pages.push(Some(page));
+        } else {
+            self.pages[position] = Some(page);
+        }
+        *self.count(kind) += 1;
+        self.index.insert(key, position);
+    }
+
+    /// Unlink a page from the clock, moving any hand on it back to its predecessor, and return it.
+    fn unlink(&mut self, position: usize) -> Page<K, V> {
+        let page = self.pages[position]
+            .take()
+            .expect("linked pages are occupied");
+        self.free.push(position);
+        self.index.remove(&page.key);
+        *self.count(page.kind) -= 1;
+        if page.next == position {
+            self.hand_hot = None;
+            self.hand_cold = None;
+            self.hand_test = None;
+        } else {
+            for hand in [&mut self.hand_hot, &mut self.hand_cold, &mut self.hand_test] {
+                if *hand == Some(position) {
+                    *hand = Some(page.prev);
+                }
+            }
+            self.page_mut(page.prev).next = page.next;
+            self.page_mut(page.next).prev = page.prev;
+        }
+        page
+    }
+
+    /// Remove an item or test page from every structure, returning the item's value.
+    fn remove_entry(&mut self, key: &K) -> Option<Arc<V>> {
+        let position = *self.index.get(key)?;
+        let page = self.unlink(position);
+        self.core.forget(key);
+        page.value
+    }
+
+    /// Run the cold hand until the cache holds at most `limit` items, returning the evicted items.
+    ///
+    /// When no cold item is left the hot hand runs instead, demoting hot items for the cold hand to evict, as the hot limit may still leave room for every item.
+    fn shrink_to(&mut self, limit: usize) -> Vec<(K, Arc<V>)> {
+        let mut evicted = Vec::new();
+        while self.len() > limit {
+            if self.cold == 0 {
+                self.run_hand_hot();
+            } else {
+                self.run_hand_cold(&mut evicted);
+            }
+        }
+        evicted
+    }
+
+    /// Move the cold hand one page. A cold item which was read since the hand last passed becomes hot, otherwise it is evicted and its key kept as a test page.
+    ///
+    /// If priorities have been assigned, cold items with more than the lowest priority held are treated as though they were read.
+    fn run_hand_cold(&mut self, evicted: &mut Vec<(K, Arc<V>)>) {
+        let Some(position) = self.hand_cold else {
+            return;
+        };
+        let lowest = self.core.priorities.lowest(self.len());
+        let page = self.page(position);
+        if page.kind == PageKind::Cold {
+            let spared = page.referenced
+                || lowest.is_some_and(|lowest| self.core.priorities.get(&page.key) != lowest);
+            if spared {
+                let page = self.page_mut(position);
+                page.kind = PageKind::Hot;
+                page.referenced = false;
+                self.cold -= 1;
+                self.hot += 1;
+                self.promotions += 1;
+            } else {
+                self.evict_page(position, evicted);
+                while self.test > self.capacity as usize {
+                    self.run_hand_test();
+                }
+            }
+        }
+        if let Some(position) = self.hand_cold {
+            self.hand_cold = Some(self.page(position).next);
+        }
+        while self.hot > self.hot_limit() {
+            self.run_hand_hot();
+        }
+    }
+
+    /// Evict the value of a cold item, keeping its key as a test page.
+    fn evict_page(&mut self, position: usize, evicted: &mut Vec<(K, Arc<V>)>) {
+        let page = self.page_mut(position);
+        page.kind = PageKind::Test;
+        let key = page.key.clone();
+        let value = page.value.take().expect("cold pages hold a value");
+        self.cold -= 1;
+        self.test += 1;
+        let lifetime = self.core.metadata.lifetime(&key, self.core.clock.now());
+        self.core.forget(&key);
+        self.core
+            .listeners
+            .notify(&key, &value, RemovalCause::Evicted);
+        self.core.stats.record_eviction(lifetime);
+        evicted.push((key, value));
+    }
+
+    /// Move the hot hand one page. A hot item which was not read since the hand last passed becomes cold, and a test page the hand passes ends its test period.
+    fn run_hand_hot(&mut self) {
+        let Some(position) = self.hand_hot else {
+            return;
+        };
+        let page = self.page_mut(position);
+        match page.kind {
+            PageKind::Hot if page.referenced => page.referenced = false,
+            PageKind::Hot => {
+                page.kind = PageKind::Cold;
+                self.hot -= 1;
+                self.cold += 1;
+                self.demotions += 1;
+            }
+            PageKind::Cold => {}
+            PageKind::Test => self.end_test(position),
+        }
+        if let Some(position) = self.hand_hot {
+            self.hand_hot = Some(self.page(position).next);
+        }
+    }
+
+    /// Move the test hand one page, ending the test period of a test page.
+    fn run_hand_test(&mut self) {
+        let Some(position) = self.hand_test else {
+            return;
+        };
+        if self.page(position).kind == PageKind::Test {
+            self.end_test(position);
+        }
+        if let Some(position) = self.hand_test {
+            self.hand_test = Some(self.page(position).next);
+        }
+    }
+
+    /// Forget a test page whose key was not reused in time, aiming for fewer cold items. Unlinking it moves any hand on it back to the previous page.
+    fn end_test(&mut self, position: usize) {
+        self.unlink(position);
+        if self.cold_target > 1 {
+            self.cold_target -= 1;
+        }
+    }
+
+    /// Remove every item which has passed its deadline.
+    fn purge_expired(&mut self) {
+        let now = self.core.clock.now();
+        while let Some(key) = self.core.expiries.pop_expired(now) {
+            if let Some(value) = self.remove_entry(&key) {
+                self.core
+                    .listeners
+                    .notify(&key, &value, RemovalCause::Expired);
+                self.core.stats.record_expiration();
+            }
+        }
+    }
+
+    /// Remove every item and test page, notifying listeners and returning the removed items.
+    fn clear_entries(&mut self) -> Vec<(K, Arc<V>)> {
+        self.index.clear();
+        self.free.clear();
+        self.hand_hot = None;
+        self.hand_cold = None;
+        self.hand_test = None;
+        self.hot = 0;
+        self.cold = 0;
+        self.test = 0;
+        self.core.clear();
+        let cleared: Vec<(K, Arc<V>)> = self
+            .pages
+            .drain(..)
+            .flatten()
+            .filter_map(|page| Some((page.key, page.value?)))
+            .collect();
+        for (key, value) in &cleared {
+            self.core
+                .listeners
+                .notify(key, value, RemovalCause::Cleared);
+        }
+        cleared
+    }
+
+    /// Get the pages in the order the cold hand reaches them.
+    fn sweep_order(&self) -> Vec<&Page<K, V>> {
+        let mut pages = Vec::with_capacity(self.index.len());
+        let Some(start) = self.hand_cold else {
+            return pages;
+        };
+        let mut position = start;
+        loop {
+            let page = self.page(position);
+            pages.push(page);
+            position = page.next;
+            if position == start {
+                return pages;
+            }
+        }
+    }
+
+    /// Get the unexpired items, with the items the policy would evict last first.
+    ///
+    /// Hot items come before cold ones and read items before unread ones, each group in the reverse of the order the cold hand reaches them.
+    fn hottest_items(&self) -> Vec<(K, Arc<V>)> {
+        let now = self.core.clock.now();
+        let mut pages: Vec<&Page<K, V>> = self
+            .sweep_order()
+            .into_iter()
+            .filter(|page| page.value.is_some() && !self.core.expiries.is_expired(&page.key, now))
+            .collect();
+        pages.reverse();
+        pages.sort_by_key(|page| Reverse((page.kind == PageKind::Hot, page.referenced)));
+        pages
+            .into_iter()
+            .filter_map(|page| Some((page.key.clone(), page.value.clone()?)))
+            .collect()
+    }
+}
+
+/// ClockProCache is a cache which approximates LIRS with three clock hands, telling hot items, which are read again soon after they are first used, from cold ones, so it keeps a higher hit rate than `ClockCache` when a small working set is mixed with one-off or looping accesses.
+///
+/// New items start cold. A cold item read before the cold hand reaches it becomes hot, otherwise the cold hand evicts it but keeps its key, without the value, as a test page. Setting a key again during its test period shows it is reused on a longer cycle than the cold items get to live, so it comes back hot and the cache aims to keep more cold items. The hot hand turns hot items which were not read since it last passed cold, keeping the hot items within the capacity left by the cold target. The test hand ends test periods, and every test period which ends without the key being reused aims for fewer cold items. At most the capacity in test pages are kept. `clock_pro_stats` reports how the items are divided.
+///
+/// All mutability is handled internally with a Mutex, so the cache can be shared between threads. Values are returned as Arcs to allow for shared ownership.
+///
+/// Example:
+/// ```
+/// use arcache::{Cache, ClockProCache};
+///
+/// let cache = ClockProCache::<u64, u64>::new(4);
+///
+/// cache.set(1, 1);
+/// cache.get(&1);
+/// // a scan of new keys only cycles through the cold items
+/// for key in 100..200 {
+///     cache.set(key, key);
+/// }
+/// assert!(cache.contains_key(&1));
+/// assert_eq!(cache.clock_pro_stats().hot, 1);
+/// ```
+pub struct ClockProCache<K: Eq + Hash + Clone + Send, V: Send + Sync> {
+    inner: Arc<Mutex<ClockProCacheInner<K, V>>>,
+    access_listeners: Arc<AccessListeners<K>>,
+    latency: Arc<LatencyTracker>,
+}
+
+impl<K: Eq + Hash + Clone + Sync + Send, V: Send + Sync> ClockProCache<K, V> {
+    /// Create a new ClockProCache with the given capacity.
+    pub fn new(capacity: u64) -> Self {
+        ClockProCache {
+            inner: Arc::new(Mutex::new(ClockProCacheInner::new(capacity))),
+            access_listeners: Arc::new(AccessListeners::new()),
+            latency: Arc::new(LatencyTracker::new()),
+        }
+    }
+
+    policy_methods!(lock, lock);
+
+    /// Set a value in the cache with a priority, when the cache is full lower priority items are evicted before higher priority ones.
+    ///
+    /// Within a priority the item the policy would evict first goes first. New items set with `set` have the lowest priority, `DEFAULT_PRIORITY`, and setting a key again without a priority keeps the one it has.
+    pub fn set_with_priority(&self, key: K, value: V, priority: u32) -> Option<Arc<V>> {
+        self.insert(
+            key,
+            Arc::new(value),
+            Some(priority),
+            None,
+            None,
+            None,
+            EntrySource::Inserted,
+        )
+        .unwrap_or(None)
+    }
+
+    /// Set a value in the cache which expires after the given time-to-live, overriding the cache's default TTL.
+    ///
+    /// Expired items are never returned and are removed before any live item is evicted.
+    pub fn set_with_ttl(&self, key: K, value: V, ttl: Duration) -> Option<Arc<V>> {
+        self.insert(
+            key,
+            Arc::new(value),
+            None,
+            Some(ttl),
+            None,
+            None,
+            EntrySource::Inserted,
+        )
+        .unwrap_or(None)
+    }
+
+    /// Set a value in the cache with a metadata value attached, which can be read back with `entry_info`.
+    ///
+    /// Metadata is dropped when the item leaves the cache or is set again without metadata.
+    pub fn set_with_metadata(
+        &self,
+        key: K,
+        value: V,
+        metadata: impl Any + Send + Sync,
+    ) -> Option<Arc<V>> {
+        self.insert(
+            key,
+            Arc::new(value),
+            None,
+            None,
+            Some(Arc::new(metadata)),
+            None,
+            EntrySource::Inserted,
+        )
+        .unwrap_or(None)
+    }
+
+    /// Get how the items are divided between hot and cold, how many test pages are kept, and how often items changed between hot and cold.
+    pub fn clock_pro_stats(&self) -> ClockProStats {
+        let inner = self.inner.lock().unwrap();
+        ClockProStats {
+            hot: inner.hot as u64,
+            cold: inner.cold as u64,
+            test: inner.test as u64,
+            cold_target: inner.cold_target as u64,
+            promotions: inner.promotions,
+            demotions: inner.demotions,
+        }
+    }
+
+    /// Insert a value with a priority and an optional TTL, evicting an item if the cache is full.
+    #[allow(clippy::too_many_arguments)]
+    fn insert(
+        &self,
+        key: K,
+        value: Arc<V>,
+        priority: Option<u32>,
+        ttl: Option<Duration>,
+        metadata: Option<EntryMetadata>,
+        expected_version: Option<u64>,
+        source: EntrySource,
+    ) -> Result<Option<Arc<V>>, CacheError> {
+        let mut inner = self.inner.lock().map_err(|_| CacheError::Poisoned)?;
+        if inner.capacity == 0 {
+            return Err(CacheError::CapacityZero);
+        }
+        inner.purge_expired();
+        inner.core.metadata.check_version(&key, expected_version)?;
+        // overwriting a key never evicts another item
+        let position = inner.index.get(&key).copied();
+        let is_new = !inner.holds(&key);
+        if is_new && !inner.core.full_behavior.evicts() && inner.len() as u64 >= inner.capacity {
+            inner.core.listeners.reject(&key);
+            return inner.core.full_behavior.rejection();
+        }
+        // a key set again during its test period is reused on a longer cycle than cold items live, so it comes back hot and more cold items are kept
+        let reused = is_new && position.is_some();
+        if let Some(position) = position.filter(|_| reused) {
+            inner.unlink(position);
+            inner.cold_target = (inner.cold_target + 1).min(inner.capacity as usize);
+            inner.promotions += 1;
+        }
+        if is_new && inner.len() as u64 >= inner.capacity {
+            let limit = inner.capacity as usize - 1;
+            inner.shrink_to(limit);
+        }
+        inner.core.stats.record_set(is_new);
+        inner.core.track(&key, priority, ttl, metadata, source);
+        if let Some(position) = position.filter(|_| !is_new) {
+            // an overwritten item counts as read and keeps its place
+            let page = inner.page_mut(position);
+            page.referenced = true;
+            return Ok(page.value.replace(value));
+        }
+        let kind = if reused {
+            PageKind::Hot
+        } else {
+            PageKind::Cold
+        };
+        inner.link(key, Some(value), kind);
+        Ok(None)
+    }
+}
+
+impl<K: Eq + Hash + Clone + Send, V: Send + Sync> Clone for ClockProCache<K, V> {
+    /// Get another handle to the same cache, sharing its items, settings and statistics.
+    fn clone(&self) -> Self {
+        ClockProCache {
+            inner: self.inner.clone(),
+            access_listeners: self.access_listeners.clone(),
+            latency: self.latency.clone(),
+        }
+    }
+}
+
+impl<K: Eq + Hash + Clone + Sync + Send, V: Send + Sync> Cache<K, V> for ClockProCache<K, V> {
+    /// Get a value from the cache, marking it as read. A key kept only as a test page is a miss.
+    fn get(&self, key: &K) -> Option<Arc<V>> {
+        let started = self.latency.start();
+        let result = {
+            let mut inner = self.inner.lock().unwrap();
+            inner.purge_expired();
+            let result = match inner.index.get(key) {
+                Some(&position) => {
+                    let page = inner.page_mut(position);
+                    if page.value.is_some() {
+                        page.referenced = true;
+                    }
+                    page.value.clone()
+                }
+                None => None,
+            };
+            if result.is_some() {
+                inner.core.stats.record_hit();
+                let now = inner.core.clock.now();
+                inner.core.metadata.touch(key, now);
+            } else {
+                inner.core.stats.record_miss();
+            }
+            result
+        };
+        self.latency.record_get(started);
+        self.access_listeners.notify(key, &result);
+        result
+    }
+
+    /// Get a value from the cache, with whether it was a hit, or a miss because the key expired, was rejected or is absent.
+    fn get_with_outcome(&self, key: &K) -> (Option<Arc<V>>, GetOutcome) {
+        let result = self.get(key);
+        let outcome = match result {
+            Some(_) => GetOutcome::Hit,
+            None => self.inner.lock().unwrap().core.listeners.miss_outcome(key),
+        };
+        (result, outcome)
+    }
+
+    /// Set a value in the cache.
+    fn set(&self, key: K, value: V) -> Option<Arc<V>> {
+        let started = self.latency.start();
+        let result = self
+            .insert(
+                key,
+                Arc::new(value),
+                None,
+                None,
+                None,
+                None,
+                EntrySource::Inserted,
+            )
+            .unwrap_or(None);
+        self.latency.record_set(started);
+        result
+    }
+
+    /// Set a value in the cache, recording how it came to be in the cache for `entry_info` and eviction listeners.
+    fn set_with_source(&self, key: K, value: V, source: EntrySource) -> Option<Arc<V>> {
+        let started = self.latency.start();
+        let result = self
+            .insert(key, Arc::new(value), None, None, None, None, source)
+            .unwrap_or(None);
+        self.latency.record_set(started);
+        result
+    }
+
+    /// Remove a value from the cache.
+    fn remove(&self, key: &K) -> Option<Arc<V>> {
+        let mut inner = self.inner.lock().unwrap();
+        let result = inner.remove_entry(key);
+        if let Some(value) = &result {
+            inner
+                .core
+                .listeners
+                .notify(key, value, RemovalCause::Removed);
+        }
+        result
+    }
+
+    /// Check if a key is in the cache and has not expired, without counting a hit or miss or updating its position.
+    fn contains_key(&self, key: &K) -> bool {
+        let inner = self.inner.lock().unwrap();
+        inner.holds(key) && !inner.core.expiries.is_expired(key, inner.core.clock.now())
+    }
+
+    /// Get the time left before an item expires, or None if it never expires or is not in the cache.
+    fn ttl_remaining(&self, key: &K) -> Option<Duration> {
+        self.entry_info(key)?.ttl_remaining
+    }
+
+    /// Clear the cache.
+    fn clear(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.clear_entries();
+    }
+
+    /// Clear the cache, returning every item which was removed.
+    fn clear_returning(&self) -> Vec<(K, Arc<V>)> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.clear_entries()
+    }
+
+    /// Copy the unexpired items into a HashMap in one locked pass.
+    fn to_map(&self) -> HashMap<K, Arc<V>> {
+        let inner = self.inner.lock().unwrap();
+        let now = inner.core.clock.now();
+        inner
+            .pages
+            .iter()
+            .flatten()
+            .filter(|page| !inner.core.expiries.is_expired(&page.key, now))
+            .filter_map(|page| Some((page.key.clone(), page.value.clone()?)))
+            .collect()
+    }
+
+    /// Get up to `limit` unexpired items, ordered with the items the policy would evict last first. Higher priority items come before lower ones, and otherwise hot items before cold ones and read items before unread ones.
+    fn hottest(&self, limit: usize) -> Vec<(K, Arc<V>)> {
+        let inner = self.inner.lock().unwrap();
+        let mut items = inner.hottest_items();
+        inner.core.priorities.sort_descending(&mut items);
+        items.truncate(limit);
+        items
+    }
+
+    /// Get the name of the eviction policy.
+    fn policy_name(&self) -> &'static str {
+        "clock-pro"
+    }
+
+    /// Get the cache as `Any`, so it can be downcast from a `dyn Cache`.
+    fn as_any(&self) -> Option<&dyn Any>
+    where
+        Self: 'static,
+    {
+        Some(self)
+    }
+
+    /// Get cache statistics.
+    fn stats(&self) -> CacheStats {
+        let inner = self.inner.lock().unwrap();
+        inner
+            .core
+            .stats
+            .snapshot(inner.len() as u64, inner.capacity)
+    }
+
+    /// Get the cache statistics together with how soon the items expire.
+    fn detailed_stats(&self) -> DetailedStats {
+        let inner = self.inner.lock().unwrap();
+        let size = inner.len() as u64;
+        let mut detailed = DetailedStats::new(inner.core.stats.snapshot(size, inner.capacity));
+        detailed.eviction_ages = Some(inner.core.stats.eviction_ages());
+        detailed.expiry_distribution = Some(
+            inner
+                .core
+                .expiries
+                .distribution(inner.core.clock.now(), size),
+        );
+        detailed
+    }
+
+    /// Change the capacity of the cache, if the new capacity is smaller than the current size, items are evicted per the policy.
+    fn change_capacity(&self, capacity: u64) {
+        self.change_capacity_collect(capacity);
+    }
+
+    /// Change the capacity of the cache, returning the items which were removed to fit the new capacity.
+    fn change_capacity_collect(&self, capacity: u64) -> Vec<(K, Arc<V>)> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.capacity = capacity;
+        let evicted = inner.shrink_to(capacity as usize);
+        while inner.test > capacity as usize {
+            inner.run_hand_test();
+        }
+        inner.cold_target = inner.cold_target.min(capacity as usize).max(1);
+        evicted
+    }
+
+    /// Evict `fraction` of the items per the policy in one locked pass, leaving the capacity unchanged.
+    fn shed(&self, fraction: f64) -> usize {
+        let mut inner = self.inner.lock().unwrap();
+        let len = inner.len();
+        inner.shrink_to(len - shed_count(len, fraction)).len()
+    }
+}
+
+impl<K: Eq + Hash + Clone + Sync + Send, V: Send + Sync> TryCache<K, V> for ClockProCache<K, V> {
+    /// Get a value from the cache, returning `CacheError::Poisoned` instead of panicking if the cache lock is poisoned.
+    fn try_get(&self, key: &K) -> Result<Option<Arc<V>>, CacheError> {
+        if self.inner.is_poisoned() {
+            return Err(CacheError::Poisoned);
+        }
+        Ok(self.get(key))
+    }
+
+    /// Set a value in the cache, returning an error instead of inserting if the cache is full and was built with `FullBehavior::ReturnError`, has a capacity of zero, or its lock is poisoned.
+    fn try_set(&self, key: K, value: V) -> Result<Option<Arc<V>>, CacheError> {
+        let started = self.latency.start();
+        let result = self.insert(
+            key,
+            Arc::new(value),
+            None,
+            None,
+            None,
+            None,
+            EntrySource::Inserted,
+        );
+        self.latency.record_set(started);
+        result
+    }
+}
+
+impl<K: Eq + Hash + Clone + Sync + Send, V: Send + Sync> VersionedCache<K, V>
+    for ClockProCache<K, V>
+{
+    /// Get the current version of an item, without counting a hit or miss or updating its position.
+    fn version(&self, key: &K) -> Option<u64> {
+        let inner = self.inner.lock().unwrap();
+        if inner.core.expiries.is_expired(key, inner.core.clock.now()) {
+            return None;
+        }
+        inner.core.metadata.version(key)
+    }
+
+    /// Set a value only if the item is at `expected_version`, or is not in the cache if it is 0.
+    fn set_versioned(
+        &self,
+        key: K,
+        value: V,
+        expected_version: u64,
+    ) -> Result<Option<Arc<V>>, CacheError> {
+        let started = self.latency.start();
+        let result = self.insert(
+            key,
+            Arc::new(value),
+            None,
+            None,
+            None,
+            Some(expected_version),
+            EntrySource::Inserted,
+        );
+        self.latency.record_set(started);
+        result
+    }
+}
+
+impl<K: Eq + Hash + Clone + Sync + Send, V: Send + Sync> IntoIterator for ClockProCache<K, V> {
+    type Item = (K, Arc<V>);
+    type IntoIter = std::vec::IntoIter<(K, Arc<V>)>;
+
+    /// Consume the handle, yielding the cache's unexpired items from the hottest to the coldest. Other handles still hold the items.
+    fn into_iter(self) -> Self::IntoIter {
+        let items = self.inner.lock().unwrap().hottest_items();
+        items.into_iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clock_pro_cache_hot_and_cold() {
+        let cache = ClockProCache::new(4);
+        for key in 1..=4 {
+            cache.set(key, key);
+        }
+        cache.get(&1);
+        cache.get(&2);
+        cache.set(5, 5);
+        let stats = cache.clock_pro_stats();
+        // the read items became hot as the cold hand passed them, and an unread one was evicted
+        assert_eq!((stats.hot, stats.cold, stats.test), (2, 2, 1));
+        assert_eq!(stats.promotions, 2);
+        assert!((stats.hot_ratio() - 0.5).abs() < f64::EPSILON);
+        assert!(cache.contains_key(&1) && cache.contains_key(&2));
+        assert_eq!(cache.stats().evictions, 1);
+    }
+
+    #[test]
+    fn test_clock_pro_cache_sheds_hot_items() {
+        let cache = ClockProCache::new(4);
+        for key in 0..4 {
+            cache.set(key, key);
+        }
+        for key in 0..4 {
+            cache.get(&key);
+        }
+        for key in 10..14 {
+            cache.set(key, key);
+        }
+        assert_eq!(cache.stats().size, 4);
+        // the hot items are demoted and evicted rather than looping on the cold hand
+        assert_eq!(cache.shed(0.5), 2);
+        assert_eq!(cache.shed(1.0), 2);
+        assert_eq!(cache.stats().size, 0);
+        assert_eq!(cache.stats().capacity, 4);
+        cache.set(1, 1);
+        assert_eq!(cache.get(&1).map(|v| *v), Some(1));
+    }
+
+    #[test]
+    fn test_clock_pro_cache_test_pages() {
+        let cache = ClockProCache::new(2);
+        cache.set(1, 1);
+        cache.set(2, 2);
+        cache.set(3, 3);
+        assert_eq!(cache.get(&1), None);
+        assert_eq!(cache.clock_pro_stats().test, 1);
+        // setting a key during its test period brings it back hot and grows the cold target
+        cache.set(1, 1);
+        let stats = cache.clock_pro_stats();
+        assert_eq!((stats.hot, stats.cold_target), (1, 2));
+        assert_eq!(cache.get(&1).map(|v| *v), Some(1));
+        assert!(stats.test <= 2);
+    }
+
+    #[test]
+    fn test_clock_pro_cache_scan_resistance() {
+        let cache = ClockProCache::new(10);
+        let hot: Vec<u64> = (0..5).collect();
+        for key in &hot {
+            cache.set(*key, *key);
+            cache.get(key);
+        }
+        for key in 1_000..2_000 {
+            cache.set(key, key);
+            for key in &hot {
+                cache.get(key);
+            }
+        }
+        for key in &hot {
+            assert_eq!(cache.get(key).map(|v| *v), Some(*key));
+        }
+        let stats = cache.clock_pro_stats();
+        assert_eq!(stats.hot + stats.cold, 10);
+        assert!(stats.test <= 10);
+        cache.change_capacity(0);
+        let stats = cache.clock_pro_stats();
+        assert_eq!((stats.hot, stats.cold, stats.test), (0, 0, 0));
+        assert_eq!(stats.cold_target, 1);
+    }
+}
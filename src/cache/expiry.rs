@@ -0,0 +1,296 @@
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+use std::ops::Bound::{Excluded, Included};
+use std::time::{Duration, Instant};
+
+use crate::cache::ExpiryDistribution;
+use crate::clock::saturating_add;
+
+/// Expiries tracks the deadlines of items in caches which do not otherwise expire, so they can be given a TTL.
+///
+/// Deadlines are kept in a map ordered by time, so expired items can be found without scanning the whole cache. Only items with a deadline are stored, so caches which never use a TTL pay nothing beyond an emptiness check.
+///
+/// With a time-to-idle, every item's deadline is also pushed back each time it is touched, but never past the deadline its TTL gives it.
+pub(crate) struct Expiries<K> {
+    default_ttl: Option<Duration>,
+    time_to_idle: Option<Duration>,
+    lifetimes: HashMap<K, Instant>,
+    deadlines: HashMap<K, (Instant, u64)>,
+    queue: BTreeMap<(Instant, u64), K>,
+    next_sequence: u64,
+}
+
+impl<K: Eq + Hash + Clone> Expiries<K> {
+    pub(crate) fn new() -> Self {
+        Expiries {
+            default_ttl: None,
+            time_to_idle: None,
+            lifetimes: HashMap::new(),
+            deadlines: HashMap::new(),
+            queue: BTreeMap::new(),
+            next_sequence: 0,
+        }
+    }
+
+    /// Set the TTL given to items which are set without one.
+    pub(crate) fn set_default_ttl(&mut self, ttl: Duration) {
+        self.default_ttl = Some(ttl);
+    }
+
+    /// Get the TTL given to items which are set without one.
+    pub(crate) fn default_ttl(&self) -> Option<Duration> {
+        self.default_ttl
+    }
+
+    /// Make items expire once they have not been touched for `time_to_idle`, on top of any TTL.
+    pub(crate) fn set_time_to_idle(&mut self, time_to_idle: Duration) {
+        self.time_to_idle = Some(time_to_idle);
+    }
+
+    /// Get the time-to-idle, if items expire when they are not touched.
+    pub(crate) fn time_to_idle(&self) -> Option<Duration> {
+        self.time_to_idle
+    }
+
+    /// Set the deadline of a key from its TTL as of `now`, or the default TTL if it has none, replacing any previous deadline.
+    pub(crate) fn set(&mut self, key: &K, ttl: Option<Duration>, now: Instant) {
+        self.remove(key);
+        // a TTL too long to represent as an instant never runs out
+        let lifetime = ttl
+            .or(self.default_ttl)
+            .and_then(|ttl| now.checked_add(ttl));
+        let idle = self
+            .time_to_idle
+            .and_then(|time_to_idle| now.checked_add(time_to_idle));
+        if let (Some(lifetime), Some(_)) = (lifetime, idle) {
+            self.lifetimes.insert(key.clone(), lifetime);
+        }
+        let deadline = match (lifetime, idle) {
+            (Some(lifetime), Some(idle)) => Some(lifetime.min(idle)),
+            (lifetime, idle) => lifetime.or(idle),
+        };
+        if let Some(deadline) = deadline {
+            self.schedule(key, deadline);
+        }
+    }
+
+    /// Push back the deadline of a key touched at `now` by the time-to-idle, up to the deadline its TTL gives it.
+    pub(crate) fn touch(&mut self, key: &K, now: Instant) {
+        let Some(time_to_idle) = self.time_to_idle else {
+            return;
+        };
+        let Some(previous) = self.deadlines.remove(key) else {
+            return;
+        };
+        self.queue.remove(&previous);
+        let idle = saturating_add(now, time_to_idle);
+        let deadline = match self.lifetimes.get(key) {
+            Some(lifetime) => idle.min(*lifetime),
+            None => idle,
+        };
+        self.schedule(key, deadline.max(previous.0));
+    }
+
+    /// Push back the deadline of a key which has not passed it yet by `extra`, returning false if it has no live deadline.
+    pub(crate) fn extend(&mut self, key: &K, extra: Duration, now: Instant) -> bool {
+        let Some(&previous) = self
+            .deadlines
+            .get(key)
+            .filter(|(deadline, _)| *deadline > now)
+        else {
+            return false;
+        };
+        self.deadlines.remove(key);
+        self.queue.remove(&previous);
+        if let Some(lifetime) = self.lifetimes.get_mut(key) {
+            *lifetime = saturating_add(*lifetime, extra);
+        }
+        self.schedule(key, saturating_add(previous.0, extra));
+        true
+    }
+
+    /// Queue a key to expire at `deadline`.
+    fn schedule(&mut self, key: &K, deadline: Instant) {
+        let deadline = (deadline, self.next_sequence);
+        self.next_sequence += 1;
+        self.deadlines.insert(key.clone(), deadline);
+        self.queue.insert(deadline, key.clone());
+    }
+
+    /// Forget the deadline of a key.
+    pub(crate) fn remove(&mut self, key: &K) {
+        if let Some(deadline) = self.deadlines.remove(key) {
+            self.queue.remove(&deadline);
+        }
+        self.lifetimes.remove(key);
+    }
+
+    /// Forget every deadline.
+    pub(crate) fn clear(&mut self) {
+        self.deadlines.clear();
+        self.queue.clear();
+        self.lifetimes.clear();
+    }
+
+    /// Get the time left before a key's deadline, or None if it has no deadline.
+    pub(crate) fn remaining(&self, key: &K, now: Instant) -> Option<Duration> {
+        self.deadlines
+            .get(key)
+            .map(|(deadline, _)| deadline.saturating_duration_since(now))
+    }
+
+    /// Check if no key has a deadline.
+    pub(crate) fn is_empty(&self) -> bool {
+        self.deadlines.is_empty()
+    }
+
+    /// Check if a key has passed its deadline.
+    pub(crate) fn is_expired(&self, key: &K, now: Instant) -> bool {
+        !self.is_empty()
+            && self
+                .deadlines
+                .get(key)
+                .is_some_and(|(deadline, _)| *deadline <= now)
+    }
+
+    /// Get the keys whose deadlines fall after `now` but within `window` of it, earliest deadline first.
+    pub(crate) fn expiring_within(&self, now: Instant, window: Duration) -> Vec<K> {
+        self.queue
+            .range((
+                Excluded((now, u64::MAX)),
+                Included((saturating_add(now, window), u64::MAX)),
+            ))
+            .map(|(_, key)| key.clone())
+            .collect()
+    }
+
+    /// Count the live deadlines by how soon they fall, the rest of the `size` items have no deadline.
+    pub(crate) fn distribution(&self, now: Instant, size: u64) -> ExpiryDistribution {
+        let mut distribution = ExpiryDistribution::default();
+        for (deadline, _) in self.queue.keys() {
+            if *deadline > now {
+                distribution.record(deadline.saturating_duration_since(now));
+            }
+        }
+        distribution.never = size.saturating_sub(self.deadlines.len() as u64);
+        distribution
+    }
+
+    /// Remove and return the key with the earliest deadline if it has passed.
+    pub(crate) fn pop_expired(&mut self, now: Instant) -> Option<K> {
+        let (&deadline, _) = self.queue.first_key_value()?;
+        if deadline.0 > now {
+            return None;
+        }
+        let key = self.queue.remove(&deadline)?;
+        self.deadlines.remove(&key);
+        self.lifetimes.remove(&key);
+        Some(key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expiries_max_ttl() {
+        let mut expiries = Expiries::new();
+        let now = Instant::now();
+        expiries.set(&1, Some(Duration::MAX), now);
+        assert!(!expiries.is_expired(&1, now));
+        assert_eq!(expiries.remaining(&1, now), None);
+
+        expiries.set_time_to_idle(Duration::MAX);
+        expiries.set(&2, Some(Duration::from_secs(60)), now);
+        expiries.touch(&2, now);
+        assert!(!expiries.is_expired(&2, now + Duration::from_secs(30)));
+        assert_eq!(expiries.distribution(now, 2).never, 1);
+
+        // extending by more than an Instant can hold keeps the item for good
+        assert!(expiries.extend(&2, Duration::MAX, now));
+        assert!(!expiries.is_expired(&2, now + Duration::from_secs(1 << 30)));
+    }
+
+    #[test]
+    fn test_expiries() {
+        let mut expiries = Expiries::new();
+        let now = Instant::now();
+        expiries.set(&1, Some(Duration::ZERO), Instant::now());
+        expiries.set(&2, Some(Duration::from_secs(60)), Instant::now());
+        expiries.set(&3, None, Instant::now());
+        assert!(expiries.is_expired(&1, Instant::now()));
+        assert!(!expiries.is_expired(&2, Instant::now()));
+        assert!(!expiries.is_expired(&3, Instant::now()));
+        assert_eq!(expiries.pop_expired(Instant::now()), Some(1));
+        assert_eq!(expiries.pop_expired(Instant::now()), None);
+        assert_eq!(expiries.pop_expired(now + Duration::from_secs(61)), Some(2));
+    }
+
+    #[test]
+    fn test_expiries_default_ttl() {
+        let mut expiries = Expiries::new();
+        expiries.set_default_ttl(Duration::ZERO);
+        expiries.set(&1, None, Instant::now());
+        expiries.set(&2, Some(Duration::from_secs(60)), Instant::now());
+        assert!(expiries.is_expired(&1, Instant::now()));
+        assert!(!expiries.is_expired(&2, Instant::now()));
+        expiries.remove(&1);
+        assert_eq!(expiries.pop_expired(Instant::now()), None);
+    }
+
+    #[test]
+    fn test_expiries_expiring_within() {
+        let mut expiries = Expiries::new();
+        expiries.set(&1, Some(Duration::ZERO), Instant::now());
+        expiries.set(&2, Some(Duration::from_secs(30)), Instant::now());
+        expiries.set(&3, Some(Duration::from_secs(10)), Instant::now());
+        expiries.set(&4, Some(Duration::from_secs(120)), Instant::now());
+        expiries.set(&5, None, Instant::now());
+        let now = Instant::now();
+        assert_eq!(
+            expiries.expiring_within(now, Duration::from_secs(60)),
+            vec![3, 2]
+        );
+        assert!(expiries.expiring_within(now, Duration::ZERO).is_empty());
+        assert_eq!(expiries.expiring_within(now, Duration::MAX), vec![3, 2, 4]);
+    }
+
+    #[test]
+    fn test_expiries_time_to_idle() {
+        let mut expiries = Expiries::new();
+        expiries.set_time_to_idle(Duration::from_secs(10));
+        let start = Instant::now();
+        expiries.set(&1, None, Instant::now());
+        expiries.set(&2, Some(Duration::from_secs(15)), Instant::now());
+        expiries.touch(&1, start + Duration::from_secs(8));
+        expiries.touch(&2, start + Duration::from_secs(8));
+        // touching 1 kept it alive, 2 is still bound by its TTL
+        assert!(!expiries.is_expired(&1, start + Duration::from_secs(17)));
+        assert!(expiries.is_expired(&2, start + Duration::from_secs(17)));
+        assert!(expiries.is_expired(&1, start + Duration::from_secs(19)));
+        assert_eq!(
+            expiries.pop_expired(start + Duration::from_secs(19)),
+            Some(2)
+        );
+        assert_eq!(
+            expiries.pop_expired(start + Duration::from_secs(19)),
+            Some(1)
+        );
+    }
+
+    #[test]
+    fn test_expiries_extend() {
+        let mut expiries = Expiries::new();
+        let now = Instant::now();
+        expiries.set(&1, Some(Duration::from_secs(10)), Instant::now());
+        expiries.set(&2, None, Instant::now());
+        expiries.set(&3, Some(Duration::ZERO), Instant::now());
+        assert!(expiries.extend(&1, Duration::from_secs(20), now));
+        assert!(!expiries.extend(&2, Duration::from_secs(20), now));
+        assert!(!expiries.extend(&3, Duration::from_secs(20), Instant::now()));
+        assert!(!expiries.is_expired(&1, now + Duration::from_secs(25)));
+        assert_eq!(expiries.pop_expired(now + Duration::from_secs(25)), Some(3));
+        assert_eq!(expiries.pop_expired(now + Duration::from_secs(31)), Some(1));
+    }
+}
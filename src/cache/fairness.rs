@@ -0,0 +1,106 @@
+use crate::sync::atomic::{AtomicU64, Ordering};
+use crate::sync::{Condvar, Mutex};
+
+/// A ticket queue which hands writers the cache lock in the order they arrived.
+///
+/// A plain mutex lets the thread which has just released it take it straight back, so under heavy insert pressure one thread can hold the cache through a long burst of evictions while the others stall. Writers take a ticket on arrival and wait for it to be served, and a writer with more evictions than fit in one batch takes a new ticket for the rest, so a writer never waits behind more than one batch per writer ahead of it.
+pub(crate) struct InsertQueue {
+    eviction_batch: usize,
+    next_ticket: AtomicU64,
+    serving: Mutex<u64>,
+    turn: Condvar,
+}
+
+/// A writer's turn in an InsertQueue, the next writer is served when it is dropped.
+pub(crate) struct Turn<'a> {
+    queue: &'a InsertQueue,
+}
+
+impl InsertQueue {
+    /// Create a new InsertQueue whose writers evict at most `eviction_batch` items a turn.
+    pub(crate) fn new(eviction_batch: usize) -> Self {
+        InsertQueue {
+            eviction_batch: eviction_batch.max(1),
+            next_ticket: AtomicU64::new(0),
+            serving: Mutex::new(0),
+            turn: Condvar::new(),
+        }
+    }
+
+    /// Get the most items a writer may evict in one turn.
+    pub(crate) fn eviction_batch(&self) -> usize {
+        self.eviction_batch
+    }
+
+    /// Wait until every writer which arrived earlier has finished its turn.
+    pub(crate) fn wait_turn(&self) -> Turn<'_> {
+        let ticket = self.next_ticket.fetch_add(1, Ordering::Relaxed);
+        let mut serving = self
+            .serving
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        while *serving != ticket {
+            serving = self
+                .turn
+                .wait(serving)
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+        }
+        Turn { queue: self }
+    }
+
+    /// Get the number of writers taking or waiting for a turn.
+    pub(crate) fn queued(&self) -> u64 {
+        let serving = *self
+            .serving
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        self.next_ticket.load(Ordering::Relaxed) - serving
+    }
+}
+
+impl Drop for Turn<'_> {
+    fn drop(&mut self) {
+        // a writer which panics during its turn still passes it on
+        let mut serving = self
+            .queue
+            .serving
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        *serving += 1;
+        drop(serving);
+        self.queue.turn.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test_insert_queue_serves_in_arrival_order() {
+        let queue = Arc::new(InsertQueue::new(4));
+        let served = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let first = queue.wait_turn();
+        let mut writers = Vec::new();
+        for writer in 0..3 {
+            let (writer_queue, served) = (queue.clone(), served.clone());
+            writers.push(thread::spawn(move || {
+                let _turn = writer_queue.wait_turn();
+                served.lock().unwrap().push(writer);
+            }));
+            // wait for the writer to take its ticket before the next arrives
+            while queue.queued() < writer + 2 {
+                thread::sleep(Duration::from_millis(1));
+            }
+        }
+        drop(first);
+        for writer in writers {
+            writer.join().unwrap();
+        }
+        assert_eq!(*served.lock().unwrap(), vec![0, 1, 2]);
+        assert_eq!(queue.queued(), 0);
+    }
+}
@@ -0,0 +1,678 @@
+use crate::sync::Mutex;
+use std::any::Any;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::cache::latency::{LatencyTracker, OperationLatencies};
+use crate::cache::listener::{
+    AccessListener, AccessListeners, CallbackPanics, EntrySource, EvictionListener, RemovalCause,
+};
+use crate::cache::metadata::{EntryInfo, EntryMetadata};
+use crate::cache::policy_core::{policy_methods, PolicyCore};
+use crate::cache::{
+    shed_count, Cache, CacheStats, DetailedStats, FullBehavior, GetOutcome, TryCache,
+    VersionedCache,
+};
+use crate::clock::Clock;
+use crate::error::CacheError;
+
+/// The position of an item in eviction order, its GDSF priority as the bits of a non-negative float, which order like the float, then when it was ranked.
+type Rank = (u64, u64);
+
+/// An item in the GDSFCache, with the cost and size it was set with and how often it has been read.
+struct GDSFEntry<V> {
+    value: Arc<V>,
+    cost: u64,
+    size: u64,
+    freq: u64,
+    rank: Rank,
+}
+
+impl<V> GDSFEntry<V> {
+    /// Get the GDSF priority the item was last ranked with.
+    fn priority(&self) -> f64 {
+        f64::from_bits(self.rank.0)
+    }
+}
+
+/// The cost and size of an item to be inserted.
+#[derive(Clone, Copy)]
+struct Weight {
+    cost: u64,
+    size: u64,
+}
+
+impl Default for Weight {
+    fn default() -> Self {
+        Weight { cost: 1, size: 1 }
+    }
+}
+
+/// GDSFCacheInner contains the inner data structure for the GDSFCache.
+struct GDSFCacheInner<K: Eq + Hash + Clone + Send, V: Send + Sync> {
+    capacity: u64,
+    entries: HashMap<K, GDSFEntry<V>>,
+    // the items from the lowest priority, the next to be evicted, to the highest
+    ranks: BTreeMap<Rank, K>,
+    next_rank: u64,
+    // the priority of the last evicted item, added to every new priority so items which stop being read age out
+    inflation: f64,
+    core: PolicyCore<K, V>,
+}
+
+impl<K: Eq + Hash + Clone + Send, V: Send + Sync> GDSFCacheInner<K, V> {
+    /// Create a new GDSFCacheInner with the given capacity.
+    fn new(capacity: u64) -> Self {
+        GDSFCacheInner {
+            capacity,
+            entries: HashMap::with_capacity(capacity as usize),
+            ranks: BTreeMap::new(),
+            next_rank: 0,
+            inflation: 0.0,
+            core: PolicyCore::new(),
+        }
+    }
+
+    /// Check if a key is held.
+    fn holds(&self, key: &K) -> bool {
+        self.entries.contains_key(key)
+    }
+
+    /// Get the number of items held.
+    fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Give an item a new rank from its frequency, cost and size and the current inflation.
+    fn rerank(&mut self, key: &K) {
+        let Some(entry) = self.entries.get_mut(key) else {
+            return;
+        };
+        self.ranks.remove(&entry.rank);
+        let priority =
+            self.inflation + entry.freq as f64 * entry.cost as f64 / entry.size.max(1) as f64;
+        entry.rank = (priority.to_bits(), self.next_rank);
+        self.next_rank += 1;
+        self.ranks.insert(entry.rank, key.clone());
+    }
+
+    /// Remove an item from every structure.
+    fn remove_entry(&mut self, key: &K) -> Option<Arc<V>> {
+        let entry = self.entries.remove(key)?;
+        self.ranks.remove(&entry.rank);
+        self.core.forget(key);
+        Some(entry.value)
+    }
+
+    /// Find the item with the lowest GDSF priority, among the items with the lowest assigned priority if priorities have been assigned.
+    fn victim(&self) -> Option<K> {
+        let lowest = self.core.priorities.lowest(self.len());
+        self.ranks
+            .values()
+            .find(|key| lowest.is_none_or(|lowest| self.core.priorities.get(key) == lowest))
+            .cloned()
+    }
+
+    /// Evict the item with the lowest GDSF priority, raising the inflation to its priority.
+    fn evict(&mut self) -> Option<(K, Arc<V>)> {
+        let key = self.victim()?;
+        let priority = self.entries.get(&key)?.priority();
+        self.inflation = self.inflation.max(priority);
+        let lifetime = self.core.metadata.lifetime(&key, self.core.clock.now());
+        let value = self.remove_entry(&key)?;
+        self.core
+            .listeners
+            .notify(&key, &value, RemovalCause::Evicted);
+        self.core.stats.record_eviction(lifetime);
+        Some((key, value))
+    }
+
+    /// Evict items per the policy until at most `len` are left.
+    fn evict_to(&mut self, len: usize) -> Vec<(K, Arc<V>)> {
+        let mut evicted = Vec::new();
+        while self.len() > len {
+            match self.evict() {
+                Some(item) => evicted.push(item),
+                None => break,
+            }
+        }
+        evicted
+    }
+
+    /// Remove every item which has passed its deadline.
+    fn purge_expired(&mut self) {
+        let now = self.core.clock.now();
+        while let Some(key) = self.core.expiries.pop_expired(now) {
+            if let Some(value) = self.remove_entry(&key) {
+                self.core
+                    .listeners
+                    .notify(&key, &value, RemovalCause::Expired);
+                self.core.stats.record_expiration();
+            }
+        }
+    }
+
+    /// Remove every item, notifying listeners and returning the removed items.
+    fn clear_entries(&mut self) -> Vec<(K, Arc<V>)> {
+        self.ranks.clear();
+        self.inflation = 0.0;
+        self.core.clear();
+        let cleared: Vec<(K, Arc<V>)> = self
+            .entries
+            .drain()
+            .map(|(key, entry)| (key, entry.value))
+            .collect();
+        for (key, value) in &cleared {
+            self.core
+                .listeners
+                .notify(key, value, RemovalCause::Cleared);
+        }
+        cleared
+    }
+
+    /// Get the unexpired items, from the highest GDSF priority to the lowest.
+    fn hottest_items(&self) -> Vec<(K, Arc<V>)> {
+        let now = self.core.clock.now();
+        self.ranks
+            .values()
+            .rev()
+            .filter(|key| !self.core.expiries.is_expired(key, now))
+            .map(|key| (key.clone(), self.entries[key].value.clone()))
+            .collect()
+    }
+}
+
+/// GDSFCache is a cache which evicts by Greedy-Dual-Size-Frequency, weighing how often an item is read against how costly it is to recompute and how large it is.
+///
+/// Each item is given a cost and a size with `set_with_cost`, items set with `set` have a cost and size of 1. Its priority is its frequency times its cost divided by its size, and when the cache is full the item with the lowest priority is evicted, so large items which are cheap to recompute go first and small expensive ones stay. Priorities given after an eviction start from the priority of the evicted item rather than from zero, so items which were read often in the past but no longer are eventually evicted too.
+///
+/// Capacity counts items, as in every other policy, the size only weighs the priority.
+///
+/// All mutability is handled internally with a Mutex, so the cache can be shared between threads. Values are returned as Arcs to allow for shared ownership.
+///
+/// Example:
+/// ```
+/// use arcache::{Cache, GDSFCache};
+///
+/// let cache = GDSFCache::<&str, &str>::new(2);
+///
+/// // a large page which is cheap to render, and a small one which is expensive
+/// cache.set_with_cost("home", "<html>...</html>", 10, 1000);
+/// cache.set_with_cost("report", "<html>.</html>", 500, 10);
+///
+/// cache.set("about", "<html>..</html>");
+/// assert!(!cache.contains_key(&"home"));
+/// assert!(cache.contains_key(&"report"));
+/// ```
+pub struct GDSFCache<K: Eq + Hash + Clone + Send, V: Send + Sync> {
+    inner: Arc<Mutex<GDSFCacheInner<K, V>>>,
+    access_listeners: Arc<AccessListeners<K>>,
+    latency: Arc<LatencyTracker>,
+}
+
+impl<K: Eq + Hash + Clone + Sync + Send, V: Send + Sync> GDSFCache<K, V> {
+    /// Create a new GDSFCache with the given capacity.
+    pub fn new(capacity: u64) -> Self {
+        GDSFCache {
+            inner: Arc::new(Mutex::new(GDSFCacheInner::new(capacity))),
+            access_listeners: Arc::new(AccessListeners::new()),
+            latency: Arc::new(LatencyTracker::new()),
+        }
+    }
+
+    policy_methods!(lock, lock);
+
+    /// Set a value in the cache with the cost of recomputing it and its size, in any units as long as they are used consistently.
+    ///
+    /// Items with a low cost for their size are evicted first. Setting an existing key replaces its cost and size.
+    pub fn set_with_cost(&self, key: K, value: V, cost: u64, size: u64) -> Option<Arc<V>> {
+        self.insert(
+            key,
+            Arc::new(value),
+            Weight { cost, size },
+            None,
+            None,
+            None,
+            None,
+            EntrySource::Inserted,
+        )
+        .unwrap_or(None)
+    }
+
+    /// Get the GDSF priority of an item, its frequency times its cost divided by its size plus the inflation when it was last read or set, without counting a hit or miss.
+    pub fn gdsf_priority(&self, key: &K) -> Option<f64> {
+        let inner = self.inner.lock().unwrap();
+        if inner.core.expiries.is_expired(key, inner.core.clock.now()) {
+            return None;
+        }
+        inner.entries.get(key).map(GDSFEntry::priority)
+    }
+
+    /// Set a value in the cache with a priority, when the cache is full lower priority items are evicted before higher priority ones.
+    ///
+    /// Within a priority the item with the lowest GDSF priority goes first. New items set with `set` have the lowest priority, `DEFAULT_PRIORITY`, and setting a key again without a priority keeps the one it has.
+    pub fn set_with_priority(&self, key: K, value: V, priority: u32) -> Option<Arc<V>> {
+        self.insert(
+            key,
+            Arc::new(value),
+            Weight::default(),
+            Some(priority),
+            None,
+            None,
+            None,
+            EntrySource::Inserted,
+        )
+        .unwrap_or(None)
+    }
+
+    /// Set a value in the cache which expires after the given time-to-live, overriding the cache's default TTL.
+    ///
+    /// Expired items are never returned and are removed before any live item is evicted.
+    pub fn set_with_ttl(&self, key: K, value: V, ttl: Duration) -> Option<Arc<V>> {
+        self.insert(
+            key,
+            Arc::new(value),
+            Weight::default(),
+            None,
+            Some(ttl),
+            None,
+            None,
+            EntrySource::Inserted,
+        )
+        .unwrap_or(None)
+    }
+
+    /// Set a value in the cache with a metadata value attached, which can be read back with `entry_info`.
+    ///
+    /// Metadata is dropped when the item leaves the cache or is set again without metadata.
+    pub fn set_with_metadata(
+        &self,
+        key: K,
+        value: V,
+        metadata: impl Any + Send + Sync,
+    ) -> Option<Arc<V>> {
+        self.insert(
+            key,
+            Arc::new(value),
+            Weight::default(),
+            None,
+            None,
+            Some(Arc::new(metadata)),
+            None,
+            EntrySource::Inserted,
+        )
+        .unwrap_or(None)
+    }
+
+    /// Insert a value with a cost and size, a priority and an optional TTL, evicting an item if the cache is full.
+    #[allow(clippy::too_many_arguments)]
+    fn insert(
+        &self,
+        key: K,
+        value: Arc<V>,
+        weight: Weight,
+        priority: Option<u32>,
+        ttl: Option<Duration>,
+        metadata: Option<EntryMetadata>,
+        expected_version: Option<u64>,
+        source: EntrySource,
+    ) -> Result<Option<Arc<V>>, CacheError> {
+        let mut inner = self.inner.lock().map_err(|_| CacheError::Poisoned)?;
+        if inner.capacity == 0 {
+            return Err(CacheError::CapacityZero);
+        }
+        inner.purge_expired();
+        inner.core.metadata.check_version(&key, expected_version)?;
+        // overwriting a key never evicts another item
+        let is_new = !inner.entries.contains_key(&key);
+        if is_new && !inner.core.full_behavior.evicts() && inner.len() as u64 >= inner.capacity {
+            inner.core.listeners.reject(&key);
+            return inner.core.full_behavior.rejection();
+        }
+        if is_new && inner.len() as u64 >= inner.capacity {
+            inner.evict();
+        }
+        inner.core.stats.record_set(is_new);
+        inner.core.track(&key, priority, ttl, metadata, source);
+        // an overwrite counts as an access, like a get
+        let old = match inner.entries.get_mut(&key) {
+            Some(entry) => {
+                entry.cost = weight.cost;
+                entry.size = weight.size;
+                entry.freq += 1;
+                Some(std::mem::replace(&mut entry.value, value))
+            }
+            None => {
+                // a placeholder rank, replaced straight away by `rerank`
+                inner.entries.insert(
+                    key.clone(),
+                    GDSFEntry {
+                        value,
+                        cost: weight.cost,
+                        size: weight.size,
+                        freq: 1,
+                        rank: (0, 0),
+                    },
+                );
+                None
+            }
+        };
+        inner.rerank(&key);
+        Ok(old)
+    }
+}
+
+impl<K: Eq + Hash + Clone + Send, V: Send + Sync> Clone for GDSFCache<K, V> {
+    /// Get another handle to the same cache, sharing its items, settings and statistics.
+    fn clone(&self) -> Self {
+        GDSFCache {
+            inner: self.inner.clone(),
+            access_listeners: self.access_listeners.clone(),
+            latency: self.latency.clone(),
+        }
+    }
+}
+
+impl<K: Eq + Hash + Clone + Sync + Send, V: Send + Sync> Cache<K, V> for GDSFCache<K, V> {
+    /// Get a value from the cache, increasing its frequency and so its GDSF priority.
+    fn get(&self, key: &K) -> Option<Arc<V>> {
+        let started = self.latency.start();
+        let result = {
+            let mut inner = self.inner.lock().unwrap();
+            inner.purge_expired();
+            let result = inner.entries.get_mut(key).map(|entry| {
+                entry.freq += 1;
+                entry.value.clone()
+            });
+            if result.is_some() {
+                inner.rerank(key);
+                inner.core.stats.record_hit();
+                let now = inner.core.clock.now();
+                inner.core.metadata.touch(key, now);
+            } else {
+                inner.core.stats.record_miss();
+            }
+            result
+        };
+        self.latency.record_get(started);
+        self.access_listeners.notify(key, &result);
+        result
+    }
+
+    /// Get a value from the cache, with whether it was a hit, or a miss because the key expired, was rejected or is absent.
+    fn get_with_outcome(&self, key: &K) -> (Option<Arc<V>>, GetOutcome) {
+        let result = self.get(key);
+        let outcome = match result {
+            Some(_) => GetOutcome::Hit,
+            None => self.inner.lock().unwrap().core.listeners.miss_outcome(key),
+        };
+        (result, outcome)
+    }
+
+    /// Set a value in the cache with a cost and size of 1.
+    fn set(&self, key: K, value: V) -> Option<Arc<V>> {
+        let started = self.latency.start();
+        let result = self
+            .insert(
+                key,
+                Arc::new(value),
+                Weight::default(),
+                None,
+                None,
+                None,
+                None,
+                EntrySource::Inserted,
+            )
+            .unwrap_or(None);
+        self.latency.record_set(started);
+        result
+    }
+
+    /// Set a value in the cache, recording how it came to be in the cache for `entry_info` and eviction listeners.
+    fn set_with_source(&self, key: K, value: V, source: EntrySource) -> Option<Arc<V>> {
+        let started = self.latency.start();
+        let result = self
+            .insert(
+                key,
+                Arc::new(value),
+                Weight::default(),
+                None,
+                None,
+                None,
+                None,
+                source,
+            )
+            .unwrap_or(None);
+        self.latency.record_set(started);
+        result
+    }
+
+    /// Remove a value from the cache.
+    fn remove(&self, key: &K) -> Option<Arc<V>> {
+        let mut inner = self.inner.lock().unwrap();
+        let result = inner.remove_entry(key);
+        if let Some(value) = &result {
+            inner
+                .core
+                .listeners
+                .notify(key, value, RemovalCause::Removed);
+        }
+        result
+    }
+
+    /// Check if a key is in the cache and has not expired, without counting a hit or miss or updating its position.
+    fn contains_key(&self, key: &K) -> bool {
+        let inner = self.inner.lock().unwrap();
+        inner.entries.contains_key(key)
+            && !inner.core.expiries.is_expired(key, inner.core.clock.now())
+    }
+
+    /// Get the time left before an item expires, or None if it never expires or is not in the cache.
+    fn ttl_remaining(&self, key: &K) -> Option<Duration> {
+        self.entry_info(key)?.ttl_remaining
+    }
+
+    /// Clear the cache.
+    fn clear(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.clear_entries();
+    }
+
+    /// Clear the cache, returning every item which was removed.
+    fn clear_returning(&self) -> Vec<(K, Arc<V>)> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.clear_entries()
+    }
+
+    /// Copy the unexpired items into a HashMap in one locked pass.
+    fn to_map(&self) -> HashMap<K, Arc<V>> {
+        let inner = self.inner.lock().unwrap();
+        let now = inner.core.clock.now();
+        inner
+            .entries
+            .iter()
+            .filter(|(key, _)| !inner.core.expiries.is_expired(key, now))
+            .map(|(key, entry)| (key.clone(), entry.value.clone()))
+            .collect()
+    }
+
+    /// Get up to `limit` unexpired items, ordered with the items the policy would evict last first. Higher priority items come before lower ones, and otherwise items with a higher GDSF priority come first.
+    fn hottest(&self, limit: usize) -> Vec<(K, Arc<V>)> {
+        let inner = self.inner.lock().unwrap();
+        let mut items = inner.hottest_items();
+        inner.core.priorities.sort_descending(&mut items);
+        items.truncate(limit);
+        items
+    }
+
+    /// Get the name of the eviction policy.
+    fn policy_name(&self) -> &'static str {
+        "gdsf"
+    }
+
+    /// Get the cache as `Any`, so it can be downcast from a `dyn Cache`.
+    fn as_any(&self) -> Option<&dyn Any>
+    where
+        Self: 'static,
+    {
+        Some(self)
+    }
+
+    /// Get cache statistics.
+    fn stats(&self) -> CacheStats {
+        let inner = self.inner.lock().unwrap();
+        inner
+            .core
+            .stats
+            .snapshot(inner.len() as u64, inner.capacity)
+    }
+
+    /// Get the cache statistics together with how soon the items expire.
+    fn detailed_stats(&self) -> DetailedStats {
+        let inner = self.inner.lock().unwrap();
+        let size = inner.len() as u64;
+        let mut detailed = DetailedStats::new(inner.core.stats.snapshot(size, inner.capacity));
+        detailed.eviction_ages = Some(inner.core.stats.eviction_ages());
+        detailed.expiry_distribution = Some(
+            inner
+                .core
+                .expiries
+                .distribution(inner.core.clock.now(), size),
+        );
+        detailed
+    }
+
+    /// Change the capacity of the cache, if the new capacity is smaller than the current size, items are evicted per the policy.
+    fn change_capacity(&self, capacity: u64) {
+        self.change_capacity_collect(capacity);
+    }
+
+    /// Change the capacity of the cache, returning the items which were removed to fit the new capacity.
+    fn change_capacity_collect(&self, capacity: u64) -> Vec<(K, Arc<V>)> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.capacity = capacity;
+        inner.evict_to(capacity as usize)
+    }
+
+    /// Evict `fraction` of the items per the policy in one locked pass, leaving the capacity unchanged.
+    fn shed(&self, fraction: f64) -> usize {
+        let mut inner = self.inner.lock().unwrap();
+        let len = inner.len();
+        inner.evict_to(len - shed_count(len, fraction)).len()
+    }
+}
+
+impl<K: Eq + Hash + Clone + Sync + Send, V: Send + Sync> TryCache<K, V> for GDSFCache<K, V> {
+    /// Get a value from the cache, returning `CacheError::Poisoned` instead of panicking if the cache lock is poisoned.
+    fn try_get(&self, key: &K) -> Result<Option<Arc<V>>, CacheError> {
+        if self.inner.is_poisoned() {
+            return Err(CacheError::Poisoned);
+        }
+        Ok(self.get(key))
+    }
+
+    /// Set a value in the cache, returning an error instead of inserting if the cache is full and was built with `FullBehavior::ReturnError`, has a capacity of zero, or its lock is poisoned.
+    fn try_set(&self, key: K, value: V) -> Result<Option<Arc<V>>, CacheError> {
+        let started = self.latency.start();
+        let result = self.insert(
+            key,
+            Arc::new(value),
+            Weight::default(),
+            None,
+            None,
+            None,
+            None,
+            EntrySource::Inserted,
+        );
+        self.latency.record_set(started);
+        result
+    }
+}
+
+impl<K: Eq + Hash + Clone + Sync + Send, V: Send + Sync> VersionedCache<K, V> for GDSFCache<K, V> {
+    /// Get the current version of an item, without counting a hit or miss or updating its position.
+    fn version(&self, key: &K) -> Option<u64> {
+        let inner = self.inner.lock().unwrap();
+        if inner.core.expiries.is_expired(key, inner.core.clock.now()) {
+            return None;
+        }
+        inner.core.metadata.version(key)
+    }
+
+    /// Set a value only if the item is at `expected_version`, or is not in the cache if it is 0.
+    fn set_versioned(
+        &self,
+        key: K,
+        value: V,
+        expected_version: u64,
+    ) -> Result<Option<Arc<V>>, CacheError> {
+        let started = self.latency.start();
+        let result = self.insert(
+            key,
+            Arc::new(value),
+            Weight::default(),
+            None,
+            None,
+            None,
+            Some(expected_version),
+            EntrySource::Inserted,
+        );
+        self.latency.record_set(started);
+        result
+    }
+}
+
+impl<K: Eq + Hash + Clone + Sync + Send, V: Send + Sync> IntoIterator for GDSFCache<K, V> {
+    type Item = (K, Arc<V>);
+    type IntoIter = std::vec::IntoIter<(K, Arc<V>)>;
+
+    /// Consume the handle, yielding the cache's unexpired items from the hottest to the coldest. Other handles still hold the items.
+    fn into_iter(self) -> Self::IntoIter {
+        let items = self.inner.lock().unwrap().hottest_items();
+        items.into_iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_gdsf_cache_evicts_cheap_large_items_first() {
+        let cache = GDSFCache::new(3);
+        cache.set_with_cost(1, 1, 10, 100);
+        cache.set_with_cost(2, 2, 10, 1);
+        cache.set_with_cost(3, 3, 100, 100);
+        // 1 costs 0.1 per unit of size, the least of the three
+        cache.set_with_cost(4, 4, 50, 1);
+        assert!(!cache.contains_key(&1));
+        // reading 3 ten times lifts it past 2, on top of the inflation left by evicting 1
+        for _ in 0..10 {
+            cache.get(&3);
+        }
+        assert_eq!(cache.gdsf_priority(&3), Some(0.1 + 11.0));
+        cache.set_with_cost(5, 5, 50, 1);
+        assert!(!cache.contains_key(&2));
+        assert!(cache.contains_key(&3));
+        assert_eq!(cache.stats().evictions, 2);
+    }
+
+    #[test]
+    fn test_gdsf_cache_inflation_ages_out_popular_items() {
+        let cache = GDSFCache::new(2);
+        cache.set(1, 1);
+        for _ in 0..4 {
+            cache.get(&1);
+        }
+        // each new key evicts the last one and raises the inflation past its priority
+        for key in 2..10 {
+            cache.set(key, key);
+        }
+        assert!(!cache.contains_key(&1));
+        assert!(cache.contains_key(&9));
+        let hottest: Vec<u64> = cache.hottest(2).into_iter().map(|(key, _)| key).collect();
+        assert_eq!(hottest, vec![9, 8]);
+    }
+}
@@ -0,0 +1,62 @@
+use std::fmt;
+use std::ops::Deref;
+use std::sync::Arc;
+
+/// EntryGuard holds a value returned by `get_pinned`, its item cannot be evicted until the guard is dropped.
+///
+/// The guard dereferences to the value. Pinning only protects against eviction, an item can still expire or be removed explicitly while it is pinned.
+///
+/// Example:
+/// ```
+/// use arcache::{Cache, LRUCache};
+///
+/// let cache = LRUCache::<&str, String>::new(1);
+/// cache.set("buffer", "data".to_string());
+///
+/// let guard = cache.get_pinned(&"buffer").unwrap();
+/// cache.set("other", "value".to_string());
+///
+/// assert_eq!(*guard, "data");
+/// assert!(cache.contains_key(&"buffer"));
+/// ```
+pub struct EntryGuard<'a, V> {
+    value: Arc<V>,
+    release: Option<Box<dyn FnOnce() + 'a>>,
+}
+
+impl<'a, V> EntryGuard<'a, V> {
+    /// Create a guard which calls `release` to unpin its item when dropped.
+    pub(crate) fn new(value: Arc<V>, release: impl FnOnce() + 'a) -> Self {
+        EntryGuard {
+            value,
+            release: Some(Box::new(release)),
+        }
+    }
+
+    /// Get the value as an Arc, which can outlive the guard.
+    pub fn value(&self) -> &Arc<V> {
+        &self.value
+    }
+}
+
+impl<V> Deref for EntryGuard<'_, V> {
+    type Target = V;
+
+    fn deref(&self) -> &V {
+        &self.value
+    }
+}
+
+impl<V: fmt::Debug> fmt::Debug for EntryGuard<'_, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("EntryGuard").field(&self.value).finish()
+    }
+}
+
+impl<V> Drop for EntryGuard<'_, V> {
+    fn drop(&mut self) {
+        if let Some(release) = self.release.take() {
+            release();
+        }
+    }
+}
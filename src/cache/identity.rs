@@ -0,0 +1,65 @@
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+// the ID of the next cache created in this process, 0 is left for statistics which belong to no single cache
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// CacheIdentity tells caches apart, it is carried in `CacheStats`, `RemovalEvent`s and log lines.
+///
+/// Every cache gets an ID unique within the process when it is created, and may be given a name with its `with_name` builder. Names are for people and need not be unique, IDs are. A CacheIdentity displays as its name, or as `cache#` and its ID if it has none.
+///
+/// Example:
+/// ```
+/// use arcache::{Cache, FIFOCache, LRUCache, RemovalEvent};
+///
+/// let users = LRUCache::<u64, String>::new(10)
+///     .with_name("users")
+///     .with_eviction_listener(|event: &RemovalEvent<u64, String>| {
+///         assert_eq!(event.cache.name(), Some("users"));
+///     });
+/// let pages = FIFOCache::<u64, String>::new(10);
+///
+/// assert_eq!(users.stats().cache.to_string(), "users");
+/// assert_ne!(users.stats().cache.id(), pages.stats().cache.id());
+/// assert_eq!(pages.stats().cache.name(), None);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct CacheIdentity {
+    id: u64,
+    name: Option<Arc<str>>,
+}
+
+impl CacheIdentity {
+    /// Create the identity of a new cache, with the next unused ID and no name.
+    pub(crate) fn new() -> Self {
+        CacheIdentity {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            name: None,
+        }
+    }
+
+    /// Give the cache a name, keeping its ID.
+    pub(crate) fn set_name(&mut self, name: String) {
+        self.name = Some(name.into());
+    }
+
+    /// Get the ID of the cache, unique within the process, or 0 for statistics which belong to no single cache.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Get the name of the cache, if it was given one.
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+}
+
+impl fmt::Display for CacheIdentity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.name {
+            Some(name) => f.write_str(name),
+            None => write!(f, "cache#{}", self.id),
+        }
+    }
+}
@@ -0,0 +1,387 @@
+use crate::sync::atomic::{AtomicU64, Ordering};
+use crate::sync::{Mutex, RwLock};
+use std::collections::HashMap;
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+use crate::cache::listener::{EvictionListener, RemovalEvent};
+use crate::cache::{Cache, CacheStats};
+
+/// The inner data structure for the Interner.
+struct InternerInner {
+    ids: HashMap<Arc<str>, u32>,
+    // each ID's string and how many references to it are held, None once the ID is free
+    strings: Vec<Option<(Arc<str>, usize)>>,
+    free: Vec<u32>,
+}
+
+impl InternerInner {
+    fn new() -> Self {
+        InternerInner {
+            ids: HashMap::new(),
+            strings: Vec::new(),
+            free: Vec::new(),
+        }
+    }
+
+    /// Take a reference to the ID of a string, interning it if needed, or None if every ID is in use.
+    fn acquire(&mut self, value: &str) -> Option<u32> {
+        if let Some(&id) = self.ids.get(value) {
+            if let Some((_, references)) = &mut self.strings[id as usize] {
+                *references += 1;
+            }
+            return Some(id);
+        }
+        let id = match self.free.pop() {
+            Some(id) => id,
+            None => {
+                let id = u32::try_from(self.strings.len()).ok()?;
+                self.strings.push(None);
+                id
+            }
+        };
+        let value: Arc<str> = Arc::from(value);
+        self.strings[id as usize] = Some((value.clone(), 1));
+        self.ids.insert(value, id);
+        Some(id)
+    }
+
+    /// Give back a reference to an ID, dropping its string and freeing the ID once none are left.
+    fn release(&mut self, id: u32) {
+        let Some(Some((_, references))) = self.strings.get_mut(id as usize) else {
+            return;
+        };
+        *references -= 1;
+        if *references == 0 {
+            if let Some((value, _)) = self.strings[id as usize].take() {
+                self.ids.remove(&value);
+            }
+            self.free.push(id);
+        }
+    }
+
+    fn resolve(&self, id: u32) -> Option<Arc<str>> {
+        self.strings
+            .get(id as usize)?
+            .as_ref()
+            .map(|(value, _)| value.clone())
+    }
+}
+
+/// Interner deduplicates strings and hands out compact `u32` IDs for them.
+///
+/// Each distinct string is stored once, no matter how many times it is interned. Every `intern` takes a reference to the string's ID and every `release` gives one back, once none are left the string is dropped and its ID is reused. Releases are queued and applied by the next `intern`, so an Interner can be registered as the eviction listener of the cache holding its IDs without taking its lock while the cache is locked.
+///
+/// Interners are cheap handles, clones share the same strings.
+///
+/// Example:
+/// ```
+/// use arcache::Interner;
+///
+/// let interner = Interner::new();
+/// let id = interner.intern("a").unwrap();
+/// assert_eq!(interner.intern("a"), Some(id));
+///
+/// interner.release(id);
+/// interner.release(id);
+/// assert!(interner.is_empty());
+/// ```
+#[derive(Clone)]
+pub struct Interner {
+    inner: Arc<RwLock<InternerInner>>,
+    released: Arc<Mutex<Vec<u32>>>,
+}
+
+impl Interner {
+    /// Create a new, empty Interner.
+    pub fn new() -> Self {
+        Interner {
+            inner: Arc::new(RwLock::new(InternerInner::new())),
+            released: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    /// Intern a string, taking a reference to its ID. Interning the same string twice returns the same ID.
+    ///
+    /// Returns None if every `u32` ID is in use.
+    pub fn intern(&self, value: &str) -> Option<u32> {
+        let mut inner = self.inner.write().unwrap();
+        self.apply_releases(&mut inner);
+        inner.acquire(value)
+    }
+
+    /// Give back a reference to an ID taken by `intern`.
+    pub fn release(&self, id: u32) {
+        self.released.lock().unwrap().push(id);
+    }
+
+    /// Apply the queued releases.
+    fn apply_releases(&self, inner: &mut InternerInner) {
+        let released = std::mem::take(&mut *self.released.lock().unwrap());
+        for id in released {
+            inner.release(id);
+        }
+    }
+
+    /// Look up the ID of a string without interning it.
+    pub fn lookup(&self, value: &str) -> Option<u32> {
+        let inner = self.inner.read().unwrap();
+        inner.ids.get(value).copied()
+    }
+
+    /// Resolve an ID back to the string it was interned from.
+    pub fn resolve(&self, id: u32) -> Option<Arc<str>> {
+        self.inner.read().unwrap().resolve(id)
+    }
+
+    /// Get the number of distinct strings which are still referenced.
+    pub fn len(&self) -> usize {
+        let mut inner = self.inner.write().unwrap();
+        self.apply_releases(&mut inner);
+        inner.ids.len()
+    }
+
+    /// Check if no strings are referenced.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Forget all interned strings and queued releases.
+    pub fn clear(&self) {
+        let mut inner = self.inner.write().unwrap();
+        *inner = InternerInner::new();
+        self.released.lock().unwrap().clear();
+    }
+}
+
+impl Default for Interner {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<V> EvictionListener<u32, V> for Interner {
+    /// Give back the reference held by an item which left the cache.
+    fn on_removal(&self, event: &RemovalEvent<u32, V>) {
+        self.release(event.key);
+    }
+}
+
+/// InternedCache wraps a cache keyed by `u32` and exposes a `&str` keyed API on top of it.
+///
+/// Keys are deduplicated by an `Interner` so that highly repetitive string keys (e.g. URLs) are stored once, while the wrapped cache only stores compact IDs.
+///
+/// The wrapped cache is built with the interner registered as its eviction listener, so a key's ID is released and reused once its item leaves the cache. IDs are only reused while no lookup is in flight, so a get never sees another key's item. A key whose set the wrapped cache turns away, for example because it is full and built with `FullBehavior::Reject`, keeps its ID until the cache is cleared. Once every `u32` ID is in use, sets of new keys are dropped.
+///
+/// Example:
+/// ```
+/// use arcache::{Cache, InternedCache, LRUCache};
+///
+/// let cache = InternedCache::new(|interner| {
+///     LRUCache::<u32, String>::new(10).with_eviction_listener(interner)
+/// });
+///
+/// cache.set("https://example.com/a", "a".to_string());
+///
+/// let value = cache.get("https://example.com/a");
+///
+/// assert_eq!(*value.unwrap(), "a".to_string());
+/// println!("{:?}", cache.stats());
+/// ```
+pub struct InternedCache<C: Cache<u32, V>, V: Send + Sync> {
+    cache: C,
+    interner: Interner,
+    unknown_misses: AtomicU64,
+    _marker: PhantomData<fn() -> V>,
+}
+
+impl<C: Cache<u32, V>, V: Send + Sync> InternedCache<C, V> {
+    /// Create a new InternedCache wrapping the cache returned by `build`, which must register the interner it is given as the cache's eviction listener.
+    pub fn new(build: impl FnOnce(Interner) -> C) -> Self {
+        let interner = Interner::new();
+        InternedCache {
+            cache: build(interner.clone()),
+            interner,
+            unknown_misses: AtomicU64::new(0),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Get a value from the cache.
+    pub fn get(&self, key: &str) -> Option<Arc<V>> {
+        // holding the interner's lock keeps the ID from being reused for another key
+        let inner = self.interner.inner.read().unwrap();
+        match inner.ids.get(key) {
+            Some(id) => self.cache.get(id),
+            None => {
+                self.unknown_misses.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+        }
+    }
+
+    /// Set a value in the cache.
+    pub fn set(&self, key: &str, value: V) -> Option<Arc<V>> {
+        let mut inner = self.interner.inner.write().unwrap();
+        self.interner.apply_releases(&mut inner);
+        let id = inner.acquire(key)?;
+        let previous = self.cache.set(id, value);
+        if previous.is_some() {
+            // the item being replaced already held a reference
+            inner.release(id);
+        }
+        previous
+    }
+
+    /// Remove a value from the cache.
+    pub fn remove(&self, key: &str) -> Option<Arc<V>> {
+        let inner = self.interner.inner.read().unwrap();
+        inner.ids.get(key).and_then(|id| self.cache.remove(id))
+    }
+
+    /// Clear the cache and the interner, holding the interner's lock throughout so no key is set in between.
+    pub fn clear(&self) {
+        let mut inner = self.interner.inner.write().unwrap();
+        self.cache.clear();
+        *inner = InternerInner::new();
+        self.interner.released.lock().unwrap().clear();
+    }
+
+    /// Clear the cache and the interner, returning every item which was removed along with its original key.
+    pub fn clear_returning(&self) -> Vec<(Arc<str>, Arc<V>)> {
+        let mut inner = self.interner.inner.write().unwrap();
+        let cleared = self
+            .cache
+            .clear_returning()
+            .into_iter()
+            .filter_map(|(id, value)| Some((inner.resolve(id)?, value)))
+            .collect();
+        *inner = InternerInner::new();
+        self.interner.released.lock().unwrap().clear();
+        cleared
+    }
+
+    /// Copy the items into a HashMap keyed by their original strings.
+    pub fn to_map(&self) -> HashMap<Arc<str>, Arc<V>> {
+        let inner = self.interner.inner.read().unwrap();
+        self.cache
+            .to_map()
+            .into_iter()
+            .filter_map(|(id, value)| Some((inner.resolve(id)?, value)))
+            .collect()
+    }
+
+    /// Get the cache statistics, lookups of keys that were never set are counted as misses.
+    pub fn stats(&self) -> CacheStats {
+        let mut stats = self.cache.stats();
+        stats.misses += self.unknown_misses.load(Ordering::Relaxed);
+        stats
+    }
+
+    /// Change the capacity of the wrapped cache.
+    pub fn change_capacity(&self, capacity: u64) {
+        self.cache.change_capacity(capacity);
+    }
+
+    /// Change the capacity of the wrapped cache, returning the items which were removed along with their original keys.
+    pub fn change_capacity_collect(&self, capacity: u64) -> Vec<(Arc<str>, Arc<V>)> {
+        // released IDs are only freed by the next set, so the evicted items' keys can still be resolved
+        let inner = self.interner.inner.read().unwrap();
+        self.cache
+            .change_capacity_collect(capacity)
+            .into_iter()
+            .filter_map(|(id, value)| Some((inner.resolve(id)?, value)))
+            .collect()
+    }
+
+    /// Get the interner used for the keys.
+    pub fn interner(&self) -> &Interner {
+        &self.interner
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::LRUCache;
+
+    fn interned_lru(capacity: u64) -> InternedCache<LRUCache<u32, i32>, i32> {
+        InternedCache::new(|interner| LRUCache::new(capacity).with_eviction_listener(interner))
+    }
+
+    #[test]
+    fn test_interner() {
+        let interner = Interner::new();
+        let a = interner.intern("a").unwrap();
+        let b = interner.intern("b").unwrap();
+        assert_ne!(a, b);
+        assert_eq!(interner.intern("a"), Some(a));
+        assert_eq!(interner.lookup("b"), Some(b));
+        assert_eq!(interner.lookup("c"), None);
+        assert_eq!(interner.resolve(a).as_deref(), Some("a"));
+        assert_eq!(interner.len(), 2);
+    }
+
+    #[test]
+    fn test_interner_releases() {
+        let interner = Interner::new();
+        let a = interner.intern("a").unwrap();
+        interner.intern("a");
+        interner.release(a);
+        assert_eq!(interner.len(), 1);
+        interner.release(a);
+        assert_eq!(interner.len(), 0);
+        assert_eq!(interner.lookup("a"), None);
+        // the freed ID is handed to the next new string
+        assert_eq!(interner.intern("b"), Some(a));
+        assert_eq!(interner.resolve(a).as_deref(), Some("b"));
+    }
+
+    #[test]
+    fn test_interned_cache() {
+        let cache = interned_lru(2);
+        cache.set("a", 1);
+        cache.set("b", 2);
+        cache.set("a", 3);
+        assert_eq!(cache.interner().len(), 2);
+        assert_eq!(cache.get("a").map(|v| *v), Some(3));
+        assert_eq!(cache.get("c"), None);
+        assert_eq!(cache.remove("b").map(|v| *v), Some(2));
+        assert_eq!(cache.get("b"), None);
+        assert_eq!(cache.interner().len(), 1);
+        let stats = cache.stats();
+        assert_eq!(stats.hits, 1);
+        assert_eq!(stats.misses, 2);
+    }
+
+    #[test]
+    fn test_interned_cache_releases_evicted_keys() {
+        let cache = interned_lru(2);
+        for key in 0..100 {
+            cache.set(&key.to_string(), key);
+        }
+        // only the keys still in the cache are interned, their IDs recycled
+        assert_eq!(cache.interner().len(), 2);
+        assert!(cache.interner().lookup("99").unwrap() < 3);
+        assert_eq!(cache.get("97"), None);
+        assert_eq!(cache.get("98").map(|v| *v), Some(98));
+        let evicted = cache.change_capacity_collect(1);
+        assert_eq!(evicted.len(), 1);
+        assert_eq!(&*evicted[0].0, "99");
+        assert_eq!(cache.interner().len(), 1);
+    }
+
+    #[test]
+    fn test_interned_cache_clear() {
+        let cache = interned_lru(2);
+        cache.set("a", 1);
+        cache.clear();
+        assert_eq!(cache.get("a"), None);
+        assert!(cache.interner().is_empty());
+        cache.set("b", 2);
+        let cleared = cache.clear_returning();
+        assert_eq!(cleared.len(), 1);
+        assert_eq!(&*cleared[0].0, "b");
+        assert!(cache.interner().is_empty());
+    }
+}
@@ -0,0 +1,391 @@
+use crate::sync::atomic::{AtomicU64, Ordering};
+use crate::sync::Mutex;
+use std::any::Any;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+
+use crate::cache::listener::EntrySource;
+use crate::cache::{Cache, CacheStats, DetailedStats, GetOutcome, IterationOrder};
+
+/// Invalidation is what an InvalidationMessage asks other caches to drop.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Invalidation<K> {
+    /// Drop a single key.
+    Key(K),
+    /// Drop every item, published when a cache is cleared.
+    All,
+}
+
+/// InvalidationMessage is published to an InvalidationBus, tagged with the cache which published it so it can ignore its own messages.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidationMessage<K> {
+    /// The ID of the publishing cache, see `InvalidatingCache::origin`.
+    pub origin: u64,
+    /// What to drop.
+    pub invalidation: Invalidation<K>,
+}
+
+/// A handler called with every message published to an InvalidationBus.
+pub type InvalidationHandler<K> = Arc<dyn Fn(&InvalidationMessage<K>) + Send + Sync>;
+
+/// InvalidationBus carries invalidations between caches, for example between the replicas of a service, so a key changed on one is not served stale by the others.
+///
+/// Every message must be delivered to every subscriber, including the subscriber of the cache which published it, caches ignore their own messages by their origin. `LocalInvalidationBus` connects caches in one process, implement this trait over Redis pub/sub, NATS or similar to connect processes, encoding keys with a `Codec`.
+pub trait InvalidationBus<K>: Send + Sync {
+    /// Deliver a message to every subscriber.
+    fn publish(&self, message: &InvalidationMessage<K>);
+
+    /// Register a handler for every message, returning an ID to unsubscribe it with.
+    fn subscribe(&self, handler: InvalidationHandler<K>) -> u64;
+
+    /// Stop calling a handler.
+    fn unsubscribe(&self, subscription: u64);
+}
+
+/// LocalInvalidationBus delivers invalidations to the caches of one process, calling each handler on the publishing thread.
+pub struct LocalInvalidationBus<K> {
+    handlers: Mutex<Vec<(u64, InvalidationHandler<K>)>>,
+    next_subscription: AtomicU64,
+}
+
+impl<K> LocalInvalidationBus<K> {
+    /// Create a new LocalInvalidationBus with no subscribers.
+    pub fn new() -> Self {
+        LocalInvalidationBus {
+            handlers: Mutex::new(Vec::new()),
+            next_subscription: AtomicU64::new(0),
+        }
+    }
+}
+
+impl<K> Default for LocalInvalidationBus<K> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: Send + Sync> InvalidationBus<K> for LocalInvalidationBus<K> {
+    fn publish(&self, message: &InvalidationMessage<K>) {
+        // handlers are called without the lock held, so they may publish or subscribe themselves
+        let handlers: Vec<InvalidationHandler<K>> = self
+            .handlers
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(_, handler)| handler.clone())
+            .collect();
+        for handler in handlers {
+            handler(message);
+        }
+    }
+
+    fn subscribe(&self, handler: InvalidationHandler<K>) -> u64 {
+        let subscription = self.next_subscription.fetch_add(1, Ordering::Relaxed);
+        self.handlers.lock().unwrap().push((subscription, handler));
+        subscription
+    }
+
+    fn unsubscribe(&self, subscription: u64) {
+        self.handlers
+            .lock()
+            .unwrap()
+            .retain(|(id, _)| *id != subscription);
+    }
+}
+
+/// InvalidatingCache wraps any cache and keeps it consistent with other caches through an InvalidationBus.
+///
+/// Every set, remove and clear publishes an invalidation, whether or not the cache held the key, since a replica which evicted a key can still change it. Invalidations published by other caches on the bus remove the key from the wrapped cache, or clear it. Values filled in by a loader, set with `EntrySource::Loaded`, are not published since they are copies of the origin rather than changes, and evictions and expirations are local decisions and are not published either. Invalidations received from the bus are applied to the wrapped cache directly, so they are not published again.
+///
+/// Example:
+/// ```
+/// use arcache::{Cache, EntrySource, InvalidatingCache, LRUCache, LocalInvalidationBus};
+/// use std::sync::Arc;
+///
+/// let bus = Arc::new(LocalInvalidationBus::new());
+/// let replica_a = InvalidatingCache::new(LRUCache::<&str, u64>::new(10), bus.clone());
+/// let replica_b = InvalidatingCache::new(LRUCache::<&str, u64>::new(10), bus);
+///
+/// replica_a.set_with_source("user", 1, EntrySource::Loaded);
+/// replica_b.set_with_source("user", 1, EntrySource::Loaded);
+///
+/// // the user changes on replica a, so replica b drops its stale copy
+/// replica_a.set("user", 2);
+/// assert!(!replica_b.contains_key(&"user"));
+/// assert_eq!(replica_a.get(&"user").map(|v| *v), Some(2));
+/// ```
+pub struct InvalidatingCache<C, K> {
+    cache: Arc<C>,
+    bus: Arc<dyn InvalidationBus<K>>,
+    origin: u64,
+    subscription: u64,
+    published: AtomicU64,
+    received: Arc<AtomicU64>,
+}
+
+impl<C, K> InvalidatingCache<C, K>
+where
+    K: Eq + Hash + Clone + Send + Sync + 'static,
+    C: Send + Sync + 'static,
+{
+    /// Create a new InvalidatingCache wrapping the given cache, publishing to and subscribing to `bus`.
+    pub fn new<V>(cache: C, bus: Arc<dyn InvalidationBus<K>>) -> Self
+    where
+        V: Send + Sync,
+        C: Cache<K, V>,
+    {
+        let cache = Arc::new(cache);
+        let origin = rand::random();
+        let received = Arc::new(AtomicU64::new(0));
+        let subscriber: Weak<C> = Arc::downgrade(&cache);
+        let counter = received.clone();
+        let subscription = bus.subscribe(Arc::new(move |message: &InvalidationMessage<K>| {
+            if message.origin == origin {
+                return;
+            }
+            let Some(cache) = subscriber.upgrade() else {
+                return;
+            };
+            match &message.invalidation {
+                Invalidation::Key(key) => {
+                    cache.remove(key);
+                }
+                Invalidation::All => cache.clear(),
+            }
+            counter.fetch_add(1, Ordering::Relaxed);
+        }));
+        InvalidatingCache {
+            cache,
+            bus,
+            origin,
+            subscription,
+            published: AtomicU64::new(0),
+            received,
+        }
+    }
+}
+
+impl<C, K> InvalidatingCache<C, K> {
+    /// Get the ID this cache tags the messages it publishes with.
+    pub fn origin(&self) -> u64 {
+        self.origin
+    }
+
+    /// Get the number of invalidations this cache has published.
+    pub fn published(&self) -> u64 {
+        self.published.load(Ordering::Relaxed)
+    }
+
+    /// Get the number of invalidations from other caches which have been applied to this cache.
+    pub fn received(&self) -> u64 {
+        self.received.load(Ordering::Relaxed)
+    }
+
+    /// Get a reference to the wrapped cache, changes made through it are not published.
+    pub fn inner(&self) -> &C {
+        &self.cache
+    }
+
+    fn publish(&self, invalidation: Invalidation<K>) {
+        self.bus.publish(&InvalidationMessage {
+            origin: self.origin,
+            invalidation,
+        });
+        self.published.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+impl<C, K> Drop for InvalidatingCache<C, K> {
+    fn drop(&mut self) {
+        self.bus.unsubscribe(self.subscription);
+    }
+}
+
+impl<K, V, C> Cache<K, V> for InvalidatingCache<C, K>
+where
+    K: Eq + Hash + Clone + Send + Sync,
+    V: Send + Sync,
+    C: Cache<K, V>,
+{
+    /// Get a value from the cache.
+    fn get(&self, key: &K) -> Option<Arc<V>> {
+        self.cache.get(key)
+    }
+
+    /// Get a value from the wrapped cache, with whether it was a hit or why it was a miss.
+    fn get_with_outcome(&self, key: &K) -> (Option<Arc<V>>, GetOutcome) {
+        self.cache.get_with_outcome(key)
+    }
+
+    /// Set a value in the cache, publishing an invalidation of the key.
+    fn set(&self, key: K, value: V) -> Option<Arc<V>> {
+        self.set_with_source(key, value, EntrySource::Inserted)
+    }
+
+    /// Set a value like `set`, passing on how it came to be in the cache to the wrapped cache. Values filled in by a loader are not published.
+    fn set_with_source(&self, key: K, value: V, source: EntrySource) -> Option<Arc<V>> {
+        if source == EntrySource::Loaded {
+            return self.cache.set_with_source(key, value, source);
+        }
+        let published = key.clone();
+        let previous = self.cache.set_with_source(key, value, source);
+        self.publish(Invalidation::Key(published));
+        previous
+    }
+
+    /// Remove a value from the cache, publishing an invalidation whether or not it was present.
+    fn remove(&self, key: &K) -> Option<Arc<V>> {
+        let removed = self.cache.remove(key);
+        self.publish(Invalidation::Key(key.clone()));
+        removed
+    }
+
+    /// Check if a key is in the cache.
+    fn contains_key(&self, key: &K) -> bool {
+        self.cache.contains_key(key)
+    }
+
+    /// Get the time left before an item expires, or None if it never expires or is not in the cache.
+    fn ttl_remaining(&self, key: &K) -> Option<Duration> {
+        self.cache.ttl_remaining(key)
+    }
+
+    /// Clear the cache, publishing an invalidation of every item.
+    fn clear(&self) {
+        self.cache.clear();
+        self.publish(Invalidation::All);
+    }
+
+    /// Clear the cache, publishing an invalidation of every item and returning every item which was removed.
+    fn clear_returning(&self) -> Vec<(K, Arc<V>)> {
+        let cleared = self.cache.clear_returning();
+        self.publish(Invalidation::All);
+        cleared
+    }
+
+    /// Copy the items of the wrapped cache into a HashMap.
+    fn to_map(&self) -> HashMap<K, Arc<V>> {
+        self.cache.to_map()
+    }
+
+    /// Get the hottest items of the wrapped cache.
+    fn hottest(&self, limit: usize) -> Vec<(K, Arc<V>)> {
+        self.cache.hottest(limit)
+    }
+
+    /// Get the items of the wrapped cache in the given order.
+    fn items(&self, order: IterationOrder) -> Option<Vec<(K, Arc<V>)>> {
+        self.cache.items(order)
+    }
+
+    /// Get the name of the wrapped cache's eviction policy.
+    fn policy_name(&self) -> &'static str {
+        self.cache.policy_name()
+    }
+
+    /// Get the cache as `Any`, so it can be downcast from a `dyn Cache`.
+    fn as_any(&self) -> Option<&dyn Any>
+    where
+        Self: 'static,
+    {
+        Some(self)
+    }
+
+    /// Get the statistics of the wrapped cache.
+    fn stats(&self) -> CacheStats {
+        self.cache.stats()
+    }
+
+    /// Get the detailed statistics of the wrapped cache.
+    fn detailed_stats(&self) -> DetailedStats {
+        self.cache.detailed_stats()
+    }
+
+    /// Change the capacity of the wrapped cache.
+    fn change_capacity(&self, capacity: u64) {
+        self.cache.change_capacity(capacity);
+    }
+
+    /// Change the capacity of the wrapped cache, returning the items which were removed.
+    fn change_capacity_collect(&self, capacity: u64) -> Vec<(K, Arc<V>)> {
+        self.cache.change_capacity_collect(capacity)
+    }
+
+    /// Shed `fraction` of the items of the wrapped cache.
+    fn shed(&self, fraction: f64) -> usize {
+        self.cache.shed(fraction)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::LRUCache;
+
+    #[test]
+    fn test_invalidating_cache_publishes_changes() {
+        let bus = Arc::new(LocalInvalidationBus::new());
+        let a = InvalidatingCache::new(LRUCache::new(2), bus.clone());
+        let b = InvalidatingCache::new(LRUCache::new(2), bus.clone());
+        // loader fills are copies of the origin and stay local
+        a.set_with_source(1, 1, EntrySource::Loaded);
+        b.set_with_source(1, 1, EntrySource::Loaded);
+        b.set_with_source(2, 2, EntrySource::Loaded);
+        assert_eq!((a.published(), b.published()), (0, 0));
+
+        a.remove(&1);
+        assert!(!b.contains_key(&1));
+        // evictions stay local
+        b.set_with_source(3, 3, EntrySource::Loaded);
+        b.set_with_source(4, 4, EntrySource::Loaded);
+        assert_eq!(b.published(), 0);
+
+        a.set(4, 4);
+        assert!(!b.contains_key(&4));
+        a.clear();
+        assert_eq!(b.stats().size, 0);
+        assert_eq!((a.published(), b.received()), (3, 3));
+        assert_eq!(a.received(), 0);
+    }
+
+    #[test]
+    fn test_invalidating_cache_publishes_keys_it_does_not_hold() {
+        let bus = Arc::new(LocalInvalidationBus::new());
+        let a = InvalidatingCache::new(LRUCache::new(1), bus.clone());
+        let b = InvalidatingCache::new(LRUCache::new(2), bus.clone());
+        a.set_with_source(1, 1, EntrySource::Loaded);
+        b.set_with_source(1, 1, EntrySource::Loaded);
+        // a evicts 1, then writes it, so b must drop its copy
+        a.set_with_source(2, 2, EntrySource::Loaded);
+        assert!(!a.contains_key(&1));
+        a.set(1, 10);
+        assert!(!b.contains_key(&1));
+
+        // deleting a key a does not hold reaches b as well
+        b.set_with_source(3, 3, EntrySource::Loaded);
+        assert_eq!(a.remove(&3), None);
+        assert!(!b.contains_key(&3));
+        assert_eq!(a.published(), 2);
+    }
+
+    #[test]
+    fn test_invalidating_cache_ignores_own_messages() {
+        let bus = Arc::new(LocalInvalidationBus::new());
+        let cache = InvalidatingCache::new(LRUCache::new(2), bus.clone());
+        cache.set(1, 1);
+        cache.set(1, 2);
+        assert_eq!(cache.get(&1).map(|v| *v), Some(2));
+        assert_eq!(cache.published(), 2);
+
+        // messages from elsewhere, for example another process, are applied
+        bus.publish(&InvalidationMessage {
+            origin: cache.origin().wrapping_add(1),
+            invalidation: Invalidation::Key(1),
+        });
+        assert!(!cache.contains_key(&1));
+        drop(cache);
+        assert!(bus.handlers.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,186 @@
+use crate::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+// each power of two range is split into this many linear sub-buckets, giving about 3% precision
+const SUB_BUCKET_BITS: u32 = 5;
+const SUB_BUCKETS: u64 = 1 << SUB_BUCKET_BITS;
+// latencies are recorded in nanoseconds up to about 18 minutes, longer ones are clamped
+const MAX_BITS: u32 = 40;
+const BUCKETS: usize = ((MAX_BITS - SUB_BUCKET_BITS + 1) as u64 * SUB_BUCKETS) as usize;
+
+/// LatencyStats summarises the recorded latencies of one kind of operation.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LatencyStats {
+    /// The number of operations recorded.
+    pub count: u64,
+    /// The median latency.
+    pub p50: Duration,
+    /// The 90th percentile latency.
+    pub p90: Duration,
+    /// The 99th percentile latency.
+    pub p99: Duration,
+    /// The 99.9th percentile latency.
+    pub p999: Duration,
+    /// The longest latency recorded.
+    pub max: Duration,
+}
+
+/// OperationLatencies holds the latency percentiles of gets and sets, measured from the call including any wait for the lock.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct OperationLatencies {
+    /// The latencies of gets.
+    pub get: LatencyStats,
+    /// The latencies of sets.
+    pub set: LatencyStats,
+}
+
+/// Histogram counts latencies in log-linear buckets, in the style of an HDR histogram.
+///
+/// Values below `SUB_BUCKETS` nanoseconds have a bucket each, above that every power of two range is split into `SUB_BUCKETS` buckets. Counts are atomic so recording does not need the cache lock.
+struct Histogram {
+    counts: Box<[AtomicU64]>,
+    max: AtomicU64,
+}
+
+impl Histogram {
+    fn new() -> Self {
+        Histogram {
+            counts: (0..BUCKETS).map(|_| AtomicU64::new(0)).collect(),
+            max: AtomicU64::new(0),
+        }
+    }
+
+    fn index(nanos: u64) -> usize {
+        let nanos = nanos.min((1 << MAX_BITS) - 1);
+        if nanos < SUB_BUCKETS {
+            return nanos as usize;
+        }
+        let shift = 63 - nanos.leading_zeros() - SUB_BUCKET_BITS;
+        ((shift as u64 + 1) * SUB_BUCKETS + (nanos >> shift) - SUB_BUCKETS) as usize
+    }
+
+    /// Get the largest value which falls in the bucket.
+    fn upper_bound(index: usize) -> u64 {
+        let index = index as u64;
+        if index < SUB_BUCKETS {
+            return index;
+        }
+        let shift = index / SUB_BUCKETS - 1;
+        let mantissa = index % SUB_BUCKETS + SUB_BUCKETS;
+        ((mantissa + 1) << shift) - 1
+    }
+
+    fn record(&self, latency: Duration) {
+        let nanos = latency.as_nanos().min(u64::MAX as u128) as u64;
+        self.counts[Self::index(nanos)].fetch_add(1, Ordering::Relaxed);
+        self.max.fetch_max(nanos, Ordering::Relaxed);
+    }
+
+    fn summary(&self) -> LatencyStats {
+        let counts: Vec<u64> = self
+            .counts
+            .iter()
+            .map(|count| count.load(Ordering::Relaxed))
+            .collect();
+        let total: u64 = counts.iter().sum();
+        let max = self.max.load(Ordering::Relaxed);
+        let percentile = |quantile: f64| {
+            let rank = ((total as f64 * quantile).ceil() as u64).max(1);
+            let mut seen = 0;
+            for (index, count) in counts.iter().enumerate() {
+                seen += count;
+                if seen >= rank {
+                    return Duration::from_nanos(Self::upper_bound(index).min(max));
+                }
+            }
+            Duration::from_nanos(max)
+        };
+        if total == 0 {
+            return LatencyStats::default();
+        }
+        LatencyStats {
+            count: total,
+            p50: percentile(0.5),
+            p90: percentile(0.9),
+            p99: percentile(0.99),
+            p999: percentile(0.999),
+            max: Duration::from_nanos(max),
+        }
+    }
+}
+
+/// LatencyTracker times gets and sets when enabled, and costs a single branch when it is not.
+pub(crate) struct LatencyTracker {
+    histograms: Option<Box<(Histogram, Histogram)>>,
+}
+
+impl LatencyTracker {
+    pub(crate) fn new() -> Self {
+        LatencyTracker { histograms: None }
+    }
+
+    pub(crate) fn enable(&mut self) {
+        if self.histograms.is_none() {
+            self.histograms = Some(Box::new((Histogram::new(), Histogram::new())));
+        }
+    }
+
+    /// Get the start time of an operation, or None if tracking is disabled.
+    pub(crate) fn start(&self) -> Option<Instant> {
+        self.histograms.as_ref().map(|_| Instant::now())
+    }
+
+    pub(crate) fn record_get(&self, started: Option<Instant>) {
+        if let (Some(histograms), Some(started)) = (&self.histograms, started) {
+            histograms.0.record(started.elapsed());
+        }
+    }
+
+    pub(crate) fn record_set(&self, started: Option<Instant>) {
+        if let (Some(histograms), Some(started)) = (&self.histograms, started) {
+            histograms.1.record(started.elapsed());
+        }
+    }
+
+    /// Get the latency percentiles, or None if tracking is disabled.
+    pub(crate) fn stats(&self) -> Option<OperationLatencies> {
+        self.histograms
+            .as_ref()
+            .map(|histograms| OperationLatencies {
+                get: histograms.0.summary(),
+                set: histograms.1.summary(),
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_histogram_buckets() {
+        for nanos in [0, 1, 31, 32, 33, 1_000, 123_456, 1 << 39] {
+            let index = Histogram::index(nanos);
+            assert!(Histogram::upper_bound(index) >= nanos);
+            assert!(index == 0 || Histogram::upper_bound(index - 1) < nanos);
+        }
+        assert_eq!(Histogram::index(u64::MAX), BUCKETS - 1);
+    }
+
+    #[test]
+    fn test_histogram_percentiles() {
+        let histogram = Histogram::new();
+        for micros in 1..=1000 {
+            histogram.record(Duration::from_micros(micros));
+        }
+        let stats = histogram.summary();
+        assert_eq!(stats.count, 1000);
+        let close = |actual: Duration, expected: u64| {
+            let expected = Duration::from_micros(expected);
+            actual >= expected && actual <= expected + expected / 16
+        };
+        assert!(close(stats.p50, 500), "p50 {:?}", stats.p50);
+        assert!(close(stats.p99, 990), "p99 {:?}", stats.p99);
+        assert_eq!(stats.max, Duration::from_micros(1000));
+    }
+}
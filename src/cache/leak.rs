@@ -0,0 +1,171 @@
+use crate::sync::Mutex;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::VecDeque;
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Weak};
+use std::time::{Duration, Instant};
+
+use crate::cache::listener::{EvictionListener, RemovalCause, RemovalEvent};
+
+/// LeakReport describes a value which is still referenced outside the cache after it was evicted or expired.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct LeakReport {
+    /// The hash of the item's key, keys themselves are not kept.
+    pub key_hash: u64,
+    /// How long ago the item left the cache.
+    pub age: Duration,
+    /// The number of references to the value still alive.
+    pub strong_count: usize,
+    /// Why the item left the cache.
+    pub cause: RemovalCause,
+}
+
+/// A value which left the cache, tracked without keeping it alive.
+struct Tracked<V> {
+    value: Weak<V>,
+    key_hash: u64,
+    removed_at: Instant,
+    cause: RemovalCause,
+}
+
+/// LeakDetector is an eviction listener which remembers evicted and expired values without keeping them alive, and reports those still referenced long after they left the cache.
+///
+/// It is a debugging aid for finding code which holds on to values the cache has let go of. Items removed with `remove` or by clearing the cache are handed back to the caller, so they are not tracked. At most `max_tracked` values are tracked, once values are no longer referenced they stop being tracked, and past the limit the oldest are forgotten.
+///
+/// Example:
+/// ```
+/// use arcache::{Cache, LRUCache, LeakDetector};
+/// use std::time::Duration;
+///
+/// let detector = LeakDetector::new(1000);
+/// let cache = LRUCache::<&str, String>::new(1).with_eviction_listener(detector.clone());
+///
+/// cache.set("a", "a".to_string());
+/// let held = cache.get(&"a").unwrap();
+/// cache.set("b", "b".to_string());
+///
+/// // "a" was evicted but is still referenced by `held`
+/// assert_eq!(detector.report(Duration::ZERO).len(), 1);
+/// drop(held);
+/// assert!(detector.report(Duration::ZERO).is_empty());
+/// ```
+pub struct LeakDetector<V> {
+    tracked: Arc<Mutex<VecDeque<Tracked<V>>>>,
+    max_tracked: usize,
+}
+
+impl<V> LeakDetector<V> {
+    /// Create a new LeakDetector which tracks up to `max_tracked` values at once.
+    pub fn new(max_tracked: usize) -> Self {
+        LeakDetector {
+            tracked: Arc::new(Mutex::new(VecDeque::new())),
+            max_tracked,
+        }
+    }
+
+    /// Get the values which left the cache at least `older_than` ago and are still referenced, oldest first.
+    pub fn report(&self, older_than: Duration) -> Vec<LeakReport> {
+        let mut tracked = self.tracked.lock().unwrap();
+        tracked.retain(|entry| entry.value.strong_count() > 0);
+        let now = Instant::now();
+        tracked
+            .iter()
+            .map(|entry| LeakReport {
+                key_hash: entry.key_hash,
+                age: now.saturating_duration_since(entry.removed_at),
+                strong_count: entry.value.strong_count(),
+                cause: entry.cause,
+            })
+            .filter(|report| report.age >= older_than && report.strong_count > 0)
+            .collect()
+    }
+
+    /// Get the number of values currently tracked, including any which have been dropped since the last report.
+    pub fn tracked(&self) -> usize {
+        self.tracked.lock().unwrap().len()
+    }
+}
+
+impl<V> Clone for LeakDetector<V> {
+    /// Get another handle to the same detector, so it can be registered with a cache and still be read.
+    fn clone(&self) -> Self {
+        LeakDetector {
+            tracked: self.tracked.clone(),
+            max_tracked: self.max_tracked,
+        }
+    }
+}
+
+impl<K: Hash, V: Send + Sync> EvictionListener<K, V> for LeakDetector<V> {
+    /// Track evicted and expired values.
+    fn on_removal(&self, event: &RemovalEvent<K, V>) {
+        if !matches!(event.cause, RemovalCause::Evicted | RemovalCause::Expired) {
+            return;
+        }
+        let mut hasher = DefaultHasher::new();
+        event.key.hash(&mut hasher);
+        let mut tracked = self.tracked.lock().unwrap();
+        if tracked.len() >= self.max_tracked {
+            tracked.retain(|entry| entry.value.strong_count() > 0);
+        }
+        while !tracked.is_empty() && tracked.len() >= self.max_tracked {
+            tracked.pop_front();
+        }
+        if self.max_tracked > 0 {
+            tracked.push_back(Tracked {
+                value: Arc::downgrade(&event.value),
+                key_hash: hasher.finish(),
+                removed_at: Instant::now(),
+                cause: event.cause,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Cache, LRUCache};
+
+    #[test]
+    fn test_leak_detector() {
+        let detector = LeakDetector::new(10);
+        let cache = LRUCache::new(1).with_eviction_listener(detector.clone());
+        cache.set(1, 1);
+        let held = cache.get(&1).unwrap();
+        cache.set(2, 2);
+        cache.set(3, 3);
+        // 2 was evicted but nothing holds it
+        assert_eq!(detector.tracked(), 2);
+        let reports = detector.report(Duration::ZERO);
+        assert_eq!(reports.len(), 1);
+        assert_eq!(
+            (reports[0].strong_count, reports[0].cause),
+            (1, RemovalCause::Evicted)
+        );
+        assert!(detector.report(Duration::from_secs(60)).is_empty());
+        // removed values are handed back, so they are not tracked
+        let removed = cache.remove(&3);
+        assert_eq!(detector.tracked(), 1);
+        drop((held, removed));
+        assert!(detector.report(Duration::ZERO).is_empty());
+        assert_eq!(detector.tracked(), 0);
+    }
+
+    #[test]
+    fn test_leak_detector_is_bounded() {
+        let detector = LeakDetector::new(2);
+        let cache = LRUCache::new(1).with_eviction_listener(detector.clone());
+        let mut held = Vec::new();
+        for key in 0..5 {
+            cache.set(key, key);
+            held.push(cache.get(&key).unwrap());
+        }
+        let reports = detector.report(Duration::ZERO);
+        assert_eq!(reports.len(), 2);
+        let mut hasher = DefaultHasher::new();
+        3.hash(&mut hasher);
+        assert_eq!(reports[1].key_hash, hasher.finish());
+    }
+}
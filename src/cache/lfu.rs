@@ -1,17 +1,163 @@
-use crate::cache::{Cache, CacheStats};
+use crate::cache::latency::{LatencyTracker, OperationLatencies};
+use crate::cache::listener::{
+    AccessListener, AccessListeners, CallbackPanics, EntrySource, EvictionListener, RemovalCause,
+};
+use crate::cache::metadata::{EntryInfo, EntryMetadata};
+use crate::cache::policy_core::{policy_methods, PolicyCore};
+use crate::cache::{
+    shed_count, split_capacity, Cache, CacheStats, DetailedStats, FullBehavior, GetOutcome,
+    IterationOrder, TryCache, VersionedCache,
+};
+use crate::clock::Clock;
+use crate::error::CacheError;
+use crate::sync::Mutex;
 use linked_hash_set::LinkedHashSet;
-use std::collections::HashMap;
+use rand::Rng;
+use std::any::Any;
+use std::cmp::Reverse;
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, HashMap};
 use std::hash::Hash;
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// LFUTieBreaking decides which item is evicted when several items share the lowest frequency.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LFUTieBreaking {
+    /// Evict the item which was least recently accessed.
+    #[default]
+    Lru,
+    /// Evict the item which was inserted first.
+    Fifo,
+    /// Evict a random item.
+    Random,
+}
+
+/// LFUOverwrite decides what happens to an item's frequency when an existing key is set again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LFUOverwrite {
+    /// Count the overwrite as an access, increasing the frequency.
+    #[default]
+    Increment,
+    /// Keep the frequency unchanged, so only gets count towards popularity.
+    Preserve,
+    /// Start the frequency again from 1, as if the item was new.
+    Reset,
+}
+
+/// The value and frequency bookkeeping of an item in the LFUCache, kept together so they cannot disagree.
+struct LFUEntry<V> {
+    value: Arc<V>,
+    freq: u64,
+    insertion: u64,
+}
+
+/// The keys which share a frequency, kept so the tie breaking strategy finds its victim without a scan.
+enum Bucket<K> {
+    /// Keys in the order they reached this frequency, the front was the least recently accessed.
+    Arrival(LinkedHashSet<K>),
+    /// Keys by the order they were inserted into the cache.
+    Insertion(BTreeMap<u64, K>),
+    /// Keys in no particular order with their positions, so a random key is picked by index and removed by swapping in the last key.
+    Indexed {
+        keys: Vec<K>,
+        positions: HashMap<K, usize>,
+    },
+}
+
+impl<K: Eq + Hash + Clone> Bucket<K> {
+    fn new(tie_breaking: LFUTieBreaking) -> Self {
+        match tie_breaking {
+            LFUTieBreaking::Lru => Bucket::Arrival(LinkedHashSet::new()),
+            LFUTieBreaking::Fifo => Bucket::Insertion(BTreeMap::new()),
+            LFUTieBreaking::Random => Bucket::Indexed {
+                keys: Vec::new(),
+                positions: HashMap::new(),
+            },
+        }
+    }
+
+    /// Add a key, `insertion` is the order it was inserted into the cache in.
+    fn insert(&mut self, key: K, insertion: u64) {
+        match self {
+            Bucket::Arrival(keys) => {
+                keys.insert(key);
+            }
+            Bucket::Insertion(keys) => {
+                keys.insert(insertion, key);
+            }
+            Bucket::Indexed { keys, positions } => {
+                if let Entry::Vacant(position) = positions.entry(key.clone()) {
+                    position.insert(keys.len());
+                    keys.push(key);
+                }
+            }
+        }
+    }
+
+    /// Take a key out, `insertion` is the order it was inserted into the cache in.
+    fn remove(&mut self, key: &K, insertion: u64) {
+        match self {
+            Bucket::Arrival(keys) => {
+                keys.remove(key);
+            }
+            Bucket::Insertion(keys) => {
+                keys.remove(&insertion);
+            }
+            Bucket::Indexed { keys, positions } => {
+                if let Some(position) = positions.remove(key) {
+                    keys.swap_remove(position);
+                    if let Some(moved) = keys.get(position) {
+                        positions.insert(moved.clone(), position);
+                    }
+                }
+            }
+        }
+    }
+
+    fn len(&self) -> usize {
+        match self {
+            Bucket::Arrival(keys) => keys.len(),
+            Bucket::Insertion(keys) => keys.len(),
+            Bucket::Indexed { keys, .. } => keys.len(),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Pick the key the tie breaking strategy evicts first.
+    fn victim(&self) -> Option<&K> {
+        match self {
+            Bucket::Arrival(keys) => keys.front(),
+            Bucket::Insertion(keys) => keys.values().next(),
+            Bucket::Indexed { keys, .. } if keys.is_empty() => None,
+            Bucket::Indexed { keys, .. } => keys.get(rand::rng().random_range(0..keys.len())),
+        }
+    }
+
+    /// Iterate the keys in the order they would be evicted, random buckets in no particular order.
+    fn iter(&self) -> Box<dyn DoubleEndedIterator<Item = &K> + '_> {
+        match self {
+            Bucket::Arrival(keys) => Box::new(keys.iter()),
+            Bucket::Insertion(keys) => Box::new(keys.values()),
+            Bucket::Indexed { keys, .. } => Box::new(keys.iter()),
+        }
+    }
+}
 
 /// The inner data structure for the LFUCache.
 struct LFUCacheInner<K: Eq + Hash + Clone + Sync + Send, V: Send + Sync> {
     capacity: u64,
-    key_value_map: HashMap<K, Arc<V>>,
-    counter: HashMap<K, u64>,
-    freq_map: HashMap<u64, LinkedHashSet<K>>,
-    hits: u64,
-    misses: u64,
+    entries: HashMap<K, LFUEntry<V>>,
+    freq_map: HashMap<u64, Bucket<K>>,
+    next_insertion: u64,
+    tie_breaking: LFUTieBreaking,
+    overwrite: LFUOverwrite,
+    decay_interval: Option<u64>,
+    accesses_since_decay: u64,
+    core: PolicyCore<K, V>,
     min_freq: u64,
 }
 
@@ -20,44 +166,252 @@ impl<K: Eq + Hash + Clone + Sync + Send, V: Send + Sync> LFUCacheInner<K, V> {
     fn new(capacity: u64) -> Self {
         LFUCacheInner {
             capacity,
-            key_value_map: HashMap::with_capacity(capacity as usize),
-            counter: HashMap::with_capacity(capacity as usize),
+            entries: HashMap::with_capacity(capacity as usize),
             freq_map: HashMap::new(),
-            hits: 0,
-            misses: 0,
+            next_insertion: 0,
+            tie_breaking: LFUTieBreaking::default(),
+            overwrite: LFUOverwrite::default(),
+            decay_interval: None,
+            accesses_since_decay: 0,
+            core: PolicyCore::new(),
             min_freq: 0,
         }
     }
 
+    /// Check if a key is held.
+    fn holds(&self, key: &K) -> bool {
+        self.entries.contains_key(key)
+    }
+
+    /// Put the key in the bucket for `freq`, creating the bucket if needed.
+    fn link(&mut self, key: &K, freq: u64, insertion: u64) {
+        let tie_breaking = self.tie_breaking;
+        self.freq_map
+            .entry(freq)
+            .or_insert_with(|| Bucket::new(tie_breaking))
+            .insert(key.clone(), insertion);
+    }
+
+    /// Take the key out of the bucket for `freq`, returning true if that emptied and dropped the bucket.
+    fn unlink(&mut self, key: &K, freq: u64, insertion: u64) -> bool {
+        let Some(bucket) = self.freq_map.get_mut(&freq) else {
+            return false;
+        };
+        bucket.remove(key, insertion);
+        if bucket.is_empty() {
+            self.freq_map.remove(&freq);
+            return true;
+        }
+        false
+    }
+
+    /// Find the lowest frequency with a bucket, or 0 if the cache is empty.
+    fn lowest_freq(&self) -> u64 {
+        self.freq_map.keys().copied().min().unwrap_or(0)
+    }
+
+    /// Move an item to a new frequency, keeping its entry, its bucket and the minimum frequency in step.
+    fn set_freq(&mut self, key: &K, freq: u64) {
+        let Some(entry) = self.entries.get_mut(key) else {
+            return;
+        };
+        let old_freq = std::mem::replace(&mut entry.freq, freq);
+        let insertion = entry.insertion;
+        let emptied = self.unlink(key, old_freq, insertion);
+        self.link(key, freq, insertion);
+        if freq < self.min_freq || (emptied && old_freq == self.min_freq && freq == old_freq + 1) {
+            self.min_freq = freq;
+        } else if emptied && old_freq == self.min_freq {
+            self.min_freq = self.lowest_freq();
+        }
+    }
+
     /// Increase the frequency of the given key.
     fn increase_freq(&mut self, key: &K) {
-        let freq = *self.counter.get(key).unwrap_or(&0);
-        *self.counter.entry(key.clone()).or_default() += 1;
-        self.freq_map.entry(freq).or_default().remove(key);
+        if let Some(freq) = self.entries.get(key).map(|entry| entry.freq) {
+            self.set_freq(key, freq + 1);
+        }
+    }
+
+    /// Move the given key back to a frequency of 1.
+    fn reset_freq(&mut self, key: &K) {
+        self.set_freq(key, 1);
+    }
 
-        if !self.freq_map.contains_key(&freq) {
-            if freq == self.min_freq {
-                self.min_freq += 1;
+    /// Count an access towards the decay interval, halving all frequencies when it is reached.
+    fn record_access(&mut self) {
+        if let Some(interval) = self.decay_interval {
+            self.accesses_since_decay += 1;
+            if self.accesses_since_decay >= interval {
+                self.decay();
             }
-            self.freq_map.remove(&freq);
         }
-        self.freq_map
-            .entry(freq + 1)
-            .or_default()
-            .insert(key.clone());
     }
 
-    /// Remove the least frequent item from the cache.
-    fn remove_least_freq(&mut self) {
-        if let Some(bucket) = self.freq_map.get_mut(&self.min_freq) {
-            if let Some(key) = bucket.pop_front() {
-                self.key_value_map.remove(&key);
-                self.counter.remove(&key);
+    /// Halve the frequency of every item (to a minimum of 1), so that old popularity fades over time.
+    fn decay(&mut self) {
+        self.accesses_since_decay = 0;
+        let mut freqs: Vec<u64> = self.freq_map.keys().copied().collect();
+        freqs.sort_unstable();
+
+        let mut freq_map: HashMap<u64, Bucket<K>> = HashMap::new();
+        for freq in freqs {
+            let new_freq = (freq / 2).max(1);
+            if let Some(bucket) = self.freq_map.remove(&freq) {
+                for key in bucket.iter() {
+                    if let Some(entry) = self.entries.get_mut(key) {
+                        entry.freq = new_freq;
+                        freq_map
+                            .entry(new_freq)
+                            .or_insert_with(|| Bucket::new(self.tie_breaking))
+                            .insert(key.clone(), entry.insertion);
+                    }
+                }
+            }
+        }
+        self.freq_map = freq_map;
+        self.min_freq = self.lowest_freq();
+    }
+
+    /// Switch the tie breaking strategy, rebuilding the buckets in the shape it needs.
+    fn set_tie_breaking(&mut self, tie_breaking: LFUTieBreaking) {
+        self.tie_breaking = tie_breaking;
+        for bucket in self.freq_map.values_mut() {
+            let mut rebuilt = Bucket::new(tie_breaking);
+            for key in bucket.iter() {
+                if let Some(entry) = self.entries.get(key) {
+                    rebuilt.insert(key.clone(), entry.insertion);
+                }
+            }
+            *bucket = rebuilt;
+        }
+    }
+
+    /// Count the items in each power of two range of frequency, index `i` counts frequencies from `2^i` to `2^(i+1) - 1`.
+    fn frequency_histogram(&self) -> Vec<u64> {
+        let mut histogram = Vec::new();
+        for (freq, bucket) in &self.freq_map {
+            let index = freq.max(&1).ilog2() as usize;
+            if histogram.len() <= index {
+                histogram.resize(index + 1, 0);
+            }
+            histogram[index] += bucket.len() as u64;
+        }
+        histogram
+    }
+
+    /// Remove the least frequent item with the given priority.
+    fn remove_lowest_priority(&mut self, lowest: u32) -> Option<(K, Arc<V>)> {
+        let mut freqs: Vec<u64> = self.freq_map.keys().copied().collect();
+        freqs.sort_unstable();
+        let key = freqs.into_iter().find_map(|freq| {
+            self.freq_map[&freq]
+                .iter()
+                .find(|key| self.core.priorities.get(key) == lowest)
+                .cloned()
+        })?;
+        let lifetime = self.core.metadata.lifetime(&key, self.core.clock.now());
+        let value = self.remove_entry(&key)?;
+        self.core
+            .listeners
+            .notify(&key, &value, RemovalCause::Evicted);
+        self.core.stats.record_eviction(lifetime);
+        Some((key, value))
+    }
+
+    /// Remove an item from every structure, recomputing the minimum frequency if its bucket is emptied.
+    fn remove_entry(&mut self, key: &K) -> Option<Arc<V>> {
+        let entry = self.entries.remove(key)?;
+        if self.unlink(key, entry.freq, entry.insertion) && entry.freq == self.min_freq {
+            self.min_freq = self.lowest_freq();
+        }
+        self.core.forget(key);
+        Some(entry.value)
+    }
+
+    /// Remove every item which has passed its deadline.
+    fn purge_expired(&mut self) {
+        let now = self.core.clock.now();
+        while let Some(key) = self.core.expiries.pop_expired(now) {
+            if let Some(value) = self.remove_entry(&key) {
+                self.core
+                    .listeners
+                    .notify(&key, &value, RemovalCause::Expired);
+                self.core.stats.record_expiration();
+            }
+        }
+    }
+
+    /// Remove every item and reset the frequency bookkeeping, notifying listeners and returning the removed items.
+    fn clear_entries(&mut self) -> Vec<(K, Arc<V>)> {
+        self.freq_map.clear();
+        self.core.clear();
+        self.min_freq = 0;
+        self.next_insertion = 0;
+        self.accesses_since_decay = 0;
+        let cleared: Vec<(K, Arc<V>)> = self
+            .entries
+            .drain()
+            .map(|(key, entry)| (key, entry.value))
+            .collect();
+        for (key, value) in &cleared {
+            self.core
+                .listeners
+                .notify(key, value, RemovalCause::Cleared);
+        }
+        cleared
+    }
+
+    /// Remove the least frequent item from the cache, ties are broken according to the tie breaking strategy.
+    ///
+    /// If priorities have been assigned the least frequent item with the lowest priority is removed instead.
+    fn remove_least_freq(&mut self) -> Option<(K, Arc<V>)> {
+        if let Some(lowest) = self.core.priorities.lowest(self.entries.len()) {
+            return self.remove_lowest_priority(lowest);
+        }
+        let key = self.freq_map.get(&self.min_freq)?.victim()?.clone();
+        let lifetime = self.core.metadata.lifetime(&key, self.core.clock.now());
+        let value = self.remove_entry(&key)?;
+        self.core
+            .listeners
+            .notify(&key, &value, RemovalCause::Evicted);
+        self.core.stats.record_eviction(lifetime);
+        Some((key, value))
+    }
+
+    /// Evict items per the policy until at most `len` are left.
+    fn evict_to(&mut self, len: usize) -> Vec<(K, Arc<V>)> {
+        let mut evicted = Vec::new();
+        while self.entries.len() > len {
+            match self.remove_least_freq() {
+                Some(item) => evicted.push(item),
+                None => break,
             }
-            if bucket.is_empty() {
-                self.freq_map.remove(&self.min_freq);
+        }
+        evicted
+    }
+
+    /// Panic if the entries, the frequency buckets and the minimum frequency disagree.
+    #[cfg(test)]
+    fn check_invariants(&self) {
+        let bucketed: usize = self.freq_map.values().map(|bucket| bucket.len()).sum();
+        assert_eq!(
+            bucketed,
+            self.entries.len(),
+            "bucket sizes do not match entries"
+        );
+        for (freq, bucket) in &self.freq_map {
+            assert!(!bucket.is_empty(), "empty bucket for frequency {}", freq);
+            for key in bucket.iter() {
+                let entry = self.entries.get(key).expect("bucketed key has no entry");
+                assert_eq!(entry.freq, *freq, "entry is in the wrong bucket");
             }
         }
+        assert_eq!(
+            self.min_freq,
+            self.lowest_freq(),
+            "minimum frequency is stale"
+        );
     }
 }
 
@@ -84,111 +438,541 @@ impl<K: Eq + Hash + Clone + Sync + Send, V: Send + Sync> LFUCacheInner<K, V> {
 /// println!("{:?}", cache.stats());
 /// ```
 pub struct LFUCache<K: Eq + Hash + Clone + Sync + Send, V: Send + Sync> {
-    inner: Mutex<LFUCacheInner<K, V>>,
+    inner: Arc<Mutex<LFUCacheInner<K, V>>>,
+    access_listeners: Arc<AccessListeners<K>>,
+    latency: Arc<LatencyTracker>,
 }
 
 impl<K: Eq + Hash + Clone + Sync + Send, V: Send + Sync> LFUCache<K, V> {
     /// Create a new LFUCache with the given capacity.
     pub fn new(capacity: u64) -> Self {
         LFUCache {
-            inner: Mutex::new(LFUCacheInner::new(capacity)),
+            inner: Arc::new(Mutex::new(LFUCacheInner::new(capacity))),
+            access_listeners: Arc::new(AccessListeners::new()),
+            latency: Arc::new(LatencyTracker::new()),
         }
     }
-}
 
-impl<K: Eq + Hash + Clone + Sync + Send, V: Send + Sync> Cache<K, V> for LFUCache<K, V> {
-    /// Get a value from the cache.
-    fn get(&self, key: &K) -> Option<Arc<V>> {
+    /// Set how ties between items with the same lowest frequency are broken, the default is `LFUTieBreaking::Lru`.
+    pub fn with_tie_breaking(self, tie_breaking: LFUTieBreaking) -> Self {
+        self.inner.lock().unwrap().set_tie_breaking(tie_breaking);
+        self
+    }
+
+    /// Set what happens to an item's frequency when its key is set again, the default is `LFUOverwrite::Increment`.
+    pub fn with_overwrite(self, overwrite: LFUOverwrite) -> Self {
+        self.inner.lock().unwrap().overwrite = overwrite;
+        self
+    }
+
+    /// Halve every item's frequency after every `interval` gets and sets, so that items which were popular long ago do not stay in the cache forever.
+    pub fn with_decay(self, interval: u64) -> Self {
+        self.inner.lock().unwrap().decay_interval = Some(interval.max(1));
+        self
+    }
+
+    /// Halve every item's frequency now.
+    pub fn decay(&self) {
         let mut inner = self.inner.lock().unwrap();
-        let result = inner.key_value_map.get(key).cloned();
+        inner.decay();
+    }
 
-        if result.is_some() {
-            inner.hits += 1;
-            inner.increase_freq(key);
-        } else {
-            inner.misses += 1;
+    /// Get the access frequency of an item, without counting it as an access.
+    ///
+    /// Returns None if the key is not in the cache or has expired.
+    pub fn frequency(&self, key: &K) -> Option<u64> {
+        let inner = self.inner.lock().unwrap();
+        if inner.core.expiries.is_expired(key, inner.core.clock.now()) {
+            return None;
         }
-        result
+        inner.entries.get(key).map(|entry| entry.freq)
     }
 
-    /// Set a value in the cache.
-    fn set(&self, key: K, value: V) -> Option<Arc<V>> {
+    /// Get the access frequency of every unexpired item, most frequent first.
+    pub fn frequencies(&self) -> Vec<(K, u64)> {
+        let inner = self.inner.lock().unwrap();
+        let now = inner.core.clock.now();
+        let mut frequencies: Vec<(K, u64)> = inner
+            .entries
+            .iter()
+            .filter(|(key, _)| !inner.core.expiries.is_expired(key, now))
+            .map(|(key, entry)| (key.clone(), entry.freq))
+            .collect();
+        frequencies.sort_by_key(|(_, freq)| Reverse(*freq));
+        frequencies
+    }
+
+    policy_methods!(lock, lock);
+
+    /// Set a value in the cache with a priority, when the cache is full lower priority items are evicted before higher priority ones.
+    ///
+    /// Within a priority the least frequently used item is evicted first. New items set with `set` have the lowest priority, `DEFAULT_PRIORITY`, and setting a key again without a priority keeps the one it has.
+    pub fn set_with_priority(&self, key: K, value: V, priority: u32) -> Option<Arc<V>> {
+        self.insert(
+            key,
+            Arc::new(value),
+            Some(priority),
+            None,
+            None,
+            None,
+            EntrySource::Inserted,
+        )
+        .unwrap_or(None)
+    }
+
+    /// Set a value in the cache which expires after the given time-to-live, overriding the cache's default TTL.
+    ///
+    /// Expired items are never returned and are removed before any live item is evicted.
+    pub fn set_with_ttl(&self, key: K, value: V, ttl: Duration) -> Option<Arc<V>> {
+        self.insert(
+            key,
+            Arc::new(value),
+            None,
+            Some(ttl),
+            None,
+            None,
+            EntrySource::Inserted,
+        )
+        .unwrap_or(None)
+    }
+
+    /// Set a value in the cache with a metadata value attached, which can be read back with `entry_info`.
+    ///
+    /// Metadata is dropped when the item leaves the cache or is set again without metadata.
+    pub fn set_with_metadata(
+        &self,
+        key: K,
+        value: V,
+        metadata: impl Any + Send + Sync,
+    ) -> Option<Arc<V>> {
+        self.insert(
+            key,
+            Arc::new(value),
+            None,
+            None,
+            Some(Arc::new(metadata)),
+            None,
+            EntrySource::Inserted,
+        )
+        .unwrap_or(None)
+    }
+
+    /// Move every item matching `predicate` into a new cache, whose capacity is in proportion to the share of items moved.
+    ///
+    /// Moved items keep their priority and remaining TTL. Frequencies start afresh in the new cache, which keeps this cache's tie breaking, overwrite and decay settings. Listeners on this cache are notified with `RemovalCause::Removed`, they are not carried over to the new cache.
+    pub fn split_off<F: Fn(&K, &V) -> bool>(&self, predicate: F) -> Self {
         let mut inner = self.inner.lock().unwrap();
-        let arc_value = Arc::new(value);
-        let existing_value = inner.key_value_map.get(&key).cloned();
+        inner.purge_expired();
+        let keys: Vec<K> = inner
+            .entries
+            .iter()
+            .filter(|(key, entry)| predicate(key, &entry.value))
+            .map(|(key, _)| key.clone())
+            .collect();
+        let capacity = split_capacity(inner.capacity, keys.len(), inner.entries.len());
+        let split = Self::new(capacity);
+        split.inner.lock().unwrap().core.clock = inner.core.clock.clone();
+        {
+            let mut split_inner = split.inner.lock().unwrap();
+            split_inner.set_tie_breaking(inner.tie_breaking);
+            split_inner.overwrite = inner.overwrite;
+            split_inner.decay_interval = inner.decay_interval;
+            if let Some(ttl) = inner.core.expiries.default_ttl() {
+                split_inner.core.expiries.set_default_ttl(ttl);
+            }
+        }
+        let now = inner.core.clock.now();
+        for key in keys {
+            let priority = inner.core.priorities.get(&key);
+            let ttl = inner.core.expiries.remaining(&key, now);
+            let metadata = inner.core.metadata.get(&key);
+            let source = inner.core.listeners.source(&key);
+            if let Some(value) = inner.remove_entry(&key) {
+                inner
+                    .core
+                    .listeners
+                    .notify(&key, &value, RemovalCause::Removed);
+                let _ = split.insert(key, value, Some(priority), ttl, metadata, None, source);
+            }
+        }
+        split
+    }
+
+    /// Insert a value with a priority and an optional TTL, evicting an item if the cache is full.
+    #[allow(clippy::too_many_arguments)]
+    fn insert(
+        &self,
+        key: K,
+        value: Arc<V>,
+        priority: Option<u32>,
+        ttl: Option<Duration>,
+        metadata: Option<EntryMetadata>,
+        expected_version: Option<u64>,
+        source: EntrySource,
+    ) -> Result<Option<Arc<V>>, CacheError> {
+        let mut inner = self.inner.lock().map_err(|_| CacheError::Poisoned)?;
+        if inner.capacity == 0 {
+            return Err(CacheError::CapacityZero);
+        }
+        inner.purge_expired();
+        inner.core.metadata.check_version(&key, expected_version)?;
+        let existing_value = inner.entries.get(&key).map(|entry| entry.value.clone());
+        if existing_value.is_none()
+            && !inner.core.full_behavior.evicts()
+            && inner.entries.len() as u64 >= inner.capacity
+        {
+            inner.core.listeners.reject(&key);
+            return inner.core.full_behavior.rejection();
+        }
+        inner.record_access();
 
-        if existing_value.is_some() {
-            inner.key_value_map.insert(key.clone(), arc_value);
-            inner.increase_freq(&key);
+        if let Some(entry) = inner.entries.get_mut(&key) {
+            entry.value = value;
+            match inner.overwrite {
+                LFUOverwrite::Increment => inner.increase_freq(&key),
+                LFUOverwrite::Preserve => {}
+                LFUOverwrite::Reset => inner.reset_freq(&key),
+            }
         } else {
-            if inner.key_value_map.len() as u64 >= inner.capacity {
+            if inner.entries.len() as u64 >= inner.capacity {
                 inner.remove_least_freq();
             }
-            inner.key_value_map.insert(key.clone(), arc_value);
-            *inner.counter.entry(key.clone()).or_default() += 1;
-            inner.freq_map.entry(1).or_default().insert(key);
+            let insertion = inner.next_insertion;
+            inner.next_insertion += 1;
+            inner.entries.insert(
+                key.clone(),
+                LFUEntry {
+                    value,
+                    freq: 1,
+                    insertion,
+                },
+            );
+            inner.link(&key, 1, insertion);
             inner.min_freq = 1;
         }
-        existing_value
+        inner.core.stats.record_set(existing_value.is_none());
+        inner.core.track(&key, priority, ttl, metadata, source);
+        Ok(existing_value)
+    }
+}
+
+impl<K: Eq + Hash + Clone + Sync + Send, V: Send + Sync> Clone for LFUCache<K, V> {
+    /// Get another handle to the same cache, sharing its items, settings and statistics.
+    fn clone(&self) -> Self {
+        LFUCache {
+            inner: self.inner.clone(),
+            access_listeners: self.access_listeners.clone(),
+            latency: self.latency.clone(),
+        }
+    }
+}
+
+impl<K: Eq + Hash + Clone + Sync + Send, V: Send + Sync> Cache<K, V> for LFUCache<K, V> {
+    /// Get a value from the cache.
+    fn get(&self, key: &K) -> Option<Arc<V>> {
+        let started = self.latency.start();
+        let result = {
+            let mut inner = self.inner.lock().unwrap();
+            inner.purge_expired();
+            let result = inner.entries.get(key).map(|entry| entry.value.clone());
+
+            if result.is_some() {
+                inner.core.stats.record_hit();
+                inner.increase_freq(key);
+                let now = inner.core.clock.now();
+                inner.core.metadata.touch(key, now);
+            } else {
+                inner.core.stats.record_miss();
+            }
+            inner.record_access();
+            result
+        };
+        self.latency.record_get(started);
+        self.access_listeners.notify(key, &result);
+        result
+    }
+
+    /// Get a value from the cache, with whether it was a hit, or a miss because the key expired, was rejected or is absent.
+    fn get_with_outcome(&self, key: &K) -> (Option<Arc<V>>, GetOutcome) {
+        let result = self.get(key);
+        let outcome = match result {
+            Some(_) => GetOutcome::Hit,
+            None => self.inner.lock().unwrap().core.listeners.miss_outcome(key),
+        };
+        (result, outcome)
+    }
+
+    /// Set a value in the cache.
+    fn set(&self, key: K, value: V) -> Option<Arc<V>> {
+        let started = self.latency.start();
+        let result = self
+            .insert(
+                key,
+                Arc::new(value),
+                None,
+                None,
+                None,
+                None,
+                EntrySource::Inserted,
+            )
+            .unwrap_or(None);
+        self.latency.record_set(started);
+        result
+    }
+
+    /// Set a value in the cache, recording how it came to be in the cache for `entry_info` and eviction listeners.
+    fn set_with_source(&self, key: K, value: V, source: EntrySource) -> Option<Arc<V>> {
+        let started = self.latency.start();
+        let result = self
+            .insert(key, Arc::new(value), None, None, None, None, source)
+            .unwrap_or(None);
+        self.latency.record_set(started);
+        result
     }
 
     /// Remove a value from the cache.
     fn remove(&self, key: &K) -> Option<Arc<V>> {
         let mut inner = self.inner.lock().unwrap();
 
-        let result = inner.key_value_map.remove(key);
-        // if let Some(_) = result {
-        //     inner.counter.remove(key);
-        //     inner.freq_map.get_mut(&1).map(|bucket| bucket.remove(key));
-        // }
-
-        if result.is_some() {
-            inner.counter.remove(key);
-            let freq = *inner.counter.get(key).unwrap_or(&0);
-            if let Some(bucket) = inner.freq_map.get_mut(&freq) {
-                bucket.remove(key);
-                if bucket.is_empty() {
-                    inner.freq_map.remove(&1);
-                    inner.min_freq = 0;
-                }
-            }
+        let result = inner.remove_entry(key);
+        if let Some(value) = &result {
+            inner
+                .core
+                .listeners
+                .notify(key, value, RemovalCause::Removed);
         }
         result
     }
 
+    /// Check if a key is in the cache and has not expired, without counting a hit or miss or updating its position.
+    fn contains_key(&self, key: &K) -> bool {
+        let inner = self.inner.lock().unwrap();
+        inner.entries.contains_key(key)
+            && !inner.core.expiries.is_expired(key, inner.core.clock.now())
+    }
+
+    /// Get the time left before an item expires, or None if it never expires or is not in the cache.
+    fn ttl_remaining(&self, key: &K) -> Option<Duration> {
+        self.entry_info(key)?.ttl_remaining
+    }
+
     /// Clear the cache.
     fn clear(&self) {
         let mut inner = self.inner.lock().unwrap();
-        inner.key_value_map.clear();
-        inner.freq_map.clear();
-        inner.counter.clear();
+        inner.clear_entries();
+    }
+
+    /// Clear the cache, returning every item which was removed.
+    fn clear_returning(&self) -> Vec<(K, Arc<V>)> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.clear_entries()
+    }
+
+    /// Copy the unexpired items into a HashMap in one locked pass.
+    fn to_map(&self) -> HashMap<K, Arc<V>> {
+        let inner = self.inner.lock().unwrap();
+        let now = inner.core.clock.now();
+        inner
+            .entries
+            .iter()
+            .filter(|(key, _)| !inner.core.expiries.is_expired(key, now))
+            .map(|(key, entry)| (key.clone(), entry.value.clone()))
+            .collect()
+    }
+
+    /// Get up to `limit` unexpired items, ordered with the items the policy would evict last first. Higher priority items come before lower ones, and otherwise most frequently used first, ties in the reverse of the order the tie breaking strategy would evict them.
+    fn hottest(&self, limit: usize) -> Vec<(K, Arc<V>)> {
+        let inner = self.inner.lock().unwrap();
+        let now = inner.core.clock.now();
+        let mut freqs: Vec<u64> = inner.freq_map.keys().copied().collect();
+        freqs.sort_unstable_by(|a, b| b.cmp(a));
+        let mut items: Vec<(K, Arc<V>)> = freqs
+            .iter()
+            .flat_map(|freq| inner.freq_map[freq].iter().rev())
+            .filter_map(|key| inner.entries.get_key_value(key))
+            .filter(|(key, _)| !inner.core.expiries.is_expired(key, now))
+            .map(|(key, entry)| (key.clone(), entry.value.clone()))
+            .collect();
+        inner.core.priorities.sort_descending(&mut items);
+        items.truncate(limit);
+        items
+    }
+
+    /// Get the unexpired items in frequency order, least frequently used first and in eviction order within a frequency, in insertion order, or in any order.
+    fn items(&self, order: IterationOrder) -> Option<Vec<(K, Arc<V>)>> {
+        let inner = self.inner.lock().unwrap();
+        let now = inner.core.clock.now();
+        let live = |key: &&K| !inner.core.expiries.is_expired(key, now);
+        let items = match order {
+            IterationOrder::Frequency => {
+                let mut freqs: Vec<u64> = inner.freq_map.keys().copied().collect();
+                freqs.sort_unstable();
+                freqs
+                    .iter()
+                    .flat_map(|freq| inner.freq_map[freq].iter())
+                    .filter(live)
+                    .filter_map(|key| inner.entries.get_key_value(key))
+                    .map(|(key, entry)| (key.clone(), entry.value.clone()))
+                    .collect()
+            }
+            IterationOrder::Insertion | IterationOrder::Arbitrary => {
+                let mut entries: Vec<(&K, &LFUEntry<V>)> =
+                    inner.entries.iter().filter(|(key, _)| live(key)).collect();
+                if order == IterationOrder::Insertion {
+                    entries.sort_unstable_by_key(|(_, entry)| entry.insertion);
+                }
+                entries
+                    .into_iter()
+                    .map(|(key, entry)| (key.clone(), entry.value.clone()))
+                    .collect()
+            }
+            _ => return None,
+        };
+        Some(items)
+    }
+
+    /// Get the name of the eviction policy.
+    fn policy_name(&self) -> &'static str {
+        "lfu"
+    }
+
+    /// Get the cache as `Any`, so it can be downcast from a `dyn Cache`.
+    fn as_any(&self) -> Option<&dyn Any>
+    where
+        Self: 'static,
+    {
+        Some(self)
     }
 
     /// Get cache statistics.
     fn stats(&self) -> CacheStats {
         let inner = self.inner.lock().unwrap();
-        CacheStats {
-            hits: inner.hits,
-            misses: inner.misses,
-            size: inner.key_value_map.len() as u64,
-            capacity: inner.capacity,
-        }
+        inner
+            .core
+            .stats
+            .snapshot(inner.entries.len() as u64, inner.capacity)
+    }
+
+    /// Get the cache statistics together with a histogram of item frequencies, to see how many items were only ever accessed once, and how soon the items expire.
+    fn detailed_stats(&self) -> DetailedStats {
+        let inner = self.inner.lock().unwrap();
+        let mut detailed = DetailedStats::new(
+            inner
+                .core
+                .stats
+                .snapshot(inner.entries.len() as u64, inner.capacity),
+        );
+        detailed.eviction_ages = Some(inner.core.stats.eviction_ages());
+        detailed.frequency_histogram = Some(inner.frequency_histogram());
+        detailed.expiry_distribution = Some(
+            inner
+                .core
+                .expiries
+                .distribution(inner.core.clock.now(), inner.entries.len() as u64),
+        );
+        detailed
     }
 
     /// Change the capacity of the cache, if the new capacity is smaller than the current size, the least frequently used items are removed.
     fn change_capacity(&self, capacity: u64) {
+        self.change_capacity_collect(capacity);
+    }
+
+    /// Change the capacity of the cache, returning the items which were removed to fit the new capacity.
+    fn change_capacity_collect(&self, capacity: u64) -> Vec<(K, Arc<V>)> {
         let mut inner = self.inner.lock().unwrap();
         let old_capacity = inner.capacity;
         inner.capacity = capacity;
-        while inner.key_value_map.len() as u64 > inner.capacity {
-            inner.remove_least_freq();
-        }
+        let evicted = inner.evict_to(capacity as usize);
 
         if old_capacity < inner.capacity {
             let additional = (inner.capacity - old_capacity) as usize;
-            inner.key_value_map.reserve(additional);
-            inner.counter.reserve(additional);
+            inner.entries.reserve(additional);
+        }
+        evicted
+    }
+
+    /// Evict `fraction` of the items per the policy in one locked pass, leaving the capacity unchanged.
+    fn shed(&self, fraction: f64) -> usize {
+        let mut inner = self.inner.lock().unwrap();
+        let len = inner.entries.len();
+        inner.evict_to(len - shed_count(len, fraction)).len()
+    }
+}
+
+impl<K: Eq + Hash + Clone + Sync + Send, V: Send + Sync> TryCache<K, V> for LFUCache<K, V> {
+    /// Get a value from the cache, returning `CacheError::Poisoned` instead of panicking if the cache lock is poisoned.
+    fn try_get(&self, key: &K) -> Result<Option<Arc<V>>, CacheError> {
+        if self.inner.is_poisoned() {
+            return Err(CacheError::Poisoned);
+        }
+        Ok(self.get(key))
+    }
+
+    /// Set a value in the cache, returning an error instead of inserting if the cache is full and was built with `FullBehavior::ReturnError`, has a capacity of zero, or its lock is poisoned.
+    fn try_set(&self, key: K, value: V) -> Result<Option<Arc<V>>, CacheError> {
+        let started = self.latency.start();
+        let result = self.insert(
+            key,
+            Arc::new(value),
+            None,
+            None,
+            None,
+            None,
+            EntrySource::Inserted,
+        );
+        self.latency.record_set(started);
+        result
+    }
+}
+
+impl<K: Eq + Hash + Clone + Sync + Send, V: Send + Sync> VersionedCache<K, V> for LFUCache<K, V> {
+    /// Get the current version of an item, without counting a hit or miss or updating its position.
+    fn version(&self, key: &K) -> Option<u64> {
+        let inner = self.inner.lock().unwrap();
+        if inner.core.expiries.is_expired(key, inner.core.clock.now()) {
+            return None;
         }
+        inner.core.metadata.version(key)
+    }
+
+    /// Set a value only if the item is at `expected_version`, or is not in the cache if it is 0.
+    fn set_versioned(
+        &self,
+        key: K,
+        value: V,
+        expected_version: u64,
+    ) -> Result<Option<Arc<V>>, CacheError> {
+        let started = self.latency.start();
+        let result = self.insert(
+            key,
+            Arc::new(value),
+            None,
+            None,
+            None,
+            Some(expected_version),
+            EntrySource::Inserted,
+        );
+        self.latency.record_set(started);
+        result
+    }
+}
+
+impl<K: Eq + Hash + Clone + Sync + Send, V: Send + Sync> IntoIterator for LFUCache<K, V> {
+    type Item = (K, Arc<V>);
+    type IntoIter = std::vec::IntoIter<(K, Arc<V>)>;
+
+    /// Consume the handle, yielding the cache's unexpired items in no particular order. Other handles still hold the items.
+    fn into_iter(self) -> Self::IntoIter {
+        let inner = self.inner.lock().unwrap();
+        let now = inner.core.clock.now();
+        let items: Vec<(K, Arc<V>)> = inner
+            .entries
+            .iter()
+            .filter(|(key, _)| !inner.core.expiries.is_expired(key, now))
+            .map(|(key, entry)| (key.clone(), entry.value.clone()))
+            .collect();
+        items.into_iter()
     }
 }
 
@@ -220,6 +1004,21 @@ mod tests {
         assert_eq!(cache.get(&1), None);
     }
 
+    #[test]
+    fn test_lfu_cache_change_capacity_collect() {
+        let cache = LFUCache::new(2);
+        cache.set(1, 1);
+        cache.set(2, 2);
+        cache.get(&1);
+        let evicted: Vec<(i32, i32)> = cache
+            .change_capacity_collect(1)
+            .into_iter()
+            .map(|(k, v)| (k, *v))
+            .collect();
+        assert_eq!(evicted, vec![(2, 2)]);
+        assert_eq!(cache.stats().size, 1);
+    }
+
     #[test]
     fn test_lfu_cache_clear() {
         let cache = LFUCache::new(2);
@@ -230,6 +1029,190 @@ mod tests {
         assert_eq!(cache.get(&2), None);
     }
 
+    #[test]
+    fn test_lfu_cache_clear_returning_resets_frequencies() {
+        let cache = LFUCache::new(2);
+        cache.set(1, 1);
+        cache.get(&1);
+        cache.set(2, 2);
+        let mut cleared: Vec<i32> = cache
+            .clear_returning()
+            .into_iter()
+            .map(|(k, _)| k)
+            .collect();
+        cleared.sort();
+        assert_eq!(cleared, vec![1, 2]);
+        cache.set(3, 3);
+        cache.set(4, 4);
+        cache.get(&4);
+        cache.set(5, 5);
+        assert_eq!(cache.get(&3), None);
+        assert_eq!(cache.get(&4).map(|v| *v), Some(4));
+        assert_eq!(cache.stats().size, 2);
+    }
+
+    #[test]
+    fn test_lfu_cache_overwrite() {
+        let cache = LFUCache::new(2).with_overwrite(LFUOverwrite::Preserve);
+        cache.set(1, 1);
+        cache.set(2, 2);
+        cache.get(&2);
+        cache.set(1, 10);
+        cache.set(1, 11);
+        cache.set(3, 3);
+        assert!(!cache.contains_key(&1));
+        assert!(cache.contains_key(&2));
+
+        let cache = LFUCache::new(2).with_overwrite(LFUOverwrite::Reset);
+        cache.set(1, 1);
+        cache.set(2, 2);
+        cache.get(&1);
+        cache.get(&1);
+        cache.get(&2);
+        cache.set(1, 10);
+        cache.set(3, 3);
+        assert!(!cache.contains_key(&1));
+        assert!(cache.contains_key(&2));
+    }
+
+    #[test]
+    fn test_lfu_cache_frequencies() {
+        let cache = LFUCache::new(3);
+        cache.set(1, 1);
+        cache.set(2, 2);
+        cache.get(&2);
+        cache.get(&2);
+        assert_eq!(cache.frequency(&1), Some(1));
+        assert_eq!(cache.frequency(&2), Some(3));
+        assert_eq!(cache.frequency(&3), None);
+        assert_eq!(cache.frequencies(), vec![(2, 3), (1, 1)]);
+        assert_eq!(cache.stats().hits, 2);
+    }
+
+    #[test]
+    fn test_lfu_cache_tie_breaking_lru() {
+        let cache = LFUCache::new(2).with_tie_breaking(LFUTieBreaking::Lru);
+        cache.set(1, 1);
+        cache.set(2, 2);
+        cache.get(&2);
+        cache.get(&1);
+        cache.set(3, 3);
+        assert_eq!(cache.get(&2), None);
+        assert_eq!(cache.get(&1).map(|v| *v), Some(1));
+    }
+
+    #[test]
+    fn test_lfu_cache_tie_breaking_fifo() {
+        let cache = LFUCache::new(2).with_tie_breaking(LFUTieBreaking::Fifo);
+        cache.set(1, 1);
+        cache.set(2, 2);
+        cache.get(&2);
+        cache.get(&1);
+        cache.set(3, 3);
+        assert_eq!(cache.get(&1), None);
+        assert_eq!(cache.get(&2).map(|v| *v), Some(2));
+    }
+
+    #[test]
+    fn test_lfu_cache_tie_breaking_fifo_after_accesses() {
+        let cache = LFUCache::new(3);
+        for key in [1, 2, 3] {
+            cache.set(key, key);
+        }
+        // 3 reaches frequency 2 before 1, switching keeps the buckets in insertion order
+        cache.get(&3);
+        cache.get(&1);
+        let cache = cache.with_tie_breaking(LFUTieBreaking::Fifo);
+        cache.get(&2);
+        cache.set(4, 4);
+        assert_eq!(cache.get(&1), None);
+        cache.inner.lock().unwrap().check_invariants();
+        assert_eq!(
+            cache
+                .items(IterationOrder::Frequency)
+                .unwrap()
+                .into_iter()
+                .map(|(key, _)| key)
+                .collect::<Vec<_>>(),
+            vec![4, 2, 3]
+        );
+    }
+
+    #[test]
+    fn test_lfu_cache_tie_breaking_random() {
+        let cache = LFUCache::new(3).with_tie_breaking(LFUTieBreaking::Random);
+        cache.set(1, 1);
+        cache.set(2, 2);
+        cache.set(3, 3);
+        cache.get(&3);
+        cache.set(4, 4);
+        assert_eq!(cache.stats().size, 3);
+        assert_eq!(cache.get(&3).map(|v| *v), Some(3));
+        assert_eq!(cache.get(&4).map(|v| *v), Some(4));
+        assert!(cache.get(&1).is_none() || cache.get(&2).is_none());
+    }
+
+    #[test]
+    fn test_lfu_cache_priorities() {
+        let cache = LFUCache::new(2);
+        cache.set_with_priority(1, 1, 5);
+        cache.set(2, 2);
+        for _ in 0..3 {
+            cache.get(&2);
+        }
+        cache.set(3, 3);
+        // 2 is more frequently used but has a lower priority
+        assert_eq!(cache.get(&2), None);
+        assert_eq!(cache.get(&1).map(|v| *v), Some(1));
+        assert_eq!(cache.get(&3).map(|v| *v), Some(3));
+    }
+
+    #[test]
+    fn test_lfu_cache_ttl() {
+        let cache = LFUCache::new(2).with_ttl(Duration::from_secs(60));
+        cache.set_with_ttl(1, 1, Duration::ZERO);
+        cache.set(2, 2);
+        assert!(!cache.contains_key(&1));
+        cache.set(3, 3);
+        // the expired item is removed rather than the least frequently used live item
+        assert_eq!(cache.get(&2).map(|v| *v), Some(2));
+        assert_eq!(cache.get(&3).map(|v| *v), Some(3));
+        assert_eq!(cache.get(&1), None);
+        assert_eq!(cache.stats().size, 2);
+    }
+
+    #[test]
+    fn test_lfu_cache_decay() {
+        let cache = LFUCache::new(2);
+        cache.set(1, 1);
+        for _ in 0..4 {
+            cache.get(&1);
+        }
+        cache.set(2, 2);
+        cache.get(&2);
+        // 1 has a frequency of 5 and 2 of 2, after decaying twice they are both 1
+        cache.decay();
+        cache.decay();
+        cache.get(&2);
+        cache.set(3, 3);
+        assert_eq!(cache.get(&1), None);
+        assert_eq!(cache.get(&2).map(|v| *v), Some(2));
+    }
+
+    #[test]
+    fn test_lfu_cache_periodic_decay() {
+        let cache = LFUCache::new(2).with_decay(4);
+        cache.set(1, 1);
+        cache.get(&1);
+        cache.get(&1);
+        // the fourth access halves 1's frequency from 3 to 1
+        cache.set(2, 2);
+        cache.get(&2);
+        cache.set(3, 3);
+        assert_eq!(cache.get(&1), None);
+        assert_eq!(cache.get(&2).map(|v| *v), Some(2));
+    }
+
     #[test]
     fn test_lfu_cache_stats() {
         let cache = LFUCache::new(2);
@@ -244,4 +1227,90 @@ mod tests {
         assert_eq!(stats.size, 2);
         assert_eq!(stats.capacity, 2);
     }
+
+    #[test]
+    fn test_lfu_cache_frequency_histogram() {
+        let cache = LFUCache::new(10);
+        for key in 0..5 {
+            cache.set(key, key);
+        }
+        // frequencies 1, 2, 3, 4 and 8
+        for (key, gets) in [(1, 1), (2, 2), (3, 3), (4, 7)] {
+            for _ in 0..gets {
+                cache.get(&key);
+            }
+        }
+        let detailed = cache.detailed_stats();
+        assert_eq!(detailed.frequency_histogram, Some(vec![1, 2, 1, 1]));
+        assert_eq!(detailed.stats.hits, 13);
+        assert_eq!(
+            crate::LRUCache::<u32, u32>::new(1)
+                .detailed_stats()
+                .frequency_histogram,
+            None
+        );
+    }
+
+    #[test]
+    fn test_lfu_cache_remove_cleans_buckets() {
+        let cache = LFUCache::new(2);
+        cache.set(1, 1);
+        cache.set(2, 2);
+        cache.get(&2);
+        cache.remove(&1);
+        cache.inner.lock().unwrap().check_invariants();
+        // the frequency 1 bucket is gone, so the next eviction must find frequency 2
+        cache.set(3, 3);
+        cache.get(&3);
+        cache.get(&3);
+        cache.set(4, 4);
+        assert_eq!(cache.get(&2), None);
+        assert_eq!(cache.get(&3).map(|v| *v), Some(3));
+        assert_eq!(cache.frequency(&2), None);
+        cache.inner.lock().unwrap().check_invariants();
+        cache.remove(&3);
+        cache.remove(&4);
+        cache.inner.lock().unwrap().check_invariants();
+        assert_eq!(cache.stats().size, 0);
+    }
+
+    #[test]
+    fn test_lfu_cache_bookkeeping_never_diverges() {
+        use rand::rngs::StdRng;
+        use rand::SeedableRng;
+
+        let settings = [
+            (LFUTieBreaking::Lru, LFUOverwrite::Increment),
+            (LFUTieBreaking::Fifo, LFUOverwrite::Preserve),
+            (LFUTieBreaking::Random, LFUOverwrite::Reset),
+        ];
+        for (seed, (tie_breaking, overwrite)) in settings.into_iter().enumerate() {
+            let mut rng = StdRng::seed_from_u64(seed as u64);
+            let cache = LFUCache::new(8)
+                .with_tie_breaking(tie_breaking)
+                .with_overwrite(overwrite)
+                .with_decay(50);
+            for _ in 0..5_000 {
+                let key = rng.random_range(0..16u32);
+                match rng.random_range(0..10) {
+                    0..=3 => {
+                        cache.set(key, key);
+                    }
+                    4..=6 => {
+                        cache.get(&key);
+                    }
+                    7 => {
+                        cache.remove(&key);
+                    }
+                    8 => {
+                        cache.set_with_priority(key, key, rng.random_range(0..3));
+                    }
+                    _ => cache.change_capacity(rng.random_range(1..10)),
+                }
+                let inner = cache.inner.lock().unwrap();
+                inner.check_invariants();
+                assert!(inner.entries.len() as u64 <= inner.capacity);
+            }
+        }
+    }
 }
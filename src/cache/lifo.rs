@@ -1,34 +1,115 @@
+use crate::sync::Mutex;
+use std::any::Any;
 use std::collections::HashMap;
 use std::hash::Hash;
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
+use std::time::Duration;
 
-use crate::cache::{Cache, CacheStats};
+use linked_hash_map::LinkedHashMap;
+
+use crate::cache::latency::{LatencyTracker, OperationLatencies};
+use crate::cache::listener::{
+    AccessListener, AccessListeners, CallbackPanics, EntrySource, EvictionListener, RemovalCause,
+};
+use crate::cache::metadata::{EntryInfo, EntryMetadata};
+use crate::cache::policy_core::{policy_methods, PolicyCore};
+use crate::cache::{
+    shed_count, split_capacity, Cache, CacheStats, DetailedStats, FullBehavior, GetOutcome,
+    IterationOrder, TryCache, VersionedCache,
+};
+use crate::clock::Clock;
+use crate::error::CacheError;
 
 /// LIFOCacheInner contains the inner data structure for the LIFOCache.
 struct LIFOCacheInner<K: Eq + Hash + Send, V: Send + Sync> {
     capacity: u64,
-    key_value_map: HashMap<K, Arc<V>>,
-    lifo: Vec<K>,
-    hits: u64,
-    misses: u64,
+    // items in insertion order, oldest first
+    key_value_map: LinkedHashMap<K, Arc<V>>,
+    core: PolicyCore<K, V>,
 }
 
-impl<K: Eq + Hash + Send, V: Send + Sync> LIFOCacheInner<K, V> {
+impl<K: Eq + Hash + Clone + Send, V: Send + Sync> LIFOCacheInner<K, V> {
     /// Create a new LIFOCacheInner with the given capacity, internally capacity is reserved for the necessary data structures.
     fn new(capacity: u64) -> Self {
         LIFOCacheInner {
             capacity,
-            key_value_map: HashMap::with_capacity(capacity as usize),
-            lifo: Vec::with_capacity(capacity as usize),
-            hits: 0,
-            misses: 0,
+            key_value_map: LinkedHashMap::with_capacity(capacity as usize),
+            core: PolicyCore::new(),
+        }
+    }
+
+    /// Check if a key is held.
+    fn holds(&self, key: &K) -> bool {
+        self.key_value_map.contains_key(key)
+    }
+
+    /// Evict the newest item, or the newest item with the lowest priority if priorities have been assigned.
+    fn evict(&mut self) -> Option<(K, Arc<V>)> {
+        let len = self.key_value_map.len();
+        let key = match self
+            .core
+            .priorities
+            .pick(len, self.key_value_map.keys().rev())
+        {
+            Some(key) => key,
+            None => self.key_value_map.back()?.0.clone(),
+        };
+        let lifetime = self.core.metadata.lifetime(&key, self.core.clock.now());
+        let value = self.remove_entry(&key)?;
+        self.core
+            .listeners
+            .notify(&key, &value, RemovalCause::Evicted);
+        self.core.stats.record_eviction(lifetime);
+        Some((key, value))
+    }
+
+    /// Evict items per the policy until at most `len` are left.
+    fn evict_to(&mut self, len: usize) -> Vec<(K, Arc<V>)> {
+        let mut evicted = Vec::new();
+        while self.key_value_map.len() > len {
+            match self.evict() {
+                Some(item) => evicted.push(item),
+                None => break,
+            }
+        }
+        evicted
+    }
+
+    /// Remove an item, forgetting its priority and deadline.
+    fn remove_entry(&mut self, key: &K) -> Option<Arc<V>> {
+        self.core.forget(key);
+        self.key_value_map.remove(key)
+    }
+
+    /// Remove every item which has passed its deadline.
+    fn purge_expired(&mut self) {
+        let now = self.core.clock.now();
+        while let Some(key) = self.core.expiries.pop_expired(now) {
+            if let Some(value) = self.remove_entry(&key) {
+                self.core
+                    .listeners
+                    .notify(&key, &value, RemovalCause::Expired);
+                self.core.stats.record_expiration();
+            }
+        }
+    }
+
+    /// Remove every item, notifying listeners and returning the removed items.
+    fn clear_entries(&mut self) -> Vec<(K, Arc<V>)> {
+        self.core.clear();
+        let cleared: Vec<(K, Arc<V>)> = self.key_value_map.drain().collect();
+        for (key, value) in &cleared {
+            self.core
+                .listeners
+                .notify(key, value, RemovalCause::Cleared);
         }
+        cleared
     }
 }
 
 /// LIFOCache is a last-in-first-out cache implementation.
 ///
-/// When the cache is full, the newest item is evicted from the cache.
+/// When the cache is full, the newest item is evicted from the cache. Overwriting a key updates its value in place, it keeps its place in the stack.
 ///
 /// All mutability is handled internally with a Mutex, so the cache can be shared between threads. Values are returned as Arcs to allow for shared ownership.
 ///
@@ -49,14 +130,167 @@ impl<K: Eq + Hash + Send, V: Send + Sync> LIFOCacheInner<K, V> {
 /// println!("{:?}", cache.stats());
 /// ```
 pub struct LIFOCache<K: Eq + Hash + Send, V: Send + Sync> {
-    inner: Mutex<LIFOCacheInner<K, V>>,
+    inner: Arc<Mutex<LIFOCacheInner<K, V>>>,
+    access_listeners: Arc<AccessListeners<K>>,
+    latency: Arc<LatencyTracker>,
 }
 
-impl<K: Eq + Hash + Sync + Send, V: Send + Sync> LIFOCache<K, V> {
+impl<K: Eq + Hash + Clone + Sync + Send, V: Send + Sync> LIFOCache<K, V> {
     /// Create a new LIFOCache with the given capacity.
     pub fn new(capacity: u64) -> Self {
         LIFOCache {
-            inner: Mutex::new(LIFOCacheInner::new(capacity)),
+            inner: Arc::new(Mutex::new(LIFOCacheInner::new(capacity))),
+            access_listeners: Arc::new(AccessListeners::new()),
+            latency: Arc::new(LatencyTracker::new()),
+        }
+    }
+
+    policy_methods!(lock, lock);
+
+    /// Set a value in the cache with a priority, when the cache is full lower priority items are evicted before higher priority ones.
+    ///
+    /// Within a priority the newest item is evicted first. New items set with `set` have the lowest priority, `DEFAULT_PRIORITY`, and setting a key again without a priority keeps the one it has.
+    pub fn set_with_priority(&self, key: K, value: V, priority: u32) -> Option<Arc<V>> {
+        self.insert(
+            key,
+            Arc::new(value),
+            Some(priority),
+            None,
+            None,
+            None,
+            EntrySource::Inserted,
+        )
+        .unwrap_or(None)
+    }
+
+    /// Set a value in the cache which expires after the given time-to-live, overriding the cache's default TTL.
+    ///
+    /// Expired items are never returned and are removed before any live item is evicted.
+    pub fn set_with_ttl(&self, key: K, value: V, ttl: Duration) -> Option<Arc<V>> {
+        self.insert(
+            key,
+            Arc::new(value),
+            None,
+            Some(ttl),
+            None,
+            None,
+            EntrySource::Inserted,
+        )
+        .unwrap_or(None)
+    }
+
+    /// Set a value in the cache with a metadata value attached, which can be read back with `entry_info`.
+    ///
+    /// Metadata is dropped when the item leaves the cache or is set again without metadata.
+    pub fn set_with_metadata(
+        &self,
+        key: K,
+        value: V,
+        metadata: impl Any + Send + Sync,
+    ) -> Option<Arc<V>> {
+        self.insert(
+            key,
+            Arc::new(value),
+            None,
+            None,
+            Some(Arc::new(metadata)),
+            None,
+            EntrySource::Inserted,
+        )
+        .unwrap_or(None)
+    }
+
+    /// Move every item matching `predicate` into a new cache, whose capacity is in proportion to the share of items moved.
+    ///
+    /// Moved items keep their priority and remaining TTL. Insertion order is preserved. Listeners on this cache are notified with `RemovalCause::Removed`, they are not carried over to the new cache.
+    pub fn split_off<F: Fn(&K, &V) -> bool>(&self, predicate: F) -> Self {
+        let mut inner = self.inner.lock().unwrap();
+        inner.purge_expired();
+        let keys: Vec<K> = inner
+            .key_value_map
+            .iter()
+            .filter(|(key, value)| predicate(key, value))
+            .map(|(key, _)| key.clone())
+            .collect();
+        let capacity = split_capacity(inner.capacity, keys.len(), inner.key_value_map.len());
+        let split = Self::new(capacity);
+        split.inner.lock().unwrap().core.clock = inner.core.clock.clone();
+        if let Some(ttl) = inner.core.expiries.default_ttl() {
+            split
+                .inner
+                .lock()
+                .unwrap()
+                .core
+                .expiries
+                .set_default_ttl(ttl);
+        }
+        let now = inner.core.clock.now();
+        for key in keys {
+            let priority = inner.core.priorities.get(&key);
+            let ttl = inner.core.expiries.remaining(&key, now);
+            let metadata = inner.core.metadata.get(&key);
+            let source = inner.core.listeners.source(&key);
+            if let Some(value) = inner.remove_entry(&key) {
+                inner
+                    .core
+                    .listeners
+                    .notify(&key, &value, RemovalCause::Removed);
+                let _ = split.insert(key, value, Some(priority), ttl, metadata, None, source);
+            }
+        }
+        split
+    }
+
+    /// Insert a value with a priority and an optional TTL, evicting an item if the cache is full.
+    #[allow(clippy::too_many_arguments)]
+    fn insert(
+        &self,
+        key: K,
+        value: Arc<V>,
+        priority: Option<u32>,
+        ttl: Option<Duration>,
+        metadata: Option<EntryMetadata>,
+        expected_version: Option<u64>,
+        source: EntrySource,
+    ) -> Result<Option<Arc<V>>, CacheError> {
+        let mut inner = self.inner.lock().map_err(|_| CacheError::Poisoned)?;
+        if inner.capacity == 0 {
+            return Err(CacheError::CapacityZero);
+        }
+        inner.purge_expired();
+        inner.core.metadata.check_version(&key, expected_version)?;
+        // overwriting a key never evicts another item
+        let is_new = !inner.key_value_map.contains_key(&key);
+        if is_new
+            && !inner.core.full_behavior.evicts()
+            && inner.key_value_map.len() as u64 >= inner.capacity
+        {
+            inner.core.listeners.reject(&key);
+            return inner.core.full_behavior.rejection();
+        }
+        if is_new && inner.key_value_map.len() as u64 >= inner.capacity {
+            inner.evict();
+        }
+        inner.core.stats.record_set(is_new);
+        inner.core.track(&key, priority, ttl, metadata, source);
+        // an overwritten item keeps its place in the stack
+        match inner.key_value_map.get_mut(&key) {
+            Some(slot) => Ok(Some(std::mem::replace(slot, value))),
+            None => {
+                inner.key_value_map.insert(key, value);
+                Ok(None)
+            }
+        }
+    }
+}
+
+impl<K: Eq + Hash + Send, V: Send + Sync> Clone for LIFOCache<K, V> {
+    /// Get another handle to the same cache, sharing its items, settings and statistics.
+    fn clone(&self) -> Self {
+        LIFOCache {
+            inner: self.inner.clone(),
+            access_listeners: self.access_listeners.clone(),
+            latency: self.latency.clone(),
         }
     }
 }
@@ -64,75 +298,281 @@ impl<K: Eq + Hash + Sync + Send, V: Send + Sync> LIFOCache<K, V> {
 impl<K: Eq + Hash + Clone + Sync + Send, V: Send + Sync> Cache<K, V> for LIFOCache<K, V> {
     /// Get a value from the cache.
     fn get(&self, key: &K) -> Option<Arc<V>> {
-        let mut inner = self.inner.lock().unwrap();
-        let result = inner.key_value_map.get(key).cloned();
-        if result.is_some() {
-            inner.hits += 1;
-        } else {
-            inner.misses += 1;
-        }
+        let started = self.latency.start();
+        let result = {
+            let mut inner = self.inner.lock().unwrap();
+            inner.purge_expired();
+            let result = inner.key_value_map.get(key).cloned();
+            if result.is_some() {
+                inner.core.stats.record_hit();
+                let now = inner.core.clock.now();
+                inner.core.metadata.touch(key, now);
+            } else {
+                inner.core.stats.record_miss();
+            }
+            result
+        };
+        self.latency.record_get(started);
+        self.access_listeners.notify(key, &result);
         result
     }
 
+    /// Get a value from the cache, with whether it was a hit, or a miss because the key expired, was rejected or is absent.
+    fn get_with_outcome(&self, key: &K) -> (Option<Arc<V>>, GetOutcome) {
+        let result = self.get(key);
+        let outcome = match result {
+            Some(_) => GetOutcome::Hit,
+            None => self.inner.lock().unwrap().core.listeners.miss_outcome(key),
+        };
+        (result, outcome)
+    }
+
     /// Set a value in the cache.
     fn set(&self, key: K, value: V) -> Option<Arc<V>> {
-        let mut inner = self.inner.lock().unwrap();
-        if inner.key_value_map.len() as u64 >= inner.capacity {
-            if let Some(oldest_key) = inner.lifo.pop() {
-                inner.key_value_map.remove(&oldest_key);
-            }
-        }
-        let arc_value = Arc::new(value);
-        let result = inner.key_value_map.insert(key.clone(), arc_value);
-        inner.lifo.push(key);
+        let started = self.latency.start();
+        let result = self
+            .insert(
+                key,
+                Arc::new(value),
+                None,
+                None,
+                None,
+                None,
+                EntrySource::Inserted,
+            )
+            .unwrap_or(None);
+        self.latency.record_set(started);
+        result
+    }
+
+    /// Set a value in the cache, recording how it came to be in the cache for `entry_info` and eviction listeners.
+    fn set_with_source(&self, key: K, value: V, source: EntrySource) -> Option<Arc<V>> {
+        let started = self.latency.start();
+        let result = self
+            .insert(key, Arc::new(value), None, None, None, None, source)
+            .unwrap_or(None);
+        self.latency.record_set(started);
         result
     }
 
     /// Remove a value from the cache.
     fn remove(&self, key: &K) -> Option<Arc<V>> {
         let mut inner = self.inner.lock().unwrap();
-        let result = inner.key_value_map.remove(key);
-        if let Some(pos) = inner.lifo.iter().position(|k| k == key) {
-            inner.lifo.remove(pos);
+        let result = inner.remove_entry(key);
+        if let Some(value) = &result {
+            inner
+                .core
+                .listeners
+                .notify(key, value, RemovalCause::Removed);
         }
         result
     }
 
+    /// Check if a key is in the cache and has not expired, without counting a hit or miss or updating its position.
+    fn contains_key(&self, key: &K) -> bool {
+        let inner = self.inner.lock().unwrap();
+        inner.key_value_map.contains_key(key)
+            && !inner.core.expiries.is_expired(key, inner.core.clock.now())
+    }
+
+    /// Get the time left before an item expires, or None if it never expires or is not in the cache.
+    fn ttl_remaining(&self, key: &K) -> Option<Duration> {
+        self.entry_info(key)?.ttl_remaining
+    }
+
     /// Clear the cache.
     fn clear(&self) {
         let mut inner = self.inner.lock().unwrap();
-        inner.key_value_map.clear();
-        inner.lifo.clear();
+        inner.clear_entries();
+    }
+
+    /// Clear the cache, returning every item which was removed.
+    fn clear_returning(&self) -> Vec<(K, Arc<V>)> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.clear_entries()
+    }
+
+    /// Copy the unexpired items into a HashMap in one locked pass.
+    fn to_map(&self) -> HashMap<K, Arc<V>> {
+        let inner = self.inner.lock().unwrap();
+        let now = inner.core.clock.now();
+        inner
+            .key_value_map
+            .iter()
+            .filter(|(key, _)| !inner.core.expiries.is_expired(key, now))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect()
+    }
+
+    /// Get up to `limit` unexpired items, ordered with the items the policy would evict last first. Higher priority items come before lower ones, and otherwise oldest first, as the newest items are evicted first.
+    fn hottest(&self, limit: usize) -> Vec<(K, Arc<V>)> {
+        let inner = self.inner.lock().unwrap();
+        let now = inner.core.clock.now();
+        let mut items: Vec<(K, Arc<V>)> = inner
+            .key_value_map
+            .iter()
+            .filter(|(key, _)| !inner.core.expiries.is_expired(key, now))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect();
+        inner.core.priorities.sort_descending(&mut items);
+        items.truncate(limit);
+        items
+    }
+
+    /// Get the unexpired items in insertion order, oldest first, or in any order.
+    fn items(&self, order: IterationOrder) -> Option<Vec<(K, Arc<V>)>> {
+        if !matches!(order, IterationOrder::Insertion | IterationOrder::Arbitrary) {
+            return None;
+        }
+        let inner = self.inner.lock().unwrap();
+        let now = inner.core.clock.now();
+        let items = inner
+            .key_value_map
+            .iter()
+            .filter(|(key, _)| !inner.core.expiries.is_expired(key, now))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect();
+        Some(items)
+    }
+
+    /// Get the name of the eviction policy.
+    fn policy_name(&self) -> &'static str {
+        "lifo"
+    }
+
+    /// Get the cache as `Any`, so it can be downcast from a `dyn Cache`.
+    fn as_any(&self) -> Option<&dyn Any>
+    where
+        Self: 'static,
+    {
+        Some(self)
     }
 
     /// Get cache statistics.
     fn stats(&self) -> CacheStats {
         let inner = self.inner.lock().unwrap();
-        CacheStats {
-            hits: inner.hits,
-            misses: inner.misses,
-            size: inner.key_value_map.len() as u64,
-            capacity: inner.capacity,
-        }
+        inner
+            .core
+            .stats
+            .snapshot(inner.key_value_map.len() as u64, inner.capacity)
+    }
+
+    /// Get the cache statistics together with how soon the items expire.
+    fn detailed_stats(&self) -> DetailedStats {
+        let inner = self.inner.lock().unwrap();
+        let size = inner.key_value_map.len() as u64;
+        let mut detailed = DetailedStats::new(inner.core.stats.snapshot(size, inner.capacity));
+        detailed.eviction_ages = Some(inner.core.stats.eviction_ages());
+        detailed.expiry_distribution = Some(
+            inner
+                .core
+                .expiries
+                .distribution(inner.core.clock.now(), size),
+        );
+        detailed
     }
 
     /// Change the capacity of the cache, if the new capacity is smaller than the current size, the oldest items are removed.
     fn change_capacity(&self, capacity: u64) {
+        self.change_capacity_collect(capacity);
+    }
+
+    /// Change the capacity of the cache, returning the items which were removed to fit the new capacity.
+    fn change_capacity_collect(&self, capacity: u64) -> Vec<(K, Arc<V>)> {
         let mut inner = self.inner.lock().unwrap();
 
         let old_capacity = inner.capacity;
         inner.capacity = capacity;
-        while inner.key_value_map.len() as u64 > inner.capacity {
-            if let Some(oldest_key) = inner.lifo.pop() {
-                inner.key_value_map.remove(&oldest_key);
-            }
-        }
+        let evicted = inner.evict_to(capacity as usize);
 
         if old_capacity < inner.capacity {
             let additional = (inner.capacity - old_capacity) as usize;
             inner.key_value_map.reserve(additional);
-            inner.lifo.reserve(additional);
         }
+        evicted
+    }
+
+    /// Evict `fraction` of the items per the policy in one locked pass, leaving the capacity unchanged.
+    fn shed(&self, fraction: f64) -> usize {
+        let mut inner = self.inner.lock().unwrap();
+        let len = inner.key_value_map.len();
+        inner.evict_to(len - shed_count(len, fraction)).len()
+    }
+}
+
+impl<K: Eq + Hash + Clone + Sync + Send, V: Send + Sync> TryCache<K, V> for LIFOCache<K, V> {
+    /// Get a value from the cache, returning `CacheError::Poisoned` instead of panicking if the cache lock is poisoned.
+    fn try_get(&self, key: &K) -> Result<Option<Arc<V>>, CacheError> {
+        if self.inner.is_poisoned() {
+            return Err(CacheError::Poisoned);
+        }
+        Ok(self.get(key))
+    }
+
+    /// Set a value in the cache, returning an error instead of inserting if the cache is full and was built with `FullBehavior::ReturnError`, has a capacity of zero, or its lock is poisoned.
+    fn try_set(&self, key: K, value: V) -> Result<Option<Arc<V>>, CacheError> {
+        let started = self.latency.start();
+        let result = self.insert(
+            key,
+            Arc::new(value),
+            None,
+            None,
+            None,
+            None,
+            EntrySource::Inserted,
+        );
+        self.latency.record_set(started);
+        result
+    }
+}
+
+impl<K: Eq + Hash + Clone + Sync + Send, V: Send + Sync> VersionedCache<K, V> for LIFOCache<K, V> {
+    /// Get the current version of an item, without counting a hit or miss or updating its position.
+    fn version(&self, key: &K) -> Option<u64> {
+        let inner = self.inner.lock().unwrap();
+        if inner.core.expiries.is_expired(key, inner.core.clock.now()) {
+            return None;
+        }
+        inner.core.metadata.version(key)
+    }
+
+    /// Set a value only if the item is at `expected_version`, or is not in the cache if it is 0.
+    fn set_versioned(
+        &self,
+        key: K,
+        value: V,
+        expected_version: u64,
+    ) -> Result<Option<Arc<V>>, CacheError> {
+        let started = self.latency.start();
+        let result = self.insert(
+            key,
+            Arc::new(value),
+            None,
+            None,
+            None,
+            Some(expected_version),
+            EntrySource::Inserted,
+        );
+        self.latency.record_set(started);
+        result
+    }
+}
+
+impl<K: Eq + Hash + Clone + Sync + Send, V: Send + Sync> IntoIterator for LIFOCache<K, V> {
+    type Item = (K, Arc<V>);
+    type IntoIter = std::vec::IntoIter<(K, Arc<V>)>;
+
+    /// Consume the handle, yielding the cache's unexpired items from oldest to newest. Other handles still hold the items.
+    fn into_iter(self) -> Self::IntoIter {
+        let inner = self.inner.lock().unwrap();
+        let now = inner.core.clock.now();
+        let items: Vec<(K, Arc<V>)> = inner
+            .key_value_map
+            .iter()
+            .filter(|(key, _)| !inner.core.expiries.is_expired(key, now))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect();
+        items.into_iter()
     }
 }
 
@@ -155,6 +595,73 @@ mod tests {
         assert_eq!(cache.get(&4).map(|v| *v), Some(4));
     }
 
+    #[test]
+    fn test_lifo_cache_overwrite_keeps_position() {
+        let cache = LIFOCache::new(3);
+        cache.set(1, 1);
+        cache.set(2, 2);
+        cache.set(3, 3);
+        for i in 0..100 {
+            assert!(cache.set(1, i).is_some());
+        }
+        let keys: Vec<i32> = cache.clone().into_iter().map(|(k, _)| k).collect();
+        assert_eq!(keys, vec![1, 2, 3]);
+        assert_eq!(cache.stats().size, 3);
+
+        // the overwritten key is still the oldest, so the newest is evicted instead
+        cache.set(4, 4);
+        assert_eq!(cache.get(&3), None);
+        assert_eq!(cache.get(&1).map(|v| *v), Some(99));
+        cache.remove(&4);
+        cache.remove(&2);
+        assert_eq!(cache.clone().into_iter().count(), 1);
+    }
+
+    #[test]
+    fn test_lifo_cache_queue_matches_model() {
+        // remove-then-set and overwrite cycles keep exactly one queue slot per live key
+        let cache = LIFOCache::new(8);
+        let mut model: Vec<u64> = Vec::new();
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        for _ in 0..10_000 {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            let key = state % 16;
+            if state.is_multiple_of(3) {
+                cache.remove(&key);
+                model.retain(|&k| k != key);
+            } else {
+                if !model.contains(&key) {
+                    if model.len() == 8 {
+                        // the newest key is evicted
+                        model.pop().unwrap();
+                    }
+                    model.push(key);
+                }
+                cache.set(key, state);
+            }
+            let keys: Vec<u64> = cache.clone().into_iter().map(|(k, _)| k).collect();
+            assert_eq!(keys, model);
+            assert_eq!(cache.stats().size, model.len() as u64);
+        }
+    }
+
+    #[test]
+    fn test_lifo_cache_change_capacity_collect() {
+        let cache = LIFOCache::new(2);
+        cache.set(1, 1);
+        cache.set(2, 2);
+        cache.get(&1);
+        let evicted: Vec<(i32, i32)> = cache
+            .change_capacity_collect(1)
+            .into_iter()
+            .map(|(k, v)| (k, *v))
+            .collect();
+        assert_eq!(evicted, vec![(2, 2)]);
+        assert_eq!(cache.stats().size, 1);
+    }
+
     #[test]
     fn test_lifo_cache_clear() {
         let cache = LIFOCache::new(2);
@@ -165,6 +672,30 @@ mod tests {
         assert_eq!(cache.get(&2), None);
     }
 
+    #[test]
+    fn test_lifo_cache_priorities() {
+        let cache = LIFOCache::new(2);
+        cache.set(1, 1);
+        cache.set_with_priority(2, 2, 10);
+        cache.set(3, 3);
+        assert_eq!(cache.get(&1), None);
+        assert_eq!(cache.get(&2).map(|v| *v), Some(2));
+        assert_eq!(cache.get(&3).map(|v| *v), Some(3));
+    }
+
+    #[test]
+    fn test_lifo_cache_ttl() {
+        let cache = LIFOCache::new(2).with_ttl(Duration::from_secs(60));
+        cache.set(1, 1);
+        cache.set_with_ttl(2, 2, Duration::ZERO);
+        assert!(!cache.contains_key(&2));
+        // the expired item is removed rather than evicting by policy
+        cache.set(3, 3);
+        assert_eq!(cache.get(&1).map(|v| *v), Some(1));
+        assert_eq!(cache.get(&2), None);
+        assert_eq!(cache.get(&3).map(|v| *v), Some(3));
+    }
+
     #[test]
     fn test_lifo_cache_change_capacity() {
         let cache = LIFOCache::new(2);
@@ -0,0 +1,381 @@
+use crate::cache::identity::CacheIdentity;
+use crate::cache::GetOutcome;
+use crate::sync::atomic::{AtomicU64, Ordering};
+use linked_hash_map::LinkedHashMap;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Arc;
+
+/// CallbackPanics is what a cache does when user code it calls while holding its lock, such as an eviction listener or a weigher, panics.
+///
+/// Example:
+/// ```
+/// use arcache::{Cache, CallbackPanics, LRUCache, RemovalEvent};
+///
+/// let cache = LRUCache::new(1)
+///     .with_eviction_listener(|_: &RemovalEvent<u64, u64>| panic!("listener failed"))
+///     .with_callback_panics(CallbackPanics::Disable);
+/// cache.set(1, 1);
+/// cache.set(2, 2);
+/// cache.set(3, 3);
+///
+/// // the listener panicked once and was not called again, and the cache still works
+/// assert_eq!(cache.stats().callback_panics, 1);
+/// assert_eq!(cache.get(&3).map(|v| *v), Some(3));
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CallbackPanics {
+    /// Let the panic unwind through the cache call, which poisons the cache lock unless the `parking_lot` feature is enabled. This is the default.
+    #[default]
+    Propagate,
+    /// Catch and count the panic, and carry on as if an eviction listener had returned, a set whose weigher panicked is abandoned.
+    Isolate,
+    /// Catch and count the panic like `Isolate`, and never call an eviction listener which has panicked again.
+    Disable,
+}
+
+/// RemovalCause describes why an item left the cache.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum RemovalCause {
+    /// The item was evicted by the cache's policy to make space.
+    Evicted,
+    /// The item passed its deadline.
+    Expired,
+    /// The item was removed with `remove`.
+    Removed,
+    /// The item was removed because the cache was cleared.
+    Cleared,
+}
+
+/// EntrySource describes how an item came to be in the cache.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum EntrySource {
+    /// The item was set directly.
+    #[default]
+    Inserted,
+    /// The item was filled in by a loader, for example on a miss in a `LoadingCache` or by a prefetch.
+    Loaded,
+    /// The item was loaded again to replace a value which was due to be refreshed.
+    Refreshed,
+}
+
+/// RemovalEvent is passed to eviction listeners when an item leaves the cache.
+#[derive(Debug, Clone)]
+#[non_exhaustive]
+pub struct RemovalEvent<K, V> {
+    pub key: K,
+    pub value: Arc<V>,
+    pub cause: RemovalCause,
+    pub source: EntrySource,
+    /// The cache the item left, so one listener can tell the events of several caches apart.
+    pub cache: CacheIdentity,
+}
+
+/// EvictionListener is notified whenever an item leaves the cache.
+///
+/// Listeners are called while the cache is locked, so they should be quick and must not call back into the cache. Closures taking a `&RemovalEvent` implement this trait.
+///
+/// Example:
+/// ```
+/// use arcache::{Cache, LRUCache, RemovalCause, RemovalEvent};
+/// use std::sync::atomic::{AtomicU64, Ordering};
+/// use std::sync::Arc;
+///
+/// let evictions = Arc::new(AtomicU64::new(0));
+/// let counter = evictions.clone();
+/// let cache = LRUCache::<&str, String>::new(1).with_eviction_listener(
+///     move |event: &RemovalEvent<&str, String>| {
+///         if event.cause == RemovalCause::Evicted {
+///             counter.fetch_add(1, Ordering::Relaxed);
+///         }
+///     },
+/// );
+///
+/// cache.set("a", "a".to_string());
+/// cache.set("b", "b".to_string());
+///
+/// assert_eq!(evictions.load(Ordering::Relaxed), 1);
+/// ```
+pub trait EvictionListener<K, V>: Send + Sync {
+    /// Called with each item which leaves the cache.
+    fn on_removal(&self, event: &RemovalEvent<K, V>);
+}
+
+impl<K, V, F> EvictionListener<K, V> for F
+where
+    F: Fn(&RemovalEvent<K, V>) + Send + Sync,
+{
+    fn on_removal(&self, event: &RemovalEvent<K, V>) {
+        self(event)
+    }
+}
+
+/// AccessOutcome describes whether a get found its key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum AccessOutcome {
+    /// The key was in the cache.
+    Hit,
+    /// The key was not in the cache or had expired.
+    Miss,
+}
+
+/// AccessListener is notified of the key and outcome of every get.
+///
+/// Unlike eviction listeners, access listeners are called after the cache lock is released, so they may call back into the cache. Closures taking a key and an `AccessOutcome` implement this trait.
+///
+/// Example:
+/// ```
+/// use arcache::{AccessOutcome, Cache, LRUCache};
+/// use std::sync::{Arc, Mutex};
+///
+/// let misses = Arc::new(Mutex::new(Vec::new()));
+/// let sink = misses.clone();
+/// let cache = LRUCache::<&str, String>::new(10).with_access_listener(
+///     move |key: &&str, outcome: AccessOutcome| {
+///         if outcome == AccessOutcome::Miss {
+///             sink.lock().unwrap().push(key.to_string());
+///         }
+///     },
+/// );
+///
+/// cache.set("a", "a".to_string());
+/// cache.get(&"a");
+/// cache.get(&"b");
+///
+/// assert_eq!(*misses.lock().unwrap(), vec!["b".to_string()]);
+/// ```
+pub trait AccessListener<K>: Send + Sync {
+    /// Called after each get with the key and whether it hit.
+    fn on_access(&self, key: &K, outcome: AccessOutcome);
+}
+
+impl<K, F> AccessListener<K> for F
+where
+    F: Fn(&K, AccessOutcome) + Send + Sync,
+{
+    fn on_access(&self, key: &K, outcome: AccessOutcome) {
+        self(key, outcome)
+    }
+}
+
+/// AccessListeners holds the access listeners registered with a cache.
+pub(crate) struct AccessListeners<K> {
+    listeners: Vec<Box<dyn AccessListener<K>>>,
+}
+
+impl<K> AccessListeners<K> {
+    pub(crate) fn new() -> Self {
+        AccessListeners {
+            listeners: Vec::new(),
+        }
+    }
+
+    /// Register a listener.
+    pub(crate) fn add(&mut self, listener: Box<dyn AccessListener<K>>) {
+        self.listeners.push(listener);
+    }
+
+    /// Notify every listener of the outcome of a get.
+    pub(crate) fn notify<V>(&self, key: &K, result: &Option<V>) {
+        let outcome = if result.is_some() {
+            AccessOutcome::Hit
+        } else {
+            AccessOutcome::Miss
+        };
+        for listener in &self.listeners {
+            listener.on_access(key, outcome);
+        }
+    }
+}
+
+/// The number of keys which expired or were rejected that a cache remembers, so `get_with_outcome` can explain their misses.
+const REMEMBERED_MISSES: usize = 1024;
+
+/// Listeners holds the eviction listeners registered with a cache, the source of every item which was not set directly so removal events can report it, and why the most recent keys which expired or were rejected are missing.
+///
+/// Events are only built when a listener is registered, so caches without listeners only pay for an emptiness check.
+pub(crate) struct Listeners<K, V> {
+    listeners: Vec<Option<Box<dyn EvictionListener<K, V>>>>,
+    sources: HashMap<K, EntrySource>,
+    panics: CallbackPanics,
+    panicked: Arc<AtomicU64>,
+    identity: CacheIdentity,
+    misses: LinkedHashMap<K, GetOutcome>,
+}
+
+impl<K: Eq + Hash + Clone, V> Listeners<K, V> {
+    pub(crate) fn new(identity: CacheIdentity) -> Self {
+        Listeners {
+            listeners: Vec::new(),
+            sources: HashMap::new(),
+            panics: CallbackPanics::Propagate,
+            panicked: Arc::new(AtomicU64::new(0)),
+            identity,
+            misses: LinkedHashMap::new(),
+        }
+    }
+
+    /// Name the cache in the events sent from now on.
+    pub(crate) fn set_name(&mut self, name: String) {
+        self.identity.set_name(name);
+    }
+
+    /// Choose what happens when a callback panics, returning the counter of caught panics for the cache's statistics.
+    pub(crate) fn set_panics(&mut self, panics: CallbackPanics) -> Arc<AtomicU64> {
+        self.panics = panics;
+        self.panicked.clone()
+    }
+
+    /// Call user code, returning None if it panicked and panics are isolated.
+    pub(crate) fn guard<R>(&self, callback: impl FnOnce() -> R) -> Option<R> {
+        if self.panics == CallbackPanics::Propagate {
+            return Some(callback());
+        }
+        let result = panic::catch_unwind(AssertUnwindSafe(callback));
+        if result.is_err() {
+            self.panicked.fetch_add(1, Ordering::Relaxed);
+        }
+        result.ok()
+    }
+
+    /// Record how an item which has just been set came to be in the cache.
+    pub(crate) fn set_source(&mut self, key: &K, source: EntrySource) {
+        if !self.misses.is_empty() {
+            self.misses.remove(key);
+        }
+        if source != EntrySource::Inserted {
+            self.sources.insert(key.clone(), source);
+        } else if !self.sources.is_empty() {
+            self.sources.remove(key);
+        }
+    }
+
+    /// Remember that a set of `key` was turned away.
+    pub(crate) fn reject(&mut self, key: &K) {
+        self.remember_miss(key, GetOutcome::MissRejected);
+    }
+
+    /// Remember why a key is missing, forgetting the oldest key once too many are remembered.
+    fn remember_miss(&mut self, key: &K, outcome: GetOutcome) {
+        self.misses.insert(key.clone(), outcome);
+        if self.misses.len() > REMEMBERED_MISSES {
+            self.misses.pop_front();
+        }
+    }
+
+    /// Explain a miss for `key`, `GetOutcome::MissAbsent` unless it recently expired or was rejected.
+    pub(crate) fn miss_outcome(&self, key: &K) -> GetOutcome {
+        self.misses
+            .get(key)
+            .copied()
+            .unwrap_or(GetOutcome::MissAbsent)
+    }
+
+    /// Get how an item came to be in the cache.
+    pub(crate) fn source(&self, key: &K) -> EntrySource {
+        self.sources.get(key).copied().unwrap_or_default()
+    }
+
+    /// Register a listener.
+    pub(crate) fn add(&mut self, listener: Box<dyn EvictionListener<K, V>>) {
+        self.listeners.push(Some(listener));
+    }
+
+    /// Notify every listener that an item has left the cache, and forget its source.
+    pub(crate) fn notify(&mut self, key: &K, value: &Arc<V>, cause: RemovalCause) {
+        let source = if self.sources.is_empty() {
+            EntrySource::Inserted
+        } else {
+            self.sources.remove(key).unwrap_or_default()
+        };
+        if cause == RemovalCause::Expired {
+            self.remember_miss(key, GetOutcome::MissExpired);
+        }
+        if self.listeners.is_empty() {
+            return;
+        }
+        let event = RemovalEvent {
+            key: key.clone(),
+            value: value.clone(),
+            cause,
+            source,
+            cache: self.identity.clone(),
+        };
+        for index in 0..self.listeners.len() {
+            let Some(listener) = &self.listeners[index] else {
+                continue;
+            };
+            if self.guard(|| listener.on_removal(&event)).is_none()
+                && self.panics == CallbackPanics::Disable
+            {
+                self.listeners[index] = None;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[test]
+    fn test_listeners_notify() {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let mut listeners = Listeners::new(CacheIdentity::default());
+        listeners.notify(&0, &Arc::new(0), RemovalCause::Removed);
+        let sink = seen.clone();
+        listeners.add(Box::new(move |event: &RemovalEvent<i32, i32>| {
+            sink.lock()
+                .unwrap()
+                .push((event.key, *event.value, event.cause));
+        }));
+        listeners.notify(&1, &Arc::new(2), RemovalCause::Evicted);
+        assert_eq!(*seen.lock().unwrap(), vec![(1, 2, RemovalCause::Evicted)]);
+    }
+
+    #[test]
+    fn test_listeners_isolate_panics() {
+        let calls = Arc::new(Mutex::new(0));
+        let mut listeners = Listeners::new(CacheIdentity::default());
+        let counter = calls.clone();
+        listeners.add(Box::new(move |_: &RemovalEvent<i32, i32>| {
+            *counter.lock().unwrap() += 1;
+            panic!("listener failed");
+        }));
+        let panicked = listeners.set_panics(CallbackPanics::Isolate);
+        listeners.notify(&1, &Arc::new(1), RemovalCause::Evicted);
+        listeners.notify(&2, &Arc::new(2), RemovalCause::Evicted);
+        assert_eq!(
+            (*calls.lock().unwrap(), panicked.load(Ordering::Relaxed)),
+            (2, 2)
+        );
+        listeners.set_panics(CallbackPanics::Disable);
+        listeners.notify(&3, &Arc::new(3), RemovalCause::Evicted);
+        listeners.notify(&4, &Arc::new(4), RemovalCause::Evicted);
+        assert_eq!(
+            (*calls.lock().unwrap(), panicked.load(Ordering::Relaxed)),
+            (3, 3)
+        );
+    }
+
+    #[test]
+    fn test_access_listeners_notify() {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let mut listeners = AccessListeners::new();
+        let sink = seen.clone();
+        listeners.add(Box::new(move |key: &i32, outcome: AccessOutcome| {
+            sink.lock().unwrap().push((*key, outcome));
+        }));
+        listeners.notify(&1, &Some(1));
+        listeners.notify(&2, &None::<i32>);
+        assert_eq!(
+            *seen.lock().unwrap(),
+            vec![(1, AccessOutcome::Hit), (2, AccessOutcome::Miss)]
+        );
+    }
+}
@@ -0,0 +1,1281 @@
+use crate::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use crate::sync::Mutex;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::hash::Hash;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{self, RecvTimeoutError};
+// loaders are real threads waiting on real time, which loom cannot model
+use std::sync::{Arc, Condvar, Mutex as StdMutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::cache::listener::{CallbackPanics, EntrySource};
+use crate::cache::negative::NegativeCache;
+use crate::cache::{Cache, CacheStats, GetOutcome};
+use crate::clock::saturating_add;
+use crate::error::CacheError;
+
+// loader type, fetches the value of a key from the origin or None if it has no value
+type Loader<K, V> = Arc<dyn Fn(&K) -> Option<V> + Send + Sync>;
+
+// a loaded value sent back to the waiting get, tagged with whether the hedged call loaded it
+type LoadResult<V> = (bool, Option<V>);
+
+/// LoadStats counts the loads made by a LoadingCache.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LoadStats {
+    /// The number of times the loader was called.
+    pub loads: u64,
+    /// The number of loads for which the loader returned no value.
+    pub load_failures: u64,
+    /// The number of loads which took longer than the load timeout and were not waited for.
+    pub load_timeouts: u64,
+    /// The number of gets answered with a stale value because its refresh failed or timed out.
+    pub stale_served: u64,
+    /// The number of values loaded in the background by `prefetch`.
+    pub prefetches: u64,
+    /// The number of gets which skipped the loader because the key recently failed to load.
+    pub negative_hits: u64,
+    /// The number of keys currently waiting out a backoff before they are loaded again.
+    pub backing_off: u64,
+    /// The number of second loader calls made because the first had not returned within the hedge delay.
+    pub hedges: u64,
+    /// The number of hedged loads where the second loader call returned first.
+    pub hedge_wins: u64,
+    /// The number of gets which did not load because the maximum number of loads were in flight.
+    pub overloaded: u64,
+    /// The number of loads, including prefetches, which failed because the loader panicked.
+    pub panicked_loads: u64,
+    /// The number of gets which waited for a load of their key already running instead of calling the loader.
+    pub coalesced: u64,
+}
+
+/// OverloadBehavior is what a LoadingCache get does when it needs a load while the maximum number of loads are in flight.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OverloadBehavior {
+    /// Wait for another load to finish, for at most the load timeout if one is set, the default. A get which gives up serves the stale value if there is one.
+    #[default]
+    Queue,
+    /// Serve the stale value without loading, `try_get` returns `CacheError::Overloaded` if there is none.
+    ServeStale,
+    /// Load nothing, `try_get` returns `CacheError::Overloaded` and `get` returns None, even if there is a stale value.
+    ReturnError,
+}
+
+/// Counts the loader calls running at once, up to a limit.
+struct InFlight {
+    limit: usize,
+    running: StdMutex<usize>,
+    finished: Condvar,
+}
+
+/// A slot for one running loader call, freed when dropped.
+struct InFlightPermit(Arc<InFlight>);
+
+impl InFlight {
+    fn new(limit: usize) -> Arc<Self> {
+        Arc::new(InFlight {
+            limit,
+            running: StdMutex::new(0),
+            finished: Condvar::new(),
+        })
+    }
+
+    /// Take a slot if one is free.
+    fn try_acquire(self: &Arc<Self>) -> Option<InFlightPermit> {
+        let mut running = self.running.lock().unwrap();
+        if *running >= self.limit {
+            return None;
+        }
+        *running += 1;
+        Some(InFlightPermit(self.clone()))
+    }
+
+    /// Wait for a slot for at most `timeout`, or for as long as it takes with no timeout.
+    fn acquire(self: &Arc<Self>, timeout: Option<Duration>) -> Option<InFlightPermit> {
+        let running = self.running.lock().unwrap();
+        let mut running = match timeout {
+            Some(timeout) => {
+                let (running, result) = self
+                    .finished
+                    .wait_timeout_while(running, timeout, |running| *running >= self.limit)
+                    .unwrap();
+                if result.timed_out() {
+                    return None;
+                }
+                running
+            }
+            None => self
+                .finished
+                .wait_while(running, |running| *running >= self.limit)
+                .unwrap(),
+        };
+        *running += 1;
+        Some(InFlightPermit(self.clone()))
+    }
+}
+
+impl Drop for InFlightPermit {
+    fn drop(&mut self) {
+        *self.0.running.lock().unwrap() -= 1;
+        self.0.finished.notify_one();
+    }
+}
+
+/// What became of a load which other gets for the same key are waiting on.
+enum FlightState<V> {
+    Loading,
+    Loaded(Option<Arc<V>>),
+    Failed,
+}
+
+impl<V> Clone for FlightState<V> {
+    fn clone(&self) -> Self {
+        match self {
+            FlightState::Loading => FlightState::Loading,
+            FlightState::Loaded(value) => FlightState::Loaded(value.clone()),
+            FlightState::Failed => FlightState::Failed,
+        }
+    }
+}
+
+/// A load running for one key, which gets for the key wait on instead of calling the loader again.
+struct Flight<V> {
+    state: StdMutex<FlightState<V>>,
+    landed: Condvar,
+}
+
+impl<V> Flight<V> {
+    /// Wait for the load to finish, for at most `timeout`, returning `FlightState::Loading` if it is still running.
+    fn wait(&self, timeout: Option<Duration>) -> FlightState<V> {
+        let state = self.state.lock().unwrap();
+        let loading = |state: &mut FlightState<V>| matches!(state, FlightState::Loading);
+        let state = match timeout {
+            Some(timeout) => {
+                self.landed
+                    .wait_timeout_while(state, timeout, loading)
+                    .unwrap()
+                    .0
+            }
+            None => self.landed.wait_while(state, loading).unwrap(),
+        };
+        state.clone()
+    }
+}
+
+/// The loads running for each key.
+type Flights<K, V> = StdMutex<HashMap<K, Arc<Flight<V>>>>;
+
+/// Held by whatever runs the loader for a key, it hands the outcome to the waiting gets and lets the next get load the key again.
+///
+/// Dropping the handle without landing it, because every loader call panicked, fails the load.
+struct FlightHandle<K: Eq + Hash, V> {
+    key: K,
+    flights: Arc<Flights<K, V>>,
+    flight: Arc<Flight<V>>,
+}
+
+impl<K: Eq + Hash, V> FlightHandle<K, V> {
+    /// Finish the load with `state`, unless it already finished, such as a hedged load whose other call returned first.
+    fn land(&self, state: FlightState<V>) {
+        {
+            let mut flights = self.flights.lock().unwrap();
+            if flights
+                .get(&self.key)
+                .is_some_and(|flight| Arc::ptr_eq(flight, &self.flight))
+            {
+                flights.remove(&self.key);
+            }
+        }
+        let mut current = self.flight.state.lock().unwrap();
+        if matches!(*current, FlightState::Loading) {
+            *current = state;
+            self.flight.landed.notify_all();
+        }
+    }
+}
+
+impl<K: Eq + Hash, V> Drop for FlightHandle<K, V> {
+    fn drop(&mut self) {
+        self.land(FlightState::Failed);
+    }
+}
+
+/// Whether a get starts the load of its key or waits on one which is already running.
+enum Joined<K: Eq + Hash, V> {
+    Leader(FlightHandle<K, V>),
+    Follower(Arc<Flight<V>>),
+}
+
+/// Join the load running for `key`, or start one if there is none.
+fn join_flight<K: Eq + Hash + Clone, V>(flights: &Arc<Flights<K, V>>, key: &K) -> Joined<K, V> {
+    let mut running = flights.lock().unwrap();
+    if let Some(flight) = running.get(key) {
+        return Joined::Follower(flight.clone());
+    }
+    let flight = Arc::new(Flight {
+        state: StdMutex::new(FlightState::Loading),
+        landed: Condvar::new(),
+    });
+    running.insert(key.clone(), flight.clone());
+    Joined::Leader(FlightHandle {
+        key: key.clone(),
+        flights: flights.clone(),
+        flight,
+    })
+}
+
+/// Keys waiting to be prefetched, and the number of threads loading them.
+struct PrefetchQueue<K> {
+    keys: VecDeque<K>,
+    // keys which are queued or being loaded
+    pending: HashSet<K>,
+    workers: usize,
+}
+
+/// LoadingCache wraps any cache and fills misses by calling a loader closure.
+///
+/// With `with_refresh_after` items are loaded again once they are older than the given duration, and with `with_load_timeout` a get waits at most the given time for its load. When a refresh fails or times out the previous, stale value is returned instead, so a slow origin does not turn every refresh into a latency spike. A load which times out keeps running on its own thread and its value is stored when it arrives.
+///
+/// With `with_retry_backoff` or `with_negative_cache` keys whose load fails, times out or panics are remembered in a NegativeCache, and gets for them skip the loader until their backoff TTL has passed, returning the stale value if there is one. This keeps a failing origin from being called on every miss.
+///
+/// With `with_hedge_after` a load which has not returned within the hedge delay starts a second loader call, and the get takes whichever value arrives first. This trades extra origin calls for a shorter tail latency on misses.
+///
+/// With `with_max_in_flight` at most the given number of loader calls run at once, counting prefetches, hedges and loads which timed out but are still running. A get which needs a load beyond that queues, serves the stale value or returns `CacheError::Overloaded`, per its `OverloadBehavior`, which protects the origin from a burst of misses such as a cold start.
+///
+/// Concurrent gets for a key which needs loading share one loader call, the first get calls the loader and the others wait for its value, for at most the load timeout. A load which timed out keeps its key until it finishes, so gets retrying the key wait on it too rather than calling the loader again.
+///
+/// When upcoming keys are known ahead of time, `prefetch` loads the missing ones in the background, on at most `with_prefetch_concurrency` threads at once.
+///
+/// Loaded values are cloned into the wrapped cache, so `V` must be `Clone`. They are set with `EntrySource::Loaded`, or `EntrySource::Refreshed` when they replace a value which was due to be refreshed, so the wrapped cache's `entry_info` and eviction listeners can tell them apart from values set directly.
+///
+/// Example:
+/// ```
+/// use arcache::{LRUCache, LoadingCache};
+/// use std::time::Duration;
+///
+/// let cache = LoadingCache::new(LRUCache::<u64, String>::new(10), |key: &u64| {
+///     Some(format!("value {}", key))
+/// })
+/// .with_load_timeout(Duration::from_secs(1));
+///
+/// assert_eq!(*cache.get(&1).unwrap(), "value 1");
+/// assert_eq!(cache.load_stats().loads, 1);
+///
+/// // the second get is a hit and does not call the loader
+/// cache.get(&1);
+/// assert_eq!(cache.load_stats().loads, 1);
+/// ```
+pub struct LoadingCache<C, K, V> {
+    cache: Arc<C>,
+    loader: Loader<K, V>,
+    refresh_after: Option<Duration>,
+    load_timeout: Option<Duration>,
+    loaded_at: Arc<Mutex<HashMap<K, Instant>>>,
+    loads: AtomicU64,
+    load_failures: AtomicU64,
+    load_timeouts: AtomicU64,
+    stale_served: AtomicU64,
+    prefetch_queue: Arc<Mutex<PrefetchQueue<K>>>,
+    prefetch_concurrency: usize,
+    prefetches: Arc<AtomicU64>,
+    negative: Option<Arc<NegativeCache<K>>>,
+    negative_hits: AtomicU64,
+    hedge_after: Option<Duration>,
+    hedges: AtomicU64,
+    hedge_wins: AtomicU64,
+    in_flight: Arc<InFlight>,
+    overload: OverloadBehavior,
+    overloaded: AtomicU64,
+    panics: CallbackPanics,
+    panicked_loads: Arc<AtomicU64>,
+    flights: Arc<Flights<K, V>>,
+    coalesced: AtomicU64,
+}
+
+impl<C, K, V> LoadingCache<C, K, V>
+where
+    C: Cache<K, V> + 'static,
+    K: Eq + Hash + Clone + Send + Sync + 'static,
+    V: Clone + Send + Sync + 'static,
+{
+    /// Create a new LoadingCache storing values in the given cache, `loader` is called for keys which are missing.
+    pub fn new(cache: C, loader: impl Fn(&K) -> Option<V> + Send + Sync + 'static) -> Self {
+        LoadingCache {
+            cache: Arc::new(cache),
+            loader: Arc::new(loader),
+            refresh_after: None,
+            load_timeout: None,
+            loaded_at: Arc::new(Mutex::new(HashMap::new())),
+            loads: AtomicU64::new(0),
+            load_failures: AtomicU64::new(0),
+            load_timeouts: AtomicU64::new(0),
+            stale_served: AtomicU64::new(0),
+            prefetch_queue: Arc::new(Mutex::new(PrefetchQueue {
+                keys: VecDeque::new(),
+                pending: HashSet::new(),
+                workers: 0,
+            })),
+            prefetch_concurrency: 1,
+            prefetches: Arc::new(AtomicU64::new(0)),
+            negative: None,
+            negative_hits: AtomicU64::new(0),
+            hedge_after: None,
+            hedges: AtomicU64::new(0),
+            hedge_wins: AtomicU64::new(0),
+            in_flight: InFlight::new(usize::MAX),
+            overload: OverloadBehavior::default(),
+            overloaded: AtomicU64::new(0),
+            panics: CallbackPanics::Propagate,
+            panicked_loads: Arc::new(AtomicU64::new(0)),
+            flights: Arc::new(StdMutex::new(HashMap::new())),
+            coalesced: AtomicU64::new(0),
+        }
+    }
+
+    /// Load items again on the first get after they are `refresh_after` old, serving the stale value if the load fails or times out.
+    pub fn with_refresh_after(mut self, refresh_after: Duration) -> Self {
+        self.refresh_after = Some(refresh_after);
+        self
+    }
+
+    /// Wait at most `timeout` for a load, after which the get returns the stale value if there is one, or None.
+    ///
+    /// Loads are run on their own thread when a timeout is set.
+    pub fn with_load_timeout(mut self, timeout: Duration) -> Self {
+        self.load_timeout = Some(timeout);
+        self
+    }
+
+    /// Call the loader a second time if a load has not returned after `delay`, using whichever value arrives first.
+    ///
+    /// Loads are run on their own threads when hedging is enabled, the slower call is left to finish and its value is dropped. A hedge delay at or beyond the load timeout never hedges.
+    pub fn with_hedge_after(mut self, delay: Duration) -> Self {
+        self.hedge_after = Some(delay);
+        self
+    }
+
+    /// Run at most `max_in_flight` loader calls at once, with `overload` deciding what a get which needs another load does.
+    ///
+    /// A hedged call is only made if it fits within the limit.
+    pub fn with_max_in_flight(mut self, max_in_flight: usize, overload: OverloadBehavior) -> Self {
+        self.in_flight = InFlight::new(max_in_flight.max(1));
+        self.overload = overload;
+        self
+    }
+
+    /// Catch a panic of the loader called on the get's thread and fail the load like a loader which returned None, so `try_get` returns `CacheError::StoreUnavailable`, instead of unwinding through the get.
+    ///
+    /// Loads run on their own threads, with a load timeout or hedging, and prefetches are always isolated. The loader is never disabled, `CallbackPanics::Disable` isolates panics like `Isolate`.
+    pub fn with_callback_panics(mut self, panics: CallbackPanics) -> Self {
+        self.panics = panics;
+        self
+    }
+
+    /// Run at most `concurrency` prefetch loads at once, the default is 1.
+    pub fn with_prefetch_concurrency(mut self, concurrency: usize) -> Self {
+        self.prefetch_concurrency = concurrency.max(1);
+        self
+    }
+
+    /// Remember keys which fail to load in `negative` and skip loading them again until their TTL has passed.
+    ///
+    /// A successful load or a direct set forgets the key, so its next failure starts the backoff over.
+    pub fn with_negative_cache(mut self, negative: NegativeCache<K>) -> Self {
+        self.negative = Some(Arc::new(negative));
+        self
+    }
+
+    /// Wait `initial` before loading a key again after it fails, growing the wait exponentially with each failure in a row up to `max`.
+    ///
+    /// This is `with_negative_cache` with a NegativeCache holding as many keys as the wrapped cache.
+    pub fn with_retry_backoff(self, initial: Duration, max: Duration) -> Self {
+        let capacity = self.cache.stats().capacity.clamp(1, usize::MAX as u64) as usize;
+        self.with_negative_cache(NegativeCache::new(initial, capacity).with_max_ttl(max))
+    }
+
+    /// Load the given keys in the background if they are missing, so later gets find them.
+    ///
+    /// Keys are queued and loaded on at most `with_prefetch_concurrency` threads, keys which are already queued, recently failed to load, or arrive in the cache before their turn are skipped. This returns straight away.
+    pub fn prefetch(&self, keys: &[K]) {
+        let spawn = {
+            let mut queue = self.prefetch_queue.lock().unwrap();
+            for key in keys {
+                if !self.cache.contains_key(key)
+                    && !self.is_negative(key)
+                    && queue.pending.insert(key.clone())
+                {
+                    queue.keys.push_back(key.clone());
+                }
+            }
+            let spawn = self
+                .prefetch_concurrency
+                .saturating_sub(queue.workers)
+                .min(queue.keys.len());
+            queue.workers += spawn;
+            spawn
+        };
+        for _ in 0..spawn {
+            self.spawn_prefetcher();
+        }
+    }
+
+    /// Get a value, loading it if it is missing or due to be refreshed.
+    pub fn get(&self, key: &K) -> Option<Arc<V>> {
+        self.try_get(key).unwrap_or(None)
+    }
+
+    /// Get a value like `get`, returning `CacheError::StoreUnavailable` if there is no value to serve because the load timed out or the loader panicked.
+    ///
+    /// A loader returning None is not an error, the key simply has no value.
+    pub fn try_get(&self, key: &K) -> Result<Option<Arc<V>>, CacheError> {
+        self.try_get_from(key, self.cache.get(key))
+    }
+
+    /// Get a value like `get`, with the outcome of the cache lookup.
+    ///
+    /// A loaded value comes with the miss which caused the load, and a miss is `MissRejected` when the load was skipped because the key recently failed to load or the loader was overloaded.
+    pub fn get_with_outcome(&self, key: &K) -> (Option<Arc<V>>, GetOutcome) {
+        let (current, outcome) = self.cache.get_with_outcome(key);
+        let rejected = current.is_none() && self.is_negative(key);
+        match self.try_get_from(key, current) {
+            Ok(None) if rejected => (None, GetOutcome::MissRejected),
+            Ok(value) => (value, outcome),
+            Err(CacheError::Overloaded) => (None, GetOutcome::MissRejected),
+            Err(_) => (None, outcome),
+        }
+    }
+
+    fn try_get_from(&self, key: &K, current: Option<Arc<V>>) -> Result<Option<Arc<V>>, CacheError> {
+        if current.is_some() && !self.needs_refresh(key) {
+            return Ok(current);
+        }
+        if self.is_negative(key) {
+            self.negative_hits.fetch_add(1, Ordering::Relaxed);
+            if current.is_some() {
+                self.stale_served.fetch_add(1, Ordering::Relaxed);
+            }
+            return Ok(current);
+        }
+        let source = match current {
+            Some(_) => EntrySource::Refreshed,
+            None => EntrySource::Loaded,
+        };
+        let flight = match join_flight(&self.flights, key) {
+            Joined::Leader(flight) => flight,
+            Joined::Follower(flight) => {
+                self.coalesced.fetch_add(1, Ordering::Relaxed);
+                let result = match flight.wait(self.load_timeout) {
+                    FlightState::Loaded(Some(value)) => return Ok(Some(value)),
+                    FlightState::Loaded(None) => Ok(None),
+                    FlightState::Failed => Err(CacheError::StoreUnavailable),
+                    FlightState::Loading => {
+                        self.load_timeouts.fetch_add(1, Ordering::Relaxed);
+                        Err(CacheError::StoreUnavailable)
+                    }
+                };
+                if current.is_some() {
+                    self.stale_served.fetch_add(1, Ordering::Relaxed);
+                    return Ok(current);
+                }
+                return result;
+            }
+        };
+        let permit = match self.acquire_permit() {
+            Some(permit) => permit,
+            None => {
+                self.overloaded.fetch_add(1, Ordering::Relaxed);
+                if current.is_some() && self.overload != OverloadBehavior::ReturnError {
+                    self.stale_served.fetch_add(1, Ordering::Relaxed);
+                    return Ok(current);
+                }
+                return Err(CacheError::Overloaded);
+            }
+        };
+        match self.load(key, source, permit, flight) {
+            Ok(Some(value)) => Ok(Some(value)),
+            result => {
+                if current.is_some() {
+                    self.stale_served.fetch_add(1, Ordering::Relaxed);
+                    return Ok(current);
+                }
+                result
+            }
+        }
+    }
+
+    /// Set a value without calling the loader.
+    pub fn set(&self, key: K, value: V) -> Option<Arc<V>> {
+        if let Some(negative) = &self.negative {
+            negative.remove(&key);
+        }
+        Self::store(
+            &self.cache,
+            &self.loaded_at,
+            self.refresh_after.is_some(),
+            key,
+            value,
+            EntrySource::Inserted,
+        )
+    }
+
+    /// Remove a value from the cache.
+    pub fn remove(&self, key: &K) -> Option<Arc<V>> {
+        self.loaded_at.lock().unwrap().remove(key);
+        self.cache.remove(key)
+    }
+
+    /// Get the counts of loads, failures, timeouts and stale values served.
+    pub fn load_stats(&self) -> LoadStats {
+        LoadStats {
+            loads: self.loads.load(Ordering::Relaxed),
+            load_failures: self.load_failures.load(Ordering::Relaxed),
+            load_timeouts: self.load_timeouts.load(Ordering::Relaxed),
+            stale_served: self.stale_served.load(Ordering::Relaxed),
+            prefetches: self.prefetches.load(Ordering::Relaxed),
+            negative_hits: self.negative_hits.load(Ordering::Relaxed),
+            backing_off: self
+                .negative
+                .as_ref()
+                .map_or(0, |negative| negative.negative_len() as u64),
+            hedges: self.hedges.load(Ordering::Relaxed),
+            hedge_wins: self.hedge_wins.load(Ordering::Relaxed),
+            overloaded: self.overloaded.load(Ordering::Relaxed),
+            panicked_loads: self.panicked_loads.load(Ordering::Relaxed),
+            coalesced: self.coalesced.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Get the statistics of the wrapped cache.
+    pub fn stats(&self) -> CacheStats {
+        self.cache.stats()
+    }
+
+    /// Get a reference to the wrapped cache.
+    pub fn inner(&self) -> &C {
+        &self.cache
+    }
+
+    /// Get the cache of keys which recently failed to load, if there is one.
+    pub fn negative_cache(&self) -> Option<&NegativeCache<K>> {
+        self.negative.as_deref()
+    }
+
+    /// Check if a key recently failed to load.
+    fn is_negative(&self, key: &K) -> bool {
+        self.negative
+            .as_ref()
+            .is_some_and(|negative| negative.contains(key))
+    }
+
+    /// Take a slot for a loader call, waiting for one if the overload behaviour queues, or None if there is no slot.
+    fn acquire_permit(&self) -> Option<InFlightPermit> {
+        match self.overload {
+            OverloadBehavior::Queue => self.in_flight.acquire(self.load_timeout),
+            _ => self.in_flight.try_acquire(),
+        }
+    }
+
+    /// Check if an item was loaded longer ago than the refresh interval.
+    fn needs_refresh(&self, key: &K) -> bool {
+        match self.refresh_after {
+            Some(refresh_after) => self
+                .loaded_at
+                .lock()
+                .unwrap()
+                .get(key)
+                .is_none_or(|loaded_at| loaded_at.elapsed() >= refresh_after),
+            None => false,
+        }
+    }
+
+    /// Call the loader, storing the value with the given source and returning it if it arrives within the load timeout.
+    ///
+    /// The flight is landed once the value is stored, so a get arriving in between finds it in the cache rather than loading it again.
+    fn load(
+        &self,
+        key: &K,
+        source: EntrySource,
+        permit: InFlightPermit,
+        flight: FlightHandle<K, V>,
+    ) -> Result<Option<Arc<V>>, CacheError> {
+        self.loads.fetch_add(1, Ordering::Relaxed);
+        let flight = Arc::new(flight);
+        let value = if self.load_timeout.is_some() || self.hedge_after.is_some() {
+            match self.load_on_thread(key, source, permit, flight.clone()) {
+                Ok(value) => value,
+                Err(RecvTimeoutError::Timeout) => {
+                    self.load_timeouts.fetch_add(1, Ordering::Relaxed);
+                    return Err(CacheError::StoreUnavailable);
+                }
+                // every loader call panicked
+                Err(RecvTimeoutError::Disconnected) => {
+                    self.load_failures.fetch_add(1, Ordering::Relaxed);
+                    self.panicked_loads.fetch_add(1, Ordering::Relaxed);
+                    return Err(CacheError::StoreUnavailable);
+                }
+            }
+        } else {
+            let loaded = match self.panics {
+                CallbackPanics::Propagate => Ok((self.loader)(key)),
+                _ => panic::catch_unwind(AssertUnwindSafe(|| (self.loader)(key))),
+            };
+            drop(permit);
+            match loaded {
+                Err(_) => {
+                    self.load_failures.fetch_add(1, Ordering::Relaxed);
+                    self.panicked_loads.fetch_add(1, Ordering::Relaxed);
+                    if let Some(negative) = &self.negative {
+                        negative.insert(key.clone());
+                    }
+                    flight.land(FlightState::Failed);
+                    return Err(CacheError::StoreUnavailable);
+                }
+                Ok(value) => value,
+            }
+        };
+        let loaded = match value {
+            Some(value) => {
+                if let Some(negative) = &self.negative {
+                    negative.remove(key);
+                }
+                let loaded = Arc::new(value.clone());
+                Self::store(
+                    &self.cache,
+                    &self.loaded_at,
+                    self.refresh_after.is_some(),
+                    key.clone(),
+                    value,
+                    source,
+                );
+                Some(loaded)
+            }
+            None => {
+                self.load_failures.fetch_add(1, Ordering::Relaxed);
+                if let Some(negative) = &self.negative {
+                    negative.insert(key.clone());
+                }
+                None
+            }
+        };
+        flight.land(FlightState::Loaded(loaded.clone()));
+        Ok(loaded)
+    }
+
+    /// Run the loader on its own thread, hedged with a second call after the hedge delay, and wait up to the load timeout for the first value.
+    fn load_on_thread(
+        &self,
+        key: &K,
+        source: EntrySource,
+        permit: InFlightPermit,
+        flight: Arc<FlightHandle<K, V>>,
+    ) -> Result<Option<V>, RecvTimeoutError> {
+        let (sender, receiver) = mpsc::channel();
+        // set once a value has been handed to the get or stored, so the slower call drops its value
+        let settled = Arc::new(AtomicBool::new(false));
+        let deadline = self
+            .load_timeout
+            .map(|timeout| saturating_add(Instant::now(), timeout));
+        self.spawn_loader(
+            key,
+            sender.clone(),
+            settled.clone(),
+            false,
+            source,
+            permit,
+            flight.clone(),
+        );
+        let hedge_after = self.hedge_after.filter(|hedge_after| {
+            self.load_timeout
+                .is_none_or(|timeout| *hedge_after < timeout)
+        });
+        let first = match hedge_after {
+            Some(hedge_after) => match receiver.recv_timeout(hedge_after) {
+                Err(RecvTimeoutError::Timeout) => {
+                    if let Some(permit) = self.in_flight.try_acquire() {
+                        self.loads.fetch_add(1, Ordering::Relaxed);
+                        self.hedges.fetch_add(1, Ordering::Relaxed);
+                        self.spawn_loader(
+                            key,
+                            sender.clone(),
+                            settled.clone(),
+                            true,
+                            source,
+                            permit,
+                            flight.clone(),
+                        );
+                    }
+                    None
+                }
+                result => Some(result),
+            },
+            None => None,
+        };
+        // only the loader threads hold senders now, so the channel disconnects if they all panic
+        drop(sender);
+        drop(flight);
+        let result = first.unwrap_or_else(|| match deadline {
+            Some(deadline) => {
+                receiver.recv_timeout(deadline.saturating_duration_since(Instant::now()))
+            }
+            None => receiver.recv().map_err(|_| RecvTimeoutError::Disconnected),
+        });
+        match result {
+            Ok((hedged, value)) => {
+                settled.store(true, Ordering::SeqCst);
+                if hedged {
+                    self.hedge_wins.fetch_add(1, Ordering::Relaxed);
+                }
+                Ok(value)
+            }
+            Err(error) => {
+                if let Some(negative) = &self.negative {
+                    // recorded while the receiver is alive, so a late value always clears it afterwards
+                    negative.insert(key.clone());
+                }
+                Err(error)
+            }
+        }
+    }
+
+    /// Start a thread which calls the loader and sends its value, storing the value and landing the flight itself if the get stopped waiting.
+    #[allow(clippy::too_many_arguments)]
+    fn spawn_loader(
+        &self,
+        key: &K,
+        sender: mpsc::Sender<LoadResult<V>>,
+        settled: Arc<AtomicBool>,
+        hedged: bool,
+        source: EntrySource,
+        permit: InFlightPermit,
+        flight: Arc<FlightHandle<K, V>>,
+    ) {
+        let loader = self.loader.clone();
+        let cache = self.cache.clone();
+        let loaded_at = self.loaded_at.clone();
+        let track_loads = self.refresh_after.is_some();
+        let negative = self.negative.clone();
+        let key = key.clone();
+        thread::Builder::new()
+            .name("arcache-loader".to_string())
+            .spawn(move || {
+                let value = loader(&key);
+                drop(permit);
+                let landed = FlightState::Loaded(value.clone().map(Arc::new));
+                match sender.send((hedged, value)) {
+                    // the get stopped waiting, store the value so later gets find it
+                    Err(mpsc::SendError((_, Some(value))))
+                        if !settled.swap(true, Ordering::SeqCst) =>
+                    {
+                        if let Some(negative) = &negative {
+                            negative.remove(&key);
+                        }
+                        Self::store(&cache, &loaded_at, track_loads, key, value, source);
+                        flight.land(landed);
+                    }
+                    Err(mpsc::SendError((_, None))) if !settled.load(Ordering::SeqCst) => {
+                        flight.land(landed);
+                    }
+                    // the get, or the other call of a hedged load, stores the value and lands the flight
+                    _ => {}
+                }
+            })
+            .expect("failed to spawn loader thread");
+    }
+
+    /// Start a thread which loads queued prefetch keys until the queue is empty.
+    fn spawn_prefetcher(&self) {
+        let queue = self.prefetch_queue.clone();
+        let loader = self.loader.clone();
+        let cache = self.cache.clone();
+        let loaded_at = self.loaded_at.clone();
+        let track_loads = self.refresh_after.is_some();
+        let prefetches = self.prefetches.clone();
+        let panicked_loads = self.panicked_loads.clone();
+        let negative = self.negative.clone();
+        let in_flight = self.in_flight.clone();
+        let flights = self.flights.clone();
+        thread::Builder::new()
+            .name("arcache-prefetch".to_string())
+            .spawn(move || loop {
+                let key = {
+                    let mut queue = queue.lock().unwrap();
+                    match queue.keys.pop_front() {
+                        Some(key) => key,
+                        None => {
+                            // leave while holding the lock, so prefetch never counts a worker which is exiting
+                            queue.workers -= 1;
+                            return;
+                        }
+                    }
+                };
+                // a key which is cached, or which a get is already loading, is left alone
+                let flight = match cache.contains_key(&key) {
+                    true => None,
+                    false => match join_flight(&flights, &key) {
+                        Joined::Leader(flight) => Some(flight),
+                        Joined::Follower(_) => None,
+                    },
+                };
+                if let Some(flight) = flight {
+                    // a panicking loader only loses its own key
+                    let permit = in_flight.acquire(None);
+                    let loaded = panic::catch_unwind(AssertUnwindSafe(|| loader(&key)));
+                    drop(permit);
+                    match loaded {
+                        Ok(Some(value)) => {
+                            let landed = FlightState::Loaded(Some(Arc::new(value.clone())));
+                            prefetches.fetch_add(1, Ordering::Relaxed);
+                            if let Some(negative) = &negative {
+                                negative.remove(&key);
+                            }
+                            Self::store(
+                                &cache,
+                                &loaded_at,
+                                track_loads,
+                                key.clone(),
+                                value,
+                                EntrySource::Loaded,
+                            );
+                            flight.land(landed);
+                        }
+                        loaded => {
+                            if loaded.is_err() {
+                                panicked_loads.fetch_add(1, Ordering::Relaxed);
+                            }
+                            if let Some(negative) = &negative {
+                                negative.insert(key.clone());
+                            }
+                        }
+                    }
+                }
+                queue.lock().unwrap().pending.remove(&key);
+            })
+            .expect("failed to spawn prefetch thread");
+    }
+
+    /// Set a value in the cache with its source, recording when it was loaded if items are refreshed.
+    fn store(
+        cache: &C,
+        loaded_at: &Mutex<HashMap<K, Instant>>,
+        track_loads: bool,
+        key: K,
+        value: V,
+        source: EntrySource,
+    ) -> Option<Arc<V>> {
+        if track_loads {
+            let mut loaded_at = loaded_at.lock().unwrap();
+            // forget keys which the wrapped cache has since evicted
+            if loaded_at.len() as u64 >= cache.stats().capacity.saturating_mul(2).max(16) {
+                loaded_at.retain(|key, _| cache.contains_key(key));
+            }
+            loaded_at.insert(key.clone(), Instant::now());
+        }
+        cache.set_with_source(key, value, source)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::LRUCache;
+    use std::sync::atomic::AtomicBool;
+
+    /// A gate for loaders, each send on the sender lets one waiting loader through and dropping it lets them all through.
+    fn gate() -> (mpsc::Sender<()>, impl Fn() + Send + Sync) {
+        let (release, released) = mpsc::channel();
+        let released = Mutex::new(released);
+        (release, move || {
+            let _ = released.lock().unwrap().recv();
+        })
+    }
+
+    #[test]
+    fn test_loading_cache() {
+        let cache = LoadingCache::new(LRUCache::new(2), |key: &u64| (*key < 10).then_some(key * 2));
+        assert_eq!(cache.get(&3).map(|v| *v), Some(6));
+        assert_eq!(cache.get(&3).map(|v| *v), Some(6));
+        assert_eq!(cache.get(&10), None);
+        assert_eq!(
+            cache.load_stats(),
+            LoadStats {
+                loads: 2,
+                load_failures: 1,
+                ..LoadStats::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_loading_cache_entry_sources() {
+        let evicted = Arc::new(Mutex::new(Vec::new()));
+        let sink = evicted.clone();
+        let lru = LRUCache::new(2).with_eviction_listener(
+            move |event: &crate::RemovalEvent<u64, u64>| {
+                sink.lock().unwrap().push((event.key, event.source));
+            },
+        );
+        let cache =
+            LoadingCache::new(lru, |key: &u64| Some(*key)).with_refresh_after(Duration::ZERO);
+        cache.set(1, 1);
+        cache.get(&2);
+        assert_eq!(
+            cache.inner().entry_info(&1).unwrap().source,
+            EntrySource::Inserted
+        );
+        assert_eq!(
+            cache.inner().entry_info(&2).unwrap().source,
+            EntrySource::Loaded
+        );
+        cache.get(&2);
+        assert_eq!(
+            cache.inner().entry_info(&2).unwrap().source,
+            EntrySource::Refreshed
+        );
+        cache.inner().set(3, 3);
+        cache.inner().set(4, 4);
+        assert_eq!(
+            *evicted.lock().unwrap(),
+            vec![(1, EntrySource::Inserted), (2, EntrySource::Refreshed)]
+        );
+    }
+
+    #[test]
+    fn test_loading_cache_timeout_serves_stale() {
+        let slow = Arc::new(AtomicBool::new(false));
+        let loader_slow = slow.clone();
+        let (release, wait) = gate();
+        let cache = LoadingCache::new(LRUCache::new(2), move |key: &u64| {
+            if loader_slow.load(Ordering::Relaxed) {
+                wait();
+                Some(key + 100)
+            } else {
+                Some(*key)
+            }
+        })
+        .with_refresh_after(Duration::ZERO)
+        .with_load_timeout(Duration::from_millis(20));
+
+        assert_eq!(cache.get(&1).map(|v| *v), Some(1));
+        slow.store(true, Ordering::Relaxed);
+        assert_eq!(cache.get(&1).map(|v| *v), Some(1));
+        assert_eq!(cache.try_get(&2), Err(CacheError::StoreUnavailable));
+        let stats = cache.load_stats();
+        assert_eq!(stats.load_timeouts, 2);
+        assert_eq!(stats.stale_served, 1);
+
+        // the timed out loads finish in the background and are stored, gets wait on them until they land
+        drop(release);
+        while cache.get(&1).map(|v| *v) != Some(101) {}
+        while cache.try_get(&2).is_err() {}
+        assert_eq!(cache.inner().get(&1).map(|v| *v), Some(101));
+        assert_eq!(cache.inner().get(&2).map(|v| *v), Some(102));
+    }
+
+    #[test]
+    fn test_loading_cache_coalesces_concurrent_loads() {
+        let calls = Arc::new(AtomicU64::new(0));
+        let loader_calls = calls.clone();
+        let (release, wait) = gate();
+        let cache = Arc::new(LoadingCache::new(LRUCache::new(10), move |key: &u64| {
+            loader_calls.fetch_add(1, Ordering::SeqCst);
+            wait();
+            Some(*key)
+        }));
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                let cache = cache.clone();
+                thread::spawn(move || cache.get(&1).map(|v| *v))
+            })
+            .collect();
+        // the load finishes once every other get has joined it
+        while cache.load_stats().coalesced < 7 {
+            thread::yield_now();
+        }
+        drop(release);
+        for thread in threads {
+            assert_eq!(thread.join().unwrap(), Some(1));
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        let stats = cache.load_stats();
+        assert_eq!((stats.loads, stats.coalesced), (1, 7));
+
+        // gets retrying after a timeout wait on the load still running rather than starting another
+        calls.store(0, Ordering::SeqCst);
+        let (release, wait) = gate();
+        let cache = LoadingCache::new(LRUCache::new(10), {
+            let calls = calls.clone();
+            move |key: &u64| {
+                calls.fetch_add(1, Ordering::SeqCst);
+                wait();
+                Some(*key)
+            }
+        })
+        .with_load_timeout(Duration::from_millis(10));
+        for _ in 0..3 {
+            assert_eq!(cache.try_get(&2), Err(CacheError::StoreUnavailable));
+        }
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+        drop(release);
+        while cache.try_get(&2).is_err() {}
+        assert_eq!(cache.get(&2).map(|v| *v), Some(2));
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_loading_cache_refresh_failure_serves_stale() {
+        let cache = LoadingCache::new(LRUCache::new(2), |_: &u64| None::<u64>)
+            .with_refresh_after(Duration::ZERO);
+        cache.set(1, 1);
+        assert_eq!(cache.get(&1).map(|v| *v), Some(1));
+        assert_eq!(cache.load_stats().load_failures, 1);
+        assert_eq!(cache.load_stats().stale_served, 1);
+    }
+
+    #[test]
+    fn test_loading_cache_isolates_loader_panics() {
+        let cache = LoadingCache::new(LRUCache::new(2), |key: &u64| {
+            assert!(*key < 10, "key out of range");
+            Some(*key)
+        })
+        .with_callback_panics(CallbackPanics::Isolate)
+        .with_refresh_after(Duration::ZERO);
+        assert_eq!(cache.try_get(&10), Err(CacheError::StoreUnavailable));
+        cache.set(11, 11);
+        // a refresh whose loader panics serves the stale value
+        assert_eq!(cache.get(&11).map(|v| *v), Some(11));
+        assert_eq!(cache.get(&1).map(|v| *v), Some(1));
+        let stats = cache.load_stats();
+        assert_eq!((stats.panicked_loads, stats.stale_served), (2, 1));
+    }
+
+    #[test]
+    fn test_loading_cache_negative_cache() {
+        let calls = Arc::new(AtomicU64::new(0));
+        let loader_calls = calls.clone();
+        let cache = LoadingCache::new(LRUCache::new(10), move |key: &u64| {
+            loader_calls.fetch_add(1, Ordering::Relaxed);
+            (*key < 10).then_some(*key)
+        })
+        .with_negative_cache(NegativeCache::new(Duration::from_secs(60), 10));
+        assert_eq!(cache.get(&10), None);
+        assert_eq!(cache.get(&10), None);
+        cache.prefetch(&[10]);
+        assert_eq!(calls.load(Ordering::Relaxed), 1);
+        let stats = cache.load_stats();
+        assert_eq!((stats.load_failures, stats.negative_hits), (1, 1));
+        assert!(cache.negative_cache().unwrap().contains(&10));
+
+        assert_eq!(
+            cache.get_with_outcome(&10),
+            (None, GetOutcome::MissRejected)
+        );
+        assert_eq!(
+            cache.get_with_outcome(&3),
+            (Some(Arc::new(3)), GetOutcome::MissAbsent)
+        );
+        assert_eq!(
+            cache.get_with_outcome(&3),
+            (Some(Arc::new(3)), GetOutcome::Hit)
+        );
+
+        // a direct set forgets the failure
+        cache.set(10, 10);
+        assert!(!cache.negative_cache().unwrap().contains(&10));
+        assert_eq!(cache.get(&10).map(|v| *v), Some(10));
+    }
+
+    #[test]
+    fn test_loading_cache_retry_backoff() {
+        let down = Arc::new(AtomicBool::new(true));
+        let loader_down = down.clone();
+        let cache = LoadingCache::new(LRUCache::new(10), move |key: &u64| {
+            (!loader_down.load(Ordering::Relaxed)).then_some(*key)
+        })
+        .with_retry_backoff(Duration::from_millis(20), Duration::from_secs(1));
+        for key in 0..3 {
+            assert_eq!(cache.get(&key), None);
+            assert_eq!(cache.get(&key), None);
+        }
+        let stats = cache.load_stats();
+        assert_eq!(
+            (stats.loads, stats.negative_hits, stats.backing_off),
+            (3, 3, 3)
+        );
+
+        down.store(false, Ordering::Relaxed);
+        thread::sleep(Duration::from_millis(30));
+        assert_eq!(cache.get(&0).map(|v| *v), Some(0));
+        let stats = cache.load_stats();
+        assert_eq!((stats.loads, stats.backing_off), (4, 0));
+        assert_eq!(cache.negative_cache().unwrap().failures(&0), None);
+    }
+
+    #[test]
+    fn test_loading_cache_hedged_load() {
+        let calls = Arc::new(AtomicU64::new(0));
+        let loader_calls = calls.clone();
+        let (release, wait) = gate();
+        // the slow call's value holds the token, so the test sees when it is dropped
+        let token = Arc::new(());
+        let slow_token = Arc::downgrade(&token);
+        // the first call for each key waits for the test, the hedged call is fast
+        let cache = LoadingCache::new(LRUCache::new(10), move |key: &u64| {
+            if loader_calls
+                .fetch_add(1, Ordering::SeqCst)
+                .is_multiple_of(2)
+            {
+                wait();
+                Some((key + 100, slow_token.upgrade().unwrap()))
+            } else {
+                Some((*key, Arc::new(())))
+            }
+        })
+        .with_load_timeout(Duration::from_secs(5))
+        .with_hedge_after(Duration::from_millis(20));
+        assert_eq!(cache.get(&1).map(|v| v.0), Some(1));
+        let stats = cache.load_stats();
+        assert_eq!((stats.loads, stats.hedges, stats.hedge_wins), (2, 1, 1));
+
+        // the slow call's value is dropped rather than replacing the hedged one
+        drop(release);
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while Arc::strong_count(&token) > 1 && Instant::now() < deadline {
+            thread::yield_now();
+        }
+        assert_eq!(Arc::strong_count(&token), 1);
+        assert_eq!(cache.get(&1).map(|v| v.0), Some(1));
+
+        // a timeout too long for an Instant waits for the loader
+        let cache = LoadingCache::new(LRUCache::new(10), |key: &u64| Some(*key))
+            .with_load_timeout(Duration::MAX)
+            .with_hedge_after(Duration::from_millis(20));
+        assert_eq!(cache.get(&2).map(|v| *v), Some(2));
+    }
+
+    #[test]
+    fn test_loading_cache_max_in_flight() {
+        let running = Arc::new(AtomicU64::new(0));
+        let most = Arc::new(AtomicU64::new(0));
+        let (loader_running, loader_most) = (running.clone(), most.clone());
+        let cache = Arc::new(
+            LoadingCache::new(LRUCache::new(10), move |key: &u64| {
+                let now = loader_running.fetch_add(1, Ordering::SeqCst) + 1;
+                loader_most.fetch_max(now, Ordering::SeqCst);
+                thread::sleep(Duration::from_millis(10));
+                loader_running.fetch_sub(1, Ordering::SeqCst);
+                Some(*key)
+            })
+            .with_max_in_flight(2, OverloadBehavior::Queue),
+        );
+        let threads: Vec<_> = (0..6)
+            .map(|key| {
+                let cache = cache.clone();
+                thread::spawn(move || cache.get(&key).map(|v| *v))
+            })
+            .collect();
+        for (key, thread) in threads.into_iter().enumerate() {
+            assert_eq!(thread.join().unwrap(), Some(key as u64));
+        }
+        assert!(most.load(Ordering::SeqCst) <= 2);
+        assert_eq!(cache.load_stats().overloaded, 0);
+    }
+
+    #[test]
+    fn test_loading_cache_overload_serves_stale() {
+        let started = Arc::new(AtomicBool::new(false));
+        let blocked = Arc::new(AtomicBool::new(true));
+        let (loader_started, loader_blocked) = (started.clone(), blocked.clone());
+        let cache = Arc::new(
+            LoadingCache::new(LRUCache::new(10), move |key: &u64| {
+                loader_started.store(true, Ordering::SeqCst);
+                while *key == 1 && loader_blocked.load(Ordering::SeqCst) {
+                    thread::sleep(Duration::from_millis(1));
+                }
+                Some(key + 100)
+            })
+            .with_refresh_after(Duration::ZERO)
+            .with_max_in_flight(1, OverloadBehavior::ServeStale),
+        );
+        cache.set(2, 2);
+        let loading = {
+            let cache = cache.clone();
+            thread::spawn(move || cache.get(&1).map(|v| *v))
+        };
+        while !started.load(Ordering::SeqCst) {
+            thread::yield_now();
+        }
+        assert_eq!(cache.try_get(&2), Ok(Some(Arc::new(2))));
+        assert_eq!(cache.try_get(&3), Err(CacheError::Overloaded));
+        blocked.store(false, Ordering::SeqCst);
+        assert_eq!(loading.join().unwrap(), Some(101));
+        let stats = cache.load_stats();
+        assert_eq!((stats.overloaded, stats.stale_served), (2, 1));
+        assert_eq!(cache.get(&3).map(|v| *v), Some(103));
+    }
+
+    #[test]
+    fn test_loading_cache_prefetch() {
+        let (started, loading) = mpsc::channel();
+        let (release, wait) = gate();
+        // the load of 3 waits for the test, so a get can join it
+        let cache = LoadingCache::new(LRUCache::new(10), move |key: &u64| {
+            started.send(*key).unwrap();
+            if *key == 3 {
+                wait();
+            }
+            Some(key * 2)
+        });
+        cache.set(1, 1);
+        cache.prefetch(&[1, 2, 3, 3]);
+        // the single prefetcher stores 2 before it loads 3
+        assert_eq!(loading.recv(), Ok(2));
+        assert_eq!(loading.recv(), Ok(3));
+        assert_eq!(cache.get(&1).map(|v| *v), Some(1));
+        assert_eq!(cache.get(&2).map(|v| *v), Some(4));
+        thread::scope(|scope| {
+            let get = scope.spawn(|| cache.get(&3).map(|v| *v));
+            while cache.load_stats().coalesced == 0 {
+                thread::yield_now();
+            }
+            drop(release);
+            assert_eq!(get.join().unwrap(), Some(6));
+        });
+        let stats = cache.load_stats();
+        assert_eq!((stats.loads, stats.prefetches), (0, 2));
+    }
+
+    #[test]
+    fn test_loading_cache_prefetch_concurrency() {
+        let running = Arc::new(AtomicU64::new(0));
+        let most = Arc::new(AtomicU64::new(0));
+        let (loader_running, loader_most) = (running.clone(), most.clone());
+        let (started, loading) = mpsc::channel();
+        let (release, wait) = gate();
+        let cache = LoadingCache::new(LRUCache::new(20), move |key: &u64| {
+            let now = loader_running.fetch_add(1, Ordering::SeqCst) + 1;
+            loader_most.fetch_max(now, Ordering::SeqCst);
+            started.send(*key).unwrap();
+            wait();
+            loader_running.fetch_sub(1, Ordering::SeqCst);
+            Some(*key)
+        })
+        .with_prefetch_concurrency(2);
+        let keys: Vec<u64> = (0..10).collect();
+        cache.prefetch(&keys);
+        cache.prefetch(&keys);
+        // both prefetchers wait in the loader, then the loads are let through one at a time
+        let mut loaded = vec![loading.recv().unwrap(), loading.recv().unwrap()];
+        for _ in 2..10 {
+            release.send(()).unwrap();
+            loaded.push(loading.recv().unwrap());
+        }
+        loaded.sort();
+        assert_eq!(loaded, keys);
+        assert_eq!(most.load(Ordering::SeqCst), 2);
+
+        // the loader is dropped once the prefetchers leave, without loading any key twice
+        drop((cache, release));
+        assert!(loading.recv().is_err());
+    }
+}
@@ -1,14 +1,44 @@
-use crate::cache::{Cache, CacheStats};
+use crate::cache::fairness::InsertQueue;
+use crate::cache::guard::EntryGuard;
+use crate::cache::latency::{LatencyTracker, OperationLatencies};
+use crate::cache::listener::{
+    AccessListener, AccessListeners, CallbackPanics, EntrySource, EvictionListener, RemovalCause,
+};
+use crate::cache::metadata::{EntryInfo, EntryMetadata};
+use crate::cache::policy_core::{policy_methods, PolicyCore};
+use crate::cache::recency::RecencyBuffer;
+use crate::cache::trace::{AccessTrace, TraceOperation};
+use crate::cache::{
+    shed_count, split_capacity, Cache, CacheStats, DetailedStats, FullBehavior, GetOutcome,
+    IterationOrder, TryCache, VersionedCache,
+};
+use crate::clock::Clock;
+use crate::error::CacheError;
+use crate::sync::{RwLock, RwLockWriteGuard};
 use linked_hash_map::LinkedHashMap;
+use std::any::Any;
+use std::collections::HashMap;
+use std::fmt::Display;
 use std::hash::Hash;
-use std::sync::{Arc, Mutex};
+use std::io::{self, Write};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+// weigher type, gives the weight an item counts towards the cache's maximum weight
+type Weigher<K, V> = Arc<dyn Fn(&K, &V) -> u64 + Send + Sync>;
 
 /// The inner data structure for the LRUCache.
 struct LRUCacheInner<K: Eq + Hash + Clone + Sync + Send, V: Send + Sync> {
     capacity: u64,
+    max_weight: u64,
+    weigher: Option<Weigher<K, V>>,
+    weights: HashMap<K, u64>,
+    total_weight: u64,
     key_value_map: LinkedHashMap<K, Arc<V>>,
-    hits: u64,
-    misses: u64,
+    probation: LinkedHashMap<K, Arc<V>>,
+    probation_capacity: Option<u64>,
+    core: PolicyCore<K, V>,
+    pins: HashMap<K, usize>,
 }
 
 impl<K: Eq + Hash + Clone + Sync + Send, V: Send + Sync> LRUCacheInner<K, V> {
@@ -16,18 +46,184 @@ impl<K: Eq + Hash + Clone + Sync + Send, V: Send + Sync> LRUCacheInner<K, V> {
     fn new(capacity: u64) -> Self {
         LRUCacheInner {
             capacity,
+            max_weight: u64::MAX,
+            weigher: None,
+            weights: HashMap::new(),
+            total_weight: 0,
             key_value_map: LinkedHashMap::with_capacity(capacity as usize),
-            hits: 0,
-            misses: 0,
+            probation: LinkedHashMap::new(),
+            probation_capacity: None,
+            core: PolicyCore::new(),
+            pins: HashMap::new(),
+        }
+    }
+
+    /// Check if a key is held, in the main region or on probation.
+    fn holds(&self, key: &K) -> bool {
+        self.key_value_map.contains_key(key) || self.probation.contains_key(key)
+    }
+
+    /// Get the number of items in the cache, including those on probation.
+    fn len(&self) -> u64 {
+        (self.key_value_map.len() + self.probation.len()) as u64
+    }
+
+    /// Check if the cache holds more items or more weight than it may.
+    fn over_limits(&self) -> bool {
+        self.len() > self.capacity || self.total_weight > self.max_weight
+    }
+
+    /// Move an item from probation into the main region, returning its value if it was on probation.
+    fn promote(&mut self, key: &K) -> Option<Arc<V>> {
+        let value = self.probation.remove(key)?;
+        self.key_value_map.insert(key.clone(), value.clone());
+        self.demote_overflow();
+        Some(value)
+    }
+
+    /// Move the least recently used items of the main region back onto probation while the main region is over its share of the capacity.
+    fn demote_overflow(&mut self) {
+        if let Some(probation_capacity) = self.probation_capacity {
+            let main_capacity = self.capacity.saturating_sub(probation_capacity);
+            while self.key_value_map.len() as u64 > main_capacity {
+                match self.key_value_map.pop_front() {
+                    Some((key, value)) => {
+                        self.probation.insert(key, value);
+                    }
+                    None => break,
+                }
+            }
+        }
+    }
+
+    /// Remove an item from either region, forgetting its priority and deadline.
+    fn remove_entry(&mut self, key: &K) -> Option<Arc<V>> {
+        self.core.forget(key);
+        if let Some(weight) = self.weights.remove(key) {
+            self.total_weight -= weight;
+        }
+        match self.key_value_map.remove(key) {
+            Some(value) => Some(value),
+            None => self.probation.remove(key),
         }
     }
+
+    /// Count the hits and misses recorded in the recency buffer and move the items accessed to the most recently used end, in the order they were accessed.
+    fn apply_accesses(&mut self, recency: &RecencyBuffer<K>) {
+        let (hits, misses) = recency.take_counts();
+        for _ in 0..hits {
+            self.core.stats.record_hit();
+        }
+        for _ in 0..misses {
+            self.core.stats.record_miss();
+        }
+        for (key, at) in recency.drain() {
+            if self.key_value_map.get_refresh(&key).is_some() {
+                self.core.expiries.touch(&key, at);
+                self.core.metadata.touch(&key, at);
+            }
+        }
+    }
+
+    /// Remove every item which has passed its deadline.
+    fn purge_expired(&mut self) {
+        let now = self.core.clock.now();
+        while let Some(key) = self.core.expiries.pop_expired(now) {
+            if let Some(value) = self.remove_entry(&key) {
+                self.core
+                    .listeners
+                    .notify(&key, &value, RemovalCause::Expired);
+                self.core.stats.record_expiration();
+            }
+        }
+    }
+
+    /// Remove every item, notifying listeners and returning the removed items.
+    fn clear_entries(&mut self) -> Vec<(K, Arc<V>)> {
+        self.core.clear();
+        self.weights.clear();
+        self.total_weight = 0;
+        let mut cleared = Vec::with_capacity(self.len() as usize);
+        cleared.extend(self.probation.drain());
+        cleared.extend(self.key_value_map.drain());
+        for (key, value) in &cleared {
+            self.core
+                .listeners
+                .notify(key, value, RemovalCause::Cleared);
+        }
+        cleared
+    }
+
+    /// Evict the least recently used items until the cache is within its capacity and maximum weight, items on probation are evicted first.
+    ///
+    /// If priorities have been assigned, the least recently used item with the lowest priority is evicted instead. Pinned items are never evicted, so the cache stays over capacity if every item is pinned.
+    fn evict_to_capacity(&mut self) -> Vec<(K, Arc<V>)> {
+        self.evict_batch(usize::MAX, None)
+    }
+
+    /// Evict like `evict_to_capacity`, but stop after `limit` items even if the cache is still over its limits.
+    ///
+    /// The `spared` key, the one a set has just stored, is never evicted, so a new item is not evicted to make room for itself.
+    fn evict_batch(&mut self, limit: usize, spared: Option<&K>) -> Vec<(K, Arc<V>)> {
+        let mut evicted = Vec::new();
+        while evicted.len() < limit && self.over_limits() {
+            match self.evict(spared) {
+                Some(item) => evicted.push(item),
+                None => break,
+            }
+        }
+        evicted
+    }
+
+    /// Evict items like `evict_to_capacity` until at most `len` are left, whatever the cache's limits.
+    fn evict_to(&mut self, len: u64) -> Vec<(K, Arc<V>)> {
+        let mut evicted = Vec::new();
+        while self.len() > len {
+            match self.evict(None) {
+                Some(item) => evicted.push(item),
+                None => break,
+            }
+        }
+        evicted
+    }
+
+    /// Evict the next item other than `spared`, or None if every other item is pinned.
+    fn evict(&mut self, spared: Option<&K>) -> Option<(K, Arc<V>)> {
+        let pins = &self.pins;
+        let eligible = |key: &K| !pins.contains_key(key) && spared != Some(key);
+        let candidates = self.probation.keys().chain(self.key_value_map.keys());
+        let key = match self
+            .core
+            .priorities
+            .pick_where(self.len() as usize, candidates, eligible)
+        {
+            Some(key) => key,
+            None => self
+                .probation
+                .keys()
+                .chain(self.key_value_map.keys())
+                .find(|key| eligible(key))?
+                .clone(),
+        };
+        let lifetime = self.core.metadata.lifetime(&key, self.core.clock.now());
+        let value = self.remove_entry(&key)?;
+        self.core
+            .listeners
+            .notify(&key, &value, RemovalCause::Evicted);
+        self.core.stats.record_eviction(lifetime);
+        Some((key, value))
+    }
 }
 
 /// LRUCache is a cache that uses the Least Frequently Recently (LRU) algorithm to evict items.
 ///
 /// When the cache is full, the item which was least recently accessed is removed to make space for the new item.
 ///
-/// All mutability is handled internally with a Mutex, so the cache can be shared between threads. Values are returned as Arcs to allow for shared ownership.
+/// Optionally the cache can be made scan resistant with `with_scan_resistance`, new items then start in a small probationary region and are only promoted to the main region when they are accessed again.
+///
+/// For read heavy workloads `with_batched_recency` lets hits share the lock by recording accesses in a buffer and reordering items in batches, at the cost of an approximate recency order.
+///
+/// All mutability is handled internally with a RwLock, so the cache can be shared between threads. Values are returned as Arcs to allow for shared ownership.
 ///
 /// Example:
 /// ```
@@ -46,14 +242,481 @@ impl<K: Eq + Hash + Clone + Sync + Send, V: Send + Sync> LRUCacheInner<K, V> {
 /// println!("{:?}", cache.stats());
 /// ```
 pub struct LRUCache<K: Eq + Hash + Clone + Sync + Send, V: Send + Sync> {
-    inner: Mutex<LRUCacheInner<K, V>>,
+    inner: Arc<RwLock<LRUCacheInner<K, V>>>,
+    access_listeners: Arc<AccessListeners<K>>,
+    latency: Arc<LatencyTracker>,
+    trace: Arc<AccessTrace>,
+    recency: Arc<Option<RecencyBuffer<K>>>,
+    inserts: Arc<Option<InsertQueue>>,
 }
 
 impl<K: Eq + Hash + Clone + Sync + Send, V: Send + Sync> LRUCache<K, V> {
     /// Create a new LRUCache with the given capacity.
     pub fn new(capacity: u64) -> Self {
         LRUCache {
-            inner: Mutex::new(LRUCacheInner::new(capacity)),
+            inner: Arc::new(RwLock::new(LRUCacheInner::new(capacity))),
+            access_listeners: Arc::new(AccessListeners::new()),
+            latency: Arc::new(LatencyTracker::new()),
+            trace: Arc::new(AccessTrace::new()),
+            recency: Arc::new(None),
+            inserts: Arc::new(None),
+        }
+    }
+
+    /// Make the cache scan resistant by reserving `probation_capacity` of the capacity for new items.
+    ///
+    /// New items are inserted on probation and only move into the main region on their second access. When the cache is full items on probation are evicted first, so a large scan of keys which are only read once cannot flush the main region.
+    pub fn with_scan_resistance(self, probation_capacity: u64) -> Self {
+        {
+            let mut inner = self.inner.write().unwrap();
+            inner.probation_capacity = Some(probation_capacity);
+        }
+        self
+    }
+
+    /// Record the accesses of gets in a buffer of `buffer_size` slots and only reorder items when the buffer fills or before any write.
+    ///
+    /// Gets are then served under a shared lock, so readers never wait on each other, and only pinned gets, hits on probation and gets of expired items take the exclusive lock. With a time-to-idle every get takes the exclusive lock, as each hit must push back its item's deadline. The recency order is approximate, accesses made while readers collide on a slot are dropped, and items hit since the last batch may be evicted as if they had not been. Hits are always counted.
+    pub fn with_batched_recency(mut self, buffer_size: usize) -> Self {
+        *Arc::get_mut(&mut self.recency)
+            .expect("batched recency must be enabled before the cache is cloned") =
+            Some(RecencyBuffer::new(buffer_size));
+        self
+    }
+
+    /// Serve sets in the order they arrive and evict at most `eviction_batch` items each time a set holds the cache lock.
+    ///
+    /// By default the thread which has just released the lock can take it straight back, and a set which has to evict many items, such as a heavy item in a cache built `with_max_weight`, holds the lock until it is done. With fair inserts a set which has more to evict releases the lock between batches and queues again behind the sets which arrived meanwhile, so no set waits behind more than one batch per set ahead of it. The cache may be over its limits between batches, and evicted values are dropped after the lock is released. Gets do not queue.
+    pub fn with_fair_inserts(mut self, eviction_batch: usize) -> Self {
+        *Arc::get_mut(&mut self.inserts)
+            .expect("fair inserts must be enabled before the cache is cloned") =
+            Some(InsertQueue::new(eviction_batch));
+        self
+    }
+
+    policy_methods!(read, write, ordered: lock_ordered);
+
+    /// Record the key hash and time of every get, set and remove, keeping up to `capacity` events until they are written out with `export_access_log`.
+    ///
+    /// Once the buffer is full the oldest events are dropped, so a trace always covers the latest accesses.
+    pub fn with_access_trace(mut self, capacity: usize) -> Self {
+        Arc::get_mut(&mut self.trace)
+            .expect("access tracing must be enabled before the cache is cloned")
+            .enable(capacity);
+        self
+    }
+
+    /// Write the recorded accesses to `writer` as an access log and empty the trace buffer, returning the number of events written.
+    ///
+    /// The format is described by `ACCESS_LOG_VERSION`, keys are only written as seeded hashes. Nothing is written if the cache was not built `with_access_trace`.
+    pub fn export_access_log<W: Write>(&self, writer: W) -> io::Result<usize> {
+        self.trace.export(writer)
+    }
+
+    /// Expire items which have not been set or hit by a get for `time_to_idle`, as well as when their TTL passes.
+    pub fn with_time_to_idle(self, time_to_idle: Duration) -> Self {
+        self.inner
+            .write()
+            .unwrap()
+            .core
+            .expiries
+            .set_time_to_idle(time_to_idle);
+        self
+    }
+
+    /// Limit the total weight of the items as well as their number, `weigher` gives the weight of each item as it is set.
+    ///
+    /// When either limit is passed the least recently used items are evicted until the cache is within both, an item heavier than `max_weight` is evicted on its own as soon as it is set. Weights are taken once per set, so a weigher should only depend on the key and value.
+    ///
+    /// A weigher which panics is handled like a panicking eviction listener, per `with_callback_panics`. When the panic is caught the set stores nothing and `try_set` returns `CacheError::CallbackPanicked`.
+    pub fn with_max_weight(
+        self,
+        max_weight: u64,
+        weigher: impl Fn(&K, &V) -> u64 + Send + Sync + 'static,
+    ) -> Self {
+        {
+            let mut inner = self.inner.write().unwrap();
+            inner.max_weight = max_weight;
+            inner.weigher = Some(Arc::new(weigher));
+        }
+        self
+    }
+
+    /// Get the number of sets holding or waiting for a turn, 0 unless the cache was built `with_fair_inserts`.
+    pub fn queued_inserts(&self) -> u64 {
+        self.inserts
+            .as_ref()
+            .as_ref()
+            .map_or(0, InsertQueue::queued)
+    }
+
+    /// Get the total weight of the items, 0 unless the cache was built `with_max_weight`.
+    pub fn total_weight(&self) -> u64 {
+        self.inner.read().unwrap().total_weight
+    }
+
+    /// Set a value in the cache with a priority, when the cache is full lower priority items are evicted before higher priority ones.
+    ///
+    /// Within a priority the least recently used item is evicted first. New items set with `set` have the lowest priority, `DEFAULT_PRIORITY`, and setting a key again without a priority keeps the one it has.
+    pub fn set_with_priority(&self, key: K, value: V, priority: u32) -> Option<Arc<V>> {
+        self.insert(
+            key,
+            Arc::new(value),
+            Some(priority),
+            None,
+            None,
+            None,
+            EntrySource::Inserted,
+        )
+        .unwrap_or(None)
+    }
+
+    /// Set a value in the cache which expires after the given time-to-live, overriding the cache's default TTL.
+    ///
+    /// Expired items are never returned and are removed before any live item is evicted.
+    pub fn set_with_ttl(&self, key: K, value: V, ttl: Duration) -> Option<Arc<V>> {
+        self.insert(
+            key,
+            Arc::new(value),
+            None,
+            Some(ttl),
+            None,
+            None,
+            EntrySource::Inserted,
+        )
+        .unwrap_or(None)
+    }
+
+    /// Set a value in the cache with a metadata value attached, which can be read back with `entry_info`.
+    ///
+    /// Metadata is dropped when the item leaves the cache or is set again without metadata.
+    pub fn set_with_metadata(
+        &self,
+        key: K,
+        value: V,
+        metadata: impl Any + Send + Sync,
+    ) -> Option<Arc<V>> {
+        self.insert(
+            key,
+            Arc::new(value),
+            None,
+            None,
+            Some(Arc::new(metadata)),
+            None,
+            EntrySource::Inserted,
+        )
+        .unwrap_or(None)
+    }
+
+    /// Get a value like `get`, tagging the access with `context`, such as the ID of the request which made it.
+    ///
+    /// The context is written with the access in the access log, and with the `log` feature enabled a debug event with the `arcache` target reports the cache, the hit or miss and the context, so misses can be joined with slow requests in a trace backend.
+    pub fn get_traced(&self, key: &K, context: &dyn Display) -> Option<Arc<V>> {
+        self.lookup(key, false, Some(context))
+    }
+
+    /// Get a value and pin its item, so it cannot be evicted until the returned guard is dropped.
+    ///
+    /// While the cache is full of pinned items new items are still accepted, the cache shrinks back to its capacity as guards are dropped and new items are set. Pins are counted, so an item stays pinned while any of its guards are alive.
+    pub fn get_pinned(&self, key: &K) -> Option<EntryGuard<'_, V>> {
+        let value = self.lookup(key, true, None)?;
+        let key = key.clone();
+        Some(EntryGuard::new(value, move || {
+            let mut inner = self.inner.write().unwrap();
+            if let Some(count) = inner.pins.get_mut(&key) {
+                *count -= 1;
+                if *count == 0 {
+                    inner.pins.remove(&key);
+                }
+            }
+        }))
+    }
+
+    /// Get how close an item is to eviction, 0 is the next item the policy would evict, without counting a hit or miss or updating its position.
+    ///
+    /// Items on probation are evicted before the main region. Priorities are not taken into account.
+    pub fn age_rank(&self, key: &K) -> Option<usize> {
+        let inner = self.lock_ordered();
+        if inner.core.expiries.is_expired(key, inner.core.clock.now()) {
+            return None;
+        }
+        inner
+            .probation
+            .keys()
+            .chain(inner.key_value_map.keys())
+            .position(|candidate| candidate == key)
+    }
+
+    /// Get when an item was last set or hit by a get, without counting a hit or miss or updating its position.
+    pub fn last_accessed(&self, key: &K) -> Option<Instant> {
+        let inner = self.lock_ordered();
+        if inner.core.expiries.is_expired(key, inner.core.clock.now()) {
+            return None;
+        }
+        inner.core.metadata.last_accessed(key)
+    }
+
+    /// Move every item matching `predicate` into a new cache, whose capacity is in proportion to the share of items moved.
+    ///
+    /// Moved items keep their priority, remaining TTL and recency, and items on probation stay on probation. Listeners on this cache are notified with `RemovalCause::Removed`, they are not carried over to the new cache.
+    pub fn split_off<F: Fn(&K, &V) -> bool>(&self, predicate: F) -> Self {
+        let mut inner = self.lock_ordered();
+        inner.purge_expired();
+        let keys: Vec<K> = inner
+            .probation
+            .iter()
+            .chain(inner.key_value_map.iter())
+            .filter(|(key, value)| predicate(key, value))
+            .map(|(key, _)| key.clone())
+            .collect();
+        let capacity = split_capacity(inner.capacity, keys.len(), inner.len() as usize);
+        let split = Self::new(capacity);
+        split.inner.write().unwrap().core.clock = inner.core.clock.clone();
+        let mut split_inner = split.inner.write().unwrap();
+        split_inner.probation_capacity = inner.probation_capacity.map(|probation_capacity| {
+            // widened so huge capacities cannot overflow, the share never exceeds `capacity`
+            let share =
+                probation_capacity as u128 * capacity as u128 / inner.capacity.max(1) as u128;
+            share.min(capacity as u128) as u64
+        });
+        if let Some(ttl) = inner.core.expiries.default_ttl() {
+            split_inner.core.expiries.set_default_ttl(ttl);
+        }
+        if let Some(time_to_idle) = inner.core.expiries.time_to_idle() {
+            split_inner.core.expiries.set_time_to_idle(time_to_idle);
+        }
+        split_inner.weigher = inner.weigher.clone();
+        split_inner.max_weight = inner.max_weight;
+        let now = inner.core.clock.now();
+        for key in keys {
+            let on_probation = inner.probation.contains_key(&key);
+            let priority = inner.core.priorities.get(&key);
+            let ttl = inner.core.expiries.remaining(&key, now);
+            let metadata = inner.core.metadata.get(&key);
+            let source = inner.core.listeners.source(&key);
+            let access = inner.core.metadata.access(&key);
+            let weight = inner.weights.get(&key).copied();
+            if let Some(value) = inner.remove_entry(&key) {
+                inner
+                    .core
+                    .listeners
+                    .notify(&key, &value, RemovalCause::Removed);
+                if let Some(weight) = weight {
+                    split_inner.weights.insert(key.clone(), weight);
+                    split_inner.total_weight += weight;
+                }
+                split_inner.core.priorities.set(&key, priority);
+                let now = split_inner.core.clock.now();
+                split_inner.core.metadata.set(&key, metadata, now);
+                split_inner.core.listeners.set_source(&key, source);
+                if let Some(access) = access {
+                    split_inner.core.metadata.restore_access(&key, access);
+                }
+                split_inner.core.expiries.set(&key, ttl, now);
+                if on_probation {
+                    split_inner.probation.insert(key, value);
+                } else {
+                    split_inner.key_value_map.insert(key, value);
+                }
+            }
+        }
+        split_inner.demote_overflow();
+        drop(split_inner);
+        split
+    }
+
+    /// Lock the cache, first applying any accesses waiting in the recency buffer.
+    fn lock_ordered(&self) -> RwLockWriteGuard<'_, LRUCacheInner<K, V>> {
+        let mut inner = self.inner.write().unwrap();
+        if let Some(recency) = self.recency.as_ref() {
+            inner.apply_accesses(recency);
+        }
+        inner
+    }
+
+    /// Look up a key under the shared lock, recording a hit in the recency buffer instead of refreshing its item's position.
+    ///
+    /// Returns None whenever the full lookup is needed instead, for an expired item, an item on probation, or when items expire on idle and each hit must push back the deadline.
+    fn shared_lookup(&self, key: &K, recency: &RecencyBuffer<K>) -> Option<Option<Arc<V>>> {
+        let (value, now) = {
+            let inner = self.inner.read().unwrap();
+            if inner.core.expiries.time_to_idle().is_some() || inner.probation.contains_key(key) {
+                return None;
+            }
+            let now = inner.core.clock.now();
+            if inner.core.expiries.is_expired(key, now) {
+                return None;
+            }
+            (inner.key_value_map.get(key).cloned(), now)
+        };
+        match &value {
+            Some(_) if recency.record(key.clone(), now) => drop(self.lock_ordered()),
+            Some(_) => {}
+            None => recency.record_miss(),
+        }
+        Some(value)
+    }
+
+    /// Look up a value, counting a hit or miss and refreshing its position, and pin it if `pin` is set and it was found.
+    ///
+    /// With batched recency unpinned gets are served under the shared lock by `shared_lookup` when they can be, and everything else takes the exclusive lock.
+    fn lookup(&self, key: &K, pin: bool, context: Option<&dyn Display>) -> Option<Arc<V>> {
+        let started = self.latency.start();
+        let shared = match self.recency.as_ref() {
+            Some(recency) if !pin => self.shared_lookup(key, recency),
+            _ => None,
+        };
+        let result = shared.unwrap_or_else(|| {
+            let mut inner = self.lock_ordered();
+            inner.purge_expired();
+            let now = inner.core.clock.now();
+            let mut result = inner.key_value_map.get_refresh(key).cloned();
+            if result.is_none() {
+                result = inner.promote(key);
+            }
+            if result.is_some() {
+                inner.core.stats.record_hit();
+                inner.core.expiries.touch(key, now);
+                inner.core.metadata.touch(key, now);
+                if pin {
+                    *inner.pins.entry(key.clone()).or_default() += 1;
+                }
+            } else {
+                inner.core.stats.record_miss();
+            }
+            result
+        });
+        self.latency.record_get(started);
+        let operation = match result {
+            Some(_) => TraceOperation::Hit,
+            None => TraceOperation::Miss,
+        };
+        self.trace.record(key, operation, context);
+        #[cfg(feature = "log")]
+        if let Some(context) = context {
+            log::debug!(
+                target: "arcache",
+                "cache {} {} for {}",
+                self.stats().cache,
+                operation.name(),
+                context
+            );
+        }
+        self.access_listeners.notify(key, &result);
+        result
+    }
+
+    /// Insert a value with a priority and an optional TTL, evicting items if the cache is over capacity.
+    #[allow(clippy::too_many_arguments)]
+    fn insert(
+        &self,
+        key: K,
+        value: Arc<V>,
+        priority: Option<u32>,
+        ttl: Option<Duration>,
+        metadata: Option<EntryMetadata>,
+        expected_version: Option<u64>,
+        source: EntrySource,
+    ) -> Result<Option<Arc<V>>, CacheError> {
+        let queue = self.inserts.as_ref().as_ref();
+        let turn = queue.map(InsertQueue::wait_turn);
+        let mut inner = self.inner.write().map_err(|_| CacheError::Poisoned)?;
+        if inner.capacity == 0 {
+            return Err(CacheError::CapacityZero);
+        }
+        if let Some(recency) = self.recency.as_ref() {
+            inner.apply_accesses(recency);
+        }
+        inner.purge_expired();
+        inner.core.metadata.check_version(&key, expected_version)?;
+        let is_new = !inner.key_value_map.contains_key(&key) && !inner.probation.contains_key(&key);
+        let weight = match inner.weigher.as_ref() {
+            Some(weigher) => Some(
+                inner
+                    .core
+                    .listeners
+                    .guard(|| weigher(&key, &value))
+                    .ok_or(CacheError::CallbackPanicked)?,
+            ),
+            None => None,
+        };
+        if is_new
+            && !inner.core.full_behavior.evicts()
+            && (inner.len() >= inner.capacity
+                || inner.total_weight.saturating_add(weight.unwrap_or(0)) > inner.max_weight)
+        {
+            inner.core.listeners.reject(&key);
+            return inner.core.full_behavior.rejection();
+        }
+        inner.core.stats.record_set(is_new);
+        self.trace.record(&key, TraceOperation::Set, None);
+        // an item which can never fit is evicted on its own, rather than after everything else
+        let oversized = weight
+            .filter(|weight| *weight > inner.max_weight)
+            .map(|_| key.clone());
+        if let Some(weight) = weight {
+            if let Some(previous) = inner.weights.insert(key.clone(), weight) {
+                inner.total_weight -= previous;
+            }
+            inner.total_weight += weight;
+        }
+        inner.core.track(&key, priority, ttl, metadata, source);
+        let spared = key.clone();
+        let result = if inner.probation.contains_key(&key) {
+            let previous = inner.probation.insert(key.clone(), value);
+            inner.promote(&key);
+            previous
+        } else if inner
+            .probation_capacity
+            .is_some_and(|probation_capacity| probation_capacity > 0)
+            && !inner.key_value_map.contains_key(&key)
+        {
+            inner.probation.insert(key, value)
+        } else {
+            inner.key_value_map.insert(key, value)
+        };
+        if let Some(key) = oversized {
+            let lifetime = inner.core.metadata.lifetime(&key, inner.core.clock.now());
+            if let Some(value) = inner.remove_entry(&key) {
+                inner
+                    .core
+                    .listeners
+                    .notify(&key, &value, RemovalCause::Evicted);
+                inner.core.stats.record_eviction(lifetime);
+            }
+        }
+        let Some(queue) = queue else {
+            inner.evict_batch(usize::MAX, Some(&spared));
+            return Ok(result);
+        };
+        let batch = queue.eviction_batch();
+        let mut evicted = inner.evict_batch(batch, Some(&spared));
+        drop(inner);
+        drop(turn);
+        // a full batch may not have been enough, finish in later turns
+        while evicted.len() == batch {
+            drop(evicted);
+            let _turn = queue.wait_turn();
+            evicted = self
+                .inner
+                .write()
+                .map_err(|_| CacheError::Poisoned)?
+                .evict_batch(batch, Some(&spared));
+        }
+        Ok(result)
+    }
+}
+
+impl<K: Eq + Hash + Clone + Sync + Send, V: Send + Sync> Clone for LRUCache<K, V> {
+    /// Get another handle to the same cache, sharing its items, settings and statistics.
+    fn clone(&self) -> Self {
+        LRUCache {
+            inner: self.inner.clone(),
+            access_listeners: self.access_listeners.clone(),
+            latency: self.latency.clone(),
+            trace: self.trace.clone(),
+            recency: self.recency.clone(),
+            inserts: self.inserts.clone(),
         }
     }
 }
@@ -61,69 +724,282 @@ impl<K: Eq + Hash + Clone + Sync + Send, V: Send + Sync> LRUCache<K, V> {
 impl<K: Eq + Hash + Clone + Sync + Send, V: Send + Sync> Cache<K, V> for LRUCache<K, V> {
     /// Get a value from the cache.
     fn get(&self, key: &K) -> Option<Arc<V>> {
-        let mut inner = self.inner.lock().unwrap();
-        let result = inner.key_value_map.get_refresh(key).cloned();
-        if result.is_some() {
-            inner.hits += 1;
-        } else {
-            inner.misses += 1;
-        }
-        result
+        self.lookup(key, false, None)
+    }
+
+    /// Get a value from the cache, with whether it was a hit, or a miss because the key expired, was rejected or is absent.
+    fn get_with_outcome(&self, key: &K) -> (Option<Arc<V>>, GetOutcome) {
+        let result = self.get(key);
+        let outcome = match result {
+            Some(_) => GetOutcome::Hit,
+            None => self.inner.read().unwrap().core.listeners.miss_outcome(key),
+        };
+        (result, outcome)
     }
 
     /// Set a value in the cache.
     fn set(&self, key: K, value: V) -> Option<Arc<V>> {
-        let mut inner = self.inner.lock().unwrap();
-        let arc_value = Arc::new(value);
-        let result = inner.key_value_map.insert(key, arc_value);
-        if inner.key_value_map.len() as u64 > inner.capacity {
-            inner.key_value_map.pop_front();
-        }
+        let started = self.latency.start();
+        let result = self
+            .insert(
+                key,
+                Arc::new(value),
+                None,
+                None,
+                None,
+                None,
+                EntrySource::Inserted,
+            )
+            .unwrap_or(None);
+        self.latency.record_set(started);
+        result
+    }
+
+    /// Set a value in the cache, recording how it came to be in the cache for `entry_info` and eviction listeners.
+    fn set_with_source(&self, key: K, value: V, source: EntrySource) -> Option<Arc<V>> {
+        let started = self.latency.start();
+        let result = self
+            .insert(key, Arc::new(value), None, None, None, None, source)
+            .unwrap_or(None);
+        self.latency.record_set(started);
         result
     }
 
     /// Remove a value from the cache.
     fn remove(&self, key: &K) -> Option<Arc<V>> {
-        let mut inner = self.inner.lock().unwrap();
-        inner.key_value_map.remove(key)
+        let mut inner = self.lock_ordered();
+        let result = inner.remove_entry(key);
+        if let Some(value) = &result {
+            inner
+                .core
+                .listeners
+                .notify(key, value, RemovalCause::Removed);
+        }
+        drop(inner);
+        self.trace.record(key, TraceOperation::Remove, None);
+        result
+    }
+
+    /// Check if a key is in the cache and has not expired, without counting a hit or miss or updating its position.
+    fn contains_key(&self, key: &K) -> bool {
+        let inner = self.inner.read().unwrap();
+        (inner.key_value_map.contains_key(key) || inner.probation.contains_key(key))
+            && !inner.core.expiries.is_expired(key, inner.core.clock.now())
+    }
+
+    /// Get the time left before an item expires, or None if it never expires or is not in the cache.
+    fn ttl_remaining(&self, key: &K) -> Option<Duration> {
+        self.entry_info(key)?.ttl_remaining
     }
 
     /// Clear the cache, removing all items.
     fn clear(&self) {
-        let mut inner = self.inner.lock().unwrap();
-        inner.key_value_map.clear();
+        let mut inner = self.lock_ordered();
+        inner.clear_entries();
+    }
+
+    /// Clear the cache, returning every item which was removed.
+    fn clear_returning(&self) -> Vec<(K, Arc<V>)> {
+        let mut inner = self.lock_ordered();
+        inner.clear_entries()
+    }
+
+    /// Copy the unexpired items into a HashMap in one locked pass.
+    fn to_map(&self) -> HashMap<K, Arc<V>> {
+        let inner = self.inner.read().unwrap();
+        let now = inner.core.clock.now();
+        inner
+            .probation
+            .iter()
+            .chain(inner.key_value_map.iter())
+            .filter(|(key, _)| !inner.core.expiries.is_expired(key, now))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect()
+    }
+
+    /// Get up to `limit` unexpired items, ordered with the items the policy would evict last first. Higher priority items come before lower ones, and otherwise most recently used first and items on probation last.
+    fn hottest(&self, limit: usize) -> Vec<(K, Arc<V>)> {
+        let inner = self.lock_ordered();
+        let now = inner.core.clock.now();
+        let mut items: Vec<(K, Arc<V>)> = inner
+            .key_value_map
+            .iter()
+            .rev()
+            .chain(inner.probation.iter().rev())
+            .filter(|(key, _)| !inner.core.expiries.is_expired(key, now))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect();
+        inner.core.priorities.sort_descending(&mut items);
+        items.truncate(limit);
+        items
+    }
+
+    /// Get the unexpired items in recency order, least recently used first and items on probation before the main region, or in any order.
+    fn items(&self, order: IterationOrder) -> Option<Vec<(K, Arc<V>)>> {
+        if !matches!(order, IterationOrder::Recency | IterationOrder::Arbitrary) {
+            return None;
+        }
+        let inner = self.lock_ordered();
+        let now = inner.core.clock.now();
+        let items = inner
+            .probation
+            .iter()
+            .chain(inner.key_value_map.iter())
+            .filter(|(key, _)| !inner.core.expiries.is_expired(key, now))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect();
+        Some(items)
+    }
+
+    /// Get the name of the eviction policy.
+    fn policy_name(&self) -> &'static str {
+        "lru"
+    }
+
+    /// Get the cache as `Any`, so it can be downcast from a `dyn Cache`.
+    fn as_any(&self) -> Option<&dyn Any>
+    where
+        Self: 'static,
+    {
+        Some(self)
     }
 
     /// Get the cache statistics.
     fn stats(&self) -> CacheStats {
-        let inner = self.inner.lock().unwrap();
-        CacheStats {
-            hits: inner.hits,
-            misses: inner.misses,
-            size: inner.key_value_map.len() as u64,
-            capacity: inner.capacity,
-        }
+        let inner = self.lock_ordered();
+        inner.core.stats.snapshot(inner.len(), inner.capacity)
+    }
+
+    /// Get the cache statistics together with how soon the items expire.
+    fn detailed_stats(&self) -> DetailedStats {
+        let inner = self.lock_ordered();
+        let size = inner.len();
+        let mut detailed = DetailedStats::new(inner.core.stats.snapshot(size, inner.capacity));
+        detailed.eviction_ages = Some(inner.core.stats.eviction_ages());
+        detailed.expiry_distribution = Some(
+            inner
+                .core
+                .expiries
+                .distribution(inner.core.clock.now(), size),
+        );
+        detailed
     }
 
     /// Change the capacity of the cache, if the new capacity is smaller than the current size, the least recently accessed items are removed
     fn change_capacity(&self, capacity: u64) {
-        let mut inner = self.inner.lock().unwrap();
+        self.change_capacity_collect(capacity);
+    }
+
+    /// Change the capacity of the cache, returning the items which were removed to fit the new capacity.
+    fn change_capacity_collect(&self, capacity: u64) -> Vec<(K, Arc<V>)> {
+        let mut inner = self.lock_ordered();
         let old_capacity = inner.capacity;
         inner.capacity = capacity;
-        while inner.key_value_map.len() as u64 > inner.capacity {
-            inner.key_value_map.pop_front();
-        }
+        // the probation share is kept as is, so it is still there if the cache grows back
+        inner.demote_overflow();
+        let evicted = inner.evict_to_capacity();
 
         if inner.capacity > old_capacity {
             let additional = (inner.capacity - old_capacity) as usize;
             inner.key_value_map.reserve(additional);
         }
+        evicted
+    }
+
+    /// Evict `fraction` of the items per the policy in one locked pass, leaving the capacity unchanged.
+    fn shed(&self, fraction: f64) -> usize {
+        let mut inner = self.lock_ordered();
+        let len = inner.len();
+        inner
+            .evict_to(len - shed_count(len as usize, fraction) as u64)
+            .len()
+    }
+}
+
+impl<K: Eq + Hash + Clone + Sync + Send, V: Send + Sync> TryCache<K, V> for LRUCache<K, V> {
+    /// Get a value from the cache, returning `CacheError::Poisoned` instead of panicking if the cache lock is poisoned.
+    fn try_get(&self, key: &K) -> Result<Option<Arc<V>>, CacheError> {
+        if self.inner.is_poisoned() {
+            return Err(CacheError::Poisoned);
+        }
+        Ok(self.get(key))
+    }
+
+    /// Set a value in the cache, returning an error instead of inserting if the cache is full and was built with `FullBehavior::ReturnError`, has a capacity of zero, or its lock is poisoned.
+    fn try_set(&self, key: K, value: V) -> Result<Option<Arc<V>>, CacheError> {
+        let started = self.latency.start();
+        let result = self.insert(
+            key,
+            Arc::new(value),
+            None,
+            None,
+            None,
+            None,
+            EntrySource::Inserted,
+        );
+        self.latency.record_set(started);
+        result
+    }
+}
+
+impl<K: Eq + Hash + Clone + Sync + Send, V: Send + Sync> VersionedCache<K, V> for LRUCache<K, V> {
+    /// Get the current version of an item, without counting a hit or miss or updating its position.
+    fn version(&self, key: &K) -> Option<u64> {
+        let inner = self.inner.read().unwrap();
+        if inner.core.expiries.is_expired(key, inner.core.clock.now()) {
+            return None;
+        }
+        inner.core.metadata.version(key)
+    }
+
+    /// Set a value only if the item is at `expected_version`, or is not in the cache if it is 0.
+    fn set_versioned(
+        &self,
+        key: K,
+        value: V,
+        expected_version: u64,
+    ) -> Result<Option<Arc<V>>, CacheError> {
+        let started = self.latency.start();
+        let result = self.insert(
+            key,
+            Arc::new(value),
+            None,
+            None,
+            None,
+            Some(expected_version),
+            EntrySource::Inserted,
+        );
+        self.latency.record_set(started);
+        result
+    }
+}
+
+impl<K: Eq + Hash + Clone + Sync + Send, V: Send + Sync> IntoIterator for LRUCache<K, V> {
+    type Item = (K, Arc<V>);
+    type IntoIter = std::vec::IntoIter<(K, Arc<V>)>;
+
+    /// Consume the handle, yielding the cache's unexpired items from least to most recently used, items on probation first. Other handles still hold the items.
+    fn into_iter(self) -> Self::IntoIter {
+        let inner = self.lock_ordered();
+        let now = inner.core.clock.now();
+        let items: Vec<(K, Arc<V>)> = inner
+            .probation
+            .iter()
+            .chain(inner.key_value_map.iter())
+            .filter(|(key, _)| !inner.core.expiries.is_expired(key, now))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect();
+        items.into_iter()
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::cache::listener::{AccessOutcome, RemovalEvent};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Mutex;
+    use std::thread;
 
     #[test]
     fn test_lru_cache() {
@@ -139,6 +1015,158 @@ mod tests {
         assert_eq!(cache.get(&4).map(|v| *v), Some(4));
     }
 
+    #[test]
+    fn test_lru_cache_full_behavior() {
+        let cache = LRUCache::new(2).with_full_behavior(FullBehavior::RejectNew);
+        cache.set(1, 1);
+        cache.set(2, 2);
+        assert_eq!(cache.set(3, 3), None);
+        assert!(!cache.contains_key(&3));
+        assert_eq!(cache.set(1, 10).map(|v| *v), Some(1));
+        assert_eq!(cache.stats().size, 2);
+
+        let cache = LRUCache::new(1).with_full_behavior(FullBehavior::ReturnError);
+        assert_eq!(cache.try_set(1, 1), Ok(None));
+        assert_eq!(cache.try_set(2, 2), Err(CacheError::Full));
+        assert!(cache.contains_key(&1));
+    }
+
+    #[test]
+    fn test_lru_cache_get_with_outcome() {
+        let cache = LRUCache::new(2).with_full_behavior(FullBehavior::RejectNew);
+        cache.set(1, 1);
+        cache.set_with_ttl(2, 2, Duration::ZERO);
+        assert_eq!(
+            cache.get_with_outcome(&1),
+            (Some(Arc::new(1)), GetOutcome::Hit)
+        );
+        assert_eq!(cache.get_with_outcome(&2), (None, GetOutcome::MissExpired));
+        cache.set(3, 3);
+        cache.set(4, 4);
+        assert_eq!(cache.get_with_outcome(&4), (None, GetOutcome::MissRejected));
+        assert_eq!(cache.get_with_outcome(&5), (None, GetOutcome::MissAbsent));
+
+        // a later set replaces the remembered outcome
+        cache.remove(&1);
+        cache.set(4, 4);
+        assert!(cache.get_with_outcome(&4).1.is_hit());
+    }
+
+    #[test]
+    fn test_lru_cache_clone_shares_state() {
+        let cache = LRUCache::new(2).with_latency_tracking();
+        let handle = cache.clone();
+        thread::spawn(move || {
+            handle.set(1, 1);
+            handle.get(&1);
+        })
+        .join()
+        .unwrap();
+        assert_eq!(cache.get(&1).map(|v| *v), Some(1));
+        assert_eq!(cache.stats().hits, 2);
+        assert_eq!(cache.latency_stats().unwrap().get.count, 2);
+        let items: Vec<i32> = cache.clone().into_iter().map(|(k, _)| k).collect();
+        assert_eq!(items, vec![1]);
+        assert!(cache.contains_key(&1));
+    }
+
+    #[test]
+    fn test_lru_cache_try_errors() {
+        let cache = LRUCache::new(0);
+        assert_eq!(cache.try_set(1, 1), Err(CacheError::CapacityZero));
+    }
+
+    // parking_lot locks are never poisoned
+    #[cfg(not(feature = "parking_lot"))]
+    #[test]
+    fn test_lru_cache_try_errors_poisoned() {
+        // a panicking eviction listener poisons the cache lock
+        let cache = LRUCache::new(1)
+            .with_eviction_listener(|_: &RemovalEvent<i32, i32>| panic!("listener failed"));
+        cache.set(1, 1);
+        let poisoned = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            cache.set(2, 2);
+        }));
+        assert!(poisoned.is_err());
+        assert_eq!(cache.try_get(&1), Err(CacheError::Poisoned));
+        assert_eq!(cache.try_set(3, 3), Err(CacheError::Poisoned));
+    }
+
+    #[test]
+    fn test_lru_cache_isolates_callback_panics() {
+        let cache = LRUCache::new(1)
+            .with_max_weight(10, |_: &i32, value: &i32| {
+                assert!(*value >= 0, "negative weight");
+                *value as u64
+            })
+            .with_eviction_listener(|_: &RemovalEvent<i32, i32>| panic!("listener failed"))
+            .with_callback_panics(CallbackPanics::Isolate);
+        cache.set(1, 1);
+        cache.set(2, 2);
+        assert_eq!(cache.try_set(3, -1), Err(CacheError::CallbackPanicked));
+        cache.set(4, 4);
+        // neither panic poisoned the lock, and the listener is still called
+        assert_eq!(cache.try_get(&4).map(|v| v.map(|v| *v)), Ok(Some(4)));
+        assert_eq!(cache.stats().callback_panics, 3);
+        assert_eq!(cache.total_weight(), 4);
+    }
+
+    #[test]
+    fn test_lru_cache_entry_info() {
+        let cache = LRUCache::new(2);
+        cache.set_with_metadata(1, 1, "origin".to_string());
+        cache.set_with_priority(2, 2, 5);
+        let info = cache.entry_info(&1).unwrap();
+        assert_eq!(
+            info.metadata::<String>().map(String::as_str),
+            Some("origin")
+        );
+        assert_eq!(cache.entry_info(&2).unwrap().priority, 5);
+        assert!(cache.entry_info(&2).unwrap().metadata::<String>().is_none());
+        assert!(cache.entry_info(&3).is_none());
+
+        cache.set(1, 10);
+        assert!(cache.entry_info(&1).unwrap().raw_metadata().is_none());
+        assert_eq!(cache.stats().hits, 0);
+    }
+
+    #[test]
+    fn test_lru_cache_age_rank() {
+        let cache = LRUCache::new(3);
+        cache.set(1, 1);
+        cache.set(2, 2);
+        cache.set(3, 3);
+        let before = cache.last_accessed(&1).unwrap();
+        cache.get(&1);
+        assert_eq!(cache.age_rank(&2), Some(0));
+        assert_eq!(cache.age_rank(&1), Some(2));
+        assert_eq!(cache.age_rank(&4), None);
+        assert!(cache.last_accessed(&1).unwrap() >= before);
+        cache.remove(&1);
+        assert_eq!(cache.last_accessed(&1), None);
+    }
+
+    #[test]
+    fn test_lru_cache_get_pinned() {
+        let cache = LRUCache::new(2);
+        cache.set(1, 1);
+        cache.set(2, 2);
+        let guard = cache.get_pinned(&1).unwrap();
+        cache.get(&2);
+        cache.set(3, 3);
+        cache.set(4, 4);
+        assert_eq!(*guard, 1);
+        assert!(cache.contains_key(&1));
+        assert!(!cache.contains_key(&2));
+        assert!(!cache.contains_key(&3));
+
+        drop(guard);
+        cache.set(5, 5);
+        assert!(!cache.contains_key(&1));
+        assert_eq!(cache.stats().size, 2);
+        assert!(cache.get_pinned(&1).is_none());
+    }
+
     #[test]
     fn test_lru_cache_change_capacity() {
         let cache = LRUCache::new(2);
@@ -149,6 +1177,22 @@ mod tests {
         assert_eq!(cache.get(&2).map(|v| *v), Some(2));
     }
 
+    #[test]
+    fn test_lru_cache_change_capacity_collect() {
+        let cache = LRUCache::new(3);
+        cache.set(1, 1);
+        cache.set(2, 2);
+        cache.set(3, 3);
+        cache.get(&1);
+        let evicted: Vec<(i32, i32)> = cache
+            .change_capacity_collect(1)
+            .into_iter()
+            .map(|(k, v)| (k, *v))
+            .collect();
+        assert_eq!(evicted, vec![(2, 2), (3, 3)]);
+        assert!(cache.change_capacity_collect(5).is_empty());
+    }
+
     #[test]
     fn test_lru_cache_clear() {
         let cache = LRUCache::new(2);
@@ -159,6 +1203,384 @@ mod tests {
         assert_eq!(cache.get(&2).map(|v| *v), None);
     }
 
+    #[test]
+    fn test_lru_cache_expiry_distribution() {
+        let cache = LRUCache::new(10);
+        for (key, secs) in [(1, 0), (2, 5), (3, 30), (4, 120)] {
+            cache.set_with_ttl(key, key, Duration::from_millis(secs * 1000 + 500));
+        }
+        cache.set(5, 5);
+        let distribution = cache.detailed_stats().expiry_distribution.unwrap();
+        assert_eq!(
+            (
+                distribution.within_1s,
+                distribution.within_10s,
+                distribution.within_60s,
+                distribution.later,
+                distribution.never
+            ),
+            (1, 1, 1, 1, 1)
+        );
+    }
+
+    #[test]
+    fn test_lru_cache_batched_recency() {
+        let cache = LRUCache::new(3).with_batched_recency(4);
+        cache.set(1, 1);
+        cache.set(2, 2);
+        cache.set(3, 3);
+        assert_eq!(cache.get(&1).map(|v| *v), Some(1));
+        // the hit is only recorded, so 1 is still first to be evicted
+        assert_eq!(
+            cache
+                .inner
+                .read()
+                .unwrap()
+                .key_value_map
+                .front()
+                .map(|(k, _)| *k),
+            Some(1)
+        );
+        // the next write applies it, so 2 is evicted instead
+        cache.set(4, 4);
+        assert!(cache.contains_key(&1));
+        assert!(!cache.contains_key(&2));
+        assert_eq!(cache.stats().hits, 1);
+    }
+
+    #[test]
+    fn test_lru_cache_batched_recency_drains_when_full() {
+        let cache = LRUCache::new(3).with_batched_recency(2);
+        cache.set(1, 1);
+        cache.set(2, 2);
+        cache.set(3, 3);
+        cache.get(&2);
+        cache.get(&1);
+        assert_eq!(cache.age_rank(&3), Some(0));
+        let order: Vec<i32> = cache.clone().into_iter().map(|(k, _)| k).collect();
+        assert_eq!(order, vec![3, 2, 1]);
+        assert!(cache.last_accessed(&1) >= cache.last_accessed(&2));
+    }
+
+    #[test]
+    fn test_lru_cache_batched_recency_shares_the_lock() {
+        let cache = LRUCache::new(3).with_batched_recency(64);
+        cache.set(1, 1);
+        // a hit only needs the shared lock, so it is served while another reader holds it
+        let reader = cache.inner.read().unwrap();
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let handle = cache.clone();
+        thread::spawn(move || sender.send(handle.get(&1).map(|v| *v)).unwrap());
+        assert_eq!(
+            receiver.recv_timeout(Duration::from_secs(5)).unwrap(),
+            Some(1)
+        );
+        drop(reader);
+
+        // every hit is counted even when readers collide on a slot of the buffer
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let cache = cache.clone();
+                thread::spawn(move || {
+                    for _ in 0..1000 {
+                        cache.get(&1);
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert_eq!(cache.stats().hits, 4001);
+        assert_eq!(cache.get(&2), None);
+        assert_eq!(cache.stats().misses, 1);
+    }
+
+    #[test]
+    fn test_lru_cache_scan_resistance() {
+        let cache = LRUCache::new(4).with_scan_resistance(2);
+        for i in 1..=2 {
+            cache.set(i, i);
+            cache.get(&i);
+        }
+        // a scan of keys which are only seen once stays on probation
+        for i in 100..200 {
+            cache.set(i, i);
+        }
+        assert_eq!(cache.stats().size, 4);
+        assert_eq!(cache.get(&1).map(|v| *v), Some(1));
+        assert_eq!(cache.get(&2).map(|v| *v), Some(2));
+        assert_eq!(cache.get(&199).map(|v| *v), Some(199));
+        assert_eq!(cache.get(&100), None);
+    }
+
+    #[test]
+    fn test_lru_cache_scan_resistance_survives_zero_capacity() {
+        let cache = LRUCache::new(3).with_scan_resistance(1);
+        cache.change_capacity(0);
+        cache.change_capacity(2);
+        cache.set(1, 1);
+        cache.get(&1);
+        cache.set(2, 2);
+        cache.get(&2);
+        // the probation share is back, so the new item is not the one evicted
+        cache.set(3, 3);
+        assert_eq!(cache.get(&3).map(|v| *v), Some(3));
+        assert!(cache.contains_key(&2));
+        assert!(!cache.contains_key(&1));
+    }
+
+    #[test]
+    fn test_lru_cache_scan_resistance_promotion_demotes() {
+        let cache = LRUCache::new(3).with_scan_resistance(1);
+        cache.set(1, 1);
+        cache.get(&1);
+        cache.set(2, 2);
+        cache.get(&2);
+        cache.set(3, 3);
+        // promoting 3 demotes 1, the least recently used item in the main region
+        cache.get(&3);
+        cache.set(4, 4);
+        assert_eq!(cache.stats().size, 3);
+        assert!(!cache.contains_key(&1));
+        assert!(cache.contains_key(&2));
+        assert!(cache.contains_key(&3));
+        assert!(cache.contains_key(&4));
+        assert_eq!(cache.remove(&4).map(|v| *v), Some(4));
+        cache.change_capacity(1);
+        assert_eq!(cache.stats().size, 1);
+        assert!(cache.contains_key(&3));
+    }
+
+    #[test]
+    fn test_lru_cache_priorities() {
+        let cache = LRUCache::new(2);
+        cache.set_with_priority(1, 1, 10);
+        cache.set(2, 2);
+        cache.get(&2);
+        cache.set(3, 3);
+        assert_eq!(cache.get(&2), None);
+        cache.set_with_priority(4, 4, 5);
+        assert_eq!(cache.get(&3), None);
+        cache.set_with_priority(5, 5, 5);
+        assert_eq!(cache.get(&1).map(|v| *v), Some(1));
+        assert_eq!(cache.get(&4), None);
+        assert_eq!(cache.get(&5).map(|v| *v), Some(5));
+    }
+
+    #[test]
+    fn test_lru_cache_priorities_spare_new_item() {
+        // like every other policy, a set makes room before its item counts, so the new item is never the victim
+        let cache = LRUCache::new(1);
+        cache.set_with_priority(1, 1, 1);
+        cache.set(2, 2);
+        assert_eq!(cache.get(&1), None);
+        assert_eq!(cache.get(&2).map(|v| *v), Some(2));
+    }
+
+    #[test]
+    fn test_lru_cache_ttl() {
+        let cache = LRUCache::new(2).with_ttl(Duration::from_secs(60));
+        cache.set_with_ttl(1, 1, Duration::ZERO);
+        cache.set(2, 2);
+        assert!(!cache.contains_key(&1));
+        assert_eq!(cache.get(&1), None);
+        assert_eq!(cache.get(&2).map(|v| *v), Some(2));
+        cache.set_with_ttl(3, 3, Duration::ZERO);
+        // the expired item is removed rather than the least recently used live item
+        cache.set(4, 4);
+        assert_eq!(cache.get(&2).map(|v| *v), Some(2));
+        assert_eq!(cache.get(&4).map(|v| *v), Some(4));
+        assert_eq!(cache.stats().size, 2);
+    }
+
+    #[test]
+    fn test_lru_cache_eviction_listener() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let sink = events.clone();
+        let cache =
+            LRUCache::new(2).with_eviction_listener(move |event: &RemovalEvent<i32, i32>| {
+                sink.lock().unwrap().push((event.key, event.cause));
+            });
+        cache.set(1, 1);
+        cache.set(2, 2);
+        cache.set(3, 3);
+        cache.remove(&2);
+        cache.set_with_ttl(4, 4, Duration::ZERO);
+        cache.get(&4);
+        cache.clear();
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![
+                (1, RemovalCause::Evicted),
+                (2, RemovalCause::Removed),
+                (4, RemovalCause::Expired),
+                (3, RemovalCause::Cleared),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_lru_cache_clear_returning() {
+        let cache = LRUCache::new(2);
+        cache.set(1, 1);
+        cache.set(2, 2);
+        let mut cleared: Vec<(i32, i32)> = cache
+            .clear_returning()
+            .into_iter()
+            .map(|(k, v)| (k, *v))
+            .collect();
+        cleared.sort();
+        assert_eq!(cleared, vec![(1, 1), (2, 2)]);
+        assert_eq!(cache.stats().size, 0);
+        assert!(cache.clear_returning().is_empty());
+    }
+
+    #[test]
+    fn test_lru_cache_stats_sampling() {
+        let cache = LRUCache::new(10).with_stats_sampling(8);
+        cache.set(1, 1);
+        for _ in 0..10_000 {
+            cache.get(&1);
+            cache.get(&2);
+        }
+        let stats = cache.stats();
+        assert!(stats.hits.abs_diff(10_000) < 1_000, "{:?}", stats);
+        assert!(stats.misses.abs_diff(10_000) < 1_000, "{:?}", stats);
+    }
+
+    #[test]
+    fn test_lru_cache_clock() {
+        struct StoppedClock(Instant);
+        impl Clock for StoppedClock {
+            fn now(&self) -> Instant {
+                self.0
+            }
+        }
+        let start = Instant::now();
+        let cache = LRUCache::new(2)
+            .with_ttl(Duration::from_millis(1))
+            .with_clock(StoppedClock(start));
+        cache.set(1, 1);
+        std::thread::sleep(Duration::from_millis(5));
+        // the clock has not moved, so the item has not expired
+        assert_eq!(cache.get(&1).map(|v| *v), Some(1));
+        assert_eq!(cache.last_accessed(&1), Some(start));
+        assert_eq!(cache.idle_time(&1), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn test_lru_cache_expiring_within() {
+        let cache = LRUCache::new(4).with_ttl(Duration::from_secs(600));
+        cache.set(1, 1);
+        cache.set_with_ttl(2, 2, Duration::from_secs(30));
+        cache.set_with_ttl(3, 3, Duration::from_secs(5));
+        cache.set_with_ttl(4, 4, Duration::ZERO);
+        assert_eq!(cache.expiring_within(Duration::from_secs(60)), vec![3, 2]);
+        assert_eq!(
+            cache.expiring_within(Duration::from_secs(3600)),
+            vec![3, 2, 1]
+        );
+        assert_eq!(cache.expiring_within(Duration::MAX), vec![3, 2, 1]);
+    }
+
+    #[test]
+    fn test_lru_cache_split_off() {
+        let removed = Arc::new(Mutex::new(Vec::new()));
+        let sink = removed.clone();
+        let cache =
+            LRUCache::new(10).with_eviction_listener(move |event: &RemovalEvent<i32, i32>| {
+                sink.lock().unwrap().push((event.key, event.cause));
+            });
+        for i in 0..4 {
+            cache.set(i, i);
+        }
+        cache.set_with_priority(4, 4, 5);
+        cache.get(&0);
+        let split = cache.split_off(|key, _| key % 2 == 0);
+        assert_eq!(cache.stats().size, 2);
+        assert_eq!(cache.stats().capacity, 10);
+        assert_eq!(split.stats().size, 3);
+        assert_eq!(split.stats().capacity, 6);
+        assert_eq!(
+            *removed.lock().unwrap(),
+            vec![
+                (2, RemovalCause::Removed),
+                (4, RemovalCause::Removed),
+                (0, RemovalCause::Removed)
+            ]
+        );
+        assert!(!cache.contains_key(&0));
+        let items: Vec<i32> = split.into_iter().map(|(k, _)| k).collect();
+        assert_eq!(items, vec![2, 4, 0]);
+    }
+
+    #[test]
+    fn test_lru_cache_split_off_huge_probation() {
+        // a probation region larger than the cache is scaled down without overflowing
+        let cache = LRUCache::new(10).with_scan_resistance(u64::MAX);
+        for i in 0..4 {
+            cache.set(i, i);
+        }
+        let split = cache.split_off(|key, _| key % 2 == 0);
+        assert_eq!(split.stats().size, 2);
+        assert_eq!(split.stats().capacity, 5);
+        assert_eq!(split.inner.read().unwrap().probation_capacity, Some(5));
+    }
+
+    #[test]
+    fn test_lru_cache_hottest() {
+        let cache = LRUCache::new(4).with_scan_resistance(2);
+        cache.set(1, 1);
+        cache.set(2, 2);
+        cache.get(&1);
+        cache.set(3, 3);
+        cache.set_with_priority(4, 4, 1);
+        let keys: Vec<i32> = cache.hottest(10).into_iter().map(|(k, _)| k).collect();
+        assert_eq!(keys, vec![4, 1, 3, 2]);
+        assert_eq!(cache.hottest(1).len(), 1);
+    }
+
+    #[test]
+    fn test_lru_cache_access_listener() {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let sink = seen.clone();
+        let cache = LRUCache::new(2).with_access_listener(move |key: &i32, outcome| {
+            sink.lock().unwrap().push((*key, outcome));
+        });
+        cache.set(1, 1);
+        cache.get(&1);
+        cache.get(&2);
+        assert_eq!(
+            *seen.lock().unwrap(),
+            vec![(1, AccessOutcome::Hit), (2, AccessOutcome::Miss)]
+        );
+    }
+
+    #[test]
+    fn test_lru_cache_to_map() {
+        let cache = LRUCache::new(3).with_scan_resistance(1);
+        cache.set(1, 1);
+        cache.get(&1);
+        cache.set(2, 2);
+        cache.set_with_ttl(3, 3, Duration::ZERO);
+        let map = cache.to_map();
+        assert_eq!(map.len(), 2);
+        assert_eq!(map.get(&1).map(|v| **v), Some(1));
+        assert_eq!(map.get(&2).map(|v| **v), Some(2));
+    }
+
+    #[test]
+    fn test_lru_cache_into_iter() {
+        let cache = LRUCache::new(3);
+        cache.set(1, 1);
+        cache.set(2, 2);
+        cache.set(3, 3);
+        cache.get(&1);
+        let items: Vec<(i32, i32)> = cache.into_iter().map(|(k, v)| (k, *v)).collect();
+        assert_eq!(items, vec![(2, 2), (3, 3), (1, 1)]);
+    }
+
     #[test]
     fn test_lru_stats() {
         let cache = LRUCache::new(2);
@@ -181,4 +1603,154 @@ mod tests {
         cache.get(&4);
         assert_eq!(cache.stats().hits, 3);
     }
+
+    #[test]
+    fn test_lru_cache_latency_tracking() {
+        assert!(LRUCache::<u64, u64>::new(2).latency_stats().is_none());
+        let cache = LRUCache::new(2).with_latency_tracking();
+        cache.set(1, 1);
+        cache.get(&1);
+        cache.get(&2);
+        let latencies = cache.latency_stats().unwrap();
+        assert_eq!(latencies.get.count, 2);
+        assert_eq!(latencies.set.count, 1);
+        assert!(latencies.get.p50 <= latencies.get.max);
+    }
+
+    #[test]
+    fn test_lru_cache_export_access_log() {
+        let cache = LRUCache::new(2).with_access_trace(16);
+        cache.set("a", 1);
+        cache.get(&"a");
+        cache.get(&"b");
+        cache.remove(&"a");
+        let mut log = Vec::new();
+        assert_eq!(cache.export_access_log(&mut log).unwrap(), 4);
+        let log = String::from_utf8(log).unwrap();
+        let operations: Vec<&str> = log
+            .lines()
+            .skip(2)
+            .map(|line| line.split(',').nth(1).unwrap())
+            .collect();
+        assert_eq!(operations, vec!["set", "hit", "miss", "remove"]);
+    }
+
+    #[test]
+    fn test_lru_cache_get_traced() {
+        let cache = LRUCache::new(2).with_access_trace(16);
+        cache.set("a", 1);
+        assert_eq!(cache.get_traced(&"a", &"request-1"), Some(Arc::new(1)));
+        assert_eq!(cache.get_traced(&"b", &42), None);
+        assert_eq!(cache.stats().misses, 1);
+        let mut log = Vec::new();
+        cache.export_access_log(&mut log).unwrap();
+        let contexts: Vec<String> = String::from_utf8(log)
+            .unwrap()
+            .lines()
+            .skip(2)
+            .map(|line| line.rsplit(',').next().unwrap().to_string())
+            .collect();
+        assert_eq!(contexts, vec!["", "request-1", "42"]);
+    }
+
+    #[test]
+    fn test_lru_cache_count_weight_and_ttl_limits() {
+        let cache = LRUCache::new(4)
+            .with_max_weight(10, |_: &u32, value: &String| value.len() as u64)
+            .with_ttl(Duration::from_secs(60));
+        cache.set(1, "aaaa".to_string());
+        cache.set(2, "bbbb".to_string());
+        cache.get(&1);
+        // 2 is the least recently used, so it goes to keep the weight within 10
+        cache.set(3, "cccc".to_string());
+        assert!(!cache.contains_key(&2));
+        assert_eq!(cache.total_weight(), 8);
+        // light items are then limited by the count
+        for key in 4..8 {
+            cache.set(key, "d".to_string());
+        }
+        assert_eq!(cache.stats().size, 4);
+        assert_eq!(cache.total_weight(), 4);
+        cache.remove(&7);
+        assert_eq!(cache.total_weight(), 3);
+        // an item heavier than the maximum weight is not kept
+        cache.set(8, "e".repeat(11));
+        assert!(!cache.contains_key(&8));
+        assert_eq!(cache.total_weight(), 3);
+        cache.set_with_ttl(9, "f".to_string(), Duration::ZERO);
+        assert!(!cache.contains_key(&9));
+    }
+
+    #[test]
+    fn test_lru_cache_fair_inserts_evict_in_batches() {
+        let evictions = Arc::new(AtomicUsize::new(0));
+        let counter = evictions.clone();
+        let cache = LRUCache::new(100)
+            .with_max_weight(10, |_: &u32, value: &String| value.len() as u64)
+            .with_fair_inserts(2)
+            .with_eviction_listener(move |_: &RemovalEvent<u32, String>| {
+                counter.fetch_add(1, Ordering::Relaxed);
+            });
+        for key in 0..10 {
+            cache.set(key, "a".to_string());
+        }
+        // the heavy item needs 7 evictions, made 2 a turn
+        cache.set(10, "b".repeat(7));
+        assert_eq!(evictions.load(Ordering::Relaxed), 7);
+        assert_eq!(cache.total_weight(), 10);
+        assert!(cache.contains_key(&10) && cache.contains_key(&7));
+    }
+
+    #[test]
+    fn test_lru_cache_fair_inserts_under_contention() {
+        let cache = LRUCache::new(50).with_fair_inserts(4);
+        let writers: Vec<_> = (0..8u64)
+            .map(|writer| {
+                let cache = cache.clone();
+                thread::spawn(move || {
+                    for key in 0..500 {
+                        cache.set(writer * 1000 + key, key);
+                    }
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+        assert_eq!(cache.stats().size, 50);
+        assert_eq!(cache.stats().evictions, 8 * 500 - 50);
+    }
+
+    #[test]
+    fn test_lru_cache_time_to_idle() {
+        let cache = LRUCache::new(10)
+            .with_ttl(Duration::from_millis(250))
+            .with_time_to_idle(Duration::from_millis(100));
+        cache.set(1, 1);
+        cache.set(2, 2);
+        for _ in 0..3 {
+            std::thread::sleep(Duration::from_millis(60));
+            assert!(cache.get(&1).is_some());
+        }
+        // 1 was kept alive by gets, 2 went idle
+        assert!(!cache.contains_key(&2));
+        std::thread::sleep(Duration::from_millis(100));
+        // 1 is now past its TTL however often it is read
+        assert!(cache.get(&1).is_none());
+        assert_eq!(cache.stats().expirations, 2);
+    }
+
+    #[test]
+    fn test_lru_cache_extend_ttl() {
+        let cache = LRUCache::new(10).with_ttl(Duration::from_millis(100));
+        cache.set(1, 1);
+        cache.set(2, 2);
+        cache.set_with_ttl(3, 3, Duration::from_secs(60));
+        assert!(cache.extend_ttl(&1, Duration::from_secs(60)));
+        assert!(!cache.extend_ttl(&4, Duration::from_secs(60)));
+        std::thread::sleep(Duration::from_millis(150));
+        assert!(cache.contains_key(&1));
+        assert!(!cache.contains_key(&2));
+        assert!(cache.entry_info(&1).unwrap().ttl_remaining.unwrap() > Duration::from_secs(59));
+    }
 }
@@ -0,0 +1,308 @@
+use std::any::Any;
+use std::collections::HashMap;
+use std::fmt;
+use std::hash::Hash;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::cache::listener::EntrySource;
+use crate::error::CacheError;
+
+/// A metadata value attached to an item, any type which can be shared between threads can be used.
+pub type EntryMetadata = Arc<dyn Any + Send + Sync>;
+
+/// EntryInfo describes an item in a cache without counting a hit or changing its position.
+///
+/// Example:
+/// ```
+/// use arcache::{Cache, EntrySource, LRUCache};
+///
+/// let cache = LRUCache::<&str, u64>::new(10);
+/// cache.set_with_metadata("key", 1, "loaded from the database");
+///
+/// let info = cache.entry_info(&"key").unwrap();
+/// assert_eq!(info.metadata::<&str>(), Some(&"loaded from the database"));
+/// assert_eq!(info.ttl_remaining, None);
+/// assert_eq!(info.source, EntrySource::Inserted);
+/// ```
+#[derive(Clone)]
+#[non_exhaustive]
+pub struct EntryInfo {
+    /// The priority the item was set with.
+    pub priority: u32,
+    /// The time left before the item expires, or None if it does not expire.
+    pub ttl_remaining: Option<Duration>,
+    /// How the item came to be in the cache.
+    pub source: EntrySource,
+    metadata: Option<EntryMetadata>,
+}
+
+impl EntryInfo {
+    pub(crate) fn new(
+        priority: u32,
+        ttl_remaining: Option<Duration>,
+        metadata: Option<EntryMetadata>,
+        source: EntrySource,
+    ) -> Self {
+        EntryInfo {
+            priority,
+            ttl_remaining,
+            source,
+            metadata,
+        }
+    }
+
+    /// Get the metadata attached to the item if it is of type `M`.
+    pub fn metadata<M: Any>(&self) -> Option<&M> {
+        self.metadata.as_ref()?.downcast_ref()
+    }
+
+    /// Get the metadata attached to the item, whatever its type.
+    pub fn raw_metadata(&self) -> Option<&EntryMetadata> {
+        self.metadata.as_ref()
+    }
+}
+
+impl fmt::Debug for EntryInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EntryInfo")
+            .field("priority", &self.priority)
+            .field("ttl_remaining", &self.ttl_remaining)
+            .field("source", &self.source)
+            .field("has_metadata", &self.metadata.is_some())
+            .finish()
+    }
+}
+
+/// Access records when an item was last set or hit, whether it has been hit since it was last set, and when it entered the cache.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Access {
+    at: Instant,
+    read: bool,
+    created: Instant,
+}
+
+/// Lifetime describes how long an item leaving the cache was in it, and how long it went untouched before leaving.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Lifetime {
+    pub(crate) never_read: bool,
+    pub(crate) lived: Duration,
+    pub(crate) idle: Duration,
+}
+
+/// Metadata tracks the version and last access of every item and the metadata values attached to items.
+///
+/// Only items with metadata have a metadata value stored. Versions come from one counter per cache, so an item which is removed and set again never gets back a version it had before.
+pub(crate) struct Metadata<K> {
+    values: HashMap<K, EntryMetadata>,
+    versions: HashMap<K, u64>,
+    accessed: HashMap<K, Access>,
+    last_version: u64,
+}
+
+impl<K: Eq + Hash + Clone> Metadata<K> {
+    pub(crate) fn new() -> Self {
+        Metadata {
+            values: HashMap::new(),
+            versions: HashMap::new(),
+            accessed: HashMap::new(),
+            last_version: 0,
+        }
+    }
+
+    /// Set the metadata of a key, replacing or forgetting any previous metadata, and give the key a new version and an access time of `now`.
+    pub(crate) fn set(&mut self, key: &K, metadata: Option<EntryMetadata>, now: Instant) {
+        self.last_version += 1;
+        self.versions.insert(key.clone(), self.last_version);
+        // overwriting an item does not restart its lifetime
+        let created = self.accessed.get(key).map_or(now, |access| access.created);
+        self.accessed.insert(
+            key.clone(),
+            Access {
+                at: now,
+                read: false,
+                created,
+            },
+        );
+        match metadata {
+            Some(metadata) => {
+                self.values.insert(key.clone(), metadata);
+            }
+            None => {
+                if !self.values.is_empty() {
+                    self.values.remove(key);
+                }
+            }
+        }
+    }
+
+    /// Forget the metadata, version and access time of a key.
+    pub(crate) fn remove(&mut self, key: &K) {
+        self.versions.remove(key);
+        self.accessed.remove(key);
+        if !self.values.is_empty() {
+            self.values.remove(key);
+        }
+    }
+
+    /// Forget all metadata, versions and access times.
+    pub(crate) fn clear(&mut self) {
+        self.values.clear();
+        self.versions.clear();
+        self.accessed.clear();
+    }
+
+    /// Record that a key which has been set was hit by a get at `at`.
+    pub(crate) fn touch(&mut self, key: &K, at: Instant) {
+        if let Some(access) = self.accessed.get_mut(key) {
+            access.at = at;
+            access.read = true;
+        }
+    }
+
+    /// Get when a key was last set or accessed.
+    pub(crate) fn last_accessed(&self, key: &K) -> Option<Instant> {
+        self.accessed.get(key).map(|access| access.at)
+    }
+
+    /// Get how long a key has gone without being set or accessed, as of `now`.
+    pub(crate) fn idle_time(&self, key: &K, now: Instant) -> Option<Duration> {
+        self.accessed
+            .get(key)
+            .map(|access| now.saturating_duration_since(access.at))
+    }
+
+    /// Get how long a key has been in the cache and gone untouched as of `now`, and whether it was never hit since it was last set.
+    pub(crate) fn lifetime(&self, key: &K, now: Instant) -> Option<Lifetime> {
+        self.accessed.get(key).map(|access| Lifetime {
+            never_read: !access.read,
+            lived: now.saturating_duration_since(access.created),
+            idle: now.saturating_duration_since(access.at),
+        })
+    }
+
+    /// Get the last access of a key, to carry it over to another cache with `restore_access`.
+    pub(crate) fn access(&self, key: &K) -> Option<Access> {
+        self.accessed.get(key).copied()
+    }
+
+    /// Replace the last access of a key which has been set.
+    pub(crate) fn restore_access(&mut self, key: &K, access: Access) {
+        if let Some(current) = self.accessed.get_mut(key) {
+            *current = access;
+        }
+    }
+
+    /// Get the keys which have not been set or accessed for at least `older_than`.
+    pub(crate) fn idle(&self, now: Instant, older_than: Duration) -> Vec<K> {
+        self.accessed
+            .iter()
+            .filter(|(_, access)| now.saturating_duration_since(access.at) >= older_than)
+            .map(|(key, _)| key.clone())
+            .collect()
+    }
+
+    /// Get the metadata of a key.
+    pub(crate) fn get(&self, key: &K) -> Option<EntryMetadata> {
+        self.values.get(key).cloned()
+    }
+
+    /// Get the version of a key, or None if it has not been set.
+    pub(crate) fn version(&self, key: &K) -> Option<u64> {
+        self.versions.get(key).copied()
+    }
+
+    /// Check that a key is at `expected` before it is set, where 0 means the key must not have been set.
+    pub(crate) fn check_version(&self, key: &K, expected: Option<u64>) -> Result<(), CacheError> {
+        match expected {
+            Some(expected) => {
+                let actual = self.version(key).unwrap_or(0);
+                if actual == expected {
+                    Ok(())
+                } else {
+                    Err(CacheError::VersionMismatch(actual))
+                }
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_metadata() {
+        let mut metadata = Metadata::new();
+        metadata.set(&1, Some(Arc::new("a") as EntryMetadata), Instant::now());
+        let info = EntryInfo::new(0, None, metadata.get(&1), EntrySource::Inserted);
+        assert_eq!(info.metadata::<&str>(), Some(&"a"));
+        assert_eq!(info.metadata::<u64>(), None);
+        metadata.set(&1, None, Instant::now());
+        assert!(metadata.get(&1).is_none());
+    }
+
+    #[test]
+    fn test_metadata_versions() {
+        let mut metadata: Metadata<u32> = Metadata::new();
+        assert_eq!(metadata.check_version(&1, Some(0)), Ok(()));
+        metadata.set(&1, None, Instant::now());
+        metadata.set(&2, None, Instant::now());
+        assert_eq!(metadata.version(&1), Some(1));
+        assert_eq!(
+            metadata.check_version(&2, Some(1)),
+            Err(CacheError::VersionMismatch(2))
+        );
+        metadata.remove(&1);
+        assert_eq!(metadata.version(&1), None);
+        metadata.set(&1, None, Instant::now());
+        assert_eq!(metadata.version(&1), Some(3));
+    }
+
+    fn never_read(metadata: &Metadata<u32>, key: &u32) -> bool {
+        metadata
+            .lifetime(key, Instant::now())
+            .is_some_and(|lifetime| lifetime.never_read)
+    }
+
+    #[test]
+    fn test_metadata_access_times() {
+        let mut metadata: Metadata<u32> = Metadata::new();
+        metadata.set(&1, None, Instant::now());
+        metadata.set(&2, None, Instant::now());
+        let later = Instant::now() + Duration::from_secs(60);
+        metadata.touch(&2, later);
+        metadata.touch(&3, later);
+        assert_eq!(metadata.last_accessed(&2), Some(later));
+        assert_eq!(metadata.last_accessed(&3), None);
+        assert_eq!(
+            metadata.idle_time(&2, later + Duration::from_secs(5)),
+            Some(Duration::from_secs(5))
+        );
+        assert_eq!(metadata.idle(later, Duration::from_secs(30)), vec![1]);
+        assert!(never_read(&metadata, &1));
+        assert!(!never_read(&metadata, &2));
+        assert!(!never_read(&metadata, &3));
+        metadata.remove(&1);
+        assert!(metadata.idle(later, Duration::from_secs(30)).is_empty());
+        metadata.set(&2, None, Instant::now());
+        assert!(never_read(&metadata, &2));
+    }
+
+    #[test]
+    fn test_metadata_lifetime() {
+        let mut metadata: Metadata<u32> = Metadata::new();
+        let start = Instant::now();
+        metadata.set(&1, None, start);
+        metadata.touch(&1, start + Duration::from_secs(10));
+        metadata.set(&1, None, start + Duration::from_secs(20));
+        let lifetime = metadata
+            .lifetime(&1, start + Duration::from_secs(25))
+            .unwrap();
+        assert_eq!(lifetime.lived, Duration::from_secs(25));
+        assert_eq!(lifetime.idle, Duration::from_secs(5));
+        assert!(lifetime.never_read);
+        assert!(metadata.lifetime(&2, start).is_none());
+    }
+}
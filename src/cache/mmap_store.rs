@@ -0,0 +1,525 @@
+use crate::sync::Mutex;
+use std::collections::{HashMap, VecDeque};
+use std::fs::{self, OpenOptions};
+use std::hash::Hash;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use memmap2::MmapMut;
+
+use crate::background::BackgroundTask;
+use crate::cache::listener::{EvictionListener, RemovalCause, RemovalEvent};
+use crate::cache::Cache;
+#[cfg(feature = "serde")]
+use crate::codec::CborCodec;
+use crate::codec::{Codec, SerializationError};
+
+/// The default size of a segment file, 64 MiB.
+const DEFAULT_SEGMENT_BYTES: usize = 64 << 20;
+
+/// Where the bytes of a value live.
+#[derive(Debug, Clone, Copy)]
+struct Location {
+    segment: u64,
+    offset: usize,
+    len: usize,
+}
+
+/// A segment file mapped into memory, which values are appended to until it is full.
+struct Segment {
+    id: u64,
+    path: PathBuf,
+    map: MmapMut,
+    written: usize,
+    live: usize,
+}
+
+impl Segment {
+    /// Create a segment file of `size` bytes and map it.
+    fn create(dir: &Path, id: u64, size: usize) -> io::Result<Self> {
+        let path = dir.join(format!("segment-{:016x}.seg", id));
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&path)?;
+        file.set_len(size as u64)?;
+        // SAFETY: the file was just created by this store, which alone writes to or truncates it until it is deleted
+        let map = unsafe { MmapMut::map_mut(&file)? };
+        Ok(Segment {
+            id,
+            path,
+            map,
+            written: 0,
+            live: 0,
+        })
+    }
+
+    fn bytes(&self, location: &Location) -> &[u8] {
+        &self.map[location.offset..location.offset + location.len]
+    }
+}
+
+impl Drop for Segment {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+struct MmapStoreInner<K> {
+    dir: PathBuf,
+    index: HashMap<K, Location>,
+    // oldest first, the last segment is the one being appended to
+    segments: VecDeque<Segment>,
+    next_segment: u64,
+    segment_bytes: usize,
+    max_bytes: u64,
+    compaction_threshold: f64,
+    demotions: u64,
+    dropped: u64,
+}
+
+impl<K: Eq + Hash + Clone> MmapStoreInner<K> {
+    fn segment(&self, id: u64) -> Option<&Segment> {
+        let position = self
+            .segments
+            .binary_search_by_key(&id, |segment| segment.id);
+        position.ok().map(|position| &self.segments[position])
+    }
+
+    fn segment_mut(&mut self, id: u64) -> Option<&mut Segment> {
+        let position = self
+            .segments
+            .binary_search_by_key(&id, |segment| segment.id);
+        position.ok().map(|position| &mut self.segments[position])
+    }
+
+    fn mapped_bytes(&self) -> u64 {
+        self.segments
+            .iter()
+            .map(|segment| segment.map.len() as u64)
+            .sum()
+    }
+
+    /// Forget where a key's bytes are, marking them as dead in their segment.
+    fn forget(&mut self, key: &K) -> Option<Location> {
+        let location = self.index.remove(key)?;
+        if let Some(segment) = self.segment_mut(location.segment) {
+            segment.live -= location.len;
+        }
+        Some(location)
+    }
+
+    /// Append the bytes of a value, replacing any earlier value of the key.
+    fn append(&mut self, key: K, bytes: &[u8]) -> io::Result<()> {
+        self.forget(&key);
+        let fits = self
+            .segments
+            .back()
+            .is_some_and(|segment| segment.written + bytes.len() <= segment.map.len());
+        if !fits {
+            let size = self.segment_bytes.max(bytes.len());
+            let segment = Segment::create(&self.dir, self.next_segment, size)?;
+            self.next_segment += 1;
+            self.segments.push_back(segment);
+        }
+        let segment = self.segments.back_mut().expect("a segment was just added");
+        let offset = segment.written;
+        segment.map[offset..offset + bytes.len()].copy_from_slice(bytes);
+        segment.written += bytes.len();
+        segment.live += bytes.len();
+        let location = Location {
+            segment: segment.id,
+            offset,
+            len: bytes.len(),
+        };
+        self.index.insert(key, location);
+        self.drop_oldest();
+        Ok(())
+    }
+
+    /// Drop the oldest segments, and every value in them, until the mapped bytes are within the limit.
+    fn drop_oldest(&mut self) {
+        while self.segments.len() > 1 && self.mapped_bytes() > self.max_bytes {
+            let oldest = self.segments.pop_front().expect("there are segments");
+            let before = self.index.len();
+            self.index
+                .retain(|_, location| location.segment != oldest.id);
+            self.dropped += (before - self.index.len()) as u64;
+        }
+    }
+
+    /// Copy the live values of mostly dead segments into the newest segment and delete them, returning how many segments were compacted.
+    fn compact(&mut self) -> io::Result<usize> {
+        let sparse: Vec<u64> = self
+            .segments
+            .iter()
+            .take(self.segments.len().saturating_sub(1))
+            .filter(|segment| {
+                (segment.live as f64) < segment.written as f64 * self.compaction_threshold
+            })
+            .map(|segment| segment.id)
+            .collect();
+        for id in &sparse {
+            let Ok(position) = self.segments.binary_search_by_key(id, |segment| segment.id) else {
+                continue;
+            };
+            let mut segment = self
+                .segments
+                .remove(position)
+                .expect("the segment was found");
+            let live: Vec<(K, Location)> = self
+                .index
+                .iter()
+                .filter(|(_, location)| location.segment == *id)
+                .map(|(key, location)| (key.clone(), *location))
+                .collect();
+            for (key, location) in live {
+                self.index.remove(&key);
+                if let Err(error) = self.append(key.clone(), segment.bytes(&location)) {
+                    // keep the values which were not copied yet where they are
+                    self.index.insert(key, location);
+                    let position = self.segments.partition_point(|other| other.id < *id);
+                    self.segments.insert(position, segment);
+                    return Err(error);
+                }
+                segment.live -= location.len;
+            }
+        }
+        Ok(sparse.len())
+    }
+}
+
+/// MmapStore is a second tier for large values, which keeps them in memory-mapped segment files rather than on the heap.
+///
+/// Values are encoded by a `Codec` and appended to the newest segment file in a directory, and an in-memory index maps each key to where its bytes are, so the operating system pages values in and out rather than the heap holding them. `new` encodes values as CBOR and requires the `serde` feature, `new_with_codec` takes any codec. Each segment file is `with_segment_bytes` long, 64 MiB by default, or the size of the value if it is larger. Overwritten and removed values leave dead bytes behind, `compact` or `start_compaction` copies the live values out of segments which are mostly dead and deletes them. Once the segments take up more than `max_bytes` the oldest segment is dropped with every value in it.
+///
+/// The store is an `EvictionListener`, so registering a clone with a cache demotes every item the cache evicts into the store, and `promote` moves an item back into the cache. Demotion encodes and copies the value while the cache is locked. The index is not persisted, so segment files are deleted when the store is dropped and any left in the directory are deleted when a store is created.
+///
+/// This requires the `mmap` feature.
+///
+/// Example:
+/// ```
+/// # #[cfg(feature = "serde")]
+/// # {
+/// use arcache::{Cache, LRUCache, MmapStore};
+///
+/// let dir = std::env::temp_dir().join(format!("arcache-doc-{}", std::process::id()));
+/// let store = MmapStore::<u64, Vec<u8>>::new(&dir, 1 << 30).unwrap();
+/// let cache = LRUCache::new(1).with_eviction_listener(store.clone());
+///
+/// cache.set(1, vec![1; 1024]);
+/// cache.set(2, vec![2; 1024]);
+/// assert!(store.contains_key(&1));
+///
+/// assert!(store.promote(&1, &cache));
+/// assert_eq!(cache.get(&1).map(|value| value.len()), Some(1024));
+/// assert!(store.contains_key(&2));
+/// # }
+/// ```
+pub struct MmapStore<K, V> {
+    inner: Arc<Mutex<MmapStoreInner<K>>>,
+    codec: Arc<dyn Codec<V>>,
+}
+
+impl<K, V> Clone for MmapStore<K, V> {
+    fn clone(&self) -> Self {
+        MmapStore {
+            inner: self.inner.clone(),
+            codec: self.codec.clone(),
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<K, V> MmapStore<K, V>
+where
+    K: Eq + Hash + Clone + Send + Sync,
+    V: serde::Serialize + serde::de::DeserializeOwned + 'static,
+{
+    /// Create a new MmapStore keeping CBOR encoded values in segment files in `dir`, using at most `max_bytes` of them.
+    ///
+    /// This requires the `serde` feature.
+    pub fn new(dir: impl AsRef<Path>, max_bytes: u64) -> io::Result<Self> {
+        Self::new_with_codec(dir, max_bytes, CborCodec)
+    }
+}
+
+impl<K, V> MmapStore<K, V>
+where
+    K: Eq + Hash + Clone + Send + Sync,
+{
+    /// Create a new MmapStore keeping values encoded by `codec` in segment files in `dir`, using at most `max_bytes` of them.
+    ///
+    /// The directory is created if it does not exist, and segment files left in it by an earlier store are deleted.
+    pub fn new_with_codec(
+        dir: impl AsRef<Path>,
+        max_bytes: u64,
+        codec: impl Codec<V> + 'static,
+    ) -> io::Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|extension| extension == "seg") {
+                fs::remove_file(path)?;
+            }
+        }
+        Ok(MmapStore {
+            inner: Arc::new(Mutex::new(MmapStoreInner {
+                dir,
+                index: HashMap::new(),
+                segments: VecDeque::new(),
+                next_segment: 0,
+                segment_bytes: DEFAULT_SEGMENT_BYTES,
+                max_bytes,
+                compaction_threshold: 0.5,
+                demotions: 0,
+                dropped: 0,
+            })),
+            codec: Arc::new(codec),
+        })
+    }
+
+    /// Set the size of each segment file, values larger than this get a segment of their own.
+    pub fn with_segment_bytes(self, segment_bytes: usize) -> Self {
+        self.inner.lock().unwrap().segment_bytes = segment_bytes.max(1);
+        self
+    }
+
+    /// Compact segments in which less than `threshold` of the written bytes are live, 0.5 by default.
+    pub fn with_compaction_threshold(self, threshold: f64) -> Self {
+        self.inner.lock().unwrap().compaction_threshold = threshold.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Encode a value and append it to the store, replacing any earlier value of the key.
+    pub fn insert(&self, key: K, value: &V) -> Result<(), SerializationError> {
+        let bytes = self.codec.encode(value)?;
+        let mut inner = self.inner.lock().unwrap();
+        inner
+            .append(key, &bytes)
+            .map_err(|error| SerializationError::new(error.to_string()))
+    }
+
+    /// Get a value from the store, decoding it from its segment.
+    ///
+    /// Values which fail to decode are treated as misses.
+    pub fn get(&self, key: &K) -> Option<V> {
+        let inner = self.inner.lock().unwrap();
+        let location = inner.index.get(key)?;
+        let segment = inner.segment(location.segment)?;
+        self.codec.decode(segment.bytes(location)).ok()
+    }
+
+    /// Remove a value from the store, returning it.
+    pub fn take(&self, key: &K) -> Option<V> {
+        let mut inner = self.inner.lock().unwrap();
+        let location = inner.forget(key)?;
+        let segment = inner.segment(location.segment)?;
+        self.codec.decode(segment.bytes(&location)).ok()
+    }
+
+    /// Remove a value from the store, returning whether it was present.
+    pub fn remove(&self, key: &K) -> bool {
+        self.inner.lock().unwrap().forget(key).is_some()
+    }
+
+    /// Move a value from the store into a cache, returning whether the key was in the store.
+    pub fn promote(&self, key: &K, cache: &(impl Cache<K, V> + ?Sized)) -> bool
+    where
+        V: Send + Sync,
+    {
+        match self.take(key) {
+            Some(value) => {
+                cache.set(key.clone(), value);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Check if a key is in the store.
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.inner.lock().unwrap().index.contains_key(key)
+    }
+
+    /// Get the number of values in the store.
+    pub fn len(&self) -> usize {
+        self.inner.lock().unwrap().index.len()
+    }
+
+    /// Check if the store holds no values.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Get the number of bytes held by live values.
+    pub fn live_bytes(&self) -> u64 {
+        let inner = self.inner.lock().unwrap();
+        inner
+            .segments
+            .iter()
+            .map(|segment| segment.live as u64)
+            .sum()
+    }
+
+    /// Get the total size of the segment files.
+    pub fn mapped_bytes(&self) -> u64 {
+        self.inner.lock().unwrap().mapped_bytes()
+    }
+
+    /// Get the number of segment files.
+    pub fn segment_count(&self) -> usize {
+        self.inner.lock().unwrap().segments.len()
+    }
+
+    /// Get the number of items demoted into the store by a cache it listens to.
+    pub fn demotions(&self) -> u64 {
+        self.inner.lock().unwrap().demotions
+    }
+
+    /// Get the number of values dropped with the oldest segment to stay within `max_bytes`.
+    pub fn dropped(&self) -> u64 {
+        self.inner.lock().unwrap().dropped
+    }
+
+    /// Remove every value and delete every segment file.
+    pub fn clear(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.index.clear();
+        inner.segments.clear();
+    }
+
+    /// Copy the live values out of segments which are mostly dead and delete them, returning how many segments were compacted.
+    ///
+    /// The segment being appended to is never compacted.
+    pub fn compact(&self) -> io::Result<usize> {
+        self.inner.lock().unwrap().compact()
+    }
+
+    /// Start a background thread which compacts the store every `interval`.
+    ///
+    /// The thread runs until the returned handle is dropped or shut down, or the store is dropped. Compactions which fail, for example because the disk is full, are retried at the next interval.
+    pub fn start_compaction(&self, interval: Duration) -> BackgroundTask
+    where
+        K: 'static,
+    {
+        let inner = Arc::downgrade(&self.inner);
+        BackgroundTask::spawn_periodic("arcache-mmap-compaction", interval, move || {
+            match inner.upgrade() {
+                Some(inner) => {
+                    let _ = inner.lock().unwrap().compact();
+                    true
+                }
+                None => false,
+            }
+        })
+    }
+}
+
+impl<K, V> EvictionListener<K, V> for MmapStore<K, V>
+where
+    K: Eq + Hash + Clone + Send + Sync,
+{
+    fn on_removal(&self, event: &RemovalEvent<K, V>) {
+        if event.cause != RemovalCause::Evicted {
+            return;
+        }
+        // a value which cannot be encoded or written is dropped, as it would have been without the store
+        if self.insert(event.key.clone(), event.value.as_ref()).is_ok() {
+            self.inner.lock().unwrap().demotions += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::LRUCache;
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    struct BytesCodec;
+
+    impl Codec<Vec<u8>> for BytesCodec {
+        fn encode(&self, value: &Vec<u8>) -> Result<Vec<u8>, SerializationError> {
+            Ok(value.clone())
+        }
+
+        fn decode(&self, bytes: &[u8]) -> Result<Vec<u8>, SerializationError> {
+            Ok(bytes.to_vec())
+        }
+    }
+
+    fn store(segment_bytes: usize, max_bytes: u64) -> MmapStore<u64, Vec<u8>> {
+        static NEXT: AtomicU64 = AtomicU64::new(0);
+        let dir = std::env::temp_dir().join(format!(
+            "arcache-mmap-{}-{}",
+            std::process::id(),
+            NEXT.fetch_add(1, Ordering::Relaxed)
+        ));
+        MmapStore::new_with_codec(dir, max_bytes, BytesCodec)
+            .unwrap()
+            .with_segment_bytes(segment_bytes)
+    }
+
+    #[test]
+    fn test_mmap_store_demotes_and_promotes() {
+        let store = store(1024, 1 << 20);
+        let cache = LRUCache::new(1).with_eviction_listener(store.clone());
+        cache.set(1, vec![1; 100]);
+        cache.set(2, vec![2; 100]);
+        cache.remove(&2);
+        assert_eq!(store.demotions(), 1);
+        assert_eq!(store.get(&1), Some(vec![1; 100]));
+        assert!(!store.contains_key(&2));
+
+        assert!(store.promote(&1, &cache));
+        assert_eq!(cache.get(&1).as_deref(), Some(&vec![1; 100]));
+        assert!(!store.promote(&1, &cache));
+        assert!(store.is_empty());
+        assert_eq!(store.live_bytes(), 0);
+    }
+
+    #[test]
+    fn test_mmap_store_segments_and_limit() {
+        let store = store(256, 1024);
+        for key in 0..8 {
+            store.insert(key, &vec![key as u8; 100]).unwrap();
+        }
+        // two values fit in each segment
+        assert_eq!(store.segment_count(), 4);
+        assert_eq!(store.mapped_bytes(), 1024);
+        store.insert(8, &vec![8; 100]).unwrap();
+        assert_eq!(store.dropped(), 2);
+        assert!(!store.contains_key(&0) && !store.contains_key(&1));
+        assert_eq!(store.get(&8), Some(vec![8; 100]));
+
+        // values larger than a segment get one of their own
+        store.insert(9, &vec![9; 600]).unwrap();
+        assert_eq!(store.get(&9).map(|value| value.len()), Some(600));
+        assert_eq!(store.dropped(), 8);
+        assert_eq!(store.len(), 2);
+        assert_eq!(store.mapped_bytes(), 256 + 600);
+    }
+
+    #[test]
+    fn test_mmap_store_compaction() {
+        let store = store(256, 1 << 20).with_compaction_threshold(0.6);
+        for key in 0..4 {
+            store.insert(key, &vec![key as u8; 100]).unwrap();
+        }
+        store.remove(&0);
+        store.insert(2, &vec![20; 100]).unwrap();
+        assert_eq!(store.segment_count(), 3);
+        assert_eq!(store.compact().unwrap(), 2);
+        assert_eq!(store.segment_count(), 2);
+        assert_eq!(store.get(&1), Some(vec![1; 100]));
+        assert_eq!(store.get(&2), Some(vec![20; 100]));
+        assert_eq!(store.get(&3), Some(vec![3; 100]));
+        assert_eq!(store.live_bytes(), 300);
+    }
+}
@@ -1,14 +1,102 @@
-use crate::cache::{Cache, CacheStats};
-use linked_hash_map::LinkedHashMap;
+use crate::cache::latency::{LatencyTracker, OperationLatencies};
+use crate::cache::listener::{
+    AccessListener, AccessListeners, CallbackPanics, EntrySource, EvictionListener, RemovalCause,
+};
+use crate::cache::metadata::{EntryInfo, EntryMetadata};
+use crate::cache::policy_core::{policy_methods, PolicyCore};
+use crate::cache::priority::DEFAULT_PRIORITY;
+use crate::cache::{
+    shed_count, split_capacity, Cache, CacheStats, DetailedStats, FullBehavior, GetOutcome,
+    IterationOrder, TryCache, VersionedCache,
+};
+use crate::clock::Clock;
+use crate::error::CacheError;
+use crate::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use crate::sync::{Mutex, RwLock};
+use std::any::Any;
+use std::collections::HashMap;
 use std::hash::Hash;
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// An item in the MRUCache with the tick of its last use.
+///
+/// The tick and read flag are atomic so gets can update them while holding only a shared lock.
+struct MRUEntry<V> {
+    value: Arc<V>,
+    used: AtomicU64,
+    // whether the item has been read since it was last set, kept here rather than in the metadata so gets need not touch it
+    read: AtomicBool,
+}
+
+impl<V> MRUEntry<V> {
+    fn new(value: Arc<V>, used: u64) -> Self {
+        MRUEntry {
+            value,
+            used: AtomicU64::new(used),
+            read: AtomicBool::new(false),
+        }
+    }
+}
+
+/// The number of recent uses remembered to find the most recently used item.
+const RECENT_USES: usize = 32;
+
+/// A ring of the most recent uses, each kept in the slot of its tick, so the most recently used item can be found without scanning every item, even after the latest ones were removed.
+///
+/// Gets write it while holding only the shared cache lock. Each slot has its own lock which writers only ever try, so a use is dropped rather than waited for if two gets land on the same slot, and a dropped use only costs a scan of the items.
+struct RecentUses<K> {
+    slots: Vec<Mutex<Option<(K, u64)>>>,
+}
+
+impl<K: Clone> RecentUses<K> {
+    fn new() -> Self {
+        RecentUses {
+            slots: (0..RECENT_USES).map(|_| Mutex::new(None)).collect(),
+        }
+    }
+
+    fn slot(&self, tick: u64) -> &Mutex<Option<(K, u64)>> {
+        &self.slots[(tick % RECENT_USES as u64) as usize]
+    }
+
+    /// Record that `key` was used at `tick`.
+    fn record(&self, key: &K, tick: u64) {
+        if let Ok(mut slot) = self.slot(tick).try_lock() {
+            if slot.as_ref().is_none_or(|(_, recorded)| *recorded < tick) {
+                *slot = Some((key.clone(), tick));
+            }
+        }
+    }
+
+    /// Get the key used at `tick`, or None if that use was dropped or has been overwritten.
+    fn at(&self, tick: u64) -> Option<K> {
+        let slot = self.slot(tick).lock().ok()?;
+        slot.as_ref()
+            .filter(|(_, recorded)| *recorded == tick)
+            .map(|(key, _)| key.clone())
+    }
+
+    /// Forget every use.
+    fn clear(&self) {
+        for slot in &self.slots {
+            if let Ok(mut slot) = slot.lock() {
+                *slot = None;
+            }
+        }
+    }
+}
 
 /// The inner data structure for the MRUCache.
+///
+/// Only the most recently used key is needed to evict, so items are kept unordered with a tick of their last use, and a get does not reorder anything. The next item to evict is found from the ring of recent uses, and the full recency order is rebuilt from the ticks when it is asked for.
 struct MRUCacheInner<K: Eq + Hash + Clone + Sync + Send, V: Send + Sync> {
     capacity: u64,
-    key_value_map: LinkedHashMap<K, Arc<V>>,
-    hits: u64,
-    misses: u64,
+    key_value_map: HashMap<K, MRUEntry<V>>,
+    tick: AtomicU64,
+    recent: RecentUses<K>,
+    idle_tracking: bool,
+    core: PolicyCore<K, V>,
 }
 
 impl<K: Eq + Hash + Clone + Sync + Send, V: Send + Sync> MRUCacheInner<K, V> {
@@ -16,10 +104,164 @@ impl<K: Eq + Hash + Clone + Sync + Send, V: Send + Sync> MRUCacheInner<K, V> {
     fn new(capacity: u64) -> Self {
         MRUCacheInner {
             capacity,
-            key_value_map: LinkedHashMap::with_capacity(capacity as usize),
-            hits: 0,
-            misses: 0,
+            key_value_map: HashMap::with_capacity(capacity as usize),
+            tick: AtomicU64::new(0),
+            recent: RecentUses::new(),
+            idle_tracking: false,
+            core: PolicyCore::new(),
+        }
+    }
+
+    /// Check if a key is held.
+    fn holds(&self, key: &K) -> bool {
+        self.key_value_map.contains_key(key)
+    }
+
+    /// Take the next tick.
+    fn next_tick(&self) -> u64 {
+        self.tick.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    /// Mark an item as the most recently used and as read, returning its value.
+    fn touch(&self, key: &K) -> Option<Arc<V>> {
+        let entry = self.key_value_map.get(key)?;
+        let tick = self.next_tick();
+        entry.used.fetch_max(tick, Ordering::Relaxed);
+        if !entry.read.load(Ordering::Relaxed) {
+            entry.read.store(true, Ordering::Relaxed);
+        }
+        self.recent.record(key, tick);
+        Some(entry.value.clone())
+    }
+
+    /// Get the items from least to most recently used.
+    fn by_recency(&self) -> Vec<(&K, &Arc<V>)> {
+        let mut items: Vec<(&K, &MRUEntry<V>)> = self.key_value_map.iter().collect();
+        items.sort_unstable_by_key(|(_, entry)| entry.used.load(Ordering::Relaxed));
+        items
+            .into_iter()
+            .map(|(key, entry)| (key, &entry.value))
+            .collect()
+    }
+
+    /// Get the most recently used key for which `wanted` returns true.
+    ///
+    /// The recent uses are walked back from the latest tick, skipping uses of items which have since been removed or used again, so removing the most recently used item leaves the next one to hand. The ticks are only scanned if the walk reaches a dropped use or the end of the ring.
+    fn most_recent_key(&self, wanted: impl Fn(&K) -> bool) -> Option<K> {
+        let latest = self.tick.load(Ordering::Relaxed);
+        for tick in (latest.saturating_sub(RECENT_USES as u64) + 1..=latest).rev() {
+            let Some(key) = self.recent.at(tick) else {
+                break;
+            };
+            let current = self
+                .key_value_map
+                .get(&key)
+                .is_some_and(|entry| entry.used.load(Ordering::Relaxed) == tick);
+            if current && wanted(&key) {
+                return Some(key);
+            }
+        }
+        self.key_value_map
+            .iter()
+            .filter(|(key, _)| wanted(key))
+            .max_by_key(|(_, entry)| entry.used.load(Ordering::Relaxed))
+            .map(|(key, _)| key.clone())
+    }
+
+    /// Evict the most recently used item, or the most recently used item with the lowest priority if priorities have been assigned.
+    fn evict(&mut self) -> Option<(K, Arc<V>)> {
+        let len = self.key_value_map.len();
+        let key = match self.core.priorities.lowest(len) {
+            // only the keys at the lowest priority are looked at, through the priority index
+            Some(lowest) => match self.core.priorities.keys_with(lowest) {
+                Some(keys) => keys
+                    .max_by_key(|key| {
+                        self.key_value_map
+                            .get(*key)
+                            .map(|entry| entry.used.load(Ordering::Relaxed))
+                    })
+                    .cloned(),
+                None => {
+                    self.most_recent_key(|key| self.core.priorities.get(key) == DEFAULT_PRIORITY)
+                }
+            },
+            None => self.most_recent_key(|_| true),
+        }?;
+        self.evict_key(key)
+    }
+
+    /// Evict items until at most `len` are left, sorting by recency once rather than scanning for every item.
+    fn evict_to(&mut self, len: usize) -> Vec<(K, Arc<V>)> {
+        let excess = self.key_value_map.len().saturating_sub(len);
+        if excess <= 1
+            || self
+                .core
+                .priorities
+                .lowest(self.key_value_map.len())
+                .is_some()
+        {
+            return (0..excess).filter_map(|_| self.evict()).collect();
+        }
+        let keys: Vec<K> = self
+            .by_recency()
+            .iter()
+            .rev()
+            .take(excess)
+            .map(|(key, _)| (*key).clone())
+            .collect();
+        keys.into_iter()
+            .filter_map(|key| self.evict_key(key))
+            .collect()
+    }
+
+    /// Evict an item, notifying listeners and counting the eviction.
+    fn evict_key(&mut self, key: K) -> Option<(K, Arc<V>)> {
+        let mut lifetime = self.core.metadata.lifetime(&key, self.core.clock.now());
+        if let (Some(lifetime), Some(entry)) = (lifetime.as_mut(), self.key_value_map.get(&key)) {
+            lifetime.never_read &= !entry.read.load(Ordering::Relaxed);
         }
+        let value = self.remove_entry(&key)?;
+        self.core
+            .listeners
+            .notify(&key, &value, RemovalCause::Evicted);
+        self.core.stats.record_eviction(lifetime);
+        Some((key, value))
+    }
+
+    /// Remove an item, forgetting its priority and deadline.
+    fn remove_entry(&mut self, key: &K) -> Option<Arc<V>> {
+        self.core.forget(key);
+        self.key_value_map.remove(key).map(|entry| entry.value)
+    }
+
+    /// Remove every item which has passed its deadline.
+    fn purge_expired(&mut self) {
+        let now = self.core.clock.now();
+        while let Some(key) = self.core.expiries.pop_expired(now) {
+            if let Some(value) = self.remove_entry(&key) {
+                self.core
+                    .listeners
+                    .notify(&key, &value, RemovalCause::Expired);
+                self.core.stats.record_expiration();
+            }
+        }
+    }
+
+    /// Remove every item, notifying listeners and returning the removed items.
+    fn clear_entries(&mut self) -> Vec<(K, Arc<V>)> {
+        self.core.clear();
+        self.recent.clear();
+        let cleared: Vec<(K, Arc<V>)> = self
+            .key_value_map
+            .drain()
+            .map(|(key, entry)| (key, entry.value))
+            .collect();
+        for (key, value) in &cleared {
+            self.core
+                .listeners
+                .notify(key, value, RemovalCause::Cleared);
+        }
+        cleared
     }
 }
 
@@ -27,7 +269,9 @@ impl<K: Eq + Hash + Clone + Sync + Send, V: Send + Sync> MRUCacheInner<K, V> {
 ///
 /// When the cache is full, the item with the most recent access is removed to make space for the new item. This is the opposite of the LRU cache.
 ///
-/// All mutability is handled internally with a Mutex, so the cache can be shared between threads. Values are returned as Arcs to allow for shared ownership.
+/// A get only stamps the item with an atomic tick, so gets take a shared lock and never wait on each other.
+///
+/// All mutability is handled internally with a RwLock, so the cache can be shared between threads. Values are returned as Arcs to allow for shared ownership.
 ///
 /// Example:
 /// ```
@@ -46,85 +290,536 @@ impl<K: Eq + Hash + Clone + Sync + Send, V: Send + Sync> MRUCacheInner<K, V> {
 /// println!("{:?}", cache.stats());
 /// ```
 pub struct MRUCache<K: Eq + Hash + Clone + Sync + Send, V: Send + Sync> {
-    inner: Mutex<MRUCacheInner<K, V>>,
+    inner: Arc<RwLock<MRUCacheInner<K, V>>>,
+    access_listeners: Arc<AccessListeners<K>>,
+    latency: Arc<LatencyTracker>,
 }
 
 impl<K: Eq + Hash + Clone + Sync + Send, V: Send + Sync> MRUCache<K, V> {
     /// Create a new MRUCache with the given capacity.
     pub fn new(capacity: u64) -> Self {
         MRUCache {
-            inner: Mutex::new(MRUCacheInner::new(capacity)),
+            inner: Arc::new(RwLock::new(MRUCacheInner::new(capacity))),
+            access_listeners: Arc::new(AccessListeners::new()),
+            latency: Arc::new(LatencyTracker::new()),
+        }
+    }
+
+    policy_methods!(read, write);
+
+    /// Record when every get hits an item, so `last_accessed`, `idle_time`, `purge_idle` and the idle ages of evicted items count reads as well as sets.
+    ///
+    /// By default a get only stamps the item with a tick, and an item counts as idle from when it was last set. With idle tracking every get takes the exclusive lock to record the time.
+    pub fn with_idle_tracking(self) -> Self {
+        self.inner.write().unwrap().idle_tracking = true;
+        self
+    }
+
+    /// Set a value in the cache with a priority, when the cache is full lower priority items are evicted before higher priority ones.
+    ///
+    /// Within a priority the most recently used item is evicted first. New items set with `set` have the lowest priority, `DEFAULT_PRIORITY`, and setting a key again without a priority keeps the one it has.
+    pub fn set_with_priority(&self, key: K, value: V, priority: u32) -> Option<Arc<V>> {
+        self.insert(
+            key,
+            Arc::new(value),
+            Some(priority),
+            None,
+            None,
+            None,
+            EntrySource::Inserted,
+        )
+        .unwrap_or(None)
+    }
+
+    /// Set a value in the cache which expires after the given time-to-live, overriding the cache's default TTL.
+    ///
+    /// Expired items are never returned and are removed before any live item is evicted.
+    pub fn set_with_ttl(&self, key: K, value: V, ttl: Duration) -> Option<Arc<V>> {
+        self.insert(
+            key,
+            Arc::new(value),
+            None,
+            Some(ttl),
+            None,
+            None,
+            EntrySource::Inserted,
+        )
+        .unwrap_or(None)
+    }
+
+    /// Set a value in the cache with a metadata value attached, which can be read back with `entry_info`.
+    ///
+    /// Metadata is dropped when the item leaves the cache or is set again without metadata.
+    pub fn set_with_metadata(
+        &self,
+        key: K,
+        value: V,
+        metadata: impl Any + Send + Sync,
+    ) -> Option<Arc<V>> {
+        self.insert(
+            key,
+            Arc::new(value),
+            None,
+            None,
+            Some(Arc::new(metadata)),
+            None,
+            EntrySource::Inserted,
+        )
+        .unwrap_or(None)
+    }
+
+    /// Get how close an item is to eviction, 0 is the next item the policy would evict, without counting a hit or miss or updating its position.
+    ///
+    /// Priorities are not taken into account.
+    pub fn age_rank(&self, key: &K) -> Option<usize> {
+        let inner = self.inner.read().unwrap();
+        if inner.core.expiries.is_expired(key, inner.core.clock.now()) {
+            return None;
+        }
+        inner
+            .by_recency()
+            .iter()
+            .rev()
+            .position(|(candidate, _)| *candidate == key)
+    }
+
+    /// Get when an item was last set, or hit by a get if the cache was built `with_idle_tracking`, without counting a hit or miss or updating its position.
+    pub fn last_accessed(&self, key: &K) -> Option<Instant> {
+        let inner = self.inner.read().unwrap();
+        if inner.core.expiries.is_expired(key, inner.core.clock.now()) {
+            return None;
+        }
+        inner.core.metadata.last_accessed(key)
+    }
+
+    /// Move every item matching `predicate` into a new cache, whose capacity is in proportion to the share of items moved.
+    ///
+    /// Moved items keep their priority and remaining TTL. Recency order is preserved. Listeners on this cache are notified with `RemovalCause::Removed`, they are not carried over to the new cache.
+    pub fn split_off<F: Fn(&K, &V) -> bool>(&self, predicate: F) -> Self {
+        let mut inner = self.inner.write().unwrap();
+        inner.purge_expired();
+        let keys: Vec<K> = inner
+            .by_recency()
+            .into_iter()
+            .filter(|(key, value)| predicate(key, value))
+            .map(|(key, _)| key.clone())
+            .collect();
+        let capacity = split_capacity(inner.capacity, keys.len(), inner.key_value_map.len());
+        let split = Self::new(capacity);
+        split.inner.write().unwrap().core.clock = inner.core.clock.clone();
+        if let Some(ttl) = inner.core.expiries.default_ttl() {
+            split
+                .inner
+                .write()
+                .unwrap()
+                .core
+                .expiries
+                .set_default_ttl(ttl);
+        }
+        let now = inner.core.clock.now();
+        for key in keys {
+            let priority = inner.core.priorities.get(&key);
+            let ttl = inner.core.expiries.remaining(&key, now);
+            let metadata = inner.core.metadata.get(&key);
+            let source = inner.core.listeners.source(&key);
+            let access = inner.core.metadata.access(&key);
+            if let Some(value) = inner.remove_entry(&key) {
+                inner
+                    .core
+                    .listeners
+                    .notify(&key, &value, RemovalCause::Removed);
+                let _ = split.insert(
+                    key.clone(),
+                    value,
+                    Some(priority),
+                    ttl,
+                    metadata,
+                    None,
+                    source,
+                );
+                if let Some(access) = access {
+                    split
+                        .inner
+                        .write()
+                        .unwrap()
+                        .core
+                        .metadata
+                        .restore_access(&key, access);
+                }
+            }
+        }
+        split
+    }
+
+    /// Insert a value with a priority and an optional TTL, evicting an item if the cache is full.
+    #[allow(clippy::too_many_arguments)]
+    fn insert(
+        &self,
+        key: K,
+        value: Arc<V>,
+        priority: Option<u32>,
+        ttl: Option<Duration>,
+        metadata: Option<EntryMetadata>,
+        expected_version: Option<u64>,
+        source: EntrySource,
+    ) -> Result<Option<Arc<V>>, CacheError> {
+        let mut inner = self.inner.write().map_err(|_| CacheError::Poisoned)?;
+        if inner.capacity == 0 {
+            return Err(CacheError::CapacityZero);
+        }
+        inner.purge_expired();
+        inner.core.metadata.check_version(&key, expected_version)?;
+        // overwriting a key never evicts another item
+        let is_new = !inner.key_value_map.contains_key(&key);
+        if is_new
+            && !inner.core.full_behavior.evicts()
+            && inner.key_value_map.len() as u64 >= inner.capacity
+        {
+            inner.core.listeners.reject(&key);
+            return inner.core.full_behavior.rejection();
+        }
+        if is_new && inner.key_value_map.len() as u64 + 1 > inner.capacity {
+            inner.evict();
+        }
+        inner.core.stats.record_set(is_new);
+        inner.core.track(&key, priority, ttl, metadata, source);
+        let tick = inner.next_tick();
+        inner.recent.record(&key, tick);
+        let entry = MRUEntry::new(value, tick);
+        Ok(inner
+            .key_value_map
+            .insert(key, entry)
+            .map(|entry| entry.value))
+    }
+}
+
+impl<K: Eq + Hash + Clone + Sync + Send, V: Send + Sync> Clone for MRUCache<K, V> {
+    /// Get another handle to the same cache, sharing its items, settings and statistics.
+    fn clone(&self) -> Self {
+        MRUCache {
+            inner: self.inner.clone(),
+            access_listeners: self.access_listeners.clone(),
+            latency: self.latency.clone(),
         }
     }
 }
 
 impl<K: Eq + Hash + Clone + Sync + Send, V: Send + Sync> Cache<K, V> for MRUCache<K, V> {
     /// Get a value from the cache.
+    ///
+    /// Gets hold only the shared lock, unless the key has expired and must be removed or the cache was built `with_idle_tracking`.
     fn get(&self, key: &K) -> Option<Arc<V>> {
-        let mut inner = self.inner.lock().unwrap();
-        let result = inner.key_value_map.get_refresh(key).cloned();
-
-        if result.is_some() {
-            inner.hits += 1;
-        } else {
-            inner.misses += 1;
-        }
+        let started = self.latency.start();
+        let shared = {
+            let inner = self.inner.read().unwrap();
+            if inner.idle_tracking || inner.core.is_expired(key) {
+                None
+            } else {
+                let result = inner.touch(key);
+                match result {
+                    Some(_) => inner.core.stats.record_shared_hit(),
+                    None => inner.core.stats.record_shared_miss(),
+                }
+                Some(result)
+            }
+        };
+        let result = shared.unwrap_or_else(|| {
+            let mut inner = self.inner.write().unwrap();
+            inner.purge_expired();
+            let result = inner.touch(key);
+            if result.is_some() {
+                inner.core.stats.record_hit();
+                if inner.idle_tracking {
+                    let now = inner.core.clock.now();
+                    inner.core.metadata.touch(key, now);
+                }
+            } else {
+                inner.core.stats.record_miss();
+            }
+            result
+        });
+        self.latency.record_get(started);
+        self.access_listeners.notify(key, &result);
         result
     }
 
+    /// Get a value from the cache, with whether it was a hit, or a miss because the key expired, was rejected or is absent.
+    fn get_with_outcome(&self, key: &K) -> (Option<Arc<V>>, GetOutcome) {
+        let result = self.get(key);
+        let outcome = match result {
+            Some(_) => GetOutcome::Hit,
+            None => self.inner.read().unwrap().core.listeners.miss_outcome(key),
+        };
+        (result, outcome)
+    }
+
     /// Set a value in the cache.
     fn set(&self, key: K, value: V) -> Option<Arc<V>> {
-        let mut inner = self.inner.lock().unwrap();
-        let arc_value = Arc::new(value);
+        let started = self.latency.start();
+        let result = self
+            .insert(
+                key,
+                Arc::new(value),
+                None,
+                None,
+                None,
+                None,
+                EntrySource::Inserted,
+            )
+            .unwrap_or(None);
+        self.latency.record_set(started);
+        result
+    }
 
-        if inner.key_value_map.len() as u64 + 1 > inner.capacity {
-            inner.key_value_map.pop_back();
-        }
-        inner.key_value_map.insert(key, arc_value)
+    /// Set a value in the cache, recording how it came to be in the cache for `entry_info` and eviction listeners.
+    fn set_with_source(&self, key: K, value: V, source: EntrySource) -> Option<Arc<V>> {
+        let started = self.latency.start();
+        let result = self
+            .insert(key, Arc::new(value), None, None, None, None, source)
+            .unwrap_or(None);
+        self.latency.record_set(started);
+        result
     }
 
     /// Remove a value from the cache.
     fn remove(&self, key: &K) -> Option<Arc<V>> {
-        let mut inner = self.inner.lock().unwrap();
-        inner.key_value_map.remove(key)
+        let mut inner = self.inner.write().unwrap();
+        let result = inner.remove_entry(key);
+        if let Some(value) = &result {
+            inner
+                .core
+                .listeners
+                .notify(key, value, RemovalCause::Removed);
+        }
+        result
+    }
+
+    /// Check if a key is in the cache and has not expired, without counting a hit or miss or updating its position.
+    fn contains_key(&self, key: &K) -> bool {
+        let inner = self.inner.read().unwrap();
+        inner.key_value_map.contains_key(key)
+            && !inner.core.expiries.is_expired(key, inner.core.clock.now())
+    }
+
+    /// Get the time left before an item expires, or None if it never expires or is not in the cache.
+    fn ttl_remaining(&self, key: &K) -> Option<Duration> {
+        self.entry_info(key)?.ttl_remaining
     }
 
     /// Clear the cache, removing all items.
     fn clear(&self) {
-        let mut inner = self.inner.lock().unwrap();
-        inner.key_value_map.clear();
+        let mut inner = self.inner.write().unwrap();
+        inner.clear_entries();
+    }
+
+    /// Clear the cache, returning every item which was removed.
+    fn clear_returning(&self) -> Vec<(K, Arc<V>)> {
+        let mut inner = self.inner.write().unwrap();
+        inner.clear_entries()
+    }
+
+    /// Copy the unexpired items into a HashMap in one locked pass.
+    fn to_map(&self) -> HashMap<K, Arc<V>> {
+        let inner = self.inner.read().unwrap();
+        let now = inner.core.clock.now();
+        inner
+            .key_value_map
+            .iter()
+            .filter(|(key, _)| !inner.core.expiries.is_expired(key, now))
+            .map(|(key, entry)| (key.clone(), entry.value.clone()))
+            .collect()
+    }
+
+    /// Get up to `limit` unexpired items, ordered with the items the policy would evict last first. Higher priority items come before lower ones, and otherwise least recently used first, as the most recently used items are evicted first.
+    fn hottest(&self, limit: usize) -> Vec<(K, Arc<V>)> {
+        let inner = self.inner.read().unwrap();
+        let now = inner.core.clock.now();
+        let mut items: Vec<(K, Arc<V>)> = inner
+            .by_recency()
+            .into_iter()
+            .filter(|(key, _)| !inner.core.expiries.is_expired(key, now))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect();
+        inner.core.priorities.sort_descending(&mut items);
+        items.truncate(limit);
+        items
+    }
+
+    /// Get the unexpired items in recency order, least recently used first, or in any order.
+    fn items(&self, order: IterationOrder) -> Option<Vec<(K, Arc<V>)>> {
+        if !matches!(order, IterationOrder::Recency | IterationOrder::Arbitrary) {
+            return None;
+        }
+        let inner = self.inner.read().unwrap();
+        let now = inner.core.clock.now();
+        let items = inner
+            .by_recency()
+            .into_iter()
+            .filter(|(key, _)| !inner.core.expiries.is_expired(key, now))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect();
+        Some(items)
+    }
+
+    /// Get the name of the eviction policy.
+    fn policy_name(&self) -> &'static str {
+        "mru"
+    }
+
+    /// Get the cache as `Any`, so it can be downcast from a `dyn Cache`.
+    fn as_any(&self) -> Option<&dyn Any>
+    where
+        Self: 'static,
+    {
+        Some(self)
     }
 
     /// Get the cache statistics.
     fn stats(&self) -> CacheStats {
-        let inner = self.inner.lock().unwrap();
-        CacheStats {
-            hits: inner.hits,
-            misses: inner.misses,
-            size: inner.key_value_map.len() as u64,
-            capacity: inner.capacity,
-        }
+        let mut inner = self.inner.write().unwrap();
+        inner.core.stats.fold_shared();
+        inner
+            .core
+            .stats
+            .snapshot(inner.key_value_map.len() as u64, inner.capacity)
+    }
+
+    /// Get the cache statistics together with how soon the items expire.
+    fn detailed_stats(&self) -> DetailedStats {
+        let mut inner = self.inner.write().unwrap();
+        inner.core.stats.fold_shared();
+        let size = inner.key_value_map.len() as u64;
+        let mut detailed = DetailedStats::new(inner.core.stats.snapshot(size, inner.capacity));
+        detailed.eviction_ages = Some(inner.core.stats.eviction_ages());
+        detailed.expiry_distribution = Some(
+            inner
+                .core
+                .expiries
+                .distribution(inner.core.clock.now(), size),
+        );
+        detailed
     }
 
     /// Change the capacity of the cache, if the new capacity is less than the current capacity, the cache will evict the most recently used items until the size equals the new capacity.
     fn change_capacity(&self, capacity: u64) {
-        let mut inner = self.inner.lock().unwrap();
+        self.change_capacity_collect(capacity);
+    }
+
+    /// Change the capacity of the cache, returning the items which were removed to fit the new capacity.
+    fn change_capacity_collect(&self, capacity: u64) -> Vec<(K, Arc<V>)> {
+        let mut inner = self.inner.write().unwrap();
         let old_capacity = inner.capacity;
         inner.capacity = capacity;
-        while inner.key_value_map.len() as u64 > inner.capacity {
-            inner.key_value_map.pop_back();
-        }
+        let evicted = inner.evict_to(capacity as usize);
 
         if inner.capacity > old_capacity {
             let additional = (inner.capacity - old_capacity) as usize;
             inner.key_value_map.reserve(additional);
         }
+        evicted
+    }
+
+    /// Evict `fraction` of the items per the policy in one locked pass, leaving the capacity unchanged.
+    fn shed(&self, fraction: f64) -> usize {
+        let mut inner = self.inner.write().unwrap();
+        let len = inner.key_value_map.len();
+        inner.evict_to(len - shed_count(len, fraction)).len()
+    }
+}
+
+impl<K: Eq + Hash + Clone + Sync + Send, V: Send + Sync> TryCache<K, V> for MRUCache<K, V> {
+    /// Get a value from the cache, returning `CacheError::Poisoned` instead of panicking if the cache lock is poisoned.
+    fn try_get(&self, key: &K) -> Result<Option<Arc<V>>, CacheError> {
+        if self.inner.is_poisoned() {
+            return Err(CacheError::Poisoned);
+        }
+        Ok(self.get(key))
+    }
+
+    /// Set a value in the cache, returning an error instead of inserting if the cache is full and was built with `FullBehavior::ReturnError`, has a capacity of zero, or its lock is poisoned.
+    fn try_set(&self, key: K, value: V) -> Result<Option<Arc<V>>, CacheError> {
+        let started = self.latency.start();
+        let result = self.insert(
+            key,
+            Arc::new(value),
+            None,
+            None,
+            None,
+            None,
+            EntrySource::Inserted,
+        );
+        self.latency.record_set(started);
+        result
+    }
+}
+
+impl<K: Eq + Hash + Clone + Sync + Send, V: Send + Sync> VersionedCache<K, V> for MRUCache<K, V> {
+    /// Get the current version of an item, without counting a hit or miss or updating its position.
+    fn version(&self, key: &K) -> Option<u64> {
+        let inner = self.inner.read().unwrap();
+        if inner.core.expiries.is_expired(key, inner.core.clock.now()) {
+            return None;
+        }
+        inner.core.metadata.version(key)
+    }
+
+    /// Set a value only if the item is at `expected_version`, or is not in the cache if it is 0.
+    fn set_versioned(
+        &self,
+        key: K,
+        value: V,
+        expected_version: u64,
+    ) -> Result<Option<Arc<V>>, CacheError> {
+        let started = self.latency.start();
+        let result = self.insert(
+            key,
+            Arc::new(value),
+            None,
+            None,
+            None,
+            Some(expected_version),
+            EntrySource::Inserted,
+        );
+        self.latency.record_set(started);
+        result
+    }
+}
+
+impl<K: Eq + Hash + Clone + Sync + Send, V: Send + Sync> IntoIterator for MRUCache<K, V> {
+    type Item = (K, Arc<V>);
+    type IntoIter = std::vec::IntoIter<(K, Arc<V>)>;
+
+    /// Consume the handle, yielding the cache's unexpired items from least to most recently used. Other handles still hold the items.
+    fn into_iter(self) -> Self::IntoIter {
+        let inner = self.inner.read().unwrap();
+        let now = inner.core.clock.now();
+        let items: Vec<(K, Arc<V>)> = inner
+            .by_recency()
+            .into_iter()
+            .filter(|(key, _)| !inner.core.expiries.is_expired(key, now))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect();
+        items.into_iter()
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::thread;
+
+    #[test]
+    fn test_mru_cache_age_rank() {
+        let cache = MRUCache::new(3);
+        cache.set(1, 1);
+        cache.set(2, 2);
+        cache.set(3, 3);
+        cache.get(&1);
+        assert_eq!(cache.age_rank(&1), Some(0));
+        assert_eq!(cache.age_rank(&2), Some(2));
+        assert!(cache.last_accessed(&3).is_some());
+        assert_eq!(cache.stats().hits, 1);
+    }
 
     #[test]
     fn test_mru_cache() {
@@ -140,6 +835,44 @@ mod tests {
         assert_eq!(cache.get(&4).map(|v| *v), Some(4));
     }
 
+    #[test]
+    fn test_mru_cache_priorities() {
+        let cache = MRUCache::new(2);
+        cache.set(1, 1);
+        cache.set_with_priority(2, 2, 10);
+        cache.set(3, 3);
+        assert_eq!(cache.get(&1), None);
+        assert_eq!(cache.get(&2).map(|v| *v), Some(2));
+        assert_eq!(cache.get(&3).map(|v| *v), Some(3));
+        cache.set(2, 20);
+        cache.set(4, 4);
+        assert_eq!(cache.get(&2).map(|v| *v), Some(20));
+
+        // every item has a priority, the most recently used at the lowest goes first
+        let cache = MRUCache::new(3);
+        cache.set_with_priority(1, 1, 5);
+        cache.set_with_priority(2, 2, 5);
+        cache.set_with_priority(3, 3, 9);
+        cache.get(&1);
+        cache.get(&3);
+        cache.set_with_priority(4, 4, 9);
+        assert_eq!(cache.get(&1), None);
+        assert!(cache.contains_key(&2));
+        assert!(cache.contains_key(&3));
+    }
+
+    #[test]
+    fn test_mru_cache_ttl() {
+        let cache = MRUCache::new(2).with_ttl(Duration::ZERO);
+        cache.set(1, 1);
+        assert_eq!(cache.get(&1), None);
+        cache.set_with_ttl(2, 2, Duration::from_secs(60));
+        cache.set_with_ttl(3, 3, Duration::from_secs(60));
+        assert!(cache.contains_key(&2));
+        assert_eq!(cache.get(&3).map(|v| *v), Some(3));
+        assert_eq!(cache.stats().size, 2);
+    }
+
     #[test]
     fn test_mru_cache_change_capacity() {
         let cache = MRUCache::new(2);
@@ -150,6 +883,42 @@ mod tests {
         assert_eq!(cache.get(&2).map(|v| *v), None);
     }
 
+    #[test]
+    fn test_mru_cache_evicts_by_recency_after_remove() {
+        let cache = MRUCache::new(4);
+        for key in 1..=4 {
+            cache.set(key, key);
+        }
+        cache.get(&1);
+        cache.get(&2);
+        cache.remove(&2);
+        assert_eq!(cache.age_rank(&1), Some(0));
+        assert_eq!(cache.change_capacity_collect(2).len(), 1);
+        assert!(!cache.contains_key(&1));
+        cache.get(&3);
+        let evicted: Vec<i32> = cache
+            .change_capacity_collect(0)
+            .into_iter()
+            .map(|(k, _)| k)
+            .collect();
+        assert_eq!(evicted, vec![3, 4]);
+    }
+
+    #[test]
+    fn test_mru_cache_change_capacity_collect() {
+        let cache = MRUCache::new(2);
+        cache.set(1, 1);
+        cache.set(2, 2);
+        cache.get(&1);
+        let evicted: Vec<(i32, i32)> = cache
+            .change_capacity_collect(1)
+            .into_iter()
+            .map(|(k, v)| (k, *v))
+            .collect();
+        assert_eq!(evicted, vec![(1, 1)]);
+        assert_eq!(cache.stats().size, 1);
+    }
+
     #[test]
     fn test_mru_cache_clear() {
         let cache = MRUCache::new(2);
@@ -159,4 +928,78 @@ mod tests {
         assert_eq!(cache.get(&1).map(|v| *v), None);
         assert_eq!(cache.get(&2).map(|v| *v), None);
     }
+
+    #[test]
+    fn test_mru_cache_gets_share_the_lock() {
+        let cache = MRUCache::new(3);
+        cache.set(1, 1);
+        cache.set(2, 2);
+        cache.set(3, 3);
+        // a get only needs the shared lock, so it is served while another reader holds it
+        let reader = cache.inner.read().unwrap();
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let handle = cache.clone();
+        thread::spawn(move || sender.send(handle.get(&1).map(|v| *v)).unwrap());
+        assert_eq!(
+            receiver.recv_timeout(Duration::from_secs(5)).unwrap(),
+            Some(1)
+        );
+        drop(reader);
+
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let cache = cache.clone();
+                thread::spawn(move || {
+                    for _ in 0..1000 {
+                        cache.get(&2);
+                        cache.get(&4);
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        let stats = cache.stats();
+        assert_eq!((stats.hits, stats.misses), (4001, 4000));
+        cache.set(4, 4);
+        assert!(!cache.contains_key(&2));
+    }
+
+    #[test]
+    fn test_mru_cache_evicts_by_recency_after_many_removes() {
+        let cache = MRUCache::new(100);
+        for key in 1..=100 {
+            cache.set(key, key);
+        }
+        cache.get(&10);
+        cache.get(&20);
+        cache.remove(&20);
+        cache.change_capacity(cache.stats().size - 1);
+        assert!(!cache.contains_key(&10));
+        // removing more items than the recent uses remember falls back to the ticks
+        for key in 40..=100 {
+            cache.remove(&key);
+        }
+        cache.change_capacity(cache.stats().size - 1);
+        assert!(!cache.contains_key(&39));
+        assert!(cache.contains_key(&38));
+    }
+
+    #[test]
+    fn test_mru_cache_idle_tracking() {
+        let untracked = MRUCache::new(2);
+        let tracked = MRUCache::new(2).with_idle_tracking();
+        untracked.set(1, 1);
+        tracked.set(1, 1);
+        let untracked_set_at = untracked.last_accessed(&1);
+        let tracked_set_at = tracked.last_accessed(&1);
+        thread::sleep(Duration::from_millis(5));
+        untracked.get(&1);
+        tracked.get(&1);
+        // by default only sets are timestamped
+        assert_eq!(untracked.last_accessed(&1), untracked_set_at);
+        assert!(tracked.last_accessed(&1) > tracked_set_at);
+        assert_eq!(tracked.stats().hits, 1);
+    }
 }
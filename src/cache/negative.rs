@@ -0,0 +1,263 @@
+use crate::sync::Mutex;
+use std::hash::Hash;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use linked_hash_map::LinkedHashMap;
+use rand::Rng;
+
+use crate::clock::{saturating_add, Clock, SystemClock};
+
+/// A key which recently failed, with how many times in a row it has failed.
+struct Failure {
+    failures: u32,
+    until: Instant,
+    // the backoff is forgotten once the key has not failed for this long
+    forget_at: Instant,
+}
+
+/// NegativeCache remembers keys which recently failed to load or were found to be absent, so callers can skip asking the origin again for a while.
+///
+/// Only keys are stored. Each failure marks a key as negative for a TTL which grows by `multiplier`, 2 by default, with every failure in a row, from `ttl` up to `max_ttl`, so a key which keeps failing is asked for less and less often. With `with_jitter` each TTL is shortened by a random fraction, so keys which failed together are not all retried at the same moment. A key's failure count is forgotten once it has not failed for twice its current TTL, or when it is removed, for example after a successful load. At most `capacity` keys are kept, the keys which failed longest ago are dropped first.
+///
+/// Example:
+/// ```
+/// use arcache::NegativeCache;
+/// use std::time::Duration;
+///
+/// let negative = NegativeCache::new(Duration::from_secs(1), 1000)
+///     .with_max_ttl(Duration::from_secs(60));
+///
+/// assert_eq!(negative.insert("missing"), Duration::from_secs(1));
+/// assert_eq!(negative.insert("missing"), Duration::from_secs(2));
+/// assert!(negative.contains(&"missing"));
+///
+/// negative.remove(&"missing");
+/// assert!(!negative.contains(&"missing"));
+/// ```
+pub struct NegativeCache<K> {
+    inner: Mutex<LinkedHashMap<K, Failure>>,
+    ttl: Duration,
+    max_ttl: Duration,
+    multiplier: f64,
+    jitter: f64,
+    capacity: usize,
+    clock: Arc<dyn Clock>,
+}
+
+impl<K: Eq + Hash + Clone> NegativeCache<K> {
+    /// Create a new NegativeCache marking failed keys for `ttl`, holding at most `capacity` keys.
+    pub fn new(ttl: Duration, capacity: usize) -> Self {
+        NegativeCache {
+            inner: Mutex::new(LinkedHashMap::new()),
+            ttl,
+            max_ttl: ttl,
+            multiplier: 2.0,
+            jitter: 0.0,
+            capacity,
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// Grow the TTL of a key with each failure in a row, up to `max_ttl`. By default the TTL does not grow.
+    pub fn with_max_ttl(mut self, max_ttl: Duration) -> Self {
+        self.max_ttl = max_ttl.max(self.ttl);
+        self
+    }
+
+    /// Set how much the TTL grows with each failure in a row, 2 by default. Multipliers below 1 are raised to 1.
+    pub fn with_multiplier(mut self, multiplier: f64) -> Self {
+        self.multiplier = multiplier.max(1.0);
+        self
+    }
+
+    /// Shorten each TTL by a random fraction of up to `jitter`, which is clamped between 0 and 1.
+    pub fn with_jitter(mut self, jitter: f64) -> Self {
+        self.jitter = jitter.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Read the time from `clock` instead of `Instant::now`.
+    pub fn with_clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// Record a failure of `key`, returning how long it is now negative for.
+    pub fn insert(&self, key: K) -> Duration {
+        if self.capacity == 0 {
+            return Duration::ZERO;
+        }
+        let now = self.clock.now();
+        let mut inner = self.inner.lock().unwrap();
+        let failures = match inner.remove(&key) {
+            Some(failure) if failure.forget_at > now => failure.failures.saturating_add(1),
+            _ => 1,
+        };
+        let ttl = self.jittered(self.backoff(failures));
+        inner.insert(
+            key,
+            Failure {
+                failures,
+                until: saturating_add(now, ttl),
+                forget_at: saturating_add(now, ttl.saturating_mul(2)),
+            },
+        );
+        while inner.len() > self.capacity {
+            inner.pop_front();
+        }
+        ttl
+    }
+
+    /// Check if a key failed within its TTL.
+    pub fn contains(&self, key: &K) -> bool {
+        let now = self.clock.now();
+        let inner = self.inner.lock().unwrap();
+        inner.get(key).is_some_and(|failure| failure.until > now)
+    }
+
+    /// Get how long a key stays negative for, or None if it is not negative.
+    pub fn remaining(&self, key: &K) -> Option<Duration> {
+        let now = self.clock.now();
+        let inner = self.inner.lock().unwrap();
+        inner
+            .get(key)
+            .filter(|failure| failure.until > now)
+            .map(|failure| failure.until - now)
+    }
+
+    /// Get how many times in a row a key has failed, or None if its failures have been forgotten.
+    pub fn failures(&self, key: &K) -> Option<u32> {
+        let now = self.clock.now();
+        let inner = self.inner.lock().unwrap();
+        inner
+            .get(key)
+            .filter(|failure| failure.forget_at > now)
+            .map(|failure| failure.failures)
+    }
+
+    /// Forget a key and its failure count, returning whether it was negative.
+    pub fn remove(&self, key: &K) -> bool {
+        let now = self.clock.now();
+        let mut inner = self.inner.lock().unwrap();
+        inner.remove(key).is_some_and(|failure| failure.until > now)
+    }
+
+    /// Get the number of keys held, including keys whose TTL has passed but whose failure count is still remembered.
+    pub fn len(&self) -> usize {
+        self.inner.lock().unwrap().len()
+    }
+
+    /// Get the number of keys which are currently negative.
+    pub fn negative_len(&self) -> usize {
+        let now = self.clock.now();
+        let inner = self.inner.lock().unwrap();
+        inner.values().filter(|failure| failure.until > now).count()
+    }
+
+    /// Check if no keys are held.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Forget every key.
+    pub fn clear(&self) {
+        self.inner.lock().unwrap().clear();
+    }
+
+    /// Get the TTL of a key after `failures` failures in a row.
+    fn backoff(&self, failures: u32) -> Duration {
+        let factor = self.multiplier.powi(failures.saturating_sub(1) as i32);
+        Duration::try_from_secs_f64(self.ttl.as_secs_f64() * factor)
+            .unwrap_or(self.max_ttl)
+            .min(self.max_ttl)
+    }
+
+    /// Shorten a TTL by a random fraction of up to the jitter.
+    fn jittered(&self, ttl: Duration) -> Duration {
+        if self.jitter == 0.0 {
+            return ttl;
+        }
+        ttl.mul_f64(1.0 - rand::rng().random::<f64>() * self.jitter)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex as StdMutex;
+
+    struct ManualClock(StdMutex<Instant>);
+
+    impl Clock for Arc<ManualClock> {
+        fn now(&self) -> Instant {
+            *self.0.lock().unwrap()
+        }
+    }
+
+    #[test]
+    fn test_negative_cache_max_ttl() {
+        let negative = NegativeCache::new(Duration::MAX, 4);
+        assert_eq!(negative.insert(1), Duration::MAX);
+        assert!(negative.contains(&1));
+        assert_eq!(negative.insert(1), Duration::MAX);
+        assert_eq!(negative.failures(&1), Some(2));
+    }
+
+    #[test]
+    fn test_negative_cache_backoff() {
+        let clock = Arc::new(ManualClock(StdMutex::new(Instant::now())));
+        let advance = |by: Duration| *clock.0.lock().unwrap() += by;
+        let negative = NegativeCache::new(Duration::from_secs(1), 10)
+            .with_max_ttl(Duration::from_secs(3))
+            .with_clock(clock.clone());
+        assert_eq!(negative.insert(1), Duration::from_secs(1));
+        assert_eq!(negative.insert(1), Duration::from_secs(2));
+        assert_eq!(negative.insert(1), Duration::from_secs(3));
+        assert_eq!(negative.remaining(&1), Some(Duration::from_secs(3)));
+        advance(Duration::from_secs(4));
+        assert!(!negative.contains(&1));
+        // still within twice the TTL, so the next failure keeps backing off
+        assert_eq!(negative.insert(1), Duration::from_secs(3));
+        advance(Duration::from_secs(7));
+        assert_eq!(negative.insert(1), Duration::from_secs(1));
+        assert!(negative.remove(&1));
+        assert_eq!(negative.insert(1), Duration::from_secs(1));
+    }
+
+    #[test]
+    fn test_negative_cache_multiplier_and_jitter() {
+        let negative = NegativeCache::new(Duration::from_secs(1), 10)
+            .with_max_ttl(Duration::from_secs(100))
+            .with_multiplier(3.0);
+        assert_eq!(negative.insert(1), Duration::from_secs(1));
+        assert_eq!(negative.insert(1), Duration::from_secs(3));
+        assert_eq!(negative.insert(1), Duration::from_secs(9));
+        assert_eq!(negative.failures(&1), Some(3));
+        assert_eq!(negative.failures(&2), None);
+
+        let jittered = NegativeCache::new(Duration::from_secs(10), 100).with_jitter(0.5);
+        for key in 0..100 {
+            let ttl = jittered.insert(key);
+            assert!(ttl >= Duration::from_secs(5) && ttl <= Duration::from_secs(10));
+        }
+        assert_eq!(jittered.negative_len(), 100);
+    }
+
+    #[test]
+    fn test_negative_cache_capacity() {
+        let negative = NegativeCache::new(Duration::from_secs(60), 2);
+        negative.insert(1);
+        negative.insert(2);
+        negative.insert(1);
+        negative.insert(3);
+        assert_eq!(negative.len(), 2);
+        assert!(negative.contains(&1));
+        assert!(!negative.contains(&2));
+        assert!(negative.contains(&3));
+        assert_eq!(
+            NegativeCache::new(Duration::from_secs(60), 0).insert(1),
+            Duration::ZERO
+        );
+    }
+}
@@ -0,0 +1,492 @@
+use crate::sync::Mutex;
+use std::any::Any;
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
+use std::hash::Hash;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::cache::listener::EntrySource;
+use crate::cache::{Cache, CacheStats, DetailedStats, IterationOrder};
+
+/// How many groups have their hits and misses counted unless `with_group_stats_limit` is used.
+const DEFAULT_GROUP_STATS_LIMIT: usize = 1024;
+
+/// The secondary keys set under each primary key.
+struct Groups<K1, K2> {
+    index: HashMap<K1, HashSet<K2>>,
+    indexed: usize,
+}
+
+impl<K1, K2> Groups<K1, K2>
+where
+    K1: Eq + Hash + Clone,
+    K2: Eq + Hash + Clone,
+{
+    /// Forget every secondary key for which `live` returns false.
+    fn retain(&mut self, mut live: impl FnMut(&K1, &K2) -> bool) {
+        self.index.retain(|primary, secondaries| {
+            secondaries.retain(|secondary| live(primary, secondary));
+            !secondaries.is_empty()
+        });
+        self.indexed = self.index.values().map(HashSet::len).sum();
+    }
+
+    /// Forget a single key.
+    fn forget(&mut self, primary: &K1, secondary: &K2) {
+        if let Some(secondaries) = self.index.get_mut(primary) {
+            if secondaries.remove(secondary) {
+                self.indexed -= 1;
+            }
+            if secondaries.is_empty() {
+                self.index.remove(primary);
+            }
+        }
+    }
+
+    /// Forget every key.
+    fn clear(&mut self) {
+        self.index.clear();
+        self.indexed = 0;
+    }
+}
+
+/// GroupStats contains the statistics of the items under one primary key of a NestedCache.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct GroupStats<K1> {
+    /// The primary key of the group.
+    pub group: K1,
+    /// Gets in the group which found a live item.
+    pub hits: u64,
+    /// Gets in the group which found no item.
+    pub misses: u64,
+    /// The number of items in the group.
+    pub size: u64,
+}
+
+/// The hits and misses counted for a group.
+#[derive(Default, Clone, Copy)]
+struct GroupCounts {
+    hits: u64,
+    misses: u64,
+}
+
+/// Keep the counts of the `limit` groups with the most misses, forgetting the rest.
+fn keep_most_misses<K1>(counts: &mut HashMap<K1, GroupCounts>, limit: usize) {
+    if counts.len() <= limit {
+        return;
+    }
+    let mut misses: Vec<u64> = counts.values().map(|counts| counts.misses).collect();
+    let (_, &mut threshold, _) =
+        misses.select_nth_unstable_by_key(limit, |misses| Reverse(*misses));
+    // groups tied at the threshold are kept until the limit is reached
+    let mut tied = limit - misses[..limit].iter().filter(|&&m| m > threshold).count();
+    counts.retain(|_, counts| {
+        if counts.misses > threshold {
+            true
+        } else if counts.misses == threshold && tied > 0 {
+            tied -= 1;
+            true
+        } else {
+            false
+        }
+    });
+}
+
+/// NestedCache wraps a cache keyed by `(primary, secondary)` pairs and keeps an index of the secondary keys set under each primary key, so a whole group can be invalidated at once.
+///
+/// This suits caches of per-user or per-tenant results, where everything cached for one user must go when their data changes. Hits and misses are also counted per group, so `top_groups_by_misses` can show which users or endpoints are thrashing the cache, the counts are kept for up to 1024 groups, or the limit given to `with_group_stats_limit`, and once twice that many groups have been read only the groups with the most misses are kept. Items the wrapped cache evicts or expires stay in the index until it grows past twice the capacity, then it is pruned on the next set. Sets and invalidations hold the index lock around the wrapped cache's lock, so no set is missed by a concurrent invalidation of its group.
+///
+/// Example:
+/// ```
+/// use arcache::{Cache, LRUCache, NestedCache};
+///
+/// let cache = NestedCache::new(LRUCache::<(u64, &str), String>::new(100));
+///
+/// cache.set((1, "profile"), "alice".to_string());
+/// cache.set((1, "settings"), "dark mode".to_string());
+/// cache.set((2, "profile"), "bob".to_string());
+///
+/// // user 1 changed, drop everything cached for them
+/// assert_eq!(cache.invalidate_group(&1).len(), 2);
+/// assert!(cache.get(&(1, "profile")).is_none());
+/// assert!(cache.get(&(2, "profile")).is_some());
+/// ```
+pub struct NestedCache<C, K1, K2> {
+    cache: C,
+    groups: Mutex<Groups<K1, K2>>,
+    counts: Mutex<HashMap<K1, GroupCounts>>,
+    group_stats_limit: usize,
+}
+
+impl<C, K1, K2> NestedCache<C, K1, K2>
+where
+    K1: Eq + Hash + Clone + Send + Sync,
+    K2: Eq + Hash + Clone + Send + Sync,
+{
+    /// Create a new NestedCache wrapping the given cache.
+    pub fn new(cache: C) -> Self {
+        NestedCache {
+            cache,
+            groups: Mutex::new(Groups {
+                index: HashMap::new(),
+                indexed: 0,
+            }),
+            counts: Mutex::new(HashMap::new()),
+            group_stats_limit: DEFAULT_GROUP_STATS_LIMIT,
+        }
+    }
+
+    /// Count hits and misses for at most `limit` groups, 1024 by default, keeping the groups with the most misses.
+    pub fn with_group_stats_limit(mut self, limit: usize) -> Self {
+        self.group_stats_limit = limit;
+        self
+    }
+
+    /// Get a reference to the wrapped cache.
+    pub fn inner(&self) -> &C {
+        &self.cache
+    }
+
+    /// Remove every item under the primary key, returning the removed items with their secondary keys.
+    pub fn invalidate_group<V>(&self, primary: &K1) -> Vec<(K2, Arc<V>)>
+    where
+        V: Send + Sync,
+        C: Cache<(K1, K2), V>,
+    {
+        let mut groups = self.groups.lock().unwrap();
+        let Some(secondaries) = groups.index.remove(primary) else {
+            return Vec::new();
+        };
+        groups.indexed -= secondaries.len();
+        secondaries
+            .into_iter()
+            .filter_map(|secondary| {
+                let value = self.cache.remove(&(primary.clone(), secondary.clone()))?;
+                Some((secondary, value))
+            })
+            .collect()
+    }
+
+    /// Get the secondary keys of the items under the primary key.
+    pub fn group_keys<V>(&self, primary: &K1) -> Vec<K2>
+    where
+        V: Send + Sync,
+        C: Cache<(K1, K2), V>,
+    {
+        let groups = self.groups.lock().unwrap();
+        self.live_keys(&groups, primary).cloned().collect()
+    }
+
+    /// Get the hits, misses and size of the group under the primary key.
+    pub fn group_stats<V>(&self, primary: &K1) -> GroupStats<K1>
+    where
+        V: Send + Sync,
+        C: Cache<(K1, K2), V>,
+    {
+        let counts = self
+            .counts
+            .lock()
+            .unwrap()
+            .get(primary)
+            .copied()
+            .unwrap_or_default();
+        let groups = self.groups.lock().unwrap();
+        GroupStats {
+            group: primary.clone(),
+            hits: counts.hits,
+            misses: counts.misses,
+            size: self.live_keys(&groups, primary).count() as u64,
+        }
+    }
+
+    /// Get the statistics of the `limit` groups with the most misses, most first.
+    pub fn top_groups_by_misses<V>(&self, limit: usize) -> Vec<GroupStats<K1>>
+    where
+        V: Send + Sync,
+        C: Cache<(K1, K2), V>,
+    {
+        let mut counts: Vec<(K1, GroupCounts)> = self
+            .counts
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(primary, counts)| (primary.clone(), *counts))
+            .collect();
+        counts.sort_by_key(|(_, counts)| Reverse(counts.misses));
+        counts.truncate(limit);
+        let groups = self.groups.lock().unwrap();
+        counts
+            .into_iter()
+            .map(|(primary, counts)| GroupStats {
+                size: self.live_keys(&groups, &primary).count() as u64,
+                group: primary,
+                hits: counts.hits,
+                misses: counts.misses,
+            })
+            .collect()
+    }
+
+    /// Forget the hits and misses counted for every group.
+    pub fn reset_group_stats(&self) {
+        self.counts.lock().unwrap().clear();
+    }
+
+    /// Iterate the indexed secondary keys under the primary key which the wrapped cache still holds.
+    fn live_keys<'a, V>(
+        &'a self,
+        groups: &'a Groups<K1, K2>,
+        primary: &'a K1,
+    ) -> impl Iterator<Item = &'a K2> + 'a
+    where
+        V: Send + Sync,
+        C: Cache<(K1, K2), V>,
+    {
+        groups
+            .index
+            .get(primary)
+            .into_iter()
+            .flatten()
+            .filter(move |secondary| {
+                self.cache
+                    .contains_key(&(primary.clone(), (*secondary).clone()))
+            })
+    }
+}
+
+impl<K1, K2, V, C> Cache<(K1, K2), V> for NestedCache<C, K1, K2>
+where
+    K1: Eq + Hash + Clone + Send + Sync,
+    K2: Eq + Hash + Clone + Send + Sync,
+    V: Send + Sync,
+    C: Cache<(K1, K2), V>,
+{
+    /// Get a value from the cache, counting the hit or miss against its group.
+    fn get(&self, key: &(K1, K2)) -> Option<Arc<V>> {
+        let value = self.cache.get(key);
+        let mut counts = self.counts.lock().unwrap();
+        let group = counts.entry(key.0.clone()).or_default();
+        match value {
+            Some(_) => group.hits += 1,
+            None => group.misses += 1,
+        }
+        if counts.len() > 2 * self.group_stats_limit {
+            keep_most_misses(&mut counts, self.group_stats_limit);
+        }
+        value
+    }
+
+    /// Set a value in the cache and index it under its primary key, pruning keys the wrapped cache no longer holds once the index is twice the capacity.
+    fn set(&self, key: (K1, K2), value: V) -> Option<Arc<V>> {
+        self.set_with_source(key, value, EntrySource::Inserted)
+    }
+
+    /// Set a value like `set`, passing on how it came to be in the cache to the wrapped cache.
+    fn set_with_source(&self, key: (K1, K2), value: V, source: EntrySource) -> Option<Arc<V>> {
+        let mut groups = self.groups.lock().unwrap();
+        if groups.indexed >= 2 * self.cache.stats().capacity.max(1) as usize {
+            // forget keys which the wrapped cache has already evicted or expired
+            groups.retain(|primary, secondary| {
+                self.cache
+                    .contains_key(&(primary.clone(), secondary.clone()))
+            });
+        }
+        let (primary, secondary) = &key;
+        if groups
+            .index
+            .entry(primary.clone())
+            .or_default()
+            .insert(secondary.clone())
+        {
+            groups.indexed += 1;
+        }
+        self.cache.set_with_source(key, value, source)
+    }
+
+    /// Remove a value from the cache.
+    fn remove(&self, key: &(K1, K2)) -> Option<Arc<V>> {
+        let mut groups = self.groups.lock().unwrap();
+        groups.forget(&key.0, &key.1);
+        self.cache.remove(key)
+    }
+
+    /// Check if a key is in the cache.
+    fn contains_key(&self, key: &(K1, K2)) -> bool {
+        self.cache.contains_key(key)
+    }
+
+    /// Get the time left before an item expires, or None if it never expires or is not in the cache.
+    fn ttl_remaining(&self, key: &(K1, K2)) -> Option<Duration> {
+        self.cache.ttl_remaining(key)
+    }
+
+    /// Clear the cache and the index.
+    fn clear(&self) {
+        let mut groups = self.groups.lock().unwrap();
+        groups.clear();
+        self.cache.clear();
+    }
+
+    /// Clear the cache and the index, returning every item which was removed.
+    fn clear_returning(&self) -> Vec<((K1, K2), Arc<V>)> {
+        let mut groups = self.groups.lock().unwrap();
+        groups.clear();
+        self.cache.clear_returning()
+    }
+
+    /// Copy the items of the wrapped cache into a HashMap.
+    fn to_map(&self) -> HashMap<(K1, K2), Arc<V>> {
+        self.cache.to_map()
+    }
+
+    /// Get the hottest items of the wrapped cache.
+    fn hottest(&self, limit: usize) -> Vec<((K1, K2), Arc<V>)> {
+        self.cache.hottest(limit)
+    }
+
+    /// Get the items of the wrapped cache in the given order.
+    fn items(&self, order: IterationOrder) -> Option<Vec<((K1, K2), Arc<V>)>> {
+        self.cache.items(order)
+    }
+
+    /// Get the name of the wrapped cache's eviction policy.
+    fn policy_name(&self) -> &'static str {
+        self.cache.policy_name()
+    }
+
+    /// Get the cache as `Any`, so it can be downcast from a `dyn Cache`.
+    fn as_any(&self) -> Option<&dyn Any>
+    where
+        Self: 'static,
+    {
+        Some(self)
+    }
+
+    /// Get the statistics of the wrapped cache.
+    fn stats(&self) -> CacheStats {
+        self.cache.stats()
+    }
+
+    /// Get the detailed statistics of the wrapped cache.
+    fn detailed_stats(&self) -> DetailedStats {
+        self.cache.detailed_stats()
+    }
+
+    /// Change the capacity of the wrapped cache.
+    fn change_capacity(&self, capacity: u64) {
+        self.cache.change_capacity(capacity);
+    }
+
+    /// Change the capacity of the wrapped cache, returning the items which were removed.
+    fn change_capacity_collect(&self, capacity: u64) -> Vec<((K1, K2), Arc<V>)> {
+        self.cache.change_capacity_collect(capacity)
+    }
+
+    /// Shed `fraction` of the items of the wrapped cache.
+    fn shed(&self, fraction: f64) -> usize {
+        self.cache.shed(fraction)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::LRUCache;
+
+    #[test]
+    fn test_nested_cache_invalidate_group() {
+        let cache = NestedCache::new(LRUCache::new(10));
+        for user in 0..3 {
+            for resource in 0..3 {
+                cache.set((user, resource), user * 10 + resource);
+            }
+        }
+        cache.remove(&(1, 0));
+        let mut keys = cache.group_keys(&1);
+        keys.sort();
+        assert_eq!(keys, vec![1, 2]);
+        let mut removed: Vec<_> = cache
+            .invalidate_group(&1)
+            .into_iter()
+            .map(|(resource, value)| (resource, *value))
+            .collect();
+        removed.sort();
+        assert_eq!(removed, vec![(1, 11), (2, 12)]);
+        assert!(cache.group_keys(&1).is_empty());
+        assert_eq!(cache.stats().size, 6);
+        assert!(cache.invalidate_group(&1).is_empty());
+    }
+
+    #[test]
+    fn test_nested_cache_group_stats() {
+        let cache = NestedCache::new(LRUCache::new(10));
+        cache.set((1, 0), 0);
+        cache.set((2, 0), 0);
+        cache.set((2, 1), 1);
+        cache.get(&(1, 0));
+        for resource in 0..5 {
+            cache.get(&(2, resource));
+        }
+        for resource in 5..7 {
+            cache.get(&(3, resource));
+        }
+        let top = cache.top_groups_by_misses(2);
+        assert_eq!(
+            top.iter()
+                .map(|stats| (stats.group, stats.hits, stats.misses, stats.size))
+                .collect::<Vec<_>>(),
+            vec![(2, 2, 3, 2), (3, 0, 2, 0)]
+        );
+        let stats = cache.group_stats(&1);
+        assert_eq!((stats.hits, stats.misses, stats.size), (1, 0, 1));
+        cache.reset_group_stats();
+        assert!(cache.top_groups_by_misses(10).is_empty());
+    }
+
+    #[test]
+    fn test_nested_cache_group_stats_limit() {
+        let cache =
+            NestedCache::new(LRUCache::<(u64, u64), u64>::new(10)).with_group_stats_limit(2);
+        for _ in 0..3 {
+            cache.get(&(1, 0));
+        }
+        cache.get(&(2, 0));
+        cache.get(&(2, 0));
+        for group in 3..100 {
+            cache.get(&(group, 0));
+        }
+        // the groups with the most misses survive every pruning
+        assert!(cache.counts.lock().unwrap().len() <= 4);
+        let top = cache.top_groups_by_misses(2);
+        assert_eq!(
+            top.iter()
+                .map(|stats| (stats.group, stats.misses))
+                .collect::<Vec<_>>(),
+            vec![(1, 3), (2, 2)]
+        );
+
+        let mut counts: HashMap<u64, GroupCounts> = (0..10)
+            .map(|group| {
+                let misses = if group < 2 { 5 } else { 1 };
+                (group, GroupCounts { hits: 0, misses })
+            })
+            .collect();
+        keep_most_misses(&mut counts, 4);
+        assert_eq!(counts.len(), 4);
+        assert!(counts.contains_key(&0) && counts.contains_key(&1));
+    }
+
+    #[test]
+    fn test_nested_cache_prunes_evicted_keys() {
+        let cache = NestedCache::new(LRUCache::new(2));
+        for resource in 0..100 {
+            cache.set((0, resource), resource);
+        }
+        // the index never holds much more than twice the capacity
+        assert!(cache.groups.lock().unwrap().indexed <= 4);
+        let mut keys = cache.group_keys(&0);
+        keys.sort();
+        assert_eq!(keys, vec![98, 99]);
+    }
+}
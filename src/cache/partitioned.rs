@@ -0,0 +1,424 @@
+use crate::sync::atomic::{AtomicU64, Ordering};
+use crate::sync::RwLock;
+use std::any::Any;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::marker::PhantomData;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::cache::listener::EntrySource;
+use crate::cache::{Cache, CacheStats, GetOutcome};
+
+// partitioner type, maps each key to the partition it belongs to
+type Partitioner<K, P> = Box<dyn Fn(&K) -> P + Send + Sync>;
+// factory type, builds the cache for a new partition with the given capacity
+type PartitionFactory<C> = Box<dyn Fn(u64) -> C + Send + Sync>;
+
+/// The inner data structure for the PartitionedCache.
+struct PartitionedCacheInner<P, C> {
+    budget: u64,
+    partitions: HashMap<P, Arc<C>>,
+}
+
+/// PartitionedCache gives each partition, such as a tenant, its own cache and shares a total capacity budget between them.
+///
+/// Partitions are created lazily with the factory the first time one of their keys is set, and the budget is then split evenly between every partition. Call `rebalance` to instead split it in proportion to how much each partition is used. `change_capacity` keeps each partition's share of the budget, shrinking partitions before growing others so the items held never exceed the old or the new budget. Every partition gets at least one slot, so the capacities can exceed a budget smaller than the number of partitions.
+///
+/// `stats` aggregates every partition, `partition_stats` gives the statistics of a single one. Lookups in partitions which do not exist yet are counted as misses.
+///
+/// Example:
+/// ```
+/// use arcache::{Cache, LRUCache, PartitionedCache};
+///
+/// // keys are (tenant, key) pairs, each tenant gets its own LRU cache
+/// let cache = PartitionedCache::new(
+///     10,
+///     |key: &(&str, u64)| key.0.to_string(),
+///     |capacity| LRUCache::new(capacity),
+/// );
+///
+/// cache.set(("a", 1), "value".to_string());
+/// cache.set(("b", 1), "value".to_string());
+///
+/// assert_eq!(cache.partition_stats(&"a".to_string()).unwrap().capacity, 5);
+/// assert_eq!(cache.stats().size, 2);
+/// ```
+pub struct PartitionedCache<C, K, V, P> {
+    inner: RwLock<PartitionedCacheInner<P, C>>,
+    partitioner: Partitioner<K, P>,
+    factory: PartitionFactory<C>,
+    unknown_misses: AtomicU64,
+    _marker: PhantomData<fn() -> V>,
+}
+
+impl<C, K, V, P> PartitionedCache<C, K, V, P> {
+    /// Create a new PartitionedCache sharing `budget` between its partitions, `partitioner` gives the partition each key belongs to and `factory` builds a partition's cache from its capacity.
+    pub fn new(
+        budget: u64,
+        partitioner: impl Fn(&K) -> P + Send + Sync + 'static,
+        factory: impl Fn(u64) -> C + Send + Sync + 'static,
+    ) -> Self {
+        PartitionedCache {
+            inner: RwLock::new(PartitionedCacheInner {
+                budget,
+                partitions: HashMap::new(),
+            }),
+            partitioner: Box::new(partitioner),
+            factory: Box::new(factory),
+            unknown_misses: AtomicU64::new(0),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<C, K, V, P: Clone> PartitionedCache<C, K, V, P> {
+    /// Get the partitions which have been created, in no particular order.
+    pub fn partitions(&self) -> Vec<P> {
+        let inner = self.inner.read().unwrap();
+        inner.partitions.keys().cloned().collect()
+    }
+}
+
+impl<K, V, C, P> PartitionedCache<C, K, V, P>
+where
+    K: Eq + Hash + Clone + Send + Sync,
+    V: Send + Sync,
+    C: Cache<K, V>,
+    P: Eq + Hash,
+{
+    /// Get the statistics of a single partition, or None if it has not been created.
+    pub fn partition_stats(&self, partition: &P) -> Option<CacheStats> {
+        let inner = self.inner.read().unwrap();
+        inner.partitions.get(partition).map(|cache| cache.stats())
+    }
+
+    /// Split the budget between the partitions in proportion to their gets, so busy partitions get more of the capacity.
+    ///
+    /// Gets are counted since each partition was created, every partition is weighted as if it had one more get so idle partitions keep some capacity.
+    pub fn rebalance(&self) {
+        let inner = self.inner.read().unwrap();
+        let weights: Vec<u64> = inner
+            .partitions
+            .values()
+            .map(|cache| {
+                let stats = cache.stats();
+                stats.hits + stats.misses + 1
+            })
+            .collect();
+        Self::distribute(&inner, &weights, false);
+    }
+
+    /// Resize every partition to its share of the budget, returning the evicted items if `collect` is set. Weights are given in the partitions' iteration order.
+    ///
+    /// Partitions which shrink are resized first, so the partitions never hold more items than the budget before or after.
+    fn distribute(
+        inner: &PartitionedCacheInner<P, C>,
+        weights: &[u64],
+        collect: bool,
+    ) -> Vec<(K, Arc<V>)> {
+        let caches: Vec<&Arc<C>> = inner.partitions.values().collect();
+        let shares = shares(inner.budget, weights);
+        let mut order: Vec<usize> = (0..caches.len()).collect();
+        order.sort_by_key(|&i| shares[i] >= caches[i].stats().capacity);
+        let mut evicted = Vec::new();
+        for i in order {
+            if collect {
+                evicted.extend(caches[i].change_capacity_collect(shares[i]));
+            } else {
+                caches[i].change_capacity(shares[i]);
+            }
+        }
+        evicted
+    }
+
+    /// Change the budget, keeping each partition's share of it, and return the evicted items if `collect` is set.
+    fn resize(&self, capacity: u64, collect: bool) -> Vec<(K, Arc<V>)> {
+        let mut inner = self.inner.write().unwrap();
+        inner.budget = capacity;
+        let weights: Vec<u64> = inner
+            .partitions
+            .values()
+            .map(|cache| cache.stats().capacity)
+            .collect();
+        Self::distribute(&inner, &weights, collect)
+    }
+
+    /// Get the partition a key belongs to if it has been created.
+    fn partition(&self, key: &K) -> Option<Arc<C>> {
+        let partition = (self.partitioner)(key);
+        let inner = self.inner.read().unwrap();
+        inner.partitions.get(&partition).cloned()
+    }
+
+    /// Get the partition a key belongs to, creating it and splitting the budget evenly again if it does not exist.
+    fn partition_or_create(&self, key: &K) -> Arc<C> {
+        let partition = (self.partitioner)(key);
+        if let Some(cache) = self.inner.read().unwrap().partitions.get(&partition) {
+            return cache.clone();
+        }
+        let mut inner = self.inner.write().unwrap();
+        if let Some(cache) = inner.partitions.get(&partition) {
+            return cache.clone();
+        }
+        let cache = Arc::new((self.factory)(1));
+        inner.partitions.insert(partition, cache.clone());
+        Self::distribute(&inner, &vec![1; inner.partitions.len()], false);
+        cache
+    }
+
+    /// Get a snapshot of every partition's cache so they can be used without holding the lock.
+    fn caches(&self) -> Vec<Arc<C>> {
+        let inner = self.inner.read().unwrap();
+        inner.partitions.values().cloned().collect()
+    }
+}
+
+impl<K, V, C, P> Cache<K, V> for PartitionedCache<C, K, V, P>
+where
+    K: Eq + Hash + Clone + Send + Sync,
+    V: Send + Sync,
+    C: Cache<K, V>,
+    P: Eq + Hash + Send + Sync,
+{
+    /// Get a value from the key's partition.
+    fn get(&self, key: &K) -> Option<Arc<V>> {
+        match self.partition(key) {
+            Some(cache) => cache.get(key),
+            None => {
+                self.unknown_misses.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+        }
+    }
+
+    /// Get a value from the key's partition, with whether it was a hit or why it was a miss.
+    fn get_with_outcome(&self, key: &K) -> (Option<Arc<V>>, GetOutcome) {
+        match self.partition(key) {
+            Some(cache) => cache.get_with_outcome(key),
+            None => {
+                self.unknown_misses.fetch_add(1, Ordering::Relaxed);
+                (None, GetOutcome::MissAbsent)
+            }
+        }
+    }
+
+    /// Set a value in the key's partition, creating the partition if needed.
+    fn set(&self, key: K, value: V) -> Option<Arc<V>> {
+        self.set_with_source(key, value, EntrySource::Inserted)
+    }
+
+    /// Set a value like `set`, passing on how it came to be in the cache to the wrapped cache.
+    fn set_with_source(&self, key: K, value: V, source: EntrySource) -> Option<Arc<V>> {
+        self.partition_or_create(&key)
+            .set_with_source(key, value, source)
+    }
+
+    /// Remove a value from the key's partition.
+    fn remove(&self, key: &K) -> Option<Arc<V>> {
+        self.partition(key)?.remove(key)
+    }
+
+    /// Check if a key is in its partition.
+    fn contains_key(&self, key: &K) -> bool {
+        self.partition(key)
+            .is_some_and(|cache| cache.contains_key(key))
+    }
+
+    /// Get the time left before an item expires, or None if it never expires or is not in the cache.
+    fn ttl_remaining(&self, key: &K) -> Option<Duration> {
+        self.partition(key)?.ttl_remaining(key)
+    }
+
+    /// Clear every partition, the partitions and their capacities are kept.
+    fn clear(&self) {
+        for cache in self.caches() {
+            cache.clear();
+        }
+    }
+
+    /// Clear every partition, returning every item which was removed.
+    fn clear_returning(&self) -> Vec<(K, Arc<V>)> {
+        self.caches()
+            .iter()
+            .flat_map(|cache| cache.clear_returning())
+            .collect()
+    }
+
+    /// Copy the items of every partition into a HashMap.
+    fn to_map(&self) -> HashMap<K, Arc<V>> {
+        self.caches()
+            .iter()
+            .flat_map(|cache| cache.to_map())
+            .collect()
+    }
+
+    /// Get up to `limit` of the hottest items, taking the hottest of each partition in turn.
+    fn hottest(&self, limit: usize) -> Vec<(K, Arc<V>)> {
+        let mut rankings: Vec<_> = self
+            .caches()
+            .iter()
+            .map(|cache| cache.hottest(limit).into_iter())
+            .collect();
+        let mut items = Vec::with_capacity(limit);
+        while items.len() < limit {
+            let before = items.len();
+            for ranking in rankings.iter_mut() {
+                if items.len() == limit {
+                    break;
+                }
+                items.extend(ranking.next());
+            }
+            if items.len() == before {
+                break;
+            }
+        }
+        items
+    }
+
+    /// Get the name of the eviction policy.
+    fn policy_name(&self) -> &'static str {
+        "partitioned"
+    }
+
+    /// Get the cache as `Any`, so it can be downcast from a `dyn Cache`.
+    fn as_any(&self) -> Option<&dyn Any>
+    where
+        Self: 'static,
+    {
+        Some(self)
+    }
+
+    /// Get the statistics of every partition added together, the capacity is the budget.
+    fn stats(&self) -> CacheStats {
+        let inner = self.inner.read().unwrap();
+        let mut stats = CacheStats::default();
+        for cache in inner.partitions.values() {
+            stats.accumulate(&cache.stats());
+        }
+        stats.misses += self.unknown_misses.load(Ordering::Relaxed);
+        stats.capacity = inner.budget;
+        stats
+    }
+
+    /// Change the budget, resizing every partition in proportion to its current capacity.
+    fn change_capacity(&self, capacity: u64) {
+        self.resize(capacity, false);
+    }
+
+    /// Change the budget, resizing every partition in proportion to its current capacity, and return the items which were removed.
+    fn change_capacity_collect(&self, capacity: u64) -> Vec<(K, Arc<V>)> {
+        self.resize(capacity, true)
+    }
+
+    /// Shed `fraction` of the items of every partition, each rounded up to whole items.
+    fn shed(&self, fraction: f64) -> usize {
+        self.caches().iter().map(|cache| cache.shed(fraction)).sum()
+    }
+}
+
+/// Split the budget in proportion to the weights, handing the slots lost to rounding to the largest remainders. Every share is at least one.
+pub(crate) fn shares(budget: u64, weights: &[u64]) -> Vec<u64> {
+    let total = weights
+        .iter()
+        .map(|&weight| weight as u128)
+        .sum::<u128>()
+        .max(1);
+    let exact: Vec<u128> = weights
+        .iter()
+        .map(|weight| budget as u128 * *weight as u128)
+        .collect();
+    let mut shares: Vec<u64> = exact.iter().map(|exact| (exact / total) as u64).collect();
+    let mut by_remainder: Vec<usize> = (0..weights.len()).collect();
+    by_remainder.sort_by_key(|&i| std::cmp::Reverse(exact[i] % total));
+    let leftover = budget.saturating_sub(shares.iter().sum()) as usize;
+    for &i in by_remainder.iter().take(leftover) {
+        shares[i] += 1;
+    }
+    shares.into_iter().map(|share| share.max(1)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::LRUCache;
+
+    // keys are (tenant, key) pairs partitioned by tenant
+    type TenantCache = PartitionedCache<LRUCache<(u64, u64), u64>, (u64, u64), u64, u64>;
+
+    fn tenant_cache(budget: u64) -> TenantCache {
+        PartitionedCache::new(budget, |key: &(u64, u64)| key.0, LRUCache::new)
+    }
+
+    #[test]
+    fn test_shares() {
+        assert_eq!(shares(10, &[1, 1, 1]).iter().sum::<u64>(), 10);
+        assert_eq!(shares(12, &[9, 2]), vec![10, 2]);
+        assert_eq!(shares(1, &[1, 1]), vec![1, 1]);
+    }
+
+    #[test]
+    fn test_partitioned_cache_isolates_partitions() {
+        let cache = tenant_cache(10);
+        cache.set((0, 0), 0);
+        for i in 0..100 {
+            cache.set((1, i), i);
+        }
+        assert!(cache.contains_key(&(0, 0)));
+        assert_eq!(cache.partition_stats(&0).unwrap().capacity, 5);
+        assert_eq!(cache.partition_stats(&1).unwrap().size, 5);
+        assert_eq!(cache.stats().size, 6);
+        assert_eq!(cache.stats().capacity, 10);
+    }
+
+    #[test]
+    fn test_partitioned_cache_stats() {
+        let cache = tenant_cache(10);
+        cache.set((0, 0), 0);
+        cache.get(&(0, 0));
+        cache.get(&(0, 1));
+        cache.get(&(1, 0));
+        let stats = cache.stats();
+        assert_eq!(stats.hits, 1);
+        assert_eq!(stats.misses, 2);
+        assert!(cache.partition_stats(&1).is_none());
+    }
+
+    #[test]
+    fn test_partitioned_cache_rebalance() {
+        let cache = tenant_cache(12);
+        cache.set((0, 0), 0);
+        cache.set((1, 0), 0);
+        for _ in 0..8 {
+            cache.get(&(0, 0));
+        }
+        cache.get(&(1, 0));
+        cache.rebalance();
+        assert_eq!(cache.partition_stats(&0).unwrap().capacity, 10);
+        assert_eq!(cache.partition_stats(&1).unwrap().capacity, 2);
+        cache.change_capacity(6);
+        assert_eq!(cache.partition_stats(&0).unwrap().capacity, 5);
+        assert_eq!(cache.partition_stats(&1).unwrap().capacity, 1);
+    }
+
+    #[test]
+    fn test_partitioned_cache_change_capacity_keeps_shares() {
+        let cache = tenant_cache(12);
+        for i in 0..8 {
+            cache.set((0, i), i);
+            cache.get(&(0, i));
+        }
+        cache.set((1, 0), 0);
+        cache.rebalance();
+        for i in 0..12 {
+            cache.set((0, i), i);
+        }
+        assert_eq!(cache.partition_stats(&0).unwrap().capacity, 11);
+        let evicted = cache.change_capacity_collect(8);
+        assert_eq!(evicted.len(), 4);
+        assert!(evicted.iter().all(|((tenant, _), _)| *tenant == 0));
+        assert_eq!(cache.partition_stats(&0).unwrap().capacity, 7);
+        assert_eq!(cache.stats().size, 8);
+        assert!(cache.contains_key(&(1, 0)));
+    }
+}
@@ -0,0 +1,277 @@
+use std::hash::Hash;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::cache::expiry::Expiries;
+use crate::cache::identity::CacheIdentity;
+use crate::cache::listener::{EntrySource, Listeners};
+use crate::cache::metadata::{EntryInfo, EntryMetadata, Metadata};
+use crate::cache::priority::Priorities;
+use crate::cache::stats::StatsRecorder;
+use crate::cache::FullBehavior;
+use crate::clock::{Clock, SystemClock};
+
+/// PolicyCore holds the bookkeeping a policy keeps beside the structures which decide its eviction order: priorities, metadata, deadlines, the clock, listeners, statistics and what to do when full.
+///
+/// Every policy keeps it in a `core` field of its inner state and gets its shared builder and query methods from `policy_methods!`, except TTLCache, which keeps deadlines in its entries and only takes the builders from `policy_builders!`.
+pub(crate) struct PolicyCore<K, V> {
+    pub(crate) priorities: Priorities<K>,
+    pub(crate) metadata: Metadata<K>,
+    pub(crate) expiries: Expiries<K>,
+    pub(crate) clock: Arc<dyn Clock>,
+    pub(crate) listeners: Listeners<K, V>,
+    pub(crate) stats: StatsRecorder,
+    pub(crate) full_behavior: FullBehavior,
+}
+
+impl<K: Eq + Hash + Clone, V> PolicyCore<K, V> {
+    pub(crate) fn new() -> Self {
+        let identity = CacheIdentity::new();
+        PolicyCore {
+            priorities: Priorities::new(),
+            metadata: Metadata::new(),
+            expiries: Expiries::new(),
+            clock: Arc::new(SystemClock),
+            listeners: Listeners::new(identity.clone()),
+            stats: StatsRecorder::new(identity),
+            full_behavior: FullBehavior::default(),
+        }
+    }
+
+    /// Record the priority, metadata, source and deadline of a key which has just been set, a key set without a priority keeps the one it has.
+    pub(crate) fn track(
+        &mut self,
+        key: &K,
+        priority: Option<u32>,
+        ttl: Option<Duration>,
+        metadata: Option<EntryMetadata>,
+        source: EntrySource,
+    ) {
+        self.priorities.assign(key, priority);
+        let now = self.clock.now();
+        self.metadata.set(key, metadata, now);
+        self.listeners.set_source(key, source);
+        self.expiries.set(key, ttl, now);
+    }
+
+    /// Forget the priority, deadline and metadata of a key which has left the cache.
+    pub(crate) fn forget(&mut self, key: &K) {
+        self.priorities.remove(key);
+        self.expiries.remove(key);
+        self.metadata.remove(key);
+    }
+
+    /// Forget the priorities, deadlines and metadata of every key.
+    pub(crate) fn clear(&mut self) {
+        self.priorities.clear();
+        self.expiries.clear();
+        self.metadata.clear();
+    }
+
+    /// Check if a key has passed its deadline, only reading the clock if some item has one.
+    pub(crate) fn is_expired(&self, key: &K) -> bool {
+        !self.expiries.is_empty() && self.expiries.is_expired(key, self.clock.now())
+    }
+
+    /// Describe a key which the policy holds, or None if it has expired.
+    pub(crate) fn entry_info(&self, key: &K) -> Option<EntryInfo> {
+        let now = self.clock.now();
+        if self.expiries.is_expired(key, now) {
+            return None;
+        }
+        Some(EntryInfo::new(
+            self.priorities.get(key),
+            self.expiries.remaining(key, now),
+            self.metadata.get(key),
+            self.listeners.source(key),
+        ))
+    }
+}
+
+/// Generate the builder methods shared by every policy built on `PolicyCore`.
+///
+/// The cache needs `inner`, `access_listeners` and `latency` fields, and its inner state a `core` field. `$write` names the method which locks `inner` for writing.
+macro_rules! policy_builders {
+    ($write:ident) => {
+        /// Register a listener which is notified whenever an item leaves the cache.
+        pub fn with_eviction_listener(
+            self,
+            listener: impl EvictionListener<K, V> + 'static,
+        ) -> Self {
+            self.inner
+                .$write()
+                .unwrap()
+                .core
+                .listeners
+                .add(Box::new(listener));
+            self
+        }
+
+        /// Choose what happens when an eviction listener panics while the cache lock is held, by default the panic unwinds through the cache call and poisons the lock. Caught panics are counted in `stats().callback_panics`.
+        pub fn with_callback_panics(self, panics: CallbackPanics) -> Self {
+            {
+                let mut inner = self.inner.$write().unwrap();
+                let panicked = inner.core.listeners.set_panics(panics);
+                inner.core.stats.set_callback_panics(panicked);
+            }
+            self
+        }
+
+        /// Name the cache, the name is carried alongside its ID in its statistics and removal events.
+        pub fn with_name(self, name: impl Into<String>) -> Self {
+            {
+                let mut inner = self.inner.$write().unwrap();
+                let name = name.into();
+                inner.core.listeners.set_name(name.clone());
+                inner.core.stats.set_name(name);
+            }
+            self
+        }
+
+        /// Register a callback which is called with the key and outcome of every get, after the cache lock is released.
+        pub fn with_access_listener(mut self, listener: impl AccessListener<K> + 'static) -> Self {
+            Arc::get_mut(&mut self.access_listeners)
+                .expect("access listeners must be registered before the cache is cloned")
+                .add(Box::new(listener));
+            self
+        }
+
+        /// Count only about one in `sample_rate` gets, the hit and miss counts in `stats` are then scaled up estimates.
+        ///
+        /// This reduces the bookkeeping on every get for extremely hot caches, a rate of 1 (the default) counts every get.
+        pub fn with_stats_sampling(self, sample_rate: u64) -> Self {
+            self.inner
+                .$write()
+                .unwrap()
+                .core
+                .stats
+                .set_sample_rate(sample_rate);
+            self
+        }
+
+        /// Make the hit and miss counts in `stats` decay exponentially, halving every `half_life`, so they reflect recent behaviour rather than the cache's whole lifetime.
+        ///
+        /// The other counts are not decayed. Decaying counts read the clock on every counted get.
+        pub fn with_stats_half_life(self, half_life: Duration) -> Self {
+            self.inner
+                .$write()
+                .unwrap()
+                .core
+                .stats
+                .set_half_life(half_life);
+            self
+        }
+
+        /// Choose what happens when a new key is set while the cache is full, the default is to evict per the policy.
+        pub fn with_full_behavior(self, behavior: FullBehavior) -> Self {
+            self.inner.$write().unwrap().core.full_behavior = behavior;
+            self
+        }
+
+        /// Time every get and set, including any wait for the cache lock, so their latency percentiles can be read with `latency_stats`.
+        pub fn with_latency_tracking(mut self) -> Self {
+            Arc::get_mut(&mut self.latency)
+                .expect("latency tracking must be enabled before the cache is cloned")
+                .enable();
+            self
+        }
+
+        /// Get the latency percentiles of gets and sets, or None if the cache was not built `with_latency_tracking`.
+        pub fn latency_stats(&self) -> Option<OperationLatencies> {
+            self.latency.stats()
+        }
+
+        /// Read the time for TTLs and access times from `clock` instead of `Instant::now`, for example a `CoarseClock` to make gets and sets cheaper.
+        pub fn with_clock(self, clock: impl Clock + 'static) -> Self {
+            self.inner.$write().unwrap().core.clock = Arc::new(clock);
+            self
+        }
+    };
+}
+
+/// Generate the builder and query methods shared by the policies which keep their deadlines in `PolicyCore`, those of `policy_builders!` included.
+///
+/// On top of what `policy_builders!` needs, the inner state needs `holds` and `remove_entry` methods. `$read` and `$write` name the methods which lock `inner` for reading and for writing, and the optional `ordered` names a method of the cache which locks it for writing once pending accesses are applied, which the idle time queries use instead.
+macro_rules! policy_methods {
+    (@ordered $cache:ident, $lock:ident) => {
+        $cache.inner.$lock().unwrap()
+    };
+    (@ordered $cache:ident, $lock:ident, $ordered:ident) => {
+        $cache.$ordered()
+    };
+    ($read:ident, $write:ident $(, ordered: $ordered:ident)?) => {
+        $crate::cache::policy_core::policy_builders!($write);
+
+        /// Give every item a time-to-live, items set without one expire this long after they were last set.
+        pub fn with_ttl(self, ttl: Duration) -> Self {
+            self.inner
+                .$write()
+                .unwrap()
+                .core
+                .expiries
+                .set_default_ttl(ttl);
+            self
+        }
+
+        /// Get the priority, remaining TTL and metadata of an item, without counting a hit or miss or updating its position.
+        pub fn entry_info(&self, key: &K) -> Option<EntryInfo> {
+            let inner = self.inner.$read().unwrap();
+            if !inner.holds(key) {
+                return None;
+            }
+            inner.core.entry_info(key)
+        }
+
+        /// Get the keys which will expire within `window`, soonest first, so they can be refreshed before they lapse.
+        ///
+        /// Items which have already expired are not included.
+        pub fn expiring_within(&self, window: Duration) -> Vec<K> {
+            let inner = self.inner.$read().unwrap();
+            inner
+                .core
+                .expiries
+                .expiring_within(inner.core.clock.now(), window)
+        }
+
+        /// Push back the deadline of a live item by `extra` without setting it again, returning false if the item is not in the cache or never expires.
+        ///
+        /// This keeps items alive past their TTL while a long running job still needs them.
+        pub fn extend_ttl(&self, key: &K, extra: Duration) -> bool {
+            let mut inner = self.inner.$write().unwrap();
+            let now = inner.core.clock.now();
+            inner.core.expiries.extend(key, extra, now)
+        }
+
+        /// Get how long an item has gone without being set or hit by a get, without counting a hit or miss or updating its position.
+        pub fn idle_time(&self, key: &K) -> Option<Duration> {
+            let inner = $crate::cache::policy_core::policy_methods!(@ordered self, $read $(, $ordered)?);
+            let now = inner.core.clock.now();
+            if inner.core.expiries.is_expired(key, now) {
+                return None;
+            }
+            inner.core.metadata.idle_time(key, now)
+        }
+
+        /// Remove every item which has not been set or hit by a get for at least `older_than`, returning how many were removed.
+        ///
+        /// Removed items are counted as expirations and listeners are notified with `RemovalCause::Expired`, so caches without a TTL can still be cleaned up periodically.
+        pub fn purge_idle(&self, older_than: Duration) -> usize {
+            let mut inner = $crate::cache::policy_core::policy_methods!(@ordered self, $write $(, $ordered)?);
+            let idle = inner.core.metadata.idle(inner.core.clock.now(), older_than);
+            let mut purged = 0;
+            for key in idle {
+                if let Some(value) = inner.remove_entry(&key) {
+                    inner
+                        .core
+                        .listeners
+                        .notify(&key, &value, RemovalCause::Expired);
+                    inner.core.stats.record_expiration();
+                    purged += 1;
+                }
+            }
+            purged
+        }
+    };
+}
+
+pub(crate) use {policy_builders, policy_methods};
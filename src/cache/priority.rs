@@ -0,0 +1,178 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::hash::Hash;
+
+/// The priority given to items which are set without one, it is the lowest priority so these items are evicted first.
+pub const DEFAULT_PRIORITY: u32 = 0;
+
+/// Priorities tracks user assigned item priorities so that eviction can prefer the lowest priority items.
+///
+/// Only items with a non-default priority are stored, so caches which never use priorities pay nothing beyond an emptiness check. Those items are also indexed by priority, so the items at the lowest priority are found without a scan.
+pub(crate) struct Priorities<K> {
+    priorities: HashMap<K, u32>,
+    levels: BTreeMap<u32, HashSet<K>>,
+}
+
+impl<K: Eq + Hash + Clone> Priorities<K> {
+    pub(crate) fn new() -> Self {
+        Priorities {
+            priorities: HashMap::new(),
+            levels: BTreeMap::new(),
+        }
+    }
+
+    /// Set the priority of a key, replacing any previous priority.
+    pub(crate) fn set(&mut self, key: &K, priority: u32) {
+        self.remove(key);
+        if priority != DEFAULT_PRIORITY {
+            self.priorities.insert(key.clone(), priority);
+            self.levels.entry(priority).or_default().insert(key.clone());
+        }
+    }
+
+    /// Set the priority of a key if one is given, otherwise keep the priority it already has, so a plain overwrite does not lose it.
+    pub(crate) fn assign(&mut self, key: &K, priority: Option<u32>) {
+        if let Some(priority) = priority {
+            self.set(key, priority);
+        }
+    }
+
+    /// Forget the priority of a key.
+    pub(crate) fn remove(&mut self, key: &K) {
+        if let Some(priority) = self.priorities.remove(key) {
+            if let Some(level) = self.levels.get_mut(&priority) {
+                level.remove(key);
+                if level.is_empty() {
+                    self.levels.remove(&priority);
+                }
+            }
+        }
+    }
+
+    /// Forget every priority.
+    pub(crate) fn clear(&mut self) {
+        self.priorities.clear();
+        self.levels.clear();
+    }
+
+    /// Get the priority of a key.
+    pub(crate) fn get(&self, key: &K) -> u32 {
+        self.priorities
+            .get(key)
+            .copied()
+            .unwrap_or(DEFAULT_PRIORITY)
+    }
+
+    /// Get the lowest priority held by any of the `len` items in the cache, or None if every item has the default priority.
+    pub(crate) fn lowest(&self, len: usize) -> Option<u32> {
+        if self.priorities.is_empty() {
+            None
+        } else if self.priorities.len() < len {
+            Some(DEFAULT_PRIORITY)
+        } else {
+            self.levels.keys().next().copied()
+        }
+    }
+
+    /// Get the keys with a priority, or None for the default priority, whose keys are not indexed.
+    pub(crate) fn keys_with(&self, priority: u32) -> Option<impl Iterator<Item = &K>> {
+        if priority == DEFAULT_PRIORITY {
+            return None;
+        }
+        Some(self.levels.get(&priority).into_iter().flatten())
+    }
+
+    /// Pick the first of the candidates (given in the policy's eviction order) with the lowest priority.
+    ///
+    /// Returns None when every item has the default priority, the policy's usual victim should be evicted in that case. When the lowest priority is held by a single item it is returned without looking at the candidates, otherwise the candidates are scanned, skipping only items of higher priorities.
+    pub(crate) fn pick<'a>(&self, len: usize, candidates: impl Iterator<Item = &'a K>) -> Option<K>
+    where
+        K: 'a,
+    {
+        self.pick_where(len, candidates, |_| true)
+    }
+
+    /// Pick like `pick`, but only among the candidates which are `eligible`, such as items which are not pinned.
+    pub(crate) fn pick_where<'a>(
+        &self,
+        len: usize,
+        mut candidates: impl Iterator<Item = &'a K>,
+        eligible: impl Fn(&K) -> bool,
+    ) -> Option<K>
+    where
+        K: 'a,
+    {
+        let lowest = self.lowest(len)?;
+        if let Some(mut keys) = self.keys_with(lowest) {
+            if let (Some(only), None) = (keys.next(), keys.next()) {
+                if eligible(only) {
+                    return Some(only.clone());
+                }
+            }
+        }
+        candidates
+            .find(|key| eligible(key) && self.get(key) == lowest)
+            .cloned()
+    }
+
+    /// Sort items so higher priorities come first, keeping the given order within each priority.
+    pub(crate) fn sort_descending<V>(&self, items: &mut [(K, V)]) {
+        if !self.priorities.is_empty() {
+            items.sort_by_key(|(key, _)| Reverse(self.get(key)));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_priorities_pick() {
+        let mut priorities = Priorities::new();
+        let keys = [1, 2, 3];
+        assert_eq!(priorities.pick(3, keys.iter()), None);
+
+        priorities.set(&1, 5);
+        assert_eq!(priorities.pick(3, keys.iter()), Some(2));
+
+        priorities.set(&2, 3);
+        priorities.set(&3, 4);
+        assert_eq!(priorities.pick(3, keys.iter()), Some(2));
+
+        priorities.remove(&2);
+        priorities.set(&2, 9);
+        assert_eq!(priorities.pick(3, keys.iter()), Some(3));
+
+        priorities.clear();
+        assert_eq!(priorities.pick(3, keys.iter()), None);
+
+        // an item which is not eligible is skipped even when it alone holds the lowest priority
+        priorities.set(&1, 1);
+        priorities.set(&2, 2);
+        priorities.set(&3, 2);
+        assert_eq!(priorities.pick(3, keys.iter()), Some(1));
+        assert_eq!(priorities.pick_where(3, keys.iter(), |key| *key != 1), None);
+        assert_eq!(priorities.pick_where(4, keys.iter(), |key| *key != 1), None);
+    }
+
+    #[test]
+    fn test_priorities_levels() {
+        let mut priorities = Priorities::new();
+        priorities.set(&1, 5);
+        priorities.set(&2, 5);
+        priorities.set(&3, 7);
+        priorities.assign(&3, None);
+        assert_eq!(priorities.get(&3), 7);
+        priorities.assign(&3, Some(2));
+        assert_eq!(priorities.lowest(3), Some(2));
+        // the only key at the lowest priority is picked without the candidates
+        assert_eq!(priorities.pick(3, std::iter::empty()), Some(3));
+        priorities.remove(&3);
+        let mut lowest: Vec<_> = priorities.keys_with(5).unwrap().copied().collect();
+        lowest.sort();
+        assert_eq!(lowest, vec![1, 2]);
+        assert!(priorities.keys_with(DEFAULT_PRIORITY).is_none());
+        assert_eq!(priorities.keys_with(9).unwrap().count(), 0);
+    }
+}
@@ -0,0 +1,283 @@
+use crate::sync::atomic::{AtomicU64, Ordering};
+use crate::sync::Mutex;
+use linked_hash_set::LinkedHashSet;
+use std::any::Any;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::cache::listener::EntrySource;
+use crate::cache::{Cache, CacheStats, DetailedStats, GetOutcome, IterationOrder};
+
+// classifier type, maps each key to the tenant it belongs to
+type Classifier<K, T> = Box<dyn Fn(&K) -> T + Send + Sync>;
+
+/// QuotaCache wraps any cache and limits how much of its capacity each tenant's keys may take up.
+///
+/// Keys are attributed to tenants with a classifier closure. A tenant with a quota can hold at most that fraction of the capacity, when it is at its quota setting a new key removes the tenant's least recently set item instead of letting the wrapped cache evict another tenant's items. Tenants without a quota are only limited by the wrapped cache's own policy.
+///
+/// Items removed to enforce a quota are removed with `remove`, so listeners on the wrapped cache see them as `RemovalCause::Removed`. They are counted and can be read with `quota_evictions`.
+///
+/// Example:
+/// ```
+/// use arcache::{Cache, LRUCache, QuotaCache};
+///
+/// // keys are "tenant:key", the noisy tenant may only use half of the cache
+/// let cache = QuotaCache::new(LRUCache::<String, u64>::new(10), |key: &String| {
+///     key.split(':').next().unwrap_or_default().to_string()
+/// })
+/// .with_quota("noisy".to_string(), 0.5);
+///
+/// cache.set("quiet:a".to_string(), 1);
+/// for i in 0..100 {
+///     cache.set(format!("noisy:{}", i), i);
+/// }
+///
+/// assert!(cache.contains_key(&"quiet:a".to_string()));
+/// assert_eq!(cache.quota_evictions(), 95);
+/// ```
+pub struct QuotaCache<C, K, T> {
+    cache: C,
+    classifier: Classifier<K, T>,
+    quotas: HashMap<T, f64>,
+    tenants: Mutex<HashMap<T, LinkedHashSet<K>>>,
+    quota_evictions: AtomicU64,
+}
+
+impl<C, K, T> QuotaCache<C, K, T>
+where
+    K: Eq + Hash + Clone,
+    T: Eq + Hash,
+{
+    /// Create a new QuotaCache wrapping the given cache, `classifier` gives the tenant each key belongs to.
+    pub fn new(cache: C, classifier: impl Fn(&K) -> T + Send + Sync + 'static) -> Self {
+        QuotaCache {
+            cache,
+            classifier: Box::new(classifier),
+            quotas: HashMap::new(),
+            tenants: Mutex::new(HashMap::new()),
+            quota_evictions: AtomicU64::new(0),
+        }
+    }
+
+    /// Limit a tenant to `fraction` of the wrapped cache's capacity, the fraction is clamped between 0 and 1.
+    ///
+    /// The limit follows the capacity if it is changed, and a tenant can always hold at least one item.
+    pub fn with_quota(mut self, tenant: T, fraction: f64) -> Self {
+        self.quotas.insert(tenant, fraction.clamp(0.0, 1.0));
+        self
+    }
+
+    /// Get the number of items removed to keep tenants within their quotas.
+    pub fn quota_evictions(&self) -> u64 {
+        self.quota_evictions.load(Ordering::Relaxed)
+    }
+
+    /// Get a reference to the wrapped cache.
+    pub fn inner(&self) -> &C {
+        &self.cache
+    }
+}
+
+impl<K, V, C, T> Cache<K, V> for QuotaCache<C, K, T>
+where
+    K: Eq + Hash + Clone + Send + Sync,
+    V: Send + Sync,
+    C: Cache<K, V>,
+    T: Eq + Hash + Send + Sync,
+{
+    /// Get a value from the cache.
+    fn get(&self, key: &K) -> Option<Arc<V>> {
+        self.cache.get(key)
+    }
+
+    /// Get a value from the wrapped cache, with whether it was a hit or why it was a miss.
+    fn get_with_outcome(&self, key: &K) -> (Option<Arc<V>>, GetOutcome) {
+        self.cache.get_with_outcome(key)
+    }
+
+    /// Set a value in the cache, first removing the tenant's least recently set item if the tenant is at its quota.
+    fn set(&self, key: K, value: V) -> Option<Arc<V>> {
+        self.set_with_source(key, value, EntrySource::Inserted)
+    }
+
+    /// Set a value like `set`, passing on how it came to be in the cache to the wrapped cache.
+    fn set_with_source(&self, key: K, value: V, source: EntrySource) -> Option<Arc<V>> {
+        let tenant = (self.classifier)(&key);
+        if let Some(&fraction) = self.quotas.get(&tenant) {
+            let limit = ((self.cache.stats().capacity as f64 * fraction) as usize).max(1);
+            let mut tenants = self.tenants.lock().unwrap();
+            let keys = tenants.entry(tenant).or_default();
+            if !keys.contains(&key) {
+                while keys.len() >= limit {
+                    let Some(oldest) = keys.pop_front() else {
+                        break;
+                    };
+                    // keys which the wrapped cache has already evicted or expired are just forgotten
+                    if self.cache.remove(&oldest).is_some() {
+                        self.quota_evictions.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+            }
+            keys.insert(key.clone());
+        }
+        self.cache.set_with_source(key, value, source)
+    }
+
+    /// Remove a value from the cache.
+    fn remove(&self, key: &K) -> Option<Arc<V>> {
+        let tenant = (self.classifier)(key);
+        if self.quotas.contains_key(&tenant) {
+            let mut tenants = self.tenants.lock().unwrap();
+            if let Some(keys) = tenants.get_mut(&tenant) {
+                keys.remove(key);
+            }
+        }
+        self.cache.remove(key)
+    }
+
+    /// Check if a key is in the cache.
+    fn contains_key(&self, key: &K) -> bool {
+        self.cache.contains_key(key)
+    }
+
+    /// Get the time left before an item expires, or None if it never expires or is not in the cache.
+    fn ttl_remaining(&self, key: &K) -> Option<Duration> {
+        self.cache.ttl_remaining(key)
+    }
+
+    /// Clear the cache and every tenant's usage.
+    fn clear(&self) {
+        self.tenants.lock().unwrap().clear();
+        self.cache.clear();
+    }
+
+    /// Clear the cache and every tenant's usage, returning every item which was removed.
+    fn clear_returning(&self) -> Vec<(K, Arc<V>)> {
+        self.tenants.lock().unwrap().clear();
+        self.cache.clear_returning()
+    }
+
+    /// Copy the items of the wrapped cache into a HashMap.
+    fn to_map(&self) -> HashMap<K, Arc<V>> {
+        self.cache.to_map()
+    }
+
+    /// Get the hottest items of the wrapped cache.
+    fn hottest(&self, limit: usize) -> Vec<(K, Arc<V>)> {
+        self.cache.hottest(limit)
+    }
+
+    /// Get the items of the wrapped cache in the given order.
+    fn items(&self, order: IterationOrder) -> Option<Vec<(K, Arc<V>)>> {
+        self.cache.items(order)
+    }
+
+    /// Get the name of the wrapped cache's eviction policy.
+    fn policy_name(&self) -> &'static str {
+        self.cache.policy_name()
+    }
+
+    /// Get the cache as `Any`, so it can be downcast from a `dyn Cache`.
+    fn as_any(&self) -> Option<&dyn Any>
+    where
+        Self: 'static,
+    {
+        Some(self)
+    }
+
+    /// Get the statistics of the wrapped cache.
+    fn stats(&self) -> CacheStats {
+        self.cache.stats()
+    }
+
+    /// Get the detailed statistics of the wrapped cache.
+    fn detailed_stats(&self) -> DetailedStats {
+        self.cache.detailed_stats()
+    }
+
+    /// Change the capacity of the wrapped cache, quotas are applied to the new capacity from the next set.
+    fn change_capacity(&self, capacity: u64) {
+        self.cache.change_capacity(capacity);
+    }
+
+    /// Change the capacity of the wrapped cache, returning the items which were removed.
+    fn change_capacity_collect(&self, capacity: u64) -> Vec<(K, Arc<V>)> {
+        self.cache.change_capacity_collect(capacity)
+    }
+
+    /// Shed `fraction` of the items of the wrapped cache.
+    fn shed(&self, fraction: f64) -> usize {
+        self.cache.shed(fraction)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::LRUCache;
+
+    #[test]
+    fn test_quota_cache_limits_tenant() {
+        let cache = QuotaCache::new(LRUCache::new(10), |key: &u64| key % 2).with_quota(1, 0.3);
+        for key in [0, 2, 4] {
+            cache.set(key, key);
+        }
+        for key in (1..100).step_by(2) {
+            cache.set(key, key);
+        }
+        for key in [0, 2, 4] {
+            assert!(cache.contains_key(&key));
+        }
+        for key in [95, 97, 99] {
+            assert!(cache.contains_key(&key));
+        }
+        assert_eq!(cache.stats().size, 6);
+        assert_eq!(cache.quota_evictions(), 47);
+    }
+
+    #[test]
+    fn test_quota_cache_overwrite_does_not_evict() {
+        let cache = QuotaCache::new(LRUCache::new(4), |key: &u64| key % 2).with_quota(1, 0.5);
+        cache.set(1, 1);
+        cache.set(3, 3);
+        assert_eq!(cache.set(1, 10).map(|v| *v), Some(1));
+        assert_eq!(cache.quota_evictions(), 0);
+        cache.set(5, 5);
+        assert!(!cache.contains_key(&3));
+        assert!(cache.contains_key(&1));
+    }
+
+    #[test]
+    fn test_quota_cache_forgets_evicted_keys() {
+        let cache = QuotaCache::new(LRUCache::new(2), |key: &u64| key % 2).with_quota(1, 0.5);
+        cache.set(1, 1);
+        // the wrapped cache evicts 1, so the tenant is no longer at its quota
+        cache.set(2, 2);
+        cache.set(4, 4);
+        cache.remove(&4);
+        cache.set(3, 3);
+        assert_eq!(cache.quota_evictions(), 0);
+        assert!(cache.contains_key(&3));
+    }
+
+    #[test]
+    fn test_quota_cache_skips_evicted_keys_at_the_front() {
+        let cache = QuotaCache::new(LRUCache::new(4), |key: &u64| key % 2).with_quota(1, 0.5);
+        cache.set(1, 1);
+        cache.set(3, 3);
+        // the wrapped cache evicts 1 and 3 behind the tenant's back
+        for key in [0, 2, 4, 6] {
+            cache.set(key, key);
+        }
+        cache.set(5, 5);
+        cache.set(7, 7);
+        assert_eq!(cache.quota_evictions(), 0);
+        cache.set(9, 9);
+        assert_eq!(cache.quota_evictions(), 1);
+        assert!(!cache.contains_key(&5));
+        assert!(cache.contains_key(&7));
+        assert!(cache.contains_key(&9));
+    }
+}
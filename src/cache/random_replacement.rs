@@ -1,35 +1,180 @@
+use crate::sync::Mutex;
 use rand::Rng;
+use std::any::Any;
 use std::collections::HashMap;
 use std::hash::Hash;
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
+use std::time::Duration;
 
-use crate::cache::{Cache, CacheStats};
+use crate::cache::latency::{LatencyTracker, OperationLatencies};
+use crate::cache::listener::{
+    AccessListener, AccessListeners, CallbackPanics, EntrySource, EvictionListener, RemovalCause,
+};
+use crate::cache::metadata::{EntryInfo, EntryMetadata};
+use crate::cache::policy_core::{policy_methods, PolicyCore};
+use crate::cache::{
+    shed_count, split_capacity, Cache, CacheStats, DetailedStats, FullBehavior, GetOutcome,
+    TryCache, VersionedCache,
+};
+use crate::clock::Clock;
+use crate::error::CacheError;
+
+type Weigher<K, V> = Arc<dyn Fn(&K, &V) -> u64 + Send + Sync>;
+
+/// Bias decides which of the sampled items the RandomReplacementCache evicts.
+enum Bias<K, V> {
+    /// Evict the sampled item which was used longest ago.
+    Age,
+    /// Evict the sampled item with the lowest weight.
+    Weight(Weigher<K, V>),
+}
+
+impl<K, V> Clone for Bias<K, V> {
+    fn clone(&self) -> Self {
+        match self {
+            Bias::Age => Bias::Age,
+            Bias::Weight(weigher) => Bias::Weight(weigher.clone()),
+        }
+    }
+}
 
 /// RandomReplacementCacheInner contains the inner data structure for the RandomReplacementCache.
 struct RandomReplacementCacheInner<K: Eq + Hash + Send, V: Send + Sync> {
     capacity: u64,
     key_value_map: HashMap<K, Arc<V>>,
     keys: Vec<K>,
-    hits: u64,
-    misses: u64,
+    // with a bias, eviction samples `sample_size` items and evicts the oldest or lightest of them
+    bias: Option<Bias<K, V>>,
+    sample_size: usize,
+    weights: HashMap<K, u64>,
+    core: PolicyCore<K, V>,
 }
 
-impl<K: Eq + Hash + Send, V: Send + Sync> RandomReplacementCacheInner<K, V> {
+impl<K: Eq + Hash + Clone + Send, V: Send + Sync> RandomReplacementCacheInner<K, V> {
     /// Create a new RandomReplacementCacheInner with the given capacity, internally capacity is reserved for the necessary data structures.
     fn new(capacity: u64) -> Self {
         RandomReplacementCacheInner {
             capacity,
             key_value_map: HashMap::with_capacity(capacity as usize),
             keys: Vec::with_capacity(capacity as usize),
-            hits: 0,
-            misses: 0,
+            bias: None,
+            sample_size: 1,
+            weights: HashMap::new(),
+            core: PolicyCore::new(),
+        }
+    }
+
+    /// Check if a key is held.
+    fn holds(&self, key: &K) -> bool {
+        self.key_value_map.contains_key(key)
+    }
+
+    /// Weigh a newly set item when eviction is biased by weight, ages come from the last access times kept for every item.
+    fn weigh(&mut self, key: &K, value: &V) {
+        if let Some(Bias::Weight(weigher)) = &self.bias {
+            let weight = weigher(key, value);
+            self.weights.insert(key.clone(), weight);
+        }
+    }
+
+    /// Evict a random item, if priorities have been assigned the item is chosen randomly from those with the lowest priority.
+    ///
+    /// With a bias several items are sampled and the one used longest ago or with the lowest weight is evicted.
+    fn evict(&mut self) -> Option<(K, Arc<V>)> {
+        if self.keys.is_empty() {
+            return None;
+        }
+        let candidates: Option<Vec<usize>> =
+            self.core.priorities.lowest(self.keys.len()).map(|lowest| {
+                (0..self.keys.len())
+                    .filter(|&i| self.core.priorities.get(&self.keys[i]) == lowest)
+                    .collect()
+            });
+        let pool = candidates.as_ref().map_or(self.keys.len(), Vec::len);
+        let mut rng = rand::rng();
+        let mut draw = || {
+            let i = rng.random_range(0..pool);
+            candidates.as_ref().map_or(i, |candidates| candidates[i])
+        };
+        let samples = self.sample_size.max(1);
+        let index = match self.bias {
+            Some(Bias::Age) => (0..samples)
+                .map(|_| draw())
+                .min_by_key(|&i| self.core.metadata.last_accessed(&self.keys[i]))?,
+            Some(Bias::Weight(_)) => (0..samples)
+                .map(|_| draw())
+                .min_by_key(|&i| self.weights.get(&self.keys[i]).copied().unwrap_or(0))?,
+            None => draw(),
+        };
+        let removed_key = self.keys.swap_remove(index);
+        self.weights.remove(&removed_key);
+        let lifetime = self
+            .core
+            .metadata
+            .lifetime(&removed_key, self.core.clock.now());
+        self.core.forget(&removed_key);
+        let value = self.key_value_map.remove(&removed_key)?;
+        self.core
+            .listeners
+            .notify(&removed_key, &value, RemovalCause::Evicted);
+        self.core.stats.record_eviction(lifetime);
+        Some((removed_key, value))
+    }
+
+    /// Evict items per the policy until at most `len` are left.
+    fn evict_to(&mut self, len: usize) -> Vec<(K, Arc<V>)> {
+        let mut evicted = Vec::new();
+        while self.key_value_map.len() > len {
+            match self.evict() {
+                Some(item) => evicted.push(item),
+                None => break,
+            }
+        }
+        evicted
+    }
+
+    /// Remove an item, forgetting its priority and deadline.
+    fn remove_entry(&mut self, key: &K) -> Option<Arc<V>> {
+        self.weights.remove(key);
+        self.core.forget(key);
+        let result = self.key_value_map.remove(key);
+        if let Some(pos) = self.keys.iter().position(|k| k == key) {
+            self.keys.remove(pos);
+        }
+        result
+    }
+
+    /// Remove every item which has passed its deadline.
+    fn purge_expired(&mut self) {
+        let now = self.core.clock.now();
+        while let Some(key) = self.core.expiries.pop_expired(now) {
+            if let Some(value) = self.remove_entry(&key) {
+                self.core
+                    .listeners
+                    .notify(&key, &value, RemovalCause::Expired);
+                self.core.stats.record_expiration();
+            }
         }
     }
+
+    /// Remove every item, notifying listeners and returning the removed items.
+    fn clear_entries(&mut self) -> Vec<(K, Arc<V>)> {
+        self.keys.clear();
+        self.weights.clear();
+        self.core.clear();
+        let cleared: Vec<(K, Arc<V>)> = self.key_value_map.drain().collect();
+        for (key, value) in &cleared {
+            self.core
+                .listeners
+                .notify(key, value, RemovalCause::Cleared);
+        }
+        cleared
+    }
 }
 
 /// RandomReplacementCache is a cache which evicts items randomly.
 ///
-/// When the cache is full, a random item is removed to make space for the new item.
+/// When the cache is full, a random item is removed to make space for the new item. Build it `with_age_bias` or `with_weigher` to sample several items and evict the least recently used or lowest weight of them instead.
 ///
 /// All mutability is handled internally with a Mutex, so the cache can be shared between threads. Values are returned as Arcs to allow for shared ownership.
 ///
@@ -50,14 +195,209 @@ impl<K: Eq + Hash + Send, V: Send + Sync> RandomReplacementCacheInner<K, V> {
 /// println!("{:?}", cache.stats());
 /// ```
 pub struct RandomReplacementCache<K: Eq + Hash + Send, V: Send + Sync> {
-    inner: Mutex<RandomReplacementCacheInner<K, V>>,
+    inner: Arc<Mutex<RandomReplacementCacheInner<K, V>>>,
+    access_listeners: Arc<AccessListeners<K>>,
+    latency: Arc<LatencyTracker>,
 }
 
-impl<K: Eq + Hash + Sync + Send, V: Send + Sync> RandomReplacementCache<K, V> {
+impl<K: Eq + Hash + Clone + Sync + Send, V: Send + Sync> RandomReplacementCache<K, V> {
     /// Create a new RandomReplacementCache with the given capacity.
     pub fn new(capacity: u64) -> Self {
         RandomReplacementCache {
-            inner: Mutex::new(RandomReplacementCacheInner::new(capacity)),
+            inner: Arc::new(Mutex::new(RandomReplacementCacheInner::new(capacity))),
+            access_listeners: Arc::new(AccessListeners::new()),
+            latency: Arc::new(LatencyTracker::new()),
+        }
+    }
+
+    /// Bias eviction towards items used longest ago, by sampling `sample_size` random items and evicting the least recently used of them.
+    ///
+    /// This approximates LRU without keeping items in order, larger samples approximate it more closely.
+    pub fn with_age_bias(self, sample_size: usize) -> Self {
+        {
+            let mut inner = self.inner.lock().unwrap();
+            inner.bias = Some(Bias::Age);
+            inner.sample_size = sample_size.max(1);
+        }
+        self
+    }
+
+    /// Bias eviction towards cheap items, by sampling `sample_size` random items and evicting the one with the lowest weight.
+    ///
+    /// Weights are computed by `weigher` when an item is set, for example from the cost of recomputing the value.
+    pub fn with_weigher(
+        self,
+        sample_size: usize,
+        weigher: impl Fn(&K, &V) -> u64 + Send + Sync + 'static,
+    ) -> Self {
+        {
+            let mut inner = self.inner.lock().unwrap();
+            inner.bias = Some(Bias::Weight(Arc::new(weigher)));
+            inner.sample_size = sample_size.max(1);
+        }
+        self
+    }
+
+    policy_methods!(lock, lock);
+
+    /// Set a value in the cache with a priority, when the cache is full lower priority items are evicted before higher priority ones.
+    ///
+    /// Within a priority the evicted item is chosen at random. New items set with `set` have the lowest priority, `DEFAULT_PRIORITY`, and setting a key again without a priority keeps the one it has.
+    pub fn set_with_priority(&self, key: K, value: V, priority: u32) -> Option<Arc<V>> {
+        self.insert(
+            key,
+            Arc::new(value),
+            Some(priority),
+            None,
+            None,
+            None,
+            EntrySource::Inserted,
+        )
+        .unwrap_or(None)
+    }
+
+    /// Set a value in the cache which expires after the given time-to-live, overriding the cache's default TTL.
+    ///
+    /// Expired items are never returned and are removed before any live item is evicted.
+    pub fn set_with_ttl(&self, key: K, value: V, ttl: Duration) -> Option<Arc<V>> {
+        self.insert(
+            key,
+            Arc::new(value),
+            None,
+            Some(ttl),
+            None,
+            None,
+            EntrySource::Inserted,
+        )
+        .unwrap_or(None)
+    }
+
+    /// Set a value in the cache with a metadata value attached, which can be read back with `entry_info`.
+    ///
+    /// Metadata is dropped when the item leaves the cache or is set again without metadata.
+    pub fn set_with_metadata(
+        &self,
+        key: K,
+        value: V,
+        metadata: impl Any + Send + Sync,
+    ) -> Option<Arc<V>> {
+        self.insert(
+            key,
+            Arc::new(value),
+            None,
+            None,
+            Some(Arc::new(metadata)),
+            None,
+            EntrySource::Inserted,
+        )
+        .unwrap_or(None)
+    }
+
+    /// Move every item matching `predicate` into a new cache, whose capacity is in proportion to the share of items moved.
+    ///
+    /// Moved items keep their priority and remaining TTL. Listeners on this cache are notified with `RemovalCause::Removed`, they are not carried over to the new cache.
+    pub fn split_off<F: Fn(&K, &V) -> bool>(&self, predicate: F) -> Self {
+        let mut inner = self.inner.lock().unwrap();
+        inner.purge_expired();
+        let keys: Vec<K> = inner
+            .key_value_map
+            .iter()
+            .filter(|(key, value)| predicate(key, value))
+            .map(|(key, _)| key.clone())
+            .collect();
+        let capacity = split_capacity(inner.capacity, keys.len(), inner.key_value_map.len());
+        let split = Self::new(capacity);
+        split.inner.lock().unwrap().core.clock = inner.core.clock.clone();
+        {
+            let mut split_inner = split.inner.lock().unwrap();
+            split_inner.bias = inner.bias.clone();
+            split_inner.sample_size = inner.sample_size;
+            if let Some(ttl) = inner.core.expiries.default_ttl() {
+                split_inner.core.expiries.set_default_ttl(ttl);
+            }
+        }
+        let now = inner.core.clock.now();
+        for key in keys {
+            let priority = inner.core.priorities.get(&key);
+            let ttl = inner.core.expiries.remaining(&key, now);
+            let metadata = inner.core.metadata.get(&key);
+            let source = inner.core.listeners.source(&key);
+            let access = inner.core.metadata.access(&key);
+            if let Some(value) = inner.remove_entry(&key) {
+                inner
+                    .core
+                    .listeners
+                    .notify(&key, &value, RemovalCause::Removed);
+                let _ = split.insert(
+                    key.clone(),
+                    value,
+                    Some(priority),
+                    ttl,
+                    metadata,
+                    None,
+                    source,
+                );
+                if let Some(access) = access {
+                    split
+                        .inner
+                        .lock()
+                        .unwrap()
+                        .core
+                        .metadata
+                        .restore_access(&key, access);
+                }
+            }
+        }
+        split
+    }
+
+    /// Insert a value with a priority and an optional TTL, evicting an item if the cache is full.
+    #[allow(clippy::too_many_arguments)]
+    fn insert(
+        &self,
+        key: K,
+        value: Arc<V>,
+        priority: Option<u32>,
+        ttl: Option<Duration>,
+        metadata: Option<EntryMetadata>,
+        expected_version: Option<u64>,
+        source: EntrySource,
+    ) -> Result<Option<Arc<V>>, CacheError> {
+        let mut inner = self.inner.lock().map_err(|_| CacheError::Poisoned)?;
+        if inner.capacity == 0 {
+            return Err(CacheError::CapacityZero);
+        }
+        inner.purge_expired();
+        inner.core.metadata.check_version(&key, expected_version)?;
+        // overwriting a key never evicts another item
+        let is_new = !inner.key_value_map.contains_key(&key);
+        if is_new
+            && !inner.core.full_behavior.evicts()
+            && inner.key_value_map.len() as u64 >= inner.capacity
+        {
+            inner.core.listeners.reject(&key);
+            return inner.core.full_behavior.rejection();
+        }
+        if is_new && inner.key_value_map.len() as u64 >= inner.capacity {
+            inner.evict();
+        }
+        inner.core.stats.record_set(is_new);
+        inner.core.track(&key, priority, ttl, metadata, source);
+        if is_new {
+            inner.keys.push(key.clone());
+        }
+        inner.weigh(&key, &value);
+        Ok(inner.key_value_map.insert(key, value))
+    }
+}
+
+impl<K: Eq + Hash + Send, V: Send + Sync> Clone for RandomReplacementCache<K, V> {
+    /// Get another handle to the same cache, sharing its items, settings and statistics.
+    fn clone(&self) -> Self {
+        RandomReplacementCache {
+            inner: self.inner.clone(),
+            access_listeners: self.access_listeners.clone(),
+            latency: self.latency.clone(),
         }
     }
 }
@@ -67,73 +407,271 @@ impl<K: Eq + Hash + Clone + Sync + Send, V: Send + Sync> Cache<K, V>
 {
     /// Get a value from the cache.
     fn get(&self, key: &K) -> Option<Arc<V>> {
-        let mut inner = self.inner.lock().unwrap();
-        let result = inner.key_value_map.get(key).cloned();
+        let started = self.latency.start();
+        let result = {
+            let mut inner = self.inner.lock().unwrap();
+            inner.purge_expired();
+            let result = inner.key_value_map.get(key).cloned();
 
-        if result.is_some() {
-            inner.hits += 1;
-        } else {
-            inner.misses += 1;
-        }
+            if result.is_some() {
+                inner.core.stats.record_hit();
+                let now = inner.core.clock.now();
+                inner.core.metadata.touch(key, now);
+            } else {
+                inner.core.stats.record_miss();
+            }
+            result
+        };
+        self.latency.record_get(started);
+        self.access_listeners.notify(key, &result);
         result
     }
 
+    /// Get a value from the cache, with whether it was a hit, or a miss because the key expired, was rejected or is absent.
+    fn get_with_outcome(&self, key: &K) -> (Option<Arc<V>>, GetOutcome) {
+        let result = self.get(key);
+        let outcome = match result {
+            Some(_) => GetOutcome::Hit,
+            None => self.inner.lock().unwrap().core.listeners.miss_outcome(key),
+        };
+        (result, outcome)
+    }
+
     /// Set a value in the cache.
     fn set(&self, key: K, value: V) -> Option<Arc<V>> {
-        let mut inner = self.inner.lock().unwrap();
-        if inner.key_value_map.len() as u64 >= inner.capacity {
-            let index = rand::rng().random_range(0..inner.keys.len());
-            let removed_key = inner.keys.swap_remove(index);
-            inner.key_value_map.remove(&removed_key);
-        }
-        let arc_value = Arc::new(value);
-        inner.keys.push(key.clone());
-        inner.key_value_map.insert(key, arc_value)
+        let started = self.latency.start();
+        let result = self
+            .insert(
+                key,
+                Arc::new(value),
+                None,
+                None,
+                None,
+                None,
+                EntrySource::Inserted,
+            )
+            .unwrap_or(None);
+        self.latency.record_set(started);
+        result
+    }
+
+    /// Set a value in the cache, recording how it came to be in the cache for `entry_info` and eviction listeners.
+    fn set_with_source(&self, key: K, value: V, source: EntrySource) -> Option<Arc<V>> {
+        let started = self.latency.start();
+        let result = self
+            .insert(key, Arc::new(value), None, None, None, None, source)
+            .unwrap_or(None);
+        self.latency.record_set(started);
+        result
     }
 
     /// Remove a value from the cache.
     fn remove(&self, key: &K) -> Option<Arc<V>> {
         let mut inner = self.inner.lock().unwrap();
-        let result = inner.key_value_map.remove(key);
-        if let Some(pos) = inner.keys.iter().position(|k| k == key) {
-            inner.keys.remove(pos);
+        let result = inner.remove_entry(key);
+        if let Some(value) = &result {
+            inner
+                .core
+                .listeners
+                .notify(key, value, RemovalCause::Removed);
         }
         result
     }
 
+    /// Check if a key is in the cache and has not expired, without counting a hit or miss or updating its position.
+    fn contains_key(&self, key: &K) -> bool {
+        let inner = self.inner.lock().unwrap();
+        inner.key_value_map.contains_key(key)
+            && !inner.core.expiries.is_expired(key, inner.core.clock.now())
+    }
+
+    /// Get the time left before an item expires, or None if it never expires or is not in the cache.
+    fn ttl_remaining(&self, key: &K) -> Option<Duration> {
+        self.entry_info(key)?.ttl_remaining
+    }
+
     /// Clear the cache.
     fn clear(&self) {
         let mut inner = self.inner.lock().unwrap();
-        inner.key_value_map.clear();
-        inner.keys.clear();
+        inner.clear_entries();
+    }
+
+    /// Clear the cache, returning every item which was removed.
+    fn clear_returning(&self) -> Vec<(K, Arc<V>)> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.clear_entries()
+    }
+
+    /// Copy the unexpired items into a HashMap in one locked pass.
+    fn to_map(&self) -> HashMap<K, Arc<V>> {
+        let inner = self.inner.lock().unwrap();
+        let now = inner.core.clock.now();
+        inner
+            .key_value_map
+            .iter()
+            .filter(|(key, _)| !inner.core.expiries.is_expired(key, now))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect()
+    }
+
+    /// Get up to `limit` unexpired items, ordered with the items the policy would evict last first. Higher priority items come before lower ones, and otherwise in no particular order as every item is equally likely to be evicted.
+    fn hottest(&self, limit: usize) -> Vec<(K, Arc<V>)> {
+        let inner = self.inner.lock().unwrap();
+        let now = inner.core.clock.now();
+        let mut items: Vec<(K, Arc<V>)> = inner
+            .key_value_map
+            .iter()
+            .filter(|(key, _)| !inner.core.expiries.is_expired(key, now))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect();
+        inner.core.priorities.sort_descending(&mut items);
+        items.truncate(limit);
+        items
+    }
+
+    /// Get the name of the eviction policy.
+    fn policy_name(&self) -> &'static str {
+        "random"
+    }
+
+    /// Get the cache as `Any`, so it can be downcast from a `dyn Cache`.
+    fn as_any(&self) -> Option<&dyn Any>
+    where
+        Self: 'static,
+    {
+        Some(self)
     }
 
     /// Get cache statistics.
     fn stats(&self) -> CacheStats {
         let inner = self.inner.lock().unwrap();
-        CacheStats {
-            hits: inner.hits,
-            misses: inner.misses,
-            size: inner.key_value_map.len() as u64,
-            capacity: inner.capacity,
-        }
+        inner
+            .core
+            .stats
+            .snapshot(inner.key_value_map.len() as u64, inner.capacity)
+    }
+
+    /// Get the cache statistics together with how soon the items expire.
+    fn detailed_stats(&self) -> DetailedStats {
+        let inner = self.inner.lock().unwrap();
+        let size = inner.key_value_map.len() as u64;
+        let mut detailed = DetailedStats::new(inner.core.stats.snapshot(size, inner.capacity));
+        detailed.eviction_ages = Some(inner.core.stats.eviction_ages());
+        detailed.expiry_distribution = Some(
+            inner
+                .core
+                .expiries
+                .distribution(inner.core.clock.now(), size),
+        );
+        detailed
     }
 
     /// Change the capacity of the cache, if the new capacity is smaller than the current size, the oldest items are removed.
     fn change_capacity(&self, capacity: u64) {
+        self.change_capacity_collect(capacity);
+    }
+
+    /// Change the capacity of the cache, returning the items which were removed to fit the new capacity.
+    fn change_capacity_collect(&self, capacity: u64) -> Vec<(K, Arc<V>)> {
         let mut inner = self.inner.lock().unwrap();
         let old_capacity = inner.capacity;
         inner.capacity = capacity;
-        while inner.key_value_map.len() as u64 > inner.capacity {
-            let index = rand::rng().random_range(0..inner.keys.len());
-            let removed_key = inner.keys.swap_remove(index);
-            inner.key_value_map.remove(&removed_key);
-        }
+        let evicted = inner.evict_to(capacity as usize);
 
         if inner.capacity > old_capacity {
             let additional = (inner.capacity - old_capacity) as usize;
             inner.key_value_map.reserve(additional);
         }
+        evicted
+    }
+
+    /// Evict `fraction` of the items per the policy in one locked pass, leaving the capacity unchanged.
+    fn shed(&self, fraction: f64) -> usize {
+        let mut inner = self.inner.lock().unwrap();
+        let len = inner.key_value_map.len();
+        inner.evict_to(len - shed_count(len, fraction)).len()
+    }
+}
+
+impl<K: Eq + Hash + Clone + Sync + Send, V: Send + Sync> TryCache<K, V>
+    for RandomReplacementCache<K, V>
+{
+    /// Get a value from the cache, returning `CacheError::Poisoned` instead of panicking if the cache lock is poisoned.
+    fn try_get(&self, key: &K) -> Result<Option<Arc<V>>, CacheError> {
+        if self.inner.is_poisoned() {
+            return Err(CacheError::Poisoned);
+        }
+        Ok(self.get(key))
+    }
+
+    /// Set a value in the cache, returning an error instead of inserting if the cache is full and was built with `FullBehavior::ReturnError`, has a capacity of zero, or its lock is poisoned.
+    fn try_set(&self, key: K, value: V) -> Result<Option<Arc<V>>, CacheError> {
+        let started = self.latency.start();
+        let result = self.insert(
+            key,
+            Arc::new(value),
+            None,
+            None,
+            None,
+            None,
+            EntrySource::Inserted,
+        );
+        self.latency.record_set(started);
+        result
+    }
+}
+
+impl<K: Eq + Hash + Clone + Sync + Send, V: Send + Sync> VersionedCache<K, V>
+    for RandomReplacementCache<K, V>
+{
+    /// Get the current version of an item, without counting a hit or miss or updating its position.
+    fn version(&self, key: &K) -> Option<u64> {
+        let inner = self.inner.lock().unwrap();
+        if inner.core.expiries.is_expired(key, inner.core.clock.now()) {
+            return None;
+        }
+        inner.core.metadata.version(key)
+    }
+
+    /// Set a value only if the item is at `expected_version`, or is not in the cache if it is 0.
+    fn set_versioned(
+        &self,
+        key: K,
+        value: V,
+        expected_version: u64,
+    ) -> Result<Option<Arc<V>>, CacheError> {
+        let started = self.latency.start();
+        let result = self.insert(
+            key,
+            Arc::new(value),
+            None,
+            None,
+            None,
+            Some(expected_version),
+            EntrySource::Inserted,
+        );
+        self.latency.record_set(started);
+        result
+    }
+}
+
+impl<K: Eq + Hash + Clone + Sync + Send, V: Send + Sync> IntoIterator
+    for RandomReplacementCache<K, V>
+{
+    type Item = (K, Arc<V>);
+    type IntoIter = std::vec::IntoIter<(K, Arc<V>)>;
+
+    /// Consume the handle, yielding the cache's unexpired items in no particular order. Other handles still hold the items.
+    fn into_iter(self) -> Self::IntoIter {
+        let inner = self.inner.lock().unwrap();
+        let now = inner.core.clock.now();
+        let items: Vec<(K, Arc<V>)> = inner
+            .key_value_map
+            .iter()
+            .filter(|(key, _)| !inner.core.expiries.is_expired(key, now))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect();
+        items.into_iter()
     }
 }
 
@@ -154,6 +692,49 @@ mod tests {
         assert_eq!(cache.get(&4).map(|v| *v), Some(4));
     }
 
+    #[test]
+    fn test_random_replacement_cache_priorities() {
+        let cache = RandomReplacementCache::new(3);
+        cache.set_with_priority(1, 1, 10);
+        cache.set_with_priority(2, 2, 10);
+        cache.set(3, 3);
+        cache.set(4, 4);
+        assert_eq!(cache.get(&3), None);
+        cache.set_with_priority(5, 5, 1);
+        assert_eq!(cache.get(&4), None);
+        assert_eq!(cache.get(&1).map(|v| *v), Some(1));
+        assert_eq!(cache.get(&2).map(|v| *v), Some(2));
+        assert_eq!(cache.get(&5).map(|v| *v), Some(5));
+    }
+
+    #[test]
+    fn test_random_replacement_cache_ttl() {
+        let cache = RandomReplacementCache::new(2);
+        cache.set(1, 1);
+        cache.set_with_ttl(2, 2, Duration::ZERO);
+        assert!(!cache.contains_key(&2));
+        // the expired item is always the one removed
+        cache.set(3, 3);
+        assert_eq!(cache.get(&1).map(|v| *v), Some(1));
+        assert_eq!(cache.get(&2), None);
+        assert_eq!(cache.get(&3).map(|v| *v), Some(3));
+    }
+
+    #[test]
+    fn test_random_replacement_cache_change_capacity_collect() {
+        let cache = RandomReplacementCache::new(3);
+        cache.set(1, 1);
+        cache.set(2, 2);
+        cache.set(3, 3);
+        let evicted = cache.change_capacity_collect(1);
+        assert_eq!(evicted.len(), 2);
+        for (key, value) in evicted {
+            assert_eq!(key, *value);
+            assert!(!cache.contains_key(&key));
+        }
+        assert_eq!(cache.stats().size, 1);
+    }
+
     #[test]
     fn test_random_replacement_cache_clear() {
         let cache = RandomReplacementCache::new(2);
@@ -172,4 +753,45 @@ mod tests {
         cache.change_capacity(1);
         assert!(cache.get(&1).is_none() || cache.get(&2).is_none());
     }
+
+    #[test]
+    fn test_random_replacement_cache_overwrite_keeps_evicting() {
+        let cache = RandomReplacementCache::new(3);
+        for round in 0..20 {
+            cache.set(0, round);
+        }
+        for key in 1..50 {
+            cache.set(key, key);
+            assert!(cache.stats().size <= 3);
+        }
+    }
+
+    #[test]
+    fn test_random_replacement_cache_age_bias() {
+        let cache = RandomReplacementCache::new(10).with_age_bias(1_000);
+        for key in 0..10 {
+            cache.set(key, key);
+        }
+        for key in (0..10).filter(|&key| key != 3) {
+            cache.get(&key);
+        }
+        cache.set(10, 10);
+        assert_eq!(cache.get(&3), None);
+        assert_eq!(cache.stats().size, 10);
+    }
+
+    #[test]
+    fn test_random_replacement_cache_weigher() {
+        let cache =
+            RandomReplacementCache::new(5).with_weigher(1_000, |_: &u32, v: &u32| *v as u64);
+        for key in 0..5 {
+            cache.set(key, if key == 2 { 1 } else { 100 });
+        }
+        cache.set(5, 100);
+        assert_eq!(cache.get(&2), None);
+        let split = cache.split_off(|key, _| *key >= 3);
+        split.set(6, 0);
+        split.set(7, 100);
+        assert_eq!(split.get(&6), None);
+    }
 }
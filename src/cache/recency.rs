@@ -0,0 +1,62 @@
+use crate::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use crate::sync::Mutex;
+use std::time::Instant;
+
+/// A fixed size ring of recorded accesses, written by readers without waiting on the cache lock or on each other.
+///
+/// Each slot has its own lock which writers only ever try, so an access is dropped rather than waited for when two readers land on the same slot. Dropped and overwritten accesses only cost recency precision, never correctness. Every access is counted as a hit, including dropped ones, and misses served without the cache lock are counted alongside, so neither is lost.
+pub(crate) struct RecencyBuffer<K> {
+    slots: Vec<Mutex<Option<(K, Instant)>>>,
+    written: AtomicUsize,
+    // how many of the written accesses have been taken as hits
+    counted: AtomicUsize,
+    misses: AtomicU64,
+}
+
+impl<K> RecencyBuffer<K> {
+    /// Create a new RecencyBuffer holding up to `size` accesses.
+    pub(crate) fn new(size: usize) -> Self {
+        RecencyBuffer {
+            slots: (0..size.max(1)).map(|_| Mutex::new(None)).collect(),
+            written: AtomicUsize::new(0),
+            counted: AtomicUsize::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// Record an access, returning true if the buffer has filled up and should be drained.
+    pub(crate) fn record(&self, key: K, at: Instant) -> bool {
+        let position = self.written.fetch_add(1, Ordering::Relaxed);
+        let index = position % self.slots.len();
+        if let Ok(mut slot) = self.slots[index].try_lock() {
+            *slot = Some((key, at));
+        }
+        index == self.slots.len() - 1
+    }
+
+    /// Take every recorded access, oldest first.
+    pub(crate) fn drain(&self) -> Vec<(K, Instant)> {
+        let mut accesses = Vec::new();
+        for slot in &self.slots {
+            let mut slot = slot.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            if let Some(access) = slot.take() {
+                accesses.push(access);
+            }
+        }
+        accesses.sort_by_key(|(_, at)| *at);
+        accesses
+    }
+
+    /// Count a miss.
+    pub(crate) fn record_miss(&self) {
+        self.misses.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Take the number of accesses recorded and misses counted since they were last taken.
+    pub(crate) fn take_counts(&self) -> (u64, u64) {
+        let written = self.written.load(Ordering::Relaxed);
+        let counted = self.counted.swap(written, Ordering::Relaxed);
+        let misses = self.misses.swap(0, Ordering::Relaxed);
+        (written.wrapping_sub(counted) as u64, misses)
+    }
+}
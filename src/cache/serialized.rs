@@ -0,0 +1,199 @@
+use crate::sync::atomic::{AtomicU64, Ordering};
+use std::hash::Hash;
+
+use crate::cache::{Cache, CacheStats};
+#[cfg(feature = "serde")]
+use crate::codec::CborCodec;
+use crate::codec::Codec;
+pub use crate::codec::SerializationError;
+use crate::error::CacheError;
+
+/// SerializedCache stores values as serialized bytes in any cache, while exposing a typed API.
+///
+/// Values are encoded by a `Codec` when they are set and decoded again on every get, so each get returns a freshly owned value rather than an `Arc`. `new` encodes them as CBOR and requires the `serde` feature, `new_with_codec` takes any codec. Storing bytes keeps the size of every item known, see `stored_bytes`, and lets the bytes be compressed or weighed by the wrapped cache. Values which fail to decode are treated as misses and counted in `decode_errors`.
+///
+/// Example:
+/// ```
+/// # #[cfg(feature = "serde")]
+/// # {
+/// use arcache::{LRUCache, SerializedCache};
+///
+/// let cache = SerializedCache::<&str, (u32, String)>::new(LRUCache::new(10));
+///
+/// cache.set("key", &(1, "value".to_string())).unwrap();
+///
+/// assert_eq!(cache.get(&"key"), Some((1, "value".to_string())));
+/// # }
+/// ```
+pub struct SerializedCache<K, T> {
+    cache: Box<dyn Cache<K, Vec<u8>>>,
+    codec: Box<dyn Codec<T>>,
+    decode_errors: AtomicU64,
+}
+
+#[cfg(feature = "serde")]
+impl<K, T> SerializedCache<K, T>
+where
+    K: Eq + Hash + Clone + Send + Sync,
+    T: serde::Serialize + serde::de::DeserializeOwned,
+{
+    /// Create a new SerializedCache storing CBOR encoded bytes in the given cache.
+    ///
+    /// This requires the `serde` feature.
+    pub fn new(cache: impl Cache<K, Vec<u8>> + 'static) -> Self {
+        Self::new_with_codec(cache, CborCodec)
+    }
+}
+
+impl<K, T> SerializedCache<K, T>
+where
+    K: Eq + Hash + Clone + Send + Sync,
+{
+    /// Create a new SerializedCache storing bytes encoded by `codec` in the given cache.
+    pub fn new_with_codec(
+        cache: impl Cache<K, Vec<u8>> + 'static,
+        codec: impl Codec<T> + 'static,
+    ) -> Self {
+        SerializedCache {
+            cache: Box::new(cache),
+            codec: Box::new(codec),
+            decode_errors: AtomicU64::new(0),
+        }
+    }
+
+    /// Get a value from the cache, decoding it from its bytes.
+    pub fn get(&self, key: &K) -> Option<T> {
+        self.try_get(key).unwrap_or(None)
+    }
+
+    /// Get a value from the cache, returning `CacheError::SerializationFailed` rather than a miss if its bytes cannot be decoded.
+    pub fn try_get(&self, key: &K) -> Result<Option<T>, CacheError> {
+        let Some(bytes) = self.cache.get(key) else {
+            return Ok(None);
+        };
+        self.codec.decode(&bytes).map(Some).map_err(|error| {
+            self.decode_errors.fetch_add(1, Ordering::Relaxed);
+            CacheError::from(error)
+        })
+    }
+
+    /// Set a value in the cache, encoding it to bytes.
+    pub fn set(&self, key: K, value: &T) -> Result<(), SerializationError> {
+        let bytes = self.codec.encode(value)?;
+        self.cache.set(key, bytes);
+        Ok(())
+    }
+
+    /// Remove a value from the cache, returning whether it was present.
+    pub fn remove(&self, key: &K) -> bool {
+        self.cache.remove(key).is_some()
+    }
+
+    /// Check if a key is in the cache.
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.cache.contains_key(key)
+    }
+
+    /// Clear the cache.
+    pub fn clear(&self) {
+        self.cache.clear();
+    }
+
+    /// Get the total number of bytes held by the cache's values.
+    pub fn stored_bytes(&self) -> u64 {
+        self.cache
+            .to_map()
+            .values()
+            .map(|bytes| bytes.len() as u64)
+            .sum()
+    }
+
+    /// Get the number of gets whose bytes could not be decoded.
+    pub fn decode_errors(&self) -> u64 {
+        self.decode_errors.load(Ordering::Relaxed)
+    }
+
+    /// Get the cache statistics.
+    pub fn stats(&self) -> CacheStats {
+        self.cache.stats()
+    }
+
+    /// Change the capacity of the wrapped cache.
+    pub fn change_capacity(&self, capacity: u64) {
+        self.cache.change_capacity(capacity);
+    }
+
+    /// Get a reference to the wrapped cache of bytes.
+    pub fn inner(&self) -> &dyn Cache<K, Vec<u8>> {
+        self.cache.as_ref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::LRUCache;
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serialized_cache() {
+        use std::collections::HashMap;
+
+        let cache = SerializedCache::<u64, HashMap<String, Vec<u32>>>::new(LRUCache::new(2));
+        let value = HashMap::from([("a".to_string(), vec![1, 2, 3])]);
+        cache.set(1, &value).unwrap();
+        assert_eq!(cache.get(&1), Some(value));
+        assert_eq!(cache.get(&2), None);
+        assert!(cache.remove(&1));
+        assert!(!cache.contains_key(&1));
+        assert_eq!(cache.stats().hits, 1);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serialized_cache_stored_bytes() {
+        let cache = SerializedCache::<u64, String>::new(LRUCache::new(2));
+        cache.set(1, &"a".repeat(100)).unwrap();
+        cache.set(2, &"b".repeat(10)).unwrap();
+        let bytes = cache.stored_bytes();
+        assert!((110..120).contains(&bytes), "stored {} bytes", bytes);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serialized_cache_decode_error() {
+        let cache = SerializedCache::<u64, String>::new(LRUCache::new(2));
+        cache.inner().set(1, vec![0xff]);
+        assert_eq!(cache.get(&1), None);
+        assert_eq!(cache.decode_errors(), 1);
+        assert!(matches!(
+            cache.try_get(&1),
+            Err(CacheError::SerializationFailed(_))
+        ));
+        assert_eq!(cache.try_get(&2), Ok(None));
+    }
+
+    struct Utf8Codec;
+
+    impl Codec<String> for Utf8Codec {
+        fn encode(&self, value: &String) -> Result<Vec<u8>, SerializationError> {
+            Ok(value.as_bytes().to_vec())
+        }
+
+        fn decode(&self, bytes: &[u8]) -> Result<String, SerializationError> {
+            String::from_utf8(bytes.to_vec())
+                .map_err(|error| SerializationError::new(error.to_string()))
+        }
+    }
+
+    #[test]
+    fn test_serialized_cache_custom_codec() {
+        let cache = SerializedCache::new_with_codec(LRUCache::new(2), Utf8Codec);
+        cache.set(1, &"abc".to_string()).unwrap();
+        assert_eq!(cache.inner().get(&1).as_deref(), Some(&b"abc".to_vec()));
+        assert_eq!(cache.get(&1), Some("abc".to_string()));
+        cache.inner().set(2, vec![0xff]);
+        assert_eq!(cache.get(&2), None);
+        assert_eq!(cache.decode_errors(), 1);
+    }
+}
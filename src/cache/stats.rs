@@ -0,0 +1,284 @@
+use crate::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::cache::identity::CacheIdentity;
+use crate::cache::metadata::Lifetime;
+use crate::cache::{CacheStats, EvictionAges};
+
+/// Hit and miss counts which decay exponentially over time.
+struct Decay {
+    hits: f64,
+    misses: f64,
+    at: Instant,
+    half_life: Duration,
+}
+
+impl Decay {
+    /// Get the factor the counts shrink by between the last update and `now`.
+    fn factor(&self, now: Instant) -> f64 {
+        let elapsed = now.saturating_duration_since(self.at).as_secs_f64();
+        0.5f64.powf(elapsed / self.half_life.as_secs_f64())
+    }
+
+    /// Decay the counts up to `now`.
+    fn advance(&mut self, now: Instant) {
+        let factor = self.factor(now);
+        self.hits *= factor;
+        self.misses *= factor;
+        self.at = now;
+    }
+}
+
+/// StatsRecorder counts the hits and misses of a cache, and the inserts, updates, evictions and expirations of its items, with how long evicted items lived.
+///
+/// By default every get is counted. With sampling only about one in `sample_rate` gets is counted and the counts are scaled up when read, so they become estimates. The gap between samples is randomised so that periodic access patterns do not bias the estimate.
+///
+/// With a half life set, hits and misses decay exponentially, so the counts read reflect recent gets more than old ones.
+pub(crate) struct StatsRecorder {
+    hits: u64,
+    misses: u64,
+    inserts: u64,
+    updates: u64,
+    evictions: u64,
+    wasted_inserts: u64,
+    expirations: u64,
+    sample_rate: u64,
+    countdown: u64,
+    rng_state: u64,
+    decay: Option<Decay>,
+    callback_panics: Option<Arc<AtomicU64>>,
+    eviction_ages: EvictionAges,
+    identity: CacheIdentity,
+    // gets counted while holding only a shared lock, added to the counts by `fold_shared`
+    shared_hits: AtomicU64,
+    shared_misses: AtomicU64,
+}
+
+impl StatsRecorder {
+    pub(crate) fn new(identity: CacheIdentity) -> Self {
+        StatsRecorder {
+            hits: 0,
+            misses: 0,
+            inserts: 0,
+            updates: 0,
+            evictions: 0,
+            wasted_inserts: 0,
+            expirations: 0,
+            sample_rate: 1,
+            countdown: 1,
+            rng_state: 0x9e37_79b9_7f4a_7c15,
+            decay: None,
+            callback_panics: None,
+            eviction_ages: EvictionAges::default(),
+            identity,
+            shared_hits: AtomicU64::new(0),
+            shared_misses: AtomicU64::new(0),
+        }
+    }
+
+    /// Name the cache in the statistics read from now on.
+    pub(crate) fn set_name(&mut self, name: String) {
+        self.identity.set_name(name);
+    }
+
+    /// Report the panics counted by the cache's callbacks, see `Listeners::set_panics`.
+    pub(crate) fn set_callback_panics(&mut self, panicked: Arc<AtomicU64>) {
+        self.callback_panics = Some(panicked);
+    }
+
+    /// Count only about one in `sample_rate` gets, a rate of 1 counts every get.
+    pub(crate) fn set_sample_rate(&mut self, sample_rate: u64) {
+        self.sample_rate = sample_rate.max(1);
+        self.countdown = 1;
+    }
+
+    /// Make hits and misses decay, halving every `half_life`, starting from the current counts.
+    pub(crate) fn set_half_life(&mut self, half_life: Duration) {
+        self.decay = Some(Decay {
+            hits: self.hits() as f64,
+            misses: self.misses() as f64,
+            at: Instant::now(),
+            half_life: half_life.max(Duration::from_nanos(1)),
+        });
+    }
+
+    /// Check if this get should be counted, drawing the gap to the next sample if it is.
+    fn sampled(&mut self) -> bool {
+        if self.sample_rate == 1 {
+            return true;
+        }
+        self.countdown -= 1;
+        if self.countdown > 0 {
+            return false;
+        }
+        // xorshift, gaps are uniform over 1..2 * sample_rate so they average sample_rate
+        self.rng_state ^= self.rng_state << 13;
+        self.rng_state ^= self.rng_state >> 7;
+        self.rng_state ^= self.rng_state << 17;
+        self.countdown = 1 + self.rng_state % (2 * self.sample_rate - 1);
+        true
+    }
+
+    /// Count a hit from a get holding only a shared lock, it is added to the counts by the next `fold_shared`.
+    pub(crate) fn record_shared_hit(&self) {
+        self.shared_hits.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a miss from a get holding only a shared lock, it is added to the counts by the next `fold_shared`.
+    pub(crate) fn record_shared_miss(&self) {
+        self.shared_misses.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Add the hits and misses counted under a shared lock to the counts, so they are sampled and decayed like any other.
+    pub(crate) fn fold_shared(&mut self) {
+        for _ in 0..self.shared_hits.swap(0, Ordering::Relaxed) {
+            self.record_hit();
+        }
+        for _ in 0..self.shared_misses.swap(0, Ordering::Relaxed) {
+            self.record_miss();
+        }
+    }
+
+    pub(crate) fn record_hit(&mut self) {
+        if self.sampled() {
+            match &mut self.decay {
+                Some(decay) => {
+                    decay.advance(Instant::now());
+                    decay.hits += self.sample_rate as f64;
+                }
+                None => self.hits += 1,
+            }
+        }
+    }
+
+    pub(crate) fn record_miss(&mut self) {
+        if self.sampled() {
+            match &mut self.decay {
+                Some(decay) => {
+                    decay.advance(Instant::now());
+                    decay.misses += self.sample_rate as f64;
+                }
+                None => self.misses += 1,
+            }
+        }
+    }
+
+    /// Count a set which stored its value, as an insert if the key is new or an update if it overwrote the key.
+    pub(crate) fn record_set(&mut self, is_new: bool) {
+        if is_new {
+            self.inserts += 1;
+        } else {
+            self.updates += 1;
+        }
+    }
+
+    /// Count an item removed by the policy to make room, with how long it lived and whether it was never hit since it was set.
+    pub(crate) fn record_eviction(&mut self, lifetime: Option<Lifetime>) {
+        self.evictions += 1;
+        let Some(lifetime) = lifetime else {
+            return;
+        };
+        if lifetime.never_read {
+            self.wasted_inserts += 1;
+        }
+        self.eviction_ages.lived.record(lifetime.lived);
+        self.eviction_ages.idle.record(lifetime.idle);
+    }
+
+    /// Get how long evicted items had lived and gone untouched.
+    pub(crate) fn eviction_ages(&self) -> EvictionAges {
+        self.eviction_ages.clone()
+    }
+
+    /// Count an item removed because it passed its deadline.
+    pub(crate) fn record_expiration(&mut self) {
+        self.expirations += 1;
+    }
+
+    /// Build the cache statistics from the counts and the cache's current size and capacity.
+    pub(crate) fn snapshot(&self, size: u64, capacity: u64) -> CacheStats {
+        CacheStats {
+            hits: self.hits(),
+            misses: self.misses(),
+            inserts: self.inserts,
+            updates: self.updates,
+            evictions: self.evictions,
+            wasted_inserts: self.wasted_inserts,
+            expirations: self.expirations,
+            callback_panics: self
+                .callback_panics
+                .as_ref()
+                .map_or(0, |panicked| panicked.load(Ordering::Relaxed)),
+            size,
+            capacity,
+            cache: self.identity.clone(),
+        }
+    }
+
+    /// Get the number of hits, extrapolated if sampling and decayed to now if decaying.
+    pub(crate) fn hits(&self) -> u64 {
+        match &self.decay {
+            Some(decay) => (decay.hits * decay.factor(Instant::now())).round() as u64,
+            None => self.hits * self.sample_rate,
+        }
+    }
+
+    /// Get the number of misses, extrapolated if sampling and decayed to now if decaying.
+    pub(crate) fn misses(&self) -> u64 {
+        match &self.decay {
+            Some(decay) => (decay.misses * decay.factor(Instant::now())).round() as u64,
+            None => self.misses * self.sample_rate,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stats_recorder() {
+        let mut stats = StatsRecorder::new(CacheIdentity::default());
+        stats.record_hit();
+        stats.record_hit();
+        stats.record_miss();
+        assert_eq!(stats.hits(), 2);
+        assert_eq!(stats.misses(), 1);
+    }
+
+    #[test]
+    fn test_stats_recorder_sampling() {
+        let mut stats = StatsRecorder::new(CacheIdentity::default());
+        stats.set_sample_rate(16);
+        for i in 0..100_000 {
+            if i % 4 == 0 {
+                stats.record_miss();
+            } else {
+                stats.record_hit();
+            }
+        }
+        let hits = stats.hits() as f64;
+        let misses = stats.misses() as f64;
+        assert!((hits - 75_000.0).abs() < 7_500.0, "hits {}", hits);
+        assert!((misses - 25_000.0).abs() < 2_500.0, "misses {}", misses);
+    }
+
+    #[test]
+    fn test_stats_recorder_decay() {
+        let mut stats = StatsRecorder::new(CacheIdentity::default());
+        stats.record_hit();
+        stats.set_half_life(Duration::from_millis(50));
+        for _ in 0..99 {
+            stats.record_hit();
+        }
+        stats.record_miss();
+        assert!(stats.hits() > 90, "hits {}", stats.hits());
+        std::thread::sleep(Duration::from_millis(200));
+        // four half lives have passed, so about a sixteenth is left
+        assert!(stats.hits() <= 7, "hits {}", stats.hits());
+        assert_eq!(stats.misses(), 0);
+        stats.record_miss();
+        assert_eq!(stats.misses(), 1);
+    }
+}
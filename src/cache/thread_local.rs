@@ -0,0 +1,482 @@
+use crate::sync::atomic::{AtomicU64, Ordering};
+use std::any::Any;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use linked_hash_map::LinkedHashMap;
+
+use crate::cache::listener::EntrySource;
+use crate::cache::{Cache, CacheStats, DetailedStats, GetOutcome, IterationOrder, TryCache};
+use crate::clock::{saturating_add, Clock, SystemClock};
+use crate::error::CacheError;
+
+/// Source of the IDs which tell the front caches of different ThreadLocalCaches apart.
+///
+/// This is a process wide static, so it is always a standard library atomic, even under loom.
+static NEXT_ID: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+
+/// Count of dropped ThreadLocalCaches, so each thread knows when to look for front caches it can prune.
+///
+/// Like `NEXT_ID` this is process wide, so it is always a standard library atomic.
+static DROPPED: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+
+thread_local! {
+    /// The current thread's front caches, keyed by the ID of the ThreadLocalCache they belong to.
+    static FRONTS: RefCell<Fronts> = RefCell::new(Fronts::default());
+}
+
+/// A thread's front caches, each with the liveness flag of the ThreadLocalCache it belongs to.
+#[derive(Default)]
+struct Fronts {
+    caches: HashMap<u64, (Arc<std::sync::atomic::AtomicBool>, Box<dyn Any>)>,
+    dropped: u64,
+}
+
+impl Fronts {
+    /// Drop the front caches of ThreadLocalCaches which have been dropped since this thread last looked.
+    fn prune(&mut self) {
+        let dropped = DROPPED.load(std::sync::atomic::Ordering::Acquire);
+        if dropped != self.dropped {
+            self.dropped = dropped;
+            self.caches
+                .retain(|_, (alive, _)| alive.load(std::sync::atomic::Ordering::Acquire));
+        }
+    }
+}
+
+/// A small per-thread LRU of values read from the shared cache, each with the time it expires in the shared cache, if it does.
+struct Front<K, V> {
+    generation: u64,
+    items: LinkedHashMap<K, (Arc<V>, Option<Instant>)>,
+}
+
+/// ThreadLocalCache puts a small LRU in front of any cache on every thread which reads from it, so the hottest keys are served without touching the shared cache or its lock.
+///
+/// Gets which miss the front cache are read from the shared cache and kept in the reading thread's front cache. Every write through the wrapper empties all the front caches, so a get never returns a value older than the last write, which makes it a good fit for read mostly caches. Items stay in front caches when the shared cache evicts them, until the next write, but a front cache stops serving an item once its TTL in the shared cache has passed. Pass the shared cache's clock to `with_clock` if it does not use `Instant::now`. Hits answered by a front cache are added to the shared cache's hits in `stats`, they are not seen by the shared cache's access listeners or recency order.
+///
+/// Example:
+/// ```
+/// use arcache::{Cache, LRUCache, ThreadLocalCache};
+///
+/// let cache = ThreadLocalCache::new(LRUCache::<&str, String>::new(100), 8);
+///
+/// cache.set("key", "value".to_string());
+/// assert_eq!(*cache.get(&"key").unwrap(), "value".to_string());
+///
+/// // the second get is served by this thread's front cache
+/// assert_eq!(*cache.get(&"key").unwrap(), "value".to_string());
+/// assert_eq!(cache.front_hits(), 1);
+/// ```
+pub struct ThreadLocalCache<C> {
+    cache: C,
+    id: u64,
+    alive: Arc<std::sync::atomic::AtomicBool>,
+    front_capacity: usize,
+    generation: AtomicU64,
+    front_hits: AtomicU64,
+    clock: Arc<dyn Clock>,
+}
+
+impl<C> ThreadLocalCache<C> {
+    /// Create a new ThreadLocalCache wrapping the given cache, keeping up to `front_capacity` items per thread.
+    pub fn new(cache: C, front_capacity: usize) -> Self {
+        ThreadLocalCache {
+            cache,
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            alive: Arc::new(std::sync::atomic::AtomicBool::new(true)),
+            front_capacity,
+            generation: AtomicU64::new(0),
+            front_hits: AtomicU64::new(0),
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// Read the time for checking whether front copies have expired from `clock` instead of `Instant::now`, it should be the clock of the wrapped cache.
+    pub fn with_clock(mut self, clock: impl Clock + 'static) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// Get the number of gets answered by a front cache.
+    pub fn front_hits(&self) -> u64 {
+        self.front_hits.load(Ordering::Relaxed)
+    }
+
+    /// Get a reference to the wrapped cache.
+    pub fn inner(&self) -> &C {
+        &self.cache
+    }
+
+    /// Empty the front cache of every thread, called after each write to the shared cache.
+    fn invalidate(&self) {
+        self.generation.fetch_add(1, Ordering::Release);
+    }
+
+    /// Run `f` on this thread's front cache, emptying it first if there has been a write since it was filled.
+    fn with_front<K, V, R>(&self, generation: u64, f: impl FnOnce(&mut Front<K, V>) -> R) -> R
+    where
+        K: Eq + Hash + 'static,
+        V: 'static,
+    {
+        FRONTS.with(|fronts| {
+            let mut fronts = fronts.borrow_mut();
+            fronts.prune();
+            let (_, front) = fronts.caches.entry(self.id).or_insert_with(|| {
+                (
+                    self.alive.clone(),
+                    Box::new(Front::<K, V> {
+                        generation,
+                        items: LinkedHashMap::new(),
+                    }),
+                )
+            });
+            let front = front
+                .downcast_mut::<Front<K, V>>()
+                .expect("front caches are keyed by the ID of the cache which created them");
+            if front.generation != generation {
+                front.items.clear();
+                front.generation = generation;
+            }
+            f(front)
+        })
+    }
+
+    /// Look a key up in this thread's front cache, returning the generation it was read at and the value if it was there.
+    fn front_get<K, V>(&self, key: &K) -> (u64, Option<Arc<V>>)
+    where
+        K: Eq + Hash + 'static,
+        V: 'static,
+    {
+        let generation = self.generation.load(Ordering::Acquire);
+        let hit = self.with_front::<K, V, _>(generation, |front| {
+            let (value, deadline) = front.items.get_refresh(key)?;
+            if deadline.is_some_and(|deadline| deadline <= self.clock.now()) {
+                front.items.remove(key);
+                return None;
+            }
+            Some(value.clone())
+        });
+        if hit.is_some() {
+            self.front_hits.fetch_add(1, Ordering::Relaxed);
+        }
+        (generation, hit)
+    }
+
+    /// Keep a value read from the shared cache at `generation` in this thread's front cache, unless there has been a write since.
+    ///
+    /// `ttl_remaining` is asked for the time left before the item expires in the shared cache, so the copy is not served past it.
+    fn keep<K, V>(
+        &self,
+        generation: u64,
+        key: &K,
+        value: &Arc<V>,
+        ttl_remaining: impl FnOnce() -> Option<Duration>,
+    ) where
+        K: Eq + Hash + Clone + 'static,
+        V: 'static,
+    {
+        if self.front_capacity == 0 || self.generation.load(Ordering::Acquire) != generation {
+            return;
+        }
+        let deadline = ttl_remaining().map(|remaining| saturating_add(self.clock.now(), remaining));
+        self.with_front(generation, |front| {
+            front.items.insert(key.clone(), (value.clone(), deadline));
+            while front.items.len() > self.front_capacity {
+                front.items.pop_front();
+            }
+        });
+    }
+}
+
+impl<C> Drop for ThreadLocalCache<C> {
+    /// Drop the front cache of the dropping thread, front caches on other threads are pruned the next time their thread uses any ThreadLocalCache.
+    fn drop(&mut self) {
+        self.alive
+            .store(false, std::sync::atomic::Ordering::Release);
+        DROPPED.fetch_add(1, std::sync::atomic::Ordering::Release);
+        let _ = FRONTS.try_with(|fronts| {
+            if let Ok(mut fronts) = fronts.try_borrow_mut() {
+                fronts.caches.remove(&self.id);
+            }
+        });
+    }
+}
+
+impl<K, V, C> Cache<K, V> for ThreadLocalCache<C>
+where
+    K: Eq + Hash + Clone + Send + Sync + 'static,
+    V: Send + Sync + 'static,
+    C: Cache<K, V>,
+{
+    /// Get a value from this thread's front cache, or from the shared cache on a miss.
+    fn get(&self, key: &K) -> Option<Arc<V>> {
+        let (generation, hit) = self.front_get(key);
+        if hit.is_some() {
+            return hit;
+        }
+        let value = self.cache.get(key)?;
+        self.keep(generation, key, &value, || self.cache.ttl_remaining(key));
+        Some(value)
+    }
+
+    /// Get a value like `get`, with whether it was a hit or why the shared cache missed.
+    fn get_with_outcome(&self, key: &K) -> (Option<Arc<V>>, GetOutcome) {
+        let (generation, hit) = self.front_get(key);
+        if hit.is_some() {
+            return (hit, GetOutcome::Hit);
+        }
+        let (value, outcome) = self.cache.get_with_outcome(key);
+        if let Some(value) = &value {
+            self.keep(generation, key, value, || self.cache.ttl_remaining(key));
+        }
+        (value, outcome)
+    }
+
+    /// Set a value in the shared cache, emptying the front caches.
+    fn set(&self, key: K, value: V) -> Option<Arc<V>> {
+        self.set_with_source(key, value, EntrySource::Inserted)
+    }
+
+    /// Set a value like `set`, passing on how it came to be in the cache to the wrapped cache.
+    fn set_with_source(&self, key: K, value: V, source: EntrySource) -> Option<Arc<V>> {
+        let result = self.cache.set_with_source(key, value, source);
+        self.invalidate();
+        result
+    }
+
+    /// Remove a value from the shared cache, emptying the front caches.
+    fn remove(&self, key: &K) -> Option<Arc<V>> {
+        let result = self.cache.remove(key);
+        self.invalidate();
+        result
+    }
+
+    /// Check if a key is in the shared cache.
+    fn contains_key(&self, key: &K) -> bool {
+        self.cache.contains_key(key)
+    }
+
+    /// Get the time left before an item expires in the shared cache.
+    fn ttl_remaining(&self, key: &K) -> Option<Duration> {
+        self.cache.ttl_remaining(key)
+    }
+
+    /// Clear the shared cache, emptying the front caches.
+    fn clear(&self) {
+        self.cache.clear();
+        self.invalidate();
+    }
+
+    /// Clear the shared cache, emptying the front caches and returning every item which was removed.
+    fn clear_returning(&self) -> Vec<(K, Arc<V>)> {
+        let cleared = self.cache.clear_returning();
+        self.invalidate();
+        cleared
+    }
+
+    /// Copy the items of the shared cache into a HashMap.
+    fn to_map(&self) -> HashMap<K, Arc<V>> {
+        self.cache.to_map()
+    }
+
+    /// Get the hottest items of the shared cache.
+    fn hottest(&self, limit: usize) -> Vec<(K, Arc<V>)> {
+        self.cache.hottest(limit)
+    }
+
+    /// Get the items of the wrapped cache in the given order.
+    fn items(&self, order: IterationOrder) -> Option<Vec<(K, Arc<V>)>> {
+        self.cache.items(order)
+    }
+
+    /// Get the name of the shared cache's eviction policy.
+    fn policy_name(&self) -> &'static str {
+        self.cache.policy_name()
+    }
+
+    /// Get the cache as `Any`, so it can be downcast from a `dyn Cache`.
+    fn as_any(&self) -> Option<&dyn Any>
+    where
+        Self: 'static,
+    {
+        Some(self)
+    }
+
+    /// Get the statistics of the shared cache, with the gets answered by front caches added to the hits.
+    fn stats(&self) -> CacheStats {
+        let mut stats = self.cache.stats();
+        stats.hits += self.front_hits();
+        stats
+    }
+
+    /// Get the detailed statistics of the shared cache, with the gets answered by front caches added to the hits.
+    fn detailed_stats(&self) -> DetailedStats {
+        let mut detailed = self.cache.detailed_stats();
+        detailed.stats.hits += self.front_hits();
+        detailed
+    }
+
+    /// Change the capacity of the shared cache, emptying the front caches.
+    fn change_capacity(&self, capacity: u64) {
+        self.cache.change_capacity(capacity);
+        self.invalidate();
+    }
+
+    /// Change the capacity of the shared cache, emptying the front caches and returning the items which were removed.
+    fn change_capacity_collect(&self, capacity: u64) -> Vec<(K, Arc<V>)> {
+        let evicted = self.cache.change_capacity_collect(capacity);
+        self.invalidate();
+        evicted
+    }
+
+    /// Shed `fraction` of the items of the shared cache and invalidate every thread's front cache.
+    fn shed(&self, fraction: f64) -> usize {
+        let shed = self.cache.shed(fraction);
+        self.invalidate();
+        shed
+    }
+}
+
+impl<K, V, C> TryCache<K, V> for ThreadLocalCache<C>
+where
+    K: Eq + Hash + Clone + Send + Sync + 'static,
+    V: Send + Sync + 'static,
+    C: TryCache<K, V>,
+{
+    /// Get a value from this thread's front cache, or from the shared cache on a miss.
+    fn try_get(&self, key: &K) -> Result<Option<Arc<V>>, CacheError> {
+        let (generation, hit) = self.front_get(key);
+        if hit.is_some() {
+            return Ok(hit);
+        }
+        let value = self.cache.try_get(key)?;
+        if let Some(value) = &value {
+            self.keep(generation, key, value, || self.cache.ttl_remaining(key));
+        }
+        Ok(value)
+    }
+
+    /// Set a value in the shared cache, emptying the front caches.
+    fn try_set(&self, key: K, value: V) -> Result<Option<Arc<V>>, CacheError> {
+        let result = self.cache.try_set(key, value);
+        self.invalidate();
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::LRUCache;
+
+    #[test]
+    fn test_thread_local_cache() {
+        let cache = ThreadLocalCache::new(LRUCache::new(10), 2);
+        cache.set(1, 1);
+        cache.set(2, 2);
+        cache.set(3, 3);
+        for key in [1, 2, 1, 2, 3] {
+            assert_eq!(cache.get(&key).map(|v| *v), Some(key));
+        }
+        // 1 and 2 were read from the shared cache once each, then hit the front cache
+        assert_eq!(cache.front_hits(), 2);
+        assert_eq!(cache.inner().stats().hits, 3);
+        assert_eq!(cache.stats().hits, 5);
+        // 3 pushed 1 out of the front cache
+        cache.get(&1);
+        assert_eq!(cache.front_hits(), 2);
+    }
+
+    #[test]
+    fn test_thread_local_cache_writes_empty_every_front() {
+        let cache = Arc::new(ThreadLocalCache::new(LRUCache::new(10), 4));
+        cache.set(1, 1);
+        assert_eq!(cache.get(&1).map(|v| *v), Some(1));
+        let other = cache.clone();
+        std::thread::spawn(move || {
+            assert_eq!(other.get(&1).map(|v| *v), Some(1));
+            other.set(1, 2);
+        })
+        .join()
+        .unwrap();
+        assert_eq!(cache.get(&1).map(|v| *v), Some(2));
+        cache.remove(&1);
+        assert_eq!(cache.get(&1), None);
+    }
+
+    #[test]
+    fn test_thread_local_cache_drop_prunes_other_threads() {
+        use std::sync::mpsc::channel;
+
+        let cache = Arc::new(ThreadLocalCache::new(LRUCache::new(10), 4));
+        cache.set(1, 1);
+        let value = cache.get(&1).unwrap();
+        let (to_worker, from_main) = channel();
+        let (to_main, from_worker) = channel();
+        let worker = {
+            let cache = cache.clone();
+            std::thread::spawn(move || {
+                cache.get(&1);
+                drop(cache);
+                to_main.send(()).unwrap();
+                from_main.recv().unwrap();
+                // using any other ThreadLocalCache prunes the front of the dropped one
+                let other = ThreadLocalCache::new(LRUCache::<u32, u32>::new(1), 1);
+                other.get(&1);
+                to_main.send(()).unwrap();
+                from_main.recv().unwrap();
+            })
+        };
+        from_worker.recv().unwrap();
+        drop(cache);
+        // only the worker's front cache still holds the value
+        assert_eq!(Arc::strong_count(&value), 2);
+        to_worker.send(()).unwrap();
+        from_worker.recv().unwrap();
+        assert_eq!(Arc::strong_count(&value), 1);
+        to_worker.send(()).unwrap();
+        worker.join().unwrap();
+    }
+
+    #[test]
+    fn test_thread_local_cache_front_copies_expire() {
+        use crate::TTLCache;
+        use std::sync::Mutex;
+
+        struct ManualClock(Mutex<Instant>);
+
+        impl Clock for Arc<ManualClock> {
+            fn now(&self) -> Instant {
+                *self.0.lock().unwrap()
+            }
+        }
+
+        let clock = Arc::new(ManualClock(Mutex::new(Instant::now())));
+        let shared = TTLCache::new(Duration::from_secs(10), 10).with_clock(clock.clone());
+        let cache = ThreadLocalCache::new(shared, 4).with_clock(clock.clone());
+        cache.set(1, 1);
+        assert_eq!(cache.get(&1).map(|v| *v), Some(1));
+        assert_eq!(cache.get(&1).map(|v| *v), Some(1));
+        assert_eq!(cache.front_hits(), 1);
+
+        *clock.0.lock().unwrap() += Duration::from_secs(11);
+        assert_eq!(cache.get(&1), None);
+        assert_eq!(cache.front_hits(), 1);
+
+        // an LRU with a default TTL expires its items the same way
+        let shared = LRUCache::new(10)
+            .with_ttl(Duration::from_secs(10))
+            .with_clock(clock.clone());
+        let cache = ThreadLocalCache::new(shared, 4).with_clock(clock.clone());
+        cache.set(1, 1);
+        assert_eq!(cache.get(&1).map(|v| *v), Some(1));
+        *clock.0.lock().unwrap() += Duration::from_secs(5);
+        assert_eq!(cache.get(&1).map(|v| *v), Some(1));
+        assert_eq!(cache.front_hits(), 1);
+        *clock.0.lock().unwrap() += Duration::from_secs(5);
+        assert_eq!(cache.get(&1), None);
+    }
+}
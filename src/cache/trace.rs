@@ -0,0 +1,193 @@
+use crate::sync::Mutex;
+use std::collections::hash_map::RandomState;
+use std::collections::VecDeque;
+use std::fmt::Display;
+use std::hash::{BuildHasher, Hash};
+use std::io::{self, Write};
+use std::time::Instant;
+
+/// The version of the access log format written by `export_access_log`.
+///
+/// An access log is CSV. The first line is a header, `#arcache-access-log` followed by this version and the number of events dropped because the buffer was full, then a line naming the columns, `elapsed_micros,operation,key_hash,context`, and one line per event, oldest first:
+///
+/// + `elapsed_micros` is the time of the event in microseconds since the trace started.
+/// + `operation` is one of `hit`, `miss`, `set` or `remove`.
+/// + `key_hash` is the key hashed with a seed chosen when the cache is built, as 16 hex digits, so keys never leave the host but repeated accesses to one key can still be matched within a trace.
+/// + `context` is the context passed to `get_traced`, such as a request ID, or empty. It is quoted if it contains a comma, a quote or a line break.
+pub const ACCESS_LOG_VERSION: u16 = 2;
+
+/// The start of the header line of an access log, followed by its format version.
+const ACCESS_LOG_HEADER: &str = "#arcache-access-log";
+
+/// TraceOperation is the kind of access recorded in a trace.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum TraceOperation {
+    Hit,
+    Miss,
+    Set,
+    Remove,
+}
+
+impl TraceOperation {
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            TraceOperation::Hit => "hit",
+            TraceOperation::Miss => "miss",
+            TraceOperation::Set => "set",
+            TraceOperation::Remove => "remove",
+        }
+    }
+}
+
+struct TraceEvent {
+    elapsed_micros: u64,
+    operation: TraceOperation,
+    key_hash: u64,
+    context: Option<Box<str>>,
+}
+
+/// TraceBuffer keeps the latest events, dropping the oldest once it is full.
+struct TraceBuffer {
+    events: VecDeque<TraceEvent>,
+    capacity: usize,
+    dropped: u64,
+}
+
+struct Trace {
+    buffer: Mutex<TraceBuffer>,
+    seed: RandomState,
+    started: Instant,
+}
+
+/// AccessTrace records the accesses of a cache when enabled, and costs a single branch when it is not.
+///
+/// Events are kept under their own lock, so an export never blocks the cache.
+pub(crate) struct AccessTrace {
+    trace: Option<Box<Trace>>,
+}
+
+impl AccessTrace {
+    pub(crate) fn new() -> Self {
+        AccessTrace { trace: None }
+    }
+
+    /// Start recording, keeping at most `capacity` events between exports.
+    pub(crate) fn enable(&mut self, capacity: usize) {
+        self.trace = Some(Box::new(Trace {
+            buffer: Mutex::new(TraceBuffer {
+                events: VecDeque::with_capacity(capacity.min(4096)),
+                capacity,
+                dropped: 0,
+            }),
+            seed: RandomState::new(),
+            started: Instant::now(),
+        }));
+    }
+
+    /// Record an access, with the context of the request which made it if there is one.
+    pub(crate) fn record<K: Hash>(
+        &self,
+        key: &K,
+        operation: TraceOperation,
+        context: Option<&dyn Display>,
+    ) {
+        let Some(trace) = &self.trace else {
+            return;
+        };
+        let event = TraceEvent {
+            elapsed_micros: trace.started.elapsed().as_micros().min(u64::MAX as u128) as u64,
+            operation,
+            key_hash: trace.seed.hash_one(key),
+            context: context.map(|context| context.to_string().into_boxed_str()),
+        };
+        let mut buffer = trace.buffer.lock().unwrap();
+        if buffer.capacity == 0 {
+            buffer.dropped += 1;
+            return;
+        }
+        if buffer.events.len() >= buffer.capacity {
+            buffer.events.pop_front();
+            buffer.dropped += 1;
+        }
+        buffer.events.push_back(event);
+    }
+
+    /// Write the buffered events as an access log and empty the buffer, returning the number of events written.
+    ///
+    /// Nothing is written if recording is disabled. The buffer is emptied before writing, so events are not exported twice even if the writer fails.
+    pub(crate) fn export<W: Write>(&self, mut writer: W) -> io::Result<usize> {
+        let Some(trace) = &self.trace else {
+            return Ok(0);
+        };
+        let (events, dropped) = {
+            let mut buffer = trace.buffer.lock().unwrap();
+            let dropped = std::mem::take(&mut buffer.dropped);
+            (std::mem::take(&mut buffer.events), dropped)
+        };
+        writeln!(
+            writer,
+            "{} {} {}",
+            ACCESS_LOG_HEADER, ACCESS_LOG_VERSION, dropped
+        )?;
+        writeln!(writer, "elapsed_micros,operation,key_hash,context")?;
+        for event in &events {
+            writeln!(
+                writer,
+                "{},{},{:016x},{}",
+                event.elapsed_micros,
+                event.operation.name(),
+                event.key_hash,
+                csv_field(event.context.as_deref().unwrap_or(""))
+            )?;
+        }
+        writer.flush()?;
+        Ok(events.len())
+    }
+}
+
+/// Quote a CSV field if it contains a separator, a quote or a line break.
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_access_trace_export() {
+        let mut trace = AccessTrace::new();
+        trace.record(&1, TraceOperation::Set, None);
+        let mut out = Vec::new();
+        assert_eq!(trace.export(&mut out).unwrap(), 0);
+        assert!(out.is_empty());
+
+        trace.enable(2);
+        trace.record(&1, TraceOperation::Set, None);
+        trace.record(&1, TraceOperation::Hit, None);
+        trace.record(&2, TraceOperation::Miss, Some(&"req, \"7\""));
+        assert_eq!(trace.export(&mut out).unwrap(), 2);
+        let log = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = log.lines().collect();
+        assert_eq!(
+            lines[0],
+            format!("#arcache-access-log {} 1", ACCESS_LOG_VERSION)
+        );
+        assert_eq!(lines[1], "elapsed_micros,operation,key_hash,context");
+        assert!(lines[2].ends_with(','));
+        assert!(lines[3].ends_with(",\"req, \"\"7\"\"\""));
+        let fields: Vec<Vec<&str>> = lines[2..].iter().map(|l| l.split(',').collect()).collect();
+        assert_eq!(fields[0][1], "hit");
+        assert_eq!(fields[1][1], "miss");
+        assert_eq!(fields[0][2].len(), 16);
+        assert_ne!(fields[0][2], fields[1][2]);
+
+        let mut out = Vec::new();
+        assert_eq!(trace.export(&mut out).unwrap(), 0);
+        assert_eq!(String::from_utf8(out).unwrap().lines().count(), 2);
+    }
+}
@@ -1,24 +1,47 @@
+use crate::sync::Mutex;
 use linked_hash_map::LinkedHashMap;
+use std::any::Any;
+use std::collections::HashMap;
 use std::hash::Hash;
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
 use std::time::{Duration, Instant};
 
-use crate::cache::{Cache, CacheStats};
+use crate::background::BackgroundTask;
+use crate::cache::latency::{LatencyTracker, OperationLatencies};
+use crate::cache::listener::{
+    AccessListener, AccessListeners, CallbackPanics, EntrySource, EvictionListener, RemovalCause,
+};
+use crate::cache::metadata::{EntryInfo, EntryMetadata};
+use crate::cache::policy_core::{policy_builders, PolicyCore};
+use crate::cache::{
+    shed_count, split_capacity, Cache, CacheStats, DetailedStats, ExpiryDistribution, FullBehavior,
+    GetOutcome, IterationOrder, TryCache, VersionedCache,
+};
+use crate::clock::{saturating_add, Clock};
+use crate::error::CacheError;
 
 /// An internal struct of the TTL cache for storing data along with its expiry time.
 #[derive(Clone)]
 struct DataWithLifetime<V> {
     data: Arc<V>,
     expiry: Instant,
+    ttl: Option<Duration>,
+    // the deadline given by `extend_ttl`, which a get does not bring forward
+    extended_until: Option<Instant>,
 }
 
+// ttl provider type, computes the TTL of each item as it is set
+type TtlProvider<K, V> = Arc<dyn Fn(&K, &V) -> Duration + Send + Sync>;
+
 /// The inner data structure for the TTLCache.
 struct TTLCacheInner<K, V> {
     ttl: Duration,
+    ttl_provider: Option<TtlProvider<K, V>>,
+    stale_grace: Duration,
     capacity: u64,
     key_value_map: LinkedHashMap<K, DataWithLifetime<V>>,
-    hits: u64,
-    misses: u64,
+    // deadlines are kept in the entries, the core's expiries stay empty
+    core: PolicyCore<K, V>,
 }
 
 /// TTLCache is a cache that uses adds a time-to-live (TTL) to each item.
@@ -50,6 +73,8 @@ struct TTLCacheInner<K, V> {
 /// ```
 pub struct TTLCache<K: Eq + Hash + Clone + Send + 'static, V: Send + Sync + 'static> {
     inner: Arc<Mutex<TTLCacheInner<K, V>>>,
+    access_listeners: Arc<AccessListeners<K>>,
+    latency: Arc<LatencyTracker>,
 }
 
 impl<K: Eq + Hash + Clone + Send + 'static, V: Send + Sync + 'static> TTLCache<K, V> {
@@ -59,140 +84,862 @@ impl<K: Eq + Hash + Clone + Send + 'static, V: Send + Sync + 'static> TTLCache<K
     pub fn new(ttl: Duration, capacity: u64) -> Self {
         let inner = Arc::new(Mutex::new(TTLCacheInner {
             ttl,
+            ttl_provider: None,
+            stale_grace: Duration::ZERO,
             capacity,
             key_value_map: LinkedHashMap::new(),
-            hits: 0,
-            misses: 0,
+            core: PolicyCore::new(),
         }));
 
-        TTLCache { inner }
+        TTLCache {
+            inner,
+            access_listeners: Arc::new(AccessListeners::new()),
+            latency: Arc::new(LatencyTracker::new()),
+        }
+    }
+
+    policy_builders!(lock);
+
+    /// Compute the TTL of each item from its key and value when it is set, for example from a response's cache-control header.
+    ///
+    /// This replaces the cache-wide TTL for items set with `set`, items set with `set_with_ttl` still use their explicit TTL.
+    pub fn with_ttl_provider(
+        self,
+        provider: impl Fn(&K, &V) -> Duration + Send + Sync + 'static,
+    ) -> Self {
+        self.inner.lock().unwrap().ttl_provider = Some(Arc::new(provider));
+        self
     }
 
-    /// Enforce the capacity of the cache by removing the least recently accessed item if the cache is at capacity.
-    fn enforce_capacity(inner: &mut TTLCacheInner<K, V>) {
-        if inner.key_value_map.len() as u64 >= inner.capacity {
-            if let Some(key) = inner.key_value_map.keys().next().cloned() {
-                inner.key_value_map.remove(&key);
+    /// Keep expired items for a further `grace` period, during which `get_allow_stale` still returns them.
+    ///
+    /// Other methods, including `get`, treat items past their TTL as missing. Stale items still take up capacity until the grace period ends or they are evicted.
+    pub fn with_stale_grace(self, grace: Duration) -> Self {
+        self.inner.lock().unwrap().stale_grace = grace;
+        self
+    }
+
+    /// Start a background thread which removes expired items every `interval`, rather than waiting for them to be accessed or pushed out.
+    ///
+    /// The thread runs until the returned handle is dropped or shut down, or the cache is dropped.
+    pub fn start_reaper(&self, interval: Duration) -> BackgroundTask {
+        let inner = Arc::downgrade(&self.inner);
+        BackgroundTask::spawn_periodic("arcache-ttl-reaper", interval, move || {
+            match inner.upgrade() {
+                Some(inner) => {
+                    Self::remove_expired(&mut inner.lock().unwrap(), true);
+                    true
+                }
+                None => false,
             }
+        })
+    }
+
+    /// Change the time-to-live of the cache.
+    ///
+    /// Existing items keep their current deadline, the new TTL is applied the next time they are accessed or set. Items set with `set_with_ttl` or given a TTL by a provider keep their own TTL.
+    pub fn set_ttl(&self, ttl: Duration) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.ttl = ttl;
+    }
+
+    /// Get the time-to-live of the cache.
+    pub fn ttl(&self) -> Duration {
+        let inner = self.inner.lock().unwrap();
+        inner.ttl
+    }
+
+    /// Set a value in the cache with a priority, when the cache is full lower priority items are evicted before higher priority ones.
+    ///
+    /// Within a priority the least recently accessed item is evicted first. New items set with `set` have the lowest priority, `DEFAULT_PRIORITY`, and setting a key again without a priority keeps the one it has. Priorities do not affect expiry.
+    pub fn set_with_priority(&self, key: K, value: V, priority: u32) -> Option<Arc<V>> {
+        self.insert(
+            key,
+            Arc::new(value),
+            Some(priority),
+            None,
+            None,
+            None,
+            EntrySource::Inserted,
+        )
+        .unwrap_or(None)
+    }
+
+    /// Set a value in the cache with its own time-to-live, overriding the cache-wide TTL for this item.
+    pub fn set_with_ttl(&self, key: K, value: V, ttl: Duration) -> Option<Arc<V>> {
+        self.insert(
+            key,
+            Arc::new(value),
+            None,
+            Some(ttl),
+            None,
+            None,
+            EntrySource::Inserted,
+        )
+        .unwrap_or(None)
+    }
+
+    /// Set a value in the cache with a metadata value attached, which can be read back with `entry_info`.
+    ///
+    /// Metadata is dropped when the item leaves the cache or is set again without metadata.
+    pub fn set_with_metadata(
+        &self,
+        key: K,
+        value: V,
+        metadata: impl Any + Send + Sync,
+    ) -> Option<Arc<V>> {
+        self.insert(
+            key,
+            Arc::new(value),
+            None,
+            None,
+            Some(Arc::new(metadata)),
+            None,
+            EntrySource::Inserted,
+        )
+        .unwrap_or(None)
+    }
+
+    /// Get a value from the cache, also returning items which have expired but are within the stale grace period.
+    ///
+    /// Stale items are returned as they are, their deadline is not extended.
+    pub fn get_allow_stale(&self, key: &K) -> Option<Arc<V>> {
+        self.lookup(key, true)
+    }
+
+    /// Get the priority, remaining TTL and metadata of an item, without counting a hit or miss or updating its position.
+    pub fn entry_info(&self, key: &K) -> Option<EntryInfo> {
+        let inner = self.inner.lock().unwrap();
+        let now = inner.core.clock.now();
+        let entry = inner
+            .key_value_map
+            .get(key)
+            .filter(|entry| entry.expiry > now)?;
+        Some(EntryInfo::new(
+            inner.core.priorities.get(key),
+            Some(entry.expiry.saturating_duration_since(now)),
+            inner.core.metadata.get(key),
+            inner.core.listeners.source(key),
+        ))
+    }
+
+    /// Get the keys which will expire within `window`, soonest first, so they can be refreshed before they lapse.
+    ///
+    /// Items which have already expired are not included.
+    pub fn expiring_within(&self, window: Duration) -> Vec<K> {
+        let inner = self.inner.lock().unwrap();
+        let now = inner.core.clock.now();
+        let horizon = saturating_add(now, window);
+        let mut expiring: Vec<(Instant, K)> = inner
+            .key_value_map
+            .iter()
+            .filter(|(_, entry)| entry.expiry > now && entry.expiry <= horizon)
+            .map(|(key, entry)| (entry.expiry, key.clone()))
+            .collect();
+        expiring.sort_by_key(|(expiry, _)| *expiry);
+        expiring.into_iter().map(|(_, key)| key).collect()
+    }
+
+    /// Push back the deadline of a live item by `extra` without setting it again, returning false if the item is not in the cache.
+    ///
+    /// This keeps items alive past their TTL while a long running job still needs them, gets refresh the deadline as usual but never bring it forward.
+    pub fn extend_ttl(&self, key: &K, extra: Duration) -> bool {
+        let mut inner = self.inner.lock().unwrap();
+        let now = inner.core.clock.now();
+        match inner.key_value_map.get_mut(key) {
+            Some(entry) if entry.expiry > now => {
+                entry.expiry = saturating_add(entry.expiry, extra);
+                entry.extended_until = Some(entry.expiry);
+                true
+            }
+            _ => false,
         }
     }
 
-    fn evict(inner: &mut TTLCacheInner<K, V>) {
-        let now = Instant::now();
-        while let Some((_, entry)) = inner.key_value_map.front() {
-            if entry.expiry < now {
-                inner.key_value_map.pop_front();
-            } else {
-                break;
+    /// Get how long an item has gone without being set or hit by a get, without counting a hit or miss or updating its position.
+    pub fn idle_time(&self, key: &K) -> Option<Duration> {
+        let inner = self.inner.lock().unwrap();
+        let now = inner.core.clock.now();
+        inner
+            .key_value_map
+            .get(key)
+            .filter(|entry| entry.expiry > now)?;
+        inner.core.metadata.idle_time(key, now)
+    }
+
+    /// Remove every item which has not been set or hit by a get for at least `older_than`, returning how many were removed.
+    ///
+    /// Removed items are counted as expirations and listeners are notified with `RemovalCause::Expired`, so caches without a TTL can still be cleaned up periodically.
+    pub fn purge_idle(&self, older_than: Duration) -> usize {
+        let mut inner = self.inner.lock().unwrap();
+        let idle = inner.core.metadata.idle(inner.core.clock.now(), older_than);
+        let mut purged = 0;
+        for key in idle {
+            inner.core.forget(&key);
+            if let Some(value) = inner.key_value_map.remove(&key).map(|entry| entry.data) {
+                inner
+                    .core
+                    .listeners
+                    .notify(&key, &value, RemovalCause::Expired);
+                inner.core.stats.record_expiration();
+                purged += 1;
             }
         }
+        purged
     }
-}
 
-impl<K: Eq + Hash + Clone + Send + Sync + 'static, V: Send + Sync + 'static> Cache<K, V>
-    for TTLCache<K, V>
-{
-    /// Get a value from the cache.
-    fn get(&self, key: &K) -> Option<Arc<V>> {
-        let now = Instant::now();
-        let (result, expired) = {
+    /// Move every item matching `predicate` into a new cache with the same TTL, whose capacity is in proportion to the share of items moved.
+    ///
+    /// Moved items keep their priority, deadline and recency. Listeners on this cache are notified with `RemovalCause::Removed`, they are not carried over to the new cache.
+    pub fn split_off<F: Fn(&K, &V) -> bool>(&self, predicate: F) -> Self {
+        let mut inner = self.inner.lock().unwrap();
+        Self::remove_expired(&mut inner, true);
+        let keys: Vec<K> = inner
+            .key_value_map
+            .iter()
+            .filter(|(key, entry)| predicate(key, &entry.data))
+            .map(|(key, _)| key.clone())
+            .collect();
+        let capacity = split_capacity(inner.capacity, keys.len(), inner.key_value_map.len());
+        let split = Self::new(inner.ttl, capacity);
+        split.inner.lock().unwrap().core.clock = inner.core.clock.clone();
+        split.inner.lock().unwrap().ttl_provider = inner.ttl_provider.clone();
+        {
+            let mut split_inner = split.inner.lock().unwrap();
+            for key in keys {
+                let priority = inner.core.priorities.get(&key);
+                let metadata = inner.core.metadata.get(&key);
+                let source = inner.core.listeners.source(&key);
+                inner.core.forget(&key);
+                if let Some(entry) = inner.key_value_map.remove(&key) {
+                    inner
+                        .core
+                        .listeners
+                        .notify(&key, &entry.data, RemovalCause::Removed);
+                    split_inner.core.priorities.set(&key, priority);
+                    let now = split_inner.core.clock.now();
+                    split_inner.core.metadata.set(&key, metadata, now);
+                    split_inner.core.listeners.set_source(&key, source);
+                    split_inner.key_value_map.insert(key, entry);
+                }
+            }
+        }
+        split
+    }
+
+    /// Look up a value, refreshing its deadline if it is live, and returning it within its stale grace period if `allow_stale` is set.
+    fn lookup(&self, key: &K, allow_stale: bool) -> Option<Arc<V>> {
+        let started = self.latency.start();
+        let (result, expired, now) = {
             let mut inner = self.inner.lock().unwrap();
-            let ttl = inner.ttl;
+            let now = inner.core.clock.now();
+            let default_ttl = inner.ttl;
+            let grace = inner.stale_grace;
             if let Some(entry) = inner.key_value_map.get_refresh(key) {
                 if entry.expiry > now {
-                    entry.expiry = now + ttl;
-                    (Some(entry.data.clone()), false)
+                    entry.expiry = saturating_add(now, entry.ttl.unwrap_or(default_ttl))
+                        .max(entry.extended_until.unwrap_or(now));
+                    (Some(entry.data.clone()), false, now)
+                } else if saturating_add(entry.expiry, grace) > now {
+                    // stale items are kept until their grace period ends
+                    (allow_stale.then(|| entry.data.clone()), false, now)
                 } else {
-                    (None, true)
+                    (None, true, now)
                 }
             } else {
-                (None, false)
+                (None, false, now)
             }
         };
 
         // Update stats in a separate lock block
         let mut inner = self.inner.lock().unwrap();
         if result.is_some() {
-            inner.hits += 1;
+            inner.core.stats.record_hit();
+            inner.core.metadata.touch(key, now);
         } else {
-            inner.misses += 1;
+            inner.core.stats.record_miss();
             if expired {
-                inner.key_value_map.remove(key);
+                inner.core.forget(key);
+                if let Some(entry) = inner.key_value_map.remove(key) {
+                    inner
+                        .core
+                        .listeners
+                        .notify(key, &entry.data, RemovalCause::Expired);
+                    inner.core.stats.record_expiration();
+                }
             }
         }
+        drop(inner);
+        self.latency.record_get(started);
+        self.access_listeners.notify(key, &result);
         result
     }
 
-    /// Set a value in the cache.
-    fn set(&self, key: K, value: V) -> Option<Arc<V>> {
-        let mut inner = self.inner.lock().unwrap();
-        if !inner.key_value_map.contains_key(&key) {
-            Self::enforce_capacity(&mut inner);
+    /// Insert a value with a priority and an optional TTL, evicting an item if the cache is full.
+    #[allow(clippy::too_many_arguments)]
+    fn insert(
+        &self,
+        key: K,
+        value: Arc<V>,
+        priority: Option<u32>,
+        ttl: Option<Duration>,
+        metadata: Option<EntryMetadata>,
+        expected_version: Option<u64>,
+        source: EntrySource,
+    ) -> Result<Option<Arc<V>>, CacheError> {
+        let mut inner = self.inner.lock().map_err(|_| CacheError::Poisoned)?;
+        if inner.capacity == 0 {
+            return Err(CacheError::CapacityZero);
         }
-        let expiry = Instant::now() + inner.ttl;
-
+        // expired items make room before any live item is evicted
         Self::evict(&mut inner);
+        if let Some(expected) = expected_version {
+            // an expired item counts as not in the cache
+            let now = inner.core.clock.now();
+            let actual = match inner.key_value_map.get(&key) {
+                Some(entry) if entry.expiry > now => inner.core.metadata.version(&key).unwrap_or(0),
+                _ => 0,
+            };
+            if actual != expected {
+                return Err(CacheError::VersionMismatch(actual));
+            }
+        }
+        // overwriting a key never evicts another item
+        let is_new = !inner.key_value_map.contains_key(&key);
+        if is_new && inner.key_value_map.len() as u64 >= inner.capacity {
+            Self::remove_expired(&mut inner, false);
+        }
+        if is_new && inner.key_value_map.len() as u64 >= inner.capacity {
+            if !inner.core.full_behavior.evicts() {
+                inner.core.listeners.reject(&key);
+                return inner.core.full_behavior.rejection();
+            }
+            Self::evict_lowest(&mut inner);
+        }
+        let ttl = ttl.or_else(|| {
+            inner
+                .ttl_provider
+                .as_ref()
+                .map(|provider| provider(&key, &value))
+        });
+        let now = inner.core.clock.now();
+        let expiry = saturating_add(now, ttl.unwrap_or(inner.ttl));
 
-        inner
+        inner.core.stats.record_set(is_new);
+        inner.core.priorities.assign(&key, priority);
+        inner.core.metadata.set(&key, metadata, now);
+        inner.core.listeners.set_source(&key, source);
+        let previous = inner
             .key_value_map
             .insert(
                 key,
                 DataWithLifetime {
-                    data: Arc::new(value),
+                    data: value,
                     expiry,
+                    ttl,
+                    extended_until: None,
                 },
             )
-            .map(|entry| entry.data)
+            .map(|entry| entry.data);
+        Ok(previous)
+    }
+
+    /// Remove the least recently accessed item with the lowest priority.
+    fn evict_lowest(inner: &mut TTLCacheInner<K, V>) -> Option<(K, Arc<V>)> {
+        let len = inner.key_value_map.len();
+        let key = inner
+            .core
+            .priorities
+            .pick(len, inner.key_value_map.keys())
+            .or_else(|| inner.key_value_map.keys().next().cloned())?;
+        let lifetime = inner.core.metadata.lifetime(&key, inner.core.clock.now());
+        inner.core.forget(&key);
+        let entry = inner.key_value_map.remove(&key)?;
+        inner
+            .core
+            .listeners
+            .notify(&key, &entry.data, RemovalCause::Evicted);
+        inner.core.stats.record_eviction(lifetime);
+        Some((key, entry.data))
+    }
+
+    /// Evict the least recently accessed items with the lowest priority until at most `len` are left.
+    fn evict_to(inner: &mut TTLCacheInner<K, V>, len: usize) -> Vec<(K, Arc<V>)> {
+        let mut evicted = Vec::new();
+        while inner.key_value_map.len() > len {
+            match Self::evict_lowest(inner) {
+                Some(item) => evicted.push(item),
+                None => break,
+            }
+        }
+        evicted
+    }
+
+    fn evict(inner: &mut TTLCacheInner<K, V>) {
+        let now = inner.core.clock.now();
+        let grace = inner.stale_grace;
+        while let Some((_, entry)) = inner.key_value_map.front() {
+            if saturating_add(entry.expiry, grace) < now {
+                if let Some((key, entry)) = inner.key_value_map.pop_front() {
+                    inner.core.forget(&key);
+                    inner
+                        .core
+                        .listeners
+                        .notify(&key, &entry.data, RemovalCause::Expired);
+                    inner.core.stats.record_expiration();
+                }
+            } else {
+                break;
+            }
+        }
+    }
+
+    /// Remove every expired item, unlike `evict` this checks every item rather than stopping at the first live one.
+    ///
+    /// With `keep_stale` items within their stale grace period are kept.
+    fn remove_expired(inner: &mut TTLCacheInner<K, V>, keep_stale: bool) {
+        let now = inner.core.clock.now();
+        let grace = if keep_stale {
+            inner.stale_grace
+        } else {
+            Duration::ZERO
+        };
+        let expired: Vec<K> = inner
+            .key_value_map
+            .iter()
+            .filter(|(_, entry)| saturating_add(entry.expiry, grace) <= now)
+            .map(|(key, _)| key.clone())
+            .collect();
+        for key in expired {
+            inner.core.forget(&key);
+            if let Some(entry) = inner.key_value_map.remove(&key) {
+                inner
+                    .core
+                    .listeners
+                    .notify(&key, &entry.data, RemovalCause::Expired);
+                inner.core.stats.record_expiration();
+            }
+        }
+    }
+
+    /// Remove every item, notifying listeners and returning the removed items.
+    fn clear_entries(inner: &mut TTLCacheInner<K, V>) -> Vec<(K, Arc<V>)> {
+        inner.core.clear();
+        let mut cleared = Vec::with_capacity(inner.key_value_map.len());
+        while let Some((key, entry)) = inner.key_value_map.pop_front() {
+            inner
+                .core
+                .listeners
+                .notify(&key, &entry.data, RemovalCause::Cleared);
+            cleared.push((key, entry.data));
+        }
+        cleared
+    }
+}
+
+impl<K: Eq + Hash + Clone + Send + 'static, V: Send + Sync + 'static> Clone for TTLCache<K, V> {
+    /// Get another handle to the same cache, sharing its items, settings and statistics.
+    fn clone(&self) -> Self {
+        TTLCache {
+            inner: self.inner.clone(),
+            access_listeners: self.access_listeners.clone(),
+            latency: self.latency.clone(),
+        }
+    }
+}
+
+impl<K: Eq + Hash + Clone + Send + Sync + 'static, V: Send + Sync + 'static> Cache<K, V>
+    for TTLCache<K, V>
+{
+    /// Get a value from the cache.
+    fn get(&self, key: &K) -> Option<Arc<V>> {
+        self.lookup(key, false)
+    }
+
+    /// Get a value from the cache, with whether it was a hit, or a miss because the key expired, was rejected or is absent.
+    fn get_with_outcome(&self, key: &K) -> (Option<Arc<V>>, GetOutcome) {
+        let result = self.get(key);
+        let outcome = match result {
+            Some(_) => GetOutcome::Hit,
+            None => {
+                let inner = self.inner.lock().unwrap();
+                let now = inner.core.clock.now();
+                // an item kept for its stale grace period has expired but not yet been removed
+                match inner.key_value_map.get(key) {
+                    Some(entry) if entry.expiry <= now => GetOutcome::MissExpired,
+                    _ => inner.core.listeners.miss_outcome(key),
+                }
+            }
+        };
+        (result, outcome)
+    }
+
+    /// Set a value in the cache.
+    fn set(&self, key: K, value: V) -> Option<Arc<V>> {
+        let started = self.latency.start();
+        let result = self
+            .insert(
+                key,
+                Arc::new(value),
+                None,
+                None,
+                None,
+                None,
+                EntrySource::Inserted,
+            )
+            .unwrap_or(None);
+        self.latency.record_set(started);
+        result
+    }
+
+    /// Set a value in the cache, recording how it came to be in the cache for `entry_info` and eviction listeners.
+    fn set_with_source(&self, key: K, value: V, source: EntrySource) -> Option<Arc<V>> {
+        let started = self.latency.start();
+        let result = self
+            .insert(key, Arc::new(value), None, None, None, None, source)
+            .unwrap_or(None);
+        self.latency.record_set(started);
+        result
     }
 
     /// Remove a value from the cache.
     fn remove(&self, key: &K) -> Option<Arc<V>> {
         let mut inner = self.inner.lock().unwrap();
-        inner.key_value_map.remove(key).map(|entry| entry.data)
+        inner.core.forget(key);
+        let result = inner.key_value_map.remove(key).map(|entry| entry.data);
+        if let Some(value) = &result {
+            inner
+                .core
+                .listeners
+                .notify(key, value, RemovalCause::Removed);
+        }
+        result
+    }
+
+    /// Check if a key is in the cache and has not expired, without counting a hit or miss or refreshing it.
+    fn contains_key(&self, key: &K) -> bool {
+        let inner = self.inner.lock().unwrap();
+        inner
+            .key_value_map
+            .get(key)
+            .is_some_and(|entry| entry.expiry > inner.core.clock.now())
+    }
+
+    /// Get the time left before an item expires, or None if it never expires or is not in the cache.
+    fn ttl_remaining(&self, key: &K) -> Option<Duration> {
+        self.entry_info(key)?.ttl_remaining
     }
 
     /// Clear the cache, removing all data.
     fn clear(&self) {
         let mut inner = self.inner.lock().unwrap();
-        inner.key_value_map.clear();
+        Self::clear_entries(&mut inner);
+    }
+
+    /// Clear the cache, returning every item which was removed.
+    fn clear_returning(&self) -> Vec<(K, Arc<V>)> {
+        let mut inner = self.inner.lock().unwrap();
+        Self::clear_entries(&mut inner)
+    }
+
+    /// Copy the unexpired items into a HashMap in one locked pass.
+    fn to_map(&self) -> HashMap<K, Arc<V>> {
+        let inner = self.inner.lock().unwrap();
+        let now = inner.core.clock.now();
+        inner
+            .key_value_map
+            .iter()
+            .filter(|(_, entry)| entry.expiry > now)
+            .map(|(key, entry)| (key.clone(), entry.data.clone()))
+            .collect()
+    }
+
+    /// Get up to `limit` unexpired items, ordered with the items the policy would evict last first. Higher priority items come before lower ones, and otherwise the most recently accessed first.
+    fn hottest(&self, limit: usize) -> Vec<(K, Arc<V>)> {
+        let inner = self.inner.lock().unwrap();
+        let now = inner.core.clock.now();
+        let mut items: Vec<(K, Arc<V>)> = inner
+            .key_value_map
+            .iter()
+            .rev()
+            .filter(|(_, entry)| entry.expiry > now)
+            .map(|(key, entry)| (key.clone(), entry.data.clone()))
+            .collect();
+        inner.core.priorities.sort_descending(&mut items);
+        items.truncate(limit);
+        items
+    }
+
+    /// Get the unexpired items in recency order, least recently used first, or in any order.
+    fn items(&self, order: IterationOrder) -> Option<Vec<(K, Arc<V>)>> {
+        if !matches!(order, IterationOrder::Recency | IterationOrder::Arbitrary) {
+            return None;
+        }
+        let inner = self.inner.lock().unwrap();
+        let now = inner.core.clock.now();
+        let items = inner
+            .key_value_map
+            .iter()
+            .filter(|(_, entry)| entry.expiry > now)
+            .map(|(key, entry)| (key.clone(), entry.data.clone()))
+            .collect();
+        Some(items)
+    }
+
+    /// Get the name of the eviction policy.
+    fn policy_name(&self) -> &'static str {
+        "ttl"
+    }
+
+    /// Get the cache as `Any`, so it can be downcast from a `dyn Cache`.
+    fn as_any(&self) -> Option<&dyn Any>
+    where
+        Self: 'static,
+    {
+        Some(self)
     }
 
     /// Get the cache statistics.
     fn stats(&self) -> CacheStats {
         let inner = self.inner.lock().unwrap();
-        CacheStats {
-            hits: inner.hits,
-            misses: inner.misses,
-            size: inner.key_value_map.len() as u64,
-            capacity: inner.capacity,
+        inner
+            .core
+            .stats
+            .snapshot(inner.key_value_map.len() as u64, inner.capacity)
+    }
+
+    /// Get the cache statistics together with how soon the items expire.
+    fn detailed_stats(&self) -> DetailedStats {
+        let inner = self.inner.lock().unwrap();
+        let mut detailed = DetailedStats::new(
+            inner
+                .core
+                .stats
+                .snapshot(inner.key_value_map.len() as u64, inner.capacity),
+        );
+        detailed.eviction_ages = Some(inner.core.stats.eviction_ages());
+        let now = inner.core.clock.now();
+        let mut distribution = ExpiryDistribution::default();
+        for entry in inner.key_value_map.values() {
+            if entry.expiry > now {
+                distribution.record(entry.expiry.saturating_duration_since(now));
+            }
         }
+        detailed.expiry_distribution = Some(distribution);
+        detailed
     }
 
     /// Change the capacity of the cache, if the new capacity is smaller than the current size, the oldest items are removed. Because the TTL is the same for all items this is identical as the ones which expire soonest.
     fn change_capacity(&self, capacity: u64) {
+        self.change_capacity_collect(capacity);
+    }
+
+    /// Change the capacity of the cache, returning the items which were removed to fit the new capacity.
+    fn change_capacity_collect(&self, capacity: u64) -> Vec<(K, Arc<V>)> {
         let mut inner = self.inner.lock().unwrap();
         let old_capacity = inner.capacity;
         inner.capacity = capacity;
-
-        while inner.key_value_map.len() as u64 > inner.capacity {
-            if let Some(key) = inner.key_value_map.keys().next().cloned() {
-                inner.key_value_map.remove(&key);
-            }
-        }
+        let evicted = Self::evict_to(&mut inner, capacity as usize);
 
         if capacity > old_capacity {
             let additional = (capacity - old_capacity) as usize;
             inner.key_value_map.reserve(additional);
         }
+        evicted
+    }
+
+    /// Evict `fraction` of the items per the policy in one locked pass, leaving the capacity unchanged.
+    fn shed(&self, fraction: f64) -> usize {
+        let mut inner = self.inner.lock().unwrap();
+        let len = inner.key_value_map.len();
+        Self::evict_to(&mut inner, len - shed_count(len, fraction)).len()
+    }
+}
+
+impl<K: Eq + Hash + Clone + Send + Sync + 'static, V: Send + Sync + 'static> TryCache<K, V>
+    for TTLCache<K, V>
+{
+    /// Get a value from the cache, returning `CacheError::Poisoned` instead of panicking if the cache lock is poisoned.
+    fn try_get(&self, key: &K) -> Result<Option<Arc<V>>, CacheError> {
+        if self.inner.is_poisoned() {
+            return Err(CacheError::Poisoned);
+        }
+        Ok(self.get(key))
+    }
+
+    /// Set a value in the cache, returning an error instead of inserting if the cache is full and was built with `FullBehavior::ReturnError`, has a capacity of zero, or its lock is poisoned.
+    fn try_set(&self, key: K, value: V) -> Result<Option<Arc<V>>, CacheError> {
+        let started = self.latency.start();
+        let result = self.insert(
+            key,
+            Arc::new(value),
+            None,
+            None,
+            None,
+            None,
+            EntrySource::Inserted,
+        );
+        self.latency.record_set(started);
+        result
+    }
+}
+
+impl<K: Eq + Hash + Clone + Send + Sync + 'static, V: Send + Sync + 'static> VersionedCache<K, V>
+    for TTLCache<K, V>
+{
+    /// Get the current version of an item, without counting a hit or miss or updating its position.
+    fn version(&self, key: &K) -> Option<u64> {
+        let inner = self.inner.lock().unwrap();
+        let now = inner.core.clock.now();
+        inner
+            .key_value_map
+            .get(key)
+            .filter(|entry| entry.expiry > now)?;
+        inner.core.metadata.version(key)
+    }
+
+    /// Set a value only if the item is at `expected_version`, or is not in the cache if it is 0.
+    fn set_versioned(
+        &self,
+        key: K,
+        value: V,
+        expected_version: u64,
+    ) -> Result<Option<Arc<V>>, CacheError> {
+        let started = self.latency.start();
+        let result = self.insert(
+            key,
+            Arc::new(value),
+            None,
+            None,
+            None,
+            Some(expected_version),
+            EntrySource::Inserted,
+        );
+        self.latency.record_set(started);
+        result
+    }
+}
+
+impl<K: Eq + Hash + Clone + Send + Sync + 'static, V: Send + Sync + 'static> IntoIterator
+    for TTLCache<K, V>
+{
+    type Item = (K, Arc<V>);
+    type IntoIter = std::vec::IntoIter<(K, Arc<V>)>;
+
+    /// Consume the handle, yielding the cache's unexpired items from least to most recently accessed. Other handles still hold the items.
+    fn into_iter(self) -> Self::IntoIter {
+        let inner = self.inner.lock().unwrap();
+        let now = inner.core.clock.now();
+        let items: Vec<(K, Arc<V>)> = inner
+            .key_value_map
+            .iter()
+            .filter(|(_, entry)| entry.expiry > now)
+            .map(|(key, entry)| (key.clone(), entry.data.clone()))
+            .collect();
+        items.into_iter()
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::cache::listener::RemovalEvent;
     use std::thread;
     use std::time::Duration;
 
+    #[test]
+    fn test_ttl_cache_max_ttl_never_expires() {
+        let cache = TTLCache::new(Duration::MAX, 2);
+        cache.set(1, 1);
+        cache.set_with_ttl(2, 2, Duration::MAX);
+        assert_eq!(cache.get(&1).map(|v| *v), Some(1));
+        assert_eq!(cache.get(&2).map(|v| *v), Some(2));
+        assert!(
+            cache.entry_info(&1).unwrap().ttl_remaining.unwrap() > Duration::from_secs(1 << 30)
+        );
+        assert!(cache.extend_ttl(&1, Duration::MAX));
+        assert_eq!(cache.get(&1).map(|v| *v), Some(1));
+    }
+
+    #[test]
+    fn test_ttl_cache_reject_new_ignores_expired() {
+        let cache = TTLCache::new(Duration::from_millis(20), 1)
+            .with_full_behavior(FullBehavior::ReturnError);
+        cache.set(1, 1);
+        assert_eq!(cache.try_set(2, 2), Err(CacheError::Full));
+        thread::sleep(Duration::from_millis(30));
+        assert_eq!(cache.try_set(2, 2), Ok(None));
+        assert!(cache.contains_key(&2));
+    }
+
+    #[test]
+    fn test_ttl_cache_evicts_expired_before_live() {
+        let cache = TTLCache::new(Duration::from_secs(60), 2);
+        cache.set(1, 1);
+        cache.set_with_ttl(2, 2, Duration::from_millis(10));
+        thread::sleep(Duration::from_millis(20));
+        // the expired item is not the oldest, it still makes room before the live one is evicted
+        cache.set(3, 3);
+        assert_eq!(cache.get(&1).map(|v| *v), Some(1));
+        assert_eq!(cache.get(&3).map(|v| *v), Some(3));
+        assert_eq!(cache.stats().size, 2);
+
+        // an overwrite at capacity evicts nothing
+        cache.set(1, 10);
+        assert_eq!(cache.get(&3).map(|v| *v), Some(3));
+        assert_eq!(cache.get(&1).map(|v| *v), Some(10));
+    }
+
+    #[test]
+    fn test_ttl_cache_entry_info() {
+        let cache = TTLCache::new(Duration::from_secs(60), 2);
+        cache.set_with_metadata(1, 1, 7u32);
+        let info = cache.entry_info(&1).unwrap();
+        assert_eq!(info.metadata::<u32>(), Some(&7));
+        assert!(info.ttl_remaining.unwrap() <= Duration::from_secs(60));
+        cache.remove(&1);
+        assert!(cache.entry_info(&1).is_none());
+    }
+
+    #[test]
+    fn test_ttl_cache_ttl_provider() {
+        let cache = TTLCache::new(Duration::from_secs(60), 10)
+            .with_ttl_provider(|_: &u64, value: &u64| Duration::from_millis(*value));
+        cache.set(1, 20);
+        cache.set(2, 10_000);
+        cache.set_with_ttl(3, 20, Duration::from_secs(60));
+        thread::sleep(Duration::from_millis(40));
+        assert!(cache.get(&1).is_none());
+        assert!(cache.get(&2).is_some());
+        assert!(cache.get(&3).is_some());
+    }
+
+    #[test]
+    fn test_ttl_cache_max_stale_grace() {
+        let cache = TTLCache::new(Duration::from_millis(10), 3).with_stale_grace(Duration::MAX);
+        cache.set(1, 1);
+        thread::sleep(Duration::from_millis(20));
+        assert!(cache.get(&1).is_none());
+        assert_eq!(cache.get_allow_stale(&1).map(|v| *v), Some(1));
+        cache.set(2, 2);
+        cache.set(3, 3);
+        assert_eq!(cache.split_off(|key, _| *key == 1).stats().size, 1);
+    }
+
+    #[test]
+    fn test_ttl_cache_stale_grace() {
+        let cache = TTLCache::new(Duration::from_millis(20), 10)
+            .with_stale_grace(Duration::from_millis(200));
+        cache.set(1, 1);
+        thread::sleep(Duration::from_millis(40));
+        assert!(cache.get(&1).is_none());
+        assert!(!cache.contains_key(&1));
+        assert_eq!(cache.get_allow_stale(&1).map(|v| *v), Some(1));
+        thread::sleep(Duration::from_millis(200));
+        assert!(cache.get_allow_stale(&1).is_none());
+        assert_eq!(cache.stats().size, 0);
+    }
+
     #[test]
     fn test_ttl_cache() {
         let cache = TTLCache::new(Duration::from_secs(1), 2);
@@ -215,6 +962,140 @@ mod tests {
         assert_eq!(cache.get(&2).map(|v| *v), Some(2));
     }
 
+    #[test]
+    fn test_ttl_cache_priorities() {
+        let cache = TTLCache::new(Duration::from_secs(60), 2);
+        cache.set_with_priority(1, 1, 5);
+        cache.set(2, 2);
+        cache.set(3, 3);
+        assert_eq!(cache.get(&2), None);
+        assert_eq!(cache.get(&1).map(|v| *v), Some(1));
+        cache.change_capacity(1);
+        assert_eq!(cache.get(&3), None);
+        assert_eq!(cache.get(&1).map(|v| *v), Some(1));
+    }
+
+    #[test]
+    fn test_ttl_cache_set_with_ttl() {
+        let cache = TTLCache::new(Duration::from_secs(60), 2);
+        cache.set_with_ttl(1, 1, Duration::ZERO);
+        cache.set(2, 2);
+        assert!(!cache.contains_key(&1));
+        assert_eq!(cache.get(&1), None);
+        assert_eq!(cache.get(&2).map(|v| *v), Some(2));
+    }
+
+    #[test]
+    fn test_ttl_cache_expiring_within() {
+        let cache = TTLCache::new(Duration::from_secs(600), 4);
+        cache.set(1, 1);
+        cache.set_with_ttl(2, 2, Duration::from_secs(30));
+        cache.set_with_ttl(3, 3, Duration::from_secs(5));
+        cache.set_with_ttl(4, 4, Duration::ZERO);
+        assert_eq!(cache.expiring_within(Duration::from_secs(60)), vec![3, 2]);
+        assert_eq!(
+            cache.expiring_within(Duration::from_secs(3600)),
+            vec![3, 2, 1]
+        );
+        assert_eq!(cache.expiring_within(Duration::MAX), vec![3, 2, 1]);
+    }
+
+    #[test]
+    fn test_ttl_cache_set_ttl() {
+        let cache = TTLCache::new(Duration::from_secs(60), 2);
+        cache.set(1, 1);
+        cache.set_ttl(Duration::ZERO);
+        assert_eq!(cache.ttl(), Duration::ZERO);
+        // 1 keeps its deadline until it is accessed, when the new TTL applies
+        assert_eq!(cache.get(&1).map(|v| *v), Some(1));
+        assert_eq!(cache.get(&1), None);
+        cache.set(2, 2);
+        assert_eq!(cache.get(&2), None);
+    }
+
+    #[test]
+    fn test_ttl_cache_change_capacity_collect() {
+        let cache = TTLCache::new(Duration::from_secs(60), 2);
+        cache.set(1, 1);
+        cache.set(2, 2);
+        cache.get(&1);
+        let evicted: Vec<(i32, i32)> = cache
+            .change_capacity_collect(1)
+            .into_iter()
+            .map(|(k, v)| (k, *v))
+            .collect();
+        assert_eq!(evicted, vec![(2, 2)]);
+        assert_eq!(cache.stats().size, 1);
+    }
+
+    #[test]
+    fn test_ttl_cache_eviction_listener() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let sink = events.clone();
+        let cache = TTLCache::new(Duration::from_secs(60), 1).with_eviction_listener(
+            move |event: &RemovalEvent<i32, i32>| {
+                sink.lock().unwrap().push((event.key, event.cause));
+            },
+        );
+        cache.set_with_ttl(1, 1, Duration::ZERO);
+        cache.get(&1);
+        cache.set(2, 2);
+        cache.set(3, 3);
+        assert_eq!(cache.clear_returning().len(), 1);
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![
+                (1, RemovalCause::Expired),
+                (2, RemovalCause::Evicted),
+                (3, RemovalCause::Cleared),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_ttl_cache_reaper() {
+        let cache = TTLCache::new(Duration::from_secs(60), 3);
+        let reaper = cache.start_reaper(Duration::from_millis(5));
+        cache.set(1, 1);
+        cache.set_with_ttl(2, 2, Duration::ZERO);
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(cache.stats().size, 1);
+        reaper.shutdown();
+
+        let reaper = cache.start_reaper(Duration::from_millis(1));
+        drop(cache);
+        while !reaper.is_finished() {
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn test_ttl_cache_split_off() {
+        let cache = TTLCache::new(Duration::from_secs(60), 4);
+        cache.set(1, 1);
+        cache.set_with_ttl(2, 2, Duration::from_secs(5));
+        cache.set(3, 3);
+        let split = cache.split_off(|key, _| *key >= 2);
+        assert_eq!(split.ttl(), Duration::from_secs(60));
+        assert_eq!(split.stats().capacity, 3);
+        assert_eq!(split.expiring_within(Duration::from_secs(10)), vec![2]);
+        assert!(!cache.contains_key(&2));
+        assert_eq!(split.get(&3).map(|v| *v), Some(3));
+    }
+
+    #[test]
+    fn test_ttl_cache_to_map_and_into_iter() {
+        let cache = TTLCache::new(Duration::from_secs(60), 3);
+        cache.set(1, 1);
+        cache.set_with_ttl(2, 2, Duration::ZERO);
+        cache.set(3, 3);
+        let map = cache.to_map();
+        assert_eq!(map.len(), 2);
+        assert!(!map.contains_key(&2));
+        let items: Vec<(i32, i32)> = cache.into_iter().map(|(k, v)| (k, *v)).collect();
+        assert_eq!(items, vec![(1, 1), (3, 3)]);
+    }
+
     #[test]
     fn test_ttl_cache_clear() {
         let cache = TTLCache::new(Duration::from_secs(1), 2);
@@ -224,4 +1105,19 @@ mod tests {
         assert_eq!(cache.get(&1), None);
         assert_eq!(cache.get(&2), None);
     }
+
+    #[test]
+    fn test_ttl_cache_extend_ttl() {
+        let cache = TTLCache::new(Duration::from_millis(100), 10);
+        cache.set(1, 1);
+        cache.set(2, 2);
+        assert!(cache.extend_ttl(&1, Duration::from_secs(60)));
+        assert!(!cache.extend_ttl(&3, Duration::from_secs(60)));
+        // a get refreshes the deadline but does not undo the extension
+        assert!(cache.get(&1).is_some());
+        std::thread::sleep(Duration::from_millis(150));
+        assert!(cache.get(&1).is_some());
+        assert!(cache.get(&2).is_none());
+        assert!(!cache.extend_ttl(&2, Duration::from_secs(60)));
+    }
 }
@@ -0,0 +1,665 @@
+use crate::sync::Mutex;
+use linked_hash_set::LinkedHashSet;
+use std::any::Any;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::Arc;
+use std::time::Duration;
+
+use linked_hash_map::LinkedHashMap;
+
+use crate::cache::latency::{LatencyTracker, OperationLatencies};
+use crate::cache::listener::{
+    AccessListener, AccessListeners, CallbackPanics, EntrySource, EvictionListener, RemovalCause,
+};
+use crate::cache::metadata::{EntryInfo, EntryMetadata};
+use crate::cache::policy_core::{policy_methods, PolicyCore};
+use crate::cache::{
+    shed_count, Cache, CacheStats, DetailedStats, FullBehavior, GetOutcome, TryCache,
+    VersionedCache,
+};
+use crate::clock::Clock;
+use crate::error::CacheError;
+
+/// The default share of the capacity for items seen once, A1in.
+const DEFAULT_A1IN_FRACTION: f64 = 0.25;
+
+/// The default number of evicted keys remembered in A1out, as a share of the capacity.
+const DEFAULT_A1OUT_FRACTION: f64 = 0.5;
+
+/// TwoQCacheInner contains the inner data structure for the TwoQCache.
+struct TwoQCacheInner<K: Eq + Hash + Clone + Send, V: Send + Sync> {
+    capacity: u64,
+    // items seen once, oldest first
+    a1in: LinkedHashMap<K, Arc<V>>,
+    // keys recently evicted from a1in, without their values, oldest first
+    a1out: LinkedHashSet<K>,
+    // items seen again after leaving a1in, least recently used first
+    am: LinkedHashMap<K, Arc<V>>,
+    a1in_fraction: f64,
+    a1out_fraction: f64,
+    core: PolicyCore<K, V>,
+}
+
+impl<K: Eq + Hash + Clone + Send, V: Send + Sync> TwoQCacheInner<K, V> {
+    /// Create a new TwoQCacheInner with the given capacity.
+    fn new(capacity: u64) -> Self {
+        TwoQCacheInner {
+            capacity,
+            a1in: LinkedHashMap::new(),
+            a1out: LinkedHashSet::new(),
+            am: LinkedHashMap::with_capacity(capacity as usize),
+            a1in_fraction: DEFAULT_A1IN_FRACTION,
+            a1out_fraction: DEFAULT_A1OUT_FRACTION,
+            core: PolicyCore::new(),
+        }
+    }
+
+    /// Check if a key is held in A1in or Am.
+    fn holds(&self, key: &K) -> bool {
+        self.a1in.contains_key(key) || self.am.contains_key(key)
+    }
+
+    /// Get the number of items held, A1out holds no items.
+    fn len(&self) -> usize {
+        self.a1in.len() + self.am.len()
+    }
+
+    /// Get the number of items A1in may hold before Am is evicted from instead.
+    fn a1in_limit(&self) -> usize {
+        ((self.capacity as f64 * self.a1in_fraction).ceil() as usize).max(1)
+    }
+
+    /// Get the number of keys A1out remembers.
+    fn a1out_limit(&self) -> usize {
+        (self.capacity as f64 * self.a1out_fraction).ceil() as usize
+    }
+
+    /// Check if the next eviction should take from A1in rather than Am.
+    fn evicts_a1in(&self) -> bool {
+        !self.a1in.is_empty() && (self.a1in.len() > self.a1in_limit() || self.am.is_empty())
+    }
+
+    /// Remember a key evicted from A1in, forgetting the oldest remembered keys beyond the limit.
+    fn remember(&mut self, key: K) {
+        self.a1out.insert(key);
+        while self.a1out.len() > self.a1out_limit() {
+            self.a1out.pop_front();
+        }
+    }
+
+    /// Remove an item from every structure, without remembering it in A1out.
+    fn remove_entry(&mut self, key: &K) -> Option<Arc<V>> {
+        let value = self.a1in.remove(key).or_else(|| self.am.remove(key))?;
+        self.core.forget(key);
+        Some(value)
+    }
+
+    /// Evict the oldest item of A1in if it holds more than its share, otherwise the least recently used item of Am. Keys evicted from A1in are remembered in A1out.
+    ///
+    /// If priorities have been assigned the first item with the lowest priority in that order is evicted instead.
+    fn evict(&mut self) -> Option<(K, Arc<V>)> {
+        let a1in_first = self.evicts_a1in();
+        let candidates = if a1in_first {
+            self.a1in.keys().chain(self.am.keys())
+        } else {
+            self.am.keys().chain(self.a1in.keys())
+        };
+        let key = match self.core.priorities.pick(self.len(), candidates) {
+            Some(key) => key,
+            None if a1in_first => self.a1in.front()?.0.clone(),
+            None => self.am.front()?.0.clone(),
+        };
+        let from_a1in = self.a1in.contains_key(&key);
+        let lifetime = self.core.metadata.lifetime(&key, self.core.clock.now());
+        let value = self.remove_entry(&key)?;
+        if from_a1in {
+            self.remember(key.clone());
+        }
+        self.core
+            .listeners
+            .notify(&key, &value, RemovalCause::Evicted);
+        self.core.stats.record_eviction(lifetime);
+        Some((key, value))
+    }
+
+    /// Evict items per the policy until at most `len` are left.
+    fn evict_to(&mut self, len: usize) -> Vec<(K, Arc<V>)> {
+        let mut evicted = Vec::new();
+        while self.len() > len {
+            match self.evict() {
+                Some(item) => evicted.push(item),
+                None => break,
+            }
+        }
+        evicted
+    }
+
+    /// Remove every item which has passed its deadline.
+    fn purge_expired(&mut self) {
+        let now = self.core.clock.now();
+        while let Some(key) = self.core.expiries.pop_expired(now) {
+            if let Some(value) = self.remove_entry(&key) {
+                self.core
+                    .listeners
+                    .notify(&key, &value, RemovalCause::Expired);
+                self.core.stats.record_expiration();
+            }
+        }
+    }
+
+    /// Remove every item and forget the keys in A1out, notifying listeners and returning the removed items.
+    fn clear_entries(&mut self) -> Vec<(K, Arc<V>)> {
+        self.a1out.clear();
+        self.core.clear();
+        let mut cleared: Vec<(K, Arc<V>)> = self.a1in.drain().collect();
+        cleared.extend(self.am.drain());
+        for (key, value) in &cleared {
+            self.core
+                .listeners
+                .notify(key, value, RemovalCause::Cleared);
+        }
+        cleared
+    }
+
+    /// Get the unexpired items, with the items the policy would evict last first.
+    fn hottest_items(&self) -> Vec<(K, Arc<V>)> {
+        let now = self.core.clock.now();
+        self.am
+            .iter()
+            .rev()
+            .chain(self.a1in.iter().rev())
+            .filter(|(key, _)| !self.core.expiries.is_expired(key, now))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect()
+    }
+}
+
+/// TwoQCache is a cache which keeps items seen only once apart from items seen again, so a large scan cannot flush out the frequently used items.
+///
+/// New items enter A1in, a first-in-first-out queue taking a quarter of the capacity. Gets of items in A1in do not move them, since closely spaced requests for a new key say little about whether it will be used again. Items leaving A1in are forgotten, but their keys are remembered in A1out, a queue of up to half the capacity in keys without values. A key set again while it is remembered in A1out has proven to be reused, so it enters Am, a least recently used queue holding the rest of the items. When the cache is full, the oldest item of A1in is evicted while A1in holds more than its share, otherwise the least recently used item of Am.
+///
+/// All mutability is handled internally with a Mutex, so the cache can be shared between threads. Values are returned as Arcs to allow for shared ownership.
+///
+/// Example:
+/// ```
+/// use arcache::{Cache, TwoQCache};
+///
+/// let cache = TwoQCache::<u64, u64>::new(4);
+///
+/// // key 1 is evicted from A1in, then set again while remembered in A1out, so it enters Am
+/// for key in [1, 2, 3, 4, 5, 1] {
+///     cache.set(key, key);
+/// }
+///
+/// // a scan of new keys only cycles through A1in
+/// for key in 100..200 {
+///     cache.set(key, key);
+/// }
+/// assert_eq!(cache.get(&1).map(|v| *v), Some(1));
+/// ```
+pub struct TwoQCache<K: Eq + Hash + Clone + Send, V: Send + Sync> {
+    inner: Arc<Mutex<TwoQCacheInner<K, V>>>,
+    access_listeners: Arc<AccessListeners<K>>,
+    latency: Arc<LatencyTracker>,
+}
+
+impl<K: Eq + Hash + Clone + Sync + Send, V: Send + Sync> TwoQCache<K, V> {
+    /// Create a new TwoQCache with the given capacity.
+    pub fn new(capacity: u64) -> Self {
+        TwoQCache {
+            inner: Arc::new(Mutex::new(TwoQCacheInner::new(capacity))),
+            access_listeners: Arc::new(AccessListeners::new()),
+            latency: Arc::new(LatencyTracker::new()),
+        }
+    }
+
+    /// Set the share of the capacity for items seen once, A1in, and the number of evicted keys remembered in A1out as a share of the capacity, 0.25 and 0.5 by default.
+    ///
+    /// Both are clamped between 0 and 1, A1in always has room for at least one item.
+    pub fn with_queue_fractions(self, a1in: f64, a1out: f64) -> Self {
+        {
+            let mut inner = self.inner.lock().unwrap();
+            inner.a1in_fraction = a1in.clamp(0.0, 1.0);
+            inner.a1out_fraction = a1out.clamp(0.0, 1.0);
+        }
+        self
+    }
+
+    policy_methods!(lock, lock);
+
+    /// Get the number of items in A1in, the number of keys remembered in A1out and the number of items in Am.
+    pub fn queue_lens(&self) -> (usize, usize, usize) {
+        let inner = self.inner.lock().unwrap();
+        (inner.a1in.len(), inner.a1out.len(), inner.am.len())
+    }
+
+    /// Set a value in the cache with a priority, when the cache is full lower priority items are evicted before higher priority ones.
+    ///
+    /// Within a priority the item the policy would evict first goes first. New items set with `set` have the lowest priority, `DEFAULT_PRIORITY`, and setting a key again without a priority keeps the one it has.
+    pub fn set_with_priority(&self, key: K, value: V, priority: u32) -> Option<Arc<V>> {
+        self.insert(
+            key,
+            Arc::new(value),
+            Some(priority),
+            None,
+            None,
+            None,
+            EntrySource::Inserted,
+        )
+        .unwrap_or(None)
+    }
+
+    /// Set a value in the cache which expires after the given time-to-live, overriding the cache's default TTL.
+    ///
+    /// Expired items are never returned and are removed before any live item is evicted.
+    pub fn set_with_ttl(&self, key: K, value: V, ttl: Duration) -> Option<Arc<V>> {
+        self.insert(
+            key,
+            Arc::new(value),
+            None,
+            Some(ttl),
+            None,
+            None,
+            EntrySource::Inserted,
+        )
+        .unwrap_or(None)
+    }
+
+    /// Set a value in the cache with a metadata value attached, which can be read back with `entry_info`.
+    ///
+    /// Metadata is dropped when the item leaves the cache or is set again without metadata.
+    pub fn set_with_metadata(
+        &self,
+        key: K,
+        value: V,
+        metadata: impl Any + Send + Sync,
+    ) -> Option<Arc<V>> {
+        self.insert(
+            key,
+            Arc::new(value),
+            None,
+            None,
+            Some(Arc::new(metadata)),
+            None,
+            EntrySource::Inserted,
+        )
+        .unwrap_or(None)
+    }
+
+    /// Insert a value with a priority and an optional TTL, evicting an item if the cache is full.
+    #[allow(clippy::too_many_arguments)]
+    fn insert(
+        &self,
+        key: K,
+        value: Arc<V>,
+        priority: Option<u32>,
+        ttl: Option<Duration>,
+        metadata: Option<EntryMetadata>,
+        expected_version: Option<u64>,
+        source: EntrySource,
+    ) -> Result<Option<Arc<V>>, CacheError> {
+        let mut inner = self.inner.lock().map_err(|_| CacheError::Poisoned)?;
+        if inner.capacity == 0 {
+            return Err(CacheError::CapacityZero);
+        }
+        inner.purge_expired();
+        inner.core.metadata.check_version(&key, expected_version)?;
+        // overwriting a key never evicts another item
+        let is_new = !inner.a1in.contains_key(&key) && !inner.am.contains_key(&key);
+        if is_new && !inner.core.full_behavior.evicts() && inner.len() as u64 >= inner.capacity {
+            inner.core.listeners.reject(&key);
+            return inner.core.full_behavior.rejection();
+        }
+        // checked before evicting, which may push the key out of A1out
+        let reused = is_new && inner.a1out.remove(&key);
+        if is_new && inner.len() as u64 >= inner.capacity {
+            inner.evict();
+        }
+        inner.core.stats.record_set(is_new);
+        inner.core.track(&key, priority, ttl, metadata, source);
+        if let Some(slot) = inner.a1in.get_mut(&key) {
+            // an overwritten item keeps its place in A1in
+            return Ok(Some(std::mem::replace(slot, value)));
+        }
+        if reused || inner.am.contains_key(&key) {
+            return Ok(inner.am.insert(key, value));
+        }
+        inner.a1in.insert(key, value);
+        Ok(None)
+    }
+}
+
+impl<K: Eq + Hash + Clone + Send, V: Send + Sync> Clone for TwoQCache<K, V> {
+    /// Get another handle to the same cache, sharing its items, settings and statistics.
+    fn clone(&self) -> Self {
+        TwoQCache {
+            inner: self.inner.clone(),
+            access_listeners: self.access_listeners.clone(),
+            latency: self.latency.clone(),
+        }
+    }
+}
+
+impl<K: Eq + Hash + Clone + Sync + Send, V: Send + Sync> Cache<K, V> for TwoQCache<K, V> {
+    /// Get a value from the cache, moving it to the most recently used end of Am if it is there.
+    fn get(&self, key: &K) -> Option<Arc<V>> {
+        let started = self.latency.start();
+        let result = {
+            let mut inner = self.inner.lock().unwrap();
+            inner.purge_expired();
+            let result = match inner.am.get_refresh(key) {
+                Some(value) => Some(value.clone()),
+                None => inner.a1in.get(key).cloned(),
+            };
+            if result.is_some() {
+                inner.core.stats.record_hit();
+                let now = inner.core.clock.now();
+                inner.core.metadata.touch(key, now);
+            } else {
+                inner.core.stats.record_miss();
+            }
+            result
+        };
+        self.latency.record_get(started);
+        self.access_listeners.notify(key, &result);
+        result
+    }
+
+    /// Get a value from the cache, with whether it was a hit, or a miss because the key expired, was rejected or is absent.
+    fn get_with_outcome(&self, key: &K) -> (Option<Arc<V>>, GetOutcome) {
+        let result = self.get(key);
+        let outcome = match result {
+            Some(_) => GetOutcome::Hit,
+            None => self.inner.lock().unwrap().core.listeners.miss_outcome(key),
+        };
+        (result, outcome)
+    }
+
+    /// Set a value in the cache.
+    fn set(&self, key: K, value: V) -> Option<Arc<V>> {
+        let started = self.latency.start();
+        let result = self
+            .insert(
+                key,
+                Arc::new(value),
+                None,
+                None,
+                None,
+                None,
+                EntrySource::Inserted,
+            )
+            .unwrap_or(None);
+        self.latency.record_set(started);
+        result
+    }
+
+    /// Set a value in the cache, recording how it came to be in the cache for `entry_info` and eviction listeners.
+    fn set_with_source(&self, key: K, value: V, source: EntrySource) -> Option<Arc<V>> {
+        let started = self.latency.start();
+        let result = self
+            .insert(key, Arc::new(value), None, None, None, None, source)
+            .unwrap_or(None);
+        self.latency.record_set(started);
+        result
+    }
+
+    /// Remove a value from the cache.
+    fn remove(&self, key: &K) -> Option<Arc<V>> {
+        let mut inner = self.inner.lock().unwrap();
+        let result = inner.remove_entry(key);
+        if let Some(value) = &result {
+            inner
+                .core
+                .listeners
+                .notify(key, value, RemovalCause::Removed);
+        }
+        result
+    }
+
+    /// Check if a key is in the cache and has not expired, without counting a hit or miss or updating its position.
+    fn contains_key(&self, key: &K) -> bool {
+        let inner = self.inner.lock().unwrap();
+        (inner.a1in.contains_key(key) || inner.am.contains_key(key))
+            && !inner.core.expiries.is_expired(key, inner.core.clock.now())
+    }
+
+    /// Get the time left before an item expires, or None if it never expires or is not in the cache.
+    fn ttl_remaining(&self, key: &K) -> Option<Duration> {
+        self.entry_info(key)?.ttl_remaining
+    }
+
+    /// Clear the cache.
+    fn clear(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.clear_entries();
+    }
+
+    /// Clear the cache, returning every item which was removed.
+    fn clear_returning(&self) -> Vec<(K, Arc<V>)> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.clear_entries()
+    }
+
+    /// Copy the unexpired items into a HashMap in one locked pass.
+    fn to_map(&self) -> HashMap<K, Arc<V>> {
+        let inner = self.inner.lock().unwrap();
+        let now = inner.core.clock.now();
+        inner
+            .a1in
+            .iter()
+            .chain(inner.am.iter())
+            .filter(|(key, _)| !inner.core.expiries.is_expired(key, now))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect()
+    }
+
+    /// Get up to `limit` unexpired items, ordered with the items the policy would evict last first. Higher priority items come before lower ones, and otherwise the items of Am from most to least recently used, then the items of A1in from newest to oldest.
+    fn hottest(&self, limit: usize) -> Vec<(K, Arc<V>)> {
+        let inner = self.inner.lock().unwrap();
+        let mut items = inner.hottest_items();
+        inner.core.priorities.sort_descending(&mut items);
+        items.truncate(limit);
+        items
+    }
+
+    /// Get the name of the eviction policy.
+    fn policy_name(&self) -> &'static str {
+        "2q"
+    }
+
+    /// Get the cache as `Any`, so it can be downcast from a `dyn Cache`.
+    fn as_any(&self) -> Option<&dyn Any>
+    where
+        Self: 'static,
+    {
+        Some(self)
+    }
+
+    /// Get cache statistics.
+    fn stats(&self) -> CacheStats {
+        let inner = self.inner.lock().unwrap();
+        inner
+            .core
+            .stats
+            .snapshot(inner.len() as u64, inner.capacity)
+    }
+
+    /// Get the cache statistics together with how soon the items expire.
+    fn detailed_stats(&self) -> DetailedStats {
+        let inner = self.inner.lock().unwrap();
+        let size = inner.len() as u64;
+        let mut detailed = DetailedStats::new(inner.core.stats.snapshot(size, inner.capacity));
+        detailed.eviction_ages = Some(inner.core.stats.eviction_ages());
+        detailed.expiry_distribution = Some(
+            inner
+                .core
+                .expiries
+                .distribution(inner.core.clock.now(), size),
+        );
+        detailed
+    }
+
+    /// Change the capacity of the cache, if the new capacity is smaller than the current size, items are evicted per the policy.
+    fn change_capacity(&self, capacity: u64) {
+        self.change_capacity_collect(capacity);
+    }
+
+    /// Change the capacity of the cache, returning the items which were removed to fit the new capacity.
+    fn change_capacity_collect(&self, capacity: u64) -> Vec<(K, Arc<V>)> {
+        let mut inner = self.inner.lock().unwrap();
+        let old_capacity = inner.capacity;
+        inner.capacity = capacity;
+        let evicted = inner.evict_to(capacity as usize);
+        while inner.a1out.len() > inner.a1out_limit() {
+            inner.a1out.pop_front();
+        }
+
+        if inner.capacity > old_capacity {
+            let additional = (inner.capacity - old_capacity) as usize;
+            inner.am.reserve(additional);
+        }
+        evicted
+    }
+
+    /// Evict `fraction` of the items per the policy in one locked pass, leaving the capacity unchanged.
+    fn shed(&self, fraction: f64) -> usize {
+        let mut inner = self.inner.lock().unwrap();
+        let len = inner.len();
+        inner.evict_to(len - shed_count(len, fraction)).len()
+    }
+}
+
+impl<K: Eq + Hash + Clone + Sync + Send, V: Send + Sync> TryCache<K, V> for TwoQCache<K, V> {
+    /// Get a value from the cache, returning `CacheError::Poisoned` instead of panicking if the cache lock is poisoned.
+    fn try_get(&self, key: &K) -> Result<Option<Arc<V>>, CacheError> {
+        if self.inner.is_poisoned() {
+            return Err(CacheError::Poisoned);
+        }
+        Ok(self.get(key))
+    }
+
+    /// Set a value in the cache, returning an error instead of inserting if the cache is full and was built with `FullBehavior::ReturnError`, has a capacity of zero, or its lock is poisoned.
+    fn try_set(&self, key: K, value: V) -> Result<Option<Arc<V>>, CacheError> {
+        let started = self.latency.start();
+        let result = self.insert(
+            key,
+            Arc::new(value),
+            None,
+            None,
+            None,
+            None,
+            EntrySource::Inserted,
+        );
+        self.latency.record_set(started);
+        result
+    }
+}
+
+impl<K: Eq + Hash + Clone + Sync + Send, V: Send + Sync> VersionedCache<K, V> for TwoQCache<K, V> {
+    /// Get the current version of an item, without counting a hit or miss or updating its position.
+    fn version(&self, key: &K) -> Option<u64> {
+        let inner = self.inner.lock().unwrap();
+        if inner.core.expiries.is_expired(key, inner.core.clock.now()) {
+            return None;
+        }
+        inner.core.metadata.version(key)
+    }
+
+    /// Set a value only if the item is at `expected_version`, or is not in the cache if it is 0.
+    fn set_versioned(
+        &self,
+        key: K,
+        value: V,
+        expected_version: u64,
+    ) -> Result<Option<Arc<V>>, CacheError> {
+        let started = self.latency.start();
+        let result = self.insert(
+            key,
+            Arc::new(value),
+            None,
+            None,
+            None,
+            Some(expected_version),
+            EntrySource::Inserted,
+        );
+        self.latency.record_set(started);
+        result
+    }
+}
+
+impl<K: Eq + Hash + Clone + Sync + Send, V: Send + Sync> IntoIterator for TwoQCache<K, V> {
+    type Item = (K, Arc<V>);
+    type IntoIter = std::vec::IntoIter<(K, Arc<V>)>;
+
+    /// Consume the handle, yielding the cache's unexpired items from the hottest to the coldest. Other handles still hold the items.
+    fn into_iter(self) -> Self::IntoIter {
+        let items = self.inner.lock().unwrap().hottest_items();
+        items.into_iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_two_q_cache() {
+        let cache = TwoQCache::new(4);
+        for key in 1..=4 {
+            cache.set(key, key);
+        }
+        assert_eq!(cache.queue_lens(), (4, 0, 0));
+        // A1in evicts its oldest item and remembers its key
+        cache.set(5, 5);
+        assert_eq!(cache.get(&1), None);
+        assert_eq!(cache.queue_lens(), (4, 1, 0));
+        // a key remembered in A1out goes straight to Am
+        cache.set(1, 1);
+        assert_eq!(cache.queue_lens(), (3, 1, 1));
+        assert_eq!(cache.get(&2), None);
+        assert_eq!(cache.get(&1).map(|v| *v), Some(1));
+    }
+
+    #[test]
+    fn test_two_q_cache_scan_resistance() {
+        let cache = TwoQCache::new(8);
+        let hot: Vec<u64> = (0..4).collect();
+        // promote the hot keys into Am by setting them again after they leave A1in
+        for key in hot
+            .iter()
+            .copied()
+            .chain(100..108)
+            .chain(hot.iter().copied())
+        {
+            cache.set(key, key);
+        }
+        assert_eq!(cache.queue_lens().2, 4);
+        for key in 1_000..2_000 {
+            cache.set(key, key);
+            for key in &hot {
+                cache.get(key);
+            }
+        }
+        for key in &hot {
+            assert_eq!(cache.get(key).map(|v| *v), Some(*key));
+        }
+        assert_eq!(cache.stats().size, 8);
+    }
+
+    #[test]
+    fn test_two_q_cache_am_is_lru() {
+        let cache = TwoQCache::new(4).with_queue_fractions(0.25, 1.0);
+        for key in [1, 2, 3, 4, 5, 6, 7, 1, 2, 3] {
+            cache.set(key, key);
+        }
+        assert_eq!(cache.queue_lens().2, 3);
+        cache.get(&1);
+        // A1in holds no more than its share of one item, so Am evicts its least recently used item, 2
+        cache.set(8, 8);
+        cache.set(9, 9);
+        assert!(!cache.contains_key(&2));
+        assert!(cache.contains_key(&1));
+        assert!(cache.contains_key(&3));
+    }
+}
@@ -0,0 +1,686 @@
+use crate::sync::Mutex;
+use linked_hash_set::LinkedHashSet;
+use std::any::Any;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::cache::latency::{LatencyTracker, OperationLatencies};
+use crate::cache::listener::{
+    AccessListener, AccessListeners, CallbackPanics, EntrySource, EvictionListener, RemovalCause,
+};
+use crate::cache::metadata::{EntryInfo, EntryMetadata};
+use crate::cache::policy_core::{policy_methods, PolicyCore};
+use crate::cache::{
+    shed_count, Cache, CacheStats, DetailedStats, FullBehavior, GetOutcome, IterationOrder,
+    TryCache, VersionedCache,
+};
+use crate::clock::Clock;
+use crate::error::CacheError;
+
+/// The highest count an item can reach, counts saturate here like a 4-bit counter.
+const MAX_COUNT: u8 = 15;
+
+/// The default window is this many accesses per unit of capacity.
+const WINDOW_PER_CAPACITY: u64 = 10;
+
+/// The value and count of an item in the WindowedLFUCache.
+struct WindowedEntry<V> {
+    value: Arc<V>,
+    count: u8,
+}
+
+/// WindowedLFUCacheInner contains the inner data structure for the WindowedLFUCache.
+struct WindowedLFUCacheInner<K: Eq + Hash + Clone + Send, V: Send + Sync> {
+    capacity: u64,
+    entries: HashMap<K, WindowedEntry<V>>,
+    // one bucket per count, each ordered from least to most recently counted
+    buckets: Vec<LinkedHashSet<K>>,
+    window: u64,
+    accesses: u64,
+    core: PolicyCore<K, V>,
+}
+
+impl<K: Eq + Hash + Clone + Send, V: Send + Sync> WindowedLFUCacheInner<K, V> {
+    /// Create a new WindowedLFUCacheInner with the given capacity, internally capacity is reserved for the necessary data structures.
+    fn new(capacity: u64) -> Self {
+        WindowedLFUCacheInner {
+            capacity,
+            entries: HashMap::with_capacity(capacity as usize),
+            buckets: (0..=MAX_COUNT).map(|_| LinkedHashSet::new()).collect(),
+            window: (capacity * WINDOW_PER_CAPACITY).max(1),
+            accesses: 0,
+            core: PolicyCore::new(),
+        }
+    }
+
+    /// Check if a key is held.
+    fn holds(&self, key: &K) -> bool {
+        self.entries.contains_key(key)
+    }
+
+    /// Count an access to the given key, saturating at `MAX_COUNT`.
+    fn increment(&mut self, key: &K) {
+        let Some(entry) = self.entries.get_mut(key) else {
+            return;
+        };
+        let count = entry.count;
+        entry.count = (count + 1).min(MAX_COUNT);
+        self.buckets[count as usize].remove(key);
+        self.buckets[entry.count as usize].insert(key.clone());
+    }
+
+    /// Count an access towards the window, halving every count when the window is over.
+    fn record_access(&mut self) {
+        self.accesses += 1;
+        if self.accesses >= self.window {
+            self.halve();
+        }
+    }
+
+    /// Halve every count, so items which were popular in earlier windows make way for newly popular ones.
+    fn halve(&mut self) {
+        self.accesses = 0;
+        let mut buckets: Vec<LinkedHashSet<K>> =
+            (0..=MAX_COUNT).map(|_| LinkedHashSet::new()).collect();
+        for (count, bucket) in self.buckets.drain(..).enumerate() {
+            for key in bucket {
+                if let Some(entry) = self.entries.get_mut(&key) {
+                    entry.count = count as u8 / 2;
+                }
+                buckets[count / 2].insert(key);
+            }
+        }
+        self.buckets = buckets;
+    }
+
+    /// Remove an item from every structure.
+    fn remove_entry(&mut self, key: &K) -> Option<Arc<V>> {
+        let entry = self.entries.remove(key)?;
+        self.buckets[entry.count as usize].remove(key);
+        self.core.forget(key);
+        Some(entry.value)
+    }
+
+    /// Evict the item with the lowest count, the least recently counted among equals.
+    ///
+    /// If priorities have been assigned the item with the lowest count among those with the lowest priority is evicted instead.
+    fn evict(&mut self) -> Option<(K, Arc<V>)> {
+        let key = match self.core.priorities.lowest(self.entries.len()) {
+            Some(lowest) => self
+                .buckets
+                .iter()
+                .flat_map(|bucket| bucket.iter())
+                .find(|key| self.core.priorities.get(key) == lowest)
+                .cloned(),
+            None => self
+                .buckets
+                .iter()
+                .find_map(|bucket| bucket.front())
+                .cloned(),
+        }?;
+        let lifetime = self.core.metadata.lifetime(&key, self.core.clock.now());
+        let value = self.remove_entry(&key)?;
+        self.core
+            .listeners
+            .notify(&key, &value, RemovalCause::Evicted);
+        self.core.stats.record_eviction(lifetime);
+        Some((key, value))
+    }
+
+    /// Evict items per the policy until at most `len` are left.
+    fn evict_to(&mut self, len: usize) -> Vec<(K, Arc<V>)> {
+        let mut evicted = Vec::new();
+        while self.entries.len() > len {
+            match self.evict() {
+                Some(item) => evicted.push(item),
+                None => break,
+            }
+        }
+        evicted
+    }
+
+    /// Remove every item which has passed its deadline.
+    fn purge_expired(&mut self) {
+        let now = self.core.clock.now();
+        while let Some(key) = self.core.expiries.pop_expired(now) {
+            if let Some(value) = self.remove_entry(&key) {
+                self.core
+                    .listeners
+                    .notify(&key, &value, RemovalCause::Expired);
+                self.core.stats.record_expiration();
+            }
+        }
+    }
+
+    /// Remove every item and reset the window, notifying listeners and returning the removed items.
+    fn clear_entries(&mut self) -> Vec<(K, Arc<V>)> {
+        for bucket in self.buckets.iter_mut() {
+            bucket.clear();
+        }
+        self.accesses = 0;
+        self.core.clear();
+        let cleared: Vec<(K, Arc<V>)> = self
+            .entries
+            .drain()
+            .map(|(key, entry)| (key, entry.value))
+            .collect();
+        for (key, value) in &cleared {
+            self.core
+                .listeners
+                .notify(key, value, RemovalCause::Cleared);
+        }
+        cleared
+    }
+}
+
+/// WindowedLFUCache is a least frequently used cache which only counts recent accesses, with a small saturating counter per item.
+///
+/// Each item's count stops at 15, and after every window of gets and sets all counts are halved, so the cache follows shifts in popularity instead of holding on to items which were popular long ago. When the cache is full, the item with the lowest count is evicted. The window defaults to ten accesses per unit of capacity.
+///
+/// All mutability is handled internally with a Mutex, so the cache can be shared between threads. Values are returned as Arcs to allow for shared ownership.
+///
+/// Example:
+/// ```
+/// use arcache::{Cache, WindowedLFUCache};
+///
+/// let cache = WindowedLFUCache::<&str, String>::new(10).with_window(1000);
+///
+/// cache.set("key", "value".to_string());
+/// cache.get(&"key");
+///
+/// assert_eq!(cache.frequency(&"key"), Some(2));
+/// assert_eq!(*cache.get(&"key").unwrap(), "value".to_string());
+/// ```
+pub struct WindowedLFUCache<K: Eq + Hash + Clone + Send, V: Send + Sync> {
+    inner: Arc<Mutex<WindowedLFUCacheInner<K, V>>>,
+    access_listeners: Arc<AccessListeners<K>>,
+    latency: Arc<LatencyTracker>,
+}
+
+impl<K: Eq + Hash + Clone + Sync + Send, V: Send + Sync> WindowedLFUCache<K, V> {
+    /// Create a new WindowedLFUCache with the given capacity.
+    pub fn new(capacity: u64) -> Self {
+        WindowedLFUCache {
+            inner: Arc::new(Mutex::new(WindowedLFUCacheInner::new(capacity))),
+            access_listeners: Arc::new(AccessListeners::new()),
+            latency: Arc::new(LatencyTracker::new()),
+        }
+    }
+
+    /// Set the number of gets and sets after which every count is halved, a shorter window adapts to changes in popularity faster.
+    pub fn with_window(self, window: u64) -> Self {
+        self.inner.lock().unwrap().window = window.max(1);
+        self
+    }
+
+    policy_methods!(lock, lock);
+
+    /// Get the count of an item in the current window, without counting it as an access.
+    ///
+    /// Returns None if the key is not in the cache or has expired.
+    pub fn frequency(&self, key: &K) -> Option<u8> {
+        let inner = self.inner.lock().unwrap();
+        if inner.core.expiries.is_expired(key, inner.core.clock.now()) {
+            return None;
+        }
+        inner.entries.get(key).map(|entry| entry.count)
+    }
+
+    /// Set a value in the cache with a priority, when the cache is full lower priority items are evicted before higher priority ones.
+    ///
+    /// Within a priority the item with the lowest count is evicted first. New items set with `set` have the lowest priority, `DEFAULT_PRIORITY`, and setting a key again without a priority keeps the one it has.
+    pub fn set_with_priority(&self, key: K, value: V, priority: u32) -> Option<Arc<V>> {
+        self.insert(
+            key,
+            Arc::new(value),
+            Some(priority),
+            None,
+            None,
+            None,
+            EntrySource::Inserted,
+        )
+        .unwrap_or(None)
+    }
+
+    /// Set a value in the cache which expires after the given time-to-live, overriding the cache's default TTL.
+    ///
+    /// Expired items are never returned and are removed before any live item is evicted.
+    pub fn set_with_ttl(&self, key: K, value: V, ttl: Duration) -> Option<Arc<V>> {
+        self.insert(
+            key,
+            Arc::new(value),
+            None,
+            Some(ttl),
+            None,
+            None,
+            EntrySource::Inserted,
+        )
+        .unwrap_or(None)
+    }
+
+    /// Set a value in the cache with a metadata value attached, which can be read back with `entry_info`.
+    ///
+    /// Metadata is dropped when the item leaves the cache or is set again without metadata.
+    pub fn set_with_metadata(
+        &self,
+        key: K,
+        value: V,
+        metadata: impl Any + Send + Sync,
+    ) -> Option<Arc<V>> {
+        self.insert(
+            key,
+            Arc::new(value),
+            None,
+            None,
+            Some(Arc::new(metadata)),
+            None,
+            EntrySource::Inserted,
+        )
+        .unwrap_or(None)
+    }
+
+    /// Insert a value with a priority and an optional TTL, evicting an item if the cache is full.
+    #[allow(clippy::too_many_arguments)]
+    fn insert(
+        &self,
+        key: K,
+        value: Arc<V>,
+        priority: Option<u32>,
+        ttl: Option<Duration>,
+        metadata: Option<EntryMetadata>,
+        expected_version: Option<u64>,
+        source: EntrySource,
+    ) -> Result<Option<Arc<V>>, CacheError> {
+        let mut inner = self.inner.lock().map_err(|_| CacheError::Poisoned)?;
+        if inner.capacity == 0 {
+            return Err(CacheError::CapacityZero);
+        }
+        inner.purge_expired();
+        inner.core.metadata.check_version(&key, expected_version)?;
+        // overwriting a key never evicts another item
+        let is_new = !inner.entries.contains_key(&key);
+        if is_new
+            && !inner.core.full_behavior.evicts()
+            && inner.entries.len() as u64 >= inner.capacity
+        {
+            inner.core.listeners.reject(&key);
+            return inner.core.full_behavior.rejection();
+        }
+        if is_new && inner.entries.len() as u64 >= inner.capacity {
+            inner.evict();
+        }
+        inner.core.stats.record_set(is_new);
+        inner.core.track(&key, priority, ttl, metadata, source);
+        let previous = match inner.entries.get_mut(&key) {
+            Some(entry) => Some(std::mem::replace(&mut entry.value, value)),
+            None => {
+                inner
+                    .entries
+                    .insert(key.clone(), WindowedEntry { value, count: 0 });
+                inner.buckets[0].insert(key.clone());
+                None
+            }
+        };
+        inner.increment(&key);
+        inner.record_access();
+        Ok(previous)
+    }
+}
+
+impl<K: Eq + Hash + Clone + Send, V: Send + Sync> Clone for WindowedLFUCache<K, V> {
+    /// Get another handle to the same cache, sharing its items, settings and statistics.
+    fn clone(&self) -> Self {
+        WindowedLFUCache {
+            inner: self.inner.clone(),
+            access_listeners: self.access_listeners.clone(),
+            latency: self.latency.clone(),
+        }
+    }
+}
+
+impl<K: Eq + Hash + Clone + Sync + Send, V: Send + Sync> Cache<K, V> for WindowedLFUCache<K, V> {
+    /// Get a value from the cache.
+    fn get(&self, key: &K) -> Option<Arc<V>> {
+        let started = self.latency.start();
+        let result = {
+            let mut inner = self.inner.lock().unwrap();
+            inner.purge_expired();
+            let result = inner.entries.get(key).map(|entry| entry.value.clone());
+
+            if result.is_some() {
+                inner.core.stats.record_hit();
+                inner.increment(key);
+                let now = inner.core.clock.now();
+                inner.core.metadata.touch(key, now);
+            } else {
+                inner.core.stats.record_miss();
+            }
+            inner.record_access();
+            result
+        };
+        self.latency.record_get(started);
+        self.access_listeners.notify(key, &result);
+        result
+    }
+
+    /// Get a value from the cache, with whether it was a hit, or a miss because the key expired, was rejected or is absent.
+    fn get_with_outcome(&self, key: &K) -> (Option<Arc<V>>, GetOutcome) {
+        let result = self.get(key);
+        let outcome = match result {
+            Some(_) => GetOutcome::Hit,
+            None => self.inner.lock().unwrap().core.listeners.miss_outcome(key),
+        };
+        (result, outcome)
+    }
+
+    /// Set a value in the cache.
+    fn set(&self, key: K, value: V) -> Option<Arc<V>> {
+        let started = self.latency.start();
+        let result = self
+            .insert(
+                key,
+                Arc::new(value),
+                None,
+                None,
+                None,
+                None,
+                EntrySource::Inserted,
+            )
+            .unwrap_or(None);
+        self.latency.record_set(started);
+        result
+    }
+
+    /// Set a value in the cache, recording how it came to be in the cache for `entry_info` and eviction listeners.
+    fn set_with_source(&self, key: K, value: V, source: EntrySource) -> Option<Arc<V>> {
+        let started = self.latency.start();
+        let result = self
+            .insert(key, Arc::new(value), None, None, None, None, source)
+            .unwrap_or(None);
+        self.latency.record_set(started);
+        result
+    }
+
+    /// Remove a value from the cache.
+    fn remove(&self, key: &K) -> Option<Arc<V>> {
+        let mut inner = self.inner.lock().unwrap();
+        let result = inner.remove_entry(key);
+        if let Some(value) = &result {
+            inner
+                .core
+                .listeners
+                .notify(key, value, RemovalCause::Removed);
+        }
+        result
+    }
+
+    /// Check if a key is in the cache and has not expired, without counting a hit or miss or updating its count.
+    fn contains_key(&self, key: &K) -> bool {
+        let inner = self.inner.lock().unwrap();
+        inner.entries.contains_key(key)
+            && !inner.core.expiries.is_expired(key, inner.core.clock.now())
+    }
+
+    /// Get the time left before an item expires, or None if it never expires or is not in the cache.
+    fn ttl_remaining(&self, key: &K) -> Option<Duration> {
+        self.entry_info(key)?.ttl_remaining
+    }
+
+    /// Clear the cache.
+    fn clear(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.clear_entries();
+    }
+
+    /// Clear the cache, returning every item which was removed.
+    fn clear_returning(&self) -> Vec<(K, Arc<V>)> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.clear_entries()
+    }
+
+    /// Copy the unexpired items into a HashMap in one locked pass.
+    fn to_map(&self) -> HashMap<K, Arc<V>> {
+        let inner = self.inner.lock().unwrap();
+        let now = inner.core.clock.now();
+        inner
+            .entries
+            .iter()
+            .filter(|(key, _)| !inner.core.expiries.is_expired(key, now))
+            .map(|(key, entry)| (key.clone(), entry.value.clone()))
+            .collect()
+    }
+
+    /// Get up to `limit` unexpired items, ordered with the items the policy would evict last first. Higher priority items come before lower ones, and otherwise highest count first, ties are broken by most recently counted.
+    fn hottest(&self, limit: usize) -> Vec<(K, Arc<V>)> {
+        let inner = self.inner.lock().unwrap();
+        let now = inner.core.clock.now();
+        let mut items: Vec<(K, Arc<V>)> = inner
+            .buckets
+            .iter()
+            .rev()
+            .flat_map(|bucket| bucket.iter().rev())
+            .filter(|key| !inner.core.expiries.is_expired(key, now))
+            .filter_map(|key| {
+                inner
+                    .entries
+                    .get(key)
+                    .map(|entry| (key.clone(), entry.value.clone()))
+            })
+            .collect();
+        inner.core.priorities.sort_descending(&mut items);
+        items.truncate(limit);
+        items
+    }
+
+    /// Get the unexpired items in frequency order, lowest count first and in eviction order within a count, or in any order.
+    fn items(&self, order: IterationOrder) -> Option<Vec<(K, Arc<V>)>> {
+        if !matches!(order, IterationOrder::Frequency | IterationOrder::Arbitrary) {
+            return None;
+        }
+        let inner = self.inner.lock().unwrap();
+        let now = inner.core.clock.now();
+        let items = inner
+            .buckets
+            .iter()
+            .flatten()
+            .filter(|key| !inner.core.expiries.is_expired(key, now))
+            .filter_map(|key| {
+                inner
+                    .entries
+                    .get(key)
+                    .map(|entry| (key.clone(), entry.value.clone()))
+            })
+            .collect();
+        Some(items)
+    }
+
+    /// Get the name of the eviction policy.
+    fn policy_name(&self) -> &'static str {
+        "windowed_lfu"
+    }
+
+    /// Get the cache as `Any`, so it can be downcast from a `dyn Cache`.
+    fn as_any(&self) -> Option<&dyn Any>
+    where
+        Self: 'static,
+    {
+        Some(self)
+    }
+
+    /// Get cache statistics.
+    fn stats(&self) -> CacheStats {
+        let inner = self.inner.lock().unwrap();
+        inner
+            .core
+            .stats
+            .snapshot(inner.entries.len() as u64, inner.capacity)
+    }
+
+    /// Get the cache statistics together with how soon the items expire.
+    fn detailed_stats(&self) -> DetailedStats {
+        let inner = self.inner.lock().unwrap();
+        let size = inner.entries.len() as u64;
+        let mut detailed = DetailedStats::new(inner.core.stats.snapshot(size, inner.capacity));
+        detailed.eviction_ages = Some(inner.core.stats.eviction_ages());
+        detailed.expiry_distribution = Some(
+            inner
+                .core
+                .expiries
+                .distribution(inner.core.clock.now(), size),
+        );
+        detailed
+    }
+
+    /// Change the capacity of the cache, if the new capacity is smaller than the current size, the items with the lowest counts are removed.
+    fn change_capacity(&self, capacity: u64) {
+        self.change_capacity_collect(capacity);
+    }
+
+    /// Change the capacity of the cache, returning the items which were removed to fit the new capacity.
+    fn change_capacity_collect(&self, capacity: u64) -> Vec<(K, Arc<V>)> {
+        let mut inner = self.inner.lock().unwrap();
+        let old_capacity = inner.capacity;
+        inner.capacity = capacity;
+        let evicted = inner.evict_to(capacity as usize);
+
+        if inner.capacity > old_capacity {
+            let additional = (inner.capacity - old_capacity) as usize;
+            inner.entries.reserve(additional);
+        }
+        evicted
+    }
+
+    /// Evict `fraction` of the items per the policy in one locked pass, leaving the capacity unchanged.
+    fn shed(&self, fraction: f64) -> usize {
+        let mut inner = self.inner.lock().unwrap();
+        let len = inner.entries.len();
+        inner.evict_to(len - shed_count(len, fraction)).len()
+    }
+}
+
+impl<K: Eq + Hash + Clone + Sync + Send, V: Send + Sync> TryCache<K, V> for WindowedLFUCache<K, V> {
+    /// Get a value from the cache, returning `CacheError::Poisoned` instead of panicking if the cache lock is poisoned.
+    fn try_get(&self, key: &K) -> Result<Option<Arc<V>>, CacheError> {
+        if self.inner.is_poisoned() {
+            return Err(CacheError::Poisoned);
+        }
+        Ok(self.get(key))
+    }
+
+    /// Set a value in the cache, returning an error instead of inserting if the cache is full and was built with `FullBehavior::ReturnError`, has a capacity of zero, or its lock is poisoned.
+    fn try_set(&self, key: K, value: V) -> Result<Option<Arc<V>>, CacheError> {
+        let started = self.latency.start();
+        let result = self.insert(
+            key,
+            Arc::new(value),
+            None,
+            None,
+            None,
+            None,
+            EntrySource::Inserted,
+        );
+        self.latency.record_set(started);
+        result
+    }
+}
+
+impl<K: Eq + Hash + Clone + Sync + Send, V: Send + Sync> VersionedCache<K, V>
+    for WindowedLFUCache<K, V>
+{
+    /// Get the current version of an item, without counting a hit or miss or updating its position.
+    fn version(&self, key: &K) -> Option<u64> {
+        let inner = self.inner.lock().unwrap();
+        if inner.core.expiries.is_expired(key, inner.core.clock.now()) {
+            return None;
+        }
+        inner.core.metadata.version(key)
+    }
+
+    /// Set a value only if the item is at `expected_version`, or is not in the cache if it is 0.
+    fn set_versioned(
+        &self,
+        key: K,
+        value: V,
+        expected_version: u64,
+    ) -> Result<Option<Arc<V>>, CacheError> {
+        let started = self.latency.start();
+        let result = self.insert(
+            key,
+            Arc::new(value),
+            None,
+            None,
+            None,
+            Some(expected_version),
+            EntrySource::Inserted,
+        );
+        self.latency.record_set(started);
+        result
+    }
+}
+
+impl<K: Eq + Hash + Clone + Sync + Send, V: Send + Sync> IntoIterator for WindowedLFUCache<K, V> {
+    type Item = (K, Arc<V>);
+    type IntoIter = std::vec::IntoIter<(K, Arc<V>)>;
+
+    /// Consume the handle, yielding the cache's unexpired items in no particular order. Other handles still hold the items.
+    fn into_iter(self) -> Self::IntoIter {
+        let inner = self.inner.lock().unwrap();
+        let now = inner.core.clock.now();
+        let items: Vec<(K, Arc<V>)> = inner
+            .entries
+            .iter()
+            .filter(|(key, _)| !inner.core.expiries.is_expired(key, now))
+            .map(|(key, entry)| (key.clone(), entry.value.clone()))
+            .collect();
+        items.into_iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_windowed_lfu_cache() {
+        let cache = WindowedLFUCache::new(2);
+        cache.set(1, 1);
+        cache.set(2, 2);
+        assert_eq!(cache.get(&1).map(|v| *v), Some(1));
+        cache.set(3, 3);
+        assert_eq!(cache.get(&2), None);
+        assert_eq!(cache.get(&1).map(|v| *v), Some(1));
+        assert_eq!(cache.get(&3).map(|v| *v), Some(3));
+    }
+
+    #[test]
+    fn test_windowed_lfu_cache_counts_saturate() {
+        let cache = WindowedLFUCache::new(2).with_window(1_000);
+        cache.set(1, 1);
+        for _ in 0..100 {
+            cache.get(&1);
+        }
+        assert_eq!(cache.frequency(&1), Some(MAX_COUNT));
+        assert_eq!(cache.frequency(&2), None);
+    }
+
+    #[test]
+    fn test_windowed_lfu_cache_adapts_to_popularity_shift() {
+        let cache = WindowedLFUCache::new(2).with_window(20);
+        cache.set(1, 1);
+        for _ in 0..10 {
+            cache.get(&1);
+        }
+        // key 1 is no longer requested, a few windows later key 2 has overtaken it
+        cache.set(2, 2);
+        for _ in 0..60 {
+            cache.get(&2);
+        }
+        assert!(cache.frequency(&2) > cache.frequency(&1));
+        cache.set(3, 3);
+        assert_eq!(cache.get(&1), None);
+        assert_eq!(cache.get(&2).map(|v| *v), Some(2));
+        assert_eq!(cache.get(&3).map(|v| *v), Some(3));
+    }
+}
@@ -0,0 +1,130 @@
+//! Clock sources for the timestamps caches keep, such as TTL deadlines and last access times.
+use std::fmt;
+// the ticker is a real thread keeping real time, which loom cannot model
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::background::BackgroundTask;
+
+/// Clock is the source of the current time for a cache.
+///
+/// Caches read the clock on gets and sets to check TTLs and record access times, so a cheaper clock than `Instant::now` can be plugged in with `with_clock`. Times must never go backwards.
+pub trait Clock: Send + Sync {
+    /// Get the current time.
+    fn now(&self) -> Instant;
+}
+
+// about a century, as good as never for a cache
+const FOREVER: Duration = Duration::from_secs(100 * 365 * 24 * 60 * 60);
+
+/// Get the instant `after` past `at`, or about a century past it if that cannot be represented, so a TTL such as `Duration::MAX` means never rather than a panic.
+pub(crate) fn saturating_add(at: Instant, after: Duration) -> Instant {
+    at.checked_add(after)
+        .or_else(|| at.checked_add(FOREVER))
+        .unwrap_or(at)
+}
+
+/// SystemClock reads `Instant::now` every time, it is the clock caches use by default.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// The time shared between a CoarseClock and its ticker thread.
+struct CoarseTime {
+    epoch: Instant,
+    elapsed_nanos: AtomicU64,
+}
+
+impl CoarseTime {
+    fn update(&self) {
+        let elapsed = self.epoch.elapsed().as_nanos() as u64;
+        self.elapsed_nanos.fetch_max(elapsed, Ordering::Relaxed);
+    }
+}
+
+/// CoarseClock is a clock updated by a background thread every `resolution`, so reading it is a single atomic load.
+///
+/// The time it reports lags behind the real time by up to the resolution, so TTLs may end up to one resolution early and access times may be that much stale. Clones share the same ticker thread, which stops once every clone has been dropped.
+///
+/// Example:
+/// ```
+/// use arcache::clock::{Clock, CoarseClock};
+/// use arcache::{Cache, LRUCache};
+/// use std::time::Duration;
+///
+/// let clock = CoarseClock::new(Duration::from_millis(5));
+/// let cache = LRUCache::<&str, String>::new(10)
+///     .with_ttl(Duration::from_secs(60))
+///     .with_clock(clock.clone());
+///
+/// cache.set("key", "value".to_string());
+/// assert!(cache.get(&"key").is_some());
+/// assert!(clock.now() <= std::time::Instant::now());
+/// ```
+#[derive(Clone)]
+pub struct CoarseClock {
+    time: Arc<CoarseTime>,
+    _ticker: Arc<BackgroundTask>,
+}
+
+impl CoarseClock {
+    /// Create a new CoarseClock whose ticker thread updates it every `resolution`.
+    pub fn new(resolution: Duration) -> Self {
+        let time = Arc::new(CoarseTime {
+            epoch: Instant::now(),
+            elapsed_nanos: AtomicU64::new(0),
+        });
+        let ticker_time = Arc::downgrade(&time);
+        let ticker =
+            BackgroundTask::spawn_periodic("arcache-coarse-clock", resolution, move || {
+                match ticker_time.upgrade() {
+                    Some(time) => {
+                        time.update();
+                        true
+                    }
+                    None => false,
+                }
+            });
+        CoarseClock {
+            time,
+            _ticker: Arc::new(ticker),
+        }
+    }
+}
+
+impl Clock for CoarseClock {
+    fn now(&self) -> Instant {
+        self.time.epoch + Duration::from_nanos(self.time.elapsed_nanos.load(Ordering::Relaxed))
+    }
+}
+
+impl fmt::Debug for CoarseClock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CoarseClock")
+            .field("now", &self.now())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn test_coarse_clock() {
+        let clock = CoarseClock::new(Duration::from_millis(1));
+        let start = clock.now();
+        assert!(start <= Instant::now());
+        while clock.now() < start + Duration::from_millis(5) {
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert!(clock.now() <= Instant::now());
+    }
+}
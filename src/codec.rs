@@ -0,0 +1,145 @@
+//! Codecs which turn values into bytes and back, for every part of the crate which stores values as bytes, such as `SerializedCache` and snapshots.
+#[cfg(feature = "serde")]
+use serde::{de::DeserializeOwned, Serialize};
+use std::fmt;
+
+use crate::error::CacheError;
+
+/// SerializationError is returned when a value cannot be encoded to bytes or decoded from them.
+#[derive(Debug)]
+pub struct SerializationError(pub(crate) String);
+
+impl SerializationError {
+    /// Create a new SerializationError with a reason, for use in custom codecs.
+    pub fn new(reason: impl Into<String>) -> Self {
+        SerializationError(reason.into())
+    }
+}
+
+impl fmt::Display for SerializationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "failed to serialize value: {}", self.0)
+    }
+}
+
+impl std::error::Error for SerializationError {}
+
+impl From<SerializationError> for CacheError {
+    fn from(error: SerializationError) -> Self {
+        CacheError::SerializationFailed(error.0)
+    }
+}
+
+/// Codec encodes values of type `T` to bytes and decodes them again.
+///
+/// Implement it to store values in a format of your choosing, such as protobuf, or to compress them on the way. `CborCodec` is provided with the `serde` feature, `JsonCodec` with the `json` feature and `BincodeCodec` with the `bincode` feature.
+///
+/// Example:
+/// ```
+/// use arcache::codec::{Codec, SerializationError};
+///
+/// struct U64Codec;
+///
+/// impl Codec<u64> for U64Codec {
+///     fn encode(&self, value: &u64) -> Result<Vec<u8>, SerializationError> {
+///         Ok(value.to_le_bytes().to_vec())
+///     }
+///
+///     fn decode(&self, bytes: &[u8]) -> Result<u64, SerializationError> {
+///         let bytes = bytes
+///             .try_into()
+///             .map_err(|_| SerializationError::new("expected 8 bytes"))?;
+///         Ok(u64::from_le_bytes(bytes))
+///     }
+/// }
+///
+/// let bytes = U64Codec.encode(&7).unwrap();
+/// assert_eq!(U64Codec.decode(&bytes).unwrap(), 7);
+/// assert!(U64Codec.decode(&[1, 2]).is_err());
+/// ```
+pub trait Codec<T>: Send + Sync {
+    /// Encode a value to bytes.
+    fn encode(&self, value: &T) -> Result<Vec<u8>, SerializationError>;
+
+    /// Decode a value from bytes.
+    fn decode(&self, bytes: &[u8]) -> Result<T, SerializationError>;
+}
+
+/// CborCodec encodes values as CBOR, it is the codec `SerializedCache` and snapshots use by default.
+///
+/// This requires the `serde` feature.
+#[cfg(feature = "serde")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CborCodec;
+
+#[cfg(feature = "serde")]
+impl<T: Serialize + DeserializeOwned> Codec<T> for CborCodec {
+    fn encode(&self, value: &T) -> Result<Vec<u8>, SerializationError> {
+        let mut bytes = Vec::new();
+        ciborium::into_writer(value, &mut bytes)
+            .map_err(|error| SerializationError(error.to_string()))?;
+        Ok(bytes)
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<T, SerializationError> {
+        ciborium::from_reader(bytes).map_err(|error| SerializationError(error.to_string()))
+    }
+}
+
+/// JsonCodec encodes values as JSON, which is larger than CBOR but readable when inspecting stored bytes.
+///
+/// This requires the `json` feature.
+#[cfg(feature = "json")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct JsonCodec;
+
+#[cfg(feature = "json")]
+impl<T: Serialize + DeserializeOwned> Codec<T> for JsonCodec {
+    fn encode(&self, value: &T) -> Result<Vec<u8>, SerializationError> {
+        serde_json::to_vec(value).map_err(|error| SerializationError(error.to_string()))
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<T, SerializationError> {
+        serde_json::from_slice(bytes).map_err(|error| SerializationError(error.to_string()))
+    }
+}
+
+/// BincodeCodec encodes values with bincode, a compact format which is fast to encode and decode but not self-describing.
+///
+/// This requires the `bincode` feature.
+#[cfg(feature = "bincode")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BincodeCodec;
+
+#[cfg(feature = "bincode")]
+impl<T: Serialize + DeserializeOwned> Codec<T> for BincodeCodec {
+    fn encode(&self, value: &T) -> Result<Vec<u8>, SerializationError> {
+        bincode::serialize(value).map_err(|error| SerializationError(error.to_string()))
+    }
+
+    fn decode(&self, bytes: &[u8]) -> Result<T, SerializationError> {
+        bincode::deserialize(bytes).map_err(|error| SerializationError(error.to_string()))
+    }
+}
+
+#[cfg(all(test, feature = "serde"))]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn round_trip(codec: &dyn Codec<HashMap<String, Vec<u32>>>) {
+        let value = HashMap::from([("a".to_string(), vec![1, 2, 3])]);
+        let bytes = codec.encode(&value).unwrap();
+        assert_eq!(codec.decode(&bytes).unwrap(), value);
+        assert!(codec.decode(&[0xff, 0x00]).is_err());
+    }
+
+    #[test]
+    fn test_codecs_round_trip() {
+        round_trip(&CborCodec);
+        #[cfg(feature = "json")]
+        round_trip(&JsonCodec);
+        #[cfg(feature = "bincode")]
+        round_trip(&BincodeCodec);
+    }
+}
@@ -0,0 +1,50 @@
+//! Errors returned by fallible cache operations.
+use std::fmt;
+
+/// CacheError is returned by fallible cache operations such as `try_get` and `try_set`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum CacheError {
+    /// The cache is full and was built with `FullBehavior::ReturnError`, so the new item was not inserted.
+    Full,
+    /// A thread panicked while holding the cache lock, so the cache's state may be inconsistent. This is never returned with the `parking_lot` feature enabled, as its locks are not poisoned.
+    Poisoned,
+    /// The cache has a capacity of zero, so it cannot hold any item.
+    CapacityZero,
+    /// The admission policy of an `AdmissionCache` rejected the new item.
+    RejectedByAdmission,
+    /// A value could not be serialized or deserialized, with the reason.
+    SerializationFailed(String),
+    /// The store behind the cache could not provide a value, for example a loader timed out or panicked.
+    StoreUnavailable,
+    /// A versioned set found the item at another version, with the version it found, or 0 if the item was not in the cache.
+    VersionMismatch(u64),
+    /// Too many loads were in flight to start another, so the item was not loaded.
+    Overloaded,
+    /// A callback such as a weigher panicked while the cache isolates callback panics with `CallbackPanics`, so the operation was abandoned.
+    CallbackPanicked,
+}
+
+impl fmt::Display for CacheError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CacheError::Full => write!(f, "the cache is full"),
+            CacheError::Poisoned => write!(f, "the cache lock is poisoned"),
+            CacheError::CapacityZero => write!(f, "the cache has a capacity of zero"),
+            CacheError::RejectedByAdmission => {
+                write!(f, "the item was rejected by the admission policy")
+            }
+            CacheError::SerializationFailed(reason) => {
+                write!(f, "failed to serialize value: {}", reason)
+            }
+            CacheError::StoreUnavailable => write!(f, "the backing store is unavailable"),
+            CacheError::VersionMismatch(actual) => {
+                write!(f, "the item is at version {}", actual)
+            }
+            CacheError::Overloaded => write!(f, "too many loads are in flight"),
+            CacheError::CallbackPanicked => write!(f, "a cache callback panicked"),
+        }
+    }
+}
+
+impl std::error::Error for CacheError {}
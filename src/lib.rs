@@ -1,12 +1,67 @@
+pub mod background;
 pub mod cache;
+pub mod clock;
+pub mod codec;
+pub mod error;
+pub mod registry;
+pub mod sketch;
+pub mod snapshot;
+pub mod sync;
+#[cfg(any(test, feature = "test_support"))]
+pub mod test_support;
+pub use crate::background::BackgroundTask;
+pub use crate::cache::admission::{
+    AdmissionCache, AdmissionPolicy, Doorkeeper, FrequencyAdmission,
+};
+pub use crate::cache::autotune::CapacityTuner;
+pub use crate::cache::bloom_filter::BloomFilterCache;
+pub use crate::cache::clock_cache::ClockCache;
+pub use crate::cache::clock_pro::{ClockProCache, ClockProStats};
 pub use crate::cache::fifo::FIFOCache;
-pub use crate::cache::lfu::LFUCache;
+pub use crate::cache::gdsf::GDSFCache;
+pub use crate::cache::guard::EntryGuard;
+pub use crate::cache::identity::CacheIdentity;
+pub use crate::cache::interned::{InternedCache, Interner};
+pub use crate::cache::invalidation::{
+    InvalidatingCache, Invalidation, InvalidationBus, InvalidationHandler, InvalidationMessage,
+    LocalInvalidationBus,
+};
+pub use crate::cache::latency::{LatencyStats, OperationLatencies};
+pub use crate::cache::leak::{LeakDetector, LeakReport};
+pub use crate::cache::lfu::{LFUCache, LFUOverwrite, LFUTieBreaking};
 pub use crate::cache::lifo::LIFOCache;
+pub use crate::cache::listener::{
+    AccessListener, AccessOutcome, CallbackPanics, EntrySource, EvictionListener, RemovalCause,
+    RemovalEvent,
+};
+pub use crate::cache::loading::{LoadStats, LoadingCache, OverloadBehavior};
 pub use crate::cache::lru::LRUCache;
+pub use crate::cache::metadata::{EntryInfo, EntryMetadata};
+#[cfg(feature = "mmap")]
+pub use crate::cache::mmap_store::MmapStore;
 pub use crate::cache::mru::MRUCache;
+pub use crate::cache::negative::NegativeCache;
+pub use crate::cache::nested::{GroupStats, NestedCache};
+pub use crate::cache::partitioned::PartitionedCache;
+pub use crate::cache::priority::DEFAULT_PRIORITY;
+pub use crate::cache::quota::QuotaCache;
 pub use crate::cache::random_replacement::RandomReplacementCache;
+pub use crate::cache::serialized::SerializedCache;
+pub use crate::cache::thread_local::ThreadLocalCache;
 pub use crate::cache::ttl::TTLCache;
-pub use crate::cache::Cache;
+pub use crate::cache::two_q::TwoQCache;
+pub use crate::cache::windowed_lfu::WindowedLFUCache;
+pub use crate::cache::{
+    AgeDistribution, Cache, CacheStats, DetailedStats, DynCache, EvictionAges, ExpiryDistribution,
+    FullBehavior, GetOutcome, IterationOrder, TryCache, VersionedCache,
+};
+pub use crate::clock::{Clock, CoarseClock, SystemClock};
+pub use crate::codec::{Codec, SerializationError};
+pub use crate::error::CacheError;
+pub use crate::registry::{CacheRegistry, RegisteredCache};
+#[cfg(feature = "serde")]
+pub use crate::snapshot::{load_snapshot, save_snapshot};
+pub use crate::snapshot::{load_snapshot_with_codec, save_snapshot_with_codec, SnapshotError};
 
 #[doc = include_str!("../README.md")]
 #[cfg(doctest)]
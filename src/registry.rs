@@ -0,0 +1,529 @@
+//! A registry of named caches, so that caches of different types can be observed and managed together.
+use crate::sync::{Mutex, RwLock};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::hash::Hash;
+use std::io::{self, Write};
+use std::marker::PhantomData;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::background::BackgroundTask;
+use crate::cache::partitioned::shares;
+use crate::cache::{Cache, CacheStats};
+
+/// RegisteredCache is the view of a cache held by a `CacheRegistry`, it does not depend on the key and value types.
+pub trait RegisteredCache: Send + Sync {
+    /// Get the name of the cache's eviction policy.
+    fn policy_name(&self) -> &'static str;
+
+    /// Get the cache statistics.
+    fn stats(&self) -> CacheStats;
+
+    /// Evict `fraction` of the items per the cache's policy, returning how many were evicted.
+    fn shed(&self, fraction: f64) -> usize;
+
+    /// Change the capacity of the cache, evicting items per its policy if it shrinks.
+    fn change_capacity(&self, capacity: u64);
+
+    /// Describe up to `limit` of the hottest keys, empty if the keys cannot be described.
+    fn hottest_keys(&self, _limit: usize) -> Vec<String> {
+        Vec::new()
+    }
+}
+
+/// Adapts a typed cache to `RegisteredCache`.
+struct Registered<C: ?Sized, K, V> {
+    cache: Arc<C>,
+    describe_key: Option<fn(&K) -> String>,
+    _marker: PhantomData<fn() -> (K, V)>,
+}
+
+impl<C, K, V> RegisteredCache for Registered<C, K, V>
+where
+    C: Cache<K, V> + ?Sized,
+    K: Eq + Hash + Clone + Send + Sync,
+    V: Send + Sync,
+{
+    fn policy_name(&self) -> &'static str {
+        self.cache.policy_name()
+    }
+
+    fn stats(&self) -> CacheStats {
+        self.cache.stats()
+    }
+
+    fn shed(&self, fraction: f64) -> usize {
+        self.cache.shed(fraction)
+    }
+
+    fn change_capacity(&self, capacity: u64) {
+        self.cache.change_capacity(capacity)
+    }
+
+    fn hottest_keys(&self, limit: usize) -> Vec<String> {
+        match self.describe_key {
+            Some(describe_key) => self
+                .cache
+                .hottest(limit)
+                .iter()
+                .map(|(key, _)| describe_key(key))
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+}
+
+/// CacheRegistry holds named caches of any type so they can be observed and shed together, for example when the process is under memory pressure.
+///
+/// Example:
+/// ```
+/// use arcache::{Cache, CacheRegistry, LFUCache, LRUCache};
+/// use std::sync::Arc;
+///
+/// let users = Arc::new(LRUCache::<u64, String>::new(100));
+/// let pages = Arc::new(LFUCache::<String, Vec<u8>>::new(100));
+///
+/// let registry = CacheRegistry::new();
+/// registry.register("users", users.clone());
+/// registry.register("pages", pages.clone());
+///
+/// for i in 0..10 {
+///     users.set(i, i.to_string());
+/// }
+///
+/// // free half of every cache
+/// assert_eq!(registry.shed_all(0.5), 5);
+/// assert_eq!(users.stats().size, 5);
+/// ```
+pub struct CacheRegistry {
+    caches: RwLock<BTreeMap<String, Arc<dyn RegisteredCache>>>,
+    budgets: Mutex<BTreeMap<String, BudgetState>>,
+}
+
+/// What budget arbitration remembers about a registered cache between rebalances.
+#[derive(Default)]
+struct BudgetState {
+    item_bytes: Option<u64>,
+    last: CacheStats,
+}
+
+impl CacheRegistry {
+    /// Create an empty registry.
+    pub fn new() -> Self {
+        CacheRegistry {
+            caches: RwLock::new(BTreeMap::new()),
+            budgets: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Register a cache under `name`, replacing any cache already registered with that name.
+    ///
+    /// The cache may be a `DynCache` whose type is only known at runtime.
+    pub fn register<C, K, V>(&self, name: impl Into<String>, cache: Arc<C>)
+    where
+        C: Cache<K, V> + ?Sized + 'static,
+        K: Eq + Hash + Clone + Send + Sync + 'static,
+        V: Send + Sync + 'static,
+    {
+        self.insert(name.into(), cache, None);
+    }
+
+    /// Register a cache under `name` like `register`, also listing its hottest keys in `dump`.
+    pub fn register_debug<C, K, V>(&self, name: impl Into<String>, cache: Arc<C>)
+    where
+        C: Cache<K, V> + ?Sized + 'static,
+        K: Eq + Hash + Clone + Send + Sync + fmt::Debug + 'static,
+        V: Send + Sync + 'static,
+    {
+        self.insert(name.into(), cache, Some(|key: &K| format!("{:?}", key)));
+    }
+
+    fn insert<C, K, V>(&self, name: String, cache: Arc<C>, describe_key: Option<fn(&K) -> String>)
+    where
+        C: Cache<K, V> + ?Sized + 'static,
+        K: Eq + Hash + Clone + Send + Sync + 'static,
+        V: Send + Sync + 'static,
+    {
+        let registered = Registered {
+            cache,
+            describe_key,
+            _marker: PhantomData,
+        };
+        self.budgets.lock().unwrap().remove(&name);
+        self.caches
+            .write()
+            .unwrap()
+            .insert(name, Arc::new(registered));
+    }
+
+    /// Remove a cache from the registry, returning whether it was registered.
+    pub fn unregister(&self, name: &str) -> bool {
+        self.budgets.lock().unwrap().remove(name);
+        self.caches.write().unwrap().remove(name).is_some()
+    }
+
+    /// Set the estimated size in bytes of an item of a registered cache, used by `rebalance_budget`, returning whether the cache is registered.
+    ///
+    /// Caches without an estimate count each item as one byte, so a budget over only those caches is a number of items.
+    pub fn set_item_bytes(&self, name: &str, item_bytes: u64) -> bool {
+        if !self.caches.read().unwrap().contains_key(name) {
+            return false;
+        }
+        let mut budgets = self.budgets.lock().unwrap();
+        budgets.entry(name.to_string()).or_default().item_bytes = Some(item_bytes.max(1));
+        true
+    }
+
+    /// Divide a memory budget of `budget_bytes` between the registered caches and resize them to fit, returning the new capacities ordered by name.
+    ///
+    /// Caches are compared over the lookups since the previous rebalance. A cache which did not evict holds everything it was asked for, so it keeps room for its current items. The rest of the budget goes to the caches which evicted, in proportion to their misses, as those are the caches a larger capacity would have served more hits. Misses stand in for that gain, no ghost entries or miss ratio curve are kept, so misses on keys a cache never held, which no capacity would have turned into hits, draw budget just like misses on keys it evicted. Every cache keeps a capacity of at least one, and caches which shrink are resized first.
+    pub fn rebalance_budget(&self, budget_bytes: u64) -> Vec<(String, u64)> {
+        let caches = self.snapshot();
+        let mut item_bytes = Vec::with_capacity(caches.len());
+        let mut reserved = Vec::with_capacity(caches.len());
+        let mut weights = Vec::with_capacity(caches.len());
+        let mut capacities = Vec::with_capacity(caches.len());
+        {
+            let mut budgets = self.budgets.lock().unwrap();
+            for (name, cache) in &caches {
+                let stats = cache.stats();
+                let state = budgets.entry(name.clone()).or_default();
+                let bytes = state.item_bytes.unwrap_or(1);
+                let misses = stats.misses.saturating_sub(state.last.misses);
+                let evicted = stats.evictions > state.last.evictions;
+                item_bytes.push(bytes);
+                reserved.push(if evicted {
+                    0
+                } else {
+                    stats.size.saturating_mul(bytes)
+                });
+                weights.push(if evicted { misses.saturating_add(1) } else { 0 });
+                capacities.push(stats.capacity);
+            }
+        }
+        if saturating_sum(&reserved) > budget_bytes {
+            reserved = shares(budget_bytes, &reserved);
+        }
+        if weights.iter().all(|&weight| weight == 0) {
+            // nothing evicted, so spread the headroom evenly
+            weights.fill(1);
+        }
+        let spare = shares(
+            budget_bytes.saturating_sub(saturating_sum(&reserved)),
+            &weights,
+        );
+        let targets: Vec<u64> = (0..caches.len())
+            .map(|i| {
+                let spare = if weights[i] == 0 { 0 } else { spare[i] };
+                (reserved[i].saturating_add(spare) / item_bytes[i]).max(1)
+            })
+            .collect();
+        // resize outside the lock so eviction listeners may use the registry, shrinking first
+        let mut order: Vec<usize> = (0..caches.len()).collect();
+        order.sort_by_key(|&i| targets[i] >= capacities[i]);
+        let mut after = vec![CacheStats::default(); caches.len()];
+        for i in order {
+            caches[i].1.change_capacity(targets[i]);
+            after[i] = caches[i].1.stats();
+        }
+        // evictions from shrinking are not demand, so the next window starts after them
+        let mut budgets = self.budgets.lock().unwrap();
+        for ((name, _), stats) in caches.iter().zip(after) {
+            if let Some(state) = budgets.get_mut(name) {
+                state.last = stats;
+            }
+        }
+        caches
+            .into_iter()
+            .map(|(name, _)| name)
+            .zip(targets)
+            .collect()
+    }
+
+    /// Start a background thread which calls `rebalance_budget` every `interval`.
+    ///
+    /// The thread runs until the returned handle is dropped or shut down, or the registry is dropped.
+    pub fn start_budget_arbitration(
+        self: &Arc<Self>,
+        budget_bytes: u64,
+        interval: Duration,
+    ) -> BackgroundTask {
+        let registry = Arc::downgrade(self);
+        BackgroundTask::spawn_periodic("arcache-budget-arbitration", interval, move || {
+            match registry.upgrade() {
+                Some(registry) => {
+                    registry.rebalance_budget(budget_bytes);
+                    true
+                }
+                None => false,
+            }
+        })
+    }
+
+    /// Get the names of the registered caches, in order.
+    pub fn names(&self) -> Vec<String> {
+        self.caches.read().unwrap().keys().cloned().collect()
+    }
+
+    /// Get a registered cache by name.
+    pub fn get(&self, name: &str) -> Option<Arc<dyn RegisteredCache>> {
+        self.caches.read().unwrap().get(name).cloned()
+    }
+
+    /// Get the statistics of every registered cache, ordered by name.
+    pub fn stats(&self) -> Vec<(String, CacheStats)> {
+        self.caches
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(name, cache)| (name.clone(), cache.stats()))
+            .collect()
+    }
+
+    /// Write a diagnostic report on every registered cache to `writer`, with up to `hottest` of the hottest keys of caches registered with `register_debug`.
+    ///
+    /// This is meant for live debugging, wire it to an admin endpoint or a signal handler. The format is for people and may change.
+    pub fn dump(&self, writer: &mut impl Write, hottest: usize) -> io::Result<()> {
+        let caches = self.snapshot();
+        writeln!(writer, "{} registered caches", caches.len())?;
+        for (name, cache) in caches {
+            writeln!(writer, "{}", stats_line(&name, cache.as_ref()))?;
+            let keys = cache.hottest_keys(hottest);
+            if !keys.is_empty() {
+                writeln!(writer, "  hottest: {}", keys.join(", "))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Evict `fraction` of the items of every registered cache, returning how many were evicted in total.
+    pub fn shed_all(&self, fraction: f64) -> usize {
+        // shed outside the registry lock so eviction listeners may use the registry
+        self.snapshot()
+            .iter()
+            .map(|(_, cache)| cache.shed(fraction))
+            .sum()
+    }
+
+    /// Copy the registered caches out of the lock, ordered by name.
+    fn snapshot(&self) -> Vec<(String, Arc<dyn RegisteredCache>)> {
+        self.caches
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(name, cache)| (name.clone(), cache.clone()))
+            .collect()
+    }
+
+    /// Start a background thread which calls `signal` every `interval` and sheds every cache by the fraction it returns.
+    ///
+    /// `signal` is where a memory pressure source, such as a cgroup memory limit, is wired in. It returns None while there is no pressure. The thread runs until the returned handle is dropped or shut down, or the registry is dropped.
+    pub fn watch_memory_pressure(
+        self: &Arc<Self>,
+        interval: Duration,
+        signal: impl Fn() -> Option<f64> + Send + 'static,
+    ) -> BackgroundTask {
+        let registry = Arc::downgrade(self);
+        BackgroundTask::spawn_periodic("arcache-memory-pressure", interval, move || {
+            let Some(registry) = registry.upgrade() else {
+                return false;
+            };
+            if let Some(fraction) = signal() {
+                registry.shed_all(fraction);
+            }
+            true
+        })
+    }
+
+    /// Start a background thread which logs the statistics of every registered cache every `interval`, at the given level with the `arcache` target.
+    ///
+    /// The thread runs until the returned handle is dropped or shut down, or the registry is dropped. This requires the `log` feature.
+    #[cfg(feature = "log")]
+    pub fn start_stats_logging(
+        self: &Arc<Self>,
+        interval: Duration,
+        level: log::Level,
+    ) -> BackgroundTask {
+        let registry = Arc::downgrade(self);
+        BackgroundTask::spawn_periodic("arcache-stats-logger", interval, move || {
+            let Some(registry) = registry.upgrade() else {
+                return false;
+            };
+            for (name, cache) in registry.snapshot() {
+                log::log!(target: "arcache", level, "{}", stats_line(&name, cache.as_ref()));
+            }
+            true
+        })
+    }
+}
+
+/// Describe the statistics of a cache in one line.
+fn stats_line(name: &str, cache: &dyn RegisteredCache) -> String {
+    let stats = cache.stats();
+    let lookups = stats.hits + stats.misses;
+    let hit_ratio = if lookups == 0 {
+        0.0
+    } else {
+        stats.hits as f64 / lookups as f64
+    };
+    format!(
+        "cache {} ({}): size {}/{}, {} hits, {} misses, hit ratio {:.3}",
+        name,
+        cache.policy_name(),
+        stats.size,
+        stats.capacity,
+        stats.hits,
+        stats.misses,
+        hit_ratio
+    )
+}
+
+impl Default for CacheRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Add up byte counts, stopping at `u64::MAX` rather than overflowing.
+fn saturating_sum(bytes: &[u64]) -> u64 {
+    bytes
+        .iter()
+        .fold(0, |total, &bytes| total.saturating_add(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DynCache, FIFOCache, LFUCache, LRUCache};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+
+    #[test]
+    fn test_registry() {
+        let lru = Arc::new(LRUCache::new(4));
+        let fifo = Arc::new(FIFOCache::new(4));
+        let registry = CacheRegistry::new();
+        registry.register("b", lru.clone());
+        registry.register("a", fifo.clone());
+        let dynamic: DynCache<u64, u64> = Arc::new(LFUCache::new(4));
+        registry.register("c", dynamic);
+        for key in 0..4 {
+            lru.set(key, key);
+            fifo.set(key.to_string(), key);
+        }
+        assert_eq!(registry.names(), vec!["a", "b", "c"]);
+        assert_eq!(registry.get("b").unwrap().policy_name(), "lru");
+        assert_eq!(registry.shed_all(0.5), 4);
+        assert_eq!(registry.stats()[0].1.size, 2);
+        assert!(registry.unregister("a"));
+        assert!(!registry.unregister("a"));
+    }
+
+    #[cfg(feature = "log")]
+    #[test]
+    fn test_registry_stats_logging() {
+        let cache = Arc::new(LRUCache::new(4));
+        cache.set(1, 1);
+        cache.get(&1);
+        cache.get(&2);
+        let registry = Arc::new(CacheRegistry::new());
+        registry.register("users", cache);
+        assert_eq!(
+            stats_line("users", registry.get("users").unwrap().as_ref()),
+            "cache users (lru): size 1/4, 1 hits, 1 misses, hit ratio 0.500"
+        );
+        let logger = registry.start_stats_logging(Duration::from_millis(10), log::Level::Info);
+        thread::sleep(Duration::from_millis(30));
+        logger.shutdown();
+    }
+
+    #[test]
+    fn test_registry_dump() {
+        let lru = Arc::new(LRUCache::new(4));
+        lru.set("a", 1);
+        lru.set("b", 2);
+        let registry = CacheRegistry::new();
+        registry.register_debug("lru", lru);
+        registry.register("fifo", Arc::new(FIFOCache::<u64, u64>::new(4)));
+        let mut report = Vec::new();
+        registry.dump(&mut report, 10).unwrap();
+        assert_eq!(
+            String::from_utf8(report).unwrap(),
+            "2 registered caches\n\
+             cache fifo (fifo): size 0/4, 0 hits, 0 misses, hit ratio 0.000\n\
+             cache lru (lru): size 2/4, 0 hits, 0 misses, hit ratio 0.000\n  \
+             hottest: \"b\", \"a\"\n"
+        );
+    }
+
+    #[test]
+    fn test_registry_rebalance_budget() {
+        let hot = Arc::new(LRUCache::new(10));
+        let idle = Arc::new(LRUCache::new(10));
+        let registry = CacheRegistry::new();
+        registry.register("hot", hot.clone());
+        registry.register("idle", idle.clone());
+        let cycle = || {
+            for key in 0..50 {
+                if hot.get(&key).is_none() {
+                    hot.set(key, key);
+                }
+            }
+        };
+        for key in 0..3 {
+            idle.set(key, key);
+        }
+        cycle();
+        assert_eq!(
+            registry.rebalance_budget(30),
+            vec![("hot".to_string(), 27), ("idle".to_string(), 3)]
+        );
+        assert_eq!(hot.stats().capacity, 27);
+        assert_eq!(idle.stats().capacity, 3);
+        assert!(registry.set_item_bytes("hot", 10));
+        assert!(!registry.set_item_bytes("missing", 10));
+        cycle();
+        assert_eq!(
+            registry.rebalance_budget(300),
+            vec![("hot".to_string(), 29), ("idle".to_string(), 3)]
+        );
+    }
+
+    #[test]
+    fn test_registry_rebalance_budget_huge_items() {
+        let cache = Arc::new(LRUCache::new(10));
+        let other = Arc::new(LRUCache::new(10));
+        for key in 0..3 {
+            cache.set(key, key);
+            other.set(key, key);
+        }
+        let registry = CacheRegistry::new();
+        registry.register("cache", cache.clone());
+        registry.register("other", other.clone());
+        registry.set_item_bytes("cache", u64::MAX);
+        registry.set_item_bytes("other", u64::MAX / 2);
+        assert_eq!(
+            registry.rebalance_budget(u64::MAX),
+            vec![("cache".to_string(), 1), ("other".to_string(), 2)]
+        );
+    }
+
+    #[test]
+    fn test_registry_watch_memory_pressure() {
+        let cache = Arc::new(LRUCache::new(10));
+        for key in 0..10 {
+            cache.set(key, key);
+        }
+        let registry = Arc::new(CacheRegistry::new());
+        registry.register("cache", cache.clone());
+        let pressure = Arc::new(AtomicBool::new(true));
+        let signal = pressure.clone();
+        let watcher = registry.watch_memory_pressure(Duration::from_millis(10), move || {
+            signal.swap(false, Ordering::Relaxed).then_some(0.5)
+        });
+        thread::sleep(Duration::from_millis(100));
+        watcher.shutdown();
+        assert_eq!(cache.stats().size, 5);
+    }
+}
@@ -0,0 +1,153 @@
+//! Probabilistic frequency estimation for building admission filters and custom policies.
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+const ROWS: usize = 4;
+const SEEDS: [u64; ROWS] = [
+    0x9e37_79b9_7f4a_7c15,
+    0xc2b2_ae3d_27d4_eb4f,
+    0x1656_67b1_9e37_79f9,
+    0x27d4_eb2f_1656_67c5,
+];
+const MAX_COUNT: u64 = 15;
+const RESET_MASK: u64 = 0x7777_7777_7777_7777;
+
+/// FrequencySketch is a count-min sketch of 4-bit counters which estimates how often keys have been seen.
+///
+/// Estimates can over count (when keys collide) but never under count, and saturate at 15. To keep the sketch biased towards recent activity all counters are halved once the number of increments reaches the sample size, this is the aging scheme used by TinyLFU.
+///
+/// Example:
+/// ```
+/// use arcache::sketch::FrequencySketch;
+///
+/// let mut sketch = FrequencySketch::new(100);
+///
+/// sketch.increment(&"key");
+/// sketch.increment(&"key");
+///
+/// assert_eq!(sketch.frequency(&"key"), 2);
+/// assert_eq!(sketch.frequency(&"other"), 0);
+/// ```
+#[derive(Debug, Clone)]
+pub struct FrequencySketch {
+    table: Vec<u64>,
+    width: usize,
+    sample_size: u64,
+    additions: u64,
+}
+
+impl FrequencySketch {
+    /// Create a new FrequencySketch sized for roughly `capacity` distinct keys, it ages after `10 * capacity` increments.
+    pub fn new(capacity: usize) -> Self {
+        let width = capacity.max(16).next_power_of_two();
+        FrequencySketch {
+            table: vec![0; width * ROWS / 16],
+            width,
+            sample_size: 10 * capacity.max(1) as u64,
+            additions: 0,
+        }
+    }
+
+    /// Set the number of increments after which all counters are halved.
+    pub fn with_sample_size(mut self, sample_size: u64) -> Self {
+        self.sample_size = sample_size.max(1);
+        self
+    }
+
+    /// Get the counter indexes of a key, one in each row.
+    fn indexes<K: Hash + ?Sized>(&self, key: &K) -> [usize; ROWS] {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        let hash = hasher.finish();
+        let mut indexes = [0; ROWS];
+        for (row, seed) in SEEDS.iter().enumerate() {
+            let spread = (hash ^ seed).wrapping_mul(*seed);
+            let column = (spread >> 32) as usize & (self.width - 1);
+            indexes[row] = row * self.width + column;
+        }
+        indexes
+    }
+
+    fn counter(&self, index: usize) -> u64 {
+        (self.table[index / 16] >> ((index % 16) * 4)) & MAX_COUNT
+    }
+
+    /// Record an occurrence of the key, ageing the sketch if the sample size has been reached.
+    pub fn increment<K: Hash + ?Sized>(&mut self, key: &K) {
+        let mut added = false;
+        for index in self.indexes(key) {
+            if self.counter(index) < MAX_COUNT {
+                self.table[index / 16] += 1 << ((index % 16) * 4);
+                added = true;
+            }
+        }
+        if added {
+            self.additions += 1;
+            if self.additions >= self.sample_size {
+                self.reset();
+            }
+        }
+    }
+
+    /// Estimate how many times the key has been seen, between 0 and 15.
+    pub fn frequency<K: Hash + ?Sized>(&self, key: &K) -> u8 {
+        self.indexes(key)
+            .into_iter()
+            .map(|index| self.counter(index))
+            .min()
+            .unwrap_or(0) as u8
+    }
+
+    /// Age the sketch by halving every counter.
+    pub fn reset(&mut self) {
+        for word in self.table.iter_mut() {
+            *word = (*word >> 1) & RESET_MASK;
+        }
+        self.additions /= 2;
+    }
+
+    /// Zero every counter.
+    pub fn clear(&mut self) {
+        self.table.iter_mut().for_each(|word| *word = 0);
+        self.additions = 0;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frequency_sketch() {
+        let mut sketch = FrequencySketch::new(64);
+        for _ in 0..5 {
+            sketch.increment(&1);
+        }
+        sketch.increment(&2);
+        assert_eq!(sketch.frequency(&1), 5);
+        assert_eq!(sketch.frequency(&2), 1);
+        assert_eq!(sketch.frequency(&3), 0);
+    }
+
+    #[test]
+    fn test_frequency_sketch_saturates() {
+        let mut sketch = FrequencySketch::new(64).with_sample_size(1000);
+        for _ in 0..100 {
+            sketch.increment(&1);
+        }
+        assert_eq!(sketch.frequency(&1), 15);
+    }
+
+    #[test]
+    fn test_frequency_sketch_ages() {
+        let mut sketch = FrequencySketch::new(64).with_sample_size(10);
+        for _ in 0..8 {
+            sketch.increment(&1);
+        }
+        sketch.increment(&2);
+        sketch.increment(&3);
+        assert_eq!(sketch.frequency(&1), 4);
+        sketch.clear();
+        assert_eq!(sketch.frequency(&1), 0);
+    }
+}
@@ -0,0 +1,479 @@
+//! A versioned binary format for saving the contents of a cache and loading them back.
+//!
+//! A snapshot is laid out as:
+//!
+//! + 4 magic bytes, `ARCS`
+//! + the format version as a little endian `u16`
+//! + the length of the policy name as a `u8`, followed by the name
+//! + the length of the payload as a little endian `u64`, followed by the payload
+//! + a CRC-32 checksum of the payload as a little endian `u32`
+//!
+//! The payload is the items ordered from the coldest to the hottest item so that loading them in order leaves the hottest items the most recently set. Each item is its key then its value, each encoded by a `Codec` and prefixed with its length as a little endian `u32`. Version 1 snapshots held the items as a single CBOR array instead, `load_snapshot` still reads them. Only keys and values are saved, priorities and TTLs are not.
+#[cfg(feature = "serde")]
+use serde::{de::DeserializeOwned, Serialize};
+use std::fmt;
+use std::hash::Hash;
+use std::io::{self, Read, Write};
+
+use crate::cache::Cache;
+#[cfg(feature = "serde")]
+use crate::codec::CborCodec;
+use crate::codec::{Codec, SerializationError};
+
+/// The magic bytes which start every snapshot.
+const MAGIC: &[u8; 4] = b"ARCS";
+
+/// The version of the snapshot format written by this crate.
+pub const SNAPSHOT_VERSION: u16 = 2;
+
+/// The version of the format which held the items as a single CBOR array.
+const CBOR_ARRAY_VERSION: u16 = 1;
+
+/// SnapshotError is returned when a snapshot cannot be saved or loaded.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum SnapshotError {
+    /// Reading or writing the snapshot failed.
+    Io(io::Error),
+    /// The data does not start with the snapshot magic bytes.
+    NotASnapshot,
+    /// The snapshot was written in a format version this crate cannot read.
+    VersionMismatch { found: u16, expected: u16 },
+    /// The snapshot was taken from a cache with a different eviction policy.
+    PolicyMismatch { found: String, expected: String },
+    /// The snapshot is truncated, fails its checksum, or its items cannot be decoded.
+    Corrupted(String),
+    /// The items could not be encoded.
+    Serialization(String),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SnapshotError::Io(error) => write!(f, "snapshot io error: {}", error),
+            SnapshotError::NotASnapshot => write!(f, "data is not a cache snapshot"),
+            SnapshotError::VersionMismatch { found, expected } => write!(
+                f,
+                "snapshot format version {} is not supported, expected {}",
+                found, expected
+            ),
+            SnapshotError::PolicyMismatch { found, expected } => write!(
+                f,
+                "snapshot was taken from a {} cache, expected {}",
+                found, expected
+            ),
+            SnapshotError::Corrupted(reason) => write!(f, "snapshot is corrupted: {}", reason),
+            SnapshotError::Serialization(reason) => {
+                write!(f, "failed to encode snapshot: {}", reason)
+            }
+        }
+    }
+}
+
+impl std::error::Error for SnapshotError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            SnapshotError::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for SnapshotError {
+    fn from(error: io::Error) -> Self {
+        // running out of data part way through means the snapshot was cut short
+        if error.kind() == io::ErrorKind::UnexpectedEof {
+            SnapshotError::Corrupted("snapshot is truncated".to_string())
+        } else {
+            SnapshotError::Io(error)
+        }
+    }
+}
+
+/// Save the unexpired items of a cache to `writer`, encoded as CBOR, returning how many were saved.
+///
+/// This requires the `serde` feature.
+///
+/// Example:
+/// ```
+/// use arcache::{load_snapshot, save_snapshot, Cache, LRUCache};
+///
+/// let cache = LRUCache::<String, u64>::new(10);
+/// cache.set("a".to_string(), 1);
+///
+/// let mut bytes = Vec::new();
+/// save_snapshot(&cache, &mut bytes).unwrap();
+///
+/// let restored = LRUCache::<String, u64>::new(10);
+/// assert_eq!(load_snapshot(&restored, &mut bytes.as_slice()).unwrap(), 1);
+/// assert_eq!(restored.get(&"a".to_string()).map(|v| *v), Some(1));
+/// ```
+#[cfg(feature = "serde")]
+pub fn save_snapshot<K, V, C>(cache: &C, writer: &mut impl Write) -> Result<usize, SnapshotError>
+where
+    K: Eq + Hash + Clone + Send + Sync + Serialize + DeserializeOwned,
+    V: Send + Sync + Serialize + DeserializeOwned,
+    C: Cache<K, V> + ?Sized,
+{
+    save_snapshot_with_codec(cache, writer, &CborCodec)
+}
+
+/// Save the unexpired items of a cache to `writer`, encoding keys and values with `codec`, returning how many were saved.
+pub fn save_snapshot_with_codec<K, V, C, X>(
+    cache: &C,
+    writer: &mut impl Write,
+    codec: &X,
+) -> Result<usize, SnapshotError>
+where
+    K: Eq + Hash + Clone + Send + Sync,
+    V: Send + Sync,
+    C: Cache<K, V> + ?Sized,
+    X: Codec<K> + Codec<V> + ?Sized,
+{
+    let items = cache.hottest(usize::MAX);
+    let mut payload = Vec::new();
+    for (key, value) in items.iter().rev() {
+        write_record(&mut payload, Codec::<K>::encode(codec, key))?;
+        write_record(&mut payload, Codec::<V>::encode(codec, value.as_ref()))?;
+    }
+
+    let policy = cache.policy_name().as_bytes();
+    writer.write_all(MAGIC)?;
+    writer.write_all(&SNAPSHOT_VERSION.to_le_bytes())?;
+    writer.write_all(&[policy.len() as u8])?;
+    writer.write_all(policy)?;
+    writer.write_all(&(payload.len() as u64).to_le_bytes())?;
+    writer.write_all(&payload)?;
+    writer.write_all(&crc32(&payload).to_le_bytes())?;
+    Ok(items.len())
+}
+
+/// Load a snapshot from `reader` into a cache, decoding it as CBOR, returning how many items were set.
+///
+/// The whole snapshot is read and checked before the cache is touched, so a snapshot which fails to load leaves the cache unchanged. The snapshot must have been taken from a cache with the same eviction policy.
+///
+/// This requires the `serde` feature.
+#[cfg(feature = "serde")]
+pub fn load_snapshot<K, V, C>(cache: &C, reader: &mut impl Read) -> Result<usize, SnapshotError>
+where
+    K: Eq + Hash + Clone + Send + Sync + Serialize + DeserializeOwned,
+    V: Send + Sync + Serialize + DeserializeOwned,
+    C: Cache<K, V> + ?Sized,
+{
+    let (version, payload) = read_payload(cache, reader)?;
+    let items: Vec<(K, V)> = if version == CBOR_ARRAY_VERSION {
+        ciborium::from_reader(payload.as_slice())
+            .map_err(|error| SnapshotError::Corrupted(error.to_string()))?
+    } else {
+        decode_records(&payload, &CborCodec)?
+    };
+    Ok(set_all(cache, items))
+}
+
+/// Load a snapshot from `reader` into a cache, decoding keys and values with `codec`, returning how many items were set.
+///
+/// The snapshot must have been saved with the same codec, as well as from a cache with the same eviction policy, and like `load_snapshot` a snapshot which fails to load leaves the cache unchanged.
+pub fn load_snapshot_with_codec<K, V, C, X>(
+    cache: &C,
+    reader: &mut impl Read,
+    codec: &X,
+) -> Result<usize, SnapshotError>
+where
+    K: Eq + Hash + Clone + Send + Sync,
+    V: Send + Sync,
+    C: Cache<K, V> + ?Sized,
+    X: Codec<K> + Codec<V> + ?Sized,
+{
+    let (version, payload) = read_payload(cache, reader)?;
+    if version != SNAPSHOT_VERSION {
+        return Err(SnapshotError::VersionMismatch {
+            found: version,
+            expected: SNAPSHOT_VERSION,
+        });
+    }
+    let items = decode_records(&payload, codec)?;
+    Ok(set_all(cache, items))
+}
+
+/// Read and check a snapshot's header and checksum, returning its format version and payload.
+fn read_payload<K, V, C>(cache: &C, reader: &mut impl Read) -> Result<(u16, Vec<u8>), SnapshotError>
+where
+    K: Eq + Hash + Clone + Send + Sync,
+    V: Send + Sync,
+    C: Cache<K, V> + ?Sized,
+{
+    let mut magic = [0; 4];
+    reader.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(SnapshotError::NotASnapshot);
+    }
+
+    let mut version = [0; 2];
+    reader.read_exact(&mut version)?;
+    let version = u16::from_le_bytes(version);
+    if !(CBOR_ARRAY_VERSION..=SNAPSHOT_VERSION).contains(&version) {
+        return Err(SnapshotError::VersionMismatch {
+            found: version,
+            expected: SNAPSHOT_VERSION,
+        });
+    }
+
+    let mut policy_len = [0; 1];
+    reader.read_exact(&mut policy_len)?;
+    let mut policy = vec![0; policy_len[0] as usize];
+    reader.read_exact(&mut policy)?;
+    let policy = String::from_utf8_lossy(&policy);
+    if policy != cache.policy_name() {
+        return Err(SnapshotError::PolicyMismatch {
+            found: policy.into_owned(),
+            expected: cache.policy_name().to_string(),
+        });
+    }
+
+    let mut payload_len = [0; 8];
+    reader.read_exact(&mut payload_len)?;
+    let payload_len = u64::from_le_bytes(payload_len);
+    let mut payload = Vec::new();
+    reader.take(payload_len).read_to_end(&mut payload)?;
+    if payload.len() as u64 != payload_len {
+        return Err(SnapshotError::Corrupted(
+            "snapshot is truncated".to_string(),
+        ));
+    }
+
+    let mut checksum = [0; 4];
+    reader.read_exact(&mut checksum)?;
+    if u32::from_le_bytes(checksum) != crc32(&payload) {
+        return Err(SnapshotError::Corrupted(
+            "checksum does not match".to_string(),
+        ));
+    }
+    Ok((version, payload))
+}
+
+/// Append an encoded key or value to a payload, prefixed with its length.
+fn write_record(
+    payload: &mut Vec<u8>,
+    encoded: Result<Vec<u8>, SerializationError>,
+) -> Result<(), SnapshotError> {
+    let bytes = encoded.map_err(|error| SnapshotError::Serialization(error.0))?;
+    let len = u32::try_from(bytes.len())
+        .map_err(|_| SnapshotError::Serialization("item is over 4 GiB".to_string()))?;
+    payload.extend_from_slice(&len.to_le_bytes());
+    payload.extend_from_slice(&bytes);
+    Ok(())
+}
+
+/// Split the next length prefixed record off the front of a payload.
+fn read_record<'a>(payload: &mut &'a [u8]) -> Result<&'a [u8], SnapshotError> {
+    let truncated = || SnapshotError::Corrupted("item is truncated".to_string());
+    let (len, rest) = payload.split_first_chunk::<4>().ok_or_else(truncated)?;
+    let len = u32::from_le_bytes(*len) as usize;
+    if rest.len() < len {
+        return Err(truncated());
+    }
+    let (record, rest) = rest.split_at(len);
+    *payload = rest;
+    Ok(record)
+}
+
+/// Decode the items of a payload written by `save_snapshot_with_codec`.
+fn decode_records<K, V, X>(mut payload: &[u8], codec: &X) -> Result<Vec<(K, V)>, SnapshotError>
+where
+    X: Codec<K> + Codec<V> + ?Sized,
+{
+    let corrupted = |error: SerializationError| SnapshotError::Corrupted(error.0);
+    let mut items = Vec::new();
+    while !payload.is_empty() {
+        let key = Codec::<K>::decode(codec, read_record(&mut payload)?).map_err(corrupted)?;
+        let value = Codec::<V>::decode(codec, read_record(&mut payload)?).map_err(corrupted)?;
+        items.push((key, value));
+    }
+    Ok(items)
+}
+
+/// Set decoded items in a cache, returning how many there were.
+fn set_all<K, V, C>(cache: &C, items: Vec<(K, V)>) -> usize
+where
+    K: Eq + Hash + Clone + Send + Sync,
+    V: Send + Sync,
+    C: Cache<K, V> + ?Sized,
+{
+    let loaded = items.len();
+    for (key, value) in items {
+        cache.set(key, value);
+    }
+    loaded
+}
+
+/// Compute the CRC-32 (IEEE) checksum of some bytes.
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in bytes {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xedb8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::LRUCache;
+
+    #[cfg(feature = "serde")]
+    fn snapshot_of(cache: &LRUCache<u64, String>) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        save_snapshot(cache, &mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_snapshot_round_trip_keeps_order() {
+        let cache = LRUCache::new(3);
+        cache.set(1, "a".to_string());
+        cache.set(2, "b".to_string());
+        cache.set(3, "c".to_string());
+        cache.get(&1);
+        let bytes = snapshot_of(&cache);
+
+        let restored = LRUCache::new(3);
+        assert_eq!(load_snapshot(&restored, &mut bytes.as_slice()).unwrap(), 3);
+        restored.set(4, "d".to_string());
+        assert!(!restored.contains_key(&2));
+        assert!(restored.contains_key(&1));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_snapshot_errors() {
+        let cache = LRUCache::new(3);
+        cache.set(1, "a".to_string());
+        let bytes = snapshot_of(&cache);
+        let restored = LRUCache::<u64, String>::new(3);
+
+        let mut wrong_version = bytes.clone();
+        wrong_version[4] = 3;
+        assert!(matches!(
+            load_snapshot(&restored, &mut wrong_version.as_slice()),
+            Err(SnapshotError::VersionMismatch {
+                found: 3,
+                expected: 2
+            })
+        ));
+
+        let mut flipped = bytes.clone();
+        let last_payload_byte = bytes.len() - 5;
+        flipped[last_payload_byte] ^= 1;
+        assert!(matches!(
+            load_snapshot(&restored, &mut flipped.as_slice()),
+            Err(SnapshotError::Corrupted(_))
+        ));
+
+        let truncated = &bytes[..bytes.len() - 2];
+        assert!(matches!(
+            load_snapshot(&restored, &mut &truncated[..]),
+            Err(SnapshotError::Corrupted(_))
+        ));
+
+        assert!(matches!(
+            load_snapshot(&restored, &mut &b"nope"[..]),
+            Err(SnapshotError::NotASnapshot)
+        ));
+
+        let lfu = crate::LFUCache::<u64, String>::new(3);
+        assert!(matches!(
+            load_snapshot(&lfu, &mut bytes.as_slice()),
+            Err(SnapshotError::PolicyMismatch { .. })
+        ));
+        assert_eq!(restored.stats().size, 0);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_snapshot_reads_cbor_array_version() {
+        let mut payload = Vec::new();
+        ciborium::into_writer(&vec![(1u64, "a"), (2, "b")], &mut payload).unwrap();
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&CBOR_ARRAY_VERSION.to_le_bytes());
+        bytes.push(3);
+        bytes.extend_from_slice(b"lru");
+        bytes.extend_from_slice(&(payload.len() as u64).to_le_bytes());
+        bytes.extend_from_slice(&payload);
+        bytes.extend_from_slice(&crc32(&payload).to_le_bytes());
+
+        let restored = LRUCache::<u64, String>::new(3);
+        assert_eq!(load_snapshot(&restored, &mut bytes.as_slice()).unwrap(), 2);
+        assert_eq!(restored.get(&2).as_deref(), Some(&"b".to_string()));
+    }
+
+    /// Encodes numbers as little endian bytes and strings as UTF-8.
+    struct RawCodec;
+
+    impl Codec<u64> for RawCodec {
+        fn encode(&self, value: &u64) -> Result<Vec<u8>, SerializationError> {
+            Ok(value.to_le_bytes().to_vec())
+        }
+
+        fn decode(&self, bytes: &[u8]) -> Result<u64, SerializationError> {
+            let bytes = bytes
+                .try_into()
+                .map_err(|_| SerializationError::new("expected 8 bytes"))?;
+            Ok(u64::from_le_bytes(bytes))
+        }
+    }
+
+    impl Codec<String> for RawCodec {
+        fn encode(&self, value: &String) -> Result<Vec<u8>, SerializationError> {
+            Ok(value.as_bytes().to_vec())
+        }
+
+        fn decode(&self, bytes: &[u8]) -> Result<String, SerializationError> {
+            String::from_utf8(bytes.to_vec())
+                .map_err(|error| SerializationError::new(error.to_string()))
+        }
+    }
+
+    #[test]
+    fn test_snapshot_with_codec() {
+        let cache = LRUCache::new(3);
+        cache.set(1, "a".to_string());
+        cache.set(2, "bc".to_string());
+        let mut bytes = Vec::new();
+        assert_eq!(
+            save_snapshot_with_codec(&cache, &mut bytes, &RawCodec).unwrap(),
+            2
+        );
+        // two items of an 8 byte key and a 1 or 2 byte value, each with a 4 byte length
+        assert_eq!(bytes.len(), 4 + 2 + 1 + 3 + 8 + 35 + 4);
+
+        let restored = LRUCache::<u64, String>::new(3);
+        assert_eq!(
+            load_snapshot_with_codec(&restored, &mut bytes.as_slice(), &RawCodec).unwrap(),
+            2
+        );
+        assert_eq!(restored.get(&2).as_deref(), Some(&"bc".to_string()));
+
+        // a value which is not UTF-8 fails to decode, leaving the cache untouched
+        let mut bad_value = bytes.clone();
+        let last_value_byte = bytes.len() - 5;
+        bad_value[last_value_byte] = 0xff;
+        let checksum = crc32(&bad_value[18..bytes.len() - 4]);
+        bad_value[bytes.len() - 4..].copy_from_slice(&checksum.to_le_bytes());
+        let empty = LRUCache::<u64, String>::new(3);
+        assert!(matches!(
+            load_snapshot_with_codec(&empty, &mut bad_value.as_slice(), &RawCodec),
+            Err(SnapshotError::Corrupted(_))
+        ));
+        assert_eq!(empty.stats().size, 0);
+    }
+}
@@ -0,0 +1,215 @@
+//! The synchronisation primitives used inside the caches.
+//!
+//! Every lock and atomic in the crate comes from this module, so the same code can run on three implementations:
+//!
+//! + By default, the standard library's.
+//! + With the `parking_lot` feature enabled, thin wrappers over `parking_lot`'s locks, which are faster under contention and are never poisoned. The wrappers keep the standard library's signatures, returning a `LockResult` which is always `Ok`.
+//! + When built with `RUSTFLAGS="--cfg loom"`, `loom`'s, so the concurrent logic can be checked under every interleaving loom explores.
+//!
+//! Policies implemented outside the crate can use this module for their own state to be checked under loom together with the caches they wrap. Values are still handed out in a standard library `Arc`, so `Arc` is not part of the layer.
+//!
+//! Example:
+//! ```
+//! use arcache::sync::atomic::{AtomicU64, Ordering};
+//! use arcache::sync::Mutex;
+//!
+//! let hits = AtomicU64::new(0);
+//! let keys = Mutex::new(Vec::new());
+//! keys.lock().unwrap().push("key");
+//! hits.fetch_add(1, Ordering::Relaxed);
+//! assert_eq!(hits.load(Ordering::Relaxed), 1);
+//! ```
+#[cfg(not(any(loom, feature = "parking_lot")))]
+pub use std::sync::{
+    atomic, Condvar, Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard,
+};
+
+#[cfg(all(feature = "parking_lot", not(loom)))]
+pub use self::parking::{Condvar, Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard};
+#[cfg(all(feature = "parking_lot", not(loom)))]
+pub use std::sync::atomic;
+
+#[cfg(loom)]
+pub use self::loom_locks::{Mutex, RwLock};
+#[cfg(loom)]
+pub use loom::sync::{atomic, Condvar, MutexGuard, RwLockReadGuard, RwLockWriteGuard};
+
+#[cfg(all(feature = "parking_lot", not(loom)))]
+mod parking {
+    use std::sync::{LockResult, TryLockError, TryLockResult};
+    use std::time::Duration;
+
+    pub type MutexGuard<'a, T> = parking_lot::MutexGuard<'a, T>;
+    pub type RwLockReadGuard<'a, T> = parking_lot::RwLockReadGuard<'a, T>;
+    pub type RwLockWriteGuard<'a, T> = parking_lot::RwLockWriteGuard<'a, T>;
+
+    /// Mutex is a `parking_lot::Mutex` with the standard library's signatures.
+    #[derive(Debug, Default)]
+    pub struct Mutex<T: ?Sized>(parking_lot::Mutex<T>);
+
+    impl<T> Mutex<T> {
+        /// Create a new unlocked mutex holding `value`.
+        pub fn new(value: T) -> Self {
+            Mutex(parking_lot::Mutex::new(value))
+        }
+
+        /// Consume the mutex, returning the value it holds.
+        pub fn into_inner(self) -> LockResult<T> {
+            Ok(self.0.into_inner())
+        }
+    }
+
+    impl<T: ?Sized> Mutex<T> {
+        /// Lock the mutex, blocking until it is available.
+        pub fn lock(&self) -> LockResult<MutexGuard<'_, T>> {
+            Ok(self.0.lock())
+        }
+
+        /// Lock the mutex if it is available without blocking.
+        pub fn try_lock(&self) -> TryLockResult<MutexGuard<'_, T>> {
+            self.0.try_lock().ok_or(TryLockError::WouldBlock)
+        }
+
+        /// A `parking_lot` lock is never poisoned.
+        pub fn is_poisoned(&self) -> bool {
+            false
+        }
+
+        /// Get a mutable reference to the value, which needs no locking as the mutex is borrowed mutably.
+        pub fn get_mut(&mut self) -> LockResult<&mut T> {
+            Ok(self.0.get_mut())
+        }
+    }
+
+    /// RwLock is a `parking_lot::RwLock` with the standard library's signatures.
+    #[derive(Debug, Default)]
+    pub struct RwLock<T: ?Sized>(parking_lot::RwLock<T>);
+
+    impl<T> RwLock<T> {
+        /// Create a new unlocked lock holding `value`.
+        pub fn new(value: T) -> Self {
+            RwLock(parking_lot::RwLock::new(value))
+        }
+    }
+
+    impl<T: ?Sized> RwLock<T> {
+        /// Lock for reading, blocking until no writer holds the lock.
+        pub fn read(&self) -> LockResult<RwLockReadGuard<'_, T>> {
+            Ok(self.0.read())
+        }
+
+        /// Lock for writing, blocking until no one else holds the lock.
+        pub fn write(&self) -> LockResult<RwLockWriteGuard<'_, T>> {
+            Ok(self.0.write())
+        }
+
+        /// A `parking_lot` lock is never poisoned.
+        pub fn is_poisoned(&self) -> bool {
+            false
+        }
+    }
+
+    /// Condvar is a `parking_lot::Condvar` with the standard library's signatures.
+    #[derive(Debug, Default)]
+    pub struct Condvar(parking_lot::Condvar);
+
+    impl Condvar {
+        /// Create a new condition variable.
+        pub fn new() -> Self {
+            Condvar(parking_lot::Condvar::new())
+        }
+
+        /// Wake every thread waiting on the condition variable.
+        pub fn notify_all(&self) {
+            self.0.notify_all();
+        }
+
+        /// Block until the condition variable is notified.
+        pub fn wait<'a, T>(&self, mut guard: MutexGuard<'a, T>) -> LockResult<MutexGuard<'a, T>> {
+            self.0.wait(&mut guard);
+            Ok(guard)
+        }
+
+        /// Wait while `condition` holds, for at most `timeout`.
+        pub fn wait_timeout_while<'a, T, F>(
+            &self,
+            mut guard: MutexGuard<'a, T>,
+            timeout: Duration,
+            condition: F,
+        ) -> LockResult<(MutexGuard<'a, T>, parking_lot::WaitTimeoutResult)>
+        where
+            F: FnMut(&mut T) -> bool,
+        {
+            let result = self.0.wait_while_for(&mut guard, condition, timeout);
+            Ok((guard, result))
+        }
+    }
+}
+
+#[cfg(loom)]
+mod loom_locks {
+    use loom::sync::{MutexGuard, RwLockReadGuard, RwLockWriteGuard};
+    use std::sync::{LockResult, TryLockResult};
+
+    /// Mutex is a `loom::sync::Mutex` with the rest of the standard library's signatures.
+    #[derive(Debug)]
+    pub struct Mutex<T>(loom::sync::Mutex<T>);
+
+    impl<T> Mutex<T> {
+        /// Create a new unlocked mutex holding `value`.
+        pub fn new(value: T) -> Self {
+            Mutex(loom::sync::Mutex::new(value))
+        }
+
+        /// Consume the mutex, returning the value it holds.
+        pub fn into_inner(self) -> LockResult<T> {
+            self.0.into_inner()
+        }
+
+        /// Lock the mutex, blocking until it is available.
+        pub fn lock(&self) -> LockResult<MutexGuard<'_, T>> {
+            self.0.lock()
+        }
+
+        /// Lock the mutex if it is available without blocking.
+        pub fn try_lock(&self) -> TryLockResult<MutexGuard<'_, T>> {
+            self.0.try_lock()
+        }
+
+        /// A loom lock is never poisoned, a panic fails the whole model.
+        pub fn is_poisoned(&self) -> bool {
+            false
+        }
+
+        /// Get a mutable reference to the value, which needs no locking as the mutex is borrowed mutably.
+        pub fn get_mut(&mut self) -> LockResult<&mut T> {
+            self.0.get_mut()
+        }
+    }
+
+    /// RwLock is a `loom::sync::RwLock` with the rest of the standard library's signatures.
+    #[derive(Debug)]
+    pub struct RwLock<T>(loom::sync::RwLock<T>);
+
+    impl<T> RwLock<T> {
+        /// Create a new unlocked lock holding `value`.
+        pub fn new(value: T) -> Self {
+            RwLock(loom::sync::RwLock::new(value))
+        }
+
+        /// Lock for reading, blocking until no writer holds the lock.
+        pub fn read(&self) -> LockResult<RwLockReadGuard<'_, T>> {
+            self.0.read()
+        }
+
+        /// Lock for writing, blocking until no one else holds the lock.
+        pub fn write(&self) -> LockResult<RwLockWriteGuard<'_, T>> {
+            self.0.write()
+        }
+
+        /// A loom lock is never poisoned, a panic fails the whole model.
+        pub fn is_poisoned(&self) -> bool {
+            false
+        }
+    }
+}
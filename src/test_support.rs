@@ -0,0 +1,396 @@
+//! Utilities for testing code which is generic over the `Cache` trait.
+//!
+//! Enabled with the `test_support` feature.
+use std::any::Any;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt::Debug;
+use std::hash::Hash;
+use std::sync::{Arc, Mutex};
+
+use crate::cache::{Cache, CacheStats};
+
+mod fuzz;
+mod model;
+pub use fuzz::{check_operations, decode_operations, fuzz_every_policy};
+pub use model::{
+    CacheModel, ClockModel, DifferentialTester, FIFOModel, LFUModel, LIFOModel, LRUModel, MRUModel,
+    ModelOp,
+};
+
+/// A call made against a MockCache, recorded in the order it was made.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MockCall<K> {
+    Get(K),
+    Set(K),
+    Remove(K),
+    Clear,
+    Stats,
+    ChangeCapacity(u64),
+}
+
+/// The inner data structure for the MockCache.
+struct MockCacheInner<K, V> {
+    capacity: u64,
+    key_value_map: HashMap<K, Arc<V>>,
+    forced_misses: HashSet<K>,
+    scripted_evictions: VecDeque<K>,
+    evictions: Vec<K>,
+    calls: Vec<MockCall<K>>,
+    hits: u64,
+    misses: u64,
+    inserts: u64,
+    updates: u64,
+}
+
+/// MockCache is a `Cache` whose hits, misses and evictions can be scripted and asserted.
+///
+/// By default it behaves like a map that never evicts. Misses can be forced for particular keys and evictions can be triggered immediately or on the next `set`.
+///
+/// Example:
+/// ```
+/// use arcache::test_support::{MockCache, MockCall};
+/// use arcache::Cache;
+///
+/// let cache = MockCache::<&str, u64>::new(10);
+///
+/// cache.set("a", 1);
+/// cache.force_miss("a");
+///
+/// assert_eq!(cache.get(&"a"), None);
+/// cache.assert_misses(1);
+/// assert_eq!(cache.calls(), vec![MockCall::Set("a"), MockCall::Get("a")]);
+/// ```
+pub struct MockCache<K: Eq + Hash + Clone + Send + Sync, V: Send + Sync> {
+    inner: Mutex<MockCacheInner<K, V>>,
+}
+
+impl<K: Eq + Hash + Clone + Send + Sync + Debug, V: Send + Sync> MockCache<K, V> {
+    /// Create a new MockCache with the given capacity, the capacity is only reported in stats and never enforced.
+    pub fn new(capacity: u64) -> Self {
+        MockCache {
+            inner: Mutex::new(MockCacheInner {
+                capacity,
+                key_value_map: HashMap::new(),
+                forced_misses: HashSet::new(),
+                scripted_evictions: VecDeque::new(),
+                evictions: Vec::new(),
+                calls: Vec::new(),
+                hits: 0,
+                misses: 0,
+                inserts: 0,
+                updates: 0,
+            }),
+        }
+    }
+
+    /// Make every `get` of the given key miss, even if the key is present, until `unforce_miss` is called.
+    pub fn force_miss(&self, key: K) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.forced_misses.insert(key);
+    }
+
+    /// Stop forcing misses for the given key.
+    pub fn unforce_miss(&self, key: &K) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.forced_misses.remove(key);
+    }
+
+    /// Evict the given key immediately, returning its value if it was present.
+    pub fn evict(&self, key: &K) -> Option<Arc<V>> {
+        let mut inner = self.inner.lock().unwrap();
+        let result = inner.key_value_map.remove(key);
+        if result.is_some() {
+            inner.evictions.push(key.clone());
+        }
+        result
+    }
+
+    /// Evict the given key on the next call to `set`, scripted evictions are applied in the order they were added.
+    pub fn evict_on_next_set(&self, key: K) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.scripted_evictions.push_back(key);
+    }
+
+    /// Get all calls made against the cache, in order.
+    pub fn calls(&self) -> Vec<MockCall<K>> {
+        let inner = self.inner.lock().unwrap();
+        inner.calls.clone()
+    }
+
+    /// Get all keys evicted from the cache, in order.
+    pub fn evictions(&self) -> Vec<K> {
+        let inner = self.inner.lock().unwrap();
+        inner.evictions.clone()
+    }
+
+    /// Assert the number of hits recorded so far.
+    pub fn assert_hits(&self, expected: u64) {
+        let inner = self.inner.lock().unwrap();
+        assert_eq!(inner.hits, expected, "unexpected number of cache hits");
+    }
+
+    /// Assert the number of misses recorded so far.
+    pub fn assert_misses(&self, expected: u64) {
+        let inner = self.inner.lock().unwrap();
+        assert_eq!(inner.misses, expected, "unexpected number of cache misses");
+    }
+
+    /// Assert that the given key has been evicted.
+    pub fn assert_evicted(&self, key: &K) {
+        let inner = self.inner.lock().unwrap();
+        assert!(
+            inner.evictions.contains(key),
+            "expected {:?} to have been evicted",
+            key
+        );
+    }
+
+    /// Assert that the given call was made at least once.
+    pub fn assert_called(&self, call: &MockCall<K>) {
+        let inner = self.inner.lock().unwrap();
+        assert!(
+            inner.calls.contains(call),
+            "expected call {:?}, got {:?}",
+            call,
+            inner.calls
+        );
+    }
+}
+
+impl<K: Eq + Hash + Clone + Send + Sync + Debug, V: Send + Sync> Cache<K, V> for MockCache<K, V> {
+    /// Get a value from the cache, unless a miss has been forced for the key.
+    fn get(&self, key: &K) -> Option<Arc<V>> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.calls.push(MockCall::Get(key.clone()));
+        let result = if inner.forced_misses.contains(key) {
+            None
+        } else {
+            inner.key_value_map.get(key).cloned()
+        };
+        if result.is_some() {
+            inner.hits += 1;
+        } else {
+            inner.misses += 1;
+        }
+        result
+    }
+
+    /// Set a value in the cache, applying the next scripted eviction if there is one.
+    fn set(&self, key: K, value: V) -> Option<Arc<V>> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.calls.push(MockCall::Set(key.clone()));
+        if let Some(evicted_key) = inner.scripted_evictions.pop_front() {
+            if inner.key_value_map.remove(&evicted_key).is_some() {
+                inner.evictions.push(evicted_key);
+            }
+        }
+        let previous = inner.key_value_map.insert(key, Arc::new(value));
+        if previous.is_some() {
+            inner.updates += 1;
+        } else {
+            inner.inserts += 1;
+        }
+        previous
+    }
+
+    /// Remove a value from the cache.
+    fn remove(&self, key: &K) -> Option<Arc<V>> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.calls.push(MockCall::Remove(key.clone()));
+        inner.key_value_map.remove(key)
+    }
+
+    /// Check if a key is in the cache, forced misses are reported as absent.
+    fn contains_key(&self, key: &K) -> bool {
+        let inner = self.inner.lock().unwrap();
+        !inner.forced_misses.contains(key) && inner.key_value_map.contains_key(key)
+    }
+
+    /// Clear the cache.
+    fn clear(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.calls.push(MockCall::Clear);
+        inner.key_value_map.clear();
+    }
+
+    /// Clear the cache, returning every item which was removed.
+    fn clear_returning(&self) -> Vec<(K, Arc<V>)> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.calls.push(MockCall::Clear);
+        inner.key_value_map.drain().collect()
+    }
+
+    /// Copy the items into a HashMap, forced misses are left out.
+    fn to_map(&self) -> HashMap<K, Arc<V>> {
+        let inner = self.inner.lock().unwrap();
+        inner
+            .key_value_map
+            .iter()
+            .filter(|(key, _)| !inner.forced_misses.contains(key))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect()
+    }
+
+    /// Get up to `limit` items in no particular order, forced misses are left out.
+    fn hottest(&self, limit: usize) -> Vec<(K, Arc<V>)> {
+        let inner = self.inner.lock().unwrap();
+        inner
+            .key_value_map
+            .iter()
+            .filter(|(key, _)| !inner.forced_misses.contains(key))
+            .take(limit)
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect()
+    }
+
+    /// Get the name of the eviction policy.
+    fn policy_name(&self) -> &'static str {
+        "mock"
+    }
+
+    /// Get the cache as `Any`, so it can be downcast from a `dyn Cache`.
+    fn as_any(&self) -> Option<&dyn Any>
+    where
+        Self: 'static,
+    {
+        Some(self)
+    }
+
+    /// Get the cache statistics.
+    fn stats(&self) -> CacheStats {
+        let mut inner = self.inner.lock().unwrap();
+        inner.calls.push(MockCall::Stats);
+        CacheStats {
+            hits: inner.hits,
+            misses: inner.misses,
+            inserts: inner.inserts,
+            updates: inner.updates,
+            evictions: inner.evictions.len() as u64,
+            wasted_inserts: 0,
+            expirations: 0,
+            callback_panics: 0,
+            size: inner.key_value_map.len() as u64,
+            capacity: inner.capacity,
+            cache: Default::default(),
+        }
+    }
+
+    /// Change the reported capacity of the cache, no items are evicted.
+    fn change_capacity(&self, capacity: u64) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.calls.push(MockCall::ChangeCapacity(capacity));
+        inner.capacity = capacity;
+    }
+    /// Change the reported capacity of the cache, no items are evicted so nothing is returned.
+    fn change_capacity_collect(&self, capacity: u64) -> Vec<(K, Arc<V>)> {
+        self.change_capacity(capacity);
+        Vec::new()
+    }
+}
+
+/// Assert that the cache holds no more items than its capacity.
+pub fn assert_within_capacity<K, V, C>(cache: &C)
+where
+    K: Eq + Hash + Clone + Send + Sync,
+    V: Send + Sync,
+    C: Cache<K, V> + ?Sized,
+{
+    let stats = cache.stats();
+    assert!(
+        stats.size <= stats.capacity,
+        "cache size {} exceeds capacity {}",
+        stats.size,
+        stats.capacity
+    );
+}
+
+/// Assert that a value which was just set can be read back.
+pub fn assert_get_after_set<K, V, C>(cache: &C, key: K, value: V)
+where
+    K: Eq + Hash + Clone + Send + Sync + Debug,
+    V: Send + Sync + PartialEq + Debug + Clone,
+    C: Cache<K, V> + ?Sized,
+{
+    cache.set(key.clone(), value.clone());
+    assert_eq!(
+        cache.get(&key).as_deref(),
+        Some(&value),
+        "expected {:?} to be readable directly after set",
+        key
+    );
+}
+
+/// Assert that every get in `keys` was counted as exactly one hit or miss.
+pub fn assert_gets_counted<K, V, C>(cache: &C, keys: &[K])
+where
+    K: Eq + Hash + Clone + Send + Sync,
+    V: Send + Sync,
+    C: Cache<K, V> + ?Sized,
+{
+    let before = cache.stats();
+    for key in keys {
+        cache.get(key);
+    }
+    let after = cache.stats();
+    assert_eq!(
+        (after.hits + after.misses) - (before.hits + before.misses),
+        keys.len() as u64,
+        "gets were not counted as exactly one hit or miss each"
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        ClockCache, ClockProCache, FIFOCache, GDSFCache, LFUCache, LIFOCache, LRUCache, MRUCache,
+        RandomReplacementCache, TTLCache, TwoQCache, WindowedLFUCache,
+    };
+    use std::time::Duration;
+
+    #[test]
+    fn test_mock_cache_scripted_behaviour() {
+        let cache = MockCache::new(2);
+        cache.set(1, 1);
+        cache.set(2, 2);
+        cache.force_miss(1);
+        assert_eq!(cache.get(&1), None);
+        assert_eq!(cache.get(&2).map(|v| *v), Some(2));
+        cache.unforce_miss(&1);
+        assert_eq!(cache.get(&1).map(|v| *v), Some(1));
+        cache.assert_hits(2);
+        cache.assert_misses(1);
+
+        cache.evict_on_next_set(2);
+        cache.set(3, 3);
+        cache.assert_evicted(&2);
+        assert_eq!(cache.evict(&1).map(|v| *v), Some(1));
+        assert_eq!(cache.evictions(), vec![2, 1]);
+        cache.assert_called(&MockCall::Set(3));
+    }
+
+    #[test]
+    fn test_invariant_helpers() {
+        let caches: Vec<Box<dyn Cache<u32, u32>>> = vec![
+            Box::new(LRUCache::new(4)),
+            Box::new(MRUCache::new(4)),
+            Box::new(LFUCache::new(4)),
+            Box::new(FIFOCache::new(4)),
+            Box::new(LIFOCache::new(4)),
+            Box::new(RandomReplacementCache::new(4)),
+            Box::new(TTLCache::new(Duration::from_secs(3600), 4)),
+            Box::new(ClockCache::new(4)),
+            Box::new(ClockProCache::new(4)),
+            Box::new(TwoQCache::new(4)),
+            Box::new(WindowedLFUCache::new(4)),
+            Box::new(GDSFCache::new(4)),
+        ];
+        for cache in caches {
+            for i in 0..10 {
+                assert_get_after_set(cache.as_ref(), i, i * 2);
+                assert_within_capacity(cache.as_ref());
+            }
+            assert_gets_counted(cache.as_ref(), &[0, 5, 9, 42]);
+        }
+    }
+}
@@ -0,0 +1,148 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::cache::Cache;
+use crate::test_support::{assert_within_capacity, ModelOp};
+use crate::{
+    BloomFilterCache, ClockCache, ClockProCache, FIFOCache, GDSFCache, LFUCache, LIFOCache,
+    LRUCache, MRUCache, RandomReplacementCache, TTLCache, ThreadLocalCache, TwoQCache,
+    WindowedLFUCache,
+};
+
+/// The number of distinct keys fuzzed operations use, small enough that keys collide and evict each other often.
+const KEY_SPACE: u8 = 16;
+
+/// The largest capacity fuzzed caches are given.
+const MAX_CAPACITY: u8 = 8;
+
+/// Decode arbitrary bytes into a sequence of operations, two bytes per operation.
+///
+/// Every set stores a value no earlier set used, so a stale or misplaced value is always detected.
+pub fn decode_operations(data: &[u8]) -> Vec<ModelOp> {
+    data.chunks_exact(2)
+        .enumerate()
+        .map(|(step, chunk)| {
+            let key = (chunk[1] % KEY_SPACE) as u32;
+            match chunk[0] % 16 {
+                0..=6 => ModelOp::Get(key),
+                7..=11 => ModelOp::Set(key, step as u32),
+                12..=13 => ModelOp::Remove(key),
+                14 => ModelOp::Clear,
+                _ => ModelOp::ChangeCapacity((chunk[1] % (MAX_CAPACITY + 1)) as u64),
+            }
+        })
+        .collect()
+}
+
+/// Run the operations against a cache, panicking as soon as any policy independent invariant is broken.
+///
+/// The cache never holds more items than its capacity, a value can be read back directly after it is set, a get or remove only ever returns the value most recently set for its key, and removed or cleared keys are gone. The cache is free to evict any item at any time.
+pub fn check_operations<C>(cache: &C, operations: &[ModelOp])
+where
+    C: Cache<u32, u32> + ?Sized,
+{
+    let policy = cache.policy_name();
+    let mut latest: HashMap<u32, u32> = HashMap::new();
+    for (step, op) in operations.iter().enumerate() {
+        let context = format!("{} cache at step {} ({:?})", policy, step, op);
+        match *op {
+            ModelOp::Get(key) => {
+                if let Some(value) = cache.get(&key) {
+                    assert_eq!(Some(*value), latest.get(&key).copied(), "{}", context);
+                }
+            }
+            ModelOp::Set(key, value) => {
+                if let Some(previous) = cache.set(key, value) {
+                    assert_eq!(Some(*previous), latest.get(&key).copied(), "{}", context);
+                }
+                latest.insert(key, value);
+                if cache.stats().capacity > 0 {
+                    assert_eq!(cache.get(&key).map(|v| *v), Some(value), "{}", context);
+                }
+            }
+            ModelOp::Remove(key) => {
+                if let Some(removed) = cache.remove(&key) {
+                    assert_eq!(Some(*removed), latest.get(&key).copied(), "{}", context);
+                }
+                latest.remove(&key);
+                assert!(!cache.contains_key(&key), "{}", context);
+            }
+            ModelOp::Clear => {
+                cache.clear();
+                latest.clear();
+                assert_eq!(cache.stats().size, 0, "{}", context);
+            }
+            ModelOp::ChangeCapacity(capacity) => cache.change_capacity(capacity),
+        }
+        assert_within_capacity(cache);
+        for (key, value) in cache.to_map() {
+            assert_eq!(Some(*value), latest.get(&key).copied(), "{}", context);
+        }
+    }
+}
+
+/// Decode arbitrary bytes into a capacity and a sequence of operations, and check them against every policy and the wrappers which keep every item they are given.
+///
+/// This is the body of the `cache_operations` fuzz target in `fuzz/`, it can also be called directly with generated input.
+pub fn fuzz_every_policy(data: &[u8]) {
+    let Some((&capacity, data)) = data.split_first() else {
+        return;
+    };
+    let capacity = (capacity % (MAX_CAPACITY + 1)) as u64;
+    let operations = decode_operations(data);
+    let caches: Vec<Box<dyn Cache<u32, u32>>> = vec![
+        Box::new(LRUCache::new(capacity)),
+        Box::new(LRUCache::new(capacity).with_scan_resistance(1)),
+        Box::new(LRUCache::new(capacity).with_batched_recency(4)),
+        Box::new(LRUCache::new(capacity).with_max_weight(8, |key: &u32, _: &u32| (key % 4) as u64)),
+        Box::new(MRUCache::new(capacity)),
+        Box::new(LFUCache::new(capacity)),
+        Box::new(FIFOCache::new(capacity)),
+        Box::new(LIFOCache::new(capacity)),
+        Box::new(RandomReplacementCache::new(capacity)),
+        Box::new(RandomReplacementCache::new(capacity).with_age_bias(2)),
+        Box::new(TTLCache::new(Duration::from_secs(60), capacity)),
+        Box::new(WindowedLFUCache::new(capacity)),
+        Box::new(TwoQCache::new(capacity)),
+        Box::new(ClockCache::new(capacity)),
+        Box::new(ClockProCache::new(capacity)),
+        Box::new(GDSFCache::new(capacity)),
+        Box::new(ThreadLocalCache::new(LRUCache::new(capacity), 2)),
+        Box::new(BloomFilterCache::new(LFUCache::new(capacity), 64)),
+    ];
+    for cache in caches {
+        check_operations(cache.as_ref(), &operations);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    #[test]
+    fn test_decode_operations() {
+        let operations = decode_operations(&[0, 17, 7, 3, 12, 3, 14, 0, 15, 20, 1]);
+        assert_eq!(
+            operations,
+            vec![
+                ModelOp::Get(1),
+                ModelOp::Set(3, 1),
+                ModelOp::Remove(3),
+                ModelOp::Clear,
+                ModelOp::ChangeCapacity(2),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_fuzz_every_policy_with_random_input() {
+        let mut rng = StdRng::seed_from_u64(7);
+        for _ in 0..100 {
+            let len = rng.random_range(0..200);
+            let data: Vec<u8> = (0..len).map(|_| rng.random()).collect();
+            fuzz_every_policy(&data);
+        }
+    }
+}
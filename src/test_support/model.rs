@@ -0,0 +1,664 @@
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::collections::VecDeque;
+use std::fmt::Debug;
+
+use crate::cache::Cache;
+
+/// An operation applied to both the cache under test and its model.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ModelOp {
+    Get(u32),
+    Set(u32, u32),
+    Remove(u32),
+    Clear,
+    ChangeCapacity(u64),
+}
+
+/// CacheModel is a simple, obviously correct reference implementation of an eviction policy.
+///
+/// Models are only used to check a real cache against, so they favour clarity over speed.
+pub trait CacheModel: Debug {
+    fn get(&mut self, key: u32) -> Option<u32>;
+    fn set(&mut self, key: u32, value: u32) -> Option<u32>;
+    fn remove(&mut self, key: u32) -> Option<u32>;
+    fn clear(&mut self);
+    fn change_capacity(&mut self, capacity: u64);
+    fn len(&self) -> usize;
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// A model of a cache that keeps its entries in a list, ordered from the next victim to the last victim.
+#[derive(Debug)]
+struct OrderedModel {
+    capacity: u64,
+    entries: Vec<(u32, u32)>,
+}
+
+impl OrderedModel {
+    fn new(capacity: u64) -> Self {
+        OrderedModel {
+            capacity,
+            entries: Vec::new(),
+        }
+    }
+
+    fn position(&self, key: u32) -> Option<usize> {
+        self.entries.iter().position(|(k, _)| *k == key)
+    }
+
+    fn remove(&mut self, key: u32) -> Option<u32> {
+        self.position(key).map(|pos| self.entries.remove(pos).1)
+    }
+
+    fn is_full(&self) -> bool {
+        self.entries.len() as u64 >= self.capacity
+    }
+}
+
+/// Model of the LRU policy: reads and writes both refresh an entry, the least recently used entry is evicted.
+#[derive(Debug)]
+pub struct LRUModel(OrderedModel);
+
+impl LRUModel {
+    pub fn new(capacity: u64) -> Self {
+        LRUModel(OrderedModel::new(capacity))
+    }
+}
+
+impl CacheModel for LRUModel {
+    fn get(&mut self, key: u32) -> Option<u32> {
+        let value = self.0.remove(key)?;
+        self.0.entries.push((key, value));
+        Some(value)
+    }
+
+    fn set(&mut self, key: u32, value: u32) -> Option<u32> {
+        let previous = self.0.remove(key);
+        self.0.entries.push((key, value));
+        if self.0.entries.len() as u64 > self.0.capacity {
+            self.0.entries.remove(0);
+        }
+        previous
+    }
+
+    fn remove(&mut self, key: u32) -> Option<u32> {
+        self.0.remove(key)
+    }
+
+    fn clear(&mut self) {
+        self.0.entries.clear();
+    }
+
+    fn change_capacity(&mut self, capacity: u64) {
+        self.0.capacity = capacity;
+        while self.0.entries.len() as u64 > capacity {
+            self.0.entries.remove(0);
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.0.entries.len()
+    }
+}
+
+/// Model of the MRU policy: reads and writes both refresh an entry, the most recently used entry is evicted to make space for a new key.
+#[derive(Debug)]
+pub struct MRUModel(OrderedModel);
+
+impl MRUModel {
+    pub fn new(capacity: u64) -> Self {
+        MRUModel(OrderedModel::new(capacity))
+    }
+}
+
+impl CacheModel for MRUModel {
+    fn get(&mut self, key: u32) -> Option<u32> {
+        let value = self.0.remove(key)?;
+        self.0.entries.push((key, value));
+        Some(value)
+    }
+
+    fn set(&mut self, key: u32, value: u32) -> Option<u32> {
+        let previous = self.0.remove(key);
+        if previous.is_none() && self.0.is_full() {
+            self.0.entries.pop();
+        }
+        if self.0.capacity > 0 {
+            self.0.entries.push((key, value));
+        }
+        previous
+    }
+
+    fn remove(&mut self, key: u32) -> Option<u32> {
+        self.0.remove(key)
+    }
+
+    fn clear(&mut self) {
+        self.0.entries.clear();
+    }
+
+    fn change_capacity(&mut self, capacity: u64) {
+        self.0.capacity = capacity;
+        while self.0.entries.len() as u64 > capacity {
+            self.0.entries.pop();
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.0.entries.len()
+    }
+}
+
+/// Model of the FIFO policy: overwriting a key keeps its original position, the oldest key is evicted.
+#[derive(Debug)]
+pub struct FIFOModel(OrderedModel);
+
+impl FIFOModel {
+    pub fn new(capacity: u64) -> Self {
+        FIFOModel(OrderedModel::new(capacity))
+    }
+}
+
+impl CacheModel for FIFOModel {
+    fn get(&mut self, key: u32) -> Option<u32> {
+        self.0.position(key).map(|pos| self.0.entries[pos].1)
+    }
+
+    fn set(&mut self, key: u32, value: u32) -> Option<u32> {
+        if let Some(pos) = self.0.position(key) {
+            return Some(std::mem::replace(&mut self.0.entries[pos].1, value));
+        }
+        if self.0.is_full() && !self.0.entries.is_empty() {
+            self.0.entries.remove(0);
+        }
+        if self.0.capacity > 0 {
+            self.0.entries.push((key, value));
+        }
+        None
+    }
+
+    fn remove(&mut self, key: u32) -> Option<u32> {
+        self.0.remove(key)
+    }
+
+    fn clear(&mut self) {
+        self.0.entries.clear();
+    }
+
+    fn change_capacity(&mut self, capacity: u64) {
+        self.0.capacity = capacity;
+        while self.0.entries.len() as u64 > capacity {
+            self.0.entries.remove(0);
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.0.entries.len()
+    }
+}
+
+/// Model of the LIFO policy: overwriting a key keeps its original position, the newest key is evicted.
+#[derive(Debug)]
+pub struct LIFOModel(OrderedModel);
+
+impl LIFOModel {
+    pub fn new(capacity: u64) -> Self {
+        LIFOModel(OrderedModel::new(capacity))
+    }
+}
+
+impl CacheModel for LIFOModel {
+    fn get(&mut self, key: u32) -> Option<u32> {
+        self.0.position(key).map(|pos| self.0.entries[pos].1)
+    }
+
+    fn set(&mut self, key: u32, value: u32) -> Option<u32> {
+        if let Some(pos) = self.0.position(key) {
+            return Some(std::mem::replace(&mut self.0.entries[pos].1, value));
+        }
+        if self.0.is_full() {
+            self.0.entries.pop();
+        }
+        if self.0.capacity > 0 {
+            self.0.entries.push((key, value));
+        }
+        None
+    }
+
+    fn remove(&mut self, key: u32) -> Option<u32> {
+        self.0.remove(key)
+    }
+
+    fn clear(&mut self) {
+        self.0.entries.clear();
+    }
+
+    fn change_capacity(&mut self, capacity: u64) {
+        self.0.capacity = capacity;
+        while self.0.entries.len() as u64 > capacity {
+            self.0.entries.pop();
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.0.entries.len()
+    }
+}
+
+/// Model of the LFU policy: reads and overwrites increase an entry's frequency, the least frequently used entry is evicted.
+///
+/// Ties are broken by evicting the entry whose frequency changed longest ago.
+#[derive(Debug)]
+pub struct LFUModel {
+    capacity: u64,
+    tick: u64,
+    // (key, value, frequency, tick of the last frequency change)
+    entries: Vec<(u32, u32, u64, u64)>,
+}
+
+impl LFUModel {
+    pub fn new(capacity: u64) -> Self {
+        LFUModel {
+            capacity,
+            tick: 0,
+            entries: Vec::new(),
+        }
+    }
+
+    fn next_tick(&mut self) -> u64 {
+        self.tick += 1;
+        self.tick
+    }
+
+    fn evict(&mut self) {
+        if let Some(pos) =
+            (0..self.entries.len()).min_by_key(|&i| (self.entries[i].2, self.entries[i].3))
+        {
+            self.entries.remove(pos);
+        }
+    }
+}
+
+impl CacheModel for LFUModel {
+    fn get(&mut self, key: u32) -> Option<u32> {
+        let tick = self.next_tick();
+        let entry = self.entries.iter_mut().find(|e| e.0 == key)?;
+        entry.2 += 1;
+        entry.3 = tick;
+        Some(entry.1)
+    }
+
+    fn set(&mut self, key: u32, value: u32) -> Option<u32> {
+        let tick = self.next_tick();
+        if let Some(entry) = self.entries.iter_mut().find(|e| e.0 == key) {
+            entry.2 += 1;
+            entry.3 = tick;
+            return Some(std::mem::replace(&mut entry.1, value));
+        }
+        if self.entries.len() as u64 >= self.capacity {
+            self.evict();
+        }
+        if self.capacity > 0 {
+            self.entries.push((key, value, 1, tick));
+        }
+        None
+    }
+
+    fn remove(&mut self, key: u32) -> Option<u32> {
+        let pos = self.entries.iter().position(|e| e.0 == key)?;
+        Some(self.entries.remove(pos).1)
+    }
+
+    fn clear(&mut self) {
+        self.entries.clear();
+    }
+
+    fn change_capacity(&mut self, capacity: u64) {
+        self.capacity = capacity;
+        while self.entries.len() as u64 > capacity {
+            self.evict();
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.entries.len()
+    }
+}
+
+/// Model of the CLOCK policy: entries sit in a ring of slots with a reference bit, set by reads and overwrites, and a hand sweeps the ring clearing bits until it finds an entry whose bit is clear to evict.
+///
+/// Removed entries leave a hole which the next new entry fills, and shrinking the capacity closes the holes, starting the ring at the hand.
+#[derive(Debug)]
+pub struct ClockModel {
+    capacity: u64,
+    // (key, value, referenced)
+    slots: Vec<Option<(u32, u32, bool)>>,
+    free: Vec<usize>,
+    hand: usize,
+}
+
+impl ClockModel {
+    pub fn new(capacity: u64) -> Self {
+        ClockModel {
+            capacity,
+            slots: Vec::new(),
+            free: Vec::new(),
+            hand: 0,
+        }
+    }
+
+    fn position(&self, key: u32) -> Option<usize> {
+        self.slots
+            .iter()
+            .position(|slot| slot.is_some_and(|(k, _, _)| k == key))
+    }
+
+    fn evict(&mut self) {
+        if self.is_empty() {
+            return;
+        }
+        loop {
+            let position = self.hand;
+            self.hand = (self.hand + 1) % self.slots.len();
+            match &mut self.slots[position] {
+                Some((_, _, referenced)) if *referenced => *referenced = false,
+                Some(_) => {
+                    self.slots[position] = None;
+                    self.free.push(position);
+                    return;
+                }
+                None => {}
+            }
+        }
+    }
+}
+
+impl CacheModel for ClockModel {
+    fn get(&mut self, key: u32) -> Option<u32> {
+        let position = self.position(key)?;
+        let (_, value, referenced) = self.slots[position].as_mut()?;
+        *referenced = true;
+        Some(*value)
+    }
+
+    fn set(&mut self, key: u32, value: u32) -> Option<u32> {
+        if self.capacity == 0 {
+            return None;
+        }
+        if let Some(position) = self.position(key) {
+            let (_, previous, referenced) = self.slots[position].as_mut()?;
+            *referenced = true;
+            return Some(std::mem::replace(previous, value));
+        }
+        if self.len() as u64 >= self.capacity {
+            self.evict();
+        }
+        match self.free.pop() {
+            Some(position) => self.slots[position] = Some((key, value, false)),
+            None => self.slots.push(Some((key, value, false))),
+        }
+        None
+    }
+
+    fn remove(&mut self, key: u32) -> Option<u32> {
+        let position = self.position(key)?;
+        self.free.push(position);
+        self.slots[position].take().map(|(_, value, _)| value)
+    }
+
+    fn clear(&mut self) {
+        self.slots.clear();
+        self.free.clear();
+        self.hand = 0;
+    }
+
+    fn change_capacity(&mut self, capacity: u64) {
+        let shrinking = capacity < self.capacity;
+        self.capacity = capacity;
+        while self.len() as u64 > capacity {
+            self.evict();
+        }
+        if shrinking {
+            self.slots.rotate_left(self.hand);
+            self.slots.retain(Option::is_some);
+            self.free.clear();
+            self.hand = 0;
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.slots.iter().flatten().count()
+    }
+}
+
+/// DifferentialTester runs a seeded, randomised sequence of operations against a cache and a model of its policy and panics at the first divergence.
+///
+/// Example:
+/// ```
+/// use arcache::test_support::{DifferentialTester, LRUModel};
+/// use arcache::LRUCache;
+///
+/// let cache = LRUCache::new(4);
+/// let mut model = LRUModel::new(4);
+///
+/// DifferentialTester::new(42)
+///     .operations(500)
+///     .key_space(8)
+///     .run(&cache, &mut model);
+/// ```
+pub struct DifferentialTester {
+    seed: u64,
+    operations: usize,
+    key_space: u32,
+    max_capacity: Option<u64>,
+}
+
+impl DifferentialTester {
+    /// Create a new DifferentialTester, the same seed always produces the same sequence of operations.
+    pub fn new(seed: u64) -> Self {
+        DifferentialTester {
+            seed,
+            operations: 1000,
+            key_space: 16,
+            max_capacity: None,
+        }
+    }
+
+    /// Set the number of operations to run.
+    pub fn operations(mut self, operations: usize) -> Self {
+        self.operations = operations;
+        self
+    }
+
+    /// Set the number of distinct keys used, a key space slightly larger than the capacity exercises eviction the most.
+    pub fn key_space(mut self, key_space: u32) -> Self {
+        self.key_space = key_space.max(1);
+        self
+    }
+
+    /// Also issue `change_capacity` calls with capacities between 1 and `max_capacity`.
+    pub fn with_capacity_changes(mut self, max_capacity: u64) -> Self {
+        self.max_capacity = Some(max_capacity.max(1));
+        self
+    }
+
+    /// Generate the sequence of operations for this tester.
+    pub fn generate(&self) -> Vec<ModelOp> {
+        let mut rng = StdRng::seed_from_u64(self.seed);
+        (0..self.operations)
+            .map(|_| {
+                let key = rng.random_range(0..self.key_space);
+                match rng.random_range(0..100) {
+                    0..45 => ModelOp::Get(key),
+                    45..85 => ModelOp::Set(key, rng.random()),
+                    85..95 => ModelOp::Remove(key),
+                    95..96 => ModelOp::Clear,
+                    _ => match self.max_capacity {
+                        Some(max) => ModelOp::ChangeCapacity(rng.random_range(1..=max)),
+                        None => ModelOp::Get(key),
+                    },
+                }
+            })
+            .collect()
+    }
+
+    /// Run the operations against the cache and the model, panicking with the seed and recent history on divergence.
+    pub fn run<C, M>(&self, cache: &C, model: &mut M)
+    where
+        C: Cache<u32, u32> + ?Sized,
+        M: CacheModel,
+    {
+        let mut history: VecDeque<ModelOp> = VecDeque::with_capacity(HISTORY_LEN);
+        for (step, op) in self.generate().into_iter().enumerate() {
+            if history.len() == HISTORY_LEN {
+                history.pop_front();
+            }
+            history.push_back(op);
+
+            let (actual, expected) = match op {
+                ModelOp::Get(key) => (cache.get(&key).map(|v| *v), model.get(key)),
+                ModelOp::Set(key, value) => {
+                    (cache.set(key, value).map(|v| *v), model.set(key, value))
+                }
+                ModelOp::Remove(key) => (cache.remove(&key).map(|v| *v), model.remove(key)),
+                ModelOp::Clear => {
+                    cache.clear();
+                    model.clear();
+                    (None, None)
+                }
+                ModelOp::ChangeCapacity(capacity) => {
+                    cache.change_capacity(capacity);
+                    model.change_capacity(capacity);
+                    (None, None)
+                }
+            };
+            let size = cache.stats().size;
+            if actual != expected || size != model.len() as u64 {
+                panic!(
+                    "cache diverged from model at step {} (seed {}): {:?} returned {:?}, expected {:?}; size {} expected {}\nrecent operations: {:?}\nmodel: {:?}",
+                    step,
+                    self.seed,
+                    op,
+                    actual,
+                    expected,
+                    size,
+                    model.len(),
+                    history,
+                    model
+                );
+            }
+        }
+    }
+}
+
+const HISTORY_LEN: usize = 20;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ClockCache, FIFOCache, LFUCache, LIFOCache, LRUCache, MRUCache, TTLCache};
+    use std::time::Duration;
+
+    #[test]
+    fn test_lru_matches_model() {
+        for seed in 0..20 {
+            let cache = LRUCache::new(4);
+            let mut model = LRUModel::new(4);
+            DifferentialTester::new(seed)
+                .key_space(8)
+                .with_capacity_changes(6)
+                .run(&cache, &mut model);
+        }
+    }
+
+    #[test]
+    fn test_clock_matches_model() {
+        for seed in 0..20 {
+            let cache = ClockCache::new(4);
+            let mut model = ClockModel::new(4);
+            DifferentialTester::new(seed)
+                .key_space(8)
+                .with_capacity_changes(6)
+                .run(&cache, &mut model);
+        }
+    }
+
+    #[test]
+    fn test_fifo_matches_model() {
+        for seed in 0..20 {
+            let cache = FIFOCache::new(4);
+            let mut model = FIFOModel::new(4);
+            DifferentialTester::new(seed)
+                .key_space(8)
+                .with_capacity_changes(6)
+                .run(&cache, &mut model);
+        }
+    }
+
+    #[test]
+    fn test_lifo_matches_model() {
+        for seed in 0..20 {
+            let cache = LIFOCache::new(4);
+            let mut model = LIFOModel::new(4);
+            DifferentialTester::new(seed)
+                .key_space(8)
+                .with_capacity_changes(6)
+                .run(&cache, &mut model);
+        }
+    }
+
+    #[test]
+    fn test_mru_matches_model() {
+        for seed in 0..20 {
+            let cache = MRUCache::new(4);
+            let mut model = MRUModel::new(4);
+            DifferentialTester::new(seed)
+                .key_space(8)
+                .with_capacity_changes(6)
+                .run(&cache, &mut model);
+        }
+    }
+
+    #[test]
+    fn test_lfu_matches_model() {
+        for seed in 0..20 {
+            let cache = LFUCache::new(4);
+            let mut model = LFUModel::new(4);
+            DifferentialTester::new(seed)
+                .key_space(8)
+                .with_capacity_changes(6)
+                .run(&cache, &mut model);
+        }
+    }
+
+    #[test]
+    fn test_ttl_without_expiry_matches_lru_model() {
+        for seed in 0..20 {
+            let cache = TTLCache::new(Duration::from_secs(3600), 4);
+            let mut model = LRUModel::new(4);
+            DifferentialTester::new(seed)
+                .key_space(8)
+                .with_capacity_changes(6)
+                .run(&cache, &mut model);
+        }
+    }
+
+    #[test]
+    fn test_generate_is_deterministic() {
+        let tester = DifferentialTester::new(7).operations(50);
+        assert_eq!(tester.generate(), tester.generate());
+    }
+
+    #[test]
+    #[should_panic(expected = "cache diverged from model")]
+    fn test_divergence_is_reported() {
+        let cache = LRUCache::new(4);
+        let mut model = FIFOModel::new(4);
+        DifferentialTester::new(1)
+            .key_space(8)
+            .run(&cache, &mut model);
+    }
+}
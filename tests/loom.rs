@@ -0,0 +1,94 @@
+//! Checks the caches' concurrent logic under every interleaving loom explores.
+//!
+//! Run with `RUSTFLAGS="--cfg loom" cargo test --release --test loom`.
+#![cfg(loom)]
+
+use arcache::sync::atomic::{AtomicU64, Ordering};
+use arcache::sync::Mutex;
+use arcache::{BloomFilterCache, Cache, LRUCache};
+use loom::thread;
+use std::sync::Arc;
+
+#[test]
+fn loom_lru_cache_concurrent_sets_respect_capacity() {
+    loom::model(|| {
+        let cache = LRUCache::new(1);
+        let handles: Vec<_> = (0..2)
+            .map(|key| {
+                let cache = cache.clone();
+                thread::spawn(move || {
+                    cache.set(key, key);
+                    if let Some(value) = cache.get(&key) {
+                        assert_eq!(*value, key);
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(cache.stats().size, 1);
+        let stats = cache.stats();
+        assert_eq!(stats.hits + stats.misses, 2);
+    });
+}
+
+#[test]
+fn loom_lru_cache_batched_recency_keeps_items() {
+    loom::model(|| {
+        let cache = LRUCache::new(2).with_batched_recency(1);
+        cache.set(1, 1);
+        cache.set(2, 2);
+        let reader = {
+            let cache = cache.clone();
+            thread::spawn(move || cache.get(&1).map(|value| *value))
+        };
+        let writer = {
+            let cache = cache.clone();
+            thread::spawn(move || cache.set(2, 20))
+        };
+        assert_eq!(reader.join().unwrap(), Some(1));
+        writer.join().unwrap();
+        assert_eq!(cache.get(&2), Some(Arc::new(20)));
+    });
+}
+
+#[test]
+fn loom_bloom_filter_cache_never_misses_a_set_key() {
+    loom::model(|| {
+        let cache = Arc::new(BloomFilterCache::new(LRUCache::new(4), 64));
+        let writer = {
+            let cache = cache.clone();
+            thread::spawn(move || {
+                cache.set(1, 1);
+            })
+        };
+        if let Some(value) = cache.get(&1) {
+            assert_eq!(*value, 1);
+        }
+        writer.join().unwrap();
+        assert_eq!(cache.get(&1), Some(Arc::new(1)));
+    });
+}
+
+#[test]
+fn loom_sync_layer_for_custom_state() {
+    loom::model(|| {
+        // state a downstream policy keeps alongside a cache, built from the same layer
+        let seen = Arc::new((Mutex::new(Vec::new()), AtomicU64::new(0)));
+        let handles: Vec<_> = (0..2)
+            .map(|key| {
+                let seen = seen.clone();
+                thread::spawn(move || {
+                    seen.0.lock().unwrap().push(key);
+                    seen.1.fetch_add(1, Ordering::Relaxed);
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(seen.0.lock().unwrap().len(), 2);
+        assert_eq!(seen.1.load(Ordering::Relaxed), 2);
+    });
+}